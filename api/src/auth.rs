@@ -0,0 +1,178 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP basic authentication of API requests. The password is a random
+//! secret, generated on first run into a file only readable by the user
+//! running the node, clients with access to that file can authenticate.
+//!
+//! Endpoints can be guarded by several secrets, so the owner secret can be
+//! used everywhere while a separate secret only opens up the public ones.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use iron::prelude::*;
+use iron::{status, BeforeMiddleware, Chain, Handler};
+use iron::headers::{Authorization, Basic};
+use rand::{self, Rng};
+
+use rest::Error;
+use util;
+use util::LOGGER;
+
+/// User name expected along with the secret
+pub const API_USERNAME: &'static str = "grin";
+
+/// Number of random bytes in a generated secret
+const SECRET_BYTES: usize = 20;
+
+/// Reads the API secret from the provided file, generating a new one in it
+/// if the file doesn't exist yet.
+pub fn init_api_secret(path: &str) -> Result<String, Error> {
+	if Path::new(path).exists() {
+		return read_api_secret(path);
+	}
+	let mut bytes = [0u8; SECRET_BYTES];
+	rand::thread_rng().fill_bytes(&mut bytes);
+	let secret = util::to_hex(bytes.to_vec());
+
+	if let Some(parent) = Path::new(path).parent() {
+		fs::create_dir_all(parent).map_err(|e| Error::Internal(format!("{}: {}", path, e)))?;
+	}
+	let mut file = File::create(path).map_err(|e| Error::Internal(format!("{}: {}", path, e)))?;
+	restrict_permissions(path)?;
+	file.write_all(secret.as_bytes())
+		.map_err(|e| Error::Internal(format!("{}: {}", path, e)))?;
+	info!(LOGGER, "Generated a new API secret in {}", path);
+	Ok(secret)
+}
+
+/// Reads the API secret stored in the provided file.
+pub fn read_api_secret(path: &str) -> Result<String, Error> {
+	let mut secret = String::new();
+	File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut secret))
+		.map_err(|e| Error::Internal(format!("{}: {}", path, e)))?;
+	let secret = secret.trim().to_string();
+	if secret.is_empty() {
+		return Err(Error::Internal(format!("{}: empty API secret", path)));
+	}
+	Ok(secret)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &str) -> Result<(), Error> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+		.map_err(|e| Error::Internal(format!("{}: {}", path, e)))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &str) -> Result<(), Error> {
+	Ok(())
+}
+
+/// Middleware rejecting the requests that don't authenticate with one of the
+/// accepted secrets.
+pub struct BasicAuthMiddleware {
+	secrets: Vec<String>,
+}
+
+impl BasicAuthMiddleware {
+	/// Middleware accepting any of the provided secrets
+	pub fn new(secrets: Vec<String>) -> BasicAuthMiddleware {
+		BasicAuthMiddleware { secrets: secrets }
+	}
+
+	fn is_authorized(&self, auth: &Basic) -> bool {
+		let password = match auth.password {
+			Some(ref p) => p,
+			None => return false,
+		};
+		auth.username == API_USERNAME
+			&& self.secrets
+				.iter()
+				.any(|s| constant_time_eq(s.as_bytes(), password.as_bytes()))
+	}
+}
+
+impl BeforeMiddleware for BasicAuthMiddleware {
+	fn before(&self, req: &mut Request) -> IronResult<()> {
+		let authorized = match req.headers.get::<Authorization<Basic>>() {
+			Some(&Authorization(ref auth)) => self.is_authorized(auth),
+			None => false,
+		};
+		if authorized {
+			return Ok(());
+		}
+		let mut resp = Response::with(status::Unauthorized);
+		resp.headers.set_raw(
+			"WWW-Authenticate",
+			vec![b"Basic realm=\"GrinAPI\"".to_vec()],
+		);
+		Err(IronError {
+			error: Box::new(Error::Unauthorized),
+			response: resp,
+		})
+	}
+}
+
+/// Wraps the handler so requests to it need to authenticate with one of the
+/// provided secrets. Left open if no secret is provided.
+pub fn guard<H: Handler>(handler: H, secrets: &[String]) -> Chain {
+	let mut chain = Chain::new(handler);
+	if !secrets.is_empty() {
+		chain.link_before(BasicAuthMiddleware::new(secrets.to_vec()));
+	}
+	chain
+}
+
+// Compares without short-circuiting so the time taken doesn't leak how much
+// of the secret has been guessed.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn secret_file() {
+		let path = "target/test_api_secret/.api_secret";
+		let _ = fs::remove_file(path);
+		let secret = init_api_secret(path).unwrap();
+		assert_eq!(secret.len(), SECRET_BYTES * 2);
+		assert_eq!(init_api_secret(path).unwrap(), secret);
+		assert_eq!(read_api_secret(path).unwrap(), secret);
+	}
+
+	#[test]
+	fn authorization() {
+		let auth = BasicAuthMiddleware::new(vec!["owner".to_string(), "foreign".to_string()]);
+		let basic = |user: &str, pass: Option<&str>| Basic {
+			username: user.to_string(),
+			password: pass.map(|p| p.to_string()),
+		};
+		assert!(auth.is_authorized(&basic(API_USERNAME, Some("owner"))));
+		assert!(auth.is_authorized(&basic(API_USERNAME, Some("foreign"))));
+		assert!(!auth.is_authorized(&basic(API_USERNAME, Some("other"))));
+		assert!(!auth.is_authorized(&basic(API_USERNAME, None)));
+		assert!(!auth.is_authorized(&basic("someone", Some("owner"))));
+	}
+}
@@ -12,15 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! High level JSON/HTTP client API
+//! High level JSON/HTTP client API, supporting both http and https URLs.
 
 use hyper;
-use hyper::client::Response;
+use hyper::client::{RequestBuilder, Response};
+use hyper::header::{Authorization, Basic};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
 use hyper::status::{StatusClass, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::io::Read;
+use std::io::{self, Read};
+use std::time::Duration;
 
+use auth::API_USERNAME;
 use rest::Error;
 
 /// Helper function to easily issue a HTTP GET request against a given URL that
@@ -30,8 +35,16 @@ pub fn get<'a, T>(url: &'a str) -> Result<T, Error>
 where
 	for<'de> T: Deserialize<'de>,
 {
-	let client = hyper::Client::new();
-	let res = check_error(client.get(url).send())?;
+	get_with_secret(url, None)
+}
+
+/// Same as get, authenticating with the provided API secret if any.
+pub fn get_with_secret<'a, T>(url: &'a str, api_secret: Option<String>) -> Result<T, Error>
+where
+	for<'de> T: Deserialize<'de>,
+{
+	let client = client()?;
+	let res = check_error(with_secret(client.get(url), api_secret).send())?;
 	serde_json::from_reader(res).map_err(|e| {
 		Error::Internal(format!("Server returned invalid JSON: {}", e))
 	})
@@ -42,21 +55,91 @@ where
 /// building, JSON serialization and deserialization, and response code
 /// checking.
 pub fn post<'a, IN>(url: &'a str, input: &IN) -> Result<(), Error>
+where
+	IN: Serialize,
+{
+	post_with_secret(url, input, None)
+}
+
+/// Same as post, authenticating with the provided API secret if any.
+pub fn post_with_secret<'a, IN>(
+	url: &'a str,
+	input: &IN,
+	api_secret: Option<String>,
+) -> Result<(), Error>
 where
 	IN: Serialize,
 {
 	let in_json = serde_json::to_string(input).map_err(|e| {
 		Error::Internal(format!("Could not serialize data to JSON: {}", e))
 	})?;
-	let client = hyper::Client::new();
-	let _res = check_error(client.post(url).body(&mut in_json.as_bytes()).send())?;
+	let client = client()?;
+	let req = with_secret(client.post(url), api_secret);
+	let _res = check_error(req.body(&mut in_json.as_bytes()).send())?;
 	Ok(())
 }
 
+/// Same as post_with_secret, deserializing the JSON object the server
+/// responds with.
+pub fn post_json_with_secret<'a, IN, OUT>(
+	url: &'a str,
+	input: &IN,
+	api_secret: Option<String>,
+) -> Result<OUT, Error>
+where
+	IN: Serialize,
+	for<'de> OUT: Deserialize<'de>,
+{
+	post_json_with_timeout(url, input, api_secret, None)
+}
+
+/// Same as post_json_with_secret, giving up with a no response error if
+/// sending the request or reading the response takes longer than the
+/// provided timeout.
+pub fn post_json_with_timeout<'a, IN, OUT>(
+	url: &'a str,
+	input: &IN,
+	api_secret: Option<String>,
+	timeout: Option<Duration>,
+) -> Result<OUT, Error>
+where
+	IN: Serialize,
+	for<'de> OUT: Deserialize<'de>,
+{
+	let in_json = serde_json::to_string(input).map_err(|e| {
+		Error::Internal(format!("Could not serialize data to JSON: {}", e))
+	})?;
+	let mut client = client()?;
+	client.set_read_timeout(timeout);
+	client.set_write_timeout(timeout);
+	let req = with_secret(client.post(url), api_secret);
+	let res = check_error(req.body(&mut in_json.as_bytes()).send())?;
+	serde_json::from_reader(res).map_err(|e| {
+		Error::Internal(format!("Server returned invalid JSON: {}", e))
+	})
+}
+
+fn with_secret<'a>(req: RequestBuilder<'a>, api_secret: Option<String>) -> RequestBuilder<'a> {
+	match api_secret {
+		Some(secret) => req.header(Authorization(Basic {
+			username: API_USERNAME.to_string(),
+			password: Some(secret),
+		})),
+		None => req,
+	}
+}
+
+// HTTP client also able to connect to https URLs
+fn client() -> Result<hyper::Client, Error> {
+	let ssl = NativeTlsClient::new()
+		.map_err(|e| Error::Internal(format!("Could not initialize TLS: {}", e)))?;
+	Ok(hyper::Client::with_connector(HttpsConnector::new(ssl)))
+}
+
 // convert hyper error and check for non success response codes
 fn check_error(res: hyper::Result<Response>) -> Result<Response, Error> {
 	if let Err(e) = res {
-		return Err(Error::Internal(format!("Error during request: {}", e)));
+		return Err(request_error(e));
 	}
 	let mut response = res.unwrap();
 	match response.status.class() {
@@ -66,6 +149,8 @@ fn check_error(res: hyper::Result<Response>) -> Result<Response, Error> {
 		}
 		StatusClass::ClientError => if response.status == StatusCode::NotFound {
 			Err(Error::NotFound)
+		} else if response.status == StatusCode::Unauthorized {
+			Err(Error::Unauthorized)
 		} else {
 			Err(Error::Argument(format!("Argument error: {}", err_msg(&mut response))))
 		},
@@ -73,6 +158,24 @@ fn check_error(res: hyper::Result<Response>) -> Result<Response, Error> {
 	}
 }
 
+// Tells apart the requests that couldn't be sent at all from the ones that
+// may have reached the server, only the former being safe to retry.
+fn request_error(e: hyper::Error) -> Error {
+	let msg = format!("Error during request: {}", e);
+	match e {
+		hyper::Error::Io(ref io) => match io.kind() {
+			io::ErrorKind::TimedOut
+			| io::ErrorKind::WouldBlock
+			| io::ErrorKind::ConnectionReset
+			| io::ErrorKind::ConnectionAborted
+			| io::ErrorKind::BrokenPipe
+			| io::ErrorKind::UnexpectedEof => Error::NoResponse(msg),
+			_ => Error::Connection(msg),
+		},
+		_ => Error::Connection(msg),
+	}
+}
+
 fn err_msg(resp: &mut Response) -> String {
 	let mut msg = String::new();
 	if let Err(_) = resp.read_to_string(&mut msg) {
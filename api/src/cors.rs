@@ -0,0 +1,191 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-origin resource sharing (CORS), letting browser applications served
+//! from other origins call the API. Preflight requests are answered before
+//! routing and authentication, as browsers never send credentials with them.
+
+use iron::prelude::*;
+use iron::Handler;
+use iron::method::Method;
+use iron::status;
+
+/// Origins, methods and headers cross-origin requests are allowed with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+	/// Origins allowed to call the API (i.e. "https://example.com"), "*"
+	/// allowing any. Credentials (API secrets) are only allowed along with
+	/// explicitly listed origins.
+	pub allowed_origins: Vec<String>,
+	/// Methods allowed in cross-origin requests
+	#[serde(default = "default_allowed_methods")]
+	pub allowed_methods: Vec<String>,
+	/// Headers allowed in cross-origin requests
+	#[serde(default = "default_allowed_headers")]
+	pub allowed_headers: Vec<String>,
+	/// How long browsers can cache the preflight response, in seconds
+	#[serde(default = "default_max_age")]
+	pub max_age_secs: u64,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+	vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn default_allowed_headers() -> Vec<String> {
+	vec!["Content-Type".to_string(), "Authorization".to_string()]
+}
+
+fn default_max_age() -> u64 {
+	3600
+}
+
+impl Default for CorsConfig {
+	fn default() -> CorsConfig {
+		CorsConfig {
+			allowed_origins: vec![],
+			allowed_methods: default_allowed_methods(),
+			allowed_headers: default_allowed_headers(),
+			max_age_secs: default_max_age(),
+		}
+	}
+}
+
+impl CorsConfig {
+	// Value of the Access-Control-Allow-Origin header for the request origin,
+	// None if the origin isn't allowed.
+	fn allow_origin(&self, origin: &str) -> Option<String> {
+		if self.allowed_origins.iter().any(|o| o == origin) {
+			Some(origin.to_string())
+		} else if self.allowed_origins.iter().any(|o| o == "*") {
+			Some("*".to_string())
+		} else {
+			None
+		}
+	}
+
+	fn is_method_allowed(&self, method: &str) -> bool {
+		self.allowed_methods
+			.iter()
+			.any(|m| m.eq_ignore_ascii_case(method))
+	}
+}
+
+/// Wraps all API handlers, answering preflight requests and adding the CORS
+/// headers to the responses of allowed cross-origin requests. Passes all
+/// requests through when not configured.
+pub struct CorsHandler<H: Handler> {
+	inner: H,
+	config: Option<CorsConfig>,
+}
+
+impl<H: Handler> CorsHandler<H> {
+	pub fn new(inner: H, config: Option<CorsConfig>) -> CorsHandler<H> {
+		CorsHandler {
+			inner: inner,
+			config: config,
+		}
+	}
+}
+
+impl<H: Handler> Handler for CorsHandler<H> {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let config = match self.config {
+			Some(ref c) => c,
+			None => return self.inner.handle(req),
+		};
+		let origin = header_value(req, "Origin");
+		let allow_origin = origin.as_ref().and_then(|o| config.allow_origin(o));
+
+		let preflight_method = header_value(req, "Access-Control-Request-Method");
+		if req.method == Method::Options && preflight_method.is_some() {
+			let mut resp = Response::with(status::NoContent);
+			let method = preflight_method.unwrap();
+			if let Some(allow_origin) = allow_origin {
+				if config.is_method_allowed(&method) {
+					set_origin_headers(&mut resp, &allow_origin);
+					let methods = config.allowed_methods.join(", ");
+					let headers = config.allowed_headers.join(", ");
+					let max_age = config.max_age_secs.to_string();
+					set_header(&mut resp, "Access-Control-Allow-Methods", &methods);
+					set_header(&mut resp, "Access-Control-Allow-Headers", &headers);
+					set_header(&mut resp, "Access-Control-Max-Age", &max_age);
+				}
+			}
+			return Ok(resp);
+		}
+
+		let allow_origin = match allow_origin {
+			Some(o) => o,
+			None => return self.inner.handle(req),
+		};
+		match self.inner.handle(req) {
+			Ok(mut resp) => {
+				set_origin_headers(&mut resp, &allow_origin);
+				Ok(resp)
+			}
+			Err(mut e) => {
+				// lets browser applications read the error too
+				set_origin_headers(&mut e.response, &allow_origin);
+				Err(e)
+			}
+		}
+	}
+}
+
+fn header_value(req: &Request, name: &str) -> Option<String> {
+	req.headers
+		.get_raw(name)
+		.and_then(|v| v.first())
+		.and_then(|v| String::from_utf8(v.clone()).ok())
+}
+
+fn set_header(resp: &mut Response, name: &'static str, value: &str) {
+	resp.headers.set_raw(name, vec![value.as_bytes().to_vec()]);
+}
+
+fn set_origin_headers(resp: &mut Response, allow_origin: &str) {
+	set_header(resp, "Access-Control-Allow-Origin", allow_origin);
+	if allow_origin != "*" {
+		// the response depends on the origin, caches must take it into account
+		set_header(resp, "Access-Control-Allow-Credentials", "true");
+		set_header(resp, "Vary", "Origin");
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn allowed_origins() {
+		let mut config = CorsConfig::default();
+		config.allowed_origins = vec!["https://example.com".to_string()];
+		assert_eq!(
+			config.allow_origin("https://example.com"),
+			Some("https://example.com".to_string())
+		);
+		assert_eq!(config.allow_origin("https://other.com"), None);
+
+		config.allowed_origins.push("*".to_string());
+		assert_eq!(
+			config.allow_origin("https://example.com"),
+			Some("https://example.com".to_string())
+		);
+		assert_eq!(config.allow_origin("https://other.com"), Some("*".to_string()));
+
+		assert!(config.is_method_allowed("post"));
+		assert!(!config.is_method_allowed("DELETE"));
+	}
+}
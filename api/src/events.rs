@@ -0,0 +1,283 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Push notifications of chain and pool events, streamed to subscribed
+//! clients as server-sent events (SSE) so they don't have to poll:
+//!
+//! ```text
+//! GET /v1/events
+//! GET /v1/events?type=block,reorg
+//! ```
+//!
+//! Each event is sent as an SSE message named after the event type, with the
+//! JSON representation of the event as data. Events are fed to the hub by the
+//! chain and pool adapters.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::Duration;
+
+use iron::prelude::*;
+use iron::Handler;
+use iron::mime::Mime;
+use iron::response::WriteBody;
+use iron::status;
+use serde_json;
+use urlencoded::UrlEncodedQuery;
+
+use chain;
+use core::core::{Block, Transaction};
+use core::core::hash::Hashed;
+use util;
+use util::LOGGER;
+
+/// Maximum number of clients subscribed at once. Each stream holds one of
+/// the API server threads for as long as it's open.
+const MAX_SUBSCRIBERS: usize = 8;
+
+/// Events queued for a subscriber, past that it's considered too slow and
+/// disconnected.
+const SUBSCRIBER_QUEUE: usize = 256;
+
+/// A comment is sent when no event happened during that long, to detect
+/// closed connections and keep proxies from timing out.
+const KEEPALIVE_SECS: u64 = 30;
+
+/// Event pushed to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Event {
+	/// A new block has been accepted by the chain
+	#[serde(rename = "block")]
+	BlockAccepted {
+		hash: String,
+		height: u64,
+		previous: String,
+		total_difficulty: u64,
+		num_inputs: usize,
+		num_outputs: usize,
+		num_kernels: usize,
+	},
+	/// The chain head switched to another branch, the depth being the number
+	/// of blocks of the old branch that aren't part of the chain anymore
+	#[serde(rename = "reorg")]
+	Reorg {
+		old_hash: String,
+		old_height: u64,
+		new_hash: String,
+		new_height: u64,
+		depth: u64,
+	},
+	/// A transaction has been accepted in the pool and broadcast. Stem
+	/// transactions are never notified. Locally submitted transactions being
+	/// rebroadcast may be notified again.
+	#[serde(rename = "tx")]
+	Transaction {
+		tx_hash: String,
+		fee: u64,
+		num_inputs: usize,
+		num_outputs: usize,
+	},
+}
+
+impl Event {
+	pub fn from_block(b: &Block) -> Event {
+		Event::BlockAccepted {
+			hash: util::to_hex(b.hash().to_vec()),
+			height: b.header.height,
+			previous: util::to_hex(b.header.previous.to_vec()),
+			total_difficulty: b.header.total_difficulty.into_num(),
+			num_inputs: b.inputs.len(),
+			num_outputs: b.outputs.len(),
+			num_kernels: b.kernels.len(),
+		}
+	}
+
+	pub fn from_reorg(old_tip: &chain::Tip, new_tip: &chain::Tip, depth: u64) -> Event {
+		Event::Reorg {
+			old_hash: util::to_hex(old_tip.last_block_h.to_vec()),
+			old_height: old_tip.height,
+			new_hash: util::to_hex(new_tip.last_block_h.to_vec()),
+			new_height: new_tip.height,
+			depth: depth,
+		}
+	}
+
+	pub fn from_transaction(tx: &Transaction) -> Event {
+		Event::Transaction {
+			tx_hash: util::to_hex(tx.hash().to_vec()),
+			fee: tx.fee(),
+			num_inputs: tx.inputs.len(),
+			num_outputs: tx.outputs.len(),
+		}
+	}
+
+	/// Type of the event, as used for the SSE event name and filtering
+	pub fn event_type(&self) -> &'static str {
+		match *self {
+			Event::BlockAccepted { .. } => "block",
+			Event::Reorg { .. } => "reorg",
+			Event::Transaction { .. } => "tx",
+		}
+	}
+}
+
+struct Subscriber {
+	types: Option<Vec<String>>,
+	sender: SyncSender<Event>,
+}
+
+/// Dispatches published events to all subscribers.
+pub struct EventHub {
+	subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl EventHub {
+	pub fn new() -> EventHub {
+		EventHub {
+			subscribers: Mutex::new(vec![]),
+		}
+	}
+
+	/// Sends the event to all subscribers interested in it. Subscribers that
+	/// went away or can't keep up get dropped.
+	pub fn publish(&self, event: Event) {
+		let mut subscribers = self.subscribers.lock().unwrap();
+		if subscribers.is_empty() {
+			return;
+		}
+		let event_type = event.event_type();
+		subscribers.retain(|s| {
+			if let Some(ref types) = s.types {
+				if !types.iter().any(|t| t == event_type) {
+					return true;
+				}
+			}
+			match s.sender.try_send(event.clone()) {
+				Ok(_) => true,
+				Err(TrySendError::Full(_)) => {
+					debug!(LOGGER, "events: dropping slow subscriber");
+					false
+				}
+				Err(TrySendError::Disconnected(_)) => false,
+			}
+		});
+	}
+
+	/// Registers a new subscriber for the provided event types, all of them
+	/// if none are provided. Returns None if there are too many subscribers
+	/// already.
+	pub fn subscribe(&self, types: Option<Vec<String>>) -> Option<Receiver<Event>> {
+		let mut subscribers = self.subscribers.lock().unwrap();
+		if subscribers.len() >= MAX_SUBSCRIBERS {
+			return None;
+		}
+		let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+		subscribers.push(Subscriber {
+			types: types,
+			sender: sender,
+		});
+		Some(receiver)
+	}
+}
+
+// Response body streaming events as they're received, until the client goes
+// away or the subscriber gets dropped.
+struct EventStream {
+	receiver: Receiver<Event>,
+}
+
+impl WriteBody for EventStream {
+	fn write_body(&mut self, res: &mut Write) -> io::Result<()> {
+		// tells the client how long to wait before reconnecting, in ms
+		res.write_all(b"retry: 5000\n\n")?;
+		res.flush()?;
+		loop {
+			match self.receiver
+				.recv_timeout(Duration::from_secs(KEEPALIVE_SECS))
+			{
+				Ok(event) => {
+					let data = serde_json::to_string(&event)
+						.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+					write!(res, "event: {}\ndata: {}\n\n", event.event_type(), data)?;
+				}
+				Err(RecvTimeoutError::Timeout) => {
+					res.write_all(b": keepalive\n\n")?;
+				}
+				Err(RecvTimeoutError::Disconnected) => return Ok(()),
+			}
+			res.flush()?;
+		}
+	}
+}
+
+/// Streams chain and pool events as server-sent events.
+/// GET /v1/events
+/// GET /v1/events?type=block,reorg,tx
+pub struct EventsHandler {
+	pub events: Arc<EventHub>,
+}
+
+impl Handler for EventsHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let mut types = None;
+		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
+			if let Some(values) = params.get("type") {
+				types = Some(
+					values
+						.iter()
+						.flat_map(|v| v.split(','))
+						.map(|t| t.trim().to_string())
+						.collect::<Vec<_>>(),
+				);
+			}
+		}
+
+		let receiver = match self.events.subscribe(types) {
+			Some(r) => r,
+			None => {
+				return Ok(Response::with((
+					status::ServiceUnavailable,
+					"Too many subscribers",
+				)))
+			}
+		};
+		let content_type: Mime = "text/event-stream".parse().unwrap();
+		let body: Box<WriteBody> = Box::new(EventStream { receiver: receiver });
+		Ok(Response::with((status::Ok, content_type, body)))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn publish_filtered() {
+		let hub = EventHub::new();
+		let all = hub.subscribe(None).unwrap();
+		let blocks = hub.subscribe(Some(vec!["block".to_string()])).unwrap();
+
+		let tx = Transaction::empty();
+		hub.publish(Event::from_transaction(&tx));
+		assert_eq!(all.try_recv().unwrap().event_type(), "tx");
+		assert!(blocks.try_recv().is_err());
+
+		// disconnected subscribers get dropped
+		drop(all);
+		hub.publish(Event::from_transaction(&tx));
+		assert_eq!(hub.subscribers.lock().unwrap().len(), 1);
+	}
+}
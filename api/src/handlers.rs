@@ -12,30 +12,62 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::fs::File;
 use std::io::Read;
-use std::sync::{Arc, RwLock};
+use std::net::{SocketAddr, TcpListener};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 
 use iron::prelude::*;
 use iron::Handler;
+use iron::headers;
+use iron::mime::Mime;
 use iron::status;
 use urlencoded::UrlEncodedQuery;
 use serde::Serialize;
 use serde_json;
 
 use chain;
-use core::core::{OutputIdentifier, Transaction, DEFAULT_OUTPUT, COINBASE_OUTPUT};
+use core::consensus;
+use core::core;
+use core::core::{OutputIdentifier, Transaction, COINBASE_KERNEL, COINBASE_OUTPUT, DEFAULT_OUTPUT};
 use core::core::hash::{Hash, Hashed};
 use core::ser;
 use pool;
 use p2p;
 use regex::Regex;
+use auth::guard;
+use cors::CorsConfig;
+use rate_limit::{RateLimitConfig, RateLimiter};
+use events::{EventHub, EventsHandler};
+use rpc::RpcHandler;
 use rest::*;
 use util::secp::pedersen::Commitment;
 use types::*;
 use util;
 use util::LOGGER;
 
+/// Maximum number of headers returned by a single header batch request
+const MAX_HEADERS_BATCH: u64 = 512;
+
+/// Maximum number of blocks returned by a single block batch request
+const MAX_BLOCKS_BATCH: u64 = 100;
+
+/// Maximum number of outputs returned by a single output listing request
+const MAX_OUTPUTS_BATCH: u64 = 1000;
+
+/// Maximum number of output commitments looked up by a single request
+const MAX_OUTPUT_IDS: usize = 500;
+
+/// Number of recent blocks looked at when estimating fees
+const FEE_ESTIMATE_WINDOW: u64 = 10;
+
+/// Share of the maximum block weight, in percent, over which a block is
+/// considered full when estimating fees
+const FULL_BLOCK_PERCENT: usize = 90;
+
 // RESTful index of available api endpoints
 // GET /v1/
 struct IndexHandler {
@@ -58,29 +90,50 @@ struct UtxoHandler {
 	chain: Arc<chain::Chain>,
 }
 
-impl UtxoHandler {
-	fn get_utxo(&self, id: &str) -> Result<Utxo, Error> {
-		let c = util::from_hex(String::from(id))
-			.map_err(|_| Error::Argument(format!("Not a valid commitment: {}", id)))?;
-		let commit = Commitment::from_vec(c);
-
-		// We need the features here to be able to generate the necessary hash
-		// to compare against the hash in the output MMR.
-		// For now we can just try both (but this probably needs to be part of the api params)
-		let outputs = [
-			OutputIdentifier::new(DEFAULT_OUTPUT, &commit),
-			OutputIdentifier::new(COINBASE_OUTPUT, &commit)
-		];
-
-		for x in outputs.iter() {
-			if let Ok(_) = self.chain.is_unspent(&x) {
-				return Ok(Utxo::new(&commit))
-			}
+/// Looks up an unspent output given its hex-encoded commitment.
+pub fn get_utxo(chain: &chain::Chain, id: &str) -> Result<Utxo, Error> {
+	let c = util::from_hex(String::from(id))
+		.map_err(|_| Error::Argument(format!("Not a valid commitment: {}", id)))?;
+	let commit = Commitment::from_vec(c);
+
+	// We need the features here to be able to generate the necessary hash
+	// to compare against the hash in the output MMR.
+	// For now we can just try both (but this probably needs to be part of the api params)
+	let outputs = [
+		OutputIdentifier::new(DEFAULT_OUTPUT, &commit),
+		OutputIdentifier::new(COINBASE_OUTPUT, &commit)
+	];
+
+	for x in outputs.iter() {
+		if let Ok(_) = chain.is_unspent(&x) {
+			return Ok(Utxo::new(&commit))
 		}
-		Err(Error::NotFound)
 	}
+	Err(Error::NotFound)
+}
+
+/// Looks up the unspent outputs among the provided hex-encoded commitments,
+/// up to MAX_OUTPUT_IDS at once.
+pub fn get_utxos<S: AsRef<str>>(chain: &chain::Chain, ids: &[S]) -> Result<Vec<Utxo>, Error> {
+	check_output_ids(ids.len())?;
+	let utxos = ids.iter()
+		.filter_map(|id| get_utxo(chain, id.as_ref()).ok())
+		.collect();
+	Ok(utxos)
+}
+
+fn check_output_ids(count: usize) -> Result<(), Error> {
+	if count > MAX_OUTPUT_IDS {
+		return Err(Error::Argument(format!(
+			"Too many outputs requested, {} at most",
+			MAX_OUTPUT_IDS
+		)));
+	}
+	Ok(())
+}
 
-	fn utxos_by_ids(&self, req: &mut Request) -> Vec<Utxo> {
+impl UtxoHandler {
+	fn utxos_by_ids(&self, req: &mut Request) -> Result<Vec<Utxo>, Error> {
 		let mut commitments: Vec<&str> = vec![];
 		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
 			if let Some(ids) = params.get("id") {
@@ -94,13 +147,7 @@ impl UtxoHandler {
 
 		debug!(LOGGER, "utxos_by_ids: {:?}", commitments);
 
-		let mut utxos: Vec<Utxo> = vec![];
-		for x in commitments {
-			if let Ok(utxo) = self.get_utxo(x) {
-				utxos.push(utxo);
-			}
-		}
-		utxos
+		get_utxos(&self.chain, &commitments)
 	}
 
 	fn outputs_at_height(
@@ -131,7 +178,7 @@ impl UtxoHandler {
 	}
 
 	// returns outputs for a specified range of blocks
-	fn outputs_block_batch(&self, req: &mut Request) -> Vec<BlockOutputs> {
+	fn outputs_block_batch(&self, req: &mut Request) -> Result<Vec<BlockOutputs>, Error> {
 		let mut commitments: Vec<Commitment> = vec![];
 		let mut start_height = 1;
 		let mut end_height = 1;
@@ -170,6 +217,7 @@ impl UtxoHandler {
 			commitments,
 			include_rp,
 		);
+		check_output_ids(commitments.len())?;
 
 		let mut return_vec = vec![];
 		for i in start_height..end_height + 1 {
@@ -179,7 +227,7 @@ impl UtxoHandler {
 			}
 		}
 
-		return_vec
+		Ok(return_vec)
 	}
 }
 
@@ -191,13 +239,36 @@ impl Handler for UtxoHandler {
 			path_elems.pop();
 		}
 		match *path_elems.last().unwrap() {
-			"byids" => json_response(&self.utxos_by_ids(req)),
-			"byheight" => json_response(&self.outputs_block_batch(req)),
+			"byids" => json_response(&try!(self.utxos_by_ids(req))),
+			"byheight" => json_response(&try!(self.outputs_block_batch(req))),
 			_ => Ok(Response::with((status::BadRequest, ""))),
 		}
 	}
 }
 
+// Looks up a transaction kernel in the chain given its hex-encoded excess,
+// not found if it's not been mined (yet).
+// GET /v1/chain/kernels/xxx
+struct KernelHandler {
+	chain: Arc<chain::Chain>,
+}
+
+impl Handler for KernelHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let id = last_path_elem(req);
+		let c = try!(
+			util::from_hex(id.clone())
+				.map_err(|_| Error::Argument(format!("Not a valid commitment: {}", id)))
+		);
+		let excess = Commitment::from_vec(c);
+		match self.chain.has_kernel(&excess) {
+			Ok(true) => json_response(&Kernel::new(&excess)),
+			Ok(false) => Err(IronError::from(Error::NotFound)),
+			Err(e) => Err(IronError::from(Error::Internal(format!("{:?}", e)))),
+		}
+	}
+}
+
 // Sum tree handler. Retrieve the roots:
 // GET /v1/sumtrees/roots
 //
@@ -206,6 +277,7 @@ impl Handler for UtxoHandler {
 // GET /v1/sumtrees/lastutxos?n=5
 // GET /v1/sumtrees/lastrangeproofs
 // GET /v1/sumtrees/lastkernels
+// GET /v1/sumtrees/outputs?start_index=1&max=100
 struct SumTreeHandler {
 	chain: Arc<chain::Chain>,
 }
@@ -230,6 +302,51 @@ impl SumTreeHandler {
 	fn get_last_n_kernel(&self, distance: u64) -> Vec<SumTreeNode> {
 		SumTreeNode::get_last_n_kernel(self.chain.clone(), distance)
 	}
+
+	// gets the unspent outputs from the provided MMR position onward, with
+	// their range proofs so wallets can rewind them
+	fn get_outputs_from(&self, start_index: u64, max: u64) -> OutputListing {
+		let (highest_index, outputs) = self.chain.unspent_outputs_from(start_index, max);
+		let last_retrieved_index = outputs.last().map(|o| o.0).unwrap_or(highest_index);
+
+		let mut indexed = vec![];
+		for (index, sum) in outputs {
+			let loc = match self.chain.get_output(&sum.commit) {
+				Ok(loc) => loc,
+				Err(_) => continue,
+			};
+			let block_output = self.chain
+				.get_block(&loc.block_hash)
+				.ok()
+				.and_then(|b| b.outputs.into_iter().find(|o| o.commit == sum.commit));
+			let output = match block_output {
+				Some(out) => OutputPrintable::from_output(&out, self.chain.clone(), true),
+				// the block body isn't available (fast sync), no proof to give
+				None => OutputPrintable {
+					output_type: if sum.features.contains(core::transaction::COINBASE_OUTPUT) {
+						OutputType::Coinbase
+					} else {
+						OutputType::Transaction
+					},
+					commit: sum.commit,
+					switch_commit_hash: sum.switch_commit_hash,
+					spent: false,
+					proof: None,
+					proof_hash: String::new(),
+				},
+			};
+			indexed.push(IndexedOutput {
+				index: index,
+				block_height: loc.height,
+				output: output,
+			});
+		}
+		OutputListing {
+			highest_index: highest_index,
+			last_retrieved_index: last_retrieved_index,
+			outputs: indexed,
+		}
+	}
 }
 
 impl Handler for SumTreeHandler {
@@ -250,8 +367,24 @@ impl Handler for SumTreeHandler {
 				}
 			}
 		}
+		let mut start_index = 1;
+		let mut max = MAX_OUTPUTS_BATCH;
+		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
+			if let Some(n) = params.get("start_index").and_then(|v| v.first()) {
+				start_index = n.parse()
+					.map_err(|_| Error::Argument(format!("Invalid start index: {}", n)))?;
+			}
+			if let Some(n) = params.get("max").and_then(|v| v.first()) {
+				max = n.parse()
+					.map_err(|_| Error::Argument(format!("Invalid max: {}", n)))?;
+			}
+		}
 		match *path_elems.last().unwrap() {
 			"roots" => json_response_pretty(&self.get_roots()),
+			"outputs" => json_response(&self.get_outputs_from(
+				start_index,
+				cmp::min(max, MAX_OUTPUTS_BATCH),
+			)),
 			"lastutxos" => json_response_pretty(&self.get_last_n_utxo(last_n)),
 			"lastrangeproofs" => json_response_pretty(&self.get_last_n_rangeproof(last_n)),
 			"lastkernels" => json_response_pretty(&self.get_last_n_kernel(last_n)),
@@ -260,14 +393,25 @@ impl Handler for SumTreeHandler {
 	}
 }
 
+// All the peers we know about, as JSON unless the request accepts "text/csv".
+// GET /v1/peers/all
 pub struct PeersAllHandler {
 	pub peers: p2p::Peers,
 }
 
 impl Handler for PeersAllHandler {
-	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let csv = match req.headers.get::<headers::Accept>() {
+			Some(accept) => accept.iter().any(|q| q.item.to_string() == "text/csv"),
+			None => false,
+		};
 		let peers = &self.peers.all_peers();
-		json_response_pretty(&peers)
+		if csv {
+			let content_type: Mime = "text/csv".parse().unwrap();
+			Ok(Response::with((status::Ok, content_type, p2p::peer_list::to_csv(peers))))
+		} else {
+			json_response_pretty(&peers)
+		}
 	}
 }
 
@@ -280,16 +424,54 @@ impl Handler for PeersConnectedHandler {
 		let mut peers = vec![];
 		for p in &self.peers.connected_peers() {
 			let p = p.read().unwrap();
-			let peer_info = p.info.clone();
-			peers.push(peer_info);
+			peers.push(p.current_info());
 		}
 		json_response(&peers)
 	}
 }
 
+/// Adds the posted list of peers to the peers we know about, leaving the
+/// ones already known untouched and skipping the ones banned or defunct on
+/// the node they come from.
+/// POST /v1/peers/import
+//
+// The list is either JSON, of peer addresses or of peers as listed by
+// peers/all, or CSV when sent as "text/csv".
+pub struct PeersImportHandler {
+	pub peers: p2p::Peers,
+}
+
+impl Handler for PeersImportHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let csv = match req.headers.get::<headers::ContentType>() {
+			Some(content_type) => content_type.to_string() == "text/csv",
+			None => false,
+		};
+		let mut body = String::new();
+		req.body
+			.read_to_string(&mut body)
+			.map_err(|e| IronError::new(e, status::BadRequest))?;
+
+		let added = if csv {
+			let peers = p2p::peer_list::from_csv(&body)
+				.map_err(|e| IronError::from(Error::Argument(format!("Invalid peer list: {}", e))))?;
+			self.peers.import_peers(peers)
+		} else if let Ok(addrs) = serde_json::from_str::<Vec<SocketAddr>>(&body) {
+			self.peers.add_peer_addrs(addrs)
+		} else {
+			let peers: Vec<p2p::PeerData> = serde_json::from_str(&body)
+				.map_err(|e| IronError::new(e, status::BadRequest))?;
+			self.peers.import_peers(peers)
+		};
+		json_response(&added)
+	}
+}
+
 /// Peer operations
-/// POST /v1/peers/10.12.12.13/ban
-/// POST /v1/peers/10.12.12.13/unban
+/// POST /v1/peers/10.12.12.13:13414/ban
+/// POST /v1/peers/10.12.12.13:13414/unban
+/// POST /v1/peers/10.12.12.13:13414/preferred
+/// POST /v1/peers/10.12.12.13:13414/defunct
 pub struct PeerPostHandler {
 	pub peers: p2p::Peers,
 }
@@ -301,26 +483,27 @@ impl Handler for PeerPostHandler {
 		if *path_elems.last().unwrap() == "" {
 			path_elems.pop();
 		}
-		match *path_elems.last().unwrap() {
+		let action = path_elems.pop().unwrap();
+		let addr = match path_elems.last().map(|a| a.parse()) {
+			Some(Ok(addr)) => addr,
+			_ => return Ok(Response::with((status::BadRequest, ""))),
+		};
+		let state = match action {
 			"ban" => {
-				path_elems.pop();
-				if let Ok(addr) = path_elems.last().unwrap().parse() {
-					self.peers.ban_peer(&addr);
-					Ok(Response::with((status::Ok, "")))
-				} else {
-					Ok(Response::with((status::BadRequest, "")))
-				}
+				self.peers.ban_peer(&addr);
+				return Ok(Response::with((status::Ok, "")));
 			}
 			"unban" => {
-				path_elems.pop();
-				if let Ok(addr) = path_elems.last().unwrap().parse() {
-					self.peers.unban_peer(&addr);
-					Ok(Response::with((status::Ok, "")))
-				} else {
-					Ok(Response::with((status::BadRequest, "")))
-				}
+				self.peers.unban_peer(&addr);
+				return Ok(Response::with((status::Ok, "")));
 			}
-			_ => Ok(Response::with((status::BadRequest, ""))),
+			"preferred" => p2p::State::Preferred,
+			"defunct" => p2p::State::Defunct,
+			_ => return Ok(Response::with((status::BadRequest, ""))),
+		};
+		match self.peers.update_state(addr, state) {
+			Ok(()) => Ok(Response::with((status::Ok, ""))),
+			Err(_) => Err(IronError::from(Error::NotFound)),
 		}
 	}
 }
@@ -350,23 +533,174 @@ impl Handler for PeerGetHandler {
 
 // Status handler. Post a summary of the server status
 // GET /v1/status
-pub struct StatusHandler {
+pub struct StatusHandler<T> {
 	pub chain: Arc<chain::Chain>,
 	pub peers: p2p::Peers,
+	pub sync_state: Arc<chain::SyncState>,
+	pub tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
 }
 
-impl StatusHandler {
+impl<T> StatusHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
 	fn get_status(&self) -> Status {
-		Status::from_tip_and_peers(self.chain.head().unwrap(), self.peers.peer_count())
+		Status::from_tip_and_peers(
+			self.chain.head().unwrap(),
+			self.peers.peer_count(),
+			self.chain.pending_reorg(),
+			self.sync_state.status(),
+			self.tx_pool.read().unwrap().accept_fee_base(),
+		)
 	}
 }
 
-impl Handler for StatusHandler {
+impl<T> Handler for StatusHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
 	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
 		json_response(&self.get_status())
 	}
 }
 
+// Mining stats handler, statistics of the local miner and stratum workers.
+// GET /v1/mining/stats
+pub struct MiningStatsHandler {
+	pub stats: Arc<RwLock<MiningStats>>,
+}
+
+impl Handler for MiningStatsHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		json_response(&*self.stats.read().unwrap())
+	}
+}
+
+/// Builds the candidate blocks served to external mining software and
+/// processes the solved ones, implemented by the server.
+pub trait BlockTemplateProvider: Send + Sync {
+	/// Builds a new block template on top of the chain head, returning why
+	/// it couldn't.
+	fn build_template(&self) -> Result<BlockTemplate, String>;
+
+	/// Submits the nonce and proof of work found for a template, processing
+	/// the solved block like one mined locally. Returns its header or why it
+	/// was rejected.
+	fn submit_solution(
+		&self,
+		template_id: u64,
+		nonce: u64,
+		pow: Vec<u32>,
+	) -> Result<core::BlockHeader, String>;
+
+	/// Submits a whole solved block, processing it like one mined locally.
+	/// Returns its header or why it was rejected.
+	fn submit_block(&self, b: core::Block) -> Result<core::BlockHeader, String>;
+}
+
+// Block template handler. Builds a candidate block on top of the chain head
+// out of the mineable pool transactions, for external mining software to
+// work on without running the internal miner.
+// GET /v1/mining/template
+pub struct BlockTemplateHandler {
+	pub provider: Arc<BlockTemplateProvider>,
+}
+
+impl Handler for BlockTemplateHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		match self.provider.build_template() {
+			Ok(template) => json_response(&template),
+			Err(e) => Err(IronError::from(Error::Internal(e))),
+		}
+	}
+}
+
+// Block submission handler. Takes either the nonce and proof of work found
+// for a template or a whole solved block. The block is processed like one
+// mined locally: validated, added to the chain and broadcast to our peers.
+// POST /v1/mining/submit
+pub struct BlockSubmitHandler {
+	pub provider: Arc<BlockTemplateProvider>,
+}
+
+impl BlockSubmitHandler {
+	fn submit(&self, req: &mut Request) -> Result<BlockHeaderInfo, Error> {
+		let submission: BlockSubmission = serde_json::from_reader(req.body.by_ref())
+			.map_err(|e| Error::Argument(format!("Invalid block submission: {}", e)))?;
+		let res = match submission {
+			BlockSubmission {
+				block: Some(block_hex),
+				..
+			} => {
+				let block_bin = util::from_hex(block_hex)
+					.map_err(|_| Error::Argument("Invalid hex in block submission.".to_string()))?;
+				let b: core::Block = ser::deserialize(&mut &block_bin[..]).map_err(|_| {
+					Error::Argument("Could not deserialize block, invalid format.".to_string())
+				})?;
+				self.provider.submit_block(b)
+			}
+			BlockSubmission {
+				template_id: Some(template_id),
+				nonce: Some(nonce),
+				pow: Some(pow),
+				..
+			} => self.provider.submit_solution(template_id, nonce, pow),
+			_ => {
+				return Err(Error::Argument(
+					"Either a block or a template id, nonce and pow are required.".to_string(),
+				))
+			}
+		};
+		res.map(|h| BlockHeaderInfo::from_header(&h))
+			.map_err(Error::Argument)
+	}
+}
+
+impl Handler for BlockSubmitHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let header = try!(self.submit(req));
+		json_response(&header)
+	}
+}
+
+/// Reloads the settings of the node configuration that can change while
+/// running, implemented by the server.
+pub trait ConfigReloader: Send + Sync {
+	/// Reloads the configuration, returning why it couldn't.
+	fn reload(&self) -> Result<(), String>;
+}
+
+// Configuration reload handler. Applies the runtime-safe settings of the
+// configuration file (peer lists and limits, log levels) without a restart.
+// POST /v1/config/reload
+pub struct ConfigReloadHandler {
+	pub reloader: Arc<ConfigReloader>,
+}
+
+impl Handler for ConfigReloadHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		match self.reloader.reload() {
+			Ok(()) => Ok(Response::with(status::Ok)),
+			Err(e) => Err(IronError::from(Error::Internal(e))),
+		}
+	}
+}
+
+// Counters of the API requests refused for going over the rate limits.
+// GET /v1/ratelimit
+pub struct RateLimitStatsHandler {
+	pub limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Handler for RateLimitStatsHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		match self.limiter {
+			Some(ref limiter) => json_response(&limiter.stats()),
+			None => Err(IronError::from(Error::NotFound)),
+		}
+	}
+}
+
 // Chain handler. Get the head details.
 // GET /v1/chain
 pub struct ChainHandler {
@@ -385,6 +719,227 @@ impl Handler for ChainHandler {
 	}
 }
 
+// Chain compaction handler. Reclaims disk space by removing spent outputs'
+// data older than the horizon.
+// POST /v1/chain/compact
+pub struct ChainCompactHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for ChainCompactHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		match self.chain.compact() {
+			Ok(()) => Ok(Response::with(status::Ok)),
+			Err(e) => Err(IronError::from(Error::Internal(format!("{:?}", e)))),
+		}
+	}
+}
+
+// Chain validation handler. Replays and verifies the whole stored chain,
+// reporting the first inconsistency found. Block processing waits for it.
+// POST /v1/chain/validate
+pub struct ChainValidateHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for ChainValidateHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		json_response(&ChainValidation::from_result(self.chain.validate()))
+	}
+}
+
+// Reorg acceptance handler. Switches to the fork that got refused for being
+// deeper than the maximum reorg depth, once verified legitimate.
+// POST /v1/chain/reorg/accept
+pub struct ChainReorgHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for ChainReorgHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		match self.chain.accept_reorg() {
+			Ok(tip) => json_response(&Tip::from_tip(tip)),
+			Err(chain::Error::Other(_)) => Err(IronError::from(Error::NotFound)),
+			Err(e) => Err(IronError::from(Error::Internal(format!("{:?}", e)))),
+		}
+	}
+}
+
+// Txhashset archive handler. Zips the sum trees as they were at the provided
+// block, along with its header, to seed other nodes with out-of-band. The
+// archive is also left in the chain data directory.
+// GET /v1/chain/txhashset/archive/<hash>
+// GET /v1/chain/txhashset/archive/<height>
+pub struct TxHashSetArchiveHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for TxHashSetArchiveHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let h = try!(parse_block_id(&self.chain, last_path_elem(req)));
+		let path = match self.chain.txhashset_archive(&h) {
+			Ok(path) => path,
+			Err(chain::Error::StoreErr(_, _)) => return Err(IronError::from(Error::NotFound)),
+			Err(chain::Error::Other(e)) => return Err(IronError::from(Error::Argument(e))),
+			Err(e) => return Err(IronError::from(Error::Internal(format!("{:?}", e)))),
+		};
+		let file = try!(
+			File::open(&path).map_err(|e| Error::Internal(format!("{:?}", e)))
+		);
+		let content_type: Mime = "application/zip".parse().unwrap();
+		Ok(Response::with((status::Ok, content_type, file)))
+	}
+}
+
+// Difficulty handler. Gets the difficulty and timestamp of the last N blocks
+// (all the blocks of the adjustment window by default) along with the
+// adjustment giving the difficulty of the next block.
+// GET /v1/chain/difficulty?last=N
+pub struct DifficultyHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl DifficultyHandler {
+	fn get_stats(&self, req: &mut Request) -> Result<DifficultyStats, Error> {
+		let window_len = consensus::DIFFICULTY_ADJUST_WINDOW + consensus::MEDIAN_TIME_WINDOW;
+		let mut last = window_len;
+		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
+			if let Some(values) = params.get("last") {
+				for value in values {
+					last = value.parse().map_err(|_| {
+						Error::Argument(format!("Invalid number of blocks: {}", value))
+					})?;
+				}
+			}
+		}
+		if last > MAX_HEADERS_BATCH {
+			return Err(Error::Argument(format!(
+				"Too many blocks requested, {} at most",
+				MAX_HEADERS_BATCH
+			)));
+		}
+
+		// walk back from the head ourselves, so the adjustment and the blocks
+		// stay consistent if a new block comes in meanwhile
+		let mut header = self.chain
+			.head_header()
+			.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+		let height = header.height;
+		let mut headers = vec![];
+		loop {
+			let previous = header.previous;
+			let at_genesis = header.height == 0;
+			headers.push(header);
+			if at_genesis || headers.len() as u64 >= cmp::max(last, window_len) {
+				break;
+			}
+			header = self.chain
+				.get_block_header(&previous)
+				.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+		}
+
+		let adj = consensus::difficulty_adjustment(
+			headers
+				.iter()
+				.map(|h| Ok((h.timestamp.to_timespec().sec as u64, h.difficulty.clone()))),
+		).map_err(|e| Error::Internal(e.to_string()))?;
+		headers.truncate(last as usize);
+		headers.reverse();
+		Ok(DifficultyStats::from_adjustment(height, adj, &headers))
+	}
+}
+
+impl Handler for DifficultyHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let stats = try!(self.get_stats(req));
+		json_response(&stats)
+	}
+}
+
+// Header batch handler. Gets a contiguous range of block headers from the
+// main chain, up to MAX_HEADERS_BATCH at once, end height included.
+// GET /v1/headers?start_height=1&end_height=100
+//
+// Headers are returned as JSON unless the request accepts
+// "application/octet-stream", in which case their canonical binary
+// serializations are returned concatenated, in height order.
+pub struct HeadersHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl HeadersHandler {
+	fn get_headers(&self, req: &mut Request) -> Result<Vec<core::BlockHeader>, Error> {
+		let heights = height_range(req, &self.chain, MAX_HEADERS_BATCH)?;
+		let mut headers = vec![];
+		for height in heights {
+			let header = self.chain
+				.get_header_by_height(height)
+				.map_err(|_| Error::NotFound)?;
+			headers.push(header);
+		}
+		Ok(headers)
+	}
+}
+
+impl Handler for HeadersHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let binary = match req.headers.get::<headers::Accept>() {
+			Some(accept) => accept
+				.iter()
+				.any(|q| q.item.to_string() == "application/octet-stream"),
+			None => false,
+		};
+		let headers = try!(self.get_headers(req));
+
+		if binary {
+			let mut bytes = vec![];
+			for header in &headers {
+				if let Err(e) = ser::serialize(&mut bytes, header) {
+					return Err(IronError::from(Error::Internal(format!("{:?}", e))));
+				}
+			}
+			let content_type: Mime = "application/octet-stream".parse().unwrap();
+			Ok(Response::with((status::Ok, content_type, bytes)))
+		} else {
+			let headers = headers
+				.iter()
+				.map(|h| BlockHeaderPrintable::from_header(h))
+				.collect::<Vec<_>>();
+			json_response(&headers)
+		}
+	}
+}
+
+/// Gets a single block header given either a hash or height.
+/// GET /v1/headers/<hash>
+/// GET /v1/headers/<height>
+pub struct HeaderHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for HeaderHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let h = try!(parse_block_id(&self.chain, last_path_elem(req)));
+		let header = try!(
+			self.chain
+				.get_block_header(&h)
+				.map_err(|_| Error::NotFound)
+		);
+		json_response(&BlockHeaderPrintable::from_header(&header))
+	}
+}
+
+// Protocol handler. Gets the machine-readable specification of the p2p
+// protocol messages, generated from their declaration.
+// GET /v1/protocol
+pub struct ProtocolHandler {}
+
+impl Handler for ProtocolHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		json_response_pretty(&p2p::protocol_spec())
+	}
+}
+
 /// Gets block details given either a hash or height.
 /// GET /v1/blocks/<hash>
 /// GET /v1/blocks/<height>
@@ -413,44 +968,13 @@ impl BlockHandler {
 			self.chain.clone(),
 		))
 	}
-
-	// Try to decode the string as a height or a hash.
-	fn parse_input(&self, input: String) -> Result<Hash, Error> {
-		if let Ok(height) = input.parse() {
-			match self.chain.clone().get_header_by_height(height) {
-				Ok(header) => return Ok(header.hash()),
-				Err(_) => return Err(Error::NotFound),
-			}
-		}
-		lazy_static! {
-			static ref RE: Regex = Regex::new(r"[0-9a-fA-F]{64}").unwrap();
-		}
-		if !RE.is_match(&input) {
-			return Err(Error::Argument(String::from("Not a valid hash or height.")));
-		}
-		let vec = util::from_hex(input).unwrap();
-		Ok(Hash::from_vec(vec))
-	}
 }
 
 impl Handler for BlockHandler {
 	fn handle(&self, req: &mut Request) -> IronResult<Response> {
-		let url = req.url.clone();
-		let mut path_elems = url.path();
-		if *path_elems.last().unwrap() == "" {
-			path_elems.pop();
-		}
-		let el = *path_elems.last().unwrap();
-		let h = try!(self.parse_input(el.to_string()));
-
-		let mut compact = false;
-		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
-			if let Some(_) = params.get("compact") {
-				compact = true;
-			}
-		}
+		let h = try!(parse_block_id(&self.chain, last_path_elem(req)));
 
-		if compact {
+		if is_compact(req) {
 			let b = try!(self.get_compact_block(&h));
 			json_response(&b)
 		} else {
@@ -460,6 +984,149 @@ impl Handler for BlockHandler {
 	}
 }
 
+/// Gets the blocks in a range of heights, the end height being inclusive. At
+/// most MAX_BLOCKS_BATCH blocks are returned, starting from the start height
+/// when no end height is provided.
+/// GET /v1/blocks?start_height=1&end_height=100
+/// GET /v1/blocks?start_height=1&end_height=100&compact
+pub struct BlocksHandler {
+	pub chain: Arc<chain::Chain>,
+}
+
+impl Handler for BlocksHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let heights = try!(height_range(req, &self.chain, MAX_BLOCKS_BATCH));
+		let compact = is_compact(req);
+
+		let mut blocks = vec![];
+		for height in heights {
+			let block = try!(
+				self.chain
+					.get_block_by_height(height)
+					.map_err(|_| Error::NotFound)
+			);
+			// compact and full blocks have different representations
+			let value = if compact {
+				serde_json::to_value(CompactBlockPrintable::from_compact_block(
+					&block.as_compact_block(),
+					self.chain.clone(),
+				))
+			} else {
+				serde_json::to_value(BlockPrintable::from_block(
+					&block,
+					self.chain.clone(),
+					false,
+				))
+			};
+			blocks.push(try!(
+				value.map_err(|e| Error::Internal(format!("{:?}", e)))
+			));
+		}
+		json_response(&blocks)
+	}
+}
+
+// Last element of the request path, ignoring a trailing slash.
+fn last_path_elem(req: &Request) -> String {
+	let url = req.url.clone();
+	let mut path_elems = url.path();
+	if *path_elems.last().unwrap() == "" {
+		path_elems.pop();
+	}
+	path_elems.last().unwrap().to_string()
+}
+
+// Whether the "compact" query param is present.
+fn is_compact(req: &mut Request) -> bool {
+	match req.get_ref::<UrlEncodedQuery>() {
+		Ok(params) => params.get("compact").is_some(),
+		Err(_) => false,
+	}
+}
+
+/// Try to decode the string as a height or a hash.
+pub fn parse_block_id(chain: &Arc<chain::Chain>, input: String) -> Result<Hash, Error> {
+	if let Ok(height) = input.parse() {
+		match chain.get_header_by_height(height) {
+			Ok(header) => return Ok(header.hash()),
+			Err(_) => return Err(Error::NotFound),
+		}
+	}
+	lazy_static! {
+		static ref RE: Regex = Regex::new(r"[0-9a-fA-F]{64}").unwrap();
+	}
+	if !RE.is_match(&input) {
+		return Err(Error::Argument(String::from("Not a valid hash or height.")));
+	}
+	let vec = util::from_hex(input).unwrap();
+	Ok(Hash::from_vec(vec))
+}
+
+// Range of heights requested through the start_height and end_height query
+// params, see bounded_range.
+fn height_range(
+	req: &mut Request,
+	chain: &Arc<chain::Chain>,
+	max_batch: u64,
+) -> Result<Range<u64>, Error> {
+	let mut start_height = None;
+	let mut end_height = None;
+	if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
+		if let Some(heights) = params.get("start_height") {
+			for height in heights {
+				start_height = Some(height.parse().map_err(|_| {
+					Error::Argument(format!("Invalid start height {}", height))
+				})?);
+			}
+		}
+		if let Some(heights) = params.get("end_height") {
+			for height in heights {
+				end_height = Some(height.parse().map_err(|_| {
+					Error::Argument(format!("Invalid end height {}", height))
+				})?);
+			}
+		}
+	}
+
+	bounded_range(chain, start_height, end_height, max_batch)
+}
+
+/// Range of heights between the start and end heights, capped to the chain
+/// head. Up to max_batch heights from the start are included when no end
+/// height is provided, requesting more is an error.
+pub fn bounded_range(
+	chain: &Arc<chain::Chain>,
+	start_height: Option<u64>,
+	end_height: Option<u64>,
+	max_batch: u64,
+) -> Result<Range<u64>, Error> {
+	let head = chain.head().map_err(|e| Error::Internal(format!("{:?}", e)))?;
+	heights_upto(start_height, end_height, max_batch, head.height)
+}
+
+// Range of heights of bounded_range, given the height of the chain head.
+fn heights_upto(
+	start_height: Option<u64>,
+	end_height: Option<u64>,
+	max_batch: u64,
+	head_height: u64,
+) -> Result<Range<u64>, Error> {
+	let start_height: u64 = start_height.unwrap_or(0);
+	let end_height: u64 = end_height
+		.unwrap_or(start_height.saturating_add(max_batch.saturating_sub(1)))
+		.min(head_height);
+	if end_height < start_height {
+		return Ok(start_height..start_height);
+	}
+	if end_height - start_height >= max_batch {
+		return Err(Error::Argument(format!(
+			"At most {} items can be requested at once",
+			max_batch
+		)));
+	}
+	Ok(start_height..end_height + 1)
+}
+
 // Get basic information about the transaction pool.
 struct PoolInfoHandler<T> {
 	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
@@ -470,13 +1137,126 @@ where
 	T: pool::BlockChain + Send + Sync + 'static,
 {
 	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
-		let pool = self.tx_pool.read().unwrap();
-		json_response(&PoolInfo {
-			pool_size: pool.pool_size(),
-			orphans_size: pool.orphans_size(),
-			total_size: pool.total_size(),
+		json_response(&PoolInfo::from_pool(&self.tx_pool.read().unwrap()))
+	}
+}
+
+// Suggests a fee per unit of transaction weight for a transaction to get
+// mined within the provided number of blocks (1 by default). Has to outbid
+// the pool transactions that won't make it in time, and to pay at least as
+// much as the cheapest recent blocks when they were full.
+// GET /v1/fees/estimate?blocks=N
+struct FeeEstimateHandler<T> {
+	chain: Arc<chain::Chain>,
+	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
+	// fee base of the recent blocks, computed once per chain head
+	recent_cache: Arc<Mutex<Option<(Hash, Option<u64>)>>>,
+}
+
+impl<T> FeeEstimateHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	fn estimate(&self, req: &mut Request) -> Result<FeeEstimate, Error> {
+		let mut blocks = 1;
+		if let Ok(params) = req.get_ref::<UrlEncodedQuery>() {
+			if let Some(values) = params.get("blocks") {
+				for value in values {
+					blocks = value.parse().map_err(|_| {
+						Error::Argument(format!("Invalid number of blocks: {}", value))
+					})?;
+				}
+			}
+		}
+		if blocks == 0 {
+			return Err(Error::Argument(String::from("Number of blocks must be at least 1")));
+		}
+
+		let pool_fee_base = self.tx_pool.read().unwrap().estimate_fee_base(blocks);
+		let blocks_fee_base = self.recent_fee_base()?;
+		Ok(FeeEstimate {
+			blocks: blocks,
+			fee_base: cmp::max(pool_fee_base, blocks_fee_base.unwrap_or(0)),
 		})
 	}
+
+	// Lowest fee per weight paid in the recent full blocks, if any, only
+	// reading the blocks again when the head changed
+	fn recent_fee_base(&self) -> Result<Option<u64>, Error> {
+		let head = self.chain
+			.head()
+			.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+		if let Some((hash, fee_base)) = *self.recent_cache.lock().unwrap() {
+			if hash == head.last_block_h {
+				return Ok(fee_base);
+			}
+		}
+		let start = head.height.saturating_sub(FEE_ESTIMATE_WINDOW - 1);
+		let mut fee_base = None;
+		for height in cmp::max(start, 1)..(head.height + 1) {
+			let header = self.chain
+				.get_header_by_height(height)
+				.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+			// pruned blocks are skipped
+			let block = match self.chain.get_block(&header.hash()) {
+				Ok(block) => block,
+				Err(_) => continue,
+			};
+			let block_weight = block.inputs.len() * consensus::BLOCK_INPUT_WEIGHT
+				+ block.outputs.len() * consensus::BLOCK_OUTPUT_WEIGHT
+				+ block.kernels.len() * consensus::BLOCK_KERNEL_WEIGHT;
+			if block_weight * 100 < consensus::MAX_BLOCK_WEIGHT * FULL_BLOCK_PERCENT {
+				continue;
+			}
+			// same weight as the transaction fee calculation, coinbase excluded
+			let outputs = block
+				.outputs
+				.iter()
+				.filter(|o| !o.features.contains(COINBASE_OUTPUT))
+				.count();
+			let kernels = block
+				.kernels
+				.iter()
+				.filter(|k| !k.features.contains(COINBASE_KERNEL))
+				.count();
+			let weight = cmp::max(
+				4 * outputs as i64 + kernels as i64 - block.inputs.len() as i64,
+				1,
+			);
+			let block_fee_base = block.total_fees() / weight as u64;
+			fee_base = Some(cmp::min(block_fee_base, fee_base.unwrap_or(block_fee_base)));
+		}
+		*self.recent_cache.lock().unwrap() = Some((head.last_block_h, fee_base));
+		Ok(fee_base)
+	}
+}
+
+impl<T> Handler for FeeEstimateHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let estimate = try!(self.estimate(req));
+		json_response(&estimate)
+	}
+}
+
+// Lists the transactions currently in the pool, most recent first.
+// GET /v1/pool
+struct PoolContentsHandler<T> {
+	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
+}
+
+impl<T> Handler for PoolContentsHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		let infos = self.tx_pool.read().unwrap().pool_transactions();
+		let mut txs = infos.iter().map(|i| PoolTx::from_info(i)).collect::<Vec<_>>();
+		txs.sort_by(|a, b| b.received.cmp(&a.received));
+		json_response(&txs)
+	}
 }
 
 /// Dummy wrapper for the hex-encoded serialized transaction.
@@ -485,8 +1265,14 @@ struct TxWrapper {
 	tx_hex: String,
 }
 
-// Push new transactions to our transaction pool, that should broadcast it
-// to the network if valid.
+// Push new transactions to our transaction pool, that should relay it along
+// the Dandelion stem and eventually broadcast it to the network if valid.
+// POST /v1/pool/push
+// POST /v1/pool/push?fluff=true
+//
+// The transaction is expected hex-encoded in a JSON wrapper, or as its raw
+// binary serialization when sent as "application/octet-stream". With fluff,
+// the transaction skips the stem phase and gets broadcast right away.
 struct PoolPushHandler<T> {
 	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
 }
@@ -496,40 +1282,125 @@ where
 	T: pool::BlockChain + Send + Sync + 'static,
 {
 	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let fluff = is_fluff(req);
+		let tx = read_posted_tx(req)?;
+		add_transaction(&self.tx_pool, tx, "push-api", fluff)?;
+		Ok(Response::with(status::Ok))
+	}
+}
+
+// Runs all the checks a transaction pushed to the pool goes through against
+// the current chain and pool, without adding it to the pool nor relaying it,
+// to know whether it would be accepted before broadcasting it.
+// POST /v1/pool/check
+//
+// Takes the transaction like pool/push and responds with the verdict, a
+// rejected transaction still being a successful request.
+struct PoolCheckHandler<T> {
+	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
+}
+
+impl<T> Handler for PoolCheckHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let tx = read_posted_tx(req)?;
+		let res = self.tx_pool.read().unwrap().check_transaction(&tx);
+		json_response(&TxVerdict::from_check(&tx, res))
+	}
+}
+
+// The transaction posted, hex-encoded in a JSON wrapper or as its raw binary
+// serialization when sent as "application/octet-stream".
+fn read_posted_tx(req: &mut Request) -> IronResult<Transaction> {
+	let binary = match req.headers.get::<headers::ContentType>() {
+		Some(content_type) => content_type.to_string() == "application/octet-stream",
+		None => false,
+	};
+	let tx_bin = if binary {
+		let mut tx_bin = vec![];
+		req.body
+			.read_to_end(&mut tx_bin)
+			.map_err(|e| IronError::new(e, status::BadRequest))?;
+		tx_bin
+	} else {
 		let wrapper: TxWrapper = serde_json::from_reader(req.body.by_ref())
 			.map_err(|e| IronError::new(e, status::BadRequest))?;
+		util::from_hex(wrapper.tx_hex)
+			.map_err(|_| Error::Argument(format!("Invalid hex in transaction wrapper.")))?
+	};
+	Ok(deserialize_tx(&tx_bin)?)
+}
 
-		let tx_bin = util::from_hex(wrapper.tx_hex)
-			.map_err(|_| Error::Argument(format!("Invalid hex in transaction wrapper.")))?;
+// Whether the "fluff" query param is set, either without value or to "true".
+fn is_fluff(req: &mut Request) -> bool {
+	match req.get_ref::<UrlEncodedQuery>() {
+		Ok(params) => match params.get("fluff") {
+			Some(values) => values.iter().all(|v| v.is_empty() || v == "true"),
+			None => false,
+		},
+		Err(_) => false,
+	}
+}
 
-		let tx: Transaction = ser::deserialize(&mut &tx_bin[..]).map_err(|_| {
-			Error::Argument("Could not deserialize transaction, invalid format.".to_string())
-		})?;
+fn deserialize_tx(tx_bin: &[u8]) -> Result<Transaction, Error> {
+	ser::deserialize(&mut &tx_bin[..]).map_err(|_| {
+		Error::Argument("Could not deserialize transaction, invalid format.".to_string())
+	})
+}
 
-		let source = pool::TxSource {
-			debug_name: "push-api".to_string(),
-			identifier: "?.?.?.?".to_string(),
-		};
-		info!(
-			LOGGER,
-			"Pushing transaction with {} inputs and {} outputs to pool.",
-			tx.inputs.len(),
-			tx.outputs.len()
-		);
+/// Deserializes the hex-encoded transaction and adds it to the pool, in the
+/// stem phase unless fluff is set.
+pub fn push_transaction<T>(
+	tx_pool: &Arc<RwLock<pool::TransactionPool<T>>>,
+	tx_hex: String,
+	source_name: &str,
+	fluff: bool,
+) -> Result<(), Error>
+where
+	T: pool::BlockChain,
+{
+	let tx_bin = util::from_hex(tx_hex)
+		.map_err(|_| Error::Argument(format!("Invalid hex in transaction wrapper.")))?;
+	let tx = deserialize_tx(&tx_bin)?;
+	add_transaction(tx_pool, tx, source_name, fluff)
+}
 
-		let res = self.tx_pool
-			.write()
-			.unwrap()
-			.add_to_memory_pool(source, tx);
+// Validates the transaction against the chain and pool and adds it, to the
+// stem pool or straight to the main pool (and broadcast) when fluffed.
+fn add_transaction<T>(
+	tx_pool: &Arc<RwLock<pool::TransactionPool<T>>>,
+	tx: Transaction,
+	source_name: &str,
+	fluff: bool,
+) -> Result<(), Error>
+where
+	T: pool::BlockChain,
+{
+	let source = pool::TxSource {
+		debug_name: source_name.to_string(),
+		identifier: "?.?.?.?".to_string(),
+	};
+	info!(
+		LOGGER,
+		"Pushing transaction with {} inputs and {} outputs to pool (fluff: {}).",
+		tx.inputs.len(),
+		tx.outputs.len(),
+		fluff
+	);
 
-		match res {
-			Ok(()) => Ok(Response::with(status::Ok)),
-			Err(e) => {
-				debug!(LOGGER, "error - {:?}", e);
-				Err(IronError::from(Error::Argument(format!("{:?}", e))))
-			}
-		}
-	}
+	// transactions pushed here usually originate from this node, they start
+	// in the stem phase to hide where they come from unless asked otherwise
+	let res = if fluff {
+		tx_pool.write().unwrap().add_to_memory_pool(source, tx)
+	} else {
+		tx_pool.write().unwrap().add_to_stempool(source, tx)
+	};
+	res.map_err(|e| {
+		debug!(LOGGER, "error - {:?}", e);
+		Error::Argument(format!("{:?}", e))
+	})
 }
 
 // Utility to serialize a struct into JSON and produce a sensible IronResult
@@ -555,31 +1426,76 @@ where
 	}
 }
 /// Start all server HTTP handlers. Register all of them with Iron
-/// and runs the corresponding HTTP server.
+/// and runs the corresponding HTTP server, over TLS when configured.
+///
+/// The owner endpoints (node management and peer information) always require
+/// authenticating with the API secret. When a foreign API secret is provided,
+/// all the other endpoints require authenticating with either of the two
+/// secrets.
 pub fn start_rest_apis<T>(
 	addr: String,
+	listener: Option<TcpListener>,
+	tls_config: Option<TLSConfig>,
+	cors_config: Option<CorsConfig>,
+	rate_limit_config: Option<RateLimitConfig>,
+	api_secret: String,
+	foreign_api_secret: Option<String>,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
 	peers: p2p::Peers,
+	events: Arc<EventHub>,
+	mining_stats: Arc<RwLock<MiningStats>>,
+	sync_state: Arc<chain::SyncState>,
+	config_reloader: Arc<ConfigReloader>,
+	block_templates: Arc<BlockTemplateProvider>,
 ) where
 	T: pool::BlockChain + Send + Sync + 'static,
 {
 	let _ = thread::Builder::new()
 		.name("apis".to_string())
 		.spawn(move || {
+			let foreign_secrets = match foreign_api_secret {
+				Some(ref foreign) => vec![foreign.clone(), api_secret.clone()],
+				None => vec![],
+			};
+			let owner_secrets = vec![api_secret.clone()];
+
 			// build handlers and register them under the appropriate endpoint
 			let utxo_handler = UtxoHandler {
 				chain: chain.clone(),
 			};
+			let kernel_handler = KernelHandler {
+				chain: chain.clone(),
+			};
 			let block_handler = BlockHandler {
 				chain: chain.clone(),
 			};
 			let chain_tip_handler = ChainHandler {
 				chain: chain.clone(),
 			};
+			let chain_compact_handler = ChainCompactHandler {
+				chain: chain.clone(),
+			};
+			let chain_validate_handler = ChainValidateHandler {
+				chain: chain.clone(),
+			};
+			let chain_difficulty_handler = DifficultyHandler {
+				chain: chain.clone(),
+			};
+			let chain_reorg_handler = ChainReorgHandler {
+				chain: chain.clone(),
+			};
+			let txhashset_archive_handler = TxHashSetArchiveHandler {
+				chain: chain.clone(),
+			};
+			let headers_handler = HeadersHandler {
+				chain: chain.clone(),
+			};
 			let status_handler = StatusHandler {
 				chain: chain.clone(),
 				peers: peers.clone(),
+				sync_state: sync_state.clone(),
+				tx_pool: tx_pool.clone(),
 			};
 			let sumtree_handler = SumTreeHandler {
 				chain: chain.clone(),
@@ -587,63 +1503,201 @@ pub fn start_rest_apis<T>(
 			let pool_info_handler = PoolInfoHandler {
 				tx_pool: tx_pool.clone(),
 			};
+			let pool_contents_handler = PoolContentsHandler {
+				tx_pool: tx_pool.clone(),
+			};
+			let fee_estimate_handler = FeeEstimateHandler {
+				chain: chain.clone(),
+				tx_pool: tx_pool.clone(),
+				recent_cache: Arc::new(Mutex::new(None)),
+			};
 			let pool_push_handler = PoolPushHandler {
 				tx_pool: tx_pool.clone(),
 			};
+			let pool_check_handler = PoolCheckHandler {
+				tx_pool: tx_pool.clone(),
+			};
 			let peers_all_handler = PeersAllHandler {
 				peers: peers.clone(),
 			};
 			let peers_connected_handler = PeersConnectedHandler {
 				peers: peers.clone(),
 			};
+			let peers_import_handler = PeersImportHandler {
+				peers: peers.clone(),
+			};
 			let peer_post_handler = PeerPostHandler {
 				peers: peers.clone(),
 			};
 			let peer_get_handler = PeerGetHandler {
 				peers: peers.clone(),
 			};
+			let header_handler = HeaderHandler {
+				chain: chain.clone(),
+			};
+			let blocks_handler = BlocksHandler {
+				chain: chain.clone(),
+			};
+			let protocol_handler = ProtocolHandler {};
+			let events_handler = EventsHandler { events: events };
+			let mining_stats_handler = MiningStatsHandler {
+				stats: mining_stats,
+			};
+			let block_template_handler = BlockTemplateHandler {
+				provider: block_templates.clone(),
+			};
+			let block_submit_handler = BlockSubmitHandler {
+				provider: block_templates,
+			};
+			let config_reload_handler = ConfigReloadHandler {
+				reloader: config_reloader,
+			};
+			let rate_limiter = rate_limit_config.map(|c| Arc::new(RateLimiter::new(c)));
+			let rate_limit_handler = RateLimitStatsHandler {
+				limiter: rate_limiter.clone(),
+			};
+			let rpc_handler = RpcHandler {
+				chain: chain.clone(),
+				tx_pool: tx_pool.clone(),
+				peers: peers.clone(),
+				sync_state: sync_state.clone(),
+			};
 
 			let route_list = vec![
 				"get blocks".to_string(),
+				"get blocks?start_height=0&end_height=99".to_string(),
 				"get chain".to_string(),
+				"post chain/compact".to_string(),
+				"post chain/validate".to_string(),
+				"post chain/reorg/accept".to_string(),
+				"get chain/txhashset/archive/<hash|height>".to_string(),
+				"get chain/difficulty?last=71".to_string(),
 				"get chain/utxos".to_string(),
+				"get chain/kernels/<excess>".to_string(),
+				"get headers?start_height=0&end_height=511".to_string(),
+				"get headers/<hash|height>".to_string(),
 				"get status".to_string(),
 				"get sumtrees/roots".to_string(),
 				"get sumtrees/lastutxos?n=10".to_string(),
 				"get sumtrees/lastrangeproofs".to_string(),
 				"get sumtrees/lastkernels".to_string(),
+				"get sumtrees/outputs?start_index=1&max=100".to_string(),
 				"get pool".to_string(),
+				"get pool/size".to_string(),
 				"post pool/push".to_string(),
+				"post pool/check".to_string(),
+				"get fees/estimate?blocks=1".to_string(),
+				"post peers/import".to_string(),
 				"post peers/a.b.c.d:p/ban".to_string(),
 				"post peers/a.b.c.d:p/unban".to_string(),
+				"post peers/a.b.c.d:p/preferred".to_string(),
+				"post peers/a.b.c.d:p/defunct".to_string(),
 				"get peers/all".to_string(),
 				"get peers/connected".to_string(),
 				"get peers/a.b.c.d".to_string(),
+				"get protocol".to_string(),
+				"get events?type=block,reorg,tx".to_string(),
+				"get mining/stats".to_string(),
+				"get mining/template".to_string(),
+				"post mining/submit".to_string(),
+				"post config/reload".to_string(),
+				"get ratelimit".to_string(),
+				"post jsonrpc".to_string(),
 			];
 			// We allow manually banning, like this:
 			// curl -v -X POST http://127.0.0.1:13413/v1/peers/88.99.251.87:13414/ban
 			let index_handler = IndexHandler { list: route_list };
 			let router = router!(
-				index: get "/" => index_handler,
-				blocks: get "/blocks/*" => block_handler,
-				chain_tip: get "/chain" => chain_tip_handler,
-				chain_utxos: get "/chain/utxos/*" => utxo_handler,
-				status: get "/status" => status_handler,
-				sumtree_roots: get "/sumtrees/*" => sumtree_handler,
-				pool_info: get "/pool" => pool_info_handler,
-				pool_push: post "/pool/push" => pool_push_handler,
-				peers_all: get "/peers/all" => peers_all_handler,
-				peers_connected: get "/peers/connected" => peers_connected_handler,
-				peer: post "/peers/*" => peer_post_handler,
-				peer: get "/peers/*" => peer_get_handler
+				index: get "/" => guard(index_handler, &foreign_secrets),
+				blocks: get "/blocks" => guard(blocks_handler, &foreign_secrets),
+				block: get "/blocks/*" => guard(block_handler, &foreign_secrets),
+				chain_tip: get "/chain" => guard(chain_tip_handler, &foreign_secrets),
+				chain_compact: post "/chain/compact" => guard(chain_compact_handler, &owner_secrets),
+				chain_validate: post "/chain/validate" => guard(chain_validate_handler, &owner_secrets),
+				chain_reorg: post "/chain/reorg/accept" => guard(chain_reorg_handler, &owner_secrets),
+				txhashset_archive: get "/chain/txhashset/archive/*" => guard(txhashset_archive_handler, &owner_secrets),
+				chain_utxos: get "/chain/utxos/*" => guard(utxo_handler, &foreign_secrets),
+				chain_kernel: get "/chain/kernels/*" => guard(kernel_handler, &foreign_secrets),
+				chain_difficulty: get "/chain/difficulty" => guard(chain_difficulty_handler, &foreign_secrets),
+				headers: get "/headers" => guard(headers_handler, &foreign_secrets),
+				header: get "/headers/*" => guard(header_handler, &foreign_secrets),
+				status: get "/status" => guard(status_handler, &foreign_secrets),
+				sumtree_roots: get "/sumtrees/*" => guard(sumtree_handler, &foreign_secrets),
+				pool_contents: get "/pool" => guard(pool_contents_handler, &owner_secrets),
+				pool_info: get "/pool/size" => guard(pool_info_handler, &foreign_secrets),
+				pool_push: post "/pool/push" => guard(pool_push_handler, &foreign_secrets),
+				pool_check: post "/pool/check" => guard(pool_check_handler, &foreign_secrets),
+				fee_estimate: get "/fees/estimate" => guard(fee_estimate_handler, &foreign_secrets),
+				peers_all: get "/peers/all" => guard(peers_all_handler, &owner_secrets),
+				peers_connected: get "/peers/connected" => guard(peers_connected_handler, &owner_secrets),
+				peers_import: post "/peers/import" => guard(peers_import_handler, &owner_secrets),
+				peer: post "/peers/*" => guard(peer_post_handler, &owner_secrets),
+				peer: get "/peers/*" => guard(peer_get_handler, &owner_secrets),
+				protocol: get "/protocol" => guard(protocol_handler, &foreign_secrets),
+				events: get "/events" => guard(events_handler, &foreign_secrets),
+				mining_stats: get "/mining/stats" => guard(mining_stats_handler, &owner_secrets),
+				mining_template: get "/mining/template" => guard(block_template_handler, &owner_secrets),
+				mining_submit: post "/mining/submit" => guard(block_submit_handler, &owner_secrets),
+				config_reload: post "/config/reload" => guard(config_reload_handler, &owner_secrets),
+				rate_limit: get "/ratelimit" => guard(rate_limit_handler, &owner_secrets),
+				jsonrpc: post "/jsonrpc" => guard(rpc_handler, &foreign_secrets)
 			);
 
 			let mut apis = ApiServer::new("/v1".to_string());
 			apis.register_handler(router);
+			if let Some(cors_config) = cors_config {
+				apis.set_cors(cors_config);
+			}
+			if let Some(rate_limiter) = rate_limiter {
+				apis.set_rate_limiter(rate_limiter);
+			}
 
-			info!(LOGGER, "Starting HTTP API server at {}.", addr);
-			apis.start(&addr[..]).unwrap_or_else(|e| {
+			// a listener already bound to the address is used as is
+			let result = match (tls_config.as_ref(), listener) {
+				(Some(tls_config), Some(listener)) => {
+					info!(LOGGER, "Starting HTTPS API server at {}.", addr);
+					apis.start_tls_on(listener, tls_config)
+				}
+				(Some(tls_config), None) => {
+					info!(LOGGER, "Starting HTTPS API server at {}.", addr);
+					apis.start_tls(&addr[..], tls_config)
+				}
+				(None, Some(listener)) => {
+					info!(LOGGER, "Starting HTTP API server at {}.", addr);
+					apis.start_on(listener)
+				}
+				(None, None) => {
+					info!(LOGGER, "Starting HTTP API server at {}.", addr);
+					apis.start(&addr[..])
+				}
+			};
+			result.unwrap_or_else(|e| {
 				error!(LOGGER, "Failed to start API HTTP server: {}.", e);
 			});
 		});
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn height_ranges() {
+		assert_eq!(heights_upto(None, None, 10, 100).unwrap(), 0..10);
+		assert_eq!(heights_upto(Some(95), None, 10, 100).unwrap(), 95..101);
+		assert_eq!(heights_upto(Some(20), Some(24), 10, 100).unwrap(), 20..25);
+		assert_eq!(heights_upto(Some(120), None, 10, 100).unwrap(), 120..120);
+		assert!(heights_upto(Some(0), Some(10), 10, 100).is_err());
+
+		// no overflow at the very end of the heights
+		assert_eq!(
+			heights_upto(Some(u64::max_value()), None, 10, 100).unwrap(),
+			u64::max_value()..u64::max_value()
+		);
+		assert_eq!(
+			heights_upto(Some(u64::max_value() - 2), None, 10, u64::max_value() - 1).unwrap(),
+			(u64::max_value() - 2)..u64::max_value()
+		);
+		assert!(heights_upto(None, None, 0, 100).is_err());
+	}
+}
@@ -20,10 +20,12 @@ extern crate grin_store as store;
 extern crate grin_util as util;
 
 extern crate hyper;
+extern crate hyper_native_tls;
 #[macro_use]
 extern crate lazy_static;
 extern crate iron;
 extern crate mount;
+extern crate rand;
 extern crate regex;
 #[macro_use]
 extern crate router;
@@ -35,11 +37,21 @@ extern crate serde_json;
 extern crate slog;
 extern crate urlencoded;
 
+mod auth;
 pub mod client;
+mod cors;
+mod events;
 mod handlers;
+mod rate_limit;
 mod rest;
+mod rpc;
 mod types;
 
-pub use handlers::start_rest_apis;
+pub use auth::{guard, init_api_secret, read_api_secret, API_USERNAME};
+pub use cors::CorsConfig;
+pub use rate_limit::{RateLimitConfig, RateLimitStats, RateLimiter};
+pub use events::{Event, EventHub};
+pub use handlers::{start_rest_apis, BlockTemplateProvider, ConfigReloader};
+pub use rpc::{RpcError, RpcResponse};
 pub use types::*;
 pub use rest::*;
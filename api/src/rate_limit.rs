@@ -0,0 +1,480 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per client IP limits on the API requests, so a single client can't keep
+//! the node busy answering it. Each client gets a budget of requests per
+//! minute, a smaller one for the expensive endpoints (reading blocks or
+//! headers, pushing transactions), and a cap on the requests it can have
+//! in progress at the same time. Requests over the limits are answered
+//! right away with a 429 (Too Many Requests). IPv6 clients are grouped by
+//! network prefix (/64 by default), as a single host usually gets a whole
+//! prefix to pick addresses from.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use iron::prelude::*;
+use iron::Handler;
+use iron::status;
+
+use util::LOGGER;
+
+// clients idle for that long are forgotten, their budgets being full again
+const CLIENT_EXPIRY_SECS: u64 = 600;
+
+// number of clients tracked before the idle ones get forgotten
+const MAX_CLIENTS: usize = 10_000;
+
+/// Limits on the requests of each client IP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+	/// Requests per minute allowed from a client, on any endpoint
+	#[serde(default = "default_requests_per_min")]
+	pub requests_per_min: u32,
+	/// Requests per minute allowed from a client on the expensive endpoints,
+	/// counted against both budgets
+	#[serde(default = "default_expensive_requests_per_min")]
+	pub expensive_requests_per_min: u32,
+	/// Requests a client can have in progress at the same time
+	#[serde(default = "default_max_concurrent")]
+	pub max_concurrent: u32,
+	/// Paths (under /v1) of the expensive endpoints, matched as prefixes
+	#[serde(default = "default_expensive_paths")]
+	pub expensive_paths: Vec<String>,
+	/// Client IPs without limits, i.e. local wallets or a reverse proxy
+	#[serde(default)]
+	pub exempt_ips: Vec<IpAddr>,
+	/// Length of the prefix IPv6 clients are grouped by, all the addresses
+	/// under the same prefix sharing the limits of a single client (128 to
+	/// limit each address on its own)
+	#[serde(default = "default_ipv6_prefix_len")]
+	pub ipv6_prefix_len: u8,
+}
+
+fn default_requests_per_min() -> u32 {
+	600
+}
+
+fn default_expensive_requests_per_min() -> u32 {
+	60
+}
+
+fn default_max_concurrent() -> u32 {
+	8
+}
+
+fn default_ipv6_prefix_len() -> u8 {
+	64
+}
+
+fn default_expensive_paths() -> Vec<String> {
+	vec![
+		"blocks".to_string(),
+		"headers".to_string(),
+		"chain/utxos".to_string(),
+		"chain/difficulty".to_string(),
+		"sumtrees".to_string(),
+		"pool/push".to_string(),
+		"pool/check".to_string(),
+		"jsonrpc".to_string(),
+	]
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> RateLimitConfig {
+		RateLimitConfig {
+			requests_per_min: default_requests_per_min(),
+			expensive_requests_per_min: default_expensive_requests_per_min(),
+			max_concurrent: default_max_concurrent(),
+			expensive_paths: default_expensive_paths(),
+			exempt_ips: vec![],
+			ipv6_prefix_len: default_ipv6_prefix_len(),
+		}
+	}
+}
+
+/// Counters of the requests refused for going over the limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitStats {
+	/// Requests refused for going over a per minute budget
+	pub limited_requests: usize,
+	/// Requests refused for going over the concurrent requests cap
+	pub concurrency_limited_requests: usize,
+	/// Clients currently tracked
+	pub clients: usize,
+}
+
+// Requests budget refilling continuously up to a per minute maximum
+#[derive(Debug, Clone)]
+struct Budget {
+	available: f64,
+	updated: Instant,
+}
+
+impl Budget {
+	fn full(per_min: u32, now: Instant) -> Budget {
+		Budget {
+			available: per_min as f64,
+			updated: now,
+		}
+	}
+
+	// refills the budget for the time elapsed, returning whether a request
+	// is left in it
+	fn refill(&mut self, per_min: u32, now: Instant) -> bool {
+		let elapsed = now.duration_since(self.updated);
+		let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		self.available = (self.available + secs * per_min as f64 / 60.0).min(per_min as f64);
+		self.updated = now;
+		self.available >= 1.0
+	}
+
+	// takes one request from a refilled budget with a request left
+	fn take(&mut self) {
+		self.available -= 1.0;
+	}
+}
+
+struct Client {
+	all: Budget,
+	expensive: Budget,
+	in_flight: u32,
+	last_seen: Instant,
+}
+
+/// Why a request got refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limited {
+	/// Over a per minute budget
+	Rate,
+	/// Over the concurrent requests cap
+	Concurrency,
+}
+
+/// Tracks the requests of each client against the configured limits.
+pub struct RateLimiter {
+	config: RateLimitConfig,
+	clients: Mutex<HashMap<IpAddr, Client>>,
+	limited: AtomicUsize,
+	concurrency_limited: AtomicUsize,
+}
+
+impl RateLimiter {
+	/// Limiter enforcing the provided limits
+	pub fn new(config: RateLimitConfig) -> RateLimiter {
+		RateLimiter {
+			config: config,
+			clients: Mutex::new(HashMap::new()),
+			limited: AtomicUsize::new(0),
+			concurrency_limited: AtomicUsize::new(0),
+		}
+	}
+
+	/// Whether requests to the provided path (under /v1) count against the
+	/// expensive endpoints budget.
+	pub fn is_expensive(&self, path: &str) -> bool {
+		let path = path.trim_left_matches('/');
+		self.config
+			.expensive_paths
+			.iter()
+			.any(|p| path.starts_with(p.trim_left_matches('/')))
+	}
+
+	/// The client the provided IP counts as: itself for IPv4, its network
+	/// prefix for IPv6.
+	pub fn client(&self, ip: IpAddr) -> IpAddr {
+		match ip {
+			IpAddr::V4(_) => ip,
+			IpAddr::V6(ipv6) => {
+				let prefix_len = cmp::min(self.config.ipv6_prefix_len, 128) as usize;
+				let mut segments = ipv6.segments();
+				for (n, segment) in segments.iter_mut().enumerate() {
+					let bits = cmp::min(prefix_len.saturating_sub(n * 16), 16);
+					if bits == 0 {
+						*segment = 0;
+					} else {
+						*segment &= !0u16 << (16 - bits);
+					}
+				}
+				IpAddr::V6(Ipv6Addr::new(
+					segments[0],
+					segments[1],
+					segments[2],
+					segments[3],
+					segments[4],
+					segments[5],
+					segments[6],
+					segments[7],
+				))
+			}
+		}
+	}
+
+	/// Accounts for a new request from the provided client IP, refusing it
+	/// if over the limits. An accepted request is in progress until `done`
+	/// gets called for the same IP.
+	pub fn start(&self, ip: IpAddr, expensive: bool, now: Instant) -> Result<(), Limited> {
+		if self.config.exempt_ips.contains(&ip) {
+			return Ok(());
+		}
+		let ip = self.client(ip);
+		let mut clients = self.clients.lock().unwrap();
+		if clients.len() >= MAX_CLIENTS && !clients.contains_key(&ip) {
+			clients.retain(|_, c| {
+				c.in_flight > 0 || now.duration_since(c.last_seen).as_secs() < CLIENT_EXPIRY_SECS
+			});
+			// all recently seen, the least recent one without requests in
+			// progress (if any) makes room
+			if clients.len() >= MAX_CLIENTS {
+				let oldest = clients
+					.iter()
+					.min_by_key(|&(_, c)| (c.in_flight > 0, c.last_seen))
+					.map(|(ip, _)| *ip);
+				if let Some(oldest) = oldest {
+					clients.remove(&oldest);
+				}
+			}
+		}
+		let config = &self.config;
+		let client = clients.entry(ip).or_insert_with(|| Client {
+			all: Budget::full(config.requests_per_min, now),
+			expensive: Budget::full(config.expensive_requests_per_min, now),
+			in_flight: 0,
+			last_seen: now,
+		});
+		client.last_seen = now;
+
+		if client.in_flight >= config.max_concurrent {
+			self.concurrency_limited.fetch_add(1, Ordering::Relaxed);
+			return Err(Limited::Concurrency);
+		}
+		// refused requests don't consume any budget
+		let all_left = client.all.refill(config.requests_per_min, now);
+		let expensive_left = client
+			.expensive
+			.refill(config.expensive_requests_per_min, now);
+		if !all_left || (expensive && !expensive_left) {
+			self.limited.fetch_add(1, Ordering::Relaxed);
+			return Err(Limited::Rate);
+		}
+		client.all.take();
+		if expensive {
+			client.expensive.take();
+		}
+		client.in_flight += 1;
+		Ok(())
+	}
+
+	/// Marks a request accepted by `start` as complete.
+	pub fn done(&self, ip: IpAddr) {
+		if self.config.exempt_ips.contains(&ip) {
+			return;
+		}
+		let mut clients = self.clients.lock().unwrap();
+		if let Some(client) = clients.get_mut(&self.client(ip)) {
+			client.in_flight = client.in_flight.saturating_sub(1);
+		}
+	}
+
+	/// Counters of the refused requests
+	pub fn stats(&self) -> RateLimitStats {
+		RateLimitStats {
+			limited_requests: self.limited.load(Ordering::Relaxed),
+			concurrency_limited_requests: self.concurrency_limited.load(Ordering::Relaxed),
+			clients: self.clients.lock().unwrap().len(),
+		}
+	}
+}
+
+/// Wraps all API handlers, refusing the requests over the limits of their
+/// client. Passes all requests through when not configured.
+pub struct RateLimitHandler<H: Handler> {
+	inner: H,
+	limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<H: Handler> RateLimitHandler<H> {
+	pub fn new(inner: H, limiter: Option<Arc<RateLimiter>>) -> RateLimitHandler<H> {
+		RateLimitHandler {
+			inner: inner,
+			limiter: limiter,
+		}
+	}
+}
+
+impl<H: Handler> Handler for RateLimitHandler<H> {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let limiter = match self.limiter {
+			Some(ref l) => l,
+			None => return self.inner.handle(req),
+		};
+		let ip = req.remote_addr.ip();
+		let path = req.url.path().join("/");
+		let expensive = limiter.is_expensive(path.trim_left_matches("v1/"));
+		if let Err(limited) = limiter.start(ip, expensive, Instant::now()) {
+			debug!(LOGGER, "api: refusing request to {} from {}, {:?} limited", path, ip, limited);
+			let mut resp = Response::with((status::TooManyRequests, ""));
+			// about the time to get a request back in the budget
+			let per_min = if expensive {
+				limiter.config.expensive_requests_per_min
+			} else {
+				limiter.config.requests_per_min
+			};
+			let retry_after = 60 / cmp::max(per_min, 1) as u64 + 1;
+			resp.headers
+				.set_raw("Retry-After", vec![retry_after.to_string().into_bytes()]);
+			return Ok(resp);
+		}
+		let res = self.inner.handle(req);
+		limiter.done(ip);
+		res
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::net::Ipv4Addr;
+	use std::time::Duration;
+
+	#[test]
+	fn per_client_limits() {
+		let limiter = RateLimiter::new(RateLimitConfig {
+			requests_per_min: 6,
+			expensive_requests_per_min: 2,
+			max_concurrent: 3,
+			exempt_ips: vec!["127.0.0.1".parse().unwrap()],
+			..RateLimitConfig::default()
+		});
+		let ip: IpAddr = "10.0.0.1".parse().unwrap();
+		let other: IpAddr = "10.0.0.2".parse().unwrap();
+		let now = Instant::now();
+
+		assert!(limiter.is_expensive("blocks/1000"));
+		assert!(limiter.is_expensive("pool/push"));
+		assert!(!limiter.is_expensive("chain"));
+
+		// at most 3 requests in progress
+		for _ in 0..3 {
+			assert_eq!(limiter.start(ip, false, now), Ok(()));
+		}
+		assert_eq!(limiter.start(ip, false, now), Err(Limited::Concurrency));
+		for _ in 0..3 {
+			limiter.done(ip);
+		}
+
+		// 2 expensive requests a minute, out of the 6 requests
+		assert_eq!(limiter.start(ip, true, now), Ok(()));
+		limiter.done(ip);
+		assert_eq!(limiter.start(ip, true, now), Ok(()));
+		limiter.done(ip);
+		assert_eq!(limiter.start(ip, true, now), Err(Limited::Rate));
+		// the refused expensive request didn't consume the last request
+		assert_eq!(limiter.start(ip, false, now), Ok(()));
+		assert_eq!(limiter.start(ip, false, now), Err(Limited::Rate));
+		// other clients and exempt ones aren't affected
+		assert_eq!(limiter.start(other, true, now), Ok(()));
+		for _ in 0..10 {
+			assert_eq!(limiter.start("127.0.0.1".parse().unwrap(), true, now), Ok(()));
+		}
+
+		// one request back every 10s
+		let later = now + Duration::from_secs(10);
+		assert_eq!(limiter.start(ip, false, later), Ok(()));
+		assert_eq!(limiter.start(ip, false, later), Err(Limited::Rate));
+
+		let stats = limiter.stats();
+		assert_eq!(stats.limited_requests, 3);
+		assert_eq!(stats.concurrency_limited_requests, 1);
+		assert_eq!(stats.clients, 2);
+	}
+
+	#[test]
+	fn client_eviction() {
+		let limiter = RateLimiter::new(RateLimitConfig::default());
+		let now = Instant::now();
+		let client_ip = |n: usize| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n as u32));
+
+		for n in 0..MAX_CLIENTS {
+			let ip = client_ip(n);
+			let seen = now + Duration::from_millis(n as u64);
+			assert_eq!(limiter.start(ip, false, seen), Ok(()));
+			// the first client still has a request in progress
+			if n > 0 {
+				limiter.done(ip);
+			}
+		}
+		assert_eq!(limiter.stats().clients, MAX_CLIENTS);
+
+		// none expired, the least recently seen idle client makes room
+		let later = now + Duration::from_secs(1);
+		assert_eq!(limiter.start(client_ip(MAX_CLIENTS), false, later), Ok(()));
+		let clients = limiter.clients.lock().unwrap();
+		assert_eq!(clients.len(), MAX_CLIENTS);
+		assert!(clients.contains_key(&client_ip(0)));
+		assert!(!clients.contains_key(&client_ip(1)));
+		assert!(clients.contains_key(&client_ip(MAX_CLIENTS)));
+	}
+
+	#[test]
+	fn ipv6_prefix_clients() {
+		let config = RateLimitConfig {
+			requests_per_min: 2,
+			..RateLimitConfig::default()
+		};
+		let limiter = RateLimiter::new(config.clone());
+		let ip: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+		let same_prefix: IpAddr = "2001:db8:1:2:ffff::7".parse().unwrap();
+		let other_prefix: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+		let now = Instant::now();
+
+		// addresses under the same /64 share their budget
+		assert_eq!(
+			limiter.client(same_prefix),
+			"2001:db8:1:2::".parse::<IpAddr>().unwrap()
+		);
+		assert_eq!(limiter.start(ip, false, now), Ok(()));
+		limiter.done(ip);
+		assert_eq!(limiter.start(same_prefix, false, now), Ok(()));
+		limiter.done(same_prefix);
+		assert_eq!(limiter.start(same_prefix, false, now), Err(Limited::Rate));
+		assert_eq!(limiter.start(other_prefix, false, now), Ok(()));
+		assert_eq!(limiter.stats().clients, 2);
+
+		// a prefix not on a segment boundary
+		let limiter = RateLimiter::new(RateLimitConfig {
+			ipv6_prefix_len: 56,
+			..config.clone()
+		});
+		assert_eq!(limiter.client(ip), limiter.client(other_prefix));
+		assert_eq!(
+			limiter.client("2001:db8:1:1ff::1".parse().unwrap()),
+			"2001:db8:1:100::".parse::<IpAddr>().unwrap()
+		);
+
+		// each address on its own
+		let limiter = RateLimiter::new(RateLimitConfig {
+			ipv6_prefix_len: 128,
+			..config
+		});
+		assert_eq!(limiter.client(ip), ip);
+		assert!(limiter.client(ip) != limiter.client(same_prefix));
+		let ipv4: IpAddr = "10.0.0.1".parse().unwrap();
+		assert_eq!(limiter.client(ipv4), ipv4);
+	}
+}
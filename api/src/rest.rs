@@ -17,19 +17,27 @@
 //!
 //! To use it, just have your service(s) implement the ApiEndpoint trait and
 //! register them on a ApiServer.
+//!
+//! The server can optionally listen over TLS, given a PKCS#12 archive holding
+//! its certificate and private key.
 
 use std::error;
 use std::fmt::{self, Display, Formatter};
-use std::net::ToSocketAddrs;
+use std::net::{TcpListener, ToSocketAddrs};
 use std::string::ToString;
 use std::mem;
+use std::sync::Arc;
 
 use iron::prelude::*;
-use iron::{status, Listening};
+use iron::{status, Listening, Protocol};
 use iron::middleware::Handler;
+use hyper::net::{HttpListener, HttpsListener};
+use hyper_native_tls::NativeTlsServer;
 use router::Router;
 use mount::Mount;
 
+use cors::{CorsConfig, CorsHandler};
+use rate_limit::{RateLimitHandler, RateLimiter};
 use store;
 
 /// Errors that can be returned by an ApiEndpoint implementation.
@@ -38,6 +46,12 @@ pub enum Error {
 	Internal(String),
 	Argument(String),
 	NotFound,
+	Unauthorized,
+	/// The server couldn't be reached, the request wasn't sent
+	Connection(String),
+	/// The request was sent but the server didn't respond in time or the
+	/// connection broke before it did, the request may have been processed
+	NoResponse(String),
 }
 
 impl Display for Error {
@@ -46,6 +60,9 @@ impl Display for Error {
 			Error::Argument(ref s) => write!(f, "Bad arguments: {}", s),
 			Error::Internal(ref s) => write!(f, "Internal error: {}", s),
 			Error::NotFound => write!(f, "Not found."),
+			Error::Unauthorized => write!(f, "Unauthorized."),
+			Error::Connection(ref s) => write!(f, "Connection error: {}", s),
+			Error::NoResponse(ref s) => write!(f, "No response: {}", s),
 		}
 	}
 }
@@ -56,6 +73,9 @@ impl error::Error for Error {
 			Error::Argument(_) => "Bad arguments.",
 			Error::Internal(_) => "Internal error.",
 			Error::NotFound => "Not found.",
+			Error::Unauthorized => "Unauthorized.",
+			Error::Connection(_) => "Connection error.",
+			Error::NoResponse(_) => "No response.",
 		}
 	}
 }
@@ -64,8 +84,11 @@ impl From<Error> for IronError {
 	fn from(e: Error) -> IronError {
 		match e {
 			Error::Argument(_) => IronError::new(e, status::Status::BadRequest),
-			Error::Internal(_) => IronError::new(e, status::Status::InternalServerError),
+			Error::Internal(_) | Error::Connection(_) | Error::NoResponse(_) => {
+				IronError::new(e, status::Status::InternalServerError)
+			}
 			Error::NotFound => IronError::new(e, status::Status::NotFound),
+			Error::Unauthorized => IronError::new(e, status::Status::Unauthorized),
 		}
 	}
 }
@@ -79,11 +102,25 @@ impl From<store::Error> for Error {
 	}
 }
 
+/// TLS configuration of an API server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TLSConfig {
+	/// Path to the PKCS#12 archive (.p12 or .pfx) holding the certificate
+	/// chain and its private key. Can be produced from PEM files with
+	/// `openssl pkcs12 -export -in cert.pem -inkey key.pem -out identity.p12`
+	pub certificate_file: String,
+	/// Password the archive is protected with
+	#[serde(default)]
+	pub certificate_password: String,
+}
+
 /// HTTP server allowing the registration of ApiEndpoint implementations.
 pub struct ApiServer {
 	root: String,
 	router: Router,
 	mount: Mount,
+	cors: Option<CorsConfig>,
+	rate_limiter: Option<Arc<RateLimiter>>,
 	server_listener: Option<Listening>,
 }
 
@@ -95,20 +132,89 @@ impl ApiServer {
 			root: root,
 			router: Router::new(),
 			mount: Mount::new(),
+			cors: None,
+			rate_limiter: None,
 			server_listener: None,
 		}
 	}
 
 	/// Starts the ApiServer at the provided address.
 	pub fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<(), String> {
+		let result = Iron::new(self.take_handler()).http(addr);
+		let return_value = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+		self.server_listener = Some(result.unwrap());
+		return_value
+	}
+
+	/// Starts the ApiServer on a listener already bound to its address.
+	pub fn start_on(&mut self, listener: TcpListener) -> Result<(), String> {
+		let listening = Iron::new(self.take_handler())
+			.listen(HttpListener::from(listener), Protocol::http())
+			.map_err(|e| e.to_string())?;
+		self.server_listener = Some(listening);
+		Ok(())
+	}
+
+	/// Starts the ApiServer at the provided address, only accepting TLS
+	/// connections.
+	pub fn start_tls<A: ToSocketAddrs>(&mut self, addr: A, tls_config: &TLSConfig) -> Result<(), String> {
+		let ssl = NativeTlsServer::new(
+			&tls_config.certificate_file,
+			&tls_config.certificate_password,
+		).map_err(|e| {
+			format!(
+				"failed to load TLS certificate {}: {}",
+				tls_config.certificate_file, e
+			)
+		})?;
+		let listening = Iron::new(self.take_handler())
+			.https(addr, ssl)
+			.map_err(|e| e.to_string())?;
+		self.server_listener = Some(listening);
+		Ok(())
+	}
+
+	/// Same as start_tls, on a listener already bound to its address.
+	pub fn start_tls_on(&mut self, listener: TcpListener, tls_config: &TLSConfig) -> Result<(), String> {
+		let ssl = NativeTlsServer::new(
+			&tls_config.certificate_file,
+			&tls_config.certificate_password,
+		).map_err(|e| {
+			format!(
+				"failed to load TLS certificate {}: {}",
+				tls_config.certificate_file, e
+			)
+		})?;
+		let https = HttpsListener::with_listener(HttpListener::from(listener), ssl);
+		let listening = Iron::new(self.take_handler())
+			.listen(https, Protocol::https())
+			.map_err(|e| e.to_string())?;
+		self.server_listener = Some(listening);
+		Ok(())
+	}
+
+	/// Allows cross-origin requests as configured, to be called before
+	/// starting the server.
+	pub fn set_cors(&mut self, cors: CorsConfig) {
+		self.cors = Some(cors);
+	}
+
+	/// Limits the requests of each client as configured in the provided
+	/// limiter, to be called before starting the server.
+	pub fn set_rate_limiter(&mut self, limiter: Arc<RateLimiter>) {
+		self.rate_limiter = Some(limiter);
+	}
+
+	// All registered handlers, ready to be served
+	fn take_handler(&mut self) -> RateLimitHandler<CorsHandler<Mount>> {
 		// replace this value to satisfy borrow checker
 		let r = mem::replace(&mut self.router, Router::new());
 		let mut m = mem::replace(&mut self.mount, Mount::new());
 		m.mount("/", r);
-		let result = Iron::new(m).http(addr);
-		let return_value = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
-		self.server_listener = Some(result.unwrap());
-		return_value
+		RateLimitHandler::new(
+			CorsHandler::new(m, self.cors.take()),
+			self.rate_limiter.take(),
+		)
 	}
 
 	/// Stops the API server
@@ -0,0 +1,391 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC 2.0 interface to the node query and submission operations,
+//! served on the same port as the REST API:
+//!
+//! ```text
+//! POST /v1/jsonrpc
+//! {"jsonrpc": "2.0", "method": "get_block", "params": {"id": 100}, "id": 1}
+//! ```
+//!
+//! Params can be passed by name or by position (in the order documented for
+//! each method). Batches of requests are supported, as well as
+//! notifications (requests without id), which get no response.
+//!
+//! Methods:
+//!
+//! * `get_tip`
+//! * `get_status`
+//! * `get_block [id, compact]` - id being a block hash or height
+//! * `get_blocks [start_height, end_height, compact]`
+//! * `get_header [id]`
+//! * `get_headers [start_height, end_height]`
+//! * `get_outputs [ids]` - unspent outputs among the provided commitments,
+//!   500 at most
+//! * `get_pool_size`
+//! * `push_transaction [tx_hex, fluff]`
+
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+use iron::prelude::*;
+use iron::Handler;
+use iron::status;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use chain;
+use core::core::hash::{Hash, Hashed};
+use handlers::{bounded_range, get_utxos, parse_block_id, push_transaction};
+use p2p;
+use pool;
+use rest::Error;
+use types::*;
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal error while processing the request
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The requested block, header or output doesn't exist
+pub const NOT_FOUND: i64 = -32001;
+
+/// Maximum number of requests in a batch
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Maximum number of blocks or headers returned by a single range request
+const MAX_RANGE: u64 = 100;
+
+/// Error object of a failed request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+	pub code: i64,
+	pub message: String,
+}
+
+impl RpcError {
+	fn new(code: i64, message: &str) -> RpcError {
+		RpcError {
+			code: code,
+			message: message.to_string(),
+		}
+	}
+}
+
+impl From<Error> for RpcError {
+	fn from(e: Error) -> RpcError {
+		let code = match e {
+			Error::Argument(_) => INVALID_PARAMS,
+			Error::NotFound => NOT_FOUND,
+			Error::Internal(_)
+			| Error::Unauthorized
+			| Error::Connection(_)
+			| Error::NoResponse(_) => INTERNAL_ERROR,
+		};
+		RpcError::new(code, &e.to_string())
+	}
+}
+
+/// Response to a single request, either holding a result or an error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+	pub jsonrpc: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub result: Option<Value>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<RpcError>,
+	pub id: Value,
+}
+
+impl RpcResponse {
+	fn new(id: Value, result: Result<Value, RpcError>) -> RpcResponse {
+		let (result, error) = match result {
+			Ok(v) => (Some(v), None),
+			Err(e) => (None, Some(e)),
+		};
+		RpcResponse {
+			jsonrpc: "2.0".to_string(),
+			result: result,
+			error: error,
+			id: id,
+		}
+	}
+}
+
+/// JSON-RPC endpoint
+/// POST /v1/jsonrpc
+pub struct RpcHandler<T> {
+	pub chain: Arc<chain::Chain>,
+	pub tx_pool: Arc<RwLock<pool::TransactionPool<T>>>,
+	pub peers: p2p::Peers,
+	pub sync_state: Arc<chain::SyncState>,
+}
+
+impl<T> RpcHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	// Processes a batch or a single request, returning None if nothing needs
+	// to be answered (only notifications).
+	fn process(&self, body: &str) -> Option<Value> {
+		let request: Value = match serde_json::from_str(body) {
+			Ok(v) => v,
+			Err(e) => {
+				let err = RpcError::new(PARSE_ERROR, &format!("Parse error: {}", e));
+				return Some(to_value(RpcResponse::new(Value::Null, Err(err))));
+			}
+		};
+		match request {
+			Value::Array(requests) => {
+				if requests.is_empty() || requests.len() > MAX_BATCH_SIZE {
+					let err = RpcError::new(
+						INVALID_REQUEST,
+						&format!("Batches must hold 1 to {} requests", MAX_BATCH_SIZE),
+					);
+					return Some(to_value(RpcResponse::new(Value::Null, Err(err))));
+				}
+				let responses = requests
+					.iter()
+					.filter_map(|r| self.process_one(r))
+					.map(|r| to_value(r))
+					.collect::<Vec<_>>();
+				if responses.is_empty() {
+					None
+				} else {
+					Some(Value::Array(responses))
+				}
+			}
+			_ => self.process_one(&request).map(|r| to_value(r)),
+		}
+	}
+
+	fn process_one(&self, request: &Value) -> Option<RpcResponse> {
+		let obj = match request.as_object() {
+			Some(o) => o,
+			None => {
+				let err = RpcError::new(INVALID_REQUEST, "Request must be an object");
+				return Some(RpcResponse::new(Value::Null, Err(err)));
+			}
+		};
+		let id = obj.get("id").cloned();
+		match id {
+			None | Some(Value::Null) | Some(Value::String(_)) | Some(Value::Number(_)) => {}
+			_ => {
+				let err = RpcError::new(INVALID_REQUEST, "Invalid id");
+				return Some(RpcResponse::new(Value::Null, Err(err)));
+			}
+		}
+
+		let result = match (obj.get("jsonrpc"), obj.get("method")) {
+			(Some(&Value::String(ref v)), Some(&Value::String(ref method))) if v == "2.0" => {
+				let params = obj.get("params").cloned().unwrap_or(Value::Null);
+				match params {
+					Value::Null | Value::Array(_) | Value::Object(_) => {
+						self.call(method, &params)
+					}
+					_ => Err(RpcError::new(INVALID_REQUEST, "Invalid params")),
+				}
+			}
+			_ => Err(RpcError::new(INVALID_REQUEST, "Invalid request")),
+		};
+		// notifications don't get any response
+		id.map(|id| RpcResponse::new(id, result))
+	}
+
+	fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+		match method {
+			"get_tip" => {
+				let head = self.chain
+					.head()
+					.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+				Ok(to_value(Tip::from_tip(head)))
+			}
+			"get_status" => {
+				let head = self.chain
+					.head()
+					.map_err(|e| Error::Internal(format!("{:?}", e)))?;
+				Ok(to_value(Status::from_tip_and_peers(
+					head,
+					self.peers.peer_count(),
+					self.chain.pending_reorg(),
+					self.sync_state.status(),
+					self.tx_pool.read().unwrap().accept_fee_base(),
+				)))
+			}
+			"get_block" => {
+				let h = parse_block_id(&self.chain, block_id(params)?)?;
+				let compact = param::<bool>(params, "compact", 1)?.unwrap_or(false);
+				self.block_value(&h, compact)
+			}
+			"get_blocks" => {
+				let heights = bounded_range(
+					&self.chain,
+					param(params, "start_height", 0)?,
+					param(params, "end_height", 1)?,
+					MAX_RANGE,
+				)?;
+				let compact = param::<bool>(params, "compact", 2)?.unwrap_or(false);
+				let mut blocks = vec![];
+				for height in heights {
+					let header = self.chain
+						.get_header_by_height(height)
+						.map_err(|_| Error::NotFound)?;
+					blocks.push(self.block_value(&header.hash(), compact)?);
+				}
+				Ok(Value::Array(blocks))
+			}
+			"get_header" => {
+				let h = parse_block_id(&self.chain, block_id(params)?)?;
+				let header = self.chain
+					.get_block_header(&h)
+					.map_err(|_| Error::NotFound)?;
+				Ok(to_value(BlockHeaderPrintable::from_header(&header)))
+			}
+			"get_headers" => {
+				let heights = bounded_range(
+					&self.chain,
+					param(params, "start_height", 0)?,
+					param(params, "end_height", 1)?,
+					MAX_RANGE,
+				)?;
+				let mut headers = vec![];
+				for height in heights {
+					let header = self.chain
+						.get_header_by_height(height)
+						.map_err(|_| Error::NotFound)?;
+					headers.push(BlockHeaderPrintable::from_header(&header));
+				}
+				Ok(to_value(headers))
+			}
+			"get_outputs" => {
+				let ids = required_param::<Vec<String>>(params, "ids", 0)?;
+				Ok(to_value(get_utxos(&self.chain, &ids)?))
+			}
+			"get_pool_size" => {
+				let pool = self.tx_pool.read().unwrap();
+				Ok(to_value(PoolInfo::from_pool(&pool)))
+			}
+			"push_transaction" => {
+				let tx_hex = required_param::<String>(params, "tx_hex", 0)?;
+				let fluff = param::<bool>(params, "fluff", 1)?.unwrap_or(false);
+				push_transaction(&self.tx_pool, tx_hex, "push-rpc", fluff)?;
+				Ok(Value::Null)
+			}
+			_ => Err(RpcError::new(
+				METHOD_NOT_FOUND,
+				&format!("Method not found: {}", method),
+			)),
+		}
+	}
+
+	fn block_value(&self, h: &Hash, compact: bool) -> Result<Value, RpcError> {
+		let block = self.chain.get_block(h).map_err(|_| Error::NotFound)?;
+		if compact {
+			Ok(to_value(CompactBlockPrintable::from_compact_block(
+				&block.as_compact_block(),
+				self.chain.clone(),
+			)))
+		} else {
+			Ok(to_value(BlockPrintable::from_block(
+				&block,
+				self.chain.clone(),
+				false,
+			)))
+		}
+	}
+}
+
+impl<T> Handler for RpcHandler<T>
+where
+	T: pool::BlockChain + Send + Sync + 'static,
+{
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let mut body = String::new();
+		req.body
+			.read_to_string(&mut body)
+			.map_err(|e| IronError::new(e, status::BadRequest))?;
+		match self.process(&body) {
+			Some(res) => Ok(Response::with((status::Ok, res.to_string()))),
+			None => Ok(Response::with(status::NoContent)),
+		}
+	}
+}
+
+// Our response types always serialize properly
+fn to_value<S: Serialize>(s: S) -> Value {
+	serde_json::to_value(s).unwrap_or(Value::Null)
+}
+
+// Gets a parameter either by name or by position.
+fn param<P>(params: &Value, name: &str, pos: usize) -> Result<Option<P>, RpcError>
+where
+	for<'de> P: Deserialize<'de>,
+{
+	let value = match *params {
+		Value::Object(ref m) => m.get(name),
+		Value::Array(ref a) => a.get(pos),
+		_ => None,
+	};
+	match value {
+		None | Some(&Value::Null) => Ok(None),
+		Some(v) => serde_json::from_value(v.clone()).map(|p| Some(p)).map_err(|e| {
+			RpcError::new(INVALID_PARAMS, &format!("Invalid param {}: {}", name, e))
+		}),
+	}
+}
+
+fn required_param<P>(params: &Value, name: &str, pos: usize) -> Result<P, RpcError>
+where
+	for<'de> P: Deserialize<'de>,
+{
+	param(params, name, pos)?
+		.ok_or_else(|| RpcError::new(INVALID_PARAMS, &format!("Missing param {}", name)))
+}
+
+// Block hash or height, as a string or a number
+fn block_id(params: &Value) -> Result<String, RpcError> {
+	match required_param::<Value>(params, "id", 0)? {
+		Value::String(s) => Ok(s),
+		Value::Number(n) => Ok(n.to_string()),
+		_ => Err(RpcError::new(INVALID_PARAMS, "Invalid param id")),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn params_by_name_or_position() {
+		let by_name: Value = serde_json::from_str(r#"{"id": 10, "compact": true}"#).unwrap();
+		let by_pos: Value = serde_json::from_str(r#"["abc", true]"#).unwrap();
+
+		assert_eq!(block_id(&by_name).unwrap(), "10");
+		assert_eq!(block_id(&by_pos).unwrap(), "abc");
+		assert_eq!(param::<bool>(&by_name, "compact", 1).unwrap(), Some(true));
+		assert_eq!(param::<bool>(&by_pos, "compact", 1).unwrap(), Some(true));
+		assert_eq!(param::<bool>(&Value::Null, "compact", 1).unwrap(), None);
+
+		let err = param::<u64>(&by_pos, "start_height", 0).unwrap_err();
+		assert_eq!(err.code, INVALID_PARAMS);
+		let err = required_param::<String>(&by_name, "tx_hex", 0).unwrap_err();
+		assert_eq!(err.code, INVALID_PARAMS);
+	}
+}
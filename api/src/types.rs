@@ -14,12 +14,13 @@
 
 use std::sync::Arc;
 
-use core::{core, ser};
+use core::{consensus, core, ser};
 use core::core::hash::Hashed;
 use core::core::SumCommit;
 use core::core::SwitchCommitHash;
 use chain;
 use p2p;
+use pool;
 use util;
 use util::secp::pedersen;
 use util::secp::constants::MAX_PROOF_SIZE;
@@ -72,15 +73,206 @@ pub struct Status {
 	pub connections: u32,
 	// The state of the current fork Tip
 	pub tip: Tip,
+	// Fork deeper than the maximum reorg depth, waiting to be accepted
+	#[serde(default)]
+	pub pending_reorg: Option<PendingReorg>,
+	// Sync stage and progress, unknown for older nodes
+	#[serde(default)]
+	pub sync_status: Option<chain::SyncStatus>,
+	// Minimum fee per unit of transaction weight for a transaction to be
+	// accepted and relayed, unknown for older nodes
+	#[serde(default)]
+	pub accept_fee_base: Option<u64>,
+	// Timing percentiles of the relay stages of the recently accepted
+	// blocks, from first hearing of them
+	#[serde(default)]
+	pub propagation: Vec<util::tracing::StageStats>,
 }
 
 impl Status {
-	pub fn from_tip_and_peers(current_tip: chain::Tip, connections: u32) -> Status {
+	pub fn from_tip_and_peers(
+		current_tip: chain::Tip,
+		connections: u32,
+		pending_reorg: Option<chain::PendingReorg>,
+		sync_status: chain::SyncStatus,
+		accept_fee_base: u64,
+	) -> Status {
 		Status {
 			protocol_version: p2p::msg::PROTOCOL_VERSION,
 			user_agent: p2p::msg::USER_AGENT.to_string(),
 			connections: connections,
 			tip: Tip::from_tip(current_tip),
+			pending_reorg: pending_reorg.map(PendingReorg::from_pending_reorg),
+			sync_status: Some(sync_status),
+			accept_fee_base: Some(accept_fee_base),
+			propagation: util::tracing::stage_stats(),
+		}
+	}
+}
+
+/// Number of recently found blocks kept in the mining stats
+pub const MAX_RECENT_BLOCKS: usize = 10;
+
+/// Mining statistics of the local miner and the stratum server, updated as
+/// they mine
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MiningStats {
+	/// Whether the local miner is running
+	pub is_mining: bool,
+	/// Mining plugin used by the local miner
+	pub plugin: Option<String>,
+	/// Graphs per second searched by the local miner
+	pub graphs_per_second: f64,
+	/// Solutions found by the local miner, whether they met the block
+	/// difficulty or not
+	pub solutions_found: u64,
+	/// Height of the block currently mined
+	pub block_height: u64,
+	/// Network difficulty of the block currently mined
+	pub network_difficulty: u64,
+	/// Whether the stratum server is running
+	pub is_stratum_running: bool,
+	/// Workers connected to the stratum server
+	pub workers: Vec<WorkerStats>,
+	/// Blocks recently found, latest first
+	pub recent_blocks: Vec<BlockFound>,
+}
+
+impl MiningStats {
+	/// Records a block found, keeping the latest MAX_RECENT_BLOCKS
+	pub fn add_block_found(&mut self, height: u64, hash: String, found_by: String, timestamp: i64) {
+		self.recent_blocks.insert(
+			0,
+			BlockFound {
+				height: height,
+				hash: hash,
+				found_by: found_by,
+				timestamp: timestamp,
+			},
+		);
+		self.recent_blocks.truncate(MAX_RECENT_BLOCKS);
+	}
+}
+
+/// Shares and blocks submitted by a stratum worker
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WorkerStats {
+	/// Connection identifier
+	pub id: usize,
+	/// Login the worker authenticated with
+	pub login: String,
+	/// Agent (mining software) reported by the worker
+	pub agent: String,
+	/// Number of valid shares
+	pub num_accepted: u64,
+	/// Number of invalid or too low difficulty shares
+	pub num_rejected: u64,
+	/// Number of shares submitted for a previous block
+	pub num_stale: u64,
+	/// Number of shares that were valid blocks
+	pub num_blocks_found: u64,
+	/// Last time we heard from the worker
+	pub last_seen: i64,
+}
+
+/// A block found by this node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockFound {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block
+	pub hash: String,
+	/// Local miner or stratum worker that found it
+	pub found_by: String,
+	/// When the block was found
+	pub timestamp: i64,
+}
+
+/// A fork with more work than the current chain that was refused for being
+/// deeper than the maximum reorg depth
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingReorg {
+	/// Tip of the refused fork
+	pub tip: Tip,
+	/// Number of blocks of the current chain the fork would replace
+	pub depth: u64,
+}
+
+impl PendingReorg {
+	pub fn from_pending_reorg(reorg: chain::PendingReorg) -> PendingReorg {
+		PendingReorg {
+			tip: Tip::from_tip(reorg.tip),
+			depth: reorg.depth,
+		}
+	}
+}
+
+/// Candidate block for external mining software to find a proof of work for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockTemplate {
+	/// Identifier of the template, to submit a solution for
+	pub template_id: u64,
+	/// Height of the candidate block
+	pub height: u64,
+	/// Hash of the block it builds on
+	pub previous: String,
+	/// Network difficulty the proof of work has to meet
+	pub difficulty: u64,
+	/// Hex of the serialized header before its nonce
+	pub pre_nonce: String,
+	/// Hex of the serialized header after its nonce
+	pub post_nonce: String,
+	/// Hex of the whole serialized candidate block, coinbase included
+	pub block: String,
+}
+
+/// Solved block submitted by external mining software, either as the nonce
+/// and proof of work found for a template or as a whole block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockSubmission {
+	/// Template the solution was found for
+	#[serde(default)]
+	pub template_id: Option<u64>,
+	/// Header nonce of the solution
+	#[serde(default)]
+	pub nonce: Option<u64>,
+	/// Cuckoo cycle nonces of the solution
+	#[serde(default)]
+	pub pow: Option<Vec<u32>>,
+	/// Hex of a whole solved block, instead of a template solution
+	#[serde(default)]
+	pub block: Option<String>,
+}
+
+/// Outcome of the validation of the chain data stored by the node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainValidation {
+	/// Whether the chain data is consistent
+	pub valid: bool,
+	/// Height of the first inconsistency found, if known
+	pub height: Option<u64>,
+	/// First inconsistency or error found
+	pub error: Option<String>,
+}
+
+impl ChainValidation {
+	pub fn from_result(res: Result<(), chain::Error>) -> ChainValidation {
+		match res {
+			Ok(()) => ChainValidation {
+				valid: true,
+				height: None,
+				error: None,
+			},
+			Err(chain::Error::Corrupted(height, msg)) => ChainValidation {
+				valid: false,
+				height: Some(height),
+				error: Some(msg),
+			},
+			Err(e) => ChainValidation {
+				valid: false,
+				height: None,
+				error: Some(format!("{:?}", e)),
+			},
 		}
 	}
 }
@@ -176,6 +368,19 @@ impl Utxo {
 	}
 }
 
+/// A transaction kernel included in the chain
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Kernel {
+	/// The kernel excess commitment
+	pub excess: PrintableCommitment,
+}
+
+impl Kernel {
+	pub fn new(excess: &pedersen::Commitment) -> Kernel {
+		Kernel { excess: PrintableCommitment(excess.clone()) }
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct PrintableCommitment(pedersen::Commitment);
 
@@ -570,6 +775,30 @@ pub struct BlockOutputs {
 	pub outputs: Vec<OutputPrintable>,
 }
 
+// For wallet restore, an unspent output along with its position in the
+// output MMR and the height of the block it was created in
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexedOutput {
+	/// Position of the output in the output MMR
+	pub index: u64,
+	/// Height of the block the output was created in
+	pub block_height: u64,
+	/// A printable version of the output, including its range proof
+	pub output: OutputPrintable,
+}
+
+/// A batch of unspent outputs, listed by their position in the output MMR so
+/// the whole UTXO set can be walked through.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputListing {
+	/// Highest position in the output MMR, the listing is done once past it
+	pub highest_index: u64,
+	/// Position of the last output in the batch, to start the next one after
+	pub last_retrieved_index: u64,
+	/// The outputs in the batch
+	pub outputs: Vec<IndexedOutput>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PoolInfo {
 	/// Size of the pool
@@ -578,6 +807,243 @@ pub struct PoolInfo {
 	pub orphans_size: usize,
 	/// Total size of pool + orphans
 	pub total_size: usize,
+	/// Number of transactions in their stem phase
+	pub stempool_size: usize,
+	/// Total weight of the pool transactions
+	pub total_weight: usize,
+}
+
+impl PoolInfo {
+	pub fn from_pool<T>(pool: &pool::TransactionPool<T>) -> PoolInfo
+	where
+		T: pool::BlockChain,
+	{
+		PoolInfo {
+			pool_size: pool.pool_size(),
+			orphans_size: pool.orphans_size(),
+			total_size: pool.total_size(),
+			stempool_size: pool.stempool_size(),
+			total_weight: pool.total_weight(),
+		}
+	}
+}
+
+/// Phase of a transaction in the pool
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum PoolTxState {
+	/// Only relayed to a single peer for now (Dandelion stem phase)
+	Stem,
+	/// Broadcast to all peers
+	Fluff,
+}
+
+/// Suggested fee for a transaction to get mined soon enough
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeEstimate {
+	/// Number of blocks the transaction should get mined within
+	pub blocks: u64,
+	/// Suggested fee per unit of transaction weight
+	pub fee_base: u64,
+}
+
+/// Difficulty and timestamp of a block, as seen by the difficulty adjustment
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DifficultyBlock {
+	/// Height of the block, none for the simulated pre-genesis blocks of the
+	/// adjustment window
+	pub height: Option<u64>,
+	/// Hash of the block, none for the simulated pre-genesis blocks
+	pub hash: Option<String>,
+	/// Timestamp of the block, in seconds since the epoch
+	pub timestamp: u64,
+	/// Difficulty the block was mined at
+	pub difficulty: u64,
+}
+
+/// Difficulty of the latest blocks and the adjustment giving the difficulty
+/// of the next one
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DifficultyStats {
+	/// Height of the chain head
+	pub height: u64,
+	/// Difficulty the next block should comply with
+	pub next_difficulty: u64,
+	/// Average difficulty over the adjustment window
+	pub average_difficulty: u64,
+	/// Median timestamp at the beginning of the adjustment window
+	pub earliest_median_ts: u64,
+	/// Median timestamp at the end of the adjustment window
+	pub latest_median_ts: u64,
+	/// Actual timespan of the adjustment window, between both medians
+	pub timespan: u64,
+	/// Timespan after dampening
+	pub damped_timespan: u64,
+	/// Dampened timespan within the time bounds, used for the adjustment
+	pub adjusted_timespan: u64,
+	/// Timespan the adjustment window should take at the target block time
+	pub target_timespan: u64,
+	/// Blocks the adjustment is computed from, earliest first
+	pub window: Vec<DifficultyBlock>,
+	/// Latest blocks of the chain, as requested, earliest first
+	pub blocks: Vec<DifficultyBlock>,
+}
+
+impl DifficultyBlock {
+	pub fn from_header(h: &core::BlockHeader) -> DifficultyBlock {
+		DifficultyBlock {
+			height: Some(h.height),
+			hash: Some(util::to_hex(h.hash().to_vec())),
+			timestamp: h.timestamp.to_timespec().sec as u64,
+			difficulty: h.difficulty.into_num(),
+		}
+	}
+}
+
+impl DifficultyStats {
+	/// Statistics from the adjustment computed at the chain head at the
+	/// provided height, along with the latest headers, earliest first.
+	pub fn from_adjustment(
+		height: u64,
+		adj: consensus::DifficultyAdjustment,
+		headers: &[core::BlockHeader],
+	) -> DifficultyStats {
+		let blocks = headers
+			.iter()
+			.map(|h| DifficultyBlock::from_header(h))
+			.collect::<Vec<_>>();
+		// the window ends at the head, preceded by simulated blocks when the
+		// chain is shorter than the window
+		let len = adj.window.len() as u64;
+		let window = adj.window
+			.iter()
+			.enumerate()
+			.map(|(i, &(ts, ref diff))| {
+				let block_height = (height + 1 + i as u64).checked_sub(len);
+				DifficultyBlock {
+					height: block_height,
+					hash: block_height.and_then(|bh| {
+						blocks
+							.iter()
+							.find(|b| b.height == Some(bh))
+							.and_then(|b| b.hash.clone())
+					}),
+					timestamp: ts,
+					difficulty: diff.into_num(),
+				}
+			})
+			.collect();
+		DifficultyStats {
+			height: height,
+			next_difficulty: adj.next_difficulty.into_num(),
+			average_difficulty: adj.average_difficulty,
+			earliest_median_ts: adj.earliest_median_ts,
+			latest_median_ts: adj.latest_median_ts,
+			timespan: adj.timespan,
+			damped_timespan: adj.damped_timespan,
+			adjusted_timespan: adj.adjusted_timespan,
+			target_timespan: consensus::BLOCK_TIME_WINDOW,
+			window: window,
+			blocks: blocks,
+		}
+	}
+}
+
+/// A transaction in the pool
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolTx {
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Hashes of the transaction kernels, several if the transaction is an
+	/// aggregate
+	pub kernel_hashes: Vec<String>,
+	/// Fee paid by the transaction
+	pub fee: u64,
+	/// Weight counted against the maximum block weight
+	pub weight: usize,
+	/// When the transaction was added, in seconds since the epoch
+	pub received: i64,
+	/// Stem or fluff phase
+	pub state: PoolTxState,
+}
+
+impl PoolTx {
+	pub fn from_info(info: &pool::PoolTxInfo) -> PoolTx {
+		PoolTx {
+			tx_hash: info.tx.hash().to_hex(),
+			kernel_hashes: info.tx.kernels.iter().map(|k| k.hash().to_hex()).collect(),
+			fee: info.tx.fee(),
+			weight: info.weight,
+			received: info.received,
+			state: if info.stem {
+				PoolTxState::Stem
+			} else {
+				PoolTxState::Fluff
+			},
+		}
+	}
+}
+
+/// Verdict of the pool checks on a transaction, run without adding it to the
+/// pool nor relaying it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxVerdict {
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Whether the pool would accept the transaction and relay it
+	pub accepted: bool,
+	/// Why the transaction would be rejected, if it would
+	pub error: Option<String>,
+	/// Fee paid by the transaction
+	pub fee: u64,
+	/// Weight counted against the maximum block weight, once checked
+	pub weight: Option<usize>,
+	/// Hashes of the pool transactions it would replace
+	pub replaces: Vec<String>,
+	/// Hashes of the pool transactions it spends outputs of
+	pub pool_parents: Vec<String>,
+	/// Inputs found neither in the chain nor in the pool, the transaction
+	/// being kept as an orphan until they show up
+	pub missing_inputs: Vec<String>,
+}
+
+impl TxVerdict {
+	pub fn from_check(
+		tx: &core::Transaction,
+		res: Result<pool::TxCheck, pool::PoolError>,
+	) -> TxVerdict {
+		let mut verdict = TxVerdict {
+			tx_hash: tx.hash().to_hex(),
+			accepted: false,
+			error: None,
+			fee: tx.fee(),
+			weight: None,
+			replaces: vec![],
+			pool_parents: vec![],
+			missing_inputs: vec![],
+		};
+		match res {
+			Ok(check) => {
+				verdict.accepted = check.missing_inputs.is_empty();
+				verdict.weight = Some(check.weight);
+				verdict.replaces = check.replaces.iter().map(|h| h.to_hex()).collect();
+				verdict.pool_parents = check.pool_parents.iter().map(|h| h.to_hex()).collect();
+				verdict.missing_inputs = check
+					.missing_inputs
+					.iter()
+					.map(|c| util::to_hex(c.0.to_vec()))
+					.collect();
+			}
+			// the pool doesn't tell what's wrong with an invalid transaction
+			Err(pool::PoolError::Invalid) => {
+				verdict.error = Some(match tx.validate() {
+					Err(e) => format!("Invalid({:?})", e),
+					Ok(_) => format!("{:?}", pool::PoolError::Invalid),
+				});
+			}
+			Err(e) => verdict.error = Some(format!("{:?}", e)),
+		}
+		verdict
+	}
 }
 
 #[test]
@@ -595,6 +1061,16 @@ fn serialize_output() {
 	assert_eq!(serialized, hex_output);
 }
 
+#[test]
+fn recent_blocks_found() {
+	let mut stats = MiningStats::default();
+	for height in 0..(MAX_RECENT_BLOCKS as u64 + 5) {
+		stats.add_block_found(height, format!("{}", height), "local miner".to_string(), 0);
+	}
+	assert_eq!(stats.recent_blocks.len(), MAX_RECENT_BLOCKS);
+	assert_eq!(stats.recent_blocks[0].height, MAX_RECENT_BLOCKS as u64 + 4);
+}
+
 #[test]
 fn serialize_utxo() {
 	let hex_commit = "{\"commit\":\"083eafae5d61a85ab07b12e1a51b3918d8e6de11fc6cde641d54af53608aa77b9f\"}";
@@ -16,32 +16,53 @@
 //! and mostly the chain pipeline.
 
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
-use util::secp::pedersen::RangeProof;
+use util::secp::key::SecretKey;
+use util::secp::pedersen::{Commitment, RangeProof};
 
 use core::core::{Input, OutputIdentifier, SumCommit};
-use core::core::pmmr::{HashSum, NoSum};
+use core::core::pmmr::{HashSum, MerkleProof, NoSum};
 
-use core::core::{Block, BlockHeader, TxKernel};
+use core::core::{Block, BlockHeader, Committed, TxKernel};
 use core::core::target::Difficulty;
-use core::core::hash::Hash;
-use grin_store::Error::NotFoundErr;
+use core::core::hash::{Hash, Hashed};
+use core::consensus;
+use core::global;
+use core::ser;
+use grin_store::Error::{NotFoundErr, SerErr};
+use checkpoints::Checkpoints;
 use pipe;
 use store;
 use sumtree;
 use types::*;
-use util::LOGGER;
+use util::{static_secp_instance, LOGGER};
+use util::memory::{self, MemoryConsumer};
+use util::zip;
+use util::tracing;
 
 
 const MAX_ORPHAN_AGE_SECS: u64 = 30;
+const MAX_ORPHAN_SIZE: usize = 200;
+
+// maximum number of blocks received during sync kept on disk while waiting
+// on their parent
+const MAX_SYNC_BLOCKS: usize = 1_000;
+
+// number of unspent outputs read at once when validating the output set
+const VALIDATE_OUTPUTS_BATCH: u64 = 1_000;
 
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
 	opts: Options,
 	added: Instant,
+	size: usize,
 }
 
 struct OrphanBlockPool {
@@ -77,6 +98,19 @@ impl OrphanBlockPool {
 			let mut orphans = self.orphans.write().unwrap();
 			let mut prev_idx = self.prev_idx.write().unwrap();
 			orphans.retain(|_, ref mut x| x.added.elapsed() < Duration::from_secs(MAX_ORPHAN_AGE_SECS));
+
+			// still too many, evict the oldest ones
+			if orphans.len() > MAX_ORPHAN_SIZE {
+				let mut by_age = orphans
+					.iter()
+					.map(|(h, x)| (x.added, h.clone()))
+					.collect::<Vec<_>>();
+				by_age.sort();
+				let excess = orphans.len() - MAX_ORPHAN_SIZE;
+				for &(_, ref h) in by_age.iter().take(excess) {
+					orphans.remove(h);
+				}
+			}
 			prev_idx.retain(|_, &mut x| orphans.contains_key(&x));
 		}
 	}
@@ -108,10 +142,41 @@ impl OrphanBlockPool {
 	}
 }
 
+/// Orphans can always be requested again, evicting the oldest first.
+impl MemoryConsumer for OrphanBlockPool {
+	fn memory_usage(&self) -> usize {
+		let orphans = self.orphans.read().unwrap();
+		orphans.values().map(|x| x.size).sum()
+	}
+
+	fn evict(&self, bytes: usize) -> usize {
+		let mut by_age = {
+			let orphans = self.orphans.read().unwrap();
+			orphans
+				.iter()
+				.map(|(h, x)| (x.added, h.clone()))
+				.collect::<Vec<_>>()
+		};
+		by_age.sort();
+
+		let mut freed = 0;
+		for (_, h) in by_age {
+			if freed >= bytes {
+				break;
+			}
+			if let Some(x) = self.remove(&h) {
+				freed += x.size;
+			}
+		}
+		freed
+	}
+}
+
 /// Facade to the blockchain block processing pipeline and storage. Provides
 /// the current view of the UTXO set according to the chain state. Also
 /// maintains locking for the pipeline to avoid conflicting processing.
 pub struct Chain {
+	db_root: String,
 	store: Arc<ChainStore>,
 	adapter: Arc<ChainAdapter>,
 
@@ -121,6 +186,21 @@ pub struct Chain {
 
 	// POW verification function
 	pow_verifier: fn(&BlockHeader, u32) -> bool,
+
+	config: ChainConfig,
+	checkpoints: Arc<Checkpoints>,
+	validation_cache: Arc<pipe::ValidationCache>,
+
+	// fork refused for being too deep, with the options its tip was
+	// processed with
+	pending_reorg: Mutex<Option<(PendingReorg, Options)>>,
+
+	// number of blocks saved during sync, waiting on their parent
+	sync_block_count: AtomicUsize,
+
+	// set once the chain is stopped, read-locked for the whole processing of
+	// a block so stopping waits for the block being processed
+	stopped: RwLock<bool>,
 }
 
 unsafe impl Sync for Chain {}
@@ -147,7 +227,10 @@ impl Chain {
 		adapter: Arc<ChainAdapter>,
 		genesis: Block,
 		pow_verifier: fn(&BlockHeader, u32) -> bool,
+		config: ChainConfig,
 	) -> Result<Chain, Error> {
+		config.validate()?;
+		let checkpoints = Checkpoints::new(&config)?;
 		let chain_store = store::ChainKVStore::new(db_root.clone())?;
 
 		// check if we have a head in store, otherwise the genesis block is it
@@ -155,11 +238,8 @@ impl Chain {
 			Ok(tip) => tip,
 			Err(NotFoundErr) => {
 				let tip = Tip::new(genesis.hash());
-				chain_store.save_block(&genesis)?;
-				chain_store.setup_height(&genesis.header, &tip)?;
-
 				// saving a new tip based on genesis
-				chain_store.save_head(&tip)?;
+				chain_store.save_block_as_head(&genesis, &tip, false)?;
 				info!(
 					LOGGER,
 					"Saved genesis block: {:?}, nonce: {:?}, pow: {:?}",
@@ -172,9 +252,48 @@ impl Chain {
 			Err(e) => return Err(Error::StoreErr(e, "chain init load head".to_owned())),
 		};
 
-		// Reset sync_head and header_head to head of current chain.
+		// data written by a version with another block header format can't be
+		// read, better to say so than failing on every block later on
+		if let Err(SerErr(_)) = chain_store.get_block_header(&head.last_block_h) {
+			return Err(Error::Other(format!(
+				"Chain data in {} is incompatible with this version, \
+				 it needs to be removed to sync the chain again",
+				db_root
+			)));
+		}
+
+		// An archive node must have every single block, which can't be the case
+		// anymore if we've been pruning before. Pruning always starts with the
+		// oldest block so checking the first one is enough.
+		if config.archive_mode && head.height > 0 {
+			let first = chain_store.get_header_by_height(1)?;
+			if let Err(NotFoundErr) = chain_store.get_block(&first.hash()) {
+				return Err(Error::Other(
+					"block history has been pruned, can't run in archive mode".to_owned(),
+				));
+			}
+		}
+
+		// Resume syncing from the header head if we were in the middle of
+		// syncing, its headers having all been validated already. Otherwise
+		// reset sync_head and header_head to head of current chain.
 		// Make sure sync_head is available for later use when needed.
-		chain_store.reset_head()?;
+		let header_head = chain_store
+			.get_header_head()
+			.ok()
+			.and_then(|t| chain_store.get_block_header(&t.last_block_h).ok().map(|_| t));
+		match header_head {
+			Some(ref t) if t.total_difficulty > head.total_difficulty => {
+				chain_store.save_sync_head(t)?;
+				info!(
+					LOGGER,
+					"Chain init: resuming sync from header head {} at {}",
+					t.last_block_h,
+					t.height,
+				);
+			}
+			_ => chain_store.reset_head()?,
+		}
 
 		info!(
 			LOGGER,
@@ -183,16 +302,44 @@ impl Chain {
 		);
 
 		let store = Arc::new(chain_store);
-		let sumtrees = sumtree::SumTrees::open(db_root, store.clone())?;
+		let sumtrees = sumtree::SumTrees::open(db_root.clone(), store.clone())?;
+
+		let orphans = Arc::new(OrphanBlockPool::new());
+		let validation_cache = Arc::new(pipe::ValidationCache::new());
+		memory::register(
+			"orphan blocks",
+			memory::Priority::Orphans,
+			Box::new(Arc::downgrade(&orphans)),
+		);
+		memory::register(
+			"validation cache",
+			memory::Priority::Cache,
+			Box::new(Arc::downgrade(&validation_cache)),
+		);
 
-		Ok(Chain {
+		let chain = Chain {
+			db_root: db_root,
 			store: store,
 			adapter: adapter,
-			head: Arc::new(Mutex::new(head)),
-			orphans: Arc::new(OrphanBlockPool::new()),
+			head: Arc::new(Mutex::new(head.clone())),
+			orphans: orphans,
 			sumtrees: Arc::new(RwLock::new(sumtrees)),
 			pow_verifier: pow_verifier,
-		})
+			config: config,
+			checkpoints: Arc::new(checkpoints),
+			validation_cache: validation_cache,
+			pending_reorg: Mutex::new(None),
+			sync_block_count: AtomicUsize::new(0),
+			stopped: RwLock::new(false),
+		};
+
+		// catch up on pruning, in case it just got enabled or the horizon was
+		// lowered since the last run
+		chain.prune_bodies(&head);
+
+		chain.resume_sync_blocks();
+
+		Ok(chain)
 	}
 /// Processes a single block, then checks for orphans, processing
 /// those as well if they're found
@@ -220,15 +367,35 @@ pub fn process_block(&self, b: Block, opts: Options)
 	pub fn process_block_no_orphans(&self, b: Block, opts: Options)
 		-> Result<(Option<Tip>, Option<Block>), Error>
 	{
+		let stopped = self.stopped.read().unwrap();
+		if *stopped {
+			return Err(Error::Other("chain stopped".to_owned()));
+		}
 		let head = self.store
 			.head()
 			.map_err(|e| Error::StoreErr(e, "chain load head".to_owned()))?;
-		let ctx = self.ctx_from_head(head, opts);
+		let ctx = self.ctx_from_head(head.clone(), opts);
 
 		let res = pipe::process_block(&b, ctx);
+		let trace_id = b.hash().to_hex();
+
+		// a block saved during sync isn't needed anymore once processed,
+		// valid or not
+		if opts.contains(SYNC) {
+			match res {
+				Err(Error::Orphan) => {}
+				_ => {
+					if let Err(e) = self.delete_sync_block(&b.header) {
+						warn!(LOGGER, "process_block: failed to delete sync block: {:?}", e);
+					}
+				}
+			}
+		}
 
 		match res {
 			Ok(Some(ref tip)) => {
+				tracing::event(&trace_id, "applied");
+
 				// block got accepted and extended the head, updating our head
 				let chain_head = self.head.clone();
 				{
@@ -236,11 +403,44 @@ pub fn process_block(&self, b: Block, opts: Options)
 					*head = tip.clone();
 				}
 
+				// discard the block bodies that just fell behind the horizon
+				self.prune_bodies(tip);
+
+				// a refused fork that has been overtaken isn't relevant anymore
+				{
+					let mut pending = self.pending_reorg.lock().unwrap();
+					let overtaken = match *pending {
+						Some((ref reorg, _)) => reorg.tip.total_difficulty <= tip.total_difficulty,
+						None => false,
+					};
+					if overtaken {
+						*pending = None;
+					}
+				}
+
 				// notifying other parts of the system of the update
 				if !opts.contains(SYNC) {
 					// broadcast the block
 					let adapter = self.adapter.clone();
 					adapter.block_accepted(&b, opts);
+
+					// the new head doesn't build on the old one, we switched
+					// to another branch
+					if tip.prev_block_h != head.last_block_h {
+						let depth = self.reorg_depth(&head);
+						info!(
+							LOGGER,
+							"Chain reorg from {} at {} to {} at {}, depth {}",
+							head.last_block_h,
+							head.height,
+							tip.last_block_h,
+							tip.height,
+							depth,
+						);
+						adapter.reorg(&head, tip, depth);
+					}
+				} else {
+					tracing::finish(&trace_id, "synced");
 				}
 				Ok((Some(tip.clone()), Some(b.clone())))
 			},
@@ -254,10 +454,13 @@ pub fn process_block(&self, b: Block, opts: Options)
 				// or less relevant blocks somehow.
 				// We should also probably consider banning nodes that send us really old blocks.
 				//
+				tracing::event(&trace_id, "forked");
 				if !opts.contains(SYNC) {
 					// broadcast the block
 					let adapter = self.adapter.clone();
 					adapter.block_accepted(&b, opts);
+				} else {
+					tracing::finish(&trace_id, "synced");
 				}
 				Ok((None, Some(b.clone())))
 			},
@@ -267,6 +470,7 @@ pub fn process_block(&self, b: Block, opts: Options)
 					block: b.clone(),
 					opts: opts,
 					added: Instant::now(),
+					size: ser::ser_vec(&b).map(|v| v.len()).unwrap_or(0),
 				};
 
 				// In the case of a fork - it is possible to have multiple blocks
@@ -274,6 +478,14 @@ pub fn process_block(&self, b: Block, opts: Options)
 				// We do not handle this currently for orphans (future enhancement?).
 				// We just assume "last one wins" for now.
 				&self.orphans.add(orphan);
+				tracing::event(&trace_id, "orphaned");
+
+				// during sync, keep it until processed, even over a restart
+				if opts.contains(SYNC) {
+					if let Err(e) = self.save_sync_block(&b) {
+						warn!(LOGGER, "process_block: failed to save sync block: {:?}", e);
+					}
+				}
 
 				debug!(
 					LOGGER,
@@ -283,6 +495,33 @@ pub fn process_block(&self, b: Block, opts: Options)
 				);
 				Err(Error::Orphan)
 			},
+			Err(Error::ReorgTooDeep(depth)) => {
+				// the block is stored but we stay on our chain until the
+				// operator accepts the reorg
+				let fork_tip = Tip::from_block(&b.header);
+				error!(
+					LOGGER,
+					"Refusing to reorg {} blocks from {} at {} to fork {} at {}, over the maximum \
+					 depth. Accept it through the API if legitimate.",
+					depth,
+					head.last_block_h,
+					head.height,
+					fork_tip.last_block_h,
+					fork_tip.height,
+				);
+				{
+					let mut pending = self.pending_reorg.lock().unwrap();
+					*pending = Some((
+						PendingReorg {
+							tip: fork_tip,
+							depth: depth,
+						},
+						opts,
+					));
+				}
+				tracing::finish(&trace_id, "reorg refused");
+				Ok((None, Some(b.clone())))
+			}
 			Err(Error::Unfit(ref msg)) => {
 				debug!(
 					LOGGER,
@@ -291,6 +530,7 @@ pub fn process_block(&self, b: Block, opts: Options)
 					b.header.height,
 					msg
 				);
+				tracing::finish(&trace_id, "unfit");
 				Err(Error::Unfit(msg.clone()))
 			}
 			Err(e) => {
@@ -301,6 +541,7 @@ pub fn process_block(&self, b: Block, opts: Options)
 					b.header.height,
 					e
 				);
+				tracing::finish(&trace_id, "rejected");
 				Err(e)
 			}
 		}
@@ -324,6 +565,10 @@ pub fn process_block(&self, b: Block, opts: Options)
 		bh: &BlockHeader,
 		opts: Options,
 	) -> Result<Option<Tip>, Error> {
+		let stopped = self.stopped.read().unwrap();
+		if *stopped {
+			return Err(Error::Other("chain stopped".to_owned()));
+		}
 		let sync_head = self.get_sync_head()?;
 		let header_head = self.get_header_head()?;
 		let sync_ctx = self.ctx_from_head(sync_head, opts);
@@ -338,12 +583,360 @@ pub fn process_block(&self, b: Block, opts: Options)
 			head: head,
 			pow_verifier: self.pow_verifier,
 			sumtrees: self.sumtrees.clone(),
+			checkpoints: self.checkpoints.clone(),
+			validation_cache: self.validation_cache.clone(),
+			max_reorg_depth: self.config.max_reorg_depth,
+		}
+	}
+
+	/// Fork with more work than our chain that hasn't been switched to as
+	/// it's deeper than the maximum reorg depth, if any.
+	pub fn pending_reorg(&self) -> Option<PendingReorg> {
+		self.pending_reorg
+			.lock()
+			.unwrap()
+			.as_ref()
+			.map(|&(ref reorg, _)| reorg.clone())
+	}
+
+	/// Switches to the pending fork refused for being too deep, once the
+	/// operator made sure it's legitimate. Returns the new chain tip.
+	pub fn accept_reorg(&self) -> Result<Tip, Error> {
+		let (reorg, opts) = match self.pending_reorg.lock().unwrap().clone() {
+			Some(pending) => pending,
+			None => return Err(Error::Other("no pending reorg".to_owned())),
+		};
+		warn!(
+			LOGGER,
+			"Accepting reorg of depth {} to {} at {}",
+			reorg.depth,
+			reorg.tip.last_block_h,
+			reorg.tip.height,
+		);
+		let b = self.get_block(&reorg.tip.last_block_h)?;
+		match self.process_block(b, opts | FORCE_REORG)? {
+			(Some(tip), _) => Ok(tip),
+			(None, _) => Err(Error::Unfit("fork isn't ahead anymore".to_owned())),
+		}
+	}
+
+	/// Stops the chain on shutdown: waits for the block being processed, if
+	/// any, refuses any further block and makes sure the chain store is
+	/// written to disk.
+	pub fn stop(&self) -> Result<(), Error> {
+		let mut stopped = self.stopped.write().unwrap();
+		*stopped = true;
+		self.store
+			.sync()
+			.map_err(|e| Error::StoreErr(e, "chain sync on stop".to_owned()))
+	}
+
+	/// Whether this chain retains the full block history and refuses to
+	/// compact historical data.
+	pub fn is_archive(&self) -> bool {
+		self.config.archive_mode
+	}
+
+	/// Compacts the chain data to reclaim disk space. Spent outputs and their
+	/// range proofs older than the cut-through horizon are physically removed
+	/// from the sum trees and, if a pruning horizon is configured, the block
+	/// bodies beyond it get deleted. Refused on archive nodes.
+	pub fn compact(&self) -> Result<(), Error> {
+		let stopped = self.stopped.read().unwrap();
+		if *stopped {
+			return Err(Error::Other("chain stopped".to_owned()));
+		}
+		if self.config.archive_mode {
+			return Err(Error::Other(
+				"archive nodes never compact their history".to_owned(),
+			));
+		}
+
+		let head = self.head()?;
+		let horizon = global::cut_through_horizon();
+		if head.height <= horizon {
+			return Ok(());
+		}
+		let removed = {
+			let mut sumtrees = self.sumtrees.write().unwrap();
+			sumtrees.compact(head.height - horizon)?
+		};
+		self.prune_bodies(&head);
+
+		info!(
+			LOGGER,
+			"chain: compacted {} sumtree positions older than {} (head at {})",
+			removed,
+			head.height - horizon,
+			head.height,
+		);
+		Ok(())
+	}
+
+	/// Zips the sum trees as they were at the provided block of the current
+	/// chain, along with its header, for operators to seed other nodes with.
+	/// Unless this is an archive node, the block has to be within the
+	/// cut-through horizon, older spent outputs being compacted away. Returns
+	/// the path of the archive, written in the chain data directory.
+	pub fn txhashset_archive(&self, h: &Hash) -> Result<PathBuf, Error> {
+		let header = self.get_block_header(h)?;
+		self.is_on_current_chain(&header)?;
+		let head = self.head()?;
+		let horizon = global::cut_through_horizon();
+		if !self.config.archive_mode && header.height + horizon < head.height {
+			return Err(Error::Other(format!(
+				"block {} at {} is beyond the cut-through horizon",
+				h, header.height
+			)));
+		}
+		// the sum trees get rewound to the block, undoing the later ones
+		let block = self.get_block(h)?;
+
+		let root = Path::new(&self.db_root);
+		let snapshot_dir = root.join(format!("txhashset_{}", h.to_hex()));
+		let snapshot_root = snapshot_dir.to_str().unwrap().to_string();
+		let _ = fs::remove_dir_all(&snapshot_dir);
+		{
+			// no block gets applied while copying
+			let _sumtrees = self.sumtrees.read().unwrap();
+			sumtree::copy_files(&self.db_root, &snapshot_root)?;
+		}
+
+		let res = self.build_archive(&block, &snapshot_dir, &snapshot_root);
+		let _ = fs::remove_dir_all(&snapshot_dir);
+		res
+	}
+
+	fn build_archive(
+		&self,
+		block: &Block,
+		snapshot_dir: &Path,
+		snapshot_root: &str,
+	) -> Result<PathBuf, Error> {
+		let mut snapshot = sumtree::SumTrees::open(snapshot_root.to_string(), self.store.clone())?;
+		sumtree::extending(&mut snapshot, |extension| extension.rewind(block))?;
+		File::create(snapshot_dir.join("header.bin"))?
+			.write_all(&ser::ser_vec(&block.header).map_err(Error::SerErr)?)?;
+
+		let archive_path = Path::new(&self.db_root).join(format!(
+			"txhashset_{}.zip",
+			block.hash().to_hex()
+		));
+		zip::compress(snapshot_dir, &File::create(&archive_path)?)?;
+		info!(
+			LOGGER,
+			"chain: txhashset at {} ({}) archived to {}",
+			block.hash(),
+			block.header.height,
+			archive_path.display(),
+		);
+		Ok(archive_path)
+	}
+
+	/// Replays and verifies the whole chain as stored, from genesis to the
+	/// head: header linkage, proofs of work and difficulties, all the block
+	/// bodies still stored, the sum tree roots of the head, the index of the
+	/// unspent outputs and the kernel sums. Returns the first inconsistency
+	/// found. The kernel sums can only be checked if no body was pruned.
+	///
+	/// Slow on a long chain and block processing waits for it to complete.
+	pub fn validate(&self) -> Result<(), Error> {
+		let mut sumtrees = self.sumtrees.write().unwrap();
+		let head = self.head()?;
+		let sizeshift = global::sizeshift() as u32;
+
+		let mut prev = self.get_header_by_height(0)?;
+		let mut kernel_excesses = vec![];
+		let mut offsets = vec![];
+		let mut overage: i64 = 0;
+		let mut pruned = 0;
+		for height in 1..head.height + 1 {
+			let header = self.get_header_by_height(height)?;
+			if header.height != height || header.previous != prev.hash() {
+				return Err(Error::Corrupted(
+					height,
+					"header doesn't follow the previous one".to_owned(),
+				));
+			}
+			if !(self.pow_verifier)(&header, sizeshift) {
+				return Err(Error::Corrupted(height, "invalid proof of work".to_owned()));
+			}
+			let diff_iter = store::DifficultyIter::from(header.previous, self.store.clone());
+			let difficulty =
+				consensus::next_difficulty(diff_iter).map_err(|e| Error::Other(e.to_string()))?;
+			if header.total_difficulty != prev.total_difficulty.clone() + difficulty {
+				return Err(Error::Corrupted(height, "wrong total difficulty".to_owned()));
+			}
+
+			match self.store.get_block(&header.hash()) {
+				Ok(block) => {
+					if block.hash() != header.hash() {
+						return Err(Error::Corrupted(
+							height,
+							"block doesn't match its header".to_owned(),
+						));
+					}
+					if let Err(e) = block.validate() {
+						return Err(Error::Corrupted(height, format!("invalid block: {:?}", e)));
+					}
+					kernel_excesses.extend(block.kernels.iter().map(|k| k.excess));
+					if !header.kernel_offset.is_zero() {
+						offsets.push(header.kernel_offset.secret_key());
+					}
+					overage += block.overage();
+				}
+				Err(NotFoundErr) => pruned += 1,
+				Err(e) => return Err(Error::StoreErr(e, "chain validate block".to_owned())),
+			}
+			prev = header;
 		}
+		if head.height == 0 {
+			return Ok(());
+		}
+
+		let (utxo_root, rproof_root, kernel_root) = sumtrees.roots();
+		if utxo_root.hash != prev.utxo_root || rproof_root.hash != prev.range_proof_root
+			|| kernel_root.hash != prev.kernel_root
+		{
+			return Err(Error::Corrupted(
+				head.height,
+				"sum tree roots don't match the head".to_owned(),
+			));
+		}
+
+		// all unspent outputs must be indexed at their position
+		let mut utxo_commits = vec![];
+		let mut start_pos = 1;
+		loop {
+			let (_, outputs) = sumtrees.outputs_from(start_pos, VALIDATE_OUTPUTS_BATCH);
+			if outputs.is_empty() {
+				break;
+			}
+			for (pos, output) in outputs {
+				match self.store.get_output_pos(&output.commit) {
+					Ok(indexed_pos) if indexed_pos == pos => {}
+					_ => {
+						return Err(Error::Corrupted(
+							head.height,
+							format!("unspent output {:?} not indexed at {}", output.commit, pos),
+						))
+					}
+				}
+				utxo_commits.push(output.commit);
+				start_pos = pos + 1;
+			}
+		}
+
+		if pruned > 0 {
+			warn!(
+				LOGGER,
+				"chain: validate skipped the kernel sums, {} block bodies pruned", pruned
+			);
+			return Ok(());
+		}
+		verify_kernel_sums(utxo_commits, kernel_excesses, offsets, overage)
+			.map_err(|e| Error::Corrupted(head.height, e))
 	}
 
-	/// Check if hash is for a known orphan.
+	/// Check if hash is for a known orphan, including the blocks received
+	/// during sync and saved until their parent gets processed.
 	pub fn is_orphan(&self, hash: &Hash) -> bool {
-		self.orphans.contains(hash)
+		self.orphans.contains(hash) || self.store.has_sync_block(hash).unwrap_or(false)
+	}
+
+	/// Drops the blocks received during sync still waiting on their parent,
+	/// once we're done syncing.
+	pub fn clear_sync_blocks(&self) -> Result<(), Error> {
+		for header in self.sync_block_headers()? {
+			self.delete_sync_block(&header)?;
+		}
+		Ok(())
+	}
+
+	// Saves a block received during sync, so it doesn't get downloaded again
+	// after a restart. Only the blocks of our header chain, which sync
+	// requested and whose proof of work got checked along their header, are
+	// kept, up to MAX_SYNC_BLOCKS.
+	fn save_sync_block(&self, b: &Block) -> Result<(), Error> {
+		let hash = b.hash();
+		if self.store.get_block_header(&hash).is_err() {
+			return Ok(());
+		}
+		if self.store.has_sync_block(&hash)? {
+			return Ok(());
+		}
+		if self.sync_block_count.load(Ordering::SeqCst) >= MAX_SYNC_BLOCKS {
+			debug!(LOGGER, "chain: too many sync blocks saved, not saving {}", hash);
+			return Ok(());
+		}
+		self.store
+			.save_sync_block(b)
+			.map_err(|e| Error::StoreErr(e, "chain save sync block".to_owned()))?;
+		self.sync_block_count.fetch_add(1, Ordering::SeqCst);
+		Ok(())
+	}
+
+	fn delete_sync_block(&self, header: &BlockHeader) -> Result<(), Error> {
+		if !self.store.has_sync_block(&header.hash())? {
+			return Ok(());
+		}
+		self.store
+			.delete_sync_block(header)
+			.map_err(|e| Error::StoreErr(e, "chain delete sync block".to_owned()))?;
+		self.sync_block_count.fetch_sub(1, Ordering::SeqCst);
+		Ok(())
+	}
+
+	// Headers of the blocks saved during sync, reading the blocks one at a
+	// time
+	fn sync_block_headers(&self) -> Result<Vec<BlockHeader>, Error> {
+		let blocks = self.store
+			.sync_blocks()
+			.map_err(|e| Error::StoreErr(e, "chain sync blocks".to_owned()))?;
+		Ok(blocks.map(|b| b.header).collect())
+	}
+
+	/// Picks up the blocks received during sync and saved before a restart.
+	/// The ones processed since get dropped and the ones whose parent we
+	/// have get processed, the others keep waiting on their parent.
+	fn resume_sync_blocks(&self) {
+		let mut headers = match self.sync_block_headers() {
+			Ok(headers) => headers,
+			Err(e) => {
+				warn!(LOGGER, "Chain init: failed to read sync blocks: {:?}", e);
+				return;
+			}
+		};
+		headers.sort_by_key(|h| h.height);
+		self.sync_block_count.store(headers.len(), Ordering::SeqCst);
+
+		let mut processed = 0;
+		let mut waiting = 0;
+		for header in headers {
+			let hash = header.hash();
+			if self.store.get_block(&hash).is_ok() {
+				let _ = self.delete_sync_block(&header);
+			} else if self.store.get_block(&header.previous).is_ok() {
+				// its parent got processed right before the restart
+				let b = match self.store.get_sync_block(&hash) {
+					Ok(b) => b,
+					Err(_) => continue,
+				};
+				if self.process_block(b, SYNC).is_ok() {
+					processed += 1;
+				}
+			} else {
+				waiting += 1;
+			}
+		}
+		if processed > 0 || waiting > 0 {
+			info!(
+				LOGGER,
+				"Chain init: resuming sync with {} saved blocks processed, {} waiting on their parent",
+				processed,
+				waiting,
+			);
+		}
 	}
 
 
@@ -359,9 +952,19 @@ pub fn process_block(&self, b: Block, opts: Options)
 		// We just processed the given block, are there any orphans that have this block
 		// as their "previous" block?
 		loop {
-			if let Some(orphan) = self.orphans.get_by_previous(&last_block_hash) {
-				self.orphans.remove(&orphan.block.hash());
-				let res = self.process_block_no_orphans(orphan.block, orphan.opts);
+			// or a block saved during sync, possibly before a restart
+			let next = match self.orphans.get_by_previous(&last_block_hash) {
+				Some(orphan) => {
+					self.orphans.remove(&orphan.block.hash());
+					Some((orphan.block, orphan.opts))
+				}
+				None => self.store
+					.get_sync_block_by_previous(&last_block_hash)
+					.ok()
+					.map(|b| (b, SYNC)),
+			};
+			if let Some((block, opts)) = next {
+				let res = self.process_block_no_orphans(block, opts);
 				match res {
 					Ok((_, b)) => {
 						// We accepted a block, so see if we can accept any orphans
@@ -381,6 +984,72 @@ pub fn process_block(&self, b: Block, opts: Options)
 		}
 	}
 
+	/// Number of blocks of the branch ending at old_tip that aren't on the
+	/// current chain anymore, walking back to the fork point.
+	fn reorg_depth(&self, old_tip: &Tip) -> u64 {
+		let head_height = self.head.lock().unwrap().height;
+		let mut depth = 0;
+		let mut current = old_tip.last_block_h;
+		loop {
+			let header = match self.store.get_block_header(&current) {
+				Ok(header) => header,
+				Err(_) => break,
+			};
+			if header.height <= head_height && self.store.is_on_current_chain(&header).is_ok() {
+				break;
+			}
+			depth += 1;
+			current = header.previous;
+		}
+		depth
+	}
+
+	/// Deletes the bodies of all blocks on the current chain that are further
+	/// than the configured pruning horizon behind the provided tip. Walks back
+	/// from the horizon until it finds a body that's already been removed, so
+	/// it's cheap to call on every new head. Headers are left untouched and
+	/// the genesis block is always kept.
+	fn prune_bodies(&self, tip: &Tip) {
+		if !self.config.is_pruning() {
+			return;
+		}
+		let horizon = match self.config.pruning_horizon {
+			Some(horizon) => horizon,
+			None => return,
+		};
+		if tip.height <= horizon {
+			return;
+		}
+
+		let mut height = tip.height - horizon;
+		let mut pruned = 0;
+		while height > 0 {
+			let hash = match self.store.get_header_by_height(height) {
+				Ok(header) => header.hash(),
+				Err(_) => break,
+			};
+			if let Err(_) = self.store.get_block(&hash) {
+				break;
+			}
+			if let Err(e) = self.store.delete_block(&hash) {
+				error!(LOGGER, "chain: failed to prune block {} at {}: {:?}", hash, height, e);
+				break;
+			}
+			pruned += 1;
+			height -= 1;
+		}
+
+		if pruned > 0 {
+			debug!(
+				LOGGER,
+				"chain: pruned {} block bodies beyond horizon {} (head at {})",
+				pruned,
+				horizon,
+				tip.height,
+			);
+		}
+	}
+
 	/// For the given commitment find the unspent output and return the associated
 	/// Return an error if the output does not exist or has been spent.
 	/// This querying is done in a way that is consistent with the current chain state,
@@ -438,6 +1107,14 @@ pub fn process_block(&self, b: Block, opts: Options)
 		sumtrees.last_n_utxo(distance)
 	}
 
+	/// Unspent outputs by position in the output MMR, starting from the
+	/// provided one, up to max of them. Also returns the size of the MMR,
+	/// the highest position.
+	pub fn unspent_outputs_from(&self, start_pos: u64, max: u64) -> (u64, Vec<(u64, SumCommit)>) {
+		let mut sumtrees = self.sumtrees.write().unwrap();
+		sumtrees.outputs_from(start_pos, max)
+	}
+
 	/// as above, for rangeproofs
 	pub fn get_last_n_rangeproof(&self, distance: u64) -> Vec<HashSum<NoSum<RangeProof>>> {
 		let mut sumtrees = self.sumtrees.write().unwrap();
@@ -495,6 +1172,84 @@ pub fn process_block(&self, b: Block, opts: Options)
 		})
 	}
 
+	/// Gets at most count consecutive block headers of the current chain,
+	/// starting at the provided height
+	pub fn get_headers_from_height(
+		&self,
+		height: u64,
+		count: usize,
+	) -> Result<Vec<BlockHeader>, Error> {
+		self.store.get_headers_from_height(height, count).map_err(|e| {
+			Error::StoreErr(e, "chain get headers from height".to_owned())
+		})
+	}
+
+	/// Gets the block at the provided height on the current chain
+	pub fn get_block_by_height(&self, height: u64) -> Result<Block, Error> {
+		let header = self.get_header_by_height(height)?;
+		self.get_block(&header.hash())
+	}
+
+	/// Finds the block on the current chain the output with the provided
+	/// commitment has been created in, and whether it's been spent since.
+	pub fn get_output(&self, commit: &Commitment) -> Result<OutputLocation, Error> {
+		let block_hash = match self.store.get_output_block(commit) {
+			Ok(h) => h,
+			Err(NotFoundErr) => return Err(Error::OutputNotFound),
+			Err(e) => return Err(Error::StoreErr(e, "chain get output block".to_owned())),
+		};
+		// the index isn't rewound on forks, the block may not be ours anymore
+		let header = self.get_block_header(&block_hash)?;
+		if self.is_on_current_chain(&header).is_err() {
+			return Err(Error::OutputNotFound);
+		}
+		let unspent = {
+			let mut sumtrees = self.sumtrees.write().unwrap();
+			sumtrees.is_unspent_commit(commit)?
+		};
+		Ok(OutputLocation {
+			commit: *commit,
+			block_hash: block_hash,
+			height: header.height,
+			spent: !unspent,
+		})
+	}
+
+	/// Whether a transaction kernel with the provided excess made it in the
+	/// chain.
+	pub fn has_kernel(&self, excess: &Commitment) -> Result<bool, Error> {
+		let mut sumtrees = self.sumtrees.write().unwrap();
+		sumtrees.has_kernel(excess)
+	}
+
+	/// Builds a proof of inclusion of the unspent output with the provided
+	/// commitment in the output MMR, which can be checked against the utxo
+	/// root of the chain head or, if provided, of the given header. The header
+	/// must be on the current chain and more recent than the compaction
+	/// horizon.
+	pub fn get_merkle_proof(
+		&self,
+		commit: &Commitment,
+		header: Option<&BlockHeader>,
+	) -> Result<MerkleProof<SumCommit>, Error> {
+		let header = match header {
+			Some(header) => header,
+			None => {
+				let mut sumtrees = self.sumtrees.write().unwrap();
+				return sumtrees.merkle_proof(commit);
+			}
+		};
+		self.is_on_current_chain(header)?;
+		let block = self.get_block(&header.hash())?;
+
+		let mut sumtrees = self.sumtrees.write().unwrap();
+		sumtree::extending(&mut sumtrees, |extension| {
+			extension.force_rollback();
+			extension.rewind(&block)?;
+			extension.merkle_proof(commit)
+		})
+	}
+
 	/// Verifies the given block header is actually on the current chain.
 	/// Checks the header_by_height index to verify the header is where we say it is
 	pub fn is_on_current_chain(&self, header: &BlockHeader) -> Result<(), Error> {
@@ -526,3 +1281,88 @@ pub fn process_block(&self, b: Block, opts: Options)
 		store::DifficultyIter::from(head.last_block_h, self.store.clone())
 	}
 }
+
+// The unspent outputs, minus all the rewards and plus all the burnt fees so
+// far (the overage of all blocks), must sum to the kernel excesses and
+// offsets of all blocks.
+fn verify_kernel_sums(
+	utxo_commits: Vec<Commitment>,
+	kernel_excesses: Vec<Commitment>,
+	offsets: Vec<SecretKey>,
+	overage: i64,
+) -> Result<(), String> {
+	let secp = static_secp_instance();
+	let secp = secp.lock().unwrap();
+
+	let mut excesses = kernel_excesses;
+	for offset in offsets {
+		excesses.push(secp.commit(0, offset)
+			.map_err(|e| format!("kernel offset: {:?}", e))?);
+	}
+	let kernel_sum = secp.commit_sum(excesses, vec![])
+		.map_err(|e| format!("kernel sum: {:?}", e))?;
+
+	let mut positive = utxo_commits;
+	let mut negative = vec![];
+	if overage != 0 {
+		let over_commit = secp.commit_value(overage.abs() as u64)
+			.map_err(|e| format!("overage: {:?}", e))?;
+		if overage < 0 {
+			negative.push(over_commit);
+		} else {
+			positive.push(over_commit);
+		}
+	}
+	let utxo_sum = secp.commit_sum(positive, negative)
+		.map_err(|e| format!("unspent outputs sum: {:?}", e))?;
+
+	if utxo_sum != kernel_sum {
+		return Err("kernel sums don't match the unspent outputs".to_owned());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn orphan(height: u64, age_ms: u64) -> Orphan {
+		let mut block = Block::default();
+		block.header.height = height;
+		block.header.previous = BlockHeader {
+			height: height - 1,
+			..Default::default()
+		}.hash();
+		Orphan {
+			block: block,
+			opts: NONE,
+			added: Instant::now() - Duration::from_millis(age_ms),
+			size: 0,
+		}
+	}
+
+	#[test]
+	fn bounded_orphans() {
+		let pool = OrphanBlockPool::new();
+		let extra = 5;
+		let count = MAX_ORPHAN_SIZE as u64 + extra;
+		for n in 1..(count + 1) {
+			pool.add(orphan(n, 1_000 - n));
+		}
+
+		// the oldest ones got evicted, with their parent index
+		assert_eq!(pool.len(), MAX_ORPHAN_SIZE);
+		for n in 1..(count + 1) {
+			let o = orphan(n, 0);
+			let kept = n > extra;
+			assert_eq!(pool.contains(&o.block.hash()), kept);
+			assert_eq!(pool.get_by_previous(&o.block.header.previous).is_some(), kept);
+		}
+
+		// and the expired ones on the next addition
+		pool.add(orphan(count + 1, MAX_ORPHAN_AGE_SECS * 1_000 + 1));
+		pool.add(orphan(count + 2, 0));
+		assert!(!pool.contains(&orphan(count + 1, 0).block.hash()));
+		assert!(pool.contains(&orphan(count + 2, 0).block.hash()));
+	}
+}
@@ -0,0 +1,188 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkpoints, known good block hashes at given heights. Headers
+//! contradicting a checkpoint are rejected, which prevents any fork of the
+//! history below the highest checkpoint. As that history can't change, the
+//! expensive validation of the blocks it contains (signatures and range
+//! proofs) can also be skipped during sync, for the blocks the highest
+//! checkpoint builds on.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use core::core::BlockHeader;
+use core::core::hash::{Hash, Hashed};
+use core::global;
+use core::global::ChainTypes;
+use types::{ChainConfig, ChainStore, Error};
+use util;
+
+/// Checkpoints shipped with the node for each chain type, as height and block
+/// hash pairs. Testing chains are started from scratch all the time so they
+/// never get any.
+fn embedded_checkpoints(chain_type: &ChainTypes) -> Vec<(u64, &'static str)> {
+	match *chain_type {
+		// to be filled as the main chain grows
+		ChainTypes::Mainnet => vec![],
+		_ => vec![],
+	}
+}
+
+/// Set of checkpoints the chain validates against
+#[derive(Debug)]
+pub struct Checkpoints {
+	checkpoints: BTreeMap<u64, Hash>,
+	assume_valid: bool,
+	// hashes of the highest checkpoint and all its ancestors, by height, once
+	// all their headers are known
+	ancestors: Mutex<Vec<Hash>>,
+}
+
+impl Checkpoints {
+	/// Checkpoints embedded for the current chain type, augmented with the
+	/// configured ones. A configured checkpoint overrides the embedded one at
+	/// the same height.
+	pub fn new(config: &ChainConfig) -> Result<Checkpoints, Error> {
+		let mut checkpoints = BTreeMap::new();
+		let chain_type = global::CHAIN_TYPE.read().unwrap().clone();
+		for (height, hash) in embedded_checkpoints(&chain_type) {
+			checkpoints.insert(height, parse_hash(hash)?);
+		}
+		for cp in &config.checkpoints {
+			checkpoints.insert(cp.height, parse_hash(&cp.hash)?);
+		}
+		Ok(Checkpoints {
+			checkpoints: checkpoints,
+			assume_valid: config.assume_valid,
+			ancestors: Mutex::new(vec![]),
+		})
+	}
+
+	/// Height of the highest checkpoint, if any.
+	pub fn highest(&self) -> Option<u64> {
+		self.checkpoints.keys().next_back().cloned()
+	}
+
+	/// Checks the header doesn't contradict the checkpoint at its height.
+	pub fn check(&self, header: &BlockHeader) -> Result<(), Error> {
+		match self.checkpoints.get(&header.height) {
+			Some(hash) if *hash != header.hash() => {
+				Err(Error::CheckpointMismatch(header.height, *hash))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Highest checkpoint at or below the provided height.
+	pub fn last_below(&self, height: u64) -> Option<(u64, Hash)> {
+		self.checkpoints
+			.range(..height + 1)
+			.next_back()
+			.map(|(h, hash)| (*h, *hash))
+	}
+
+	/// Whether the full validation of the block with the provided header can
+	/// be skipped, as assume valid is on and the highest checkpoint builds on
+	/// it. Blocks of any other fork, even below the checkpoint, are never
+	/// assumed valid.
+	pub fn assumed_valid(&self, header: &BlockHeader, store: &ChainStore) -> bool {
+		if !self.assume_valid {
+			return false;
+		}
+		let (height, hash) = match self.checkpoints.iter().next_back() {
+			Some((height, hash)) => (*height, *hash),
+			None => return false,
+		};
+		if header.height > height {
+			return false;
+		}
+		let mut ancestors = self.ancestors.lock().unwrap();
+		if ancestors.is_empty() {
+			match ancestry(store, hash, height) {
+				Some(hashes) => *ancestors = hashes,
+				None => return false,
+			}
+		}
+		ancestors.get(header.height as usize) == Some(&header.hash())
+	}
+}
+
+// Hashes of the block with the provided hash and all its ancestors, indexed
+// by height, following the previous hash of each header. None until all of
+// them are in the store.
+fn ancestry(store: &ChainStore, hash: Hash, height: u64) -> Option<Vec<Hash>> {
+	let mut hashes = vec![hash; height as usize + 1];
+	let mut header = match store.get_block_header(&hash) {
+		Ok(header) => header,
+		Err(_) => return None,
+	};
+	if header.height != height {
+		return None;
+	}
+	while header.height > 0 {
+		let prev = match store.get_block_header(&header.previous) {
+			Ok(prev) => prev,
+			Err(_) => return None,
+		};
+		if prev.height + 1 != header.height {
+			return None;
+		}
+		hashes[prev.height as usize] = header.previous;
+		header = prev;
+	}
+	Some(hashes)
+}
+
+fn parse_hash(hash: &str) -> Result<Hash, Error> {
+	if hash.len() == 64 {
+		if let Ok(bytes) = util::from_hex(hash.to_string()) {
+			return Ok(Hash::from_vec(bytes));
+		}
+	}
+	Err(Error::Other(format!("invalid checkpoint hash {}", hash)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use types::Checkpoint;
+
+	#[test]
+	fn configured_checkpoints() {
+		let header = BlockHeader::default();
+		let mut config = ChainConfig::default();
+		config.checkpoints = vec![
+			Checkpoint {
+				height: 0,
+				hash: header.hash().to_hex(),
+			},
+			Checkpoint {
+				height: 10,
+				hash: header.hash().to_hex(),
+			},
+		];
+		let checkpoints = Checkpoints::new(&config).unwrap();
+
+		assert_eq!(checkpoints.highest(), Some(10));
+		assert_eq!(checkpoints.last_below(9), Some((0, header.hash())));
+		assert_eq!(checkpoints.last_below(10), Some((10, header.hash())));
+
+		assert!(checkpoints.check(&header).is_ok());
+		let mut other = header.clone();
+		other.height = 10;
+		other.nonce = 1;
+		assert!(checkpoints.check(&other).is_err());
+	}
+}
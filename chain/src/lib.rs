@@ -35,6 +35,7 @@ extern crate grin_store;
 extern crate grin_util as util;
 
 mod chain;
+pub mod checkpoints;
 pub mod pipe;
 pub mod store;
 pub mod sumtree;
@@ -43,4 +44,6 @@ pub mod types;
 // Re-export the base interface
 
 pub use chain::Chain;
-pub use types::{ChainAdapter, ChainStore, Error, Options, Tip, NONE, SKIP_POW, SYNC, MINE};
+pub use types::{ChainAdapter, ChainConfig, ChainStore, Checkpoint, Error, Options,
+                OutputLocation, PendingReorg, SyncState, SyncStatus, Tip, NONE, SKIP_POW, SYNC,
+                MINE, FORCE_REORG};
@@ -14,12 +14,14 @@
 
 //! Implementation of the chain block acceptance (or refusal) pipeline.
 
-use std::sync::{Arc, RwLock};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
 
 use time;
 
+use checkpoints::Checkpoints;
 use core::consensus;
-use core::core::hash::{Hash, Hashed};
+use core::core::hash::{Hash, HashWriter, Hashed};
 use core::core::{Block, BlockHeader};
 use core::core::target::Difficulty;
 use grin_store;
@@ -27,7 +29,118 @@ use types::*;
 use store;
 use sumtree;
 use core::global;
+use core::ser::{self, Writer};
 use util::LOGGER;
+use util::memory::MemoryConsumer;
+use util::tracing;
+
+/// Number of header and block hashes the validation cache remembers each
+const VALIDATION_CACHE_SIZE: usize = 2_000;
+
+/// Bounded set of hashes, evicting the oldest ones first.
+struct HashCache {
+	hashes: HashSet<Hash>,
+	order: VecDeque<Hash>,
+}
+
+impl HashCache {
+	fn new() -> HashCache {
+		HashCache {
+			hashes: HashSet::new(),
+			order: VecDeque::new(),
+		}
+	}
+
+	fn contains(&self, h: &Hash) -> bool {
+		self.hashes.contains(h)
+	}
+
+	fn len(&self) -> usize {
+		self.order.len()
+	}
+
+	// Evicts the n oldest hashes, returns how many were actually evicted
+	fn evict(&mut self, n: usize) -> usize {
+		let mut evicted = 0;
+		while evicted < n {
+			match self.order.pop_front() {
+				Some(h) => {
+					self.hashes.remove(&h);
+					evicted += 1;
+				}
+				None => break,
+			}
+		}
+		evicted
+	}
+
+	fn insert(&mut self, h: Hash) {
+		if self.hashes.insert(h) {
+			self.order.push_back(h);
+			if self.order.len() > VALIDATION_CACHE_SIZE {
+				if let Some(old) = self.order.pop_front() {
+					self.hashes.remove(&old);
+				}
+			}
+		}
+	}
+}
+
+/// Remembers the headers whose proof of work has been verified and the blocks
+/// that passed the isolated block validation (sums, signatures and range
+/// proofs), so the same objects coming through again on reorgs or repeated
+/// relay don't get the expensive checks recomputed. Contextual checks
+/// (difficulty, sum tree roots, etc.) are always run. Blocks are only added
+/// once fully processed and are identified by the hash of their whole
+/// content, as the block hash only covers the header.
+pub struct ValidationCache {
+	headers: Mutex<HashCache>,
+	blocks: Mutex<HashCache>,
+}
+
+impl ValidationCache {
+	/// Builds a new empty cache.
+	pub fn new() -> ValidationCache {
+		ValidationCache {
+			headers: Mutex::new(HashCache::new()),
+			blocks: Mutex::new(HashCache::new()),
+		}
+	}
+
+	fn has_valid_pow(&self, h: &Hash) -> bool {
+		self.headers.lock().unwrap().contains(h)
+	}
+
+	fn add_valid_pow(&self, h: Hash) {
+		self.headers.lock().unwrap().insert(h);
+	}
+
+	fn has_valid_block(&self, h: &Hash) -> bool {
+		self.blocks.lock().unwrap().contains(h)
+	}
+
+	fn add_valid_block(&self, h: Hash) {
+		self.blocks.lock().unwrap().insert(h);
+	}
+}
+
+/// Rough memory cost of a cached hash, accounting for both the set and the
+/// eviction queue
+const CACHED_HASH_SIZE: usize = 80;
+
+impl MemoryConsumer for ValidationCache {
+	fn memory_usage(&self) -> usize {
+		let len = self.headers.lock().unwrap().len() + self.blocks.lock().unwrap().len();
+		len * CACHED_HASH_SIZE
+	}
+
+	fn evict(&self, bytes: usize) -> usize {
+		let n = bytes / CACHED_HASH_SIZE + 1;
+		let from_headers = self.headers.lock().unwrap().evict(n);
+		let from_blocks = self.blocks.lock().unwrap().evict(n - from_headers);
+		(from_headers + from_blocks) * CACHED_HASH_SIZE
+	}
+}
 
 /// Contextual information required to process a new block and either reject or
 /// accept it.
@@ -42,6 +155,12 @@ pub struct BlockContext {
 	pub pow_verifier: fn(&BlockHeader, u32) -> bool,
 	/// MMR sum tree states
 	pub sumtrees: Arc<RwLock<sumtree::SumTrees>>,
+	/// Known good blocks the chain can't fork from
+	pub checkpoints: Arc<Checkpoints>,
+	/// Headers and blocks already proven valid
+	pub validation_cache: Arc<ValidationCache>,
+	/// Maximum number of blocks a reorg can remove from the chain
+	pub max_reorg_depth: Option<u64>,
 }
 
 /// Runs the block processing pipeline, including validation and finding a
@@ -87,10 +206,13 @@ pub fn process_block(b: &Block, mut ctx: BlockContext) -> Result<Option<Tip>, Er
 		.head()
 		.map_err(|e| Error::StoreErr(e, "pipe reload head".to_owned()))?;
 
+	let content_hash = block_content_hash(b)?;
+
 	// start a chain extension unit of work dependent on the success of the
 	// internal validation and saving operations
-	sumtree::extending(&mut sumtrees, |mut extension| {
-		validate_block(b, &mut ctx, &mut extension)?;
+	let res = sumtree::extending(&mut sumtrees, |mut extension| {
+		validate_block(b, &content_hash, &mut ctx, &mut extension)?;
+		tracing::event(&b.hash().to_hex(), "validated");
 		debug!(
 			LOGGER,
 			"pipe: process_block: {} at {} is valid, save and append.",
@@ -98,13 +220,23 @@ pub fn process_block(b: &Block, mut ctx: BlockContext) -> Result<Option<Tip>, Er
 			b.header.height,
 		);
 
-		add_block(b, &mut ctx)?;
-		let h = update_head(b, &mut ctx)?;
+		let h = add_block(b, &mut ctx)?;
 		if h.is_none() {
 			extension.force_rollback();
 		}
 		Ok(h)
-	})
+	})?;
+	ctx.validation_cache.add_valid_block(content_hash);
+	Ok(res)
+}
+
+// Hash of the whole serialized block, proof of work and body included, the
+// block hash only covering the header.
+fn block_content_hash(b: &Block) -> Result<Hash, Error> {
+	let data = ser::ser_vec(b).map_err(Error::SerErr)?;
+	let mut hasher = HashWriter::default();
+	hasher.write_fixed_bytes(&data).map_err(Error::SerErr)?;
+	Ok(hasher.into_hash())
 }
 
 /// Process the block header.
@@ -203,6 +335,8 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext) -> Result<(), E
 		return Err(Error::InvalidBlockVersion(header.version));
 	}
 
+	ctx.checkpoints.check(header)?;
+
 	if header.timestamp
 		> time::now_utc() + time::Duration::seconds(12 * (consensus::BLOCK_TIME_SEC as i64))
 	{
@@ -211,7 +345,7 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext) -> Result<(), E
 		return Err(Error::InvalidBlockTime);
 	}
 
-	if !ctx.opts.contains(SKIP_POW) {
+	if !ctx.opts.contains(SKIP_POW) && !ctx.validation_cache.has_valid_pow(&header.hash()) {
 		let n = global::sizeshift() as u32;
 		if !(ctx.pow_verifier)(header, n) {
 			error!(LOGGER, "pipe: validate_header failed for cuckoo shift size {}", n);
@@ -220,6 +354,7 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext) -> Result<(), E
 		if header.height % 500 == 0 {
 			debug!(LOGGER, "Validating header validated, using cuckoo shift size {}", n);
 		}
+		ctx.validation_cache.add_valid_pow(header.hash());
 	}
 
 	// first I/O cost, better as late as possible
@@ -235,6 +370,20 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext) -> Result<(), E
 		return Err(Error::InvalidBlockHeight);
 	}
 
+	// once our chain went through a checkpoint, anything forking off before
+	// it is refused
+	let head = ctx.store
+		.head()
+		.map_err(|e| Error::StoreErr(e, "pipe checkpoint head".to_owned()))?;
+	if let Some((cp_height, cp_hash)) = ctx.checkpoints.last_below(head.height) {
+		if header.height <= cp_height {
+			match ctx.store.get_header_by_height(header.height) {
+				Ok(ref h) if h.hash() == header.hash() => {}
+				_ => return Err(Error::CheckpointMismatch(cp_height, cp_hash)),
+			}
+		}
+	}
+
 	// TODO - get rid of the automated testing mode check here somehow
 	if header.timestamp <= prev.timestamp && !global::is_automated_testing_mode() {
 		// prevent time warp attacks and some timestamp manipulations by forcing strict
@@ -287,12 +436,20 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext) -> Result<(), E
 /// Fully validate the block content.
 fn validate_block(
 	b: &Block,
+	content_hash: &Hash,
 	ctx: &mut BlockContext,
 	ext: &mut sumtree::Extension,
 ) -> Result<(), Error> {
 
-	// main isolated block validation, checks all commitment sums and sigs
-	b.validate().map_err(&Error::InvalidBlockProof)?;
+	// main isolated block validation, checks all commitment sums and sigs,
+	// skipped when syncing history that's set in stone by a checkpoint (the
+	// sum tree roots are still checked against the header) or if the block
+	// already went through it
+	let assumed_valid =
+		ctx.opts.contains(SYNC) && ctx.checkpoints.assumed_valid(&b.header, &*ctx.store);
+	if !assumed_valid && !ctx.validation_cache.has_valid_block(content_hash) {
+		b.validate().map_err(&Error::InvalidBlockProof)?;
+	}
 
 	if b.header.previous != ctx.head.last_block_h {
 		rewind_and_apply_fork(b, ctx.store.clone(), ext)?;
@@ -332,11 +489,27 @@ fn validate_block(
 	Ok(())
 }
 
-/// Officially adds the block to our chain.
-fn add_block(b: &Block, ctx: &mut BlockContext) -> Result<(), Error> {
-	ctx.store
-		.save_block(b)
-		.map_err(|e| Error::StoreErr(e, "pipe save block".to_owned()))
+/// Officially adds the block to our chain, making it the new head if it's
+/// on the chain with the most work. Returns the new head if updated.
+fn add_block(b: &Block, ctx: &mut BlockContext) -> Result<Option<Tip>, Error> {
+	let h = match update_head(b, ctx) {
+		Ok(h) => h,
+		// the tip of a fork too deep to switch to is kept, for the operator
+		// to accept the reorg later on
+		Err(Error::ReorgTooDeep(depth)) => {
+			ctx.store
+				.save_block(b)
+				.map_err(|e| Error::StoreErr(e, "pipe save block".to_owned()))?;
+			return Err(Error::ReorgTooDeep(depth));
+		}
+		Err(e) => return Err(e),
+	};
+	if h.is_none() {
+		ctx.store
+			.save_block(b)
+			.map_err(|e| Error::StoreErr(e, "pipe save block".to_owned()))?;
+	}
+	Ok(h)
 }
 
 /// Officially adds the block header to our header chain.
@@ -348,29 +521,30 @@ fn add_block_header(bh: &BlockHeader, ctx: &mut BlockContext) -> Result<(), Erro
 
 /// Directly updates the head if we've just appended a new block to it or handle
 /// the situation where we've just added enough work to have a fork with more
-/// work than the head.
+/// work than the head. The block, the height index and the heads are saved
+/// together, atomically.
 fn update_head(b: &Block, ctx: &mut BlockContext) -> Result<Option<Tip>, Error> {
 	// if we made a fork with more work than the head (which should also be true
 	// when extending the head), update it
 	let tip = Tip::from_block(&b.header);
 	if tip.total_difficulty > ctx.head.total_difficulty {
-		// update the block height index
-		ctx.store
-			.setup_height(&b.header, &ctx.head)
-			.map_err(|e| Error::StoreErr(e, "pipe setup height".to_owned()))?;
+		// switching to a fork deeper than allowed requires the operator's
+		// approval
+		if b.header.previous != ctx.head.last_block_h && !ctx.opts.contains(FORCE_REORG) {
+			if let Some(max_depth) = ctx.max_reorg_depth {
+				let depth = fork_depth(b, ctx)?;
+				if depth > max_depth {
+					return Err(Error::ReorgTooDeep(depth));
+				}
+			}
+		}
 
 		// in sync mode, only update the "body chain", otherwise update both the
 		// "header chain" and "body chain", updating the header chain in sync resets
 		// all additional "future" headers we've received
-		if ctx.opts.contains(SYNC) {
-			ctx.store
-				.save_body_head(&tip)
-				.map_err(|e| Error::StoreErr(e, "pipe save body".to_owned()))?;
-		} else {
-			ctx.store
-				.save_head(&tip)
-				.map_err(|e| Error::StoreErr(e, "pipe save head".to_owned()))?;
-		}
+		ctx.store
+			.save_block_as_head(b, &ctx.head, ctx.opts.contains(SYNC))
+			.map_err(|e| Error::StoreErr(e, "pipe save head".to_owned()))?;
 		ctx.head = tip.clone();
 		if b.header.height % 100 == 0 {
 			info!(LOGGER, "pipe: chain head reached {} @ {} [{}]",
@@ -385,6 +559,19 @@ fn update_head(b: &Block, ctx: &mut BlockContext) -> Result<Option<Tip>, Error>
 	}
 }
 
+// Number of blocks of the current chain switching to the fork ending with the
+// provided block would remove.
+fn fork_depth(b: &Block, ctx: &BlockContext) -> Result<u64, Error> {
+	let mut current = b.header.previous;
+	loop {
+		let header = ctx.store.get_block_header(&current)?;
+		if ctx.store.is_on_current_chain(&header).is_ok() {
+			return Ok(ctx.head.height.saturating_sub(header.height));
+		}
+		current = header.previous;
+	}
+}
+
 /// Update the sync head so we can keep syncing from where we left off.
 fn update_sync_head(bh: &BlockHeader, ctx: &mut BlockContext) -> Result<Option<Tip>, Error> {
 	let tip = Tip::from_block(bh);
@@ -23,7 +23,7 @@ use core::core::hash::{Hash, Hashed};
 use core::core::{Block, BlockHeader};
 use core::consensus::TargetError;
 use core::core::target::Difficulty;
-use grin_store::{self, option_to_not_found, to_key, Error, u64_to_key};
+use grin_store::{self, option_to_not_found, to_key, Batch, Error, SerIterator, u64_to_key};
 
 const STORE_SUBPATH: &'static str = "chain";
 
@@ -35,6 +35,9 @@ const SYNC_HEAD_PREFIX: u8 = 's' as u8;
 const HEADER_HEIGHT_PREFIX: u8 = '8' as u8;
 const COMMIT_POS_PREFIX: u8 = 'c' as u8;
 const KERNEL_POS_PREFIX: u8 = 'k' as u8;
+const OUTPUT_BLOCK_PREFIX: u8 = 'o' as u8;
+const SYNC_BLOCK_PREFIX: u8 = 'S' as u8;
+const SYNC_BLOCK_PREV_PREFIX: u8 = 'P' as u8;
 
 /// An implementation of the ChainStore trait backed by a simple key-value
 /// store.
@@ -48,6 +51,44 @@ impl ChainKVStore {
 		let db = grin_store::Store::open(format!("{}/{}", root_path, STORE_SUBPATH).as_str())?;
 		Ok(ChainKVStore { db: db })
 	}
+
+	/// Adds the writes maintaining the "header_by_height" index to the batch,
+	/// reading the index as the batch sees it. See setup_height.
+	fn batch_setup_height<'a>(
+		&self,
+		mut batch: Batch<'a>,
+		header: &BlockHeader,
+		old_tip: &Tip,
+	) -> Result<Batch<'a>, Error> {
+		// remove headers ahead if we backtracked
+		for n in header.height..old_tip.height {
+			batch = batch.delete(&u64_to_key(HEADER_HEIGHT_PREFIX, n))?;
+		}
+
+		batch = batch.put_ser(&u64_to_key(HEADER_HEIGHT_PREFIX, header.height), header)?;
+
+		if header.height > 0 {
+			let mut prev_header: BlockHeader = option_to_not_found(batch.get_ser(
+				&to_key(BLOCK_HEADER_PREFIX, &mut header.previous.to_vec()),
+			))?;
+			while prev_header.height > 0 {
+				let at_height: Option<BlockHeader> =
+					batch.get_ser(&u64_to_key(HEADER_HEIGHT_PREFIX, prev_header.height))?;
+				if at_height.map(|h| h.hash()) == Some(prev_header.hash()) {
+					break;
+				}
+				batch = batch.put_ser(
+					&u64_to_key(HEADER_HEIGHT_PREFIX, prev_header.height),
+					&prev_header,
+				)?;
+
+				prev_header = option_to_not_found(batch.get_ser(
+					&to_key(BLOCK_HEADER_PREFIX, &mut prev_header.previous.to_vec()),
+				))?;
+			}
+		}
+		Ok(batch)
+	}
 }
 
 impl ChainStore for ChainKVStore {
@@ -61,7 +102,7 @@ impl ChainStore for ChainKVStore {
 
 	fn save_head(&self, t: &Tip) -> Result<(), Error> {
 		self.db
-			.batch()
+			.batch()?
 			.put_ser(&vec![HEAD_PREFIX], t)?
 			.put_ser(&vec![HEADER_HEAD_PREFIX], t)?
 			.write()
@@ -89,9 +130,12 @@ impl ChainStore for ChainKVStore {
 
 	// Reset both header_head and sync_head to the current head of the body chain
 	fn reset_head(&self) -> Result<(), Error> {
-		let tip = self.head()?;
-		self.save_header_head(&tip)?;
-		self.save_sync_head(&tip)
+		let batch = self.db.batch()?;
+		let tip: Tip = option_to_not_found(batch.get_ser(&vec![HEAD_PREFIX]))?;
+		batch
+			.put_ser(&vec![HEADER_HEAD_PREFIX], &tip)?
+			.put_ser(&vec![SYNC_HEAD_PREFIX], &tip)?
+			.write()
 	}
 
 	fn get_block(&self, h: &Hash) -> Result<Block, Error> {
@@ -107,7 +151,7 @@ impl ChainStore for ChainKVStore {
 	/// Save the block and its header
 	fn save_block(&self, b: &Block) -> Result<(), Error> {
 		let batch = self.db
-			.batch()
+			.batch()?
 			.put_ser(
 				&to_key(BLOCK_PREFIX, &mut b.hash().to_vec())[..],
 				b,
@@ -119,6 +163,27 @@ impl ChainStore for ChainKVStore {
 		batch.write()
 	}
 
+	fn save_block_as_head(&self, b: &Block, old_tip: &Tip, body_only: bool) -> Result<(), Error> {
+		let tip = Tip::from_block(&b.header);
+		let mut batch = self.db
+			.batch()?
+			.put_ser(&to_key(BLOCK_PREFIX, &mut b.hash().to_vec())[..], b)?
+			.put_ser(
+				&to_key(BLOCK_HEADER_PREFIX, &mut b.hash().to_vec())[..],
+				&b.header,
+			)?;
+		batch = self.batch_setup_height(batch, &b.header, old_tip)?;
+		batch = batch.put_ser(&vec![HEAD_PREFIX], &tip)?;
+		if !body_only {
+			batch = batch.put_ser(&vec![HEADER_HEAD_PREFIX], &tip)?;
+		}
+		batch.write()
+	}
+
+	fn delete_block(&self, h: &Hash) -> Result<(), Error> {
+		self.db.delete(&to_key(BLOCK_PREFIX, &mut h.to_vec())[..])
+	}
+
 	fn is_on_current_chain(&self, header: &BlockHeader) -> Result<(), Error> {
 		let header_at_height = self.get_header_by_height(header.height)?;
 		if header.hash() == header_at_height.hash() {
@@ -135,10 +200,70 @@ impl ChainStore for ChainKVStore {
 		)
 	}
 
+	fn save_sync_block(&self, b: &Block) -> Result<(), Error> {
+		self.db
+			.batch()?
+			.put_ser(&to_key(SYNC_BLOCK_PREFIX, &mut b.hash().to_vec())[..], b)?
+			.put_ser(
+				&to_key(SYNC_BLOCK_PREV_PREFIX, &mut b.header.previous.to_vec())[..],
+				&b.hash(),
+			)?
+			.write()
+	}
+
+	fn has_sync_block(&self, h: &Hash) -> Result<bool, Error> {
+		self.db.exists(&to_key(SYNC_BLOCK_PREFIX, &mut h.to_vec()))
+	}
+
+	fn get_sync_block_by_previous(&self, h: &Hash) -> Result<Block, Error> {
+		let hash: Hash = option_to_not_found(
+			self.db
+				.get_ser(&to_key(SYNC_BLOCK_PREV_PREFIX, &mut h.to_vec())),
+		)?;
+		option_to_not_found(self.db.get_ser(&to_key(SYNC_BLOCK_PREFIX, &mut hash.to_vec())))
+	}
+
+	fn get_sync_block(&self, h: &Hash) -> Result<Block, Error> {
+		option_to_not_found(self.db.get_ser(&to_key(SYNC_BLOCK_PREFIX, &mut h.to_vec())))
+	}
+
+	fn sync_blocks(&self) -> Result<SerIterator<Block>, Error> {
+		self.db.iter::<Block>(&to_key(SYNC_BLOCK_PREFIX, &mut vec![]))
+	}
+
+	fn delete_sync_block(&self, bh: &BlockHeader) -> Result<(), Error> {
+		let hash = bh.hash();
+		let prev_key = to_key(SYNC_BLOCK_PREV_PREFIX, &mut bh.previous.to_vec());
+		let mut batch = self.db.batch()?;
+		// another block with the same parent may have replaced it in the index
+		let indexed: Option<Hash> = batch.get_ser(&prev_key)?;
+		if indexed == Some(hash) {
+			batch = batch.delete(&prev_key)?;
+		}
+		batch
+			.delete(&to_key(SYNC_BLOCK_PREFIX, &mut hash.to_vec())[..])?
+			.write()
+	}
+
 	fn get_header_by_height(&self, height: u64) -> Result<BlockHeader, Error> {
 		option_to_not_found(self.db.get_ser(&u64_to_key(HEADER_HEIGHT_PREFIX, height)))
 	}
 
+	fn get_headers_from_height(&self, height: u64, count: usize) -> Result<Vec<BlockHeader>, Error> {
+		// range scan of the height index, stopping at the first gap
+		let headers = self.db
+			.iter_from::<BlockHeader>(
+				&to_key(HEADER_HEIGHT_PREFIX, &mut vec![]),
+				&u64_to_key(HEADER_HEIGHT_PREFIX, height),
+			)?
+			.enumerate()
+			.take_while(|&(i, ref h)| h.height == height + i as u64)
+			.map(|(_, h)| h)
+			.take(count)
+			.collect();
+		Ok(headers)
+	}
+
 	fn delete_header_by_height(&self, height: u64) -> Result<(), Error> {
 		self.db.delete(&u64_to_key(HEADER_HEIGHT_PREFIX, height))
 	}
@@ -157,6 +282,20 @@ impl ChainStore for ChainKVStore {
 		)
 	}
 
+	fn save_output_block(&self, commit: &Commitment, h: &Hash) -> Result<(), Error> {
+		self.db.put_ser(
+			&to_key(OUTPUT_BLOCK_PREFIX, &mut commit.as_ref().to_vec())[..],
+			h,
+		)
+	}
+
+	fn get_output_block(&self, commit: &Commitment) -> Result<Hash, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(OUTPUT_BLOCK_PREFIX, &mut commit.as_ref().to_vec())),
+		)
+	}
+
 	fn save_kernel_pos(&self, excess: &Commitment, pos: u64) -> Result<(), Error> {
 		self.db.put_ser(
 			&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec())[..],
@@ -178,30 +317,13 @@ impl ChainStore for ChainKVStore {
 	/// We need to handle the case where we have no index entry for a given
 	/// height to account for the case where we just switched to a new fork and
 	/// the height jumped beyond current chain height.
-	fn setup_height(&self, header: &BlockHeader, old_tip: &Tip)
-		-> Result<(), Error> {
-
-		// remove headers ahead if we backtracked
-		for n in header.height..old_tip.height {
-			self.delete_header_by_height(n)?;
-		}
-
-		self.db
-			.put_ser(&u64_to_key(HEADER_HEIGHT_PREFIX, header.height), header)?;
-
-		if header.height > 0 {
-			let mut prev_header = self.get_block_header(&header.previous)?;
-			while prev_header.height > 0 {
-				if let Ok(_) = self.is_on_current_chain(&prev_header) {
-					break;
-				}
-				self.db
-					.put_ser(&u64_to_key(HEADER_HEIGHT_PREFIX, prev_header.height), &prev_header)?;
+	fn setup_height(&self, header: &BlockHeader, old_tip: &Tip) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		self.batch_setup_height(batch, header, old_tip)?.write()
+	}
 
-				prev_header = self.get_block_header(&prev_header.previous)?;
-			}
-		}
-		Ok(())
+	fn sync(&self) -> Result<(), Error> {
+		self.db.sync()
 	}
 }
 
@@ -21,8 +21,8 @@ use std::path::Path;
 use std::sync::Arc;
 
 use core::core::{Block, SumCommit, Input, Output, OutputIdentifier, TxKernel, COINBASE_OUTPUT};
-use core::core::pmmr::{HashSum, NoSum, Summable, PMMR};
-use core::core::hash::Hashed;
+use core::core::pmmr::{HashSum, MerkleProof, NoSum, Summable, PMMR};
+use core::core::hash::{Hash, Hashed};
 use grin_store;
 use grin_store::sumtree::PMMRBackend;
 use types::ChainStore;
@@ -35,6 +35,24 @@ const UTXO_SUBDIR: &'static str = "utxo";
 const RANGE_PROOF_SUBDIR: &'static str = "rangeproof";
 const KERNEL_SUBDIR: &'static str = "kernel";
 
+/// Copies the files of the sum trees in the chain data directory to another
+/// data directory, to work on a snapshot of them. The sum trees must not be
+/// modified meanwhile.
+pub fn copy_files(root_dir: &str, dst_root_dir: &str) -> Result<(), Error> {
+	for subdir in [UTXO_SUBDIR, RANGE_PROOF_SUBDIR, KERNEL_SUBDIR].iter() {
+		let src = Path::new(root_dir).join(SUMTREES_SUBDIR).join(subdir);
+		let dst = Path::new(dst_root_dir).join(SUMTREES_SUBDIR).join(subdir);
+		fs::create_dir_all(&dst)?;
+		for entry in fs::read_dir(&src)? {
+			let entry = entry?;
+			if entry.file_type()?.is_file() {
+				fs::copy(entry.path(), dst.join(entry.file_name()))?;
+			}
+		}
+	}
+	Ok(())
+}
+
 struct PMMRHandle<T>
 where
 	T: Summable + Clone,
@@ -115,6 +133,53 @@ impl SumTrees {
 		}
 	}
 
+	/// Whether the output indexed under the provided commitment is still
+	/// present in the output MMR, as in unspent.
+	pub fn is_unspent_commit(&mut self, commit: &Commitment) -> Result<bool, Error> {
+		match self.commit_index.get_output_pos(commit) {
+			Ok(pos) => {
+				let output_pmmr = PMMR::at(
+					&mut self.output_pmmr_h.backend,
+					self.output_pmmr_h.last_pos,
+				);
+				Ok(output_pmmr.get(pos).is_some())
+			}
+			Err(grin_store::Error::NotFoundErr) => Err(Error::OutputNotFound),
+			Err(e) => Err(Error::StoreErr(e, format!("sumtree unspent commit check"))),
+		}
+	}
+
+	/// Whether the kernel indexed under the provided excess is in the kernel
+	/// MMR. The index isn't rewound on forks, a kernel only included in an
+	/// abandoned fork may still be reported until the MMR grows past it.
+	pub fn has_kernel(&mut self, excess: &Commitment) -> Result<bool, Error> {
+		match self.commit_index.get_kernel_pos(excess) {
+			Ok(pos) => {
+				let kernel_pmmr =
+					PMMR::at(&mut self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+				Ok(kernel_pmmr.get(pos).is_some())
+			}
+			Err(grin_store::Error::NotFoundErr) => Ok(false),
+			Err(e) => Err(Error::StoreErr(e, format!("sumtree kernel check"))),
+		}
+	}
+
+	/// Proof of inclusion of the unspent output with the provided commitment
+	/// in the output MMR.
+	pub fn merkle_proof(&mut self, commit: &Commitment) -> Result<MerkleProof<SumCommit>, Error> {
+		match self.commit_index.get_output_pos(commit) {
+			Ok(pos) => {
+				let output_pmmr = PMMR::at(
+					&mut self.output_pmmr_h.backend,
+					self.output_pmmr_h.last_pos,
+				);
+				output_merkle_proof(&output_pmmr, pos)
+			}
+			Err(grin_store::Error::NotFoundErr) => Err(Error::OutputNotFound),
+			Err(e) => Err(Error::StoreErr(e, format!("sumtree merkle proof"))),
+		}
+	}
+
 	/// Check the output being spent by the input has sufficiently matured.
 	/// This only applies for coinbase outputs being spent (1,000 blocks).
 	/// Non-coinbase outputs will always pass this check.
@@ -151,6 +216,19 @@ impl SumTrees {
 		output_pmmr.get_last_n_insertions(distance)
 	}
 
+	/// Unspent outputs from the provided position in the output MMR onward,
+	/// up to max of them, along with the current size of the MMR.
+	pub fn outputs_from(&mut self, start_pos: u64, max: u64) -> (u64, Vec<(u64, SumCommit)>) {
+		let last_pos = self.output_pmmr_h.last_pos;
+		let output_pmmr = PMMR::at(&mut self.output_pmmr_h.backend, last_pos);
+		let outputs = output_pmmr
+			.leaves_from(start_pos, max)
+			.into_iter()
+			.map(|(pos, hsum)| (pos, hsum.sum))
+			.collect();
+		(last_pos, outputs)
+	}
+
 	/// as above, for range proofs
 	pub fn last_n_rangeproof(&mut self, distance: u64) -> Vec<HashSum<NoSum<RangeProof>>> {
 		let rproof_pmmr = PMMR::at(&mut self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos);
@@ -176,6 +254,17 @@ impl SumTrees {
 		let kernel_pmmr = PMMR::at(&mut self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
 		(output_pmmr.root(), rproof_pmmr.root(), kernel_pmmr.root())
 	}
+
+	/// Compacts the output and range proof MMRs, physically removing the data
+	/// of outputs spent (and the corresponding range proofs) before the
+	/// provided height. Kernels are never pruned. Returns the number of MMR
+	/// positions that got removed.
+	pub fn compact(&mut self, horizon: u64) -> Result<usize, Error> {
+		let cutoff = horizon as u32;
+		let outputs = self.output_pmmr_h.backend.compact(cutoff)?;
+		let rproofs = self.rproof_pmmr_h.backend.compact(cutoff)?;
+		Ok(outputs + rproofs)
+	}
 }
 
 /// Starts a new unit of work to extend the chain with additional blocks,
@@ -243,6 +332,7 @@ pub struct Extension<'a> {
 
 	commit_index: Arc<ChainStore>,
 	new_output_commits: HashMap<Commitment, u64>,
+	new_output_blocks: HashMap<Commitment, Hash>,
 	new_kernel_excesses: HashMap<Commitment, u64>,
 	rollback: bool,
 }
@@ -265,6 +355,7 @@ impl<'a> Extension<'a> {
 			),
 			commit_index: commit_index,
 			new_output_commits: HashMap::new(),
+			new_output_blocks: HashMap::new(),
 			new_kernel_excesses: HashMap::new(),
 			rollback: false,
 		}
@@ -302,6 +393,12 @@ impl<'a> Extension<'a> {
 		for kernel in &b.kernels {
 			self.apply_kernel(kernel)?;
 		}
+
+		// remember where each output got created
+		let hash = b.hash();
+		for out in &b.outputs {
+			self.new_output_blocks.insert(out.commitment(), hash);
+		}
 		Ok(())
 	}
 
@@ -310,6 +407,10 @@ impl<'a> Extension<'a> {
 			self.commit_index.save_output_pos(commit, *pos)?;
 		}
 
+		for (commit, hash) in &self.new_output_blocks {
+			self.commit_index.save_output_block(commit, hash)?;
+		}
+
 		for (excess, pos) in &self.new_kernel_excesses {
 			self.commit_index.save_kernel_pos(excess, *pos)?;
 		}
@@ -496,6 +597,16 @@ impl<'a> Extension<'a> {
 		)
 	}
 
+	/// Proof of inclusion of the unspent output with the provided commitment
+	/// in the output MMR, as currently extended (or rewound).
+	pub fn merkle_proof(&self, commit: &Commitment) -> Result<MerkleProof<SumCommit>, Error> {
+		match self.get_output_pos(commit) {
+			Ok(pos) => output_merkle_proof(&self.output_pmmr, pos),
+			Err(grin_store::Error::NotFoundErr) => Err(Error::OutputNotFound),
+			Err(e) => Err(Error::StoreErr(e, format!("sumtree merkle proof"))),
+		}
+	}
+
 	/// Force the rollback of this extension, no matter the result
 	pub fn force_rollback(&mut self) {
 		self.rollback = true;
@@ -524,3 +635,15 @@ impl<'a> Extension<'a> {
 		)
 	}
 }
+
+// Builds the proof for the output at the provided position, which doesn't
+// exist if spent (or not created yet when rewound).
+fn output_merkle_proof(
+	output_pmmr: &PMMR<SumCommit, PMMRBackend<SumCommit>>,
+	pos: u64,
+) -> Result<MerkleProof<SumCommit>, Error> {
+	if let None = output_pmmr.get(pos) {
+		return Err(Error::OutputNotFound);
+	}
+	output_pmmr.merkle_proof(pos).map_err(&Error::SumTreeErr)
+}
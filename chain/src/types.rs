@@ -14,7 +14,8 @@
 
 //! Base types that the block chain pipeline requires.
 
-use std::io;
+use std::{cmp, fmt, io, mem};
+use std::sync::RwLock;
 
 use util::secp::pedersen::Commitment;
 
@@ -22,8 +23,10 @@ use grin_store as store;
 use core::core::{Block, BlockHeader, block, transaction};
 use core::core::hash::{Hash, Hashed};
 use core::core::target::Difficulty;
+use core::global;
 use core::ser;
 use grin_store;
+use util::LOGGER;
 
 bitflags! {
 /// Options for block validation
@@ -36,6 +39,113 @@ bitflags! {
 		const SYNC = 0b00000010,
 		/// Block validation on a block we mined ourselves
 		const MINE = 0b00000100,
+		/// Switches to the block's fork even if deeper than the maximum reorg
+		/// depth, after the operator approved it
+		const FORCE_REORG = 0b00001000,
+	}
+}
+
+/// Chain configuration, mostly governing how much history the node retains.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+	/// Number of blocks behind the head beyond which full block bodies are
+	/// discarded. Headers are always kept and the outputs and kernels remain
+	/// in their respective MMRs. Forks deeper than the horizon can't be
+	/// processed anymore once the corresponding bodies have been pruned, so
+	/// it can't be below the cut-through horizon nor the maximum reorg depth.
+	/// Not set by default, meaning all block bodies are retained.
+	#[serde(default)]
+	pub pruning_horizon: Option<u64>,
+
+	/// Archive nodes guarantee the retention of the full block history and
+	/// never compact historical data, so other nodes can rely on them for
+	/// deep history requests and initial sync. Incompatible with a pruning
+	/// horizon.
+	#[serde(default)]
+	pub archive_mode: bool,
+
+	/// If set, the chain gets compacted every compaction_interval seconds,
+	/// removing spent outputs' data older than the cut-through horizon.
+	/// Compaction can also be triggered manually through the API.
+	#[serde(default)]
+	pub compaction_interval: Option<u64>,
+
+	/// Known good block hashes at given heights, in addition to the ones
+	/// shipped with the node (and overriding them at the same height).
+	/// Headers contradicting a checkpoint are rejected.
+	#[serde(default)]
+	pub checkpoints: Vec<Checkpoint>,
+
+	/// Skips the full validation of blocks below the highest checkpoint
+	/// during sync, as their history can't be forked. On by default.
+	#[serde(default = "default_assume_valid")]
+	pub assume_valid: bool,
+
+	/// Maximum number of blocks a reorg can remove from the current chain.
+	/// Deeper forks with more work aren't switched to automatically, they
+	/// stay pending until accepted through the API. Unlimited if not set.
+	#[serde(default)]
+	pub max_reorg_depth: Option<u64>,
+}
+
+fn default_assume_valid() -> bool {
+	true
+}
+
+/// A known good block hash at a given height
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block, hex encoded
+	pub hash: String,
+}
+
+impl Default for ChainConfig {
+	fn default() -> ChainConfig {
+		ChainConfig {
+			pruning_horizon: None,
+			archive_mode: false,
+			compaction_interval: None,
+			checkpoints: vec![],
+			assume_valid: true,
+			max_reorg_depth: None,
+		}
+	}
+}
+
+impl ChainConfig {
+	/// Whether old block bodies get pruned with this configuration.
+	pub fn is_pruning(&self) -> bool {
+		self.pruning_horizon.is_some() && !self.archive_mode
+	}
+
+	/// Checks the configuration for conflicting options.
+	pub fn validate(&self) -> Result<(), Error> {
+		if self.archive_mode && self.pruning_horizon.is_some() {
+			return Err(Error::Other(
+				"archive_mode and pruning_horizon are mutually exclusive".to_owned(),
+			));
+		}
+		if let Some(horizon) = self.pruning_horizon {
+			// the bodies of the blocks a reorg undoes must still be there
+			let min_horizon = cmp::max(
+				global::cut_through_horizon(),
+				self.max_reorg_depth.unwrap_or(0),
+			);
+			if horizon < min_horizon {
+				return Err(Error::Other(format!(
+					"pruning_horizon {} is below the cut-through horizon or maximum reorg depth {}",
+					horizon, min_horizon
+				)));
+			}
+		}
+		if self.archive_mode && self.compaction_interval.is_some() {
+			return Err(Error::Other(
+				"archive_mode and compaction_interval are mutually exclusive".to_owned(),
+			));
+		}
+		Ok(())
 	}
 }
 
@@ -86,6 +196,13 @@ pub enum Error {
 	GenesisBlockRequired,
 	/// Error from underlying tx handling
 	Transaction(transaction::Error),
+	/// The block contradicts the checkpoint at its height
+	CheckpointMismatch(u64, Hash),
+	/// Switching to the block's fork would reorg more blocks than allowed
+	ReorgTooDeep(u64),
+	/// Inconsistency in the stored chain data at the given height, found
+	/// when validating it
+	Corrupted(u64, String),
 	/// Anything else
 	Other(String),
 }
@@ -117,6 +234,8 @@ impl Error {
 				Error::SerErr(_) |
 				Error::SumTreeErr(_)|
 				Error::GenesisBlockRequired |
+				Error::ReorgTooDeep(_) |
+				Error::Corrupted(_, _) |
 				Error::Other(_) => false,
 			_ => true,
 		}
@@ -129,6 +248,114 @@ impl From<transaction::Error> for Error {
 	}
 }
 
+/// Where an output has been created on the current chain and whether it has
+/// been spent since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLocation {
+	/// Commitment of the output
+	pub commit: Commitment,
+	/// Hash of the block the output has been created in
+	pub block_hash: Hash,
+	/// Height of the block the output has been created in
+	pub height: u64,
+	/// Whether the output has been spent
+	pub spent: bool,
+}
+
+/// Stages of the synchronization of our chain with our peers, with the
+/// progress of the current one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SyncStatus {
+	/// Not syncing yet, waiting to be connected to some peers
+	Initial,
+	/// Caught up with our peers, only receiving new blocks
+	NoSync,
+	/// Downloading the block headers of the most worked peer chain
+	HeaderSync {
+		/// Height of our header chain
+		current_height: u64,
+		/// Height of the most worked peer chain
+		highest_height: u64,
+	},
+	/// Downloading the blocks of our header chain
+	BodySync {
+		/// Height of our block chain
+		current_height: u64,
+		/// Height of our header chain
+		highest_height: u64,
+	},
+}
+
+impl fmt::Display for SyncStatus {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SyncStatus::Initial => write!(f, "Waiting for peers"),
+			SyncStatus::NoSync => write!(f, "Running"),
+			SyncStatus::HeaderSync {
+				current_height,
+				highest_height,
+			} => write!(
+				f,
+				"Downloading headers ({}/{})",
+				current_height, highest_height
+			),
+			SyncStatus::BodySync {
+				current_height,
+				highest_height,
+			} => write!(
+				f,
+				"Downloading blocks ({}/{})",
+				current_height, highest_height
+			),
+		}
+	}
+}
+
+/// Current sync status, owned by the server and shared with everything that
+/// behaves differently while we're syncing.
+pub struct SyncState {
+	current: RwLock<SyncStatus>,
+}
+
+impl SyncState {
+	/// A new sync state, in its initial stage
+	pub fn new() -> SyncState {
+		SyncState {
+			current: RwLock::new(SyncStatus::Initial),
+		}
+	}
+
+	/// Whether we're still syncing (or about to), i.e. not caught up with
+	/// our peers yet.
+	pub fn is_syncing(&self) -> bool {
+		*self.current.read().unwrap() != SyncStatus::NoSync
+	}
+
+	/// The current sync stage and its progress
+	pub fn status(&self) -> SyncStatus {
+		*self.current.read().unwrap()
+	}
+
+	/// Moves to the provided sync status, logging stage changes.
+	pub fn update(&self, new_status: SyncStatus) {
+		let mut current = self.current.write().unwrap();
+		if mem::discriminant(&*current) != mem::discriminant(&new_status) {
+			info!(LOGGER, "sync: {} -> {}", *current, new_status);
+		}
+		*current = new_status;
+	}
+}
+
+/// A fork with more work than the current chain that hasn't been switched to
+/// as it's deeper than the maximum reorg depth.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingReorg {
+	/// Tip of the refused fork
+	pub tip: Tip,
+	/// Number of blocks of the current chain the fork would replace
+	pub depth: u64,
+}
+
 /// The tip of a fork. A handle to the fork ancestry from its leaf in the
 /// blockchain tree. References the max height and the latest and previous
 /// blocks
@@ -217,6 +444,20 @@ pub trait ChainStore: Send + Sync {
 	/// Save the provided block in store
 	fn save_block(&self, b: &Block) -> Result<(), store::Error>;
 
+	/// Saves the provided block and makes it the new head of the chain in a
+	/// single atomic write, along with the height index and, unless body_only
+	/// (syncing), the head of the header chain. A crash never leaves the head
+	/// and the height index out of step.
+	fn save_block_as_head(
+		&self,
+		b: &Block,
+		old_tip: &Tip,
+		body_only: bool,
+	) -> Result<(), store::Error>;
+
+	/// Deletes the full block body, leaving the corresponding header in store
+	fn delete_block(&self, h: &Hash) -> Result<(), store::Error>;
+
 	/// Save the provided block header in store
 	fn save_block_header(&self, bh: &BlockHeader) -> Result<(), store::Error>;
 
@@ -235,9 +476,36 @@ pub trait ChainStore: Send + Sync {
 	/// Reset header_head and sync_head to head of current body chain
 	fn reset_head(&self) -> Result<(), store::Error>;
 
+	/// Saves a block received during sync that can't be processed before its
+	/// parent, so it doesn't have to be downloaded again after a restart
+	fn save_sync_block(&self, b: &Block) -> Result<(), store::Error>;
+
+	/// Whether the block with the provided hash has been saved during sync
+	fn has_sync_block(&self, h: &Hash) -> Result<bool, store::Error>;
+
+	/// Gets a block saved during sync by the hash of its parent
+	fn get_sync_block_by_previous(&self, h: &Hash) -> Result<Block, store::Error>;
+
+	/// Gets a block saved during sync by its hash
+	fn get_sync_block(&self, h: &Hash) -> Result<Block, store::Error>;
+
+	/// Iterates over all the blocks saved during sync
+	fn sync_blocks(&self) -> Result<store::SerIterator<Block>, store::Error>;
+
+	/// Deletes a block saved during sync, once processed
+	fn delete_sync_block(&self, bh: &BlockHeader) -> Result<(), store::Error>;
+
 	/// Gets the block header at the provided height
 	fn get_header_by_height(&self, height: u64) -> Result<BlockHeader, store::Error>;
 
+	/// Gets at most count consecutive block headers of the current chain,
+	/// starting at the provided height
+	fn get_headers_from_height(
+		&self,
+		height: u64,
+		count: usize,
+	) -> Result<Vec<BlockHeader>, store::Error>;
+
 	/// Delete the block header at the height
 	fn delete_header_by_height(&self, height: u64) -> Result<(), store::Error>;
 
@@ -253,6 +521,14 @@ pub trait ChainStore: Send + Sync {
 	/// UTXO MMR. Used as an index for spending and pruning.
 	fn get_output_pos(&self, commit: &Commitment) -> Result<u64, store::Error>;
 
+	/// Saves the hash of the block an output, represented by its commitment,
+	/// has been created in.
+	fn save_output_block(&self, commit: &Commitment, h: &Hash) -> Result<(), store::Error>;
+
+	/// Gets the hash of the block an output, represented by its commitment,
+	/// has been created in.
+	fn get_output_block(&self, commit: &Commitment) -> Result<Hash, store::Error>;
+
 	/// Saves the position of a kernel, represented by its excess, in the
 	/// UTXO MMR. Used as an index for spending and pruning.
 	fn save_kernel_pos(&self, commit: &Commitment, pos: u64) -> Result<(), store::Error>;
@@ -263,8 +539,11 @@ pub trait ChainStore: Send + Sync {
 
 	/// Saves the provided block header at the corresponding height. Also check
 	/// the consistency of the height chain in store by assuring previous
-	/// headers are also at their respective heights.
+	/// headers are also at their respective heights. Atomic.
 	fn setup_height(&self, bh: &BlockHeader, old_tip: &Tip) -> Result<(), store::Error>;
+
+	/// Makes sure everything saved so far is written to disk.
+	fn sync(&self) -> Result<(), store::Error>;
 }
 
 /// Bridge between the chain pipeline and the rest of the system. Handles
@@ -274,10 +553,16 @@ pub trait ChainAdapter {
 	/// The blockchain pipeline has accepted this block as valid and added
 	/// it to our chain.
 	fn block_accepted(&self, b: &Block, opts: Options);
+
+	/// The chain head switched from one branch to another. The depth is the
+	/// number of blocks of the old branch that are not part of the chain
+	/// anymore.
+	fn reorg(&self, old_tip: &Tip, new_tip: &Tip, depth: u64);
 }
 
 /// Dummy adapter used as a placeholder for real implementations
 pub struct NoopAdapter {}
 impl ChainAdapter for NoopAdapter {
 	fn block_accepted(&self, _: &Block, _: Options) {}
+	fn reorg(&self, _: &Tip, _: &Tip, _: u64) {}
 }
@@ -22,6 +22,7 @@ extern crate rand;
 extern crate time;
 
 use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 
 use chain::Chain;
@@ -34,6 +35,7 @@ use core::global;
 use core::global::ChainTypes;
 
 use keychain::Keychain;
+use util::zip;
 
 use pow::{cuckoo, types, MiningWorker};
 
@@ -42,6 +44,10 @@ fn clean_output_dir(dir_name: &str) {
 }
 
 fn setup(dir_name: &str) -> Chain {
+	setup_with_config(dir_name, chain::ChainConfig::default())
+}
+
+fn setup_with_config(dir_name: &str, config: chain::ChainConfig) -> Chain {
 	let _ = env_logger::init();
 	clean_output_dir(dir_name);
 	global::set_mining_mode(ChainTypes::AutomatedTesting);
@@ -51,6 +57,7 @@ fn setup(dir_name: &str) -> Chain {
 		Arc::new(NoopAdapter {}),
 		genesis_block,
 		pow::verify_size,
+		config,
 	).unwrap()
 }
 
@@ -118,6 +125,9 @@ fn mine_empty_chain() {
 		let header_by_height = chain.get_header_by_height(n).unwrap();
 		assert_eq!(header_by_height.hash(), bhash);
 	}
+
+	// the whole chain replays fine, proofs of work and sums included
+	chain.validate().unwrap();
 }
 
 #[test]
@@ -304,6 +314,27 @@ fn spend_in_fork() {
 	assert!(chain.is_unspent(&OutputIdentifier::from_output(&tx2.outputs[0])).is_ok());
 	assert!(chain.is_unspent(&OutputIdentifier::from_output(&tx1.outputs[0])).is_err());
 
+	// check output and block lookups
+	let out1 = chain.get_output(&tx1.outputs[0].commitment()).unwrap();
+	assert_eq!(out1.height, 5);
+	assert!(out1.spent);
+	let out2 = chain.get_output(&tx2.outputs[0].commitment()).unwrap();
+	assert_eq!(out2.height, 6);
+	assert_eq!(out2.block_hash, prev_main.hash());
+	assert!(!out2.spent);
+	assert_eq!(chain.get_block_by_height(6).unwrap().hash(), prev_main.hash());
+
+	// check merkle proofs, against the head and a previous header
+	let out2_id = OutputIdentifier::from_output(&tx2.outputs[0]);
+	let proof = chain.get_merkle_proof(&out2_id.commit, None).unwrap();
+	assert!(head.verify_output_proof(&out2_id, &proof));
+	let out1_id = OutputIdentifier::from_output(&tx1.outputs[0]);
+	assert!(chain.get_merkle_proof(&out1_id.commit, None).is_err());
+	let header5 = chain.get_header_by_height(5).unwrap();
+	let proof = chain.get_merkle_proof(&out1_id.commit, Some(&header5)).unwrap();
+	assert!(header5.verify_output_proof(&out1_id, &proof));
+	assert!(!head.verify_output_proof(&out1_id, &proof));
+
 	// make the fork win
 	let fork_next = prepare_fork_block(&kc, &prev_fork, &chain, 10);
 	let prev_fork = fork_next.header.clone();
@@ -317,6 +348,244 @@ fn spend_in_fork() {
 	assert!(chain.is_unspent(&OutputIdentifier::from_output(&tx1.outputs[0])).is_err());
 }
 
+#[test]
+fn reorg_too_deep() {
+	let kc = Keychain::from_random_seed().unwrap();
+	let mut config = chain::ChainConfig::default();
+	config.max_reorg_depth = Some(1);
+	let chain = setup_with_config(".grin7", config);
+	let genesis = chain.head_header().unwrap();
+
+	// 2 blocks on the main chain
+	let mut prev = genesis.clone();
+	for n in 2..4 {
+		let b = prepare_block(&kc, &prev, &chain, n);
+		prev = b.header.clone();
+		chain.process_block(b, chain::SKIP_POW).unwrap();
+	}
+
+	// a fork from genesis with more work, 2 blocks deep so refused
+	let fork = prepare_fork_block(&kc, &genesis, &chain, 4);
+	let fork_hash = fork.hash();
+	let (tip, _) = chain.process_block(fork, chain::SKIP_POW).unwrap();
+	assert!(tip.is_none());
+	assert_eq!(chain.head_header().unwrap().hash(), prev.hash());
+	let pending = chain.pending_reorg().unwrap();
+	assert_eq!(pending.depth, 2);
+	assert_eq!(pending.tip.last_block_h, fork_hash);
+
+	// until accepted
+	let tip = chain.accept_reorg().unwrap();
+	assert_eq!(tip.last_block_h, fork_hash);
+	assert_eq!(chain.head_header().unwrap().hash(), fork_hash);
+	assert!(chain.pending_reorg().is_none());
+}
+
+#[test]
+fn sync_blocks_survive_restart() {
+	let kc = Keychain::from_random_seed().unwrap();
+
+	// valid blocks built on another chain with the same genesis
+	let source = setup(".grin8a");
+	let genesis = source.head_header().unwrap();
+	let b1 = prepare_block(&kc, &genesis, &source, 2);
+	source.process_block(b1.clone(), chain::SKIP_POW).unwrap();
+	let b2 = prepare_block(&kc, &b1.header, &source, 3);
+
+	// a block out of our header chain isn't saved, gone after a restart
+	let chain = setup(".grin8b");
+	let res = chain.process_block(b2.clone(), chain::SYNC | chain::SKIP_POW);
+	assert!(res.is_err());
+	chain.stop().unwrap();
+	drop(chain);
+	let chain = chain::Chain::init(
+		".grin8b".to_string(),
+		Arc::new(NoopAdapter {}),
+		pow::mine_genesis_block(None).unwrap(),
+		pow::verify_size,
+		chain::ChainConfig::default(),
+	).unwrap();
+	assert!(!chain.is_orphan(&b2.hash()));
+	drop(chain);
+
+	// once the headers are synced, the second block arrives first and gets
+	// saved
+	let chain = setup(".grin8");
+	for h in vec![&b1.header, &b2.header] {
+		chain.sync_block_header(h, chain::SYNC | chain::SKIP_POW).unwrap();
+	}
+	let res = chain.process_block(b2.clone(), chain::SYNC | chain::SKIP_POW);
+	assert!(res.is_err());
+	assert!(chain.is_orphan(&b2.hash()));
+	chain.stop().unwrap();
+	drop(chain);
+
+	// still there after a restart, processed once its parent comes in
+	let chain = chain::Chain::init(
+		".grin8".to_string(),
+		Arc::new(NoopAdapter {}),
+		pow::mine_genesis_block(None).unwrap(),
+		pow::verify_size,
+		chain::ChainConfig::default(),
+	).unwrap();
+	assert!(chain.is_orphan(&b2.hash()));
+	chain.process_block(b1, chain::SYNC | chain::SKIP_POW).unwrap();
+	assert_eq!(chain.head_header().unwrap().hash(), b2.hash());
+	assert!(!chain.is_orphan(&b2.hash()));
+}
+
+#[test]
+fn txhashset_archive() {
+	let kc = Keychain::from_random_seed().unwrap();
+	let chain = setup(".grin9");
+	let mut prev = chain.head_header().unwrap();
+	let mut headers = vec![];
+	for n in 2..5 {
+		let b = prepare_block(&kc, &prev, &chain, n);
+		prev = b.header.clone();
+		headers.push(b.header.clone());
+		chain.process_block(b, chain::SKIP_POW).unwrap();
+	}
+
+	// archived as of the first block, with its header
+	let path = chain.txhashset_archive(&headers[0].hash()).unwrap();
+	let dst = Path::new(".grin9_unzipped");
+	let _ = fs::remove_dir_all(dst);
+	zip::decompress(fs::File::open(&path).unwrap(), dst).unwrap();
+	assert!(dst.join("header.bin").exists());
+	let utxo_file = |root: &Path| {
+		fs::metadata(root.join("sumtrees").join("utxo").join("pmmr_dat.bin"))
+			.unwrap()
+			.len()
+	};
+	assert!(utxo_file(dst) < utxo_file(Path::new(".grin9")));
+	let _ = fs::remove_dir_all(dst);
+
+	// the chain itself is left untouched
+	let b = prepare_block(&kc, &prev, &chain, 5);
+	chain.process_block(b, chain::SKIP_POW).unwrap();
+	assert_eq!(chain.head().unwrap().height, 4);
+}
+
+#[test]
+fn tampered_body_rejected() {
+	let kc = Keychain::from_random_seed().unwrap();
+	let other_kc = Keychain::from_random_seed().unwrap();
+	let chain = setup(".grin11");
+	let genesis = chain.head_header().unwrap();
+	let b = prepare_block(&kc, &genesis, &chain, 2);
+
+	// a valid body, just not the one committed to by the header
+	let mut tampered = prepare_block(&other_kc, &genesis, &chain, 2);
+	tampered.header = b.header.clone();
+	for _ in 0..2 {
+		let res = chain.process_block(tampered.clone(), chain::SKIP_POW);
+		assert!(res.is_err());
+		assert_eq!(chain.head().unwrap().height, 0);
+	}
+
+	// the genuine block still goes through, but the tampered one never does
+	chain.process_block(b.clone(), chain::SKIP_POW).unwrap();
+	assert_eq!(chain.head_header().unwrap().hash(), b.hash());
+	assert!(chain.process_block(tampered, chain::SKIP_POW).is_err());
+}
+
+#[test]
+fn fork_below_checkpoint_validated() {
+	let kc = Keychain::from_random_seed().unwrap();
+	let source = setup(".grin12a");
+	let genesis = source.head_header().unwrap();
+	let b1 = prepare_block(&kc, &genesis, &source, 2);
+	source.process_block(b1.clone(), chain::SKIP_POW).unwrap();
+	let b2 = prepare_block(&kc, &b1.header, &source, 3);
+	source.process_block(b2.clone(), chain::SKIP_POW).unwrap();
+
+	let mut config = chain::ChainConfig::default();
+	config.checkpoints = vec![
+		Checkpoint {
+			height: 2,
+			hash: b2.hash().to_hex(),
+		},
+	];
+	let chain = setup_with_config(".grin12", config);
+	for h in vec![&b1.header, &b2.header] {
+		chain.sync_block_header(h, chain::SYNC | chain::SKIP_POW).unwrap();
+	}
+
+	// a block of another fork below the checkpoint, with an invalid kernel
+	// signature but roots matching its content
+	let other_kc = Keychain::from_random_seed().unwrap();
+	let mut fork = prepare_block_nosum(&other_kc, &genesis, 2, vec![]);
+	fork.kernels[0].excess_sig = b1.kernels[0].excess_sig.clone();
+	chain.set_sumtree_roots(&mut fork, false).unwrap();
+	assert!(chain.process_block(fork, chain::SYNC | chain::SKIP_POW).is_err());
+	assert_eq!(chain.head().unwrap().height, 0);
+
+	// the blocks the checkpoint builds on still go through
+	for b in vec![b1, b2.clone()] {
+		chain.process_block(b, chain::SYNC | chain::SKIP_POW).unwrap();
+	}
+	assert_eq!(chain.head_header().unwrap().hash(), b2.hash());
+}
+
+#[test]
+fn prune_block_bodies() {
+	let kc = Keychain::from_random_seed().unwrap();
+	let mut config = chain::ChainConfig::default();
+	config.pruning_horizon = Some(2);
+	let chain = setup_with_config(".grin10", config);
+	let genesis = chain.head_header().unwrap();
+	let mut prev = genesis.clone();
+	let mut headers = vec![];
+	for n in 2..7 {
+		let b = prepare_block(&kc, &prev, &chain, n);
+		prev = b.header.clone();
+		headers.push(b.header.clone());
+		chain.process_block(b, chain::SKIP_POW).unwrap();
+	}
+
+	// only the bodies within the horizon are left, with genesis and all headers
+	assert_eq!(chain.head().unwrap().height, 5);
+	for h in &headers[..3] {
+		assert!(chain.get_block(&h.hash()).is_err());
+		assert_eq!(chain.get_block_header(&h.hash()).unwrap().hash(), h.hash());
+	}
+	for h in &headers[3..] {
+		assert!(chain.get_block(&h.hash()).is_ok());
+	}
+	assert!(chain.get_block(&genesis.hash()).is_ok());
+}
+
+#[test]
+fn pruning_horizon_below_cut_through_refused() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let horizon = global::cut_through_horizon();
+	let mut config = chain::ChainConfig::default();
+	for h in vec![0, horizon - 1] {
+		config.pruning_horizon = Some(h);
+		assert!(config.validate().is_err());
+	}
+	config.pruning_horizon = Some(horizon);
+	assert!(config.validate().is_ok());
+
+	// nor below the maximum reorg depth
+	config.max_reorg_depth = Some(horizon + 1);
+	assert!(config.validate().is_err());
+
+	config.max_reorg_depth = None;
+	config.pruning_horizon = Some(0);
+	clean_output_dir(".grin13");
+	assert!(
+		chain::Chain::init(
+			".grin13".to_string(),
+			Arc::new(NoopAdapter {}),
+			pow::mine_genesis_block(None).unwrap(),
+			pow::verify_size,
+			config,
+		).is_err()
+	);
+}
+
 fn prepare_block(kc: &Keychain, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block {
 	let mut b = prepare_block_nosum(kc, prev, diff, vec![]);
 	chain.set_sumtree_roots(&mut b, false).unwrap();
@@ -65,4 +65,38 @@ fn test_various_store_indices() {
 
 	let block_header = chain_store.get_header_by_height(1).unwrap();
 	assert_eq!(block_header.hash(), block_hash);
+
+	// the block, its height and the head all get saved at once
+	let next = Block::new(&block.header, vec![], &keychain, &key_id, Difficulty::one()).unwrap();
+	chain_store
+		.save_block_as_head(&next, &Tip::from_block(&block.header), false)
+		.unwrap();
+	assert_eq!(chain_store.head().unwrap().last_block_h, next.hash());
+	assert_eq!(chain_store.get_header_head().unwrap().last_block_h, next.hash());
+	assert_eq!(chain_store.get_header_by_height(2).unwrap().hash(), next.hash());
+
+	// consecutive headers from a height, as many as there are
+	let heights = |from, count| {
+		chain_store
+			.get_headers_from_height(from, count)
+			.unwrap()
+			.iter()
+			.map(|h| h.height)
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(heights(0, 10), vec![0, 1, 2]);
+	assert_eq!(heights(1, 1), vec![1]);
+	assert_eq!(heights(3, 10), Vec::<u64>::new());
+
+	// stopping at the first gap in the height index
+	chain_store.delete_header_by_height(1).unwrap();
+	assert_eq!(heights(0, 10), vec![0]);
+	chain_store.setup_height(&block.header, &Tip::from_block(&block.header)).unwrap();
+	assert_eq!(heights(0, 10), vec![0, 1, 2]);
+
+	// pruning the block body keeps the header around
+	chain_store.delete_block(&block_hash).unwrap();
+	assert!(chain_store.get_block(&block_hash).is_err());
+	let block_header = chain_store.get_block_header(&block_hash).unwrap();
+	assert_eq!(block_header.hash(), block_hash);
 }
@@ -52,6 +52,7 @@ fn test_coinbase_maturity() {
 		Arc::new(NoopAdapter {}),
 		genesis_block,
 		pow::verify_size,
+		chain::ChainConfig::default(),
 	).unwrap();
 
 	let mut miner_config = types::MinerConfig {
@@ -15,9 +15,9 @@
 //! Configuration file management
 
 use std::env;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::PathBuf;
-use std::fs::File;
+use std::fs::{self, File};
 
 use toml;
 use grin::ServerConfig;
@@ -32,6 +32,16 @@ use wallet::WalletConfig;
 const CONFIG_FILE_NAME: &'static str = "grin.toml";
 const GRIN_HOME: &'static str = ".grin";
 
+/// Location of the configuration file in the user home directory, the last
+/// place looked at when searching for one.
+pub fn home_config_path() -> Option<PathBuf> {
+	env::home_dir().map(|mut p| {
+		p.push(GRIN_HOME);
+		p.push(CONFIG_FILE_NAME);
+		p
+	})
+}
+
 /// Returns the defaults, as strewn throughout the code
 
 impl Default for ConfigMembers {
@@ -159,8 +169,10 @@ impl GlobalConfig {
 
 	/// Serialize config
 	pub fn ser_config(&mut self) -> Result<String, ConfigError> {
+		// going through a toml value first, which emits plain values before
+		// tables as toml requires, whatever the order of our struct fields
 		let encoded: Result<String, toml::ser::Error> =
-			toml::to_string(self.members.as_mut().unwrap());
+			toml::Value::try_from(self.members.as_mut().unwrap()).and_then(|v| toml::to_string(&v));
 		match encoded {
 			Ok(enc) => return Ok(enc),
 			Err(e) => {
@@ -171,6 +183,22 @@ impl GlobalConfig {
 		}
 	}
 
+	/// Write config to the config file path, creating its directory if
+	/// needed
+	pub fn write_config(&mut self) -> Result<(), ConfigError> {
+		let encoded = self.ser_config()?;
+		let path = match self.config_file_path {
+			Some(ref p) => p.clone(),
+			None => return Err(ConfigError::FileNotFoundError(String::from(""))),
+		};
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		let mut file = File::create(&path)?;
+		file.write_all(encoded.as_bytes())?;
+		Ok(())
+	}
+
 	/*pub fn wallet_enabled(&mut self) -> bool {
         return self.members.as_mut().unwrap().wallet.as_mut().unwrap().enable_wallet;
     }*/
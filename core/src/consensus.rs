@@ -128,6 +128,18 @@ pub fn valid_header_version(height: u64, version: u16) -> bool {
 	}
 }
 
+/// Output feature bits defined at a given block version. All other bits are
+/// reserved for output types introduced by later hard forks: they still parse
+/// fine, so older nodes can relay transactions using them, but outputs with
+/// unknown features can't be included in a block of that version.
+pub fn known_output_features(version: u16) -> u8 {
+	match version {
+		// uncomment below as new output types get introduced
+		/* 2 => 0b00000011, */
+		_ => 0b00000001,
+	}
+}
+
 /// Time window in blocks to calculate block time median
 pub const MEDIAN_TIME_WINDOW: u64 = 11;
 
@@ -185,6 +197,39 @@ impl fmt::Display for TargetError {
 /// by using the difference between the median timestamps at the beginning 
 /// and the end of the window.
 pub fn next_difficulty<T>(cursor: T) -> Result<Difficulty, TargetError>
+where
+	T: IntoIterator<Item = Result<(u64, Difficulty), TargetError>>,
+{
+	difficulty_adjustment(cursor).map(|adj| adj.next_difficulty)
+}
+
+/// Intermediate values of a difficulty adjustment, for those who want to
+/// follow the computation rather than only get its result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifficultyAdjustment {
+	/// Average difficulty over the last DIFFICULTY_ADJUST_WINDOW blocks
+	pub average_difficulty: u64,
+	/// Median timestamp at the beginning of the window
+	pub earliest_median_ts: u64,
+	/// Median timestamp at the end of the window
+	pub latest_median_ts: u64,
+	/// Actual timespan of the window, between both medians
+	pub timespan: u64,
+	/// Timespan after dampening
+	pub damped_timespan: u64,
+	/// Dampened timespan within the time bounds, used for the adjustment
+	pub adjusted_timespan: u64,
+	/// Difficulty the next block should comply with
+	pub next_difficulty: Difficulty,
+	/// Timestamp and difficulty of the blocks the adjustment is computed
+	/// from, earliest first, DIFFICULTY_ADJUST_WINDOW+MEDIAN_TIME_WINDOW of
+	/// them, including simulated pre-genesis blocks early in the chain
+	pub window: Vec<(u64, Difficulty)>,
+}
+
+/// Runs the difficulty adjustment of `next_difficulty`, returning all its
+/// intermediate values along with the resulting difficulty.
+pub fn difficulty_adjustment<T>(cursor: T) -> Result<DifficultyAdjustment, TargetError>
 where
 	T: IntoIterator<Item = Result<(u64, Difficulty), TargetError>>,
 {
@@ -192,27 +237,29 @@ where
 	// to latest, and pad with simulated pre-genesis data to allow earlier
 	// adjustment if there isn't enough window data
 	// length will be DIFFICULTY_ADJUST_WINDOW+MEDIAN_TIME_WINDOW
-	let diff_data = global::difficulty_data_to_vector(cursor);
+	let diff_data = global::difficulty_data_to_vector(cursor)
+		.into_iter()
+		.collect::<Result<Vec<_>, _>>()?;
 	// Get the difficulty sum for averaging later
 	// Which in this case is the sum of the last
 	// DIFFICULTY_ADJUST_WINDOW elements
 	let diff_sum = diff_data.iter()
 		.skip(MEDIAN_TIME_WINDOW as usize)
 		.take(DIFFICULTY_ADJUST_WINDOW as usize)
-		.fold(Difficulty::zero(), |sum, d| sum + d.clone().unwrap().1);
+		.fold(Difficulty::zero(), |sum, d| sum + d.1.clone());
 
 	// Obtain the median window for the earlier time period
 	// which is just the first MEDIAN_TIME_WINDOW elements 
 	let mut window_earliest: Vec<u64> = diff_data.iter()
 		.take(MEDIAN_TIME_WINDOW as usize)
-		.map(|n| n.clone().unwrap().0)
+		.map(|n| n.0)
 		.collect();
 
 	// Obtain the median window for the latest time period
 	// i.e. the last MEDIAN_TIME_WINDOW elements
 	let mut window_latest: Vec<u64> = diff_data.iter()
 		.skip(DIFFICULTY_ADJUST_WINDOW as usize)
-		.map(|n| n.clone().unwrap().0)
+		.map(|n| n.0)
 		.collect();
 
 	// And obtain our median values
@@ -247,7 +294,16 @@ where
 		diff_avg * Difficulty::from_num(BLOCK_TIME_WINDOW).into_num()
 		/ Difficulty::from_num(adj_ts).into_num();
 
-	Ok(max(Difficulty::from_num(difficulty), Difficulty::one()))
+	Ok(DifficultyAdjustment {
+		average_difficulty: diff_avg,
+		earliest_median_ts: earliest_ts,
+		latest_median_ts: latest_ts,
+		timespan: ts_delta,
+		damped_timespan: ts_damp,
+		adjusted_timespan: adj_ts,
+		next_difficulty: max(Difficulty::from_num(difficulty), Difficulty::one()),
+		window: diff_data,
+	})
 }
 
 /// Consensus rule that collections of items are sorted lexicographically.
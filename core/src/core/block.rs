@@ -25,6 +25,7 @@ use core::{
 	Output,
 	OutputIdentifier,
 	ShortId,
+	SumCommit,
 	SwitchCommitHash,
 	Proof,
 	TxKernel,
@@ -36,6 +37,7 @@ use consensus;
 use consensus::{exceeds_weight, reward, REWARD, VerifySortOrder};
 use core::hash::{Hash, Hashed, ZERO_HASH};
 use core::id::ShortIdentifiable;
+use core::pmmr::MerkleProof;
 use core::target::Difficulty;
 use core::transaction;
 use ser::{self, Readable, Reader, Writeable, Writer, WriteableSorted, read_and_verify_sorted};
@@ -43,6 +45,7 @@ use util::kernel_sig_msg;
 use util::LOGGER;
 use global;
 use keychain;
+use keychain::BlindingFactor;
 
 /// Errors thrown by Block validation
 #[derive(Debug, Clone, PartialEq)]
@@ -77,6 +80,8 @@ pub enum Error {
 	CoinbaseOutputCountExceeded,
 	/// Limit on number of coinbase kernels in a valid block.
 	CoinbaseKernelCountExceeded,
+	/// Output features not defined for the version of the block
+	UnknownOutputFeatures(u8),
 	/// Other unspecified error condition
 	Other(String)
 }
@@ -130,6 +135,8 @@ pub struct BlockHeader {
 	pub difficulty: Difficulty,
 	/// Total accumulated difficulty since genesis block
 	pub total_difficulty: Difficulty,
+	/// Sum of the kernel offsets of all the transactions in the block
+	pub kernel_offset: BlindingFactor,
 }
 
 impl Default for BlockHeader {
@@ -147,10 +154,23 @@ impl Default for BlockHeader {
 			kernel_root: ZERO_HASH,
 			nonce: 0,
 			pow: Proof::zero(proof_size),
+			kernel_offset: BlindingFactor::zero(),
 		}
 	}
 }
 
+impl BlockHeader {
+	/// Verifies the provided proof shows the output is part of the output
+	/// MMR this header commits to, as in unspent as of this block.
+	pub fn verify_output_proof(
+		&self,
+		output: &OutputIdentifier,
+		proof: &MerkleProof<SumCommit>,
+	) -> bool {
+		proof.verify(&output.as_sum_commit(), &self.utxo_root)
+	}
+}
+
 /// Serialization of a block header
 impl Writeable for BlockHeader {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
@@ -168,6 +188,7 @@ impl Writeable for BlockHeader {
 		try!(writer.write_u64(self.nonce));
 		try!(self.difficulty.write(writer));
 		try!(self.total_difficulty.write(writer));
+		try!(self.kernel_offset.write(writer));
 
 		if writer.serialization_mode() != ser::SerializationMode::Hash {
 			try!(self.pow.write(writer));
@@ -188,6 +209,7 @@ impl Readable for BlockHeader {
 		let nonce = reader.read_u64()?;
 		let difficulty = Difficulty::read(reader)?;
 		let total_difficulty = Difficulty::read(reader)?;
+		let kernel_offset = BlindingFactor::read(reader)?;
 		let pow = Proof::read(reader)?;
 
 		Ok(BlockHeader {
@@ -205,6 +227,7 @@ impl Readable for BlockHeader {
 			nonce: nonce,
 			difficulty: difficulty,
 			total_difficulty: total_difficulty,
+			kernel_offset: kernel_offset,
 		})
 	}
 }
@@ -387,7 +410,7 @@ impl Block {
 		key_id: &keychain::Identifier,
 		difficulty: Difficulty,
 	) -> Result<Block, Error> {
-		let fees = txs.iter().map(|tx| tx.fee).sum();
+		let fees = txs.iter().map(|tx| tx.fee()).sum();
 		let (reward_out, reward_proof) = Block::reward_output(
 			keychain,
 			key_id,
@@ -446,16 +469,16 @@ impl Block {
 		let mut kernels = vec![];
 		let mut inputs = vec![];
 		let mut outputs = vec![];
+		let mut offsets = vec![];
 
 		// iterate over the all the txs
-		// build the kernel for each
-		// and collect all the kernels, inputs and outputs
+		// and collect all the kernels, inputs, outputs and offsets
 		// to build the block (which we can sort of think of as one big tx?)
 		for tx in txs {
 			// validate each transaction and gather their kernels
-			let excess = tx.validate()?;
-			let kernel = tx.build_kernel(excess);
-			kernels.push(kernel);
+			tx.validate()?;
+			kernels.extend(tx.kernels.iter().cloned());
+			offsets.push(tx.offset.clone());
 
 			for input in tx.inputs.clone() {
 				inputs.push(input);
@@ -487,6 +510,7 @@ impl Block {
 					previous: prev.hash(),
 					total_difficulty: difficulty +
 						prev.total_difficulty.clone(),
+					kernel_offset: transaction::sum_offsets(offsets)?,
 					..Default::default()
 				},
 				inputs: inputs,
@@ -546,6 +570,7 @@ impl Block {
 				pow: self.header.pow.clone(),
 				difficulty: self.header.difficulty.clone(),
 				total_difficulty: self.header.total_difficulty.clone(),
+				kernel_offset: self.header.kernel_offset.clone(),
 				..self.header
 			},
 			inputs: new_inputs,
@@ -564,6 +589,7 @@ impl Block {
 	pub fn validate(&self) -> Result<(), Error> {
 		self.verify_weight()?;
 		self.verify_sorted()?;
+		self.verify_output_features()?;
 		self.verify_coinbase()?;
 		self.verify_kernels()?;
 		Ok(())
@@ -583,6 +609,17 @@ impl Block {
 		Ok(())
 	}
 
+	// Outputs with features unknown at this block version can be relayed but
+	// not mined.
+	fn verify_output_features(&self) -> Result<(), Error> {
+		for out in &self.outputs {
+			if !out.features.is_known(self.header.version) {
+				return Err(Error::UnknownOutputFeatures(out.features.bits()));
+			}
+		}
+		Ok(())
+	}
+
 	/// Verifies the sum of input/output commitments match the sum in kernels
 	/// and that all kernel signatures are valid.
 	fn verify_kernels(&self) -> Result<(), Error> {
@@ -601,12 +638,15 @@ impl Block {
 		// sum all inputs and outs commitments
 		let io_sum = self.sum_commitments()?;
 
-		// sum all kernels commitments
-		let proof_commits = map_vec!(self.kernels, |proof| proof.excess);
+		// sum all kernels commitments, along with the offset
+		let mut proof_commits = map_vec!(self.kernels, |proof| proof.excess);
 
 		let proof_sum = {
 			let secp = static_secp_instance();
 			let secp = secp.lock().unwrap();
+			if !self.header.kernel_offset.is_zero() {
+				proof_commits.push(secp.commit(0, self.header.kernel_offset.secret_key())?);
+			}
 			secp.commit_sum(proof_commits, vec![])?
 		};
 
@@ -17,9 +17,9 @@
 //! the excess signature, etc.
 //!
 //! Each building function is a combinator that produces a function taking
-//! a transaction a sum of blinding factors, to return another transaction
-//! and sum. Combinators can then be chained and executed using the
-//! _transaction_ function.
+//! a transaction, its kernel and a sum of blinding factors, to return another
+//! transaction, kernel and sum. Combinators can then be chained and executed
+//! using the _transaction_ function.
 //!
 //! Example:
 //! build::transaction(vec![input_rand(75), output_rand(42), output_rand(32),
@@ -27,7 +27,8 @@
 
 use util::{secp, kernel_sig_msg};
 
-use core::{Transaction, Input, Output, OutputFeatures, SwitchCommitHash, COINBASE_OUTPUT, DEFAULT_OUTPUT};
+use core::{Transaction, TxKernel, Input, Output, OutputFeatures, SwitchCommitHash, COINBASE_OUTPUT,
+           DEFAULT_OUTPUT};
 use core::hash::Hash;
 use keychain;
 use keychain::{Keychain, BlindSum, BlindingFactor, Identifier};
@@ -39,8 +40,10 @@ pub struct Context<'a> {
 }
 
 /// Function type returned by the transaction combinators. Transforms a
-/// (Transaction, BlindSum) pair into another, provided some context.
-pub type Append = for<'a> Fn(&'a mut Context, (Transaction, BlindSum)) -> (Transaction, BlindSum);
+/// (Transaction, TxKernel, BlindSum) triple into another, provided some
+/// context.
+pub type Append = for<'a> Fn(&'a mut Context, (Transaction, TxKernel, BlindSum))
+	-> (Transaction, TxKernel, BlindSum);
 
 /// Adds an input with the provided value and blinding key to the transaction
 /// being built.
@@ -50,14 +53,14 @@ fn build_input(
 	out_block: Option<Hash>,
 	key_id: Identifier,
 ) -> Box<Append> {
-	Box::new(move |build, (tx, sum)| -> (Transaction, BlindSum) {
+	Box::new(move |build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
 		let commit = build.keychain.commit(value, &key_id).unwrap();
 		let input = Input::new(
 			features,
 			commit,
 			out_block,
 		);
-		(tx.with_input(input), sum.sub_key_id(key_id.clone()))
+		(tx.with_input(input), kern, sum.sub_key_id(key_id.clone()))
 	})
 }
 
@@ -86,7 +89,7 @@ pub fn coinbase_input(
 /// Adds an output with the provided value and key identifier from the
 /// keychain.
 pub fn output(value: u64, key_id: Identifier) -> Box<Append> {
-	Box::new(move |build, (tx, sum)| -> (Transaction, BlindSum) {
+	Box::new(move |build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
 		debug!(
 			LOGGER,
 			"Building an output: {}, {}",
@@ -125,6 +128,7 @@ pub fn output(value: u64, key_id: Identifier) -> Box<Append> {
 				switch_commit_hash: switch_commit_hash,
 				proof: rproof,
 			}),
+			kern,
 			sum.add_key_id(key_id.clone()),
 		)
 	})
@@ -132,15 +136,15 @@ pub fn output(value: u64, key_id: Identifier) -> Box<Append> {
 
 /// Sets the fee on the transaction being built.
 pub fn with_fee(fee: u64) -> Box<Append> {
-	Box::new(move |_build, (tx, sum)| -> (Transaction, BlindSum) {
-		(tx.with_fee(fee), sum)
+	Box::new(move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+		(tx, kern.with_fee(fee), sum)
 	})
 }
 
 /// Sets the lock_height on the transaction being built.
 pub fn with_lock_height(lock_height: u64) -> Box<Append> {
-	Box::new(move |_build, (tx, sum)| -> (Transaction, BlindSum) {
-		(tx.with_lock_height(lock_height), sum)
+	Box::new(move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+		(tx, kern.with_lock_height(lock_height), sum)
 	})
 }
 
@@ -148,15 +152,38 @@ pub fn with_lock_height(lock_height: u64) -> Box<Append> {
 /// combination with the initial_tx function when a new transaction is built
 /// by adding to a pre-existing one.
 pub fn with_excess(excess: BlindingFactor) -> Box<Append> {
-	Box::new(move |_build, (tx, sum)| -> (Transaction, BlindSum) {
-		(tx, sum.add_blinding_factor(excess.clone()))
+	Box::new(move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+		(tx, kern, sum.add_blinding_factor(excess.clone()))
+	})
+}
+
+/// Sets the kernel offset of the transaction being built. The offset gets
+/// subtracted from the excess the kernel signs for.
+pub fn with_offset(offset: BlindingFactor) -> Box<Append> {
+	Box::new(move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+		(
+			Transaction {
+				offset: offset.clone(),
+				..tx
+			},
+			kern,
+			sum.sub_blinding_factor(offset.clone()),
+		)
 	})
 }
 
 /// Sets an initial transaction to add to when building a new transaction.
-pub fn initial_tx(tx: Transaction) -> Box<Append> {
-	Box::new(move |_build, (_, sum)| -> (Transaction, BlindSum) {
-		(tx.clone(), sum)
+/// The kernel of the initial transaction, if any, is taken over as the
+/// kernel being built. Building on an aggregated transaction, with several
+/// kernels, fails.
+pub fn initial_tx(mut tx: Transaction) -> Box<Append> {
+	let kern = if tx.kernels.len() == 1 {
+		tx.kernels.pop().unwrap()
+	} else {
+		TxKernel::empty()
+	};
+	Box::new(move |_build, (_, _, sum)| -> (Transaction, TxKernel, BlindSum) {
+		(tx.clone(), kern.clone(), sum)
 	})
 }
 
@@ -175,14 +202,21 @@ pub fn transaction(
 	keychain: &keychain::Keychain,
 ) -> Result<(Transaction, BlindingFactor), keychain::Error> {
 	let mut ctx = Context { keychain };
-	let (mut tx, sum) = elems.iter().fold(
-		(Transaction::empty(), BlindSum::new()),
+	let (tx, mut kern, sum) = elems.iter().fold(
+		(Transaction::empty(), TxKernel::empty(), BlindSum::new()),
 		|acc, elem| elem(&mut ctx, acc),
 	);
+	// only left if the initial transaction had more than one
+	if !tx.kernels.is_empty() {
+		return Err(keychain::Error::Transaction(
+			"can't build on an aggregated transaction".to_string(),
+		));
+	}
 	let blind_sum = ctx.keychain.blind_sum(&sum)?;
-	let msg = secp::Message::from_slice(&kernel_sig_msg(tx.fee, tx.lock_height))?;
-	tx.excess_sig = Keychain::aggsig_sign_with_blinding(&keychain.secp(), &msg, &blind_sum)?;
-	Ok((tx, blind_sum))
+	let msg = secp::Message::from_slice(&kernel_sig_msg(kern.fee, kern.lock_height))?;
+	kern.excess = keychain.secp().commit(0, blind_sum.secret_key())?;
+	kern.excess_sig = Keychain::aggsig_sign_with_blinding(&keychain.secp(), &msg, &blind_sum)?;
+	Ok((tx.with_kernel(kern), blind_sum))
 }
 
 // Just a simple test, most exhaustive tests in the core mod.rs.
@@ -208,7 +242,7 @@ mod test {
 			&keychain,
 		).unwrap();
 
-		tx.verify_sig().unwrap();
+		tx.verify_kernels().unwrap();
 	}
 
 	#[test]
@@ -222,6 +256,25 @@ mod test {
 			&keychain,
 		).unwrap();
 
-		tx.verify_sig().unwrap();
+		tx.verify_kernels().unwrap();
+	}
+
+	#[test]
+	fn build_on_aggregated_tx() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let key_id1 = keychain.derive_key_id(1).unwrap();
+		let key_id2 = keychain.derive_key_id(2).unwrap();
+		let key_id3 = keychain.derive_key_id(3).unwrap();
+
+		let (tx, _) = transaction(
+			vec![input(6, ZERO_HASH, key_id1), output(2, key_id2), with_fee(4)],
+			&keychain,
+		).unwrap();
+		let kernel = tx.kernels[0].clone();
+		let aggregated = tx.with_kernel(kernel);
+
+		// an error, not a panic
+		let res = transaction(vec![initial_tx(aggregated), output(1, key_id3)], &keychain);
+		assert!(res.is_err());
 	}
 }
@@ -214,11 +214,14 @@ mod test {
 	use super::*;
 	use core::target::Difficulty;
 	use core::hash::ZERO_HASH;
-	use core::build::{initial_tx, input, output, with_excess, with_fee, with_lock_height};
+	use core::build::{initial_tx, input, output, with_excess, with_fee, with_lock_height,
+	                  with_offset};
 	use core::block::Error::KernelLockHeight;
 	use ser;
 	use keychain;
 	use keychain::{BlindingFactor, Keychain};
+	use rand::thread_rng;
+	use util::secp::key::SecretKey;
 
 	#[test]
 	pub fn test_amount_to_hr() {
@@ -264,7 +267,7 @@ mod test {
 		let mut vec = Vec::new();
 		ser::serialize(&mut vec, &tx).expect("serialization failed");
 		println!("{}", vec.len());
-		assert!(vec.len() == 5364);
+		assert!(vec.len() == 5438);
 	}
 
 	#[test]
@@ -273,7 +276,8 @@ mod test {
 		let mut vec = Vec::new();
 		ser::serialize(&mut vec, &tx).expect("serialization failed");
 		let dtx: Transaction = ser::deserialize(&mut &vec[..]).unwrap();
-		assert_eq!(dtx.fee, 2);
+		assert_eq!(dtx.fee(), 2);
+		assert_eq!(dtx.kernels.len(), 1);
 		assert_eq!(dtx.inputs.len(), 2);
 		assert_eq!(dtx.outputs.len(), 1);
 		assert_eq!(tx.hash(), dtx.hash());
@@ -321,7 +325,7 @@ mod test {
 	#[test]
 	fn blind_tx() {
 		let btx = tx2i1o();
-		btx.verify_sig().unwrap(); // unwrap will panic if invalid
+		btx.verify_kernels().unwrap(); // unwrap will panic if invalid
 
 		// checks that the range proof on our blind output is sufficiently hiding
 		let Output { proof, .. } = btx.outputs[0];
@@ -386,6 +390,111 @@ mod test {
 		tx_final.validate().unwrap();
 	}
 
+	#[test]
+	fn tx_with_offset() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let key_id1 = keychain.derive_key_id(1).unwrap();
+		let key_id2 = keychain.derive_key_id(2).unwrap();
+		let offset = random_offset(&keychain);
+
+		let (tx, _) = build::transaction(
+			vec![
+				input(10, ZERO_HASH, key_id1),
+				output(8, key_id2),
+				with_fee(2),
+				with_offset(offset.clone()),
+			],
+			&keychain,
+		).unwrap();
+		assert_eq!(tx.offset, offset);
+		tx.validate().unwrap();
+
+		// the offset survives serialization
+		let mut vec = Vec::new();
+		ser::serialize(&mut vec, &tx).expect("serialization failed");
+		let dtx: Transaction = ser::deserialize(&mut &vec[..]).unwrap();
+		assert_eq!(dtx.offset, offset);
+		dtx.validate().unwrap();
+
+		// and can't be tampered with
+		let mut bad_tx = tx.clone();
+		bad_tx.offset = random_offset(&keychain);
+		assert_eq!(bad_tx.validate(), Err(transaction::Error::KernelSumMismatch));
+	}
+
+	#[test]
+	fn aggregate_cut_through() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let key_id1 = keychain.derive_key_id(1).unwrap();
+		let key_id2 = keychain.derive_key_id(2).unwrap();
+		let key_id3 = keychain.derive_key_id(3).unwrap();
+		let (offset1, offset2) = (random_offset(&keychain), random_offset(&keychain));
+
+		// the second transaction spends the output of the first one
+		let (tx1, _) = build::transaction(
+			vec![
+				input(10, ZERO_HASH, key_id1),
+				output(8, key_id2.clone()),
+				with_fee(2),
+				with_offset(offset1.clone()),
+			],
+			&keychain,
+		).unwrap();
+		let (tx2, _) = build::transaction(
+			vec![
+				input(8, ZERO_HASH, key_id2),
+				output(4, key_id3),
+				with_fee(4),
+				with_lock_height(1),
+				with_offset(offset2.clone()),
+			],
+			&keychain,
+		).unwrap();
+
+		let agg_tx = transaction::aggregate(vec![tx1.clone(), tx2.clone()]).unwrap();
+		assert_eq!(agg_tx.inputs, tx1.inputs);
+		assert_eq!(agg_tx.outputs, tx2.outputs);
+		assert_eq!(agg_tx.kernels.len(), 2);
+		assert_eq!(agg_tx.fee(), 6);
+		assert_eq!(agg_tx.lock_height(), 1);
+		assert_eq!(
+			agg_tx.offset,
+			transaction::sum_offsets(vec![offset1, offset2]).unwrap()
+		);
+		agg_tx.validate().unwrap();
+
+		// keeping either offset alone doesn't balance the kernels
+		let mut bad_tx = agg_tx.clone();
+		bad_tx.offset = tx1.offset.clone();
+		assert!(bad_tx.validate().is_err());
+
+		// the block the aggregate goes in carries its offset
+		let key_id4 = keychain.derive_key_id(4).unwrap();
+		let b = Block::new(
+			&BlockHeader {
+				height: 1,
+				..BlockHeader::default()
+			},
+			vec![&agg_tx],
+			&keychain,
+			&key_id4,
+			Difficulty::one(),
+		).unwrap();
+		assert_eq!(b.header.kernel_offset, agg_tx.offset);
+		b.validate().unwrap();
+	}
+
+	#[test]
+	fn aggregate_without_offsets() {
+		let tx1 = tx1i1o();
+		let tx2 = tx2i1o();
+		let agg_tx = transaction::aggregate(vec![tx1, tx2]).unwrap();
+		assert!(agg_tx.offset.is_zero());
+		assert_eq!(agg_tx.inputs.len(), 3);
+		assert_eq!(agg_tx.outputs.len(), 2);
+		agg_tx.validate().unwrap();
+	}
+
 	#[test]
 	fn reward_empty_block() {
 		let keychain = keychain::Keychain::from_random_seed().unwrap();
@@ -407,7 +516,7 @@ mod test {
 		let key_id = keychain.derive_key_id(1).unwrap();
 
 		let mut tx1 = tx2i1o();
-		tx1.verify_sig().unwrap();
+		tx1.verify_kernels().unwrap();
 
 		let b = Block::new(
 			&BlockHeader::default(),
@@ -497,13 +606,18 @@ mod test {
 	#[test]
 	pub fn test_verify_1i1o_sig() {
 		let tx = tx1i1o();
-		tx.verify_sig().unwrap();
+		tx.verify_kernels().unwrap();
 	}
 
 	#[test]
 	pub fn test_verify_2i1o_sig() {
 		let tx = tx2i1o();
-		tx.verify_sig().unwrap();
+		tx.verify_kernels().unwrap();
+	}
+
+	// utility producing a random kernel offset
+	fn random_offset(keychain: &Keychain) -> BlindingFactor {
+		BlindingFactor::new(SecretKey::new(keychain.secp(), &mut thread_rng()))
 	}
 
 	// utility producing a transaction with 2 inputs and a single outputs
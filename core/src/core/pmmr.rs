@@ -165,6 +165,126 @@ where
 	}
 }
 
+/// Proof of inclusion of an element in a MMR. Holds the hashes and sums of
+/// the siblings on the path from the element up to the peak containing it,
+/// as well as all the other peaks, which is enough to recompute the MMR root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof<T>
+where
+	T: Summable,
+{
+	/// Size of the MMR the proof was built against
+	pub mmr_size: u64,
+	/// Position of the proven element in the MMR
+	pub pos: u64,
+	/// Siblings on the path from the element to its peak, bottom up
+	pub path: Vec<HashSum<T>>,
+	/// All the other peaks of the MMR, left to right
+	pub peaks: Vec<HashSum<T>>,
+}
+
+impl<T> MerkleProof<T>
+where
+	T: Summable + Hashed + Clone,
+{
+	/// Verifies the proof shows the provided element is part of a MMR with
+	/// the provided root hash.
+	pub fn verify(&self, elmt: &T, root: &Hash) -> bool {
+		if self.pos == 0 || self.pos > self.mmr_size || bintree_postorder_height(self.pos) > 0 {
+			return false;
+		}
+
+		// hash our way up to the peak containing the element
+		let mut current = HashSum::from_summable(self.pos, elmt);
+		let mut pos = self.pos;
+		for sibling in &self.path {
+			let (parent, sibling_pos) = family(pos);
+			if parent > self.mmr_size {
+				return false;
+			}
+			current = if sibling_pos < pos {
+				sibling.clone() + current
+			} else {
+				current + sibling.clone()
+			};
+			pos = parent;
+		}
+
+		// and bag it with the other peaks, the same way the root is computed
+		let peaks_pos = peaks(self.mmr_size);
+		let idx = match peaks_pos.iter().position(|&p| p == pos) {
+			Some(idx) => idx,
+			None => return false,
+		};
+		if peaks_pos.len() != self.peaks.len() + 1 {
+			return false;
+		}
+		let mut all_peaks = self.peaks.clone();
+		all_peaks.insert(idx, current);
+		let mut bagged: Option<HashSum<T>> = None;
+		for peak in all_peaks {
+			bagged = match bagged {
+				None => Some(peak),
+				Some(hsum) => Some(hsum + peak),
+			};
+		}
+		bagged.map(|hsum| hsum.hash == *root).unwrap_or(false)
+	}
+}
+
+impl<T> Writeable for MerkleProof<T>
+where
+	T: Summable,
+{
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(
+			writer,
+			[write_u64, self.mmr_size],
+			[write_u64, self.pos],
+			[write_u64, self.path.len() as u64]
+		);
+		for hsum in &self.path {
+			hsum.write(writer)?;
+		}
+		writer.write_u64(self.peaks.len() as u64)?;
+		for hsum in &self.peaks {
+			hsum.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl<T> Readable for MerkleProof<T>
+where
+	T: Summable,
+{
+	fn read(reader: &mut Reader) -> Result<MerkleProof<T>, ser::Error> {
+		let (mmr_size, pos, path_len) = ser_multiread!(reader, read_u64, read_u64, read_u64);
+		// a path can't be longer than the height of a MMR
+		if path_len > 64 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut path = Vec::with_capacity(path_len as usize);
+		for _ in 0..path_len {
+			path.push(HashSum::read(reader)?);
+		}
+		let peaks_len = reader.read_u64()?;
+		if peaks_len > 64 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut peaks = Vec::with_capacity(peaks_len as usize);
+		for _ in 0..peaks_len {
+			peaks.push(HashSum::read(reader)?);
+		}
+		Ok(MerkleProof {
+			mmr_size: mmr_size,
+			pos: pos,
+			path: path,
+			peaks: peaks,
+		})
+	}
+}
+
 /// Storage backend for the MMR, just needs to be indexed by order of insertion.
 /// The PMMR itself does not need the Backend to be accurate on the existence
 /// of an element (i.e. remove could be a no-op) but layers above can
@@ -351,6 +471,47 @@ where
 		}
 	}
 
+	/// Builds a proof of inclusion of the element at the provided position.
+	/// Fails if the element or one of the siblings on its path to the peak
+	/// has been pruned.
+	pub fn merkle_proof(&self, position: u64) -> Result<MerkleProof<T>, String> {
+		if position == 0 || bintree_postorder_height(position) > 0 {
+			return Err(format!("Node at {} is not a leaf, no proof.", position));
+		}
+		if let None = self.get(position) {
+			return Err(format!("No element at {}, no proof.", position));
+		}
+
+		let peaks_pos = peaks(self.last_pos);
+		let mut path = vec![];
+		let mut current = position;
+		while !peaks_pos.contains(&current) {
+			let (parent, sibling) = family(current);
+			if parent > self.last_pos {
+				return Err(format!("Node at {} has no peak, invalid tree.", position));
+			}
+			match self.get(sibling) {
+				Some(hsum) => path.push(hsum),
+				None => return Err(format!("Sibling {} of {} pruned, no proof.", sibling, position)),
+			}
+			current = parent;
+		}
+
+		let mut peaks = vec![];
+		for &pi in peaks_pos.iter().filter(|&&pi| pi != current) {
+			match self.get(pi) {
+				Some(hsum) => peaks.push(hsum),
+				None => return Err(format!("Missing peak at {}, invalid tree.", pi)),
+			}
+		}
+		Ok(MerkleProof {
+			mmr_size: self.last_pos,
+			pos: position,
+			path: path,
+			peaks: peaks,
+		})
+	}
+
 	/// Helper function to get the last N nodes inserted, i.e. the last
 	/// n nodes along the bottom of the tree
 	pub fn get_last_n_insertions(&self, n: u64) -> Vec<HashSum<T>> {
@@ -382,6 +543,24 @@ where
 		return_vec
 	}
 
+	/// Leaves that haven't been pruned, from the provided position onward, in
+	/// insertion order. At most max of them are returned, along with their
+	/// positions.
+	pub fn leaves_from(&self, start_pos: u64, max: u64) -> Vec<(u64, HashSum<T>)> {
+		let mut leaves = vec![];
+		for pos in start_pos.max(1)..self.last_pos + 1 {
+			if leaves.len() as u64 >= max {
+				break;
+			}
+			if bintree_postorder_height(pos) == 0 {
+				if let Some(hsum) = self.backend.get(pos) {
+					leaves.push((pos, hsum));
+				}
+			}
+		}
+		leaves
+	}
+
 	/// Total size of the tree, including intermediary nodes an ignoring any
 	/// pruning.
 	pub fn unpruned_size(&self) -> u64 {
@@ -781,7 +960,7 @@ fn most_significant_pos(num: u64) -> u64 {
 #[cfg(test)]
 mod test {
 	use super::*;
-	use core::hash::Hashed;
+	use core::hash::{Hashed, ZERO_HASH};
 
 	#[test]
 	fn some_all_ones() {
@@ -952,6 +1131,48 @@ mod test {
 		assert_eq!(pmmr.unpruned_size(), 16);
 	}
 
+	#[test]
+	fn pmmr_merkle_proof() {
+		let elems = [
+			TestElem([0, 0, 0, 1]),
+			TestElem([0, 0, 0, 2]),
+			TestElem([0, 0, 0, 3]),
+			TestElem([0, 0, 0, 4]),
+			TestElem([0, 0, 0, 5]),
+			TestElem([0, 0, 0, 6]),
+			TestElem([0, 0, 0, 7]),
+		];
+
+		let mut ba = VecBackend::new();
+		let mut pmmr = PMMR::new(&mut ba);
+		let mut positions = vec![];
+		for elem in &elems {
+			positions.push(pmmr.push(*elem).unwrap());
+		}
+		let root = pmmr.root().hash;
+
+		for (elem, &pos) in elems.iter().zip(positions.iter()) {
+			let proof = pmmr.merkle_proof(pos).unwrap();
+			assert!(proof.verify(elem, &root));
+
+			// roundtrip through serialization
+			let vec = ser::ser_vec(&proof).unwrap();
+			let proof2: MerkleProof<TestElem> = ser::deserialize(&mut &vec[..]).unwrap();
+			assert_eq!(proof, proof2);
+		}
+
+		// wrong element, position or root
+		let proof = pmmr.merkle_proof(positions[2]).unwrap();
+		assert!(!proof.verify(&elems[3], &root));
+		assert!(!proof.verify(&elems[2], &ZERO_HASH));
+		let mut wrong_pos = proof.clone();
+		wrong_pos.pos = positions[3];
+		assert!(!wrong_pos.verify(&elems[2], &root));
+
+		// no proof for intermediary nodes
+		assert!(pmmr.merkle_proof(3).is_err());
+	}
+
 	#[test]
 	fn pmmr_get_last_n_insertions() {
 		let elems = [
@@ -1004,6 +1225,30 @@ mod test {
 		);
 	}
 
+	#[test]
+	fn pmmr_leaves_from() {
+		let mut ba = VecBackend::new();
+		let sz: u64;
+		{
+			let mut pmmr = PMMR::new(&mut ba);
+			for n in 1..6 {
+				pmmr.push(TestElem([0, 0, 0, n])).unwrap();
+			}
+			pmmr.prune(2, 0).unwrap();
+			sz = pmmr.unpruned_size();
+		}
+		let pmmr = PMMR::at(&mut ba, sz);
+
+		// leaves are at 1, 2, 4, 5 and 8, 2 being pruned
+		let res = pmmr.leaves_from(0, 10);
+		assert_eq!(res.iter().map(|l| l.0).collect::<Vec<_>>(), vec![1, 4, 5, 8]);
+		assert_eq!(res[3].1.sum, 5);
+
+		let res = pmmr.leaves_from(3, 2);
+		assert_eq!(res.iter().map(|l| l.0).collect::<Vec<_>>(), vec![4, 5]);
+		assert!(pmmr.leaves_from(9, 10).is_empty());
+	}
+
 	#[test]
 	#[allow(unused_variables)]
 	fn pmmr_prune() {
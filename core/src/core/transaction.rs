@@ -19,6 +19,7 @@ use util::{static_secp_instance, kernel_sig_msg};
 use util::secp::pedersen::{Commitment, RangeProof};
 use std::cmp::min;
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::ops;
 
 use consensus;
@@ -26,7 +27,7 @@ use consensus::VerifySortOrder;
 use core::Committed;
 use core::hash::{Hash, Hashed, ZERO_HASH};
 use core::pmmr::Summable;
-use keychain::{Identifier, Keychain};
+use keychain::{BlindingFactor, Identifier, Keychain};
 use ser::{self, read_and_verify_sorted, Readable, Reader, Writeable, WriteableSorted, Writer};
 use util;
 
@@ -84,6 +85,8 @@ pub enum Error {
 	LockHeight(u64),
 	/// Error originating from an invalid switch commitment (coinbase lock_height related)
 	SwitchCommitment,
+	/// The sum of the kernel excesses doesn't match the transaction excess
+	KernelSumMismatch,
 }
 
 impl From<secp::Error> for Error {
@@ -167,6 +170,31 @@ impl TxKernel {
 		}
 		Ok(())
 	}
+
+	/// Builds a new kernel with zero excess, no signature and no fee, to be
+	/// completed when building a transaction.
+	pub fn empty() -> TxKernel {
+		TxKernel {
+			features: DEFAULT_KERNEL,
+			fee: 0,
+			lock_height: 0,
+			excess: Commitment::from_vec(vec![0; 33]),
+			excess_sig: Signature::from_raw_data(&[0; 64]).unwrap(),
+		}
+	}
+
+	/// Builds a new kernel with the provided fee.
+	pub fn with_fee(self, fee: u64) -> TxKernel {
+		TxKernel { fee: fee, ..self }
+	}
+
+	/// Builds a new kernel with the provided lock_height.
+	pub fn with_lock_height(self, lock_height: u64) -> TxKernel {
+		TxKernel {
+			lock_height: lock_height,
+			..self
+		}
+	}
 }
 
 /// A transaction
@@ -176,37 +204,35 @@ pub struct Transaction {
 	pub inputs: Vec<Input>,
 	/// Set of outputs the transaction produces.
 	pub outputs: Vec<Output>,
-	/// Fee paid by the transaction.
-	pub fee: u64,
-	/// Transaction is not valid before this chain height.
-	pub lock_height: u64,
-	/// The signature proving the excess is a valid public key, which signs
-	/// the transaction fee.
-	pub excess_sig: Signature,
+	/// Kernels proving the transaction sums to zero, a single one unless the
+	/// transaction is an aggregate of several others.
+	pub kernels: Vec<TxKernel>,
+	/// Part of the excess of the transaction not covered by its kernels. The
+	/// offsets of aggregated transactions get summed so their kernels can't be
+	/// matched back to their inputs and outputs.
+	pub offset: BlindingFactor,
 }
 
 /// Implementation of Writeable for a fully blinded transaction, defines how to
 /// write the transaction as binary.
 impl Writeable for Transaction {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		ser_multiwrite!(
-			writer,
-			[write_u64, self.fee],
-			[write_u64, self.lock_height]
-		);
-		self.excess_sig.write(writer)?;
+		self.offset.write(writer)?;
 		ser_multiwrite!(
 			writer,
 			[write_u64, self.inputs.len() as u64],
-			[write_u64, self.outputs.len() as u64]
+			[write_u64, self.outputs.len() as u64],
+			[write_u64, self.kernels.len() as u64]
 		);
 
 		// Consensus rule that everything is sorted in lexicographical order on the wire.
 		let mut inputs = self.inputs.clone();
 		let mut outputs = self.outputs.clone();
+		let mut kernels = self.kernels.clone();
 
 		try!(inputs.write_sorted(writer));
 		try!(outputs.write_sorted(writer));
+		try!(kernels.write_sorted(writer));
 
 		Ok(())
 	}
@@ -216,24 +242,20 @@ impl Writeable for Transaction {
 /// transaction from a binary stream.
 impl Readable for Transaction {
 	fn read(reader: &mut Reader) -> Result<Transaction, ser::Error> {
-		let (fee, lock_height) =
-			ser_multiread!(reader, read_u64, read_u64);
+		let offset = BlindingFactor::read(reader)?;
 
-		let excess_sig = Signature::read(reader)?;
-
-		let (input_len, output_len) =
-			ser_multiread!(reader, read_u64, read_u64);
+		let (input_len, output_len, kernel_len) =
+			ser_multiread!(reader, read_u64, read_u64, read_u64);
 
 		let inputs = read_and_verify_sorted(reader, input_len)?;
 		let outputs = read_and_verify_sorted(reader, output_len)?;
+		let kernels = read_and_verify_sorted(reader, kernel_len)?;
 
 		Ok(Transaction {
-			fee: fee,
-			lock_height: lock_height,
-			excess_sig: excess_sig,
 			inputs: inputs,
 			outputs: outputs,
-			..Default::default()
+			kernels: kernels,
+			offset: offset,
 		})
 	}
 }
@@ -246,7 +268,7 @@ impl Committed for Transaction {
 		&self.outputs
 	}
 	fn overage(&self) -> i64 {
-		(self.fee as i64)
+		(self.fee() as i64)
 	}
 }
 
@@ -257,31 +279,20 @@ impl Default for Transaction {
 }
 
 impl Transaction {
-	/// Creates a new empty transaction (no inputs or outputs, zero fee).
+	/// Creates a new empty transaction (no inputs, outputs or kernels, zero
+	/// offset).
 	pub fn empty() -> Transaction {
-		Transaction {
-			fee: 0,
-			lock_height: 0,
-			excess_sig: Signature::from_raw_data(&[0;64]).unwrap(),
-			inputs: vec![],
-			outputs: vec![],
-		}
+		Transaction::new(vec![], vec![], vec![])
 	}
 
-	/// Creates a new transaction initialized with
-	/// the provided inputs, outputs, fee and lock_height.
-	pub fn new(
-		inputs: Vec<Input>,
-		outputs: Vec<Output>,
-		fee: u64,
-		lock_height: u64,
-	) -> Transaction {
+	/// Creates a new transaction initialized with the provided inputs,
+	/// outputs and kernels, with no offset.
+	pub fn new(inputs: Vec<Input>, outputs: Vec<Output>, kernels: Vec<TxKernel>) -> Transaction {
 		Transaction {
-			fee: fee,
-			lock_height: lock_height,
-			excess_sig: Signature::from_raw_data(&[0;64]).unwrap(),
 			inputs: inputs,
 			outputs: outputs,
+			kernels: kernels,
+			offset: BlindingFactor::zero(),
 		}
 	}
 
@@ -309,59 +320,76 @@ impl Transaction {
 		}
 	}
 
-	/// Builds a new transaction with the provided fee.
-	pub fn with_fee(self, fee: u64) -> Transaction {
-		Transaction { fee: fee, ..self }
-	}
-
-	/// Builds a new transaction with the provided lock_height.
-	pub fn with_lock_height(self, lock_height: u64) -> Transaction {
+	/// Builds a new transaction with the provided kernel added. Existing
+	/// kernels, if any, are kept intact.
+	pub fn with_kernel(self, kernel: TxKernel) -> Transaction {
+		let mut new_kerns = self.kernels;
+		new_kerns.push(kernel);
+		new_kerns.sort();
 		Transaction {
-			lock_height: lock_height,
+			kernels: new_kerns,
 			..self
 		}
 	}
 
-	/// The verification for a MimbleWimble transaction involves getting the
-	/// excess of summing all commitments and using it as a public key
-	/// to verify the embedded signature. The rational is that if the values
-	/// sum to zero as they should in r.G + v.H then only k.G the excess
-	/// of the sum of r.G should be left. And r.G is the definition of a
-	/// public key generated using r as a private key.
-	pub fn verify_sig(&self) -> Result<Commitment, secp::Error> {
-		let rsum = self.sum_commitments()?;
+	/// Total fee paid by the transaction, over all its kernels.
+	pub fn fee(&self) -> u64 {
+		self.kernels.iter().map(|k| k.fee).sum()
+	}
 
-		let msg = Message::from_slice(&kernel_sig_msg(self.fee, self.lock_height))?;
+	/// The transaction can't be mined before this height, the highest lock
+	/// height of its kernels.
+	pub fn lock_height(&self) -> u64 {
+		self.kernels.iter().map(|k| k.lock_height).max().unwrap_or(0)
+	}
 
+	/// The excess of the sum of all the transaction commitments, less the
+	/// offset. That's what the excess of the kernels must sum to.
+	pub fn kernel_excess(&self) -> Result<Commitment, secp::Error> {
+		let io_sum = self.sum_commitments()?;
+		if self.offset.is_zero() {
+			return Ok(io_sum);
+		}
 		let secp = static_secp_instance();
 		let secp = secp.lock().unwrap();
-		let sig = self.excess_sig;
-		// pretend the sum is a public key (which it is, being of the form r.G) and
-		// verify the transaction sig with it
-		let valid = Keychain::aggsig_verify_single_from_commit(&secp, &sig, &msg, &rsum);
-		if !valid {
-			return Err(secp::Error::IncorrectSignature);
-		}
-		Ok(rsum)
+		let offset_commit = secp.commit(0, self.offset.secret_key())?;
+		secp.commit_sum(vec![io_sum], vec![offset_commit])
 	}
 
-	/// Builds a transaction kernel
-	pub fn build_kernel(&self, excess: Commitment) -> TxKernel {
-		TxKernel {
-			features: DEFAULT_KERNEL,
-			excess: excess,
-			excess_sig: self.excess_sig.clone(),
-			fee: self.fee,
-			lock_height: self.lock_height,
+	/// The verification for a MimbleWimble transaction involves getting the
+	/// excess of summing all commitments and checking it matches the sum of
+	/// the kernel excesses (once adjusted for the offset). Each kernel excess
+	/// is then used as a public key to verify the kernel signature. The
+	/// rational is that if the values sum to zero as they should in r.G + v.H
+	/// then only k.G the excess of the sum of r.G should be left. And r.G is
+	/// the definition of a public key generated using r as a private key.
+	pub fn verify_kernels(&self) -> Result<(), Error> {
+		if self.kernels.is_empty() {
+			return Err(Error::KernelSumMismatch);
+		}
+		let excess = self.kernel_excess()?;
+		let kernel_sum = {
+			let secp = static_secp_instance();
+			let secp = secp.lock().unwrap();
+			secp.commit_sum(map_vec!(self.kernels, |k| k.excess), vec![])?
+		};
+		if kernel_sum != excess {
+			return Err(Error::KernelSumMismatch);
 		}
+		for kernel in &self.kernels {
+			kernel.verify()?;
+		}
+		Ok(())
 	}
 
 	/// Validates all relevant parts of a fully built transaction. Checks the
-	/// excess value against the signature as well as range proofs for each
-	/// output.
-	pub fn validate(&self) -> Result<Commitment, Error> {
-		if self.fee & 1 != 0 {
-			return Err(Error::OddFee);
+	/// excess value against the kernels and their signatures as well as range
+	/// proofs for each output.
+	pub fn validate(&self) -> Result<(), Error> {
+		for kernel in &self.kernels {
+			if kernel.fee & 1 != 0 {
+				return Err(Error::OddFee);
+			}
 		}
 		if self.inputs.len() > consensus::MAX_BLOCK_INPUTS {
 			return Err(Error::TooManyInputs);
@@ -370,17 +398,78 @@ impl Transaction {
 		for out in &self.outputs {
 			out.verify_proof()?;
 		}
-		let excess = self.verify_sig()?;
-		Ok(excess)
+		self.verify_kernels()
 	}
 
 	fn verify_sorted(&self) -> Result<(), Error> {
 		self.inputs.verify_sort_order()?;
 		self.outputs.verify_sort_order()?;
+		self.kernels.verify_sort_order()?;
 		Ok(())
 	}
 }
 
+/// Sums the provided kernel offsets, skipping the zero ones.
+pub fn sum_offsets(offsets: Vec<BlindingFactor>) -> Result<BlindingFactor, secp::Error> {
+	let keys = offsets
+		.iter()
+		.filter(|o| !o.is_zero())
+		.map(|o| o.secret_key())
+		.collect::<Vec<_>>();
+	if keys.is_empty() {
+		return Ok(BlindingFactor::zero());
+	}
+	let secp = static_secp_instance();
+	let secp = secp.lock().unwrap();
+	Ok(BlindingFactor::new(secp.blind_sum(keys, vec![])?))
+}
+
+/// Aggregates the provided transactions into a single one, with all their
+/// kernels and the sum of their offsets. Outputs spent by an input of one of
+/// the transactions are cut-through: both disappear from the aggregate, which
+/// still sums to the same excess.
+pub fn aggregate(txs: Vec<Transaction>) -> Result<Transaction, Error> {
+	let mut inputs: Vec<Input> = vec![];
+	let mut outputs: Vec<Output> = vec![];
+	let mut kernels: Vec<TxKernel> = vec![];
+	let mut offsets: Vec<BlindingFactor> = vec![];
+
+	for tx in txs {
+		inputs.extend(tx.inputs);
+		outputs.extend(tx.outputs);
+		kernels.extend(tx.kernels);
+		offsets.push(tx.offset);
+	}
+
+	let spent = inputs
+		.iter()
+		.map(|inp| inp.commitment())
+		.collect::<HashSet<_>>();
+	let produced = outputs
+		.iter()
+		.map(|out| out.commitment())
+		.collect::<HashSet<_>>();
+	let mut new_inputs = inputs
+		.into_iter()
+		.filter(|inp| !produced.contains(&inp.commitment()))
+		.collect::<Vec<_>>();
+	let mut new_outputs = outputs
+		.into_iter()
+		.filter(|out| !spent.contains(&out.commitment()))
+		.collect::<Vec<_>>();
+
+	new_inputs.sort();
+	new_outputs.sort();
+	kernels.sort();
+
+	Ok(Transaction {
+		inputs: new_inputs,
+		outputs: new_outputs,
+		kernels: kernels,
+		offset: sum_offsets(offsets)?,
+	})
+}
+
 /// A transaction input.
 ///
 /// Primarily a reference to an output being spent by the transaction.
@@ -474,6 +563,17 @@ bitflags! {
 		const DEFAULT_OUTPUT = 0b00000000,
 		/// Output is a coinbase output, must not be spent until maturity
 		const COINBASE_OUTPUT = 0b00000001,
+		/// Bits reserved for future output types, see
+		/// consensus::known_output_features
+		const RESERVED_OUTPUT = 0b11111110,
+	}
+}
+
+impl OutputFeatures {
+	/// Whether all the features set are defined at the provided block version.
+	/// Outputs with unknown features can be relayed but not mined.
+	pub fn is_known(&self, version: u16) -> bool {
+		self.bits() & !consensus::known_output_features(version) == 0
 	}
 }
 
@@ -985,6 +1085,40 @@ mod test {
 		assert_eq!(dout.proof, out.proof);
 	}
 
+	#[test]
+	fn test_output_unknown_features() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let key_id = keychain.derive_key_id(1).unwrap();
+		let commit = keychain.commit(5, &key_id).unwrap();
+		let switch_commit = keychain.switch_commit(&key_id).unwrap();
+		let switch_commit_hash = SwitchCommitHash::from_switch_commit(
+			switch_commit,
+			&keychain,
+			&key_id,
+		);
+		let msg = secp::pedersen::ProofMessage::empty();
+		let proof = keychain.range_proof(5, &key_id, commit, msg).unwrap();
+
+		// a future output type, unknown at version 1
+		let features = OutputFeatures::from_bits(0b00000100).unwrap();
+		let out = Output {
+			features: features,
+			commit: commit,
+			switch_commit_hash: switch_commit_hash,
+			proof: proof,
+		};
+
+		// still parses fine, so it can be relayed
+		let mut vec = vec![];
+		ser::serialize(&mut vec, &out).expect("serialized failed");
+		let dout: Output = ser::deserialize(&mut &vec[..]).unwrap();
+		assert_eq!(dout.features, features);
+
+		assert!(DEFAULT_OUTPUT.is_known(1));
+		assert!(COINBASE_OUTPUT.is_known(1));
+		assert!(!dout.features.is_known(1));
+	}
+
 	#[test]
 	fn test_output_value_recovery() {
 		let keychain = Keychain::from_random_seed().unwrap();
@@ -25,6 +25,7 @@ use std::sync::RwLock;
 use consensus::PROOFSIZE;
 use consensus::DEFAULT_SIZESHIFT;
 use consensus::COINBASE_MATURITY;
+use consensus::CUT_THROUGH_HORIZON;
 use consensus::{MEDIAN_TIME_WINDOW, INITIAL_DIFFICULTY, 
 	BLOCK_TIME_SEC, DIFFICULTY_ADJUST_WINDOW};
 use core::target::Difficulty;
@@ -51,6 +52,9 @@ pub const AUTOMATED_TESTING_COINBASE_MATURITY: u64 = 3;
 /// User testing coinbase maturity
 pub const USER_TESTING_COINBASE_MATURITY: u64 = 3;
 
+/// Automated testing cut-through horizon
+pub const AUTOMATED_TESTING_CUT_THROUGH_HORIZON: u64 = 2;
+
 /// Testing initial block difficulty
 pub const TESTING_INITIAL_DIFFICULTY: u64 = 1;
 
@@ -139,6 +143,19 @@ pub fn coinbase_maturity() -> u64 {
 	}
 }
 
+/// Cut-through horizon, beyond which spent outputs get compacted and forks
+/// can't be processed anymore
+pub fn cut_through_horizon() -> u64 {
+	let param_ref = CHAIN_TYPE.read().unwrap();
+	match *param_ref {
+		ChainTypes::AutomatedTesting => AUTOMATED_TESTING_CUT_THROUGH_HORIZON,
+		ChainTypes::UserTesting => CUT_THROUGH_HORIZON as u64,
+		ChainTypes::Testnet1 => CUT_THROUGH_HORIZON as u64,
+		ChainTypes::Testnet2 => CUT_THROUGH_HORIZON as u64,
+		ChainTypes::Mainnet => CUT_THROUGH_HORIZON as u64,
+	}
+}
+
 /// Max Proof Target
 pub fn max_proof_target() -> [u8; 8] {
 	let param_ref = CHAIN_TYPE.read().unwrap();
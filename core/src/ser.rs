@@ -22,7 +22,7 @@
 use std::{cmp, error, fmt};
 use std::io::{self, Read, Write};
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
-use keychain::{Identifier, IDENTIFIER_SIZE};
+use keychain::{BlindingFactor, Identifier, IDENTIFIER_SIZE};
 use consensus;
 use consensus::VerifySortOrder;
 use core::hash::Hashed;
@@ -30,7 +30,9 @@ use core::transaction::{SWITCH_COMMIT_HASH_SIZE, SwitchCommitHash};
 use util::secp::pedersen::Commitment;
 use util::secp::pedersen::RangeProof;
 use util::secp::Signature;
-use util::secp::constants::{MAX_PROOF_SIZE, PEDERSEN_COMMITMENT_SIZE, AGG_SIGNATURE_SIZE};
+use util::secp::constants::{MAX_PROOF_SIZE, PEDERSEN_COMMITMENT_SIZE, AGG_SIGNATURE_SIZE,
+                            SECRET_KEY_SIZE};
+use util::static_secp_instance;
 
 /// Possible errors deriving from serializing or deserializing.
 #[derive(Debug)]
@@ -375,6 +377,26 @@ impl Writeable for Signature {
 	}
 }
 
+impl Writeable for BlindingFactor {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_fixed_bytes(&self.secret_key()[..].to_vec())
+	}
+}
+
+/// Zero is a valid blinding factor on the wire (no offset) even though secp
+/// won't accept it as a key.
+impl Readable for BlindingFactor {
+	fn read(reader: &mut Reader) -> Result<BlindingFactor, Error> {
+		let bytes = reader.read_fixed_bytes(SECRET_KEY_SIZE)?;
+		if bytes.iter().all(|b| *b == 0) {
+			return Ok(BlindingFactor::zero());
+		}
+		let secp = static_secp_instance();
+		let secp = secp.lock().unwrap();
+		BlindingFactor::from_slice(&secp, &bytes).map_err(|_| Error::CorruptedData)
+	}
+}
+
 
 /// Utility wrapper for an underlying byte Writer. Defines higher level methods
 /// to write numbers, byte vectors, hashes, etc.
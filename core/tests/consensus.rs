@@ -245,6 +245,24 @@ fn next_target_adjustment() {
 	);
 }
 
+/// Checks the intermediate values of a difficulty adjustment
+#[test]
+fn difficulty_adjustment_details() {
+	global::set_mining_mode(global::ChainTypes::AutomatedTesting);
+	let just_enough = DIFFICULTY_ADJUST_WINDOW + MEDIAN_TIME_WINDOW;
+	let adj = difficulty_adjustment(repeat(90, 1000, just_enough, Some(1_000_000))).unwrap();
+	assert_eq!(adj.window.len() as u64, just_enough);
+	assert_eq!(adj.window.first().unwrap().0, 1_000_000);
+	assert_eq!(adj.window.last().unwrap().0, 1_000_000 + 70 * 90);
+	assert_eq!(adj.average_difficulty, 1000);
+	assert_eq!(adj.earliest_median_ts, 1_000_000 + 5 * 90);
+	assert_eq!(adj.latest_median_ts, 1_000_000 + 65 * 90);
+	assert_eq!(adj.timespan, 5400);
+	assert_eq!(adj.damped_timespan, 4200);
+	assert_eq!(adj.adjusted_timespan, 4200);
+	assert_eq!(adj.next_difficulty, Difficulty::from_num(857));
+}
+
 #[test]
 fn hard_fork_1() {
 	assert!(valid_header_version(0, 1));
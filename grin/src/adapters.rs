@@ -14,11 +14,11 @@
 
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicBool, Ordering};
 use rand;
 use rand::Rng;
 
-use chain::{self, ChainAdapter, Options, MINE};
+use api;
+use chain::{self, ChainAdapter, Options, SyncState, MINE};
 use core::core;
 use core::core::block::BlockHeader;
 use core::core::hash::{Hash, Hashed};
@@ -28,15 +28,18 @@ use p2p;
 use pool;
 use util::OneTime;
 use store;
+use sync::HeaderRanges;
 use util::LOGGER;
+use util::tracing;
 
 /// Implementation of the NetAdapter for the blockchain. Gets notified when new
 /// blocks and transactions are received and forwards to the chain and pool
 /// implementations.
 pub struct NetToChainAdapter {
-	currently_syncing: Arc<AtomicBool>,
+	sync_state: Arc<SyncState>,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+	header_ranges: Arc<HeaderRanges>,
 	peers: OneTime<p2p::Peers>,
 }
 
@@ -50,6 +53,12 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	}
 
 	fn transaction_received(&self, tx: core::Transaction) {
+		// our chain state is behind while syncing, we can't validate (and
+		// relay) transactions against it
+		if self.sync_state.is_syncing() {
+			debug!(LOGGER, "Ignoring tx {} received while syncing.", tx.hash());
+			return;
+		}
 		let source = pool::TxSource {
 			debug_name: "p2p".to_string(),
 			identifier: "?.?.?.?".to_string(),
@@ -67,6 +76,25 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn stem_transaction_received(&self, tx: core::Transaction) {
+		if self.sync_state.is_syncing() {
+			debug!(LOGGER, "Ignoring stem tx {} received while syncing.", tx.hash());
+			return;
+		}
+		let source = pool::TxSource {
+			debug_name: "p2p stem".to_string(),
+			identifier: "?.?.?.?".to_string(),
+		};
+		debug!(LOGGER, "Received stem tx {}, going to process.", tx.hash());
+
+		// continues along the stem, the stem pool fluffing it in turn
+		// eventually or once its embargo expires
+		let h = tx.hash();
+		if let Err(e) = self.tx_pool.write().unwrap().add_relayed_to_stempool(source, tx) {
+			debug!(LOGGER, "Stem transaction {} rejected: {:?}", h, e);
+		}
+	}
+
 	fn block_received(&self, b: core::Block, addr: SocketAddr) -> bool {
 		let bhash = b.hash();
 		debug!(
@@ -77,10 +105,17 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			addr,
 		);
 
+		tracing::start("block", &bhash.to_hex(), &addr.to_string());
+		tracing::event(&bhash.to_hex(), "block received");
+
 		// pushing the new block through the chain pipeline
+		let prev_hash = b.header.previous;
 		let res = self.chain.process_block(b, self.chain_opts());
 		if let Err(ref e) = res {
 			debug!(LOGGER, "Block {} refused by chain: {:?}", bhash, e);
+			if let &chain::Error::Orphan = e {
+				self.request_orphan_parent(prev_hash, &addr);
+			}
 			if e.is_bad_block() {
 				debug!(LOGGER, "block_received: {} is a bad block, resetting head", bhash);
 				let _ = self.chain.reset_head();
@@ -100,10 +135,14 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			addr,
 		);
 
+		tracing::start("block", &bhash.to_hex(), &addr.to_string());
+		tracing::event(&bhash.to_hex(), "compact block received");
+
 		debug!(
 			LOGGER,
 			"*** cannot hydrate compact block (not yet implemented), falling back to requesting full block",
 		);
+		tracing::event(&bhash.to_hex(), "full block requested");
 
 		self.request_block(&bh.header, &addr);
 
@@ -120,6 +159,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			addr,
 		);
 
+		// first hearing of a block is usually its header, blocks being
+		// relayed header first
+		tracing::start("block", &bhash.to_hex(), &addr.to_string());
+		tracing::event(&bhash.to_hex(), "header received");
+
 		// pushing the new block header through the header chain pipeline
 		// we will go ask for the block if this is a new header
 		let res = self.chain.process_block_header(&bh, self.chain_opts());
@@ -153,44 +197,17 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			addr,
 		);
 
-		// try to add each header to our header chain
-		let mut added_hs = vec![];
-		for bh in bhs {
-			let res = self.chain.sync_block_header(&bh, self.chain_opts());
-			match res {
-				Ok(_) => {
-					added_hs.push(bh.hash());
-				}
-				Err(chain::Error::Unfit(s)) => {
-					info!(
-						LOGGER,
-						"Received unfit block header {} at {}: {}.",
-						bh.hash(),
-						bh.height,
-						s
-					);
-				}
-				Err(chain::Error::StoreErr(e, explanation)) => {
-					error!(
-						LOGGER,
-						"Store error processing block header {}: in {} {:?}",
-						bh.hash(),
-						explanation,
-						e
-					);
-					return;
-				}
-				Err(e) => {
-					info!(LOGGER, "Invalid block header {}: {:?}.", bh.hash(), e);
-					// TODO penalize peer somehow
-				}
+		// headers from a range ahead of our header chain wait for the ones
+		// preceding them, which may complete other ranges received earlier
+		let mut next = self.header_ranges
+			.receive(bhs, addr, &self.chain)
+			.map(|bhs| (bhs, addr));
+		while let Some((bhs, addr)) = next {
+			if !self.add_headers(bhs, addr) {
+				return;
 			}
+			next = self.header_ranges.next_ready(&self.chain);
 		}
-		info!(
-			LOGGER,
-			"Added {} headers to the header chain.",
-			added_hs.len()
-		);
 	}
 
 	fn locate_headers(&self, locator: Vec<Hash>) -> Vec<core::BlockHeader> {
@@ -213,18 +230,15 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 		// looks like we know one, getting as many following headers as allowed
 		let hh = header.height;
-		let mut headers = vec![];
-		for h in (hh + 1)..(hh + (p2p::MAX_BLOCK_HEADERS as u64)) {
-			let header = self.chain.get_header_by_height(h);
-			match header {
-				Ok(head) => headers.push(head),
-				Err(chain::Error::StoreErr(store::Error::NotFoundErr, _)) => break,
-				Err(e) => {
-					error!(LOGGER, "Could not build header locator: {:?}", e);
-					return vec![];
-				}
+		let headers = match self.chain
+			.get_headers_from_height(hh + 1, p2p::MAX_BLOCK_HEADERS as usize - 1)
+		{
+			Ok(headers) => headers,
+			Err(e) => {
+				error!(LOGGER, "Could not build header locator: {:?}", e);
+				return vec![];
 			}
-		}
+		};
 
 		debug!(
 			LOGGER,
@@ -235,6 +249,16 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		headers
 	}
 
+	fn headers_from_height(&self, height: u64, count: usize) -> Vec<core::BlockHeader> {
+		match self.chain.get_headers_from_height(height, count) {
+			Ok(headers) => headers,
+			Err(e) => {
+				error!(LOGGER, "Could not get headers from {}: {:?}", height, e);
+				vec![]
+			}
+		}
+	}
+
 	/// Gets a full block by its hash.
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		let b = self.chain.get_block(&h);
@@ -248,14 +272,16 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 impl NetToChainAdapter {
 	pub fn new(
-		currently_syncing: Arc<AtomicBool>,
+		sync_state: Arc<SyncState>,
 		chain_ref: Arc<chain::Chain>,
 		tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+		header_ranges: Arc<HeaderRanges>,
 	) -> NetToChainAdapter {
 		NetToChainAdapter {
-			currently_syncing: currently_syncing,
+			sync_state: sync_state,
 			chain: chain_ref,
 			tx_pool: tx_pool,
+			header_ranges: header_ranges,
 			peers: OneTime::new(),
 		}
 	}
@@ -298,6 +324,21 @@ impl NetToChainAdapter {
 		}
 	}
 
+	// We received a block we can't connect to our chain yet, ask the peer
+	// that sent it for its parent. If the parent is itself a known orphan,
+	// it's already been requested when we got it. Body sync takes care of
+	// missing blocks while syncing.
+	fn request_orphan_parent(&self, prev_hash: Hash, addr: &SocketAddr) {
+		if self.sync_state.is_syncing() || self.chain.is_orphan(&prev_hash) {
+			return;
+		}
+		if let Ok(_) = self.chain.get_block(&prev_hash) {
+			return;
+		}
+		debug!(LOGGER, "request_orphan_parent: requesting {} from {}", prev_hash, addr);
+		self.peers.borrow().request_block(prev_hash, addr);
+	}
+
 	// After receiving a compact block if we cannot successfully hydrate
 	// it into a full block then fallback to requesting the full block
 	// from the same peer that gave us the compact block
@@ -306,11 +347,7 @@ impl NetToChainAdapter {
 	// consider additional peers for redundancy?
 	fn request_block(&self, bh: &BlockHeader, addr: &SocketAddr) {
 		if let None = self.peers.borrow().adapter.get_block(bh.hash()) {
-			if let Some(peer) = self.peers.borrow().get_connected_peer(addr) {
-				if let Ok(peer) = peer.read() {
-					let _ = peer.send_block_request(bh.hash());
-				}
-			}
+			self.peers.borrow().request_block(bh.hash(), addr);
 		} else {
 			debug!(LOGGER, "request_block: block {} already known", bh.hash());
 		}
@@ -335,8 +372,58 @@ impl NetToChainAdapter {
 	}
 
 	/// Prepare options for the chain pipeline
+	// Tries to add each header to our header chain, false if we couldn't
+	// because of a store error. The peer that sent us invalid headers gets
+	// penalized.
+	fn add_headers(&self, bhs: Vec<core::BlockHeader>, addr: SocketAddr) -> bool {
+		let mut added_hs = vec![];
+		let mut invalid = false;
+		for bh in bhs {
+			let res = self.chain.sync_block_header(&bh, self.chain_opts());
+			match res {
+				Ok(_) => {
+					added_hs.push(bh.hash());
+				}
+				Err(chain::Error::Unfit(s)) => {
+					info!(
+						LOGGER,
+						"Received unfit block header {} at {}: {}.",
+						bh.hash(),
+						bh.height,
+						s
+					);
+				}
+				Err(chain::Error::StoreErr(e, explanation)) => {
+					error!(
+						LOGGER,
+						"Store error processing block header {}: in {} {:?}",
+						bh.hash(),
+						explanation,
+						e
+					);
+					return false;
+				}
+				Err(e) => {
+					info!(LOGGER, "Invalid block header {}: {:?}.", bh.hash(), e);
+					invalid = invalid || e.is_bad_block();
+				}
+			}
+		}
+		info!(
+			LOGGER,
+			"Added {} headers to the header chain.",
+			added_hs.len()
+		);
+		if invalid {
+			self.peers
+				.borrow()
+				.penalize_peer(&addr, p2p::Misbehavior::BadData);
+		}
+		true
+	}
+
 	fn chain_opts(&self) -> chain::Options {
-		let opts = if self.currently_syncing.load(Ordering::Relaxed) {
+		let opts = if self.sync_state.is_syncing() {
 			chain::SYNC
 		} else {
 			chain::NONE
@@ -351,6 +438,7 @@ impl NetToChainAdapter {
 pub struct ChainToPoolAndNetAdapter {
 	tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
 	peers: OneTime<p2p::Peers>,
+	events: Arc<api::EventHub>,
 }
 
 impl ChainAdapter for ChainToPoolAndNetAdapter {
@@ -394,16 +482,43 @@ impl ChainAdapter for ChainToPoolAndNetAdapter {
 			// "header first" propagation if we are not the originator of this block
 			self.peers.borrow().broadcast_header(&b.header);
 		}
+
+		let bhash = b.hash().to_hex();
+		tracing::event(&bhash, "relayed");
+		tracing::finish(&bhash, "accepted");
+
+		self.events.publish(api::Event::from_block(b));
+	}
+
+	fn reorg(&self, old_tip: &chain::Tip, new_tip: &chain::Tip, depth: u64) {
+		// transactions from the blocks we just orphaned go back to the pool,
+		// unless they're also in the new branch
+		let restored = self.tx_pool
+			.write()
+			.unwrap()
+			.reconcile_reorg(old_tip.last_block_h, depth);
+		debug!(
+			LOGGER,
+			"Reorg to {} at {} (depth {}), restored {} txs to the pool",
+			new_tip.last_block_h,
+			new_tip.height,
+			depth,
+			restored,
+		);
+		self.events
+			.publish(api::Event::from_reorg(old_tip, new_tip, depth));
 	}
 }
 
 impl ChainToPoolAndNetAdapter {
 	pub fn new(
 		tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+		events: Arc<api::EventHub>,
 	) -> ChainToPoolAndNetAdapter {
 		ChainToPoolAndNetAdapter {
 			tx_pool: tx_pool,
 			peers: OneTime::new(),
+			events: events,
 		}
 	}
 	pub fn init(&self, peers: p2p::Peers) {
@@ -415,19 +530,45 @@ impl ChainToPoolAndNetAdapter {
 /// transactions that have been accepted.
 pub struct PoolToNetAdapter {
 	peers: OneTime<p2p::Peers>,
+	events: Arc<api::EventHub>,
 }
 
 impl pool::PoolAdapter for PoolToNetAdapter {
 	fn tx_accepted(&self, tx: &core::Transaction) {
 		self.peers.borrow().broadcast_transaction(tx);
+		self.events.publish(api::Event::from_transaction(tx));
+	}
+
+	fn stem_tx_accepted(&self, tx: &core::Transaction) -> Result<(), pool::PoolError> {
+		// a single random peer continues the stem, only the ones recent enough
+		// to tell stem transactions apart qualify
+		let peers = self.peers
+			.borrow()
+			.connected_peers()
+			.into_iter()
+			.filter(|p| {
+				let p = p.read().unwrap();
+				let stem_peer = p.is_connected() && p.info.version >= p2p::msg::STEM_VERSION;
+				stem_peer
+			})
+			.collect::<Vec<_>>();
+		let res = match rand::thread_rng().choose(&peers) {
+			Some(p) => p.read()
+				.unwrap()
+				.send_stem_transaction(tx)
+				.map_err(|e| pool::PoolError::StemRelay(format!("{:?}", e))),
+			None => Err(pool::PoolError::StemRelay("no stem peer".to_string())),
+		};
+		res
 	}
 }
 
 impl PoolToNetAdapter {
 	/// Create a new pool to net adapter
-	pub fn new() -> PoolToNetAdapter {
+	pub fn new(events: Arc<api::EventHub>) -> PoolToNetAdapter {
 		PoolToNetAdapter {
 			peers: OneTime::new(),
+			events: events,
 		}
 	}
 
@@ -486,4 +627,11 @@ impl pool::BlockChain for PoolToChainAdapter {
 			.head_header()
 			.map_err(|_| pool::PoolError::GenericPoolError)
 	}
+
+	fn get_block(&self, h: &Hash) -> Result<core::Block, pool::PoolError> {
+		self.chain
+			.borrow()
+			.get_block(h)
+			.map_err(|_| pool::PoolError::GenericPoolError)
+	}
 }
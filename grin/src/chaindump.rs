@@ -0,0 +1,156 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Portable dumps of the blocks of a chain, to bootstrap a node from a copy
+//! it trusts instead of downloading everything from its peers. A dump is a
+//! short header identifying the chain by its genesis, followed by all the
+//! blocks after the genesis in their canonical serialization, in height
+//! order. Imported blocks go through the same full validation as blocks
+//! received from the network.
+
+use std::io::{Read, Write};
+
+use chain;
+use core::core::Block;
+use core::core::hash::{Hash, Hashed};
+use core::ser::{self, Readable, Reader, Writeable, Writer};
+use util::LOGGER;
+
+const DUMP_MAGIC: [u8; 8] = *b"GRINBLKS";
+const DUMP_VERSION: u16 = 1;
+
+// blocks between two progress messages
+const PROGRESS_BLOCKS: u64 = 1_000;
+
+/// Header at the start of a dump file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpHeader {
+	/// Genesis of the chain the blocks belong to
+	pub genesis: Hash,
+	/// Number of blocks following the header, from height 1
+	pub block_count: u64,
+}
+
+impl Writeable for DumpHeader {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		try!(writer.write_fixed_bytes(&DUMP_MAGIC));
+		try!(writer.write_u16(DUMP_VERSION));
+		try!(self.genesis.write(writer));
+		writer.write_u64(self.block_count)
+	}
+}
+
+impl Readable for DumpHeader {
+	fn read(reader: &mut Reader) -> Result<DumpHeader, ser::Error> {
+		let magic = try!(reader.read_fixed_bytes(DUMP_MAGIC.len()));
+		if magic[..] != DUMP_MAGIC[..] {
+			return Err(ser::Error::CorruptedData);
+		}
+		let version = try!(reader.read_u16());
+		if version != DUMP_VERSION {
+			return Err(ser::Error::CorruptedData);
+		}
+		Ok(DumpHeader {
+			genesis: try!(Hash::read(reader)),
+			block_count: try!(reader.read_u64()),
+		})
+	}
+}
+
+/// Writes all the blocks of the chain, up to its head, returning the number
+/// of blocks written. Fails if a block body was pruned, a pruning node not
+/// having a full copy of the chain to export.
+pub fn export(chain: &chain::Chain, sink: &mut Write) -> Result<u64, String> {
+	let head = chain.head().map_err(|e| format!("chain head: {:?}", e))?;
+	let genesis = chain
+		.get_header_by_height(0)
+		.map_err(|e| format!("genesis: {:?}", e))?;
+	let header = DumpHeader {
+		genesis: genesis.hash(),
+		block_count: head.height,
+	};
+	ser::serialize(sink, &header).map_err(|e| format!("dump header: {:?}", e))?;
+
+	for height in 1..head.height + 1 {
+		let block = chain
+			.get_header_by_height(height)
+			.and_then(|h| chain.get_block(&h.hash()))
+			.map_err(|e| format!("block at height {} (pruned?): {:?}", height, e))?;
+		ser::serialize(sink, &block).map_err(|e| format!("block at height {}: {:?}", height, e))?;
+		if height % PROGRESS_BLOCKS == 0 {
+			info!(LOGGER, "chaindump: exported {} of {} blocks", height, head.height);
+		}
+	}
+	Ok(head.height)
+}
+
+/// Reads the blocks of a dump and adds them to the chain, fully validated,
+/// skipping the ones the chain already has. Returns the number of blocks
+/// added. Stops at the first invalid block, the blocks before it staying in
+/// the chain.
+pub fn import(chain: &chain::Chain, source: &mut Read) -> Result<u64, String> {
+	let header: DumpHeader =
+		ser::deserialize(source).map_err(|e| format!("dump header: {:?}", e))?;
+	let genesis = chain
+		.get_header_by_height(0)
+		.map_err(|e| format!("genesis: {:?}", e))?;
+	if header.genesis != genesis.hash() {
+		return Err(format!(
+			"dump of another chain, genesis {} instead of {}",
+			header.genesis,
+			genesis.hash()
+		));
+	}
+
+	let mut imported = 0;
+	for n in 1..header.block_count + 1 {
+		let block: Block =
+			ser::deserialize(source).map_err(|e| format!("block {} of the dump: {:?}", n, e))?;
+		if chain.get_block(&block.hash()).is_ok() {
+			continue;
+		}
+		let height = block.header.height;
+		chain
+			.process_block(block, chain::NONE)
+			.map_err(|e| format!("block at height {}: {:?}", height, e))?;
+		imported += 1;
+		if n % PROGRESS_BLOCKS == 0 {
+			info!(LOGGER, "chaindump: processed {} of {} blocks", n, header.block_count);
+		}
+	}
+	Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::core::hash::ZERO_HASH;
+
+	#[test]
+	fn dump_header_roundtrip() {
+		let header = DumpHeader {
+			genesis: ZERO_HASH,
+			block_count: 42,
+		};
+		let mut vec = vec![];
+		ser::serialize(&mut vec, &header).unwrap();
+		assert_eq!(vec.len(), 8 + 2 + 32 + 8);
+		let read: DumpHeader = ser::deserialize(&mut &vec[..]).unwrap();
+		assert_eq!(read, header);
+
+		// not a dump
+		vec[0] = b'X';
+		assert!(ser::deserialize::<DumpHeader>(&mut &vec[..]).is_err());
+	}
+}
@@ -0,0 +1,183 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scheduling of the outbound connections to peers. Addresses to connect to
+//! get queued and dialed a few at a time, the most promising ones first, so
+//! a burst of addresses (from our seeds or a peer list) doesn't turn into as
+//! many simultaneous dials all waiting on their timeout.
+
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashSet};
+use std::net::SocketAddr;
+
+use p2p;
+
+/// How soon an address gets dialed compared to the other queued ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DialPriority {
+	/// Addresses we never connected to successfully
+	Low,
+	/// Healthy peers
+	Normal,
+	/// Preferred peers
+	High,
+}
+
+impl DialPriority {
+	/// Priority of an address given what our peer store knows about it, if
+	/// anything. The peers preferred in our configuration are flagged as such
+	/// in the store.
+	pub fn of(peer: Option<&p2p::PeerData>) -> DialPriority {
+		match peer.map(|p| p.flags) {
+			Some(p2p::State::Preferred) => DialPriority::High,
+			Some(p2p::State::Healthy) => DialPriority::Normal,
+			_ => DialPriority::Low,
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct QueuedDial {
+	addr: SocketAddr,
+	priority: DialPriority,
+	seq: u64,
+}
+
+// highest priority first, then first queued first
+impl Ord for QueuedDial {
+	fn cmp(&self, other: &QueuedDial) -> Ordering {
+		self.priority
+			.cmp(&other.priority)
+			.then_with(|| other.seq.cmp(&self.seq))
+	}
+}
+
+impl PartialOrd for QueuedDial {
+	fn partial_cmp(&self, other: &QueuedDial) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// Addresses waiting to be dialed and the dials in progress, never more than
+/// the concurrency limit at a time.
+pub struct DialQueue {
+	queue: BinaryHeap<QueuedDial>,
+	queued: HashSet<SocketAddr>,
+	in_flight: HashSet<SocketAddr>,
+	seq: u64,
+	max_in_flight: usize,
+}
+
+impl DialQueue {
+	/// Empty queue, allowing the provided number of dials at the same time
+	/// (at least one).
+	pub fn new(max_in_flight: usize) -> DialQueue {
+		DialQueue {
+			queue: BinaryHeap::new(),
+			queued: HashSet::new(),
+			in_flight: HashSet::new(),
+			seq: 0,
+			max_in_flight: cmp::max(max_in_flight, 1),
+		}
+	}
+
+	/// Updates the number of dials allowed at the same time, i.e. after a
+	/// configuration reload. Dials in progress are left alone.
+	pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+		self.max_in_flight = cmp::max(max_in_flight, 1);
+	}
+
+	/// Queues an address to dial, unless it's already queued or being
+	/// dialed. Returns whether it was queued.
+	pub fn push(&mut self, addr: SocketAddr, priority: DialPriority) -> bool {
+		if self.queued.contains(&addr) || self.in_flight.contains(&addr) {
+			return false;
+		}
+		self.seq += 1;
+		self.queue.push(QueuedDial {
+			addr: addr,
+			priority: priority,
+			seq: self.seq,
+		});
+		self.queued.insert(addr);
+		true
+	}
+
+	/// Next address to dial, if any is queued and we're below the
+	/// concurrency limit. The address is considered being dialed until
+	/// `done` gets called with it.
+	pub fn next(&mut self) -> Option<SocketAddr> {
+		if self.in_flight.len() >= self.max_in_flight {
+			return None;
+		}
+		let dial = match self.queue.pop() {
+			Some(dial) => dial,
+			None => return None,
+		};
+		self.queued.remove(&dial.addr);
+		self.in_flight.insert(dial.addr);
+		Some(dial.addr)
+	}
+
+	/// Marks the dial to the provided address as complete, successful or not.
+	pub fn done(&mut self, addr: &SocketAddr) {
+		self.in_flight.remove(addr);
+	}
+
+	/// Number of addresses waiting to be dialed.
+	pub fn queued_count(&self) -> usize {
+		self.queue.len()
+	}
+
+	/// Number of dials in progress.
+	pub fn in_flight_count(&self) -> usize {
+		self.in_flight.len()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn addr(port: u16) -> SocketAddr {
+		format!("10.0.0.1:{}", port).parse().unwrap()
+	}
+
+	#[test]
+	fn bounded_and_prioritized() {
+		let mut dials = DialQueue::new(2);
+		assert!(dials.push(addr(1), DialPriority::Low));
+		assert!(dials.push(addr(2), DialPriority::Normal));
+		assert!(dials.push(addr(3), DialPriority::High));
+		assert!(dials.push(addr(4), DialPriority::Normal));
+		// already queued
+		assert!(!dials.push(addr(2), DialPriority::High));
+
+		assert_eq!(dials.next(), Some(addr(3)));
+		assert_eq!(dials.next(), Some(addr(2)));
+		// at the limit until a dial completes
+		assert_eq!(dials.next(), None);
+		assert_eq!(dials.in_flight_count(), 2);
+		// being dialed
+		assert!(!dials.push(addr(3), DialPriority::High));
+
+		dials.done(&addr(3));
+		assert_eq!(dials.next(), Some(addr(4)));
+		dials.done(&addr(2));
+		dials.done(&addr(4));
+		assert_eq!(dials.next(), Some(addr(1)));
+		assert_eq!(dials.next(), None);
+		assert_eq!(dials.queued_count(), 0);
+	}
+}
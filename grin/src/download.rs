@@ -0,0 +1,225 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of the blocks requested from our peers during body sync. Each
+//! needed block gets assigned to a single peer at a time, spread over all the
+//! peers we sync from with a limit on how many blocks each can have
+//! outstanding. Requests not answered in time, or whose peer went away, get
+//! assigned again, to another peer when possible.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use time;
+
+use core::core::hash::Hash;
+
+struct BlockRequest {
+	addr: SocketAddr,
+	time: time::Tm,
+}
+
+/// Blocks requested from our peers and not received yet.
+pub struct BlockDownloads {
+	requests: HashMap<Hash, BlockRequest>,
+	// last peer that failed to deliver each block, avoided when reassigning
+	failed: HashMap<Hash, SocketAddr>,
+	max_per_peer: usize,
+	timeout: time::Duration,
+}
+
+impl BlockDownloads {
+	/// No downloads yet, allowing the provided number of outstanding blocks
+	/// per peer, each expected within the provided number of seconds.
+	pub fn new(max_per_peer: usize, timeout_secs: i64) -> BlockDownloads {
+		BlockDownloads {
+			requests: HashMap::new(),
+			failed: HashMap::new(),
+			max_per_peer: max_per_peer,
+			timeout: time::Duration::seconds(timeout_secs),
+		}
+	}
+
+	/// Stops tracking the requested blocks we now have, returning them with
+	/// the peer each was requested from.
+	pub fn received<F>(&mut self, have: F) -> Vec<(Hash, SocketAddr)>
+	where
+		F: Fn(&Hash) -> bool,
+	{
+		let received = self.requests
+			.iter()
+			.filter(|&(h, _)| have(h))
+			.map(|(h, r)| (*h, r.addr))
+			.collect::<Vec<_>>();
+		for &(h, _) in &received {
+			self.requests.remove(&h);
+			self.failed.remove(&h);
+		}
+		received
+	}
+
+	/// Gives up on the requests that timed out or went to a peer we're not
+	/// syncing from anymore, returning them with the peer each was requested
+	/// from. Their blocks get assigned again on the next `assign`.
+	pub fn expire(&mut self, now: time::Tm, addrs: &[SocketAddr]) -> Vec<(Hash, SocketAddr)> {
+		let timeout = self.timeout;
+		let expired = self.requests
+			.iter()
+			.filter(|&(_, r)| now - r.time > timeout || !addrs.contains(&r.addr))
+			.map(|(h, r)| (*h, r.addr))
+			.collect::<Vec<_>>();
+		for &(h, addr) in &expired {
+			self.requests.remove(&h);
+			self.failed.insert(h, addr);
+		}
+		expired
+	}
+
+	/// Assigns the provided blocks, in order, to the provided peers, skipping
+	/// the ones already requested. Each block goes to the peer with the fewest
+	/// outstanding blocks, earlier peers first on ties, and the peer that last
+	/// failed to deliver it only if no other has room. Returns the new
+	/// assignments, considered outstanding until received or expired.
+	pub fn assign(
+		&mut self,
+		now: time::Tm,
+		hashes: &[Hash],
+		addrs: &[SocketAddr],
+	) -> Vec<(Hash, SocketAddr)> {
+		let mut counts = addrs
+			.iter()
+			.map(|a| (*a, self.outstanding(a)))
+			.collect::<Vec<_>>();
+		let mut assigned = vec![];
+
+		for h in hashes {
+			if self.requests.contains_key(h) {
+				continue;
+			}
+			let failed = self.failed.get(h).cloned();
+			let pick = {
+				let max_per_peer = self.max_per_peer;
+				let free = counts
+					.iter()
+					.enumerate()
+					.filter(|&(_, &(_, n))| n < max_per_peer);
+				let mut best: Option<(usize, bool, usize)> = None;
+				for (i, &(addr, n)) in free {
+					let retry = Some(addr) == failed;
+					let better = match best {
+						None => true,
+						Some((_, best_retry, best_n)) => (retry, n) < (best_retry, best_n),
+					};
+					if better {
+						best = Some((i, retry, n));
+					}
+				}
+				best.map(|(i, _, _)| i)
+			};
+			let i = match pick {
+				Some(i) => i,
+				// all our peers are busy
+				None => break,
+			};
+			counts[i].1 += 1;
+			let addr = counts[i].0;
+			self.requests.insert(
+				*h,
+				BlockRequest {
+					addr: addr,
+					time: now,
+				},
+			);
+			assigned.push((*h, addr));
+		}
+		assigned
+	}
+
+	/// Stops tracking a request that couldn't be sent, so its block can be
+	/// assigned again right away.
+	pub fn cancel(&mut self, h: &Hash) {
+		if let Some(r) = self.requests.remove(h) {
+			self.failed.insert(*h, r.addr);
+		}
+	}
+
+	/// Number of blocks requested from the provided peer and not received
+	/// yet.
+	pub fn outstanding(&self, addr: &SocketAddr) -> usize {
+		self.requests.values().filter(|r| r.addr == *addr).count()
+	}
+
+	/// Number of blocks requested and not received yet, from all peers.
+	pub fn in_flight_count(&self) -> usize {
+		self.requests.len()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::core::hash::Hashed;
+
+	fn addr(port: u16) -> SocketAddr {
+		format!("10.0.0.1:{}", port).parse().unwrap()
+	}
+
+	#[test]
+	fn assign_expire_and_reassign() {
+		let mut downloads = BlockDownloads::new(2, 10);
+		let hashes = (0..6u64).map(|n| n.hash()).collect::<Vec<_>>();
+		let peers = vec![addr(1), addr(2)];
+		let now = time::now_utc();
+
+		// spread over both peers, 2 each at most
+		let assigned = downloads.assign(now, &hashes, &peers);
+		assert_eq!(
+			assigned,
+			vec![
+				(hashes[0], addr(1)),
+				(hashes[1], addr(2)),
+				(hashes[2], addr(1)),
+				(hashes[3], addr(2)),
+			]
+		);
+		assert!(downloads.assign(now, &hashes, &peers).is_empty());
+
+		// a received block frees a slot on its peer
+		let received = downloads.received(|h| *h == hashes[1]);
+		assert_eq!(received, vec![(hashes[1], addr(2))]);
+		let needed = vec![hashes[0], hashes[2], hashes[3], hashes[4], hashes[5]];
+		assert_eq!(
+			downloads.assign(now, &needed, &peers),
+			vec![(hashes[4], addr(2))]
+		);
+
+		// the first peer goes away, its blocks go to the other one first
+		let later = now + time::Duration::seconds(5);
+		downloads.received(|h| *h == hashes[3] || *h == hashes[4]);
+		assert_eq!(downloads.expire(later, &[addr(2)]).len(), 2);
+		assert_eq!(downloads.in_flight_count(), 0);
+		let needed = vec![hashes[0], hashes[2], hashes[5]];
+		assert_eq!(
+			downloads.assign(later, &needed, &peers),
+			vec![
+				(hashes[0], addr(2)),
+				(hashes[2], addr(2)),
+				(hashes[5], addr(1)),
+			]
+		);
+
+		// and everything times out eventually
+		let much_later = later + time::Duration::seconds(11);
+		assert_eq!(downloads.expire(much_later, &peers).len(), 3);
+	}
+}
@@ -21,10 +21,13 @@
 #![deny(unused_mut)]
 #![warn(missing_docs)]
 
+extern crate ctrlc;
 extern crate futures;
 extern crate futures_cpupool as cpupool;
 extern crate hyper;
 extern crate itertools;
+#[macro_use]
+extern crate lazy_static;
 extern crate rand;
 extern crate serde;
 #[macro_use]
@@ -35,6 +38,7 @@ extern crate slog;
 extern crate time;
 extern crate tokio_core;
 extern crate tokio_timer;
+extern crate toml;
 
 extern crate grin_api as api;
 extern crate grin_chain as chain;
@@ -48,11 +52,19 @@ extern crate grin_util as util;
 extern crate grin_wallet as wallet;
 
 mod adapters;
+mod chaindump;
+mod dial;
+mod download;
 mod server;
 mod seed;
 mod sync;
 mod types;
 mod miner;
+mod ports;
+mod reload;
+mod stratumserver;
+mod templates;
 
-pub use server::Server;
-pub use types::{Seeding, ServerConfig, ServerStats};
+pub use server::{Server, StatsReader};
+pub use types::{PeerStats, PortRange, Seeding, ServerConfig, ServerStats, StratumServerConfig};
+pub use ports::{read_discovery_file, AssignedPorts};
@@ -16,7 +16,10 @@
 //! block and mine the block to produce a valid header with its proof-of-work.
 
 use rand::{self, Rng};
-use std::sync::{Arc, RwLock};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 use time;
@@ -25,17 +28,16 @@ use adapters::PoolToChainAdapter;
 use core::consensus;
 use core::core;
 use core::core::Proof;
-use pow::cuckoo;
-use core::core::target::Difficulty;
-use core::core::{Block, BlockHeader, Transaction};
-use core::core::hash::{Hash, Hashed};
-use pow::MiningWorker;
+use core::core::{Block, Transaction};
+use core::core::hash::Hashed;
+use pow::mining::{self, JobProvider, MiningJob};
 use pow::types::MinerConfig;
+use core::global;
 use core::ser;
-use core::ser::AsFixedBytes;
 use util::LOGGER;
 use types::Error;
 
+use api;
 use chain;
 use pool;
 use util;
@@ -43,73 +45,122 @@ use keychain::{Identifier, Keychain};
 use wallet;
 use wallet::BlockFees;
 
-use pow::plugin::PluginMiner;
+// Weight left for transactions once the coinbase output and kernel are in
+const MAX_TX_WEIGHT: usize =
+	consensus::MAX_BLOCK_WEIGHT - consensus::BLOCK_OUTPUT_WEIGHT - consensus::BLOCK_KERNEL_WEIGHT;
 
-use itertools::Itertools;
-
-
-// Max number of transactions this miner will assemble in a block
-const MAX_TX: u32 = 5000;
-
-const PRE_NONCE_SIZE: usize = 146;
-
-/// Serializer that outputs pre and post nonce portions of a block header
-/// which can then be sent off to miner to mutate at will
-pub struct HeaderPartWriter {
-	//
-	pub pre_nonce: Vec<u8>,
-	// Post nonce is currently variable length
-	// because of difficulty
-	pub post_nonce: Vec<u8>,
-	// which difficulty field we're on
-	bytes_written: usize,
-	writing_pre: bool,
+// The block currently mined and its job identifier
+struct CurrentJob {
+	id: u64,
+	block: Block,
 }
 
-impl Default for HeaderPartWriter {
-	fn default() -> HeaderPartWriter {
-		HeaderPartWriter {
-			bytes_written: 0,
-			writing_pre: true,
-			pre_nonce: Vec::new(),
-			post_nonce: Vec::new(),
-		}
-	}
+/// Jobs of the mining plugin: the latest block built by the miner. Solutions
+/// meeting the block difficulty are pushed through the chain processing
+/// pipeline.
+struct MinerJobs {
+	chain: Arc<chain::Chain>,
+	stats: Arc<RwLock<api::MiningStats>>,
+	current: RwLock<Option<CurrentJob>>,
+	// set once a solution made a block, its coinbase key being used
+	block_found: AtomicBool,
+	coinbase_key_path: Option<String>,
+	stop_state: Arc<AtomicBool>,
+	debug_output_id: String,
 }
 
-impl HeaderPartWriter {
-	pub fn parts_as_hex_strings(&self) -> (String, String) {
-		(
-			String::from(format!("{:02x}", self.pre_nonce.iter().format(""))),
-			String::from(format!("{:02x}", self.post_nonce.iter().format(""))),
-		)
+impl MinerJobs {
+	fn has_job(&self) -> bool {
+		self.current.read().unwrap().is_some()
 	}
-}
 
-impl ser::Writer for HeaderPartWriter {
-	fn serialization_mode(&self) -> ser::SerializationMode {
-		ser::SerializationMode::Hash
+	fn set_job(&self, id: u64, block: Block) {
+		{
+			let mut stats = self.stats.write().unwrap();
+			stats.block_height = block.header.height;
+			stats.network_difficulty = block.header.difficulty.into_num();
+		}
+		*self.current.write().unwrap() = Some(CurrentJob {
+			id: id,
+			block: block,
+		});
 	}
+}
 
-	fn write_fixed_bytes<T: AsFixedBytes>(&mut self, bytes_in: &T) -> Result<(), ser::Error> {
-		if self.writing_pre {
-			for i in 0..bytes_in.len() {
-				self.pre_nonce.push(bytes_in.as_ref()[i])
+impl JobProvider for MinerJobs {
+	fn get_job(&self) -> Option<MiningJob> {
+		loop {
+			if self.stop_state.load(Ordering::Relaxed) {
+				return None;
 			}
-		} else if self.bytes_written != 0 {
-			for i in 0..bytes_in.len() {
-				self.post_nonce.push(bytes_in.as_ref()[i])
+			if let Some(ref job) = *self.current.read().unwrap() {
+				return Some(MiningJob {
+					job_id: job.id,
+					header: job.block.header.clone(),
+				});
 			}
+			thread::sleep(Duration::from_millis(100));
 		}
+	}
 
-		self.bytes_written += bytes_in.len();
+	fn submit_solution(&self, job_id: u64, nonce: u64, proof: Proof) {
+		let mut b = {
+			let mut current = self.current.write().unwrap();
+			let difficulty = match *current {
+				Some(ref job) if job.id == job_id => job.block.header.difficulty.clone(),
+				_ => return,
+			};
+			self.stats.write().unwrap().solutions_found += 1;
+			let proof_diff = proof.clone().to_difficulty();
+			trace!(
+				LOGGER,
+				"Found cuckoo solution for nonce {} of difficulty {} (difficulty target {})",
+				nonce,
+				proof_diff.into_num(),
+				difficulty.into_num()
+			);
+			if proof_diff < difficulty {
+				return;
+			}
+			// the miner builds the next job on top of the new head
+			current.take().unwrap().block
+		};
 
-		if self.bytes_written == PRE_NONCE_SIZE && self.writing_pre {
-			self.writing_pre = false;
-			self.bytes_written = 0;
+		b.header.nonce = nonce;
+		b.header.pow = proof;
+		let (height, hash) = (b.header.height, b.hash());
+		info!(
+			LOGGER,
+			"(Server ID: {}) Found valid proof of work, adding block {}.",
+			self.debug_output_id,
+			hash
+		);
+		util::tracing::start("block", &hash.to_hex(), "local miner");
+		let res = self.chain.process_block(b.clone(), chain::MINE);
+		match res {
+			Ok(_) => {
+				if let Some(ref key_path) = self.coinbase_key_path {
+					coinbase_accepted(key_path, &b);
+				}
+				self.stats.write().unwrap().add_block_found(
+					height,
+					hash.to_hex(),
+					"local miner".to_string(),
+					time::get_time().sec,
+				)
+			}
+			Err(e) => error!(
+				LOGGER,
+				"(Server ID: {}) Error validating mined block: {:?}",
+				self.debug_output_id,
+				e
+			),
 		}
+		self.block_found.store(true, Ordering::Relaxed);
+	}
 
-		Ok(())
+	fn report_graph_rate(&self, graphs_per_second: f64) {
+		self.stats.write().unwrap().graphs_per_second = graphs_per_second;
 	}
 }
 
@@ -117,6 +168,10 @@ pub struct Miner {
 	config: MinerConfig,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+	stats: Arc<RwLock<api::MiningStats>>,
+	stop_state: Arc<AtomicBool>,
+	// how long the wallet gets to build a coinbase, retrying forever if none
+	coinbase_timeout: Option<Duration>,
 
 	// Just to hold the port we're on, so this miner can be identified
 	// while watching debug output
@@ -125,353 +180,114 @@ pub struct Miner {
 
 impl Miner {
 	/// Creates a new Miner. Needs references to the chain state and its
-	/// storage.
+	/// storage, to the mining stats it updates and to the server stop state,
+	/// mining until it's set.
 	pub fn new(
 		config: MinerConfig,
 		chain_ref: Arc<chain::Chain>,
 		tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+		stats: Arc<RwLock<api::MiningStats>>,
+		stop_state: Arc<AtomicBool>,
 	) -> Miner {
 		Miner {
 			config: config,
 			chain: chain_ref,
 			tx_pool: tx_pool,
+			stats: stats,
+			stop_state: stop_state,
+			coinbase_timeout: None,
 			debug_output_id: String::from("none"),
 		}
 	}
 
-	/// Keeping this optional so setting in a separate function
-	/// instead of in the new function
-	pub fn set_debug_output_id(&mut self, debug_output_id: String) {
-		self.debug_output_id = debug_output_id;
+	/// Gives up building a block when the wallet doesn't provide the coinbase
+	/// within the timeout, instead of retrying until it does.
+	pub fn set_coinbase_timeout(&mut self, timeout: Duration) {
+		self.coinbase_timeout = Some(timeout);
 	}
 
-	/// Inner part of the mining loop for cuckoo-miner async mode
-	pub fn inner_loop_async(
-		&self,
-		plugin_miner: &mut PluginMiner,
-		difficulty: Difficulty,
-		b: &mut Block,
-		cuckoo_size: u32,
-		head: &BlockHeader,
-		latest_hash: &Hash,
-		attempt_time_per_block: u32,
-	) -> Option<Proof> {
-		debug!(
-			LOGGER,
-			"(Server ID: {}) Mining at Cuckoo{} for at most {} secs at height {} and difficulty {}.",
-			self.debug_output_id,
-			cuckoo_size,
-			attempt_time_per_block,
-			b.header.height,
-			b.header.difficulty
-		);
-
-		// look for a pow for at most attempt_time_per_block sec on the
-		// same block (to give a chance to new
-		// transactions) and as long as the head hasn't changed
-		// Will change this to something else at some point
-		let deadline = time::get_time().sec + attempt_time_per_block as i64;
-
-		// how often to output stats
-		let stat_output_interval = 2;
-		let mut next_stat_output = time::get_time().sec + stat_output_interval;
-
-		// Get parts of the header
-		let mut header_parts = HeaderPartWriter::default();
-		ser::Writeable::write(&b.header, &mut header_parts).unwrap();
-		let (pre, post) = header_parts.parts_as_hex_strings();
-
-		//Just test output to mine a genesis block when needed
-		/*let mut header_parts = HeaderPartWriter::default();
-		let gen = genesis::genesis();
-		ser::Writeable::write(&gen.header, &mut header_parts).unwrap();
-		let (pre, post) = header_parts.parts_as_hex_strings();
-		println!("pre, post: {}, {}", pre, post);*/
-
-		// Start the miner working
-		let miner = plugin_miner.get_consumable();
-		let job_handle = miner.notify(1, &pre, &post, 0).unwrap();
-
-		let mut sol = None;
-
-		while head.hash() == *latest_hash && time::get_time().sec < deadline {
-			if let Some(s) = job_handle.get_solution() {
-				let proof = Proof::new(s.solution_nonces.to_vec());
-				let proof_diff = proof.clone().to_difficulty();
-				trace!(LOGGER, "Found cuckoo solution for nonce {} of difficulty {} (difficulty target {})",
-					s.get_nonce_as_u64(),
-					proof_diff.into_num(),
-					difficulty.into_num());
-				if proof_diff >= b.header.difficulty {
-					sol = Some(proof);
-					b.header.nonce = s.get_nonce_as_u64();
-					break;
-				}
-			}
-			if time::get_time().sec > next_stat_output {
-				let mut sps_total = 0.0;
-				for i in 0..plugin_miner.loaded_plugin_count() {
-					let stats = job_handle.get_stats(i);
-					if let Ok(stat_vec) = stats {
-						for s in stat_vec {
-							if s.in_use == 0 {continue;}
-							let last_solution_time_secs = s.last_solution_time as f64 / 1000000000.0;
-							let last_hashes_per_sec = 1.0 / last_solution_time_secs;
-							let status = match s.has_errored {
-								0 => "OK",
-								_ => "ERRORED",
-							};
-							debug!(
-								LOGGER,
-								"Mining: Plugin {} - Device {} ({}) Status: {} : Last Graph time: {}s; \
-								 Graphs per second: {:.*} - Total Attempts: {}",
-								i,
-								s.device_id,
-								s.device_name,
-								status,
-								last_solution_time_secs,
-								3,
-								last_hashes_per_sec,
-								s.iterations_completed
-							);
-							if last_hashes_per_sec.is_finite() {
-								sps_total += last_hashes_per_sec;
-							}
-						}
-					}
-				}
-				info!(LOGGER, "Mining at {} graphs per second", sps_total);
-				next_stat_output = time::get_time().sec + stat_output_interval;
-			}
-			// avoid busy wait
-			thread::sleep(Duration::from_millis(100));
-		}
-		if sol == None {
-			debug!(
-				LOGGER,
-				"(Server ID: {}) No solution found after {} seconds, continuing...",
-				self.debug_output_id,
-				attempt_time_per_block
-			);
-		}
-
-		job_handle.stop_jobs();
-		sol
-	}
-
-	/// The inner part of mining loop for cuckoo miner sync mode
-	pub fn inner_loop_sync_plugin(
-		&self,
-		plugin_miner: &mut PluginMiner,
-		b: &mut Block,
-		cuckoo_size: u32,
-		head: &BlockHeader,
-		attempt_time_per_block: u32,
-		latest_hash: &mut Hash,
-	) -> Option<Proof> {
-		// look for a pow for at most attempt_time_per_block sec on the same block (to
-  // give a chance to new
-  // transactions) and as long as the head hasn't changed
-		let deadline = time::get_time().sec + attempt_time_per_block as i64;
-		let stat_check_interval = 3;
-		let mut next_stat_check = time::get_time().sec + stat_check_interval;
-
-		debug!(
-			LOGGER,
-			"(Server ID: {}) Mining at Cuckoo{} for {} secs (will wait for last solution) \
-			 on block {} at difficulty {}.",
-			self.debug_output_id,
-			cuckoo_size,
-			attempt_time_per_block,
-			latest_hash,
-			b.header.difficulty
-		);
-		let mut iter_count = 0;
-
-		if self.config.slow_down_in_millis != None && self.config.slow_down_in_millis.unwrap() > 0 {
-			debug!(
-				LOGGER,
-				"(Server ID: {}) Artificially slowing down loop by {}ms per iteration.",
-				self.debug_output_id,
-				self.config.slow_down_in_millis.unwrap()
-			);
-		}
-
-		let mut sol = None;
-		while head.hash() == *latest_hash && time::get_time().sec < deadline {
-			let pow_hash = b.hash();
-			if let Ok(proof) = plugin_miner.mine(&pow_hash[..]) {
-				let proof_diff = proof.clone().to_difficulty();
-				trace!(LOGGER, "Found cuckoo solution for nonce {} of difficulty {} (difficulty target {})",
-					b.header.nonce,
-					proof_diff.into_num(),
-					b.header.difficulty.into_num());
-				if proof_diff >= b.header.difficulty {
-					sol = Some(proof);
-					break;
-				}
-			}
-
-			if time::get_time().sec >= next_stat_check {
-				let stats_vec = plugin_miner.get_stats(0).unwrap();
-				for s in stats_vec.into_iter() {
-					if s.in_use == 0 {continue;}
-					let last_solution_time_secs = s.last_solution_time as f64 / 1000000000.0;
-					let last_hashes_per_sec = 1.0 / last_solution_time_secs;
-					let status = match s.has_errored {
-						0 => "OK",
-						_ => "ERRORED",
-					};
-					debug!(
-						LOGGER,
-						"Plugin 0 - Device {} ({}) Status: {} - Last Graph time: {}; Graphs per second: {:.*}",
-						s.device_id,
-						s.device_name,
-						status,
-						last_solution_time_secs,
-						3,
-						last_hashes_per_sec
-					);
-					info!(LOGGER, "Mining at {} graphs per second", last_hashes_per_sec);
-				}
-				next_stat_check = time::get_time().sec + stat_check_interval;
-			}
-
-			b.header.nonce += 1;
-			*latest_hash = self.chain.head().unwrap().last_block_h;
-			iter_count += 1;
-
-			// Artificial slow down
-			if self.config.slow_down_in_millis != None
-				&& self.config.slow_down_in_millis.unwrap() > 0
-			{
-				thread::sleep(Duration::from_millis(
-					self.config.slow_down_in_millis.unwrap(),
-				));
-			}
-		}
-
-		if sol == None {
-			debug!(
-				LOGGER,
-				"(Server ID: {}) No solution found after {} iterations, continuing...",
-				self.debug_output_id,
-				iter_count
-			)
+	/// File holding the account keys the coinbase outputs are built with,
+	/// if any, to report the blocks accepted to with `coinbase_accepted`.
+	pub fn coinbase_key_path(&self) -> Option<String> {
+		if self.config.burn_reward {
+			None
+		} else {
+			self.config.coinbase_key_path.clone()
 		}
-
-		sol
 	}
 
-	/// The inner part of mining loop for the internal miner
-	pub fn inner_loop_sync_internal<T: MiningWorker>(
-		&self,
-		miner: &mut T,
-		b: &mut Block,
-		cuckoo_size: u32,
-		head: &BlockHeader,
-		attempt_time_per_block: u32,
-		latest_hash: &mut Hash,
-	) -> Option<Proof> {
-		// look for a pow for at most 2 sec on the same block (to give a chance to new
-		// transactions) and as long as the head hasn't changed
-		let deadline = time::get_time().sec + attempt_time_per_block as i64;
-
-		debug!(
-			LOGGER,
-			"(Server ID: {}) Mining at Cuckoo{} for at most {} secs on block {} at difficulty {}.",
-			self.debug_output_id,
-			cuckoo_size,
-			attempt_time_per_block,
-			latest_hash,
-			b.header.difficulty
-		);
-		let mut iter_count = 0;
-
-		if self.config.slow_down_in_millis != None && self.config.slow_down_in_millis.unwrap() > 0 {
-			debug!(
-				LOGGER,
-				"(Server ID: {}) Artificially slowing down loop by {}ms per iteration.",
-				self.debug_output_id,
-				self.config.slow_down_in_millis.unwrap()
-			);
-		}
-
-		let mut sol = None;
-		while head.hash() == *latest_hash && time::get_time().sec < deadline {
-			let pow_hash = b.hash();
-			if let Ok(proof) = miner.mine(&pow_hash[..]) {
-				let proof_diff = proof.clone().to_difficulty();
-				if proof_diff >= b.header.difficulty {
-					sol = Some(proof);
-					break;
-				}
-			}
-
-			b.header.nonce += 1;
-			*latest_hash = self.chain.head().unwrap().last_block_h;
-			iter_count += 1;
-
-			// Artificial slow down
-			if self.config.slow_down_in_millis != None
-				&& self.config.slow_down_in_millis.unwrap() > 0
-			{
-				thread::sleep(Duration::from_millis(
-					self.config.slow_down_in_millis.unwrap(),
-				));
-			}
-		}
-
-		if sol == None {
-			debug!(
-				LOGGER,
-				"(Server ID: {}) No solution found after {} iterations, continuing...",
-				self.debug_output_id,
-				iter_count
-			)
-		}
-
-		sol
+	/// Keeping this optional so setting in a separate function
+	/// instead of in the new function
+	pub fn set_debug_output_id(&mut self, debug_output_id: String) {
+		self.debug_output_id = debug_output_id;
 	}
 
 	/// Starts the mining loop, building a new block on top of the existing
-	/// chain anytime required and looking for PoW solution.
-	pub fn run_loop(&self, miner_config: MinerConfig, cuckoo_size: u32, proof_size: usize) {
+	/// chain anytime required and handing it to the configured mining plugin,
+	/// running on its own thread, to look for a PoW solution. Returns once the
+	/// server stops.
+	pub fn run_loop(&self) {
 		info!(
 			LOGGER,
 			"(Server ID: {}) Starting miner loop.",
 			self.debug_output_id
 		);
-		let mut plugin_miner = None;
-		let mut miner = None;
-		if miner_config.use_cuckoo_miner {
-			plugin_miner = Some(PluginMiner::new(
-				consensus::EASINESS,
-				cuckoo_size,
-				proof_size,
-			));
-			plugin_miner.as_mut().unwrap().init(miner_config.clone());
-		} else {
-			miner = Some(cuckoo::Miner::new(
-				consensus::EASINESS,
-				cuckoo_size,
-				proof_size,
-			));
-		}
+		let jobs = Arc::new(MinerJobs {
+			chain: self.chain.clone(),
+			stats: self.stats.clone(),
+			current: RwLock::new(None),
+			block_found: AtomicBool::new(false),
+			coinbase_key_path: self.coinbase_key_path(),
+			stop_state: self.stop_state.clone(),
+			debug_output_id: self.debug_output_id.clone(),
+		});
+
+		// plugins may not be sendable, loading it on the thread it runs on
+		let plugin_jobs = jobs.clone();
+		let config = self.config.clone();
+		let _ = thread::Builder::new()
+			.name("mining_plugin".to_string())
+			.spawn(move || {
+				let mut plugin = mining::load_plugin(&config);
+				info!(LOGGER, "Mining with plugin {}", plugin.name());
+				{
+					let mut stats = plugin_jobs.stats.write().unwrap();
+					stats.is_mining = true;
+					stats.plugin = Some(plugin.name());
+				}
+				plugin.run(plugin_jobs.clone());
+				plugin_jobs.stats.write().unwrap().is_mining = false;
+			});
 
 		// to prevent the wallet from generating a new HD key derivation for each
 		// iteration, we keep the returned derivation to provide it back when
 		// nothing has changed
 		let mut key_id = None;
+		let mut latest_hash = None;
+		let mut deadline = 0;
+		let mut job_id = 0;
+
+		while !self.stop_state.load(Ordering::Relaxed) {
+			if jobs.block_found.swap(false, Ordering::Relaxed) {
+				debug!(LOGGER, "resetting key_id in miner to None");
+				key_id = None;
+			}
+
+			// look for a pow for at most attempt_time_per_block sec on the same block
+			// (to give a chance to new transactions) and as long as the head hasn't
+			// changed
+			let head = self.chain.head_header().unwrap();
+			if Some(head.hash()) == latest_hash && jobs.has_job()
+				&& time::get_time().sec < deadline
+			{
+				thread::sleep(Duration::from_millis(100));
+				continue;
+			}
 
-		loop {
 			debug!(LOGGER, "in miner loop...");
 			trace!(LOGGER, "key_id: {:?}", key_id);
 
 			// get the latest chain state and build a block on top of it
-			let head = self.chain.head_header().unwrap();
-			let mut latest_hash = self.chain.head().unwrap().last_block_h;
-
 			let mut result = self.build_block(&head, key_id.clone());
 			while let Err(e) = result {
 				match e {
@@ -485,83 +301,39 @@ impl Miner {
 				thread::sleep(Duration::from_millis(100));
 				result = self.build_block(&head, key_id.clone());
 			}
+			let (b, block_fees) = result.unwrap();
 
-			let (mut b, block_fees) = result.unwrap();
-
-			let mut sol = None;
-			let mut use_async = false;
-			if let Some(c) = self.config.cuckoo_miner_async_mode {
-				if c {
-					use_async = true;
-				}
-			}
-			if let Some(mut p) = plugin_miner.as_mut() {
-				if use_async {
-					sol = self.inner_loop_async(
-						&mut p,
-						b.header.difficulty.clone(),
-						&mut b,
-						cuckoo_size,
-						&head,
-						&latest_hash,
-						miner_config.attempt_time_per_block,
-					);
-				} else {
-					sol = self.inner_loop_sync_plugin(
-						p,
-						&mut b,
-						cuckoo_size,
-						&head,
-						miner_config.attempt_time_per_block,
-						&mut latest_hash,
-					);
-				}
-			}
-			if let Some(m) = miner.as_mut() {
-				sol = self.inner_loop_sync_internal(
-					m,
-					&mut b,
-					cuckoo_size,
-					&head,
-					miner_config.attempt_time_per_block,
-					&mut latest_hash,
-				);
-			}
+			debug!(
+				LOGGER,
+				"setting pubkey in miner to pubkey from block_fees - {:?}",
+				block_fees
+			);
+			key_id = block_fees.key_id();
 
-			// we found a solution, push our block through the chain processing pipeline
-			if let Some(proof) = sol {
-				info!(
-					LOGGER,
-					"(Server ID: {}) Found valid proof of work, adding block {}.",
-					self.debug_output_id,
-					b.hash()
-				);
-				b.header.pow = proof;
-				let res = self.chain.process_block(b, chain::MINE);
-				if let Err(e) = res {
-					error!(
-						LOGGER,
-						"(Server ID: {}) Error validating mined block: {:?}",
-						self.debug_output_id,
-						e
-					);
-				}
-				debug!(LOGGER, "resetting key_id in miner to None");
-				key_id = None;
-			} else {
-				debug!(
-					LOGGER,
-					"setting pubkey in miner to pubkey from block_fees - {:?}",
-					block_fees
-				);
-				key_id = block_fees.key_id();
-			}
+			debug!(
+				LOGGER,
+				"(Server ID: {}) Mining at Cuckoo{} for at most {} secs at height {} and difficulty {}.",
+				self.debug_output_id,
+				global::sizeshift(),
+				self.config.attempt_time_per_block,
+				b.header.height,
+				b.header.difficulty
+			);
+			job_id += 1;
+			jobs.set_job(job_id, b);
+			latest_hash = Some(head.hash());
+			deadline = time::get_time().sec + self.config.attempt_time_per_block as i64;
 		}
+		info!(
+			LOGGER,
+			"(Server ID: {}) Miner stopped.",
+			self.debug_output_id
+		);
 	}
 
 	/// Builds a new block with the chain head as previous and eligible
 	/// transactions from the pool.
-	fn build_block(
+	pub fn build_block(
 		&self,
 		head: &core::BlockHeader,
 		key_id: Option<Identifier>,
@@ -578,15 +350,18 @@ impl Miner {
 		let diff_iter = self.chain.difficulty_iter();
 		let difficulty = consensus::next_difficulty(diff_iter).unwrap();
 
-		// extract current transaction from the pool
-		let txs_box = self.tx_pool
-			.read()
-			.unwrap()
-			.prepare_mineable_transactions(MAX_TX);
+		// extract current transaction from the pool, the ones spending each
+		// other's outputs cut-through in a single transaction
+		let txs_box = pool::aggregate_dependent(
+			self.tx_pool
+				.read()
+				.unwrap()
+				.prepare_mineable_transactions(MAX_TX_WEIGHT),
+		);
 		let txs: Vec<&Transaction> = txs_box.iter().map(|tx| tx.as_ref()).collect();
 
 		// build the coinbase and the block itself
-		let fees = txs.iter().map(|tx| tx.fee).sum();
+		let fees = txs.iter().map(|tx| tx.fee()).sum();
 		let height = head.height + 1;
 		let block_fees = BlockFees {
 			fees,
@@ -653,19 +428,58 @@ impl Miner {
 		Ok((out, kernel, block_fees))
 	}
 
+	// Builds the coinbase with the account keys of the configured file, all
+	// the coinbases paying to the same key until a block built with it is
+	// accepted, so rebuilt templates don't use up key indexes.
+	fn account_coinbase(
+		&self,
+		key_path: &str,
+		block_fees: BlockFees,
+	) -> Result<(core::Output, core::TxKernel, BlockFees), Error> {
+		let keychain = account_keychain(key_path)?;
+		let index = coinbase_index(key_path, block_fees.height)?;
+		let key_id = keychain
+			.derive_key_id(index)
+			.map_err(|e| Error::Coinbase(format!("{:?}", e)))?;
+
+		let (out, kernel) = core::Block::reward_output(
+			&keychain,
+			&key_id,
+			block_fees.fees,
+			block_fees.height,
+		).map_err(|e| Error::Coinbase(format!("{:?}", e)))?;
+		let block_fees = BlockFees {
+			key_id: Some(key_id),
+			..block_fees
+		};
+		debug!(LOGGER, "get_coinbase: {:?} from account keys", block_fees);
+		Ok((out, kernel, block_fees))
+	}
+
 	fn get_coinbase(
 		&self,
 		block_fees: BlockFees,
 	) -> Result<(core::Output, core::TxKernel, BlockFees), Error> {
 		if self.config.burn_reward {
 			self.burn_reward(block_fees)
+		} else if let Some(ref key_path) = self.config.coinbase_key_path {
+			self.account_coinbase(key_path, block_fees)
 		} else {
 			let url = format!(
 				"{}/v1/receive/coinbase",
 				self.config.wallet_listener_url.as_str()
 			);
 
-			let res = wallet::client::create_coinbase(&url, &block_fees)?;
+			let secret = match self.config.wallet_listener_secret_path {
+				Some(ref path) => Some(api::read_api_secret(path)?),
+				None => None,
+			};
+			let res = match self.coinbase_timeout {
+				Some(timeout) => {
+					wallet::client::create_coinbase_with_timeout(&url, &block_fees, secret, timeout)?
+				}
+				None => wallet::client::create_coinbase(&url, &block_fees, secret)?,
+			};
 
 			let out_bin = util::from_hex(res.output).unwrap();
 			let kern_bin = util::from_hex(res.kernel).unwrap();
@@ -684,3 +498,138 @@ impl Miner {
 		}
 	}
 }
+
+lazy_static! {
+	// Held while reading or moving the coinbase key index, all the miners of
+	// the node sharing the same index file
+	static ref COINBASE_INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Moves the coinbase account keys of the provided file to their next index
+/// once a block paying its coinbase to the current one got accepted. All the
+/// blocks built until then, whatever their height, share that key.
+pub fn coinbase_accepted(key_path: &str, b: &Block) {
+	let res = account_keychain(key_path).and_then(|keychain| {
+		let index = coinbase_index(key_path, b.header.height)?;
+		let key_id = keychain
+			.derive_key_id(index)
+			.map_err(|e| Error::Coinbase(format!("{:?}", e)))?;
+		let commit = keychain
+			.commit(consensus::reward(b.total_fees()), &key_id)
+			.map_err(|e| Error::Coinbase(format!("{:?}", e)))?;
+		if b.outputs.iter().any(|out| out.commitment() == commit) {
+			advance_coinbase_index(key_path, index)?;
+		}
+		Ok(())
+	});
+	if let Err(e) = res {
+		error!(
+			LOGGER,
+			"Could not move to the next coinbase key after block {}: {:?}",
+			b.hash(),
+			e
+		);
+	}
+}
+
+fn account_keychain(key_path: &str) -> Result<Keychain, Error> {
+	let mut hex = String::new();
+	File::open(key_path)
+		.and_then(|mut f| f.read_to_string(&mut hex))
+		.map_err(|e| Error::Coinbase(format!("reading {}: {}", key_path, e)))?;
+	let seed = util::from_hex(hex.trim().to_string())
+		.map_err(|_| Error::Coinbase(format!("invalid keys in {}", key_path)))?;
+	Keychain::from_seed(&seed)
+		.map_err(|e| Error::Coinbase(format!("invalid keys in {}: {:?}", key_path, e)))
+}
+
+// Index of the coinbase account key the blocks we build pay to until one of
+// them is accepted, tracked in a file next to the keys file so restarts keep
+// it. Without index file yet, starts past the provided height as coinbases
+// used to take the key at the index of their height.
+fn coinbase_index(key_path: &str, height: u64) -> Result<u32, Error> {
+	let _lock = COINBASE_INDEX_LOCK.lock().unwrap();
+	let index_path = format!("{}.index", key_path);
+	match read_index(&index_path)? {
+		Some(index) => Ok(index),
+		None => {
+			let index = (height as u32).saturating_add(1);
+			write_index(&index_path, index)?;
+			Ok(index)
+		}
+	}
+}
+
+// Moves past the provided index, unless another accepted block already did.
+fn advance_coinbase_index(key_path: &str, index: u32) -> Result<(), Error> {
+	let _lock = COINBASE_INDEX_LOCK.lock().unwrap();
+	let index_path = format!("{}.index", key_path);
+	if read_index(&index_path)? != Some(index) {
+		return Ok(());
+	}
+	let next = index
+		.checked_add(1)
+		.ok_or(Error::Coinbase("no coinbase key index left".to_string()))?;
+	write_index(&index_path, next)
+}
+
+fn read_index(index_path: &str) -> Result<Option<u32>, Error> {
+	match File::open(index_path) {
+		Ok(mut f) => {
+			let mut content = String::new();
+			f.read_to_string(&mut content)
+				.map_err(|e| Error::Coinbase(format!("{}: {}", index_path, e)))?;
+			let index = content
+				.trim()
+				.parse::<u32>()
+				.map_err(|_| Error::Coinbase(format!("invalid index in {}", index_path)))?;
+			Ok(Some(index))
+		}
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(Error::Coinbase(format!("{}: {}", index_path, e))),
+	}
+}
+
+fn write_index(index_path: &str, index: u32) -> Result<(), Error> {
+	// replaced at once, a partial write could get keys reused
+	let tmp_path = format!("{}.tmp", index_path);
+	File::create(&tmp_path)
+		.and_then(|mut f| {
+			f.write_all(index.to_string().as_bytes())?;
+			f.sync_all()
+		})
+		.and_then(|_| fs::rename(&tmp_path, index_path))
+		.map_err(|e| Error::Coinbase(format!("{}: {}", index_path, e)))
+}
+
+#[cfg(test)]
+mod test {
+	use std::env;
+	use std::fs;
+
+	use super::{advance_coinbase_index, coinbase_index};
+
+	#[test]
+	fn coinbase_indexes() {
+		let dir = env::temp_dir().join("grin_coinbase_index");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let key_path = dir.join("mining.key");
+		let key_path = key_path.to_str().unwrap();
+
+		// past the height based indexes, then the same one for every block
+		// built until one is accepted
+		assert_eq!(coinbase_index(key_path, 10).unwrap(), 11);
+		assert_eq!(coinbase_index(key_path, 10).unwrap(), 11);
+		assert_eq!(coinbase_index(key_path, 12).unwrap(), 11);
+
+		advance_coinbase_index(key_path, 11).unwrap();
+		assert_eq!(coinbase_index(key_path, 12).unwrap(), 12);
+
+		// a second block accepted with the same key doesn't skip one
+		advance_coinbase_index(key_path, 11).unwrap();
+		assert_eq!(coinbase_index(key_path, 13).unwrap(), 12);
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,202 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Automatic assignment of the API, p2p and wallet listener ports from a
+//! configured range. Mostly useful for simulations and local multi-node
+//! setups where dozens of nodes run on the same host. The assigned ports are
+//! written to a discovery file so other processes can find each node.
+
+use std::fs::{self, File};
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+
+use serde_json;
+
+use types::{Error, PortRange, ServerConfig};
+use util::LOGGER;
+
+/// Name of the discovery file, under the db root, when none is configured
+const DISCOVERY_FILE: &'static str = "ports.json";
+
+/// Ports assigned to a node instance, as written in its discovery file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignedPorts {
+	/// Port of the REST API
+	pub api: u16,
+	/// Port of the p2p server
+	pub p2p: u16,
+	/// Port of the wallet listener mining rewards get sent to
+	pub wallet: u16,
+}
+
+/// Listeners bound on the ports picked for the API and the p2p server, to be
+/// handed over to them so no other process can take the ports in between.
+pub struct PortListeners {
+	/// Listener for the REST API
+	pub api: TcpListener,
+	/// Listener for the p2p server
+	pub p2p: TcpListener,
+}
+
+/// If a port range is configured, picks free ports for the API, the p2p
+/// server and the wallet listener, updates the configuration accordingly and
+/// writes the assignment to the discovery file. The wallet listener runs in
+/// its own process so its port is only checked, the API and p2p ports are
+/// returned bound.
+pub fn assign_ports(config: &mut ServerConfig) -> Result<Option<PortListeners>, Error> {
+	let range = match config.port_range.clone() {
+		Some(range) => range,
+		None => return Ok(None),
+	};
+
+	let api_host = host_of(&config.api_http_addr);
+	let p2p_host = config.p2p_config.host;
+	let (api, api_listener) = next_free_port(&range, &[], |port| {
+		TcpListener::bind((&api_host[..], port))
+	})?;
+	let (p2p, p2p_listener) = next_free_port(&range, &[api], |port| {
+		TcpListener::bind((p2p_host, port))
+	})?;
+	let (wallet, _) = next_free_port(&range, &[api, p2p], |port| {
+		TcpListener::bind(("0.0.0.0", port))
+	})?;
+	let ports = AssignedPorts { api, p2p, wallet };
+
+	config.api_http_addr = with_port(&config.api_http_addr, ports.api);
+	config.p2p_config.port = ports.p2p;
+	if let Some(ref mut mining_config) = config.mining_config {
+		mining_config.wallet_listener_url =
+			with_port(&mining_config.wallet_listener_url, ports.wallet);
+	}
+
+	let path = discovery_file_path(config);
+	write_discovery_file(&path, &ports)?;
+	info!(
+		LOGGER,
+		"Assigned ports api {}, p2p {}, wallet {}, written to {}",
+		ports.api,
+		ports.p2p,
+		ports.wallet,
+		path,
+	);
+	Ok(Some(PortListeners {
+		api: api_listener,
+		p2p: p2p_listener,
+	}))
+}
+
+/// Reads the ports a node has been assigned from its discovery file.
+pub fn read_discovery_file(path: &str) -> Result<AssignedPorts, Error> {
+	let file = File::open(path).map_err(|e| Error::Ports(format!("{}: {}", path, e)))?;
+	serde_json::from_reader(file).map_err(|e| Error::Ports(format!("{}: {}", path, e)))
+}
+
+// First port of the range, not already taken, we can bind to, along with its
+// listener.
+fn next_free_port<F>(range: &PortRange, taken: &[u16], bind: F) -> Result<(u16, TcpListener), Error>
+where
+	F: Fn(u16) -> io::Result<TcpListener>,
+{
+	let mut port = range.start;
+	while port <= range.end {
+		if !taken.contains(&port) {
+			if let Ok(l) = bind(port) {
+				return Ok((port, l));
+			}
+		}
+		port = match port.checked_add(1) {
+			Some(p) => p,
+			None => break,
+		};
+	}
+	Err(Error::Ports(format!(
+		"no free port left in {}-{}",
+		range.start,
+		range.end
+	)))
+}
+
+fn discovery_file_path(config: &ServerConfig) -> String {
+	match config.discovery_file {
+		Some(ref f) => f.clone(),
+		None => format!("{}/{}", config.db_root, DISCOVERY_FILE),
+	}
+}
+
+fn write_discovery_file(path: &str, ports: &AssignedPorts) -> Result<(), Error> {
+	if let Some(parent) = Path::new(path).parent() {
+		fs::create_dir_all(parent).map_err(|e| Error::Ports(format!("{}: {}", path, e)))?;
+	}
+	let file = File::create(path).map_err(|e| Error::Ports(format!("{}: {}", path, e)))?;
+	serde_json::to_writer_pretty(file, ports)
+		.map_err(|e| Error::Ports(format!("{}: {}", path, e)))
+}
+
+// Host part of an address (i.e. "127.0.0.1" for "127.0.0.1:13413").
+fn host_of(addr: &str) -> String {
+	match addr.rfind(':') {
+		Some(idx) => addr[..idx].to_string(),
+		None => addr.to_string(),
+	}
+}
+
+// Replaces the port of an address or URL (i.e. "127.0.0.1:13413" or
+// "http://127.0.0.1:13415"), appending it if there's none.
+fn with_port(addr: &str, port: u16) -> String {
+	if let Some(idx) = addr.rfind(':') {
+		let tail = &addr[idx + 1..];
+		if !tail.is_empty() && tail.chars().all(|c| c.is_digit(10)) {
+			return format!("{}:{}", &addr[..idx], port);
+		}
+	}
+	format!("{}:{}", addr, port)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn replace_port() {
+		assert_eq!(with_port("127.0.0.1:13413", 20000), "127.0.0.1:20000");
+		assert_eq!(with_port("http://127.0.0.1:13415", 20001), "http://127.0.0.1:20001");
+		assert_eq!(with_port("http://127.0.0.1", 20002), "http://127.0.0.1:20002");
+	}
+
+	#[test]
+	fn free_ports() {
+		// a range at the very end of the port numbers
+		let range = PortRange {
+			start: 65534,
+			end: 65535,
+		};
+		let (port, _l) = next_free_port(&range, &[65534], |port| {
+			if port == 65535 {
+				TcpListener::bind(("127.0.0.1", 0))
+			} else {
+				Err(io::Error::new(io::ErrorKind::Other, "taken"))
+			}
+		}).unwrap();
+		assert_eq!(port, 65535);
+		assert!(next_free_port(&range, &[65534, 65535], |_| TcpListener::bind(("127.0.0.1", 0))).is_err());
+		assert!(
+			next_free_port(&range, &[], |_| Err(io::Error::new(io::ErrorKind::Other, "taken")))
+				.is_err()
+		);
+
+		assert_eq!(host_of("127.0.0.1:13413"), "127.0.0.1");
+		assert_eq!(host_of("localhost"), "localhost");
+	}
+}
@@ -0,0 +1,77 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reloads the settings of the configuration file that can safely change
+//! while the server runs: the p2p peer lists, peer limits and broadcast
+//! fanout, and the log levels. Everything else requires a restart.
+
+use std::fs::File;
+use std::io::Read;
+
+use toml;
+
+use api;
+use p2p;
+use util::{self, LoggingConfig, LOGGER};
+
+// the parts of the configuration file we reload, the rest being ignored
+#[derive(Deserialize)]
+struct ReloadedMembers {
+	server: ReloadedServer,
+	logging: Option<LoggingConfig>,
+}
+
+#[derive(Deserialize)]
+struct ReloadedServer {
+	p2p_config: p2p::P2PConfig,
+}
+
+/// Reloads the configuration file the server was started with, applying the
+/// new settings to the running peers and logger.
+pub struct ConfigReload {
+	config_file_path: Option<String>,
+	peers: p2p::Peers,
+}
+
+impl ConfigReload {
+	/// Reloader of the provided configuration file, if any
+	pub fn new(config_file_path: Option<String>, peers: p2p::Peers) -> ConfigReload {
+		ConfigReload {
+			config_file_path: config_file_path,
+			peers: peers,
+		}
+	}
+}
+
+impl api::ConfigReloader for ConfigReload {
+	fn reload(&self) -> Result<(), String> {
+		let path = match self.config_file_path {
+			Some(ref path) => path,
+			None => return Err("the server wasn't started from a configuration file".to_string()),
+		};
+		let mut contents = String::new();
+		File::open(path)
+			.and_then(|mut file| file.read_to_string(&mut contents))
+			.map_err(|e| format!("could not read {}: {}", path, e))?;
+		let members: ReloadedMembers =
+			toml::from_str(&contents).map_err(|e| format!("could not parse {}: {}", path, e))?;
+
+		self.peers.update_config(members.server.p2p_config);
+		if let Some(logging) = members.logging {
+			util::update_log_levels(&logging);
+		}
+		warn!(LOGGER, "Reloaded the configuration from {}.", path);
+		Ok(())
+	}
+}
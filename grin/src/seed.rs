@@ -18,7 +18,7 @@
 
 use std::net::SocketAddr;
 use std::str::{self, FromStr};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time;
 
 use cpupool;
@@ -29,17 +29,25 @@ use tokio_core::reactor;
 use tokio_timer::Timer;
 use time::now_utc;
 
+use dial::{DialPriority, DialQueue};
 use p2p;
 use util::LOGGER;
 
-const PEER_MAX_COUNT: u32 = 25;
-const PEER_PREFERRED_COUNT: u32 = 8;
 const SEEDS_URL: &'static str = "http://grin-tech.org/seeds.txt";
 
+/// Seconds between checks of our peer count, to look for more peers as soon
+/// as we're below the minimum
+const DISCOVERY_CHECK_SECS: u64 = 3;
+
+/// Seconds between requests for more peers to our connected peers while
+/// we're below the minimum peer count
+const DISCOVERY_REQUEST_SECS: u64 = 15;
+
 pub struct Seeder {
 	peers: p2p::Peers,
 	p2p_server: Arc<p2p::Server>,
 	capabilities: p2p::Capabilities,
+	dials: Arc<Mutex<DialQueue>>,
 }
 
 impl Seeder {
@@ -48,10 +56,12 @@ impl Seeder {
 		p2p_server: Arc<p2p::Server>,
 		peers: p2p::Peers,
 	) -> Seeder {
+		let dial_concurrency = peers.config().dial_concurrency as usize;
 		Seeder {
 			peers: peers,
 			p2p_server: p2p_server,
 			capabilities: capabilities,
+			dials: Arc::new(Mutex::new(DialQueue::new(dial_concurrency))),
 		}
 	}
 
@@ -67,7 +77,8 @@ impl Seeder {
 
 		// check seeds and start monitoring connections
 		let seeder = self.connect_to_seeds(tx.clone(), seed_list)
-			.join(self.monitor_peers(tx.clone()));
+			.join(self.monitor_peers(tx.clone()))
+			.join(self.discover_peers(tx.clone()));
 
 		h.spawn(seeder.map(|_| ()).map_err(|e| {
 			error!(LOGGER, "Seeding or peer monitoring error: {}", e);
@@ -80,14 +91,12 @@ impl Seeder {
 		tx: mpsc::UnboundedSender<SocketAddr>,
 	) -> Box<Future<Item = (), Error = String>> {
 		let peers = self.peers.clone();
-		let capabilities = self.capabilities.clone();
 
 		// Unban peer after 3 hours
 		let ban_windows: i64 = 10800;
 
-		// now spawn a new future to regularly check if we need to acquire more peers
-		// and if so, gets them from db and unban the banned peers after the ban is
-		// expired
+		// now spawn a new future to regularly clean up our peers and unban the
+		// banned peers after the ban is expired
 		let mon_loop = Timer::default()
 			.interval(time::Duration::from_secs(30))
 			.for_each(move |_| {
@@ -103,9 +112,17 @@ impl Seeder {
 				let mut healthy_count = 0;
 				let mut banned_count = 0;
 				let mut defunct_count = 0;
+				let mut preferred_count = 0;
 				for x in peers.all_peers() {
 					if x.flags == p2p::State::Healthy {
 						healthy_count += 1
+					} else if x.flags == p2p::State::Preferred {
+						preferred_count += 1;
+						// always try to stay connected to preferred peers
+						if !peers.is_known(&x.addr) {
+							debug!(LOGGER, "monitor_peers: queue preferred peer {}", x.addr);
+							tx.unbounded_send(x.addr).unwrap();
+						}
 					} else if x.flags == p2p::State::Banned {
 						let interval = now_utc().to_timespec().sec - x.last_banned;
 						if interval >= ban_windows {
@@ -125,39 +142,121 @@ impl Seeder {
 
 				debug!(
 					LOGGER,
-					"monitor_peers: all {} = {} healthy + {} banned + {} defunct",
+					"monitor_peers: all {} = {} healthy + {} preferred + {} banned + {} defunct",
 					total_count,
 					healthy_count,
+					preferred_count,
 					banned_count,
 					defunct_count,
 				);
 
-				// maintenance step first, clean up p2p server peers
-				{
-					peers.clean_peers(PEER_MAX_COUNT as usize);
+				// maintenance step, clean up p2p server peers
+				let config = peers.config();
+				peers.clean_peers(config.peer_max_count as usize);
+
+				// keep enough peers able to provide the history we may need,
+				// pruned peers can't
+				let targets = [
+					(p2p::Capabilities::FULL_HIST, config.min_full_hist_peers),
+					(p2p::Capabilities::UTXO_HIST, config.min_utxo_hist_peers),
+				];
+				let counts = targets
+					.iter()
+					.map(|&(capab, min)| (capab, min, peers.capability_count(capab)))
+					.collect::<Vec<_>>();
+				// peers with a capability we don't have more of than needed stay
+				let keep = counts
+					.iter()
+					.filter(|&&(_, min, count)| count <= min)
+					.fold(p2p::Capabilities::UNKNOWN, |keep, &(capab, _, _)| keep | capab);
+				for &(capab, min, count) in &counts {
+					if count >= min {
+						continue;
+					}
+					let candidates = peers
+						.find_peers(p2p::State::Healthy, capab, ((min - count) * 4) as usize)
+						.into_iter()
+						.filter(|p| !peers.is_known(&p.addr))
+						.collect::<Vec<_>>();
+					if candidates.is_empty() {
+						debug!(LOGGER, "monitor_peers: no other known peer with {:?}", capab);
+						continue;
+					}
+					debug!(
+						LOGGER,
+						"monitor_peers: {} peers with {:?}, below {}, dialing more", count, capab, min
+					);
+					if peers.peer_count() >= config.peer_max_count {
+						peers.make_room(keep);
+					}
+					for p in candidates {
+						tx.unbounded_send(p.addr).unwrap();
+					}
 				}
+				Ok(())
+			})
+			.map_err(|e| e.to_string());
+
+		Box::new(mon_loop)
+	}
 
-				// not enough peers, getting more from db
-				if peers.peer_count() < PEER_PREFERRED_COUNT {
-					// loop over connected peers
-					// ask them for their list of peers
+	// Checks our peer count every few seconds and, as soon as it drops below
+	// the minimum, asks our connected peers for more and dials the healthy
+	// peers we know about, including the ones we just learned about, until
+	// we're back above the minimum.
+	fn discover_peers(
+		&self,
+		tx: mpsc::UnboundedSender<SocketAddr>,
+	) -> Box<Future<Item = (), Error = String>> {
+		let peers = self.peers.clone();
+		let capabilities = self.capabilities.clone();
+		let mut last_request: Option<time::Instant> = None;
+
+		let discovery = Timer::default()
+			.interval(time::Duration::from_secs(DISCOVERY_CHECK_SECS))
+			.for_each(move |_| {
+				let min_count = peers.config().peer_min_preferred_count;
+				let count = peers.peer_count();
+				if count >= min_count {
+					if last_request.is_some() {
+						debug!(LOGGER, "discover_peers: back to {} peers", count);
+						last_request = None;
+					}
+					return Ok(());
+				}
+
+				// ask our connected peers for their peers, regularly as the
+				// addresses they send us may not all work out
+				let request_due = match last_request {
+					Some(t) => t.elapsed() >= time::Duration::from_secs(DISCOVERY_REQUEST_SECS),
+					None => true,
+				};
+				if request_due {
+					debug!(
+						LOGGER,
+						"discover_peers: {} peers, below {}, asking for more", count, min_count
+					);
 					for p in peers.connected_peers() {
 						if let Ok(p) = p.try_read() {
-							debug!(
-								LOGGER,
-								"monitor_peers: asking {} for more peers", p.info.addr,
-							);
 							let _ = p.send_peer_request(capabilities);
 						} else {
-							warn!(LOGGER, "monitor_peers: failed to get read lock on peer",);
+							warn!(LOGGER, "discover_peers: failed to get read lock on peer");
 						}
 					}
+					last_request = Some(time::Instant::now());
+				}
 
-					// find some peers from our db
-					// and queue them up for a connection attempt
-					let peers = peers.find_peers(p2p::State::Healthy, p2p::Capabilities::UNKNOWN, 100);
-					for p in peers {
-						debug!(LOGGER, "monitor_peers: queue to soon try {}", p.addr,);
+				// queue the healthy peers we know about for a connection
+				// attempt, the ones we're connected to or already dialing
+				// being skipped
+				let missing = (min_count - count) as usize;
+				let healthy = peers.find_peers(
+					p2p::State::Healthy,
+					p2p::Capabilities::UNKNOWN,
+					missing * 4,
+				);
+				for p in healthy {
+					if !peers.is_known(&p.addr) {
 						tx.unbounded_send(p.addr).unwrap();
 					}
 				}
@@ -165,7 +264,7 @@ impl Seeder {
 			})
 			.map_err(|e| e.to_string());
 
-		Box::new(mon_loop)
+		Box::new(discovery)
 	}
 
 	// Check if we have any pre-existing peer in db. If so, start with those,
@@ -212,8 +311,9 @@ impl Seeder {
 	}
 
 	/// Builds a future to continuously listen on a channel receiver for new
-	/// addresses to and initiate a connection if the max peer count isn't
-	/// exceeded. A request for more peers is also automatically sent after
+	/// addresses and queue them for a connection if the max peer count isn't
+	/// exceeded. Preferred and healthy peers get dialed first, a few at a
+	/// time. A request for more peers is also automatically sent after
 	/// connection.
 	fn listen_for_addrs(
 		&self,
@@ -223,18 +323,20 @@ impl Seeder {
 		let capab = self.capabilities;
 		let peers = self.peers.clone();
 		let p2p_server = self.p2p_server.clone();
+		let dials = self.dials.clone();
 
 		let listener = rx.for_each(move |peer_addr| {
 			debug!(LOGGER, "New peer address to connect to: {}.", peer_addr);
-			let inner_h = h.clone();
-			if peers.peer_count() < PEER_MAX_COUNT {
-				h.spawn(connect_and_req(
+			if peers.peer_count() < peers.config().peer_max_count && !peers.is_known(&peer_addr) {
+				let priority = DialPriority::of(peers.get_peer(peer_addr).ok().as_ref());
+				dials.lock().unwrap().push(peer_addr, priority);
+				dial_queued(
+					dials.clone(),
 					capab,
 					p2p_server.clone(),
 					peers.clone(),
-					inner_h,
-					peer_addr,
-				))
+					h.clone(),
+				);
 			}
 			Box::new(future::ok(()))
 		});
@@ -242,6 +344,52 @@ impl Seeder {
 	}
 }
 
+// Dials the queued addresses, as many at a time as the configured concurrency
+// allows, each completed dial starting the next one.
+fn dial_queued(
+	dials: Arc<Mutex<DialQueue>>,
+	capab: p2p::Capabilities,
+	p2p_server: Arc<p2p::Server>,
+	peers: p2p::Peers,
+	h: reactor::Handle,
+) {
+	loop {
+		let addr = {
+			let mut dials = dials.lock().unwrap();
+			dials.set_max_in_flight(peers.config().dial_concurrency as usize);
+			match dials.next() {
+				Some(addr) => {
+					debug!(
+						LOGGER,
+						"dial_queued: dialing {}, {} dials in progress, {} queued",
+						addr,
+						dials.in_flight_count(),
+						dials.queued_count(),
+					);
+					addr
+				}
+				None => break,
+			}
+		};
+		// we may have connected to it, or to enough peers, while it was queued
+		if peers.is_known(&addr) || peers.peer_count() >= peers.config().peer_max_count {
+			dials.lock().unwrap().done(&addr);
+			continue;
+		}
+
+		let dials = dials.clone();
+		let p2p_server = p2p_server.clone();
+		let peers = peers.clone();
+		let inner_h = h.clone();
+		let connect = connect_and_req(capab, p2p_server.clone(), peers.clone(), h.clone(), addr);
+		h.spawn(connect.then(move |_| {
+			dials.lock().unwrap().done(&addr);
+			dial_queued(dials, capab, p2p_server, peers, inner_h);
+			Ok(())
+		}));
+	}
+}
+
 /// Extract the list of seeds from a pre-defined text file available through
 /// http. Easy method until we have a set of DNS names we can rely on.
 pub fn web_seeds(h: reactor::Handle) -> Box<Future<Item = Vec<SocketAddr>, Error = String>> {
@@ -16,6 +16,8 @@
 //! the peer-to-peer server, the blockchain and the transaction pool) and acts
 //! as a facade.
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -30,15 +32,22 @@ use tokio_timer::Timer;
 use adapters::*;
 use api;
 use chain;
+use chaindump;
 use core::{global, genesis};
+use core::core::Block;
 use miner;
 use p2p;
 use pool;
+use ports;
+use reload::ConfigReload;
 use seed;
+use stratumserver;
 use sync;
+use templates::BlockTemplates;
 use types::*;
 use pow;
 use util::LOGGER;
+use util::memory;
 
 
 /// Grin server holding internal structures.
@@ -53,49 +62,169 @@ pub struct Server {
 	chain: Arc<chain::Chain>,
 	/// in-memory transaction pool
 	tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
-	currently_syncing: Arc<AtomicBool>,
+	/// mining stats, updated by the miner and the stratum server
+	mining_stats: Arc<RwLock<api::MiningStats>>,
+	sync_state: Arc<chain::SyncState>,
+	/// set when the server is asked to stop
+	stop_state: Arc<AtomicBool>,
 }
 
 impl Server {
-	/// Instantiates and starts a new server.
+	/// Instantiates and starts a new server, running until it gets a SIGINT
+	/// or SIGTERM and shuts down gracefully.
 	pub fn start(config: ServerConfig) -> Result<Server, Error> {
+		Server::start_with_stats(config, |_| {})
+	}
+
+	/// Instantiates and starts a new server, running the provided function on
+	/// a separate thread with a reader of the server stats, i.e. to display
+	/// them. Runs until the server gets a SIGINT or SIGTERM, or is stopped
+	/// through the stats reader.
+	pub fn start_with_stats<F>(config: ServerConfig, on_start: F) -> Result<Server, Error>
+	where
+		F: FnOnce(StatsReader) + Send + 'static,
+	{
 		let mut evtlp = reactor::Core::new().unwrap();
 
-		let mut mining_config = config.mining_config.clone();
 		let serv = Server::future(config, &evtlp.handle())?;
+
+		let stop_state = serv.stop_state.clone();
+		if let Err(e) = ctrlc::set_handler(move || {
+			stop_state.store(true, Ordering::Relaxed);
+		}) {
+			warn!(LOGGER, "Could not handle SIGINT and SIGTERM: {:?}", e);
+		}
+
+		let stats_reader = serv.stats_reader();
+		let _ = thread::Builder::new()
+			.name("stats_reader".to_string())
+			.spawn(move || on_start(stats_reader));
+		// the server may have updated its config (auto-assigned ports)
+		let mut mining_config = serv.config.mining_config.clone();
 		if mining_config.as_mut().unwrap().enable_mining {
 			serv.start_miner(mining_config.unwrap());
 		}
+		if let Some(stratum_config) = serv.config.stratum_mining_config.clone() {
+			if stratum_config.enable_stratum_server {
+				serv.start_stratum_server(stratum_config);
+			}
+		}
 
-		let forever = Timer::default()
-			.interval(time::Duration::from_secs(60))
-			.for_each(move |_| {
-				debug!(LOGGER, "event loop running");
-				Ok(())
-			})
+		let stop_state = serv.stop_state.clone();
+		let running = Timer::default()
+			.interval(time::Duration::from_secs(1))
+			.take_while(move |_| Ok(!stop_state.load(Ordering::Relaxed)))
+			.for_each(|_| Ok(()))
 			.map_err(|_| ());
+		evtlp.run(running).unwrap();
 
-		evtlp.run(forever).unwrap();
+		serv.stop();
+		// keep the event loop going a little longer for the goodbyes and
+		// everything queued before them to reach our peers
+		let flush = Timer::default()
+			.sleep(time::Duration::from_secs(1))
+			.map_err(|_| ());
+		let _ = evtlp.run(flush);
+		warn!(LOGGER, "Grin server stopped.");
 		Ok(serv)
 	}
 
+	/// Opens the chain data of a stopped server and replays it to check its
+	/// integrity, returning the first inconsistency found.
+	pub fn validate_chain(config: ServerConfig) -> Result<(), Error> {
+		if !chain::Chain::chain_exists(config.db_root.clone()) {
+			return Err(Error::Chain(chain::Error::Other(
+				format!("no chain data under {}", config.db_root),
+			)));
+		}
+		// the genesis block is only needed to start a new chain
+		let chain = chain::Chain::init(
+			config.db_root.clone(),
+			Arc::new(chain::types::NoopAdapter {}),
+			genesis::genesis_dev(),
+			pow::verify_size,
+			config.chain_config.clone(),
+		)?;
+		info!(LOGGER, "Validating the chain up to {:?}", chain.head()?);
+		chain.validate()?;
+		Ok(())
+	}
+
+	/// Writes all the blocks of the chain of a stopped server to a dump file,
+	/// returning the number of blocks written. The chain must still have all
+	/// its block bodies, as on an archive node.
+	pub fn export_chain(config: ServerConfig, path: &str) -> Result<u64, Error> {
+		if !chain::Chain::chain_exists(config.db_root.clone()) {
+			return Err(Error::Chain(chain::Error::Other(
+				format!("no chain data under {}", config.db_root),
+			)));
+		}
+		let chain = chain::Chain::init(
+			config.db_root.clone(),
+			Arc::new(chain::types::NoopAdapter {}),
+			genesis::genesis_dev(),
+			pow::verify_size,
+			config.chain_config.clone(),
+		)?;
+		let res = File::create(path)
+			.map_err(|e| Error::ChainDump(format!("{}: {}", path, e)))
+			.and_then(|file| {
+				let mut writer = BufWriter::new(file);
+				let count = chaindump::export(&chain, &mut writer).map_err(Error::ChainDump)?;
+				writer
+					.flush()
+					.map_err(|e| Error::ChainDump(format!("{}: {}", path, e)))?;
+				Ok(count)
+			});
+		// stopped whether the export succeeded or not
+		chain.stop()?;
+		res
+	}
+
+	/// Adds the blocks of a dump file to the chain of a stopped server,
+	/// starting a new chain if it has none yet, returning the number of blocks
+	/// added. Each block is fully validated, as if received from a peer.
+	pub fn import_chain(config: ServerConfig, path: &str) -> Result<u64, Error> {
+		let file = File::open(path).map_err(|e| Error::ChainDump(format!("{}: {}", path, e)))?;
+		let chain = chain::Chain::init(
+			config.db_root.clone(),
+			Arc::new(chain::types::NoopAdapter {}),
+			genesis_block(&config)?,
+			pow::verify_size,
+			config.chain_config.clone(),
+		)?;
+		info!(LOGGER, "Importing blocks from {} on top of {:?}", path, chain.head()?);
+		let res = chaindump::import(&chain, &mut BufReader::new(file)).map_err(Error::ChainDump);
+		// the blocks imported before any failure are kept
+		chain.stop()?;
+		res
+	}
+
 	/// Instantiates a new server associated with the provided future reactor.
 	pub fn future(mut config: ServerConfig, evt_handle: &reactor::Handle) -> Result<Server, Error> {
+		let (api_listener, p2p_listener) = match ports::assign_ports(&mut config)? {
+			Some(listeners) => (Some(listeners.api), Some(listeners.p2p)),
+			None => (None, None),
+		};
+
+		// chain and pool events pushed to API subscribers
+		let events = Arc::new(api::EventHub::new());
+		let mining_stats = Arc::new(RwLock::new(api::MiningStats::default()));
+
 		let pool_adapter = Arc::new(PoolToChainAdapter::new());
-		let pool_net_adapter = Arc::new(PoolToNetAdapter::new());
+		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(events.clone()));
 		let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
 			config.pool_config.clone(),
 			pool_adapter.clone(),
 			pool_net_adapter.clone(),
 		)));
 
-		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(tx_pool.clone()));
+		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
+			tx_pool.clone(),
+			events.clone(),
+		));
 
-		let genesis = match config.chain_type {
-			global::ChainTypes::Testnet1 => genesis::genesis_testnet1(),
-			//global::ChainTypes::Testnet2 => genesis::genesis_testnet2(),
-			_ => pow::mine_genesis_block(config.mining_config.clone())?,
-		};
+		let genesis = genesis_block(&config)?;
 		info!(
 			LOGGER,
 			"Starting server, genesis block: {}",
@@ -107,16 +236,29 @@ impl Server {
 			chain_adapter.clone(),
 			genesis.clone(),
 			pow::verify_size,
+			config.chain_config.clone(),
 		)?);
 
 		pool_adapter.set_chain(shared_chain.clone());
 
-		let currently_syncing = Arc::new(AtomicBool::new(true));
+		// a pruning node can't serve the full block history anymore, so it
+		// shouldn't advertise it either, while archive nodes always do
+		if config.chain_config.archive_mode {
+			config.capabilities.insert(p2p::Capabilities::FULL_HIST);
+		} else if config.chain_config.is_pruning() {
+			config.capabilities.remove(p2p::Capabilities::FULL_HIST);
+		}
+		// we can always serve our headers by height
+		config.capabilities.insert(p2p::Capabilities::HEADER_RANGES);
+
+		let sync_state = Arc::new(chain::SyncState::new());
+		let header_ranges = Arc::new(sync::HeaderRanges::new());
 
 		let net_adapter = Arc::new(NetToChainAdapter::new(
-			currently_syncing.clone(),
+			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
+			header_ranges.clone(),
 		));
 
 		// thread pool (single thread) for offloading handler.handle()
@@ -171,21 +313,80 @@ impl Server {
 		};
 
 		sync::run_sync(
-			currently_syncing.clone(),
+			sync_state.clone(),
 			p2p_server.peers.clone(),
 			shared_chain.clone(),
+			header_ranges,
 			skip_sync_wait,
 			);
 
-		evt_handle.spawn(p2p_server.start(evt_handle.clone()).map_err(|_| ()));
+		if let Some(interval) = config.chain_config.compaction_interval {
+			start_compaction(interval, shared_chain.clone(), sync_state.clone());
+		}
+
+		memory::register(
+			"p2p send queues",
+			memory::Priority::Network,
+			Box::new(p2p_server.peers.memory_consumer()),
+		);
+		memory::register(
+			"transaction pool",
+			memory::Priority::Pool,
+			Box::new(pool::PoolMemory(Arc::downgrade(&tx_pool))),
+		);
+		if let Some(budget) = config.memory_budget {
+			memory::set_budget((budget * 1024 * 1024) as usize);
+			start_memory_enforcement();
+		}
+
+		start_stempool_monitor(tx_pool.clone());
+
+		evt_handle.spawn(
+			p2p_server
+				.start_with(evt_handle.clone(), p2p_listener)
+				.map_err(|_| ()),
+		);
 
 		info!(LOGGER, "Starting rest apis at: {}", &config.api_http_addr);
 
+		let api_secret = api::init_api_secret(&config.owner_api_secret_path())?;
+		let foreign_api_secret = match config.foreign_api_secret_path {
+			Some(ref path) => Some(api::init_api_secret(path)?),
+			None => None,
+		};
+		let stop_state = Arc::new(AtomicBool::new(false));
+		let mut template_miner = miner::Miner::new(
+			config.mining_config.clone().unwrap_or_default(),
+			shared_chain.clone(),
+			tx_pool.clone(),
+			mining_stats.clone(),
+			stop_state.clone(),
+		);
+		template_miner.set_debug_output_id(format!("Port {}", config.p2p_config.port));
 		api::start_rest_apis(
 			config.api_http_addr.clone(),
+			api_listener,
+			config.api_tls_config.clone(),
+			config.api_cors_config.clone(),
+			config.api_rate_limit_config.clone(),
+			api_secret,
+			foreign_api_secret,
 			shared_chain.clone(),
 			tx_pool.clone(),
 			p2p_server.peers.clone(),
+			events,
+			mining_stats.clone(),
+			sync_state.clone(),
+			Arc::new(ConfigReload::new(
+				config.config_file_path.clone(),
+				p2p_server.peers.clone(),
+			)),
+			Arc::new(BlockTemplates::new(
+				shared_chain.clone(),
+				sync_state.clone(),
+				template_miner,
+				mining_stats.clone(),
+			)),
 		);
 
 		warn!(LOGGER, "Grin server started.");
@@ -195,10 +396,26 @@ impl Server {
 			p2p: p2p_server,
 			chain: shared_chain,
 			tx_pool: tx_pool,
-			currently_syncing: currently_syncing,
+			mining_stats: mining_stats,
+			sync_state: sync_state,
+			stop_state: stop_state,
 		})
 	}
 
+	/// Stops the server: the miner and stratum server stop, we stop accepting
+	/// connections and say goodbye to our peers, then wait for the block being
+	/// processed, if any, and make sure the chain and peer stores are on disk.
+	/// The event loop needs to keep running a little for the goodbyes to be
+	/// sent.
+	pub fn stop(&self) {
+		warn!(LOGGER, "Stopping the server...");
+		self.stop_state.store(true, Ordering::Relaxed);
+		self.p2p.stop();
+		if let Err(e) = self.chain.stop() {
+			error!(LOGGER, "Error stopping the chain: {:?}", e);
+		}
+	}
+
 	/// Asks the server to connect to a peer at the provided network address.
 	pub fn connect_peer(&self, addr: SocketAddr) -> Result<(), Error> {
 		let handle = self.evt_handle.clone();
@@ -217,13 +434,18 @@ impl Server {
 	}
 
 	/// Start mining for blocks on a separate thread. Uses toy miner by default,
-	/// mostly for testing, but can also load a plugin from cuckoo-miner
+	/// mostly for testing, but can also load a plugin from cuckoo-miner, as
+	/// configured
 	pub fn start_miner(&self, config: pow::types::MinerConfig) {
-		let cuckoo_size = global::sizeshift();
-		let proof_size = global::proofsize();
-		let currently_syncing = self.currently_syncing.clone();
-
-		let mut miner = miner::Miner::new(config.clone(), self.chain.clone(), self.tx_pool.clone());
+		let sync_state = self.sync_state.clone();
+
+		let mut miner = miner::Miner::new(
+			config,
+			self.chain.clone(),
+			self.tx_pool.clone(),
+			self.mining_stats.clone(),
+			self.stop_state.clone(),
+		);
 		miner.set_debug_output_id(format!("Port {}", self.config.p2p_config.port));
 		let _ = thread::Builder::new()
 			.name("miner".to_string())
@@ -231,10 +453,44 @@ impl Server {
 				// TODO push this down in the run loop so miner gets paused anytime we
 				// decide to sync again
 				let secs_5 = time::Duration::from_secs(5);
-				while currently_syncing.load(Ordering::Relaxed) {
+				while sync_state.is_syncing() {
 					thread::sleep(secs_5);
 				}
-				miner.run_loop(config.clone(), cuckoo_size as u32, proof_size);
+				miner.run_loop();
+			});
+	}
+
+	/// Start the stratum server on a separate thread, external miners mining
+	/// the blocks it builds. The coinbase goes to the wallet configured in the
+	/// mining configuration.
+	pub fn start_stratum_server(&self, config: StratumServerConfig) {
+		let sync_state = self.sync_state.clone();
+		let mining_config = self.config.mining_config.clone().unwrap_or_default();
+		let mut miner = miner::Miner::new(
+			mining_config,
+			self.chain.clone(),
+			self.tx_pool.clone(),
+			self.mining_stats.clone(),
+			self.stop_state.clone(),
+		);
+		miner.set_debug_output_id(format!("Port {}", self.config.p2p_config.port));
+		let stratum_server = stratumserver::StratumServer::new(
+			config,
+			self.chain.clone(),
+			miner,
+			self.mining_stats.clone(),
+			self.stop_state.clone(),
+		);
+		let _ = thread::Builder::new()
+			.name("stratum_server".to_string())
+			.spawn(move || {
+				let secs_5 = time::Duration::from_secs(5);
+				while sync_state.is_syncing() {
+					thread::sleep(secs_5);
+				}
+				if let Err(e) = stratum_server.run_loop() {
+					error!(LOGGER, "Stratum server failed: {:?}", e);
+				}
 			});
 	}
 
@@ -250,9 +506,110 @@ impl Server {
 	/// consumers
 
 	pub fn get_server_stats(&self) -> Result<ServerStats, Error> {
-		Ok(ServerStats {
-			peer_count: self.peer_count(),
-			head: self.head(),
-		})
+		Ok(self.stats_reader().get_server_stats())
 	}
+
+	/// Reader of the server stats, usable from other threads
+	pub fn stats_reader(&self) -> StatsReader {
+		StatsReader {
+			chain: self.chain.clone(),
+			peers: self.p2p.peers.clone(),
+			tx_pool: self.tx_pool.clone(),
+			mining_stats: self.mining_stats.clone(),
+			sync_state: self.sync_state.clone(),
+			stop_state: self.stop_state.clone(),
+		}
+	}
+}
+
+/// Reads the stats of a running server from any thread, unlike the server
+/// itself which is tied to its event loop.
+#[derive(Clone)]
+pub struct StatsReader {
+	chain: Arc<chain::Chain>,
+	peers: p2p::Peers,
+	tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>,
+	mining_stats: Arc<RwLock<api::MiningStats>>,
+	sync_state: Arc<chain::SyncState>,
+	stop_state: Arc<AtomicBool>,
+}
+
+impl StatsReader {
+	/// Asks the server to shut down gracefully, as on SIGINT
+	pub fn stop_server(&self) {
+		self.stop_state.store(true, Ordering::Relaxed);
+	}
+
+	/// The current server stats
+	pub fn get_server_stats(&self) -> ServerStats {
+		let peer_stats = self.peers
+			.connected_peers()
+			.iter()
+			.map(|p| PeerStats::from_peer(&p.read().unwrap()))
+			.collect();
+		let (pool_size, stem_pool_size) = {
+			let tx_pool = self.tx_pool.read().unwrap();
+			(tx_pool.pool_size(), tx_pool.stempool_size())
+		};
+		ServerStats {
+			peer_count: self.peers.peer_count(),
+			head: self.chain.head().unwrap(),
+			sync_status: self.sync_state.status(),
+			peer_stats: peer_stats,
+			pool_size: pool_size,
+			stem_pool_size: stem_pool_size,
+			mining_stats: self.mining_stats.read().unwrap().clone(),
+			msg_stats: self.peers.stats(),
+		}
+	}
+
+	/// Conditions of the virtual network between the server and its peers,
+	/// for simulations to add latency, lost messages or partitions
+	pub fn network_conditions(&self) -> Arc<p2p::NetworkConditions> {
+		self.peers.network_conditions()
+	}
+}
+
+// Periodically compacts the chain, skipping runs while we're syncing.
+fn start_compaction(interval: u64, chain: Arc<chain::Chain>, sync_state: Arc<chain::SyncState>) {
+	let _ = thread::Builder::new()
+		.name("compactor".to_string())
+		.spawn(move || loop {
+			thread::sleep(time::Duration::from_secs(interval));
+			if sync_state.is_syncing() {
+				continue;
+			}
+			if let Err(e) = chain.compact() {
+				error!(LOGGER, "Scheduled chain compaction failed: {:?}", e);
+			}
+		});
+}
+
+// Genesis block of the configured chain type, used to start a new chain.
+fn genesis_block(config: &ServerConfig) -> Result<Block, Error> {
+	match config.chain_type {
+		global::ChainTypes::Testnet1 => Ok(genesis::genesis_testnet1()),
+		//global::ChainTypes::Testnet2 => genesis::genesis_testnet2(),
+		_ => Ok(pow::mine_genesis_block(config.mining_config.clone())?),
+	}
+}
+
+// Periodically relays or fluffs the transactions in their stem phase.
+fn start_stempool_monitor(tx_pool: Arc<RwLock<pool::TransactionPool<PoolToChainAdapter>>>) {
+	let _ = thread::Builder::new()
+		.name("stempool".to_string())
+		.spawn(move || loop {
+			thread::sleep(time::Duration::from_secs(1));
+			tx_pool.write().unwrap().process_stempool();
+		});
+}
+
+// Periodically checks the memory usage against the configured budget.
+fn start_memory_enforcement() {
+	let _ = thread::Builder::new()
+		.name("memory".to_string())
+		.spawn(move || loop {
+			thread::sleep(time::Duration::from_secs(10));
+			memory::enforce();
+		});
 }
@@ -0,0 +1,721 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum server, letting external miners mine blocks built by this node.
+//! Workers connect over TCP and exchange newline delimited JSON-RPC messages:
+//! they log in, get sent a new job anytime the block to mine changes and
+//! submit their shares, the ones meeting the network difficulty being
+//! processed as new blocks.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{self, Value};
+use time;
+
+use api::{MiningStats, WorkerStats};
+use chain;
+use core::consensus;
+use core::core::{Block, BlockHeader, Proof};
+use core::core::hash::Hashed;
+use core::core::target::Difficulty;
+use core::global;
+use core::ser;
+use keychain::Identifier;
+use miner::{self, Miner};
+use pow::cuckoo;
+use pow::mining::HeaderPartWriter;
+use types::{Error, StratumServerConfig};
+use util;
+use util::LOGGER;
+use wallet::BlockFees;
+
+// JSON-RPC error codes, on top of the standard ones
+const ERR_LOGIN_REQUIRED: i32 = -32500;
+const ERR_LOW_DIFFICULTY: i32 = -32501;
+const ERR_INVALID_SOLUTION: i32 = -32502;
+const ERR_STALE_SOLUTION: i32 = -32503;
+const ERR_NO_JOB: i32 = -32504;
+const ERR_DUPLICATE_SHARE: i32 = -32505;
+const ERR_INVALID_LOGIN: i32 = -32506;
+const ERR_INVALID_PARAMS: i32 = -32602;
+const ERR_UNKNOWN_METHOD: i32 = -32601;
+
+// Maximum number of connected workers, each read on its own thread
+const MAX_WORKERS: usize = 256;
+
+// Maximum length of a request line, way over the longest valid request
+const MAX_LINE_LEN: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RpcRequest {
+	id: String,
+	jsonrpc: String,
+	method: String,
+	params: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RpcResponse {
+	id: String,
+	jsonrpc: String,
+	method: String,
+	result: Option<Value>,
+	error: Option<RpcError>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RpcError {
+	code: i32,
+	message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LoginParams {
+	login: String,
+	pass: String,
+	agent: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SubmitParams {
+	height: u64,
+	job_id: u64,
+	nonce: u64,
+	pow: Vec<u32>,
+}
+
+/// A job sent to workers: the block header to find a proof of work for, split
+/// around the nonce workers iterate over, and the difficulty of the shares
+/// to submit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobTemplate {
+	/// Height of the block to mine
+	pub height: u64,
+	/// Job identifier, to submit solutions with
+	pub job_id: u64,
+	/// Minimum difficulty of the submitted shares
+	pub difficulty: u64,
+	/// Hex of the serialized header before the nonce
+	pub pre_nonce: String,
+	/// Hex of the serialized header after the nonce
+	pub post_nonce: String,
+}
+
+struct Worker {
+	// write end of the worker connection
+	stream: TcpStream,
+	logged_in: bool,
+	stats: WorkerStats,
+}
+
+impl Worker {
+	fn send(&mut self, msg: &str) -> Result<(), ::std::io::Error> {
+		self.stream.write_all(msg.as_bytes())?;
+		self.stream.write_all(b"\n")?;
+		self.stream.flush()
+	}
+}
+
+// The blocks being mined at the current height, a job per block version,
+// and the shares submitted for them
+struct Jobs {
+	height: u64,
+	blocks: Vec<Block>,
+	shares: HashSet<(u64, u64)>,
+}
+
+impl Jobs {
+	// Starts mining at a new height, forgetting the previous jobs.
+	fn reset(&mut self, height: u64) {
+		self.height = height;
+		self.blocks.clear();
+		self.shares.clear();
+	}
+
+	// Records a share submitted for a job, false if it was already.
+	fn record_share(&mut self, job_id: u64, nonce: u64) -> bool {
+		self.shares.insert((job_id, nonce))
+	}
+}
+
+/// Builds the blocks external miners work on and processes the solutions
+/// they find.
+pub struct StratumServer {
+	config: StratumServerConfig,
+	chain: Arc<chain::Chain>,
+	miner: Miner,
+	jobs: Arc<RwLock<Jobs>>,
+	workers: Arc<Mutex<HashMap<usize, Worker>>>,
+	stats: Arc<RwLock<MiningStats>>,
+	stop_state: Arc<AtomicBool>,
+}
+
+impl StratumServer {
+	/// Creates a new stratum server, building its blocks with the provided
+	/// miner (for the coinbase and pool transactions) and updating the
+	/// provided mining stats until the server stop state is set.
+	pub fn new(
+		config: StratumServerConfig,
+		chain: Arc<chain::Chain>,
+		miner: Miner,
+		stats: Arc<RwLock<MiningStats>>,
+		stop_state: Arc<AtomicBool>,
+	) -> StratumServer {
+		StratumServer {
+			config: config,
+			chain: chain,
+			miner: miner,
+			stats: stats,
+			stop_state: stop_state,
+			jobs: Arc::new(RwLock::new(Jobs {
+				height: 0,
+				blocks: vec![],
+				shares: HashSet::new(),
+			})),
+			workers: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Starts listening for workers and loops building new jobs anytime the
+	/// chain head changes or the current job gets too old, until the server
+	/// stops.
+	pub fn run_loop(&self) -> Result<(), Error> {
+		let listener = TcpListener::bind(&self.config.stratum_server_addr).map_err(|e| {
+			Error::Stratum(format!(
+				"could not listen on {}: {}",
+				self.config.stratum_server_addr, e
+			))
+		})?;
+		warn!(
+			LOGGER,
+			"Stratum server listening on {}", self.config.stratum_server_addr
+		);
+		self.stats.write().unwrap().is_stratum_running = true;
+		self.accept_workers(listener);
+
+		// reuse the coinbase key derivation while the height doesn't change,
+		// so the wallet doesn't derive a new key for each job
+		let mut key_id = None;
+		let mut head_hash = None;
+		let mut deadline = 0;
+		while !self.stop_state.load(Ordering::Relaxed) {
+			let head = self.chain.head_header().unwrap();
+			if Some(head.hash()) != head_hash || time::get_time().sec >= deadline {
+				if Some(head.hash()) != head_hash {
+					key_id = None;
+				}
+				let (b, block_fees) = self.build_block(&head, key_id.clone());
+				key_id = block_fees.key_id();
+				head_hash = Some(head.hash());
+				deadline = time::get_time().sec + self.config.attempt_time_per_block as i64;
+
+				let job = {
+					let mut jobs = self.jobs.write().unwrap();
+					if jobs.height != b.header.height {
+						jobs.reset(b.header.height);
+					}
+					let mut stats = self.stats.write().unwrap();
+					stats.block_height = b.header.height;
+					stats.network_difficulty = b.header.difficulty.into_num();
+					jobs.blocks.push(b);
+					job_template(&jobs, self.config.minimum_share_difficulty).unwrap()
+				};
+				debug!(
+					LOGGER,
+					"Stratum server sending job {} at height {}", job.job_id, job.height
+				);
+				self.broadcast_job(job);
+			}
+			thread::sleep(Duration::from_millis(500));
+		}
+		self.stats.write().unwrap().is_stratum_running = false;
+		warn!(LOGGER, "Stratum server stopped.");
+		Ok(())
+	}
+
+	// Builds a new block on top of the provided head, retrying until it can.
+	fn build_block(&self, head: &BlockHeader, key_id: Option<Identifier>) -> (Block, BlockFees) {
+		let mut key_id = key_id;
+		let mut result = self.miner.build_block(head, key_id.clone());
+		while let Err(e) = result {
+			match e {
+				Error::Chain(chain::Error::DuplicateCommitment(_)) => {
+					debug!(
+						LOGGER,
+						"Duplicate commit for potential coinbase detected. Trying next derivation."
+					);
+					key_id = None;
+				}
+				ae => {
+					warn!(LOGGER, "Error building new block: {:?}. Retrying.", ae);
+				}
+			}
+			thread::sleep(Duration::from_millis(100));
+			result = self.miner.build_block(head, key_id.clone());
+		}
+		result.unwrap()
+	}
+
+	// Sends the new job to all logged in workers, dropping the ones we can't
+	// reach anymore.
+	fn broadcast_job(&self, job: JobTemplate) {
+		let msg = RpcRequest {
+			id: "Stratum".to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "job".to_string(),
+			params: Some(serde_json::to_value(job).unwrap()),
+		};
+		let msg = serde_json::to_string(&msg).unwrap();
+		let mut workers = self.workers.lock().unwrap();
+		let mut gone = vec![];
+		for (id, worker) in workers.iter_mut() {
+			if worker.logged_in && worker.send(&msg).is_err() {
+				gone.push(*id);
+			}
+		}
+		for id in gone {
+			if let Some(worker) = workers.remove(&id) {
+				info!(LOGGER, "Stratum worker gone: {:?}", worker.stats);
+			}
+		}
+		publish_worker_stats(&workers, &self.stats);
+	}
+
+	// Accepts workers on a separate thread, each connection being read on
+	// its own thread.
+	fn accept_workers(&self, listener: TcpListener) {
+		let handler = WorkerHandler {
+			config: self.config.clone(),
+			chain: self.chain.clone(),
+			coinbase_key_path: self.miner.coinbase_key_path(),
+			jobs: self.jobs.clone(),
+			workers: self.workers.clone(),
+			stats: self.stats.clone(),
+		};
+		let _ = thread::Builder::new()
+			.name("stratum_listener".to_string())
+			.spawn(move || {
+				let mut next_id = 0;
+				for stream in listener.incoming() {
+					let stream = match stream {
+						Ok(s) => s,
+						Err(e) => {
+							warn!(LOGGER, "Stratum server couldn't accept worker: {}", e);
+							continue;
+						}
+					};
+					if handler.workers.lock().unwrap().len() >= MAX_WORKERS {
+						debug!(
+							LOGGER,
+							"Stratum server refusing {:?}, too many workers",
+							stream.peer_addr()
+						);
+						continue;
+					}
+					let write_stream = match stream.try_clone() {
+						Ok(s) => s,
+						Err(_) => continue,
+					};
+					next_id += 1;
+					let id = next_id;
+					info!(
+						LOGGER,
+						"Stratum worker {} connected from {:?}",
+						id,
+						stream.peer_addr()
+					);
+					handler.workers.lock().unwrap().insert(
+						id,
+						Worker {
+							stream: write_stream,
+							logged_in: false,
+							stats: WorkerStats {
+								id: id,
+								last_seen: time::get_time().sec,
+								..Default::default()
+							},
+						},
+					);
+					let handler = handler.clone();
+					let _ = thread::Builder::new()
+						.name(format!("stratum_worker_{}", id))
+						.spawn(move || handler.handle_worker(id, stream));
+				}
+			});
+	}
+}
+
+// Everything needed to answer the requests of a worker.
+#[derive(Clone)]
+struct WorkerHandler {
+	config: StratumServerConfig,
+	chain: Arc<chain::Chain>,
+	coinbase_key_path: Option<String>,
+	jobs: Arc<RwLock<Jobs>>,
+	workers: Arc<Mutex<HashMap<usize, Worker>>>,
+	stats: Arc<RwLock<MiningStats>>,
+}
+
+impl WorkerHandler {
+	fn handle_worker(&self, id: usize, stream: TcpStream) {
+		let mut reader = BufReader::new(stream);
+		loop {
+			let line = match read_line(&mut reader, MAX_LINE_LEN) {
+				Ok(Some(l)) => l,
+				Ok(None) => break,
+				Err(e) => {
+					debug!(LOGGER, "Stratum worker {} read failed: {}", id, e);
+					break;
+				}
+			};
+			if line.trim().is_empty() {
+				continue;
+			}
+			let response = match serde_json::from_str::<RpcRequest>(&line) {
+				Ok(req) => self.handle_request(id, req),
+				Err(e) => {
+					debug!(LOGGER, "Stratum worker {} sent invalid request: {}", id, e);
+					break;
+				}
+			};
+			let response = serde_json::to_string(&response).unwrap();
+			let mut workers = self.workers.lock().unwrap();
+			publish_worker_stats(&workers, &self.stats);
+			match workers.get_mut(&id) {
+				Some(worker) => {
+					if worker.send(&response).is_err() {
+						break;
+					}
+				}
+				None => break,
+			}
+		}
+		let mut workers = self.workers.lock().unwrap();
+		if let Some(worker) = workers.remove(&id) {
+			info!(LOGGER, "Stratum worker disconnected: {:?}", worker.stats);
+		}
+		publish_worker_stats(&workers, &self.stats);
+	}
+
+	fn handle_request(&self, id: usize, req: RpcRequest) -> RpcResponse {
+		let logged_in = {
+			let mut workers = self.workers.lock().unwrap();
+			match workers.get_mut(&id) {
+				Some(worker) => {
+					worker.stats.last_seen = time::get_time().sec;
+					worker.logged_in
+				}
+				None => false,
+			}
+		};
+		let result = match req.method.as_str() {
+			"login" => self.login(id, req.params),
+			"keepalive" => Ok(Value::String("ok".to_string())),
+			"getjobtemplate" | "submit" if !logged_in => {
+				Err(rpc_error(ERR_LOGIN_REQUIRED, "Login required"))
+			}
+			"getjobtemplate" => self.job_template(),
+			"submit" => self.submit(id, req.params),
+			_ => Err(rpc_error(ERR_UNKNOWN_METHOD, "Method not found")),
+		};
+		let (result, error) = match result {
+			Ok(r) => (Some(r), None),
+			Err(e) => (None, Some(e)),
+		};
+		RpcResponse {
+			id: req.id,
+			jsonrpc: "2.0".to_string(),
+			method: req.method,
+			result: result,
+			error: error,
+		}
+	}
+
+	fn login(&self, id: usize, params: Option<Value>) -> Result<Value, RpcError> {
+		let params: LoginParams = parse_params(params)?;
+		if !check_password(&self.config, &params.pass) {
+			return Err(rpc_error(ERR_INVALID_LOGIN, "Invalid password"));
+		}
+		let mut workers = self.workers.lock().unwrap();
+		if let Some(worker) = workers.get_mut(&id) {
+			worker.logged_in = true;
+			worker.stats.login = params.login;
+			worker.stats.agent = params.agent;
+			info!(LOGGER, "Stratum worker logged in: {:?}", worker.stats);
+		}
+		Ok(Value::String("ok".to_string()))
+	}
+
+	fn job_template(&self) -> Result<Value, RpcError> {
+		let jobs = self.jobs.read().unwrap();
+		match job_template(&jobs, self.config.minimum_share_difficulty) {
+			Some(job) => Ok(serde_json::to_value(job).unwrap()),
+			None => Err(rpc_error(ERR_NO_JOB, "No job available yet")),
+		}
+	}
+
+	// Checks a submitted share, processing it as a new block if it meets the
+	// network difficulty.
+	fn submit(&self, id: usize, params: Option<Value>) -> Result<Value, RpcError> {
+		let params: SubmitParams = parse_params(params)?;
+		let b = {
+			let jobs = self.jobs.read().unwrap();
+			if params.height != jobs.height {
+				None
+			} else {
+				jobs.blocks.get(params.job_id as usize).cloned()
+			}
+		};
+		let mut b = match b {
+			Some(b) => b,
+			None => {
+				self.update_stats(id, |stats| stats.num_stale += 1);
+				return Err(rpc_error(ERR_STALE_SOLUTION, "Solution submitted too late"));
+			}
+		};
+
+		b.header.nonce = params.nonce;
+		let share_difficulty = match check_solution(&mut b, params.pow) {
+			Some(d) => d,
+			None => {
+				self.update_stats(id, |stats| stats.num_rejected += 1);
+				return Err(rpc_error(ERR_INVALID_SOLUTION, "Failed to validate solution"));
+			}
+		};
+		if share_difficulty < Difficulty::from_num(self.config.minimum_share_difficulty) {
+			self.update_stats(id, |stats| stats.num_rejected += 1);
+			return Err(rpc_error(
+				ERR_LOW_DIFFICULTY,
+				"Share rejected due to low difficulty",
+			));
+		}
+		// a share only counts once, whoever submits it
+		let recorded = {
+			let mut jobs = self.jobs.write().unwrap();
+			jobs.height == params.height && jobs.record_share(params.job_id, params.nonce)
+		};
+		if !recorded {
+			self.update_stats(id, |stats| stats.num_rejected += 1);
+			return Err(rpc_error(ERR_DUPLICATE_SHARE, "Share already submitted"));
+		}
+
+		if share_difficulty >= b.header.difficulty {
+			let hash = b.hash();
+			info!(
+				LOGGER,
+				"Stratum worker {} found valid proof of work, adding block {}.", id, hash
+			);
+			let height = b.header.height;
+			util::tracing::start("block", &hash.to_hex(), "stratum miner");
+			if let Err(e) = self.chain.process_block(b.clone(), chain::MINE) {
+				error!(LOGGER, "Error validating block {} from stratum worker: {:?}", hash, e);
+				self.update_stats(id, |stats| stats.num_rejected += 1);
+				return Err(rpc_error(ERR_INVALID_SOLUTION, "Failed to validate block"));
+			}
+			if let Some(ref key_path) = self.coinbase_key_path {
+				miner::coinbase_accepted(key_path, &b);
+			}
+			let mut login = String::new();
+			self.update_stats(id, |stats| {
+				stats.num_blocks_found += 1;
+				login = stats.login.clone();
+			});
+			self.stats.write().unwrap().add_block_found(
+				height,
+				hash.to_hex(),
+				format!("stratum worker {}", login),
+				time::get_time().sec,
+			);
+		}
+		self.update_stats(id, |stats| stats.num_accepted += 1);
+		Ok(Value::String("ok".to_string()))
+	}
+
+	fn update_stats<F>(&self, id: usize, f: F)
+	where
+		F: FnOnce(&mut WorkerStats),
+	{
+		if let Some(worker) = self.workers.lock().unwrap().get_mut(&id) {
+			f(&mut worker.stats);
+		}
+	}
+}
+
+// Copies the stats of the connected workers to the mining stats.
+fn publish_worker_stats(workers: &HashMap<usize, Worker>, stats: &RwLock<MiningStats>) {
+	let mut worker_stats = workers
+		.values()
+		.map(|w| w.stats.clone())
+		.collect::<Vec<WorkerStats>>();
+	worker_stats.sort_by_key(|w| w.id);
+	stats.write().unwrap().workers = worker_stats;
+}
+
+// Job for the latest version of the block being mined, if any.
+fn job_template(jobs: &Jobs, share_difficulty: u64) -> Option<JobTemplate> {
+	let b = match jobs.blocks.last() {
+		Some(b) => b,
+		None => return None,
+	};
+	let mut header_parts = HeaderPartWriter::default();
+	ser::Writeable::write(&b.header, &mut header_parts).unwrap();
+	let (pre_nonce, post_nonce) = header_parts.parts_as_hex_strings();
+	Some(JobTemplate {
+		height: jobs.height,
+		job_id: (jobs.blocks.len() - 1) as u64,
+		difficulty: share_difficulty,
+		pre_nonce: pre_nonce,
+		post_nonce: post_nonce,
+	})
+}
+
+// Sets the proof of work of the block if valid, returning its difficulty.
+fn check_solution(b: &mut Block, pow: Vec<u32>) -> Option<Difficulty> {
+	if pow.len() != global::proofsize() {
+		return None;
+	}
+	let proof = Proof::new(pow);
+	let sizeshift = global::sizeshift() as u32;
+	let cuckoo = cuckoo::Cuckoo::new(&b.hash()[..], sizeshift);
+	if !cuckoo.verify(proof.clone(), consensus::EASINESS as u64) {
+		return None;
+	}
+	b.header.pow = proof.clone();
+	Some(proof.to_difficulty())
+}
+
+// Whether a worker can log in with the provided password.
+fn check_password(config: &StratumServerConfig, pass: &str) -> bool {
+	match config.stratum_server_password {
+		Some(ref password) => password == pass,
+		None => true,
+	}
+}
+
+// Reads a line, without its end of line, refusing lines longer than the
+// provided maximum. None once the connection is closed.
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<Option<String>> {
+	let mut buf = vec![];
+	reader
+		.by_ref()
+		.take(max_len as u64 + 1)
+		.read_until(b'\n', &mut buf)?;
+	if buf.is_empty() {
+		return Ok(None);
+	}
+	if buf.last() == Some(&b'\n') {
+		buf.pop();
+	} else if buf.len() > max_len {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+	}
+	String::from_utf8(buf)
+		.map(|l| Some(l))
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid utf-8"))
+}
+
+fn parse_params<T>(params: Option<Value>) -> Result<T, RpcError>
+where
+	for<'de> T: ::serde::Deserialize<'de>,
+{
+	params
+		.and_then(|p| serde_json::from_value(p).ok())
+		.ok_or(rpc_error(ERR_INVALID_PARAMS, "Invalid request parameters"))
+}
+
+fn rpc_error(code: i32, message: &str) -> RpcError {
+	RpcError {
+		code: code,
+		message: message.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn jobs_and_shares() {
+		let mut jobs = Jobs {
+			height: 0,
+			blocks: vec![],
+			shares: HashSet::new(),
+		};
+		assert!(job_template(&jobs, 1).is_none());
+
+		jobs.reset(5);
+		jobs.blocks.push(Block::default());
+		jobs.blocks.push(Block::default());
+		let job = job_template(&jobs, 3).unwrap();
+		assert_eq!(job.height, 5);
+		assert_eq!(job.job_id, 1);
+		assert_eq!(job.difficulty, 3);
+
+		// each share counts once, until the next height
+		assert!(jobs.record_share(1, 42));
+		assert!(!jobs.record_share(1, 42));
+		assert!(jobs.record_share(0, 42));
+		jobs.reset(6);
+		assert!(jobs.blocks.is_empty());
+		assert!(jobs.record_share(1, 42));
+	}
+
+	#[test]
+	fn login_password() {
+		let mut config = StratumServerConfig::default();
+		assert!(check_password(&config, "anything"));
+		config.stratum_server_password = Some("secret".to_string());
+		assert!(check_password(&config, "secret"));
+		assert!(!check_password(&config, "guess"));
+		assert!(!check_password(&config, ""));
+	}
+
+	#[test]
+	fn line_length_limit() {
+		let mut reader = io::Cursor::new(b"short\nlast".to_vec());
+		assert_eq!(read_line(&mut reader, 8).unwrap(), Some("short".to_string()));
+		assert_eq!(read_line(&mut reader, 8).unwrap(), Some("last".to_string()));
+		assert_eq!(read_line(&mut reader, 8).unwrap(), None);
+
+		let mut reader = io::Cursor::new(b"12345678\n123456789\n".to_vec());
+		assert_eq!(read_line(&mut reader, 8).unwrap(), Some("12345678".to_string()));
+		assert!(read_line(&mut reader, 8).is_err());
+	}
+
+	#[test]
+	fn submit_params() {
+		let req: RpcRequest = serde_json::from_str(
+			r#"{"id":"1","jsonrpc":"2.0","method":"submit",
+			"params":{"height":5,"job_id":2,"nonce":8834,"pow":[1,2,3]}}"#,
+		).unwrap();
+		let params: SubmitParams = parse_params(req.params).unwrap();
+		assert_eq!(params.height, 5);
+		assert_eq!(params.job_id, 2);
+		assert_eq!(params.nonce, 8834);
+		assert_eq!(params.pow, vec![1, 2, 3]);
+
+		let req: RpcRequest =
+			serde_json::from_str(r#"{"id":"2","jsonrpc":"2.0","method":"submit","params":{"height":5}}"#)
+				.unwrap();
+		let err = parse_params::<SubmitParams>(req.params).unwrap_err();
+		assert_eq!(err.code, ERR_INVALID_PARAMS);
+		let err = parse_params::<LoginParams>(None).unwrap_err();
+		assert_eq!(err.code, ERR_INVALID_PARAMS);
+	}
+}
@@ -13,23 +13,309 @@
 // limitations under the License.
 
 use std::{cmp, thread};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
 use std::time::Duration;
-use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use time;
 
-use chain;
+use chain::{self, SyncState, SyncStatus};
+use core::core::BlockHeader;
+use download::BlockDownloads;
 use core::core::hash::{Hash, Hashed};
 use core::core::target::Difficulty;
 use p2p::{self, Peer, Peers, ChainAdapter};
 use types::Error;
 use util::LOGGER;
 
-/// Starts the syncing loop, just spawns two threads that loop forever
+/// Length of the window over which the throughput of sync sources is
+/// measured, the primary source can only be rotated at the end of a window
+const SOURCE_WINDOW_SECS: i64 = 60;
+
+/// Maximum number of blocks requested from a peer and not received yet
+const MAX_BLOCKS_PER_PEER: usize = 10;
+
+/// Seconds after which a requested block that hasn't been received gets
+/// requested again, from another peer when possible
+const BLOCK_REQUEST_TIMEOUT_SECS: i64 = 20;
+
+/// Number of headers in each range requested during header sync, as many as
+/// a locator request gets us
+const HEADER_RANGE_SIZE: u64 = p2p::MAX_BLOCK_HEADERS as u64 - 1;
+
+/// Maximum number of header ranges requested or waiting to be added ahead
+/// of our header chain
+const MAX_HEADER_RANGES: u64 = 16;
+
+/// Seconds after which a header range that hasn't been received gets
+/// requested from another peer
+const HEADER_RANGE_TIMEOUT_SECS: i64 = 10;
+
+struct RangeRequest {
+	addr: SocketAddr,
+	time: time::Tm,
+}
+
+/// Header ranges requested from several peers in parallel during header
+/// sync, ahead of the headers our locator request gets us. Ranges follow a
+/// fixed grid of heights so they stay the same as our header chain advances.
+/// Ranges received before the headers preceding them are kept until those
+/// get added to our header chain.
+pub struct HeaderRanges {
+	requested: Mutex<HashMap<u64, RangeRequest>>,
+	received: Mutex<BTreeMap<u64, (Vec<BlockHeader>, SocketAddr)>>,
+}
+
+impl HeaderRanges {
+	pub fn new() -> HeaderRanges {
+		HeaderRanges {
+			requested: Mutex::new(HashMap::new()),
+			received: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	// Requests the ranges following the provided sync head height up to the
+	// provided height, one range per peer at a time. Ranges that timed out or
+	// whose peer is gone get requested from another peer.
+	fn request(&self, sync_height: u64, max_height: u64, peers: &Vec<Arc<RwLock<Peer>>>) {
+		let now = time::now_utc();
+		let addrs = peers
+			.iter()
+			.filter_map(|p| p.try_read().ok().map(|p| p.info.addr))
+			.collect::<Vec<_>>();
+
+		for (start, addr) in self.assign(now, sync_height, max_height, &addrs) {
+			let mut sent = false;
+			if let Some(peer) = peers.iter().find(|p| match p.try_read() {
+				Ok(p) => p.info.addr == addr,
+				Err(_) => false,
+			}) {
+				if let Ok(peer) = peer.try_read() {
+					sent = peer.send_header_range_request(start, HEADER_RANGE_SIZE as u16).is_ok();
+				}
+			}
+			if !sent {
+				self.requested.lock().unwrap().remove(&start);
+			}
+		}
+	}
+
+	// Assigns the ranges to request to the provided peers, considered
+	// requested from then on, after dropping the requests that timed out or
+	// whose peer is gone.
+	fn assign(
+		&self,
+		now: time::Tm,
+		sync_height: u64,
+		max_height: u64,
+		addrs: &[SocketAddr],
+	) -> Vec<(u64, SocketAddr)> {
+		let mut requested = self.requested.lock().unwrap();
+		let received = self.received.lock().unwrap();
+		requested.retain(|start, r| {
+			if start + HEADER_RANGE_SIZE <= sync_height + 1 {
+				return false;
+			}
+			let expired = now - r.time > time::Duration::seconds(HEADER_RANGE_TIMEOUT_SECS);
+			if expired || !addrs.contains(&r.addr) {
+				debug!(
+					LOGGER,
+					"sync: header range at {} from {} not received, reassigning", start, r.addr
+				);
+				return false;
+			}
+			true
+		});
+
+		let busy = requested.values().map(|r| r.addr).collect::<HashSet<_>>();
+		let mut free = addrs.iter().filter(|a| !busy.contains(*a));
+
+		// the first grid range past the headers the locator gets us
+		let mut assigned = vec![];
+		let mut start = (sync_height / HEADER_RANGE_SIZE + 1) * HEADER_RANGE_SIZE + 1;
+		for _ in 0..MAX_HEADER_RANGES {
+			if start > max_height {
+				break;
+			}
+			if !requested.contains_key(&start) && !received.contains_key(&start) {
+				let addr = match free.next() {
+					Some(addr) => *addr,
+					None => break,
+				};
+				requested.insert(start, RangeRequest { addr: addr, time: now });
+				assigned.push((start, addr));
+			}
+			start += HEADER_RANGE_SIZE;
+		}
+		assigned
+	}
+
+	/// Headers received from a peer, returned if they can be added to our
+	/// header chain right away. A range requested from that peer that's ahead
+	/// of our header chain is kept for later instead.
+	pub fn receive(
+		&self,
+		headers: Vec<BlockHeader>,
+		addr: SocketAddr,
+		chain: &chain::Chain,
+	) -> Option<Vec<BlockHeader>> {
+		self.receive_with(headers, addr, |h| chain.get_block_header(h).is_ok())
+	}
+
+	fn receive_with<F>(
+		&self,
+		headers: Vec<BlockHeader>,
+		addr: SocketAddr,
+		have_header: F,
+	) -> Option<Vec<BlockHeader>>
+	where
+		F: Fn(&Hash) -> bool,
+	{
+		let (start, previous) = match headers.first() {
+			Some(h) => (h.height, h.previous),
+			None => return Some(headers),
+		};
+		let is_range = {
+			let mut requested = self.requested.lock().unwrap();
+			let from_peer = requested.get(&start).map_or(false, |r| r.addr == addr);
+			if from_peer {
+				requested.remove(&start);
+			}
+			from_peer
+		};
+		if is_range && !have_header(&previous) {
+			debug!(
+				LOGGER,
+				"sync: keeping {} headers at {} from {} until we have the previous ones",
+				headers.len(),
+				start,
+				addr,
+			);
+			self.received.lock().unwrap().insert(start, (headers, addr));
+			return None;
+		}
+		Some(headers)
+	}
+
+	/// The next range kept for later that can now be added to our header
+	/// chain, if any, with the peer it came from. Ranges that don't follow our
+	/// header chain (from another fork) get dropped.
+	pub fn next_ready(&self, chain: &chain::Chain) -> Option<(Vec<BlockHeader>, SocketAddr)> {
+		let sync_height = match chain.get_sync_head() {
+			Ok(head) => head.height,
+			Err(_) => return None,
+		};
+		self.next_ready_with(sync_height, |h| chain.get_block_header(h).is_ok())
+	}
+
+	fn next_ready_with<F>(
+		&self,
+		sync_height: u64,
+		have_header: F,
+	) -> Option<(Vec<BlockHeader>, SocketAddr)>
+	where
+		F: Fn(&Hash) -> bool,
+	{
+		let mut received = self.received.lock().unwrap();
+		loop {
+			let start = match received.keys().next() {
+				Some(start) => *start,
+				None => return None,
+			};
+			let linked = have_header(&(received[&start].0)[0].previous);
+			if linked {
+				return received.remove(&start);
+			}
+			if start > sync_height + 1 {
+				return None;
+			}
+			debug!(LOGGER, "sync: dropping header range at {}, not on our chain", start);
+			received.remove(&start);
+		}
+	}
+}
+
+/// Keeps track of the blocks each peer delivered during body sync to
+/// measure how fast they are, rotating the primary sync source away from
+/// a peer as soon as another one turns out to be significantly faster.
+struct SyncSources {
+	primary: Option<SocketAddr>,
+	received: HashMap<SocketAddr, u64>,
+	window_start: time::Tm,
+}
+
+impl SyncSources {
+	fn new() -> SyncSources {
+		SyncSources {
+			primary: None,
+			received: HashMap::new(),
+			window_start: time::now_utc(),
+		}
+	}
+
+	// Credits a peer with a block it's been asked for that we now have.
+	fn credit(&mut self, addr: SocketAddr) {
+		*self.received.entry(addr).or_insert(0) += 1;
+	}
+
+	// Selects the primary sync source among the provided peers. At the end of
+	// each window, switches to the peer that delivered the most blocks if it
+	// delivered at least twice as many as the current primary. Without a
+	// primary, starts with our most worked peer.
+	fn select_primary(
+		&mut self,
+		now: time::Tm,
+		most_work: Option<SocketAddr>,
+		addrs: &[SocketAddr],
+	) -> Option<SocketAddr> {
+		if let Some(primary) = self.primary {
+			if !addrs.contains(&primary) {
+				debug!(LOGGER, "sync: primary sync source {} gone", primary);
+				self.primary = None;
+			}
+		}
+
+		if now - self.window_start > time::Duration::seconds(SOURCE_WINDOW_SECS) {
+			let best = addrs
+				.iter()
+				.filter_map(|a| self.received.get(a).map(|n| (*a, *n)))
+				.max_by_key(|&(_, n)| n);
+			if let (Some(primary), Some((addr, n))) = (self.primary, best) {
+				let primary_n = self.received.get(&primary).cloned().unwrap_or(0);
+				if addr != primary && n >= 2 * cmp::max(primary_n, 1) {
+					info!(
+						LOGGER,
+						"sync: rotating sync source from {} ({} blocks) to {} ({} blocks)",
+						primary,
+						primary_n,
+						addr,
+						n,
+					);
+					self.primary = Some(addr);
+				}
+			}
+			self.received.clear();
+			self.window_start = now;
+		}
+
+		if self.primary.is_none() {
+			self.primary = match most_work {
+				Some(addr) if addrs.contains(&addr) => Some(addr),
+				_ => addrs.first().cloned(),
+			};
+		}
+		self.primary
+	}
+}
+
+/// Starts the syncing loop, just spawns a thread that loops forever, moving
+/// the sync state from its initial stage to header sync, then body sync and
+/// finally no sync once caught up (and back to syncing if we fall behind).
 pub fn run_sync(
-	currently_syncing: Arc<AtomicBool>,
+	sync_state: Arc<SyncState>,
 	peers: p2p::Peers,
 	chain: Arc<chain::Chain>,
+	header_ranges: Arc<HeaderRanges>,
 	skip_sync_wait: bool,
 ) {
 
@@ -39,6 +325,8 @@ pub fn run_sync(
 		.spawn(move || {
 			let mut prev_body_sync = time::now_utc();
 			let mut prev_header_sync = prev_body_sync.clone();
+			let mut sources = SyncSources::new();
+			let mut downloads = BlockDownloads::new(MAX_BLOCKS_PER_PEER, BLOCK_REQUEST_TIMEOUT_SECS);
 
 			// initial sleep to give us time to peer with some nodes
 			if !skip_sync_wait {
@@ -46,26 +334,34 @@ pub fn run_sync(
 			}
 
 			loop {
-				let syncing = needs_syncing(
-					currently_syncing.clone(), peers.clone(), chain.clone());
+				let syncing = needs_syncing(&sync_state, &peers, &chain);
 				if syncing {
 
 					let current_time = time::now_utc();
 
-					// run the header sync every 10s
+					// run the header sync every 10s, also while syncing bodies
+					// to follow the headers of new blocks
 					if current_time - prev_header_sync > time::Duration::seconds(10) {
 						header_sync(
 							peers.clone(),
 							chain.clone(),
+							&header_ranges,
 						);
 						prev_header_sync = current_time;
 					}
 
-					// run the body_sync every 5s
-					if current_time - prev_body_sync > time::Duration::seconds(5) {
+					// run the body_sync every 5s, once we have all the headers, topping up
+					// the blocks requested from each peer
+					let body_syncing = match sync_state.status() {
+						SyncStatus::BodySync { .. } => true,
+						_ => false,
+					};
+					if body_syncing && current_time - prev_body_sync > time::Duration::seconds(5) {
 						body_sync(
 							peers.clone(),
 							chain.clone(),
+							&mut sources,
+							&mut downloads,
 						);
 						prev_body_sync = current_time;
 					}
@@ -78,7 +374,12 @@ pub fn run_sync(
 		});
 }
 
-fn body_sync(peers: Peers, chain: Arc<chain::Chain>) {
+fn body_sync(
+	peers: Peers,
+	chain: Arc<chain::Chain>,
+	sources: &mut SyncSources,
+	downloads: &mut BlockDownloads,
+) {
 
 	let body_head: chain::Tip = chain.head().unwrap();
 	let header_head: chain::Tip = chain.get_header_head().unwrap();
@@ -113,45 +414,79 @@ fn body_sync(peers: Peers, chain: Arc<chain::Chain>) {
 	}
 	hashes.reverse();
 
-	// if we have 5 peers to sync from then ask for 50 blocks total (peer_count * 10)
-	// max will be 80 if all 8 peers are advertising more work
-	let peer_count = cmp::min(peers.more_work_peers().len(), 10);
-	let block_count = peer_count * 10;
+	// credit the peers for the blocks they delivered, then give up on the
+	// requests they didn't answer in time or that went to peers now gone
+	for (_, addr) in downloads.received(|h| chain.get_block(h).is_ok() || chain.is_orphan(h)) {
+		sources.credit(addr);
+	}
+	let more_work_peers = peers.more_work_peers();
+	let mut addrs = more_work_peers
+		.iter()
+		.filter_map(|p| p.try_read().ok().map(|p| p.info.addr))
+		.collect::<Vec<_>>();
+	let now = time::now_utc();
+	let mut timed_out = HashSet::new();
+	for (hash, addr) in downloads.expire(now, &addrs) {
+		debug!(LOGGER, "body_sync: block {} not received from {}, reassigning", hash, addr);
+		// still connected, it just didn't deliver
+		if addrs.contains(&addr) {
+			timed_out.insert(addr);
+		}
+	}
+	for addr in timed_out {
+		peers.penalize_peer(&addr, p2p::Misbehavior::Timeout);
+	}
+
+	// the primary sync source gets the first pick of the blocks we need
+	let most_work = peers
+		.most_work_peer()
+		.and_then(|p| p.try_read().ok().map(|p| p.info.addr));
+	if let Some(primary) = sources.select_primary(time::now_utc(), most_work, &addrs) {
+		addrs.retain(|a| *a != primary);
+		addrs.insert(0, primary);
+	}
 
+	// only ask for blocks that we have not yet processed, either successfully
+	// stored or in our orphan list, and not more than all our peers can have
+	// outstanding
+	let block_count = addrs.len() * MAX_BLOCKS_PER_PEER + downloads.in_flight_count();
 	let hashes_to_get = hashes
 		.iter()
-		.filter(|x| {
-			// only ask for blocks that we have not yet processed
-			// either successfully stored or in our orphan list
-			!chain.get_block(x).is_ok() && !chain.is_orphan(x)
-		})
+		.filter(|x| !chain.get_block(x).is_ok() && !chain.is_orphan(x))
 		.take(block_count)
 		.cloned()
 		.collect::<Vec<_>>();
 
-	if hashes_to_get.len() > 0 {
+	let assigned = downloads.assign(now, &hashes_to_get, &addrs);
+	if assigned.len() > 0 {
 		debug!(
 			LOGGER,
-			"block_sync: {}/{} requesting blocks {:?} from {} peers",
+			"body_sync: {}/{} requesting {} blocks from {} peers, {} in flight",
 			body_head.height,
 			header_head.height,
-			hashes_to_get,
-			peer_count,
-			);
-
-		for hash in hashes_to_get.clone() {
-			// TODO - Is there a threshold where we sync from most_work_peer (not more_work_peer)?
-			let peer = peers.more_work_peer();
-			if let Some(peer) = peer {
-				if let Ok(peer) = peer.try_read() {
-					let _ = peer.send_block_request(hash);
-				}
-			}
+			assigned.len(),
+			addrs.len(),
+			downloads.in_flight_count(),
+		);
+	}
+	for (hash, addr) in assigned {
+		let sent = match peers.get_connected_peer(&addr) {
+			Some(peer) => match peer.try_read() {
+				Ok(peer) => peer.send_block_request(hash).is_ok(),
+				Err(_) => false,
+			},
+			None => false,
+		};
+		if !sent {
+			downloads.cancel(&hash);
 		}
 	}
 }
 
-pub fn header_sync(peers: Peers, chain: Arc<chain::Chain>) {
+/// Asks the most worked peer for the headers following our header chain
+/// and, when it's far enough ahead, the peers that can serve header ranges
+/// for the following headers, all in parallel.
+pub fn header_sync(peers: Peers, chain: Arc<chain::Chain>, header_ranges: &HeaderRanges) {
 	if let Ok(header_head) = chain.get_header_head() {
 		let difficulty = header_head.total_difficulty;
 
@@ -163,12 +498,39 @@ pub fn header_sync(peers: Peers, chain: Arc<chain::Chain>) {
 						peer.clone(),
 						chain.clone(),
 					);
+					request_header_ranges(&peers, &chain, header_ranges, p.info.height);
 				}
 			}
 		}
 	}
 }
 
+// Requests the headers past the ones our locator request gets us by ranges,
+// from the peers with more work than our header chain that support it.
+fn request_header_ranges(
+	peers: &Peers,
+	chain: &chain::Chain,
+	header_ranges: &HeaderRanges,
+	max_height: u64,
+) {
+	let (sync_head, header_head) = match (chain.get_sync_head(), chain.get_header_head()) {
+		(Ok(sync_head), Ok(header_head)) => (sync_head, header_head),
+		_ => return,
+	};
+	let range_peers = peers
+		.more_work_peers()
+		.into_iter()
+		.filter(|p| match p.try_read() {
+			Ok(p) => {
+				p.info.capabilities.contains(p2p::Capabilities::HEADER_RANGES)
+					&& p.info.total_difficulty > header_head.total_difficulty
+			}
+			Err(_) => false,
+		})
+		.collect::<Vec<_>>();
+	header_ranges.request(sync_head.height, max_height, &range_peers);
+}
+
 /// Request some block headers from a peer to advance us.
 fn request_headers(
 	peer: Arc<RwLock<Peer>>,
@@ -195,32 +557,31 @@ fn request_headers(
 
 
 /// Whether we're currently syncing the chain or we're fully caught up and
-/// just receiving blocks through gossip.
-pub fn needs_syncing(
-	currently_syncing: Arc<AtomicBool>,
-	peers: Peers,
-	chain: Arc<chain::Chain>) -> bool {
+/// just receiving blocks through gossip. Moves the sync state to the stage
+/// we're at and updates its progress.
+pub fn needs_syncing(sync_state: &SyncState, peers: &Peers, chain: &chain::Chain) -> bool {
 
 	let local_diff = peers.total_difficulty();
 	let peer = peers.most_work_peer();
 
 	// if we're already syncing, we're caught up if no peer has a higher
 	// difficulty than us
-	if currently_syncing.load(Ordering::Relaxed) {
-		if let Some(peer) = peer {
+	if sync_state.is_syncing() {
+		if let Some(ref peer) = peer {
 			if let Ok(peer) = peer.try_read() {
 				if peer.info.total_difficulty <= local_diff {
 					info!(LOGGER, "synchronized at {:?} @ {:?}", local_diff, chain.head().unwrap().height);
-					currently_syncing.store(false, Ordering::Relaxed);
+					sync_state.update(SyncStatus::NoSync);
 					let _ = chain.reset_head();
+					let _ = chain.clear_sync_blocks();
 				}
 			}
 		} else {
 			info!(LOGGER, "sync: no peers available, disabling sync");
-			currently_syncing.store(false, Ordering::Relaxed);
+			sync_state.update(SyncStatus::NoSync);
 		}
 	} else {
-		if let Some(peer) = peer {
+		if let Some(ref peer) = peer {
 			if let Ok(peer) = peer.try_read() {
 				// sum the last 5 difficulties to give us the threshold
 				let threshold = chain
@@ -237,12 +598,47 @@ pub fn needs_syncing(
 						peer.info.total_difficulty,
 						threshold,
 					);
-					currently_syncing.store(true, Ordering::Relaxed);
+					let header_height = chain.get_header_head().map(|h| h.height).unwrap_or(0);
+					sync_state.update(SyncStatus::HeaderSync {
+						current_height: header_height,
+						highest_height: peer.info.height,
+					});
 				}
 			}
 		}
 	}
-	currently_syncing.load(Ordering::Relaxed)
+
+	if sync_state.is_syncing() {
+		if let Some(status) = peer.and_then(|p| sync_progress(&p, chain)) {
+			sync_state.update(status);
+		}
+	}
+	sync_state.is_syncing()
+}
+
+// Sync stage we're at and its progress against the provided most worked
+// peer: header sync as long as the peer has more work than our header chain,
+// body sync until we have all the blocks of our header chain.
+fn sync_progress(peer: &Arc<RwLock<Peer>>, chain: &chain::Chain) -> Option<SyncStatus> {
+	let peer = match peer.try_read() {
+		Ok(peer) => peer,
+		Err(_) => return None,
+	};
+	let (head, header_head) = match (chain.head(), chain.get_header_head()) {
+		(Ok(head), Ok(header_head)) => (head, header_head),
+		_ => return None,
+	};
+	if peer.info.total_difficulty > header_head.total_difficulty {
+		Some(SyncStatus::HeaderSync {
+			current_height: header_head.height,
+			highest_height: peer.info.height,
+		})
+	} else {
+		Some(SyncStatus::BodySync {
+			current_height: head.height,
+			highest_height: header_head.height,
+		})
+	}
 }
 
 /// We build a locator based on sync_head.
@@ -288,6 +684,105 @@ fn get_locator_heights(height: u64) -> Vec<u64> {
 #[cfg(test)]
 mod test {
 	use super::*;
+	use core::core::hash::ZERO_HASH;
+
+	fn addr(port: u16) -> SocketAddr {
+		format!("10.0.0.1:{}", port).parse().unwrap()
+	}
+
+	fn range(start: u64, previous: Hash) -> Vec<BlockHeader> {
+		let mut header = BlockHeader::default();
+		header.height = start;
+		header.previous = previous;
+		vec![header]
+	}
+
+	#[test]
+	fn header_ranges_request_receive() {
+		let ranges = HeaderRanges::new();
+		let now = time::now_utc();
+		let peers = vec![addr(1), addr(2)];
+		let (first, second) = (HEADER_RANGE_SIZE + 1, 2 * HEADER_RANGE_SIZE + 1);
+
+		// the ranges past the locator headers go to one peer each
+		assert_eq!(
+			ranges.assign(now, 0, 3 * HEADER_RANGE_SIZE, &peers),
+			vec![(first, addr(1)), (second, addr(2))]
+		);
+		assert!(ranges.assign(now, 0, 3 * HEADER_RANGE_SIZE, &peers).is_empty());
+
+		// headers we didn't request this range from go through as usual
+		let known = BlockHeader::default().hash();
+		let have = |h: &Hash| *h == known;
+		assert!(ranges.receive_with(range(second, ZERO_HASH), addr(1), &have).is_some());
+
+		// the second range, ahead of our header chain, is kept for later
+		assert!(ranges.receive_with(range(second, ZERO_HASH), addr(2), &have).is_none());
+		assert!(ranges.next_ready_with(0, &have).is_none());
+		assert!(ranges.receive_with(range(first, known), addr(1), &have).is_some());
+
+		// and ready once the headers preceding it got added
+		let (headers, from) = ranges.next_ready_with(first, |h| *h == ZERO_HASH).unwrap();
+		assert_eq!(headers[0].height, second);
+		assert_eq!(from, addr(2));
+		assert!(ranges.next_ready_with(first, |_| true).is_none());
+	}
+
+	#[test]
+	fn header_ranges_reassign() {
+		let ranges = HeaderRanges::new();
+		let now = time::now_utc();
+		let first = HEADER_RANGE_SIZE + 1;
+		let max_height = 2 * HEADER_RANGE_SIZE;
+		assert_eq!(ranges.assign(now, 0, max_height, &[addr(1)]), vec![(first, addr(1))]);
+
+		// the peer went away
+		assert_eq!(ranges.assign(now, 0, max_height, &[addr(2)]), vec![(first, addr(2))]);
+
+		// the peer didn't answer in time
+		let later = now + time::Duration::seconds(HEADER_RANGE_TIMEOUT_SECS + 1);
+		assert!(ranges.assign(now, 0, max_height, &[addr(2)]).is_empty());
+		assert_eq!(ranges.assign(later, 0, max_height, &[addr(2)]), vec![(first, addr(2))]);
+
+		// a range not on our chain gets dropped once our chain got past it
+		assert!(ranges.receive_with(range(first, ZERO_HASH), addr(2), |_| false).is_none());
+		assert!(ranges.next_ready_with(first, |_| false).is_none());
+		assert!(ranges.received.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn sync_source_rotation() {
+		let mut sources = SyncSources::new();
+		let start = sources.window_start;
+		let peers = vec![addr(1), addr(2), addr(3)];
+
+		// starting with the most worked peer
+		assert_eq!(sources.select_primary(start, Some(addr(2)), &peers), Some(addr(2)));
+
+		// not rotated before the end of the window, however slow
+		for _ in 0..10 {
+			sources.credit(addr(3));
+		}
+		assert_eq!(sources.select_primary(start, None, &peers), Some(addr(2)));
+
+		// nor for a peer that isn't at least twice as fast
+		let window = time::Duration::seconds(SOURCE_WINDOW_SECS + 1);
+		for _ in 0..6 {
+			sources.credit(addr(2));
+		}
+		assert_eq!(sources.select_primary(start + window, None, &peers), Some(addr(2)));
+		assert!(sources.received.is_empty());
+
+		// but to a faster one
+		sources.credit(addr(2));
+		sources.credit(addr(3));
+		sources.credit(addr(3));
+		let next = start + window + window;
+		assert_eq!(sources.select_primary(next, None, &peers), Some(addr(3)));
+
+		// and away from one that went away
+		assert_eq!(sources.select_primary(next, Some(addr(1)), &peers[..2]), Some(addr(1)));
+	}
 
 	#[test]
 	fn test_get_locator_heights() {
@@ -0,0 +1,276 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block templates served to external mining software through the API. A
+//! template is a candidate block built on top of the chain head like the
+//! ones the internal miner works on, its coinbase going to the wallet of the
+//! mining configuration. Templates are kept until the chain moves to the
+//! next height, solutions found for them being processed like the blocks the
+//! internal miner finds.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use time;
+
+use api;
+use chain;
+use core::core::{Block, BlockHeader, Proof};
+use core::core::hash::Hashed;
+use core::global;
+use core::ser;
+use keychain::Identifier;
+use miner::{self, Miner};
+use pow::mining::HeaderPartWriter;
+use types::Error;
+use util;
+use util::LOGGER;
+
+// Maximum number of templates kept for the current height, the oldest ones
+// being dropped first
+const MAX_TEMPLATES: usize = 100;
+
+// Name the blocks found through the API are credited to in the mining stats
+const FOUND_BY: &'static str = "mining api";
+
+/// Seconds the wallet gets to build the coinbase of a template before the
+/// request fails, instead of waiting for the wallet indefinitely like the
+/// internal miner does.
+pub const COINBASE_TIMEOUT_SECS: u64 = 10;
+
+// Templates issued at the height currently mined, identified by their
+// position since the first one issued at that height
+struct Templates {
+	height: u64,
+	first_id: u64,
+	blocks: Vec<Block>,
+	// coinbase key reused while the height doesn't change, so the wallet
+	// doesn't derive a new key for each template
+	key_id: Option<Identifier>,
+}
+
+impl Templates {
+	fn new(height: u64) -> Templates {
+		Templates {
+			height: height,
+			first_id: 0,
+			blocks: vec![],
+			key_id: None,
+		}
+	}
+
+	// Keeps a new template, dropping the oldest one when too many have been
+	// issued, and returns its id.
+	fn add(&mut self, b: Block) -> u64 {
+		let template_id = self.first_id + self.blocks.len() as u64;
+		self.blocks.push(b);
+		if self.blocks.len() > MAX_TEMPLATES {
+			self.blocks.remove(0);
+			self.first_id += 1;
+		}
+		template_id
+	}
+
+	// The template with the provided id, if issued for the provided height
+	// and still kept
+	fn get(&self, height: u64, template_id: u64) -> Option<&Block> {
+		if self.height != height {
+			return None;
+		}
+		template_id
+			.checked_sub(self.first_id)
+			.and_then(|n| self.blocks.get(n as usize))
+	}
+}
+
+/// Builds the block templates requested through the API, keeping track of
+/// the ones issued, and processes the solved blocks submitted back.
+pub struct BlockTemplates {
+	chain: Arc<chain::Chain>,
+	sync_state: Arc<chain::SyncState>,
+	miner: Miner,
+	stats: Arc<RwLock<api::MiningStats>>,
+	templates: RwLock<Templates>,
+}
+
+impl BlockTemplates {
+	/// Templates built by the provided miner, on top of the chain head. The
+	/// blocks found get recorded in the mining stats.
+	pub fn new(
+		chain: Arc<chain::Chain>,
+		sync_state: Arc<chain::SyncState>,
+		mut miner: Miner,
+		stats: Arc<RwLock<api::MiningStats>>,
+	) -> BlockTemplates {
+		miner.set_coinbase_timeout(Duration::from_secs(COINBASE_TIMEOUT_SECS));
+		BlockTemplates {
+			chain: chain,
+			sync_state: sync_state,
+			miner: miner,
+			stats: stats,
+			templates: RwLock::new(Templates::new(0)),
+		}
+	}
+
+	// Processes a solved block like the ones the internal miner finds: added
+	// to the chain and broadcast to our peers. The chain checks the proof of
+	// work meets the block difficulty.
+	fn process_block(&self, b: Block) -> Result<BlockHeader, String> {
+		let header = b.header.clone();
+		let hash = header.hash();
+		info!(
+			LOGGER,
+			"Block {} at height {} submitted through the mining api, adding it.",
+			hash,
+			header.height
+		);
+		util::tracing::start("block", &hash.to_hex(), FOUND_BY);
+		self.chain
+			.process_block(b.clone(), chain::MINE)
+			.map_err(|e| format!("block rejected: {:?}", e))?;
+		if let Some(key_path) = self.miner.coinbase_key_path() {
+			miner::coinbase_accepted(&key_path, &b);
+		}
+		self.stats.write().unwrap().add_block_found(
+			header.height,
+			hash.to_hex(),
+			FOUND_BY.to_string(),
+			time::get_time().sec,
+		);
+		Ok(header)
+	}
+}
+
+impl api::BlockTemplateProvider for BlockTemplates {
+	fn build_template(&self) -> Result<api::BlockTemplate, String> {
+		if self.sync_state.is_syncing() {
+			return Err("the node is syncing".to_string());
+		}
+		let head = self.chain.head_header().map_err(|e| format!("{:?}", e))?;
+		let height = head.height + 1;
+
+		// built without holding the lock, the wallet may take a while to
+		// provide the coinbase
+		let key_id = {
+			let templates = self.templates.read().unwrap();
+			if templates.height == height {
+				templates.key_id.clone()
+			} else {
+				None
+			}
+		};
+		let res = match self.miner.build_block(&head, key_id) {
+			// the wallet may not know the key anymore, derive a new one
+			Err(Error::Chain(chain::Error::DuplicateCommitment(_))) => {
+				self.miner.build_block(&head, None)
+			}
+			res => res,
+		};
+		let (b, block_fees) = res.map_err(|e| format!("{:?}", e))?;
+
+		let template_id = {
+			let mut templates = self.templates.write().unwrap();
+			if templates.height > height {
+				return Err("the chain head moved while building the template".to_string());
+			}
+			if templates.height != height {
+				*templates = Templates::new(height);
+			}
+			templates.key_id = block_fees.key_id();
+			templates.add(b.clone())
+		};
+		debug!(
+			LOGGER,
+			"Issued block template {} at height {}", template_id, b.header.height
+		);
+
+		let mut header_parts = HeaderPartWriter::default();
+		ser::Writeable::write(&b.header, &mut header_parts).unwrap();
+		let (pre_nonce, post_nonce) = header_parts.parts_as_hex_strings();
+		Ok(api::BlockTemplate {
+			template_id: template_id,
+			height: b.header.height,
+			previous: b.header.previous.to_hex(),
+			difficulty: b.header.difficulty.into_num(),
+			pre_nonce: pre_nonce,
+			post_nonce: post_nonce,
+			block: util::to_hex(ser::ser_vec(&b).unwrap()),
+		})
+	}
+
+	fn submit_solution(
+		&self,
+		template_id: u64,
+		nonce: u64,
+		pow: Vec<u32>,
+	) -> Result<BlockHeader, String> {
+		if pow.len() != global::proofsize() {
+			return Err(format!(
+				"proof of work of {} nonces, expected {}",
+				pow.len(),
+				global::proofsize()
+			));
+		}
+		let head = self.chain.head_header().map_err(|e| format!("{:?}", e))?;
+		let mut b = {
+			let templates = self.templates.read().unwrap();
+			match templates.get(head.height + 1, template_id) {
+				Some(b) => b.clone(),
+				None => return Err(format!("unknown or stale template {}", template_id)),
+			}
+		};
+		b.header.nonce = nonce;
+		b.header.pow = Proof::new(pow);
+		self.process_block(b)
+	}
+
+	fn submit_block(&self, b: Block) -> Result<BlockHeader, String> {
+		self.process_block(b)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn block(nonce: u64) -> Block {
+		let mut b = Block::default();
+		b.header.nonce = nonce;
+		b
+	}
+
+	#[test]
+	fn issued_templates() {
+		let mut templates = Templates::new(10);
+		assert_eq!(templates.add(block(0)), 0);
+		assert_eq!(templates.add(block(1)), 1);
+		assert_eq!(templates.get(10, 1).unwrap().header.nonce, 1);
+		assert!(templates.get(10, 2).is_none());
+		// stale once the chain moved on
+		assert!(templates.get(11, 1).is_none());
+	}
+
+	#[test]
+	fn oldest_templates_dropped() {
+		let mut templates = Templates::new(10);
+		for n in 0..(MAX_TEMPLATES as u64 + 5) {
+			assert_eq!(templates.add(block(n)), n);
+		}
+		assert_eq!(templates.blocks.len(), MAX_TEMPLATES);
+		assert!(templates.get(10, 4).is_none());
+		assert_eq!(templates.get(10, 5).unwrap().header.nonce, 5);
+		let last = MAX_TEMPLATES as u64 + 4;
+		assert_eq!(templates.get(10, last).unwrap().header.nonce, last);
+	}
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::convert::From;
+use std::path::MAIN_SEPARATOR;
 
 use api;
 use chain;
@@ -40,6 +41,14 @@ pub enum Error {
 	/// Error originating from wallet API.
 	Wallet(wallet::Error),
 	Cuckoo(pow::cuckoo::Error),
+	/// Error assigning ports or writing the discovery file
+	Ports(String),
+	/// Error starting the stratum server
+	Stratum(String),
+	/// Error exporting or importing a chain dump
+	ChainDump(String),
+	/// Error building a coinbase with the configured account keys
+	Coinbase(String),
 }
 
 impl From<core::block::Error> for Error {
@@ -102,16 +111,50 @@ impl Default for Seeding {
 	}
 }
 
+/// Inclusive range of ports to pick from when ports are auto-assigned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortRange {
+	/// First port of the range
+	pub start: u16,
+	/// Last port of the range
+	pub end: u16,
+}
+
 /// Full server configuration, aggregating configurations required for the
 /// different components.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
-	/// Directory under which the rocksdb stores will be created
+	/// Directory under which the LMDB stores will be created
 	pub db_root: String,
 
 	/// Network address for the Rest API HTTP server.
 	pub api_http_addr: String,
 
+	/// When set, the Rest API only accepts TLS connections, using the
+	/// configured certificate.
+	#[serde(default)]
+	pub api_tls_config: Option<api::TLSConfig>,
+
+	/// Cross-origin requests allowed on the Rest API, for browser based
+	/// applications. Only same-origin requests are allowed if unset.
+	#[serde(default)]
+	pub api_cors_config: Option<api::CorsConfig>,
+
+	/// Limits on the Rest API requests of each client IP, for nodes exposing
+	/// their API publicly. No limits if unset.
+	#[serde(default)]
+	pub api_rate_limit_config: Option<api::RateLimitConfig>,
+
+	/// File holding the secret required by the owner API endpoints, generated
+	/// on first run, .api_secret in the db root if unset.
+	#[serde(default)]
+	pub api_secret_path: Option<String>,
+
+	/// File holding the secret required by the other API endpoints, generated
+	/// on first run. The owner secret is accepted as well. Left open if unset.
+	#[serde(default)]
+	pub foreign_api_secret_path: Option<String>,
+
 	/// Setup the server for tests, testnet or mainnet
 	#[serde(default)]
 	pub chain_type: ChainTypes,
@@ -135,13 +178,46 @@ pub struct ServerConfig {
 	/// Configuration for the mining daemon
 	pub mining_config: Option<pow::types::MinerConfig>,
 
+	/// Configuration for the stratum server external miners connect to
+	#[serde(default)]
+	pub stratum_mining_config: Option<StratumServerConfig>,
+
 	/// Transaction pool configuration
 	#[serde(default)]
 	pub pool_config: pool::PoolConfig,
 
+	/// Chain configuration, including block body pruning
+	#[serde(default)]
+	pub chain_config: chain::ChainConfig,
+
+	/// When set, the API, p2p and wallet listener ports are picked from this
+	/// range instead of using the configured ones.
+	#[serde(default)]
+	pub port_range: Option<PortRange>,
+
+	/// File the auto-assigned ports get written to, defaults to ports.json
+	/// under the db root.
+	#[serde(default)]
+	pub discovery_file: Option<String>,
+
+	/// Memory budget, in megabytes, for the data held by caches, orphan
+	/// pools, the transaction pool and peer send queues. Unlimited if unset.
+	#[serde(default)]
+	pub memory_budget: Option<u64>,
+
 	/// Whether to skip the sync timeout on startup
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
+
+	/// Whether to show the terminal dashboard when running the server in the
+	/// foreground, instead of logging to stdout
+	#[serde(default)]
+	pub run_tui: Option<bool>,
+
+	/// Configuration file the server was started from, if any, which the
+	/// owner API can reload some settings from while running
+	#[serde(skip)]
+	pub config_file_path: Option<String>,
 }
 
 impl Default for ServerConfig {
@@ -149,14 +225,72 @@ impl Default for ServerConfig {
 		ServerConfig {
 			db_root: ".grin".to_string(),
 			api_http_addr: "0.0.0.0:13413".to_string(),
+			api_tls_config: None,
+			api_cors_config: None,
+			api_rate_limit_config: None,
+			api_secret_path: None,
+			foreign_api_secret_path: None,
 			capabilities: p2p::Capabilities::FULL_NODE,
 			seeding_type: Seeding::default(),
 			seeds: None,
 			p2p_config: p2p::P2PConfig::default(),
 			mining_config: Some(pow::types::MinerConfig::default()),
+			stratum_mining_config: None,
 			chain_type: ChainTypes::default(),
 			pool_config: pool::PoolConfig::default(),
+			chain_config: chain::ChainConfig::default(),
+			port_range: None,
+			discovery_file: None,
+			memory_budget: None,
 			skip_sync_wait: Some(true),
+			run_tui: None,
+			config_file_path: None,
+		}
+	}
+}
+
+impl ServerConfig {
+	/// File holding the secret of the owner API endpoints, which always
+	/// require one as they manage the node
+	pub fn owner_api_secret_path(&self) -> String {
+		match self.api_secret_path {
+			Some(ref path) => path.clone(),
+			None => format!("{}{}{}", self.db_root, MAIN_SEPARATOR, ".api_secret"),
+		}
+	}
+}
+
+/// Stratum server configuration. Blocks mined through the stratum server pay
+/// their reward to the wallet configured in the mining configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StratumServerConfig {
+	/// Whether the stratum server is started
+	pub enable_stratum_server: bool,
+
+	/// Network address the stratum server listens on
+	pub stratum_server_addr: String,
+
+	/// Seconds after which a new job is built, picking up the latest
+	/// transactions from the pool, even if the chain head hasn't changed
+	pub attempt_time_per_block: u32,
+
+	/// Minimum difficulty of the shares submitted by workers, shares below
+	/// it are rejected
+	pub minimum_share_difficulty: u64,
+
+	/// Password workers have to log in with, any is accepted if not set
+	#[serde(default)]
+	pub stratum_server_password: Option<String>,
+}
+
+impl Default for StratumServerConfig {
+	fn default() -> StratumServerConfig {
+		StratumServerConfig {
+			enable_stratum_server: false,
+			stratum_server_addr: "127.0.0.1:13416".to_string(),
+			attempt_time_per_block: 60,
+			minimum_share_difficulty: 1,
+			stratum_server_password: None,
 		}
 	}
 }
@@ -172,4 +306,51 @@ pub struct ServerStats {
 	pub peer_count: u32,
 	/// Chain head
 	pub head: chain::Tip,
+	/// Sync stage and progress
+	pub sync_status: chain::SyncStatus,
+	/// Connected peers
+	pub peer_stats: Vec<PeerStats>,
+	/// Number of transactions in the pool
+	pub pool_size: usize,
+	/// Number of transactions in the stem pool
+	pub stem_pool_size: usize,
+	/// Stats of the miner and the stratum server
+	pub mining_stats: api::MiningStats,
+	/// Messages exchanged with our peers, by message type
+	pub msg_stats: Vec<p2p::MsgStats>,
+}
+
+/// Stats of a connected peer
+#[derive(Clone, Debug)]
+pub struct PeerStats {
+	/// Address of the peer
+	pub addr: String,
+	/// Software the peer runs
+	pub user_agent: String,
+	/// Protocol version of the peer
+	pub version: u32,
+	/// Whether the peer connected to us or we to it
+	pub direction: String,
+	/// Total difficulty of the peer chain
+	pub total_difficulty: u64,
+	/// Height of the peer chain
+	pub height: u64,
+	/// Messages from the peer we had to skip, corrupted or unknown
+	pub skipped_msgs: u64,
+}
+
+impl PeerStats {
+	/// Stats of the provided peer
+	pub fn from_peer(peer: &p2p::Peer) -> PeerStats {
+		let info = &peer.info;
+		PeerStats {
+			addr: info.addr.to_string(),
+			user_agent: info.user_agent.clone(),
+			version: info.version,
+			direction: format!("{:?}", info.direction),
+			total_difficulty: info.total_difficulty.into_num(),
+			height: info.height,
+			skipped_msgs: peer.skipped_msgs(),
+		}
+	}
 }
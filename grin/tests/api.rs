@@ -105,6 +105,14 @@ fn simple_server_wallet() {
 	assert!(last_block_by_hash.is_ok());
 	let last_block_by_hash_compact = get_block_by_hash_compact(&base_addr, api_server_port, &block_hash);
 	assert!(last_block_by_hash_compact.is_ok());
+	let blocks = get_blocks_by_range(&base_addr, api_server_port, 0, height);
+	assert_eq!(blocks.unwrap().len() as u64, height + 1);
+
+	warn!(LOGGER, "Testing header handler");
+	let header_by_height = get_header(&base_addr, api_server_port, &height.to_string());
+	assert_eq!(header_by_height.unwrap().hash, block_hash);
+	let header_by_hash = get_header(&base_addr, api_server_port, &block_hash);
+	assert_eq!(header_by_hash.unwrap().height, height);
 
 	warn!(LOGGER, "Testing chain utxo handler");
 	let start_height = 0;
@@ -116,6 +124,12 @@ fn simple_server_wallet() {
 	assert!(utxos_by_ids1.is_ok());
 	let utxos_by_ids2 = get_utxos_by_ids2(&base_addr, api_server_port, ids.clone());
 	assert!(utxos_by_ids2.is_ok());
+	// the number of commitments looked up at once is capped
+	let too_many = vec![ids[0].clone(); 501];
+	match get_utxos_by_ids1(&base_addr, api_server_port, too_many) {
+		Err(Error::API(api::Error::Argument(_))) => {}
+		res => panic!("expected an argument error, got {:?}", res),
+	}
 
 	warn!(LOGGER, "Testing sumtree handler");
 	let roots = get_sumtree_roots(&base_addr, api_server_port);
@@ -132,6 +146,9 @@ fn simple_server_wallet() {
 	assert!(last_10_kernels.is_ok());
 	let last_5_kernels = getsumtree_lastkernels(&base_addr, api_server_port, 5);
 	assert!(last_5_kernels.is_ok());
+	let outputs = get_sumtree_outputs(&base_addr, api_server_port, 1, 2).unwrap();
+	assert!(outputs.outputs.len() <= 2);
+	assert!(outputs.last_retrieved_index <= outputs.highest_index);
 
 	//let some more mining happen, make sure nothing pukes
 	thread::sleep(time::Duration::from_millis(5000));
@@ -180,50 +197,74 @@ fn test_p2p() {
 	let base_addr = server_config_one.base_addr;
 	let api_server_port = server_config_one.api_server_port;
 
+	// The owner endpoints always require the secret, generated by the node
+	let api_secret =
+		api::read_api_secret("target/test_servers/p2p_server_one/.grin/.api_secret").unwrap();
+	let url = format!("http://{}:{}/v1/peers/connected", base_addr, api_server_port);
+	match api::client::get::<Vec<p2p::PeerInfo>>(url.as_str()) {
+		Err(api::Error::Unauthorized) => {}
+		res => panic!("expected unauthorized, got {:?}", res),
+	}
+	let url = format!("http://{}:{}/v1/chain/reorg/accept", base_addr, api_server_port);
+	match api::client::post(url.as_str(), &"") {
+		Err(api::Error::Unauthorized) => {}
+		res => panic!("expected unauthorized, got {:?}", res),
+	}
+
 	// Check that when we get peer connected the peer is here
-	let peers_connected = get_connected_peers(&base_addr, api_server_port);
+	let peers_connected = get_connected_peers(&base_addr, api_server_port, &api_secret);
 	assert!(peers_connected.is_ok());
 	assert_eq!(peers_connected.unwrap().len(), 1);
 
 	// Check that peer all is also working
-	let mut peers_all = get_all_peers(&base_addr, api_server_port);
+	let mut peers_all = get_all_peers(&base_addr, api_server_port, &api_secret);
 	assert!(peers_all.is_ok());
 	assert_eq!(peers_all.unwrap().len(), 1);
 
 	// Check that the peer status is Healthy
 	let addr =  format!("{}:{}", server_config_two.base_addr, server_config_two.p2p_server_port);
-	let peer = get_peer(&base_addr, api_server_port, &addr);
+	let peer = get_peer(&base_addr, api_server_port, &api_secret, &addr);
 	assert!(peer.is_ok());
 	assert_eq!(peer.unwrap().flags, p2p::State::Healthy);
 
 	// Ban the peer
-	let ban_result = ban_peer(&base_addr, api_server_port, &addr);
+	let ban_result = ban_peer(&base_addr, api_server_port, &api_secret, &addr);
 	assert!(ban_result.is_ok());
 	thread::sleep(time::Duration::from_millis(2000));
 
 	// Check its status is banned with get peer
-	let peer = get_peer(&base_addr, api_server_port, &addr);
+	let peer = get_peer(&base_addr, api_server_port, &api_secret, &addr);
 	assert!(peer.is_ok());
 	assert_eq!(peer.unwrap().flags, p2p::State::Banned);
 
 	// Check from peer all
-	peers_all = get_all_peers(&base_addr, api_server_port);
+	peers_all = get_all_peers(&base_addr, api_server_port, &api_secret);
 	assert!(peers_all.is_ok());
 	assert_eq!(peers_all.unwrap().len(), 1);
 
 	// Unban
-	let unban_result = unban_peer(&base_addr, api_server_port, &addr);
+	let unban_result = unban_peer(&base_addr, api_server_port, &api_secret, &addr);
 	assert!(unban_result.is_ok());
 
 	// Check from peer connected
-	let peers_connected = get_connected_peers(&base_addr, api_server_port);
+	let peers_connected = get_connected_peers(&base_addr, api_server_port, &api_secret);
 	assert!(peers_connected.is_ok());
 	assert_eq!(peers_connected.unwrap().len(), 1);
 
 	// Check its status is banned with get peer
-	let peer = get_peer(&base_addr, api_server_port, &addr);
+	let peer = get_peer(&base_addr, api_server_port, &api_secret, &addr);
 	assert!(peer.is_ok());
 	assert_eq!(peer.unwrap().flags, p2p::State::Healthy);
+	// Mark it as preferred, then defunct
+	let preferred_result = set_peer_state(&base_addr, api_server_port, &api_secret, &addr, "preferred");
+	assert!(preferred_result.is_ok());
+	let peer = get_peer(&base_addr, api_server_port, &api_secret, &addr);
+	assert_eq!(peer.unwrap().flags, p2p::State::Preferred);
+
+	let defunct_result = set_peer_state(&base_addr, api_server_port, &api_secret, &addr, "defunct");
+	assert!(defunct_result.is_ok());
+	let peer = get_peer(&base_addr, api_server_port, &api_secret, &addr);
+	assert_eq!(peer.unwrap().flags, p2p::State::Defunct);
 }
 
 // Tip handler function
@@ -259,6 +300,17 @@ fn get_block_by_hash_compact(base_addr: &String, api_server_port: u16, block_has
 	api::client::get::<api::CompactBlockPrintable>(url.as_str()).map_err(|e| Error::API(e))
 }
 
+fn get_blocks_by_range(base_addr: &String, api_server_port: u16, start_height: u64, end_height: u64) -> Result<Vec<api::BlockPrintable>, Error> {
+	let url = format!("http://{}:{}/v1/blocks?start_height={}&end_height={}", base_addr, api_server_port, start_height, end_height);
+	api::client::get::<Vec<api::BlockPrintable>>(url.as_str()).map_err(|e| Error::API(e))
+}
+
+// Header handler function
+fn get_header(base_addr: &String, api_server_port: u16, id: &String) -> Result<api::BlockHeaderPrintable, Error> {
+	let url = format!("http://{}:{}/v1/headers/{}", base_addr, api_server_port, id);
+	api::client::get::<api::BlockHeaderPrintable>(url.as_str()).map_err(|e| Error::API(e))
+}
+
 // Chain utxo handler functions
 fn get_utxos_by_ids1(base_addr: &String, api_server_port: u16, ids: Vec<String>) -> Result<Vec<api::Utxo>, Error> {
 	let url = format!("http://{}:{}/v1/chain/utxos/byids?id={}", base_addr, api_server_port, ids.join(","));
@@ -287,6 +339,11 @@ fn get_sumtree_roots(base_addr: &String, api_server_port: u16)  -> Result<api::S
 	api::client::get::<api::SumTrees>(url.as_str()).map_err(|e| Error::API(e))
 }
 
+fn get_sumtree_outputs(base_addr: &String, api_server_port: u16, start_index: u64, max: u64) -> Result<api::OutputListing, Error> {
+	let url = format!("http://{}:{}/v1/sumtrees/outputs?start_index={}&max={}", base_addr, api_server_port, start_index, max);
+	api::client::get::<api::OutputListing>(url.as_str()).map_err(|e| Error::API(e))
+}
+
 fn get_sumtree_lastutxos(base_addr: &String, api_server_port: u16, n: u64) -> Result<Vec<api::SumTreeNode>, Error> {
 	let url: String;
 	if n == 0 {
@@ -329,36 +386,53 @@ fn get_ids_from_block_outputs(block_outputs: Vec<api::BlockOutputs>) -> Vec<Stri
 	ids
 }
 
-pub fn ban_peer(base_addr: &String, api_server_port: u16, peer_addr: &String) -> Result<(), Error> {
+pub fn ban_peer(base_addr: &String, api_server_port: u16, api_secret: &String, peer_addr: &String) -> Result<(), Error> {
 	let url = format!(
 		"http://{}:{}/v1/peers/{}/ban", base_addr, api_server_port, peer_addr
 	);
-	api::client::post(url.as_str(), &"").map_err(|e| Error::API(e))
+	api::client::post_with_secret(url.as_str(), &"", Some(api_secret.clone())).map_err(|e| Error::API(e))
 }
 
-pub fn unban_peer(base_addr: &String, api_server_port: u16, peer_addr: &String) -> Result<(), Error> {
+pub fn unban_peer(base_addr: &String, api_server_port: u16, api_secret: &String, peer_addr: &String) -> Result<(), Error> {
 	let url = format!(
 		"http://{}:{}/v1/peers/{}/unban",
 		base_addr,
 		api_server_port,
 		peer_addr
 	);
-	api::client::post(url.as_str(), &"").map_err(|e| Error::API(e))
+	api::client::post_with_secret(url.as_str(), &"", Some(api_secret.clone())).map_err(|e| Error::API(e))
+}
+
+pub fn set_peer_state(
+	base_addr: &String,
+	api_server_port: u16,
+	api_secret: &String,
+	peer_addr: &String,
+	state: &str,
+) -> Result<(), Error> {
+	let url = format!(
+		"http://{}:{}/v1/peers/{}/{}",
+		base_addr,
+		api_server_port,
+		peer_addr,
+		state
+	);
+	api::client::post_with_secret(url.as_str(), &"", Some(api_secret.clone())).map_err(|e| Error::API(e))
 }
 
-pub fn get_peer(base_addr: &String, api_server_port: u16, peer_addr: &String) -> Result<p2p::PeerData, Error> {
+pub fn get_peer(base_addr: &String, api_server_port: u16, api_secret: &String, peer_addr: &String) -> Result<p2p::PeerData, Error> {
 	let url = format!("http://{}:{}/v1/peers/{}", base_addr, api_server_port, peer_addr);
-	api::client::get::<p2p::PeerData>(url.as_str()).map_err(|e| Error::API(e))
+	api::client::get_with_secret::<p2p::PeerData>(url.as_str(), Some(api_secret.clone())).map_err(|e| Error::API(e))
 }
 
-pub fn get_connected_peers(base_addr: &String, api_server_port: u16) -> Result<Vec<p2p::PeerInfo>, Error> {
+pub fn get_connected_peers(base_addr: &String, api_server_port: u16, api_secret: &String) -> Result<Vec<p2p::PeerInfo>, Error> {
 	let url = format!("http://{}:{}/v1/peers/connected", base_addr, api_server_port);
-	api::client::get::<Vec<p2p::PeerInfo>>(url.as_str()).map_err(|e| Error::API(e))
+	api::client::get_with_secret::<Vec<p2p::PeerInfo>>(url.as_str(), Some(api_secret.clone())).map_err(|e| Error::API(e))
 }
 
-pub fn get_all_peers(base_addr: &String, api_server_port: u16) -> Result<Vec<p2p::PeerData>, Error> {
+pub fn get_all_peers(base_addr: &String, api_server_port: u16, api_secret: &String) -> Result<Vec<p2p::PeerData>, Error> {
 	let url = format!("http://{}:{}/v1/peers/all", base_addr, api_server_port);
-	api::client::get::<Vec<p2p::PeerData>>(url.as_str()).map_err(|e| Error::API(e))
+	api::client::get_with_secret::<Vec<p2p::PeerData>>(url.as_str(), Some(api_secret.clone())).map_err(|e| Error::API(e))
 }
 
 /// Error type wrapping underlying module errors.
@@ -171,6 +171,8 @@ impl LocalServerContainer {
 		let mut wallet_config = WalletConfig::default();
 
 		wallet_config.api_listen_port = format!("{}", config.wallet_port);
+		// test wallets are driven directly, without owner API
+		wallet_config.owner_api_listen_port = None;
 		wallet_config.check_node_api_http_addr = config.wallet_validating_node_url.clone();
 		wallet_config.data_file_dir = working_dir.clone();
 		Ok(
@@ -285,11 +287,12 @@ impl LocalServerContainer {
 		self.wallet_config = WalletConfig::default();
 
 		self.wallet_config.api_listen_port = format!("{}", self.config.wallet_port);
+		self.wallet_config.owner_api_listen_port = None;
 		self.wallet_config.check_node_api_http_addr = self.config.wallet_validating_node_url.clone();
 		self.wallet_config.data_file_dir = self.working_dir.clone();
 
 		let _=fs::create_dir_all(self.wallet_config.clone().data_file_dir);
-		wallet::WalletSeed::init_file(&self.wallet_config);
+		wallet::WalletSeed::init_file(&self.wallet_config, "");
 
 		let wallet_seed =
 			wallet::WalletSeed::from_file(&self.wallet_config).expect("Failed to read wallet seed file.");
@@ -327,7 +330,8 @@ impl LocalServerContainer {
 			minimum_confirmations,
 			dest.to_string(),
 			max_outputs,
-			selection_strategy == "all",
+			selection_strategy.parse().expect("Unknown selection strategy"),
+			None,
 			);
 		match result {
 			Ok(_) => {
@@ -338,7 +342,7 @@ impl LocalServerContainer {
 					selection_strategy,
 				)
 			}
-			Err(wallet::Error::NotEnoughFunds(available)) => {
+			Err(wallet::Error::NotEnoughFunds { available, .. }) => {
 				println!(
 					"Tx not sent: insufficient funds (max: {})",
 					core::core::amount_to_hr_string(available),
@@ -524,7 +528,7 @@ impl LocalServerContainerPool {
 				return_container_ref.lock().unwrap().push(server_ref);
 			});
 			// Not a big fan of sleeping hack here, but there appears to be a
-			// concurrency issue when creating files in rocksdb that causes
+			// concurrency issue when creating the store files that causes
 			// failure if we don't pause a bit before starting the next server
 			thread::sleep(time::Duration::from_millis(500));
 			handles.push(handle);
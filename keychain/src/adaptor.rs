@@ -0,0 +1,121 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Adaptor signatures, the building block of atomic swaps. One party picks
+//! a secret t and shares the adaptor point T = t*G. The kernel nonce then
+//! includes T on top of the nonces of both parties, so the partial
+//! signatures only add up to a valid kernel signature once t gets added to
+//! them. Whoever completes the signature reveals t to everyone that saw the
+//! partial signatures, as the difference between the final signature and
+//! their sum.
+//!
+//! As signers negate their nonce when the nonce sum requires it, the secret
+//! added on completion, and extracted afterward, may be -t instead of t.
+//! Both are tried, the right one being the one matching the adaptor point.
+//!
+//! The wallet locks transactions sent through a file on an adaptor point
+//! with these (see wallet receive --adaptor). A full swap also locks funds
+//! in a 2 of 2 output, which the wallet doesn't build yet (see
+//! `Keychain::multisig_commit`).
+
+use rand::thread_rng;
+
+use util::secp::{Secp256k1, Signature};
+use util::secp::key::{PublicKey, SecretKey};
+use util::secp::aggsig;
+
+use keychain::Error;
+
+/// New random adaptor secret, along with its adaptor point.
+pub fn new_secret(secp: &Secp256k1) -> Result<(SecretKey, PublicKey), Error> {
+	let secret = SecretKey::new(secp, &mut thread_rng());
+	let point = PublicKey::from_secret_key(secp, &secret)?;
+	Ok((secret, point))
+}
+
+/// Sum of the public nonces of both parties and of the adaptor point, used
+/// as the kernel nonce.
+pub fn nonce_sum(
+	secp: &Secp256k1,
+	their_pub_nonce: &PublicKey,
+	our_sec_nonce: &SecretKey,
+	adaptor_point: &PublicKey,
+) -> Result<PublicKey, Error> {
+	let mut sum = PublicKey::from_combination(secp, vec![their_pub_nonce, adaptor_point])?;
+	sum.add_exp_assign(secp, our_sec_nonce)?;
+	Ok(sum)
+}
+
+// the s part of a signature, as a scalar
+fn sig_scalar(secp: &Secp256k1, sig: &Signature) -> Result<SecretKey, Error> {
+	let raw = sig.to_raw_data();
+	Ok(SecretKey::from_slice(secp, &raw[32..])?)
+}
+
+// the signature with the provided scalar added to its s part
+fn add_to_sig(secp: &Secp256k1, sig: &Signature, scalar: &SecretKey) -> Result<Signature, Error> {
+	let mut s = sig_scalar(secp, sig)?;
+	s.add_assign(secp, scalar)?;
+	let mut raw = sig.to_raw_data();
+	raw[32..].copy_from_slice(&s[..]);
+	Ok(Signature::from_raw_data(&raw)?)
+}
+
+fn negate(secp: &Secp256k1, key: &SecretKey) -> Result<SecretKey, Error> {
+	Ok(secp.blind_sum(vec![], vec![key.clone()])?)
+}
+
+/// Completes the sum of the partial signatures (nonce sum included) with the
+/// adaptor secret, into a signature valid for the final public key and
+/// message.
+pub fn complete(
+	secp: &Secp256k1,
+	sig: &Signature,
+	secret: &SecretKey,
+	msg: &::util::secp::Message,
+	final_pubkey: &PublicKey,
+) -> Result<Signature, Error> {
+	for t in vec![secret.clone(), negate(secp, secret)?] {
+		let completed = add_to_sig(secp, sig, &t)?;
+		if aggsig::verify_single(secp, &completed, msg, None, final_pubkey, false) {
+			return Ok(completed);
+		}
+	}
+	Err(Error::Adaptor(
+		"adaptor secret doesn't complete the signature".to_string(),
+	))
+}
+
+/// Extracts the adaptor secret from a completed signature and the partial
+/// signatures it was built from.
+pub fn extract(
+	secp: &Secp256k1,
+	final_sig: &Signature,
+	partial_sigs: &[Signature],
+	adaptor_point: &PublicKey,
+) -> Result<SecretKey, Error> {
+	let mut partials = vec![];
+	for sig in partial_sigs {
+		partials.push(sig_scalar(secp, sig)?);
+	}
+	let diff = secp.blind_sum(vec![sig_scalar(secp, final_sig)?], partials)?;
+	for t in vec![diff.clone(), negate(secp, &diff)?] {
+		if PublicKey::from_secret_key(secp, &t)? == *adaptor_point {
+			return Ok(t);
+		}
+	}
+	Err(Error::Adaptor(
+		"signature wasn't completed with the adaptor secret".to_string(),
+	))
+}
@@ -18,7 +18,7 @@ use util::secp::{self, Secp256k1};
 use extkey::Identifier;
 use keychain::Error;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BlindingFactor(secp::key::SecretKey);
 
 impl BlindingFactor {
@@ -26,6 +26,17 @@ impl BlindingFactor {
 		BlindingFactor(secret_key)
 	}
 
+	/// The zero blinding factor. Not a valid secret key for secp, stands for
+	/// the absence of a kernel offset.
+	pub fn zero() -> BlindingFactor {
+		BlindingFactor(secp::key::ZERO_KEY)
+	}
+
+	/// Whether this is the zero blinding factor.
+	pub fn is_zero(&self) -> bool {
+		self.0 == secp::key::ZERO_KEY
+	}
+
 	pub fn secret_key(&self) -> secp::key::SecretKey {
 		self.0
 	}
@@ -256,6 +256,28 @@ impl ExtendedKey {
 		Ok(ext_key)
 	}
 
+	/// Derives the extended key of an account, the root of a key derivation
+	/// branch separate from the one of this key. Account 0 is this key itself.
+	pub fn derive_account(&self, secp: &Secp256k1, account: u32) -> Result<ExtendedKey, Error> {
+		if account == 0 {
+			return Ok(self.clone());
+		}
+		ExtendedKey::from_seed(secp, &self.account_seed(account))
+	}
+
+	/// Seed the extended key of an account (other than 0) is built from.
+	/// Holding it gives the keys of that account and none of the others.
+	pub fn account_seed(&self, account: u32) -> Vec<u8> {
+		let mut n_bytes: [u8; 4] = [0; 4];
+		BigEndian::write_u32(&mut n_bytes, account);
+
+		let mut seed = self.key[..].to_vec();
+		seed.extend_from_slice(b"account");
+		seed.extend_from_slice(&n_bytes);
+
+		blake2b(64, &self.chain_code[..], &seed[..]).as_bytes().to_vec()
+	}
+
 	/// Derive a child key from this extended key
 	pub fn derive(&self, secp: &Secp256k1, n: u32) -> Result<ChildKey, Error> {
 		let mut n_bytes: [u8; 4] = [0; 4];
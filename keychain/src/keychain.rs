@@ -23,6 +23,7 @@ use util::secp::pedersen::{Commitment, ProofMessage, ProofInfo, RangeProof};
 use util::secp::aggsig;
 use util::logger::LOGGER;
 use util::kernel_sig_msg;
+use adaptor;
 use blake2;
 use blind::{BlindSum, BlindingFactor};
 use extkey::{self, Identifier};
@@ -32,6 +33,8 @@ pub enum Error {
 	ExtendedKey(extkey::Error),
 	Secp(secp::Error),
 	KeyDerivation(String),
+	Adaptor(String),
+	Transaction(String),
 }
 
 impl From<secp::Error> for Error {
@@ -98,6 +101,31 @@ impl Keychain {
 		Ok(keychain)
 	}
 
+	/// Keychain of an account, deriving its keys on a separate branch. Outputs
+	/// of different accounts have different root key ids.
+	pub fn derive_account(&self, account: u32) -> Result<Keychain, Error> {
+		let extkey = self.extkey.derive_account(&self.secp, account)?;
+		Ok(Keychain {
+			secp: self.secp.clone(),
+			extkey: extkey,
+			aggsig_context: Arc::new(RwLock::new(None)),
+			key_overrides: self.key_overrides.clone(),
+			key_derivation_cache: Arc::new(RwLock::new(HashMap::new())),
+		})
+	}
+
+	/// Seed of the keychain of an account, from which `from_seed` builds the
+	/// same keychain as `derive_account`, to hand out the keys of a single
+	/// account. The default account (0) has no separate seed.
+	pub fn account_seed(&self, account: u32) -> Result<Vec<u8>, Error> {
+		if account == 0 {
+			return Err(Error::KeyDerivation(
+				"the default account uses the wallet root keys".to_string(),
+			));
+		}
+		Ok(self.extkey.account_seed(account))
+	}
+
 	/// For testing - probably not a good idea to use outside of tests.
 	pub fn from_random_seed() -> Result<Keychain, Error> {
 		let seed: String = thread_rng().gen_ascii_chars().take(16).collect();
@@ -253,15 +281,18 @@ impl Keychain {
 			.filter_map(|k| self.derived_key(&k).ok())
 			.collect();
 
+		// zero blinding factors (no offset) aren't valid keys
 		pos_keys.extend(&blind_sum
 			.positive_blinding_factors
 			.iter()
+			.filter(|b| !b.is_zero())
 			.map(|b| b.secret_key())
 			.collect::<Vec<SecretKey>>());
 
 		neg_keys.extend(&blind_sum
 			.negative_blinding_factors
 			.iter()
+			.filter(|b| !b.is_zero())
 			.map(|b| b.secret_key())
 			.collect::<Vec<SecretKey>>());
 
@@ -278,6 +309,17 @@ impl Keychain {
 		});
 	}
 
+	/// Restores a context saved by an earlier invocation, to resume the
+	/// exchange it was created for
+	pub fn aggsig_restore_context(&self, sec_key: SecretKey, sec_nonce: SecretKey) {
+		let mut context = self.aggsig_context.write().unwrap();
+		*context = Some(AggSigTxContext{
+			sec_key: sec_key,
+			sec_nonce: sec_nonce,
+			output_ids: vec![],
+		});
+	}
+
 	/// Tracks an output contributing to my excess value (if it needs to
 	/// be kept between invocations
 	pub fn aggsig_add_output(&self, id: &Identifier){
@@ -365,6 +407,87 @@ impl Keychain {
 		Ok(sig)
 	}
 
+	/// New adaptor secret and its adaptor point, to lock a kernel signature
+	/// on the secret (i.e. for an atomic swap).
+	pub fn adaptor_new_secret(&self) -> Result<(SecretKey, PublicKey), Error> {
+		adaptor::new_secret(&self.secp)
+	}
+
+	/// Our partial signature for a kernel whose nonce includes the adaptor
+	/// point, on top of both public nonces. Adding it to the other party's
+	/// partial signature isn't enough for a valid kernel signature, the
+	/// adaptor secret being needed as well.
+	pub fn aggsig_calculate_adaptor_partial_sig(
+		&self,
+		other_pub_nonce: &PublicKey,
+		adaptor_point: &PublicKey,
+		fee: u64,
+		lock_height: u64,
+	) -> Result<Signature, Error> {
+		let (_, sec_nonce) = self.aggsig_get_private_keys();
+		let nonce_sum = adaptor::nonce_sum(&self.secp, other_pub_nonce, &sec_nonce, adaptor_point)?;
+		let msg = secp::Message::from_slice(&kernel_sig_msg(fee, lock_height))?;
+		self.aggsig_sign_single(&msg, Some(&sec_nonce), Some(&nonce_sum), Some(&nonce_sum))
+	}
+
+	/// Verifies the other party's partial signature for a kernel locked on
+	/// the provided adaptor point.
+	pub fn aggsig_verify_adaptor_partial_sig(
+		&self,
+		sig: &Signature,
+		other_pub_nonce: &PublicKey,
+		adaptor_point: &PublicKey,
+		pubkey: &PublicKey,
+		fee: u64,
+		lock_height: u64,
+	) -> bool {
+		let (_, sec_nonce) = self.aggsig_get_private_keys();
+		let nonce_sum = match adaptor::nonce_sum(&self.secp, other_pub_nonce, &sec_nonce, adaptor_point) {
+			Ok(n) => n,
+			Err(_) => return false,
+		};
+		let msg = secp::Message::from_slice(&kernel_sig_msg(fee, lock_height)).unwrap();
+		self.aggsig_verify_single(sig, &msg, Some(&nonce_sum), pubkey, true)
+	}
+
+	/// Final signature of a kernel locked on an adaptor point, from both
+	/// partial signatures and the adaptor secret. Publishing it reveals the
+	/// secret to the other party, see `aggsig_extract_adaptor_secret`.
+	pub fn aggsig_calculate_adaptor_final_sig(
+		&self,
+		their_sig: &Signature,
+		our_sig: &Signature,
+		their_pub_nonce: &PublicKey,
+		adaptor_secret: &SecretKey,
+		final_pubkey: &PublicKey,
+		fee: u64,
+		lock_height: u64,
+	) -> Result<Signature, Error> {
+		let (_, sec_nonce) = self.aggsig_get_private_keys();
+		let adaptor_point = PublicKey::from_secret_key(&self.secp, adaptor_secret)?;
+		let nonce_sum = adaptor::nonce_sum(&self.secp, their_pub_nonce, &sec_nonce, &adaptor_point)?;
+		let sig = aggsig::add_signatures_single(&self.secp, their_sig, our_sig, &nonce_sum)?;
+		let msg = secp::Message::from_slice(&kernel_sig_msg(fee, lock_height))?;
+		adaptor::complete(&self.secp, &sig, adaptor_secret, &msg, final_pubkey)
+	}
+
+	/// Adaptor secret revealed by the final signature of a kernel built
+	/// from the provided partial signatures.
+	pub fn aggsig_extract_adaptor_secret(
+		&self,
+		final_sig: &Signature,
+		their_sig: &Signature,
+		our_sig: &Signature,
+		adaptor_point: &PublicKey,
+	) -> Result<SecretKey, Error> {
+		adaptor::extract(
+			&self.secp,
+			final_sig,
+			&[their_sig.clone(), our_sig.clone()],
+			adaptor_point,
+		)
+	}
+
 	/// Helper function to calculate final public key
 	pub fn aggsig_calculate_final_pubkey(
 		&self,
@@ -376,6 +499,50 @@ impl Keychain {
 		Ok(pk_sum)
 	}
 
+	/// Our public share of a blinding factor split between 2 parties (2 of 2
+	/// output), a commitment to a zero value with our part of the blinding
+	/// factor. Exchanged with the other party to build the shared commitment.
+	///
+	/// Only the keychain side of 2 of 2 outputs is provided: the wallet
+	/// doesn't build nor spend them, as a shared output can't get a range
+	/// proof without one party knowing the whole blinding factor.
+	pub fn multisig_commit_share(&self, key_id: &Identifier) -> Result<Commitment, Error> {
+		self.commit(0, key_id)
+	}
+
+	/// Commitment to the amount with a blinding factor split between us,
+	/// with our share derived from the key id, and the other party, with the
+	/// public share it sent. Neither knows the whole blinding factor so
+	/// spending the output requires both of us.
+	pub fn multisig_commit(
+		&self,
+		amount: u64,
+		key_id: &Identifier,
+		their_share: &Commitment,
+	) -> Result<Commitment, Error> {
+		let our_commit = self.commit(amount, key_id)?;
+		let commit = self.secp.commit_sum(vec![our_commit, their_share.clone()], vec![])?;
+		Ok(commit)
+	}
+
+	/// Creates the aggsig context to spend a 2 of 2 output with the other
+	/// party: our secret key is the sum of the blinding factors of the
+	/// outputs we receive, less our share of the spent output one. Partial
+	/// signatures are then exchanged as for any other transaction.
+	pub fn aggsig_create_multisig_context(
+		&self,
+		share_key_id: &Identifier,
+		output_key_ids: &[Identifier],
+	) -> Result<(), Error> {
+		let mut blind_sum = BlindSum::new().sub_key_id(share_key_id.clone());
+		for key_id in output_key_ids {
+			blind_sum = blind_sum.add_key_id(key_id.clone());
+		}
+		let blinding = self.blind_sum(&blind_sum)?;
+		self.aggsig_create_context(blinding.secret_key());
+		Ok(())
+	}
+
 	/// Just a simple sig, creates its own nonce, etc
 	pub fn aggsig_sign_from_key_id(
 		&self,
@@ -417,6 +584,29 @@ impl Keychain {
 		Ok(sig)
 	}
 
+	/// Public key identifying the wallet as the recipient of payments,
+	/// derived on child 0 which is never used for outputs.
+	pub fn payment_proof_pubkey(&self) -> Result<PublicKey, Error> {
+		let key = self.extkey.derive(&self.secp, 0)?.key;
+		Ok(PublicKey::from_secret_key(&self.secp, &key)?)
+	}
+
+	/// Key to encrypt secrets the wallet keeps on disk, derived from child 0
+	/// and the provided salt so it's different for each secret.
+	pub fn storage_key(&self, salt: &[u8]) -> Result<[u8; 32], Error> {
+		let key = self.extkey.derive(&self.secp, 0)?.key;
+		let hash = blake2::blake2b::blake2b(32, &key[..], salt);
+		let mut res = [0; 32];
+		res.copy_from_slice(hash.as_bytes());
+		Ok(res)
+	}
+
+	/// Signs a payment proof with the key of payment_proof_pubkey.
+	pub fn payment_proof_sign(&self, msg: &Message) -> Result<Signature, Error> {
+		let key = self.extkey.derive(&self.secp, 0)?.key;
+		Ok(self.secp.sign(msg, &key)?)
+	}
+
 	pub fn sign(&self, msg: &Message, key_id: &Identifier) -> Result<Signature, Error> {
 		let skey = self.derived_key(key_id)?;
 		let sig = self.secp.sign(msg, &skey)?;
@@ -463,6 +653,139 @@ mod test {
 		secp.verify_from_commit(&msg, &sig, &commit).unwrap();
 	}
 
+	#[test]
+	fn test_account_derivation() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let default = keychain.derive_account(0).unwrap();
+		let account = keychain.derive_account(1).unwrap();
+
+		assert_eq!(default.root_key_id(), keychain.root_key_id());
+		assert_eq!(
+			default.derive_key_id(1).unwrap(),
+			keychain.derive_key_id(1).unwrap()
+		);
+		assert!(account.root_key_id() != keychain.root_key_id());
+		assert!(account.derive_key_id(1).unwrap() != keychain.derive_key_id(1).unwrap());
+		assert_eq!(
+			account.derive_key_id(1).unwrap(),
+			keychain.derive_account(1).unwrap().derive_key_id(1).unwrap()
+		);
+
+		// an account seed gives the same keys as the account
+		let seed = keychain.account_seed(1).unwrap();
+		assert_eq!(
+			Keychain::from_seed(&seed).unwrap().derive_key_id(1).unwrap(),
+			keychain.derive_account(1).unwrap().derive_key_id(1).unwrap()
+		);
+		assert!(keychain.account_seed(0).is_err());
+	}
+
+	#[test]
+	fn test_multisig_output() {
+		let alice = Keychain::from_random_seed().unwrap();
+		let bob = Keychain::from_random_seed().unwrap();
+		let alice_share_id = alice.derive_key_id(1).unwrap();
+		let bob_share_id = bob.derive_key_id(1).unwrap();
+
+		// both end up with the same commitment from the other's public share
+		let alice_share = alice.multisig_commit_share(&alice_share_id).unwrap();
+		let bob_share = bob.multisig_commit_share(&bob_share_id).unwrap();
+		let commit = alice.multisig_commit(10, &alice_share_id, &bob_share).unwrap();
+		assert_eq!(
+			commit,
+			bob.multisig_commit(10, &bob_share_id, &alice_share).unwrap()
+		);
+
+		// spending it to a new output of Alice takes both partial signatures
+		let out_id = alice.derive_key_id(2).unwrap();
+		alice
+			.aggsig_create_multisig_context(&alice_share_id, &[out_id.clone()])
+			.unwrap();
+		bob.aggsig_create_multisig_context(&bob_share_id, &[])
+			.unwrap();
+		let (alice_pub, alice_nonce) = alice.aggsig_get_public_keys();
+		let (bob_pub, bob_nonce) = bob.aggsig_get_public_keys();
+
+		let bob_sig = bob.aggsig_calculate_partial_sig(&alice_nonce, 0, 0).unwrap();
+		assert!(alice.aggsig_verify_partial_sig(&bob_sig, &bob_nonce, &bob_pub, 0, 0));
+		let alice_sig = alice.aggsig_calculate_partial_sig(&bob_nonce, 0, 0).unwrap();
+		let final_sig = alice
+			.aggsig_calculate_final_sig(&bob_sig, &alice_sig, &bob_nonce)
+			.unwrap();
+		let final_pubkey = alice.aggsig_calculate_final_pubkey(&bob_pub).unwrap();
+		assert!(alice.aggsig_verify_final_sig_build_msg(&final_sig, &final_pubkey, 0, 0));
+		assert!(final_pubkey != alice_pub);
+
+		// and the signature is for the excess of the spend, output less input
+		let secp = alice.secp();
+		let excess = secp.commit_sum(vec![alice.commit(10, &out_id).unwrap()], vec![commit])
+			.unwrap();
+		let msg = secp::Message::from_slice(&::util::kernel_sig_msg(0, 0)).unwrap();
+		assert!(Keychain::aggsig_verify_single_from_commit(
+			secp,
+			&final_sig,
+			&msg,
+			&excess
+		));
+	}
+
+	#[test]
+	fn test_adaptor_swap_lock() {
+		let alice = Keychain::from_random_seed().unwrap();
+		let bob = Keychain::from_random_seed().unwrap();
+		let alice_share_id = alice.derive_key_id(1).unwrap();
+		let bob_share_id = bob.derive_key_id(1).unwrap();
+		let alice_share = alice.multisig_commit_share(&alice_share_id).unwrap();
+		let bob_share = bob.multisig_commit_share(&bob_share_id).unwrap();
+		let commit = alice.multisig_commit(10, &alice_share_id, &bob_share).unwrap();
+
+		// Alice locks the spend of the shared output to Bob on her secret
+		let (secret, point) = alice.adaptor_new_secret().unwrap();
+		let out_id = bob.derive_key_id(2).unwrap();
+		alice
+			.aggsig_create_multisig_context(&alice_share_id, &[])
+			.unwrap();
+		bob.aggsig_create_multisig_context(&bob_share_id, &[out_id.clone()])
+			.unwrap();
+		let (alice_pub, alice_nonce) = alice.aggsig_get_public_keys();
+		let (bob_pub, bob_nonce) = bob.aggsig_get_public_keys();
+
+		let alice_sig = alice
+			.aggsig_calculate_adaptor_partial_sig(&bob_nonce, &point, 0, 0)
+			.unwrap();
+		assert!(bob.aggsig_verify_adaptor_partial_sig(&alice_sig, &alice_nonce, &point, &alice_pub, 0, 0));
+		let bob_sig = bob
+			.aggsig_calculate_adaptor_partial_sig(&alice_nonce, &point, 0, 0)
+			.unwrap();
+		assert!(alice.aggsig_verify_adaptor_partial_sig(&bob_sig, &bob_nonce, &point, &bob_pub, 0, 0));
+
+		// the partial signatures alone don't make a valid kernel
+		let final_pubkey = alice.aggsig_calculate_final_pubkey(&bob_pub).unwrap();
+		let msg = secp::Message::from_slice(&::util::kernel_sig_msg(0, 0)).unwrap();
+		let unlocked = alice.aggsig_calculate_final_sig(&bob_sig, &alice_sig, &bob_nonce);
+		if let Ok(sig) = unlocked {
+			assert!(!alice.aggsig_verify_single(&sig, &msg, None, &final_pubkey, false));
+		}
+
+		// with the secret they do, for the excess of the spend
+		let final_sig = alice
+			.aggsig_calculate_adaptor_final_sig(&bob_sig, &alice_sig, &bob_nonce, &secret, &final_pubkey, 0, 0)
+			.unwrap();
+		let secp = alice.secp();
+		let excess = secp.commit_sum(vec![bob.commit(10, &out_id).unwrap()], vec![commit])
+			.unwrap();
+		assert!(Keychain::aggsig_verify_single_from_commit(secp, &final_sig, &msg, &excess));
+
+		// and Bob learns the secret from the published kernel
+		let extracted = bob
+			.aggsig_extract_adaptor_secret(&final_sig, &alice_sig, &bob_sig, &point)
+			.unwrap();
+		assert_eq!(extracted, secret);
+		let (other, other_point) = alice.adaptor_new_secret().unwrap();
+		assert!(other != secret);
+		assert!(bob.aggsig_extract_adaptor_secret(&final_sig, &alice_sig, &bob_sig, &other_point).is_err());
+	}
+
 	#[test]
 	fn test_rewind_range_proof() {
 		let keychain = Keychain::from_random_seed().unwrap();
@@ -17,18 +17,23 @@
 extern crate blake2_rfc as blake2;
 extern crate byteorder;
 extern crate grin_util as util;
+extern crate hmac;
+extern crate pbkdf2;
 extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 #[macro_use]
 extern crate slog;
 
 mod blind;
+pub mod adaptor;
 mod extkey;
 
 pub use blind::{BlindSum, BlindingFactor};
 pub use extkey::{ExtendedKey, Identifier, IDENTIFIER_SIZE};
 pub mod keychain;
+pub mod mnemonic;
 pub use keychain::{Error, Keychain, AggSigTxContext};
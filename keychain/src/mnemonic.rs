@@ -0,0 +1,193 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BIP39 mnemonic recovery phrases. Entropy is encoded as a list of words
+//! from the english wordlist, with a checksum catching most typos. The seed
+//! is derived from the phrase and an optional passphrase with PBKDF2.
+
+use std::{error, fmt};
+
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use sha2::{Digest, Sha256, Sha512};
+
+/// Number of PBKDF2 rounds when deriving the seed
+const PBKDF2_ROUNDS: usize = 2048;
+
+/// Size of the seed derived from a mnemonic
+pub const SEED_SIZE: usize = 64;
+
+/// A mnemonic error
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Error {
+	/// Entropy isn't of 16, 20, 24, 28 or 32 bytes
+	InvalidEntropySize,
+	/// The phrase isn't of 12, 15, 18, 21 or 24 words
+	InvalidLength,
+	/// A word isn't part of the wordlist
+	InvalidWord(String),
+	/// The checksum doesn't match, a word is likely mistyped
+	BadChecksum,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			Error::InvalidWord(ref w) => write!(f, "mnemonic: unknown word '{}'", w),
+			_ => f.write_str(error::Error::description(self)),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn description(&self) -> &str {
+		match *self {
+			Error::InvalidEntropySize => "mnemonic: entropy isn't of 16, 20, 24, 28 or 32 bytes",
+			Error::InvalidLength => "mnemonic: phrase isn't of 12, 15, 18, 21 or 24 words",
+			Error::InvalidWord(_) => "mnemonic: unknown word",
+			Error::BadChecksum => "mnemonic: invalid checksum",
+		}
+	}
+}
+
+fn wordlist() -> Vec<&'static str> {
+	include_str!("wordlists/en.txt").lines().collect()
+}
+
+/// Encodes the entropy as a mnemonic phrase, 24 words for 32 bytes.
+pub fn from_entropy(entropy: &[u8]) -> Result<String, Error> {
+	if entropy.len() < 16 || entropy.len() > 32 || entropy.len() % 4 != 0 {
+		return Err(Error::InvalidEntropySize);
+	}
+	// 1 bit of checksum every 32 bits of entropy, from the sha256 of it
+	let checksum_bits = entropy.len() / 4;
+	let hash = Sha256::digest(entropy);
+	let mut bits = bytes_to_bits(entropy);
+	bits.extend(bytes_to_bits(&hash[..1]).into_iter().take(checksum_bits));
+
+	let words = wordlist();
+	let phrase = bits.chunks(11)
+		.map(|chunk| words[chunk.iter().fold(0, |acc, b| (acc << 1) | *b as usize)])
+		.collect::<Vec<_>>();
+	Ok(phrase.join(" "))
+}
+
+/// Decodes the entropy of a mnemonic phrase, validating its checksum.
+pub fn to_entropy(mnemonic: &str) -> Result<Vec<u8>, Error> {
+	let phrase = mnemonic.split_whitespace().collect::<Vec<_>>();
+	if phrase.len() < 12 || phrase.len() > 24 || phrase.len() % 3 != 0 {
+		return Err(Error::InvalidLength);
+	}
+	let words = wordlist();
+	let mut bits = vec![];
+	for w in phrase.iter() {
+		let index = words
+			.binary_search(&w.to_lowercase().as_str())
+			.map_err(|_| Error::InvalidWord(w.to_string()))?;
+		for i in (0..11).rev() {
+			bits.push(((index >> i) & 1) as u8);
+		}
+	}
+
+	let checksum_bits = bits.len() / 33;
+	let entropy_bits = bits.len() - checksum_bits;
+	let entropy = bits[..entropy_bits]
+		.chunks(8)
+		.map(|byte| byte.iter().fold(0u8, |acc, b| (acc << 1) | b))
+		.collect::<Vec<u8>>();
+
+	let hash = Sha256::digest(&entropy);
+	let expected = bytes_to_bits(&hash[..1]);
+	if bits[entropy_bits..] != expected[..checksum_bits] {
+		return Err(Error::BadChecksum);
+	}
+	Ok(entropy)
+}
+
+/// Derives the seed of a mnemonic phrase protected by the provided
+/// passphrase, which can be empty. The phrase is validated first, any
+/// passphrase gives a valid (but different) seed.
+pub fn to_seed(mnemonic: &str, passphrase: &str) -> Result<[u8; SEED_SIZE], Error> {
+	to_entropy(mnemonic)?;
+	let normalized = mnemonic
+		.split_whitespace()
+		.map(|w| w.to_lowercase())
+		.collect::<Vec<_>>()
+		.join(" ");
+	let salt = format!("mnemonic{}", passphrase);
+	let mut seed = [0u8; SEED_SIZE];
+	pbkdf2::<Hmac<Sha512>>(
+		normalized.as_bytes(),
+		salt.as_bytes(),
+		PBKDF2_ROUNDS,
+		&mut seed,
+	);
+	Ok(seed)
+}
+
+fn bytes_to_bits(bytes: &[u8]) -> Vec<u8> {
+	let mut bits = Vec::with_capacity(bytes.len() * 8);
+	for byte in bytes {
+		for i in (0..8).rev() {
+			bits.push((byte >> i) & 1);
+		}
+	}
+	bits
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use util;
+
+	#[test]
+	fn mnemonic_vectors() {
+		let entropy = [0u8; 32];
+		let phrase = from_entropy(&entropy).unwrap();
+		assert_eq!(
+			phrase,
+			"abandon abandon abandon abandon abandon abandon abandon abandon \
+			 abandon abandon abandon abandon abandon abandon abandon abandon \
+			 abandon abandon abandon abandon abandon abandon abandon art"
+		);
+		assert_eq!(to_entropy(&phrase).unwrap(), entropy.to_vec());
+		assert_eq!(
+			util::to_hex(to_seed(&phrase, "TREZOR").unwrap().to_vec()),
+			"bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd30971\
+			 70af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8"
+		);
+
+		let entropy = [0x7f; 32];
+		let phrase = from_entropy(&entropy).unwrap();
+		assert_eq!(
+			phrase,
+			"legal winner thank year wave sausage worth useful legal winner thank \
+			 year wave sausage worth useful legal winner thank year wave sausage \
+			 worth title"
+		);
+		assert_eq!(to_entropy(&phrase).unwrap(), entropy.to_vec());
+	}
+
+	#[test]
+	fn mnemonic_errors() {
+		assert_eq!(from_entropy(&[0; 15]), Err(Error::InvalidEntropySize));
+		assert_eq!(to_entropy("abandon abandon"), Err(Error::InvalidLength));
+
+		let phrase = from_entropy(&[0; 32]).unwrap();
+		let typo = phrase.replace("art", "arts");
+		assert_eq!(to_entropy(&typo), Err(Error::InvalidWord("arts".to_string())));
+		let swapped = phrase.replace("art", "able");
+		assert_eq!(to_entropy(&swapped), Err(Error::BadChecksum));
+	}
+}
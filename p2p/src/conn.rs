@@ -16,12 +16,13 @@
 //! or receiving data from the TCP socket, as well as dealing with timeouts.
 
 use std::iter;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use futures;
-use futures::{Future, Stream, stream};
-use futures::sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender};
+use futures::{future, Future, Stream, stream};
+use futures::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures_cpupool::CpuPool;
 use tokio_core::net::TcpStream;
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -31,10 +32,16 @@ use tokio_timer::{Timer, TimerError};
 use core::core::hash::Hash;
 use core::ser;
 use msg::*;
-use types::Error;
+use netsim::{Delivery, NetworkConditions};
+use types::{Corruption, Error};
 use rate_limit::*;
+use stats::{msg_type_of, TrafficStats};
 use util::LOGGER;
 
+/// Maximum number of messages we skip from a peer, being corrupted or of a
+/// type we don't know about, before considering its stream corrupted
+const MAX_SKIPPED_MSGS: u64 = 10;
+
 /// Handler to provide to the connection, will be called back anytime a message
 /// is received. The provided sender can be use to immediately send back
 /// another message.
@@ -71,29 +78,36 @@ where
 /// receiving data, parsing message headers and timeouts.
 #[allow(dead_code)]
 pub struct Connection {
-	// Channel to push bytes to the remote peer
+	// Channel to push bytes to the remote peer, an empty message closing the
+	// connection once everything before it has been sent
 	outbound_chan: UnboundedSender<Vec<u8>>,
 
-	// Close the connection with the remote peer
-	close_chan: Sender<()>,
-
 	// Bytes we've sent.
 	sent_bytes: Arc<Mutex<u64>>,
 
 	// Bytes we've received.
 	received_bytes: Arc<Mutex<u64>>,
 
-	// Counter for read errors.
-	error_count: Mutex<u64>,
+	// Bytes queued to be sent.
+	queued_bytes: Arc<Mutex<u64>>,
+
+	// Messages received we had to skip.
+	skipped_msgs: Arc<Mutex<u64>>,
 }
 
 impl Connection {
 	/// Start listening on the provided connection and wraps it. Does not hang
 	/// the current thread, instead just returns a future and the Connection
-	/// itself.
+	/// itself. Messages received go through the network conditions of the
+	/// link with the peer at the provided address. Message headers carry a
+	/// checksum if both peers support it, as negotiated in the handshake.
 	pub fn listen<F>(
 		conn: TcpStream,
+		addr: SocketAddr,
+		checksum: bool,
 		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		conditions: Arc<NetworkConditions>,
 		handler: F,
 	) -> (Connection, Box<Future<Item = (), Error = Error>>)
 	where
@@ -109,31 +123,27 @@ impl Connection {
 		// prepare the channel that will transmit data to the connection writer
 		let (tx, rx) = futures::sync::mpsc::unbounded();
 
-		// same for closing the connection
-		let (close_tx, close_rx) = futures::sync::mpsc::channel(1);
-		let close_conn = close_rx
-			.for_each(|_| Ok(()))
-			.map_err(|_| Error::ConnectionClose);
-
 		let me = Connection {
 			outbound_chan: tx.clone(),
-			close_chan: close_tx,
 			sent_bytes: Arc::new(Mutex::new(0)),
 			received_bytes: Arc::new(Mutex::new(0)),
-			error_count: Mutex::new(0),
+			queued_bytes: Arc::new(Mutex::new(0)),
+			skipped_msgs: Arc::new(Mutex::new(0)),
 		};
 
 		// setup the reading future, getting messages from the peer and processing them
-		let read_msg = me.read_msg(tx, reader, handler, pool).map(|_| ());
+		let read_msg = me.read_msg(tx, reader, handler, pool, stats.clone(), addr, checksum, conditions)
+			.map(|_| ());
 
 		// setting the writing future
 		// getting messages from our system and sending them out
-		let write_msg = me.write_msg(rx, writer).map(|_| ());
+		let write_msg = me.write_msg(rx, writer, checksum, stats).map(|_| ());
 
-		// select between our different futures and return them
+		// select between our different futures and return them, the connection
+		// closing when either the reading or the writing is done
 		let fut = Box::new(
-			close_conn
-				.select(read_msg.select(write_msg).map(|_| ()).map_err(|(e, _)| e))
+			read_msg
+				.select(write_msg)
 				.map(|_| ())
 				.map_err(|(e, _)| e),
 		);
@@ -142,28 +152,46 @@ impl Connection {
 	}
 
 	/// Prepares the future that gets message data produced by our system and
-	/// sends it to the peer connection
+	/// sends it to the peer connection, without the header checksum if the
+	/// peer doesn't support it
 	fn write_msg<W>(
 		&self,
 		rx: UnboundedReceiver<Vec<u8>>,
 		writer: W,
+		checksum: bool,
+		stats: Arc<TrafficStats>,
 	) -> Box<Future<Item = W, Error = Error>>
 	where
 		W: AsyncWrite + 'static,
 	{
 		let sent_bytes = self.sent_bytes.clone();
+		let queued_bytes = self.queued_bytes.clone();
 		let send_data = rx
 			.map_err(|_| Error::ConnectionClose)
+			// an empty message asks to close the connection
+			.take_while(|data| Ok(!data.is_empty()))
 			.map(move |data| {
 				trace!(LOGGER, "write_msg: start");
+				// messages are always built with a checksum
+				let data = if checksum { data } else { strip_checksum(data) };
 				// add the count of bytes sent
 				let mut sent_bytes = sent_bytes.lock().unwrap();
 				*sent_bytes += data.len() as u64;
+				// every message, including handler replies, starts with its header
+				if let Some(msg_type) = msg_type_of(&data) {
+					stats.record_sent(msg_type, data.len() as u64);
+				}
 				data
 			})
 			// write the data and make sure the future returns the right types
-			.fold(writer, |writer, data| {
-				write_all(writer, data).map_err(|e| Error::Connection(e)).map(|(writer, _)| {
+			.fold(writer, move |writer, data| {
+				let queued_bytes = queued_bytes.clone();
+				let len = data.len() as u64;
+				write_all(writer, data).map_err(|e| Error::Connection(e)).map(move |(writer, _)| {
+					// replies sent directly by the handler aren't counted as
+					// queued, hence the saturation
+					let mut queued_bytes = queued_bytes.lock().unwrap();
+					*queued_bytes = queued_bytes.saturating_sub(len);
 					trace!(LOGGER, "write_msg: done");
 					writer
 				})
@@ -179,6 +207,10 @@ impl Connection {
 		reader: R,
 		handler: F,
 		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		addr: SocketAddr,
+		checksum: bool,
+		conditions: Arc<NetworkConditions>,
 	) -> Box<Future<Item = R, Error = Error>>
 	where
 		F: Handler + 'static,
@@ -190,7 +222,9 @@ impl Connection {
 
 		// setup the reading future, getting messages from the peer and processing them
 		let recv_bytes = self.received_bytes.clone();
+		let skipped_msgs = self.skipped_msgs.clone();
 		let handler = Arc::new(handler);
+		let header_len = header_len(checksum);
 
 		let mut count = 0;
 
@@ -199,31 +233,75 @@ impl Connection {
 			trace!(LOGGER, "read_msg: count (per buffered fold): {}", count);
 
 			let recv_bytes = recv_bytes.clone();
+			let skipped_msgs = skipped_msgs.clone();
+			let stats = stats.clone();
 			let handler = handler.clone();
 			let sender_inner = sender.clone();
 			let pool = pool.clone();
+			let conditions = conditions.clone();
 
 			// first read the message header
-			read_exact(reader, vec![0u8; HEADER_LEN as usize])
+			read_exact(reader, vec![0u8; header_len as usize])
 				.from_err()
 				.and_then(move |(reader, buf)| {
 					trace!(LOGGER, "read_msg: start");
 
-					let header = try!(ser::deserialize::<MsgHeader>(&mut &buf[..]));
+					// past a header we can't make sense of, we don't know where
+					// the next message starts anymore
+					let header = match parse_header(&buf) {
+						Ok(header) => Ok(header),
+						Err(Corruption::UnknownType(t, len)) => Err((t, len)),
+						Err(c) => {
+							debug!(LOGGER, "read_msg: corrupted stream from {}: {:?}", addr, c);
+							return Err(Error::Corrupted(c));
+						}
+					};
 					Ok((reader, header))
 				})
 				.and_then(move |(reader, header)| {
 					// now that we have a size, proceed with the body
-					read_exact(reader, vec![0u8; header.msg_len as usize])
+					let len = match header {
+						Ok(ref header) => header.msg_len,
+						Err((_, len)) => len,
+					};
+					read_exact(reader, vec![0u8; len as usize])
 						.map(|(reader, buf)| (reader, header, buf))
 						.from_err()
 				})
 				.and_then(move |(reader, header, buf)| {
 					// add the count of bytes received
-					let mut recv_bytes = recv_bytes.lock().unwrap();
-					*recv_bytes += header.serialized_len() + header.msg_len;
+					let msg_bytes = header_len + buf.len() as u64;
+					*recv_bytes.lock().unwrap() += msg_bytes;
+
+					// a message we can't use still leaves the stream in sync, it
+					// just gets skipped, unless the peer sends too many of them
+					let header = match header {
+						Ok(ref header) if !header.verify(&buf) => Err(Corruption::BadChecksum),
+						Ok(header) => Ok(header),
+						Err((t, len)) => Err(Corruption::UnknownType(t, len)),
+					};
+					let header = match header {
+						Ok(header) => header,
+						Err(c) => {
+							let mut skipped_msgs = skipped_msgs.lock().unwrap();
+							*skipped_msgs += 1;
+							debug!(LOGGER, "read_msg: skipping message from {}: {:?}", addr, c);
+							let res = if *skipped_msgs > MAX_SKIPPED_MSGS {
+								Err(Error::Corrupted(Corruption::TooManySkipped(*skipped_msgs)))
+							} else {
+								Ok(reader)
+							};
+							return Box::new(future::result(res)) as Box<Future<Item = R, Error = Error>>;
+						}
+					};
+					stats.record_received(header.msg_type, msg_bytes);
 
-					pool.spawn_fn(move || {
+					let delivery = conditions.delivery(&addr);
+					if delivery == Delivery::Drop {
+						trace!(LOGGER, "read_msg: dropped {:?} from {}", header.msg_type, addr);
+						return Box::new(future::ok(reader)) as Box<Future<Item = R, Error = Error>>;
+					}
+					let handle = move || {
 						let msg_type = header.msg_type;
 						if let Err(e) = handler.handle(sender_inner.clone(), header, buf) {
 							debug!(LOGGER, "Invalid {:?} message: {}", msg_type, e);
@@ -232,7 +310,18 @@ impl Connection {
 
 						trace!(LOGGER, "read_msg: done (via cpu_pool)");
 						Ok(reader)
-					})
+					};
+					match delivery {
+						// the following messages from the peer wait as well,
+						// keeping them in order
+						Delivery::Delay(latency) => Box::new(
+							Timer::default()
+								.sleep(latency)
+								.from_err()
+								.and_then(move |_| pool.spawn_fn(handle)),
+						),
+						_ => Box::new(pool.spawn_fn(handle)),
+					}
 				})
 		});
 		Box::new(read_msg)
@@ -246,13 +335,16 @@ impl Connection {
 		let mut data = vec![];
 		try!(ser::serialize(
 			&mut data,
-			&MsgHeader::new(t, body_data.len() as u64),
+			&MsgHeader::new(t, &body_data),
 		));
 		data.append(&mut body_data);
 
+		let len = data.len() as u64;
 		self.outbound_chan
 			.unbounded_send(data)
-			.map_err(|_| Error::ConnectionClose)
+			.map_err(|_| Error::ConnectionClose)?;
+		*self.queued_bytes.lock().unwrap() += len;
+		Ok(())
 	}
 
 	/// Bytes sent and received by this peer to the remote peer.
@@ -261,6 +353,22 @@ impl Connection {
 		let recv = *self.received_bytes.lock().unwrap();
 		(sent, recv)
 	}
+
+	/// Bytes waiting in the send queue to the remote peer.
+	pub fn queued_bytes(&self) -> u64 {
+		*self.queued_bytes.lock().unwrap()
+	}
+
+	/// Messages received from the remote peer we had to skip.
+	pub fn skipped_msgs(&self) -> u64 {
+		*self.skipped_msgs.lock().unwrap()
+	}
+
+	/// Closes the connection to the remote peer once all the messages queued
+	/// so far have been sent.
+	pub fn close(&self) {
+		let _ = self.outbound_chan.unbounded_send(vec![]);
+	}
 }
 
 /// Connection wrapper that handles a request/response oriented interaction with
@@ -281,7 +389,11 @@ impl TimeoutConnection {
 	/// Same as Connection
 	pub fn listen<F>(
 		conn: TcpStream,
+		addr: SocketAddr,
+		checksum: bool,
 		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		conditions: Arc<NetworkConditions>,
 		handler: F,
 	) -> (TimeoutConnection, Box<Future<Item = (), Error = Error>>)
 	where
@@ -292,7 +404,7 @@ impl TimeoutConnection {
 		// Decorates the handler to remove the "subscription" from the expected
 		// responses. We got our replies, so no timeout should occur.
 		let exp = expects.clone();
-		let (conn, fut) = Connection::listen(conn, pool, move |sender, header: MsgHeader, data| {
+		let (conn, fut) = Connection::listen(conn, addr, checksum, pool, stats, conditions, move |sender, header: MsgHeader, data| {
 			let msg_type = header.msg_type;
 			let recv_h = try!(handler.handle(sender, header, data));
 
@@ -323,7 +435,10 @@ impl TimeoutConnection {
 
 				for x in exp.iter() {
 					if Instant::now() - x.time > Duration::new(5, 0) {
-						trace!(LOGGER, "timeout_conn: timeout: {:?}, {:?}", x.msg_type, x.hash);
+						debug!(
+							LOGGER,
+							"timeout_conn: {} timed out on {:?}, {:?}", addr, x.msg_type, x.hash
+						);
 						return Err(TimerError::TooLong);
 					}
 				}
@@ -398,4 +513,19 @@ impl TimeoutConnection {
 	pub fn transmitted_bytes(&self) -> (u64, u64) {
 		self.underlying.transmitted_bytes()
 	}
+
+	/// Same as Connection
+	pub fn queued_bytes(&self) -> u64 {
+		self.underlying.queued_bytes()
+	}
+
+	/// Same as Connection
+	pub fn skipped_msgs(&self) -> u64 {
+		self.underlying.skipped_msgs()
+	}
+
+	/// Same as Connection
+	pub fn close(&self) {
+		self.underlying.close()
+	}
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::collections::VecDeque;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
@@ -19,6 +20,7 @@ use std::sync::{Arc, RwLock};
 use futures::{self, Future};
 use rand::Rng;
 use rand::os::OsRng;
+use time;
 use tokio_core::net::TcpStream;
 
 use core::core::target::Difficulty;
@@ -40,7 +42,8 @@ pub struct Handshake {
 	/// The genesis block header of the chain seen by this node.
 	/// We only want to connect to other nodes seeing the same chain (forks are ok).
 	genesis: Hash,
-	config: P2PConfig,
+	/// Our configuration, shared with the peers so it can be reloaded
+	config: Arc<RwLock<P2PConfig>>,
 }
 
 unsafe impl Sync for Handshake {}
@@ -48,7 +51,7 @@ unsafe impl Send for Handshake {}
 
 impl Handshake {
 	/// Creates a new handshake handler
-	pub fn new(genesis: Hash, config: P2PConfig) -> Handshake {
+	pub fn new(genesis: Hash, config: Arc<RwLock<P2PConfig>>) -> Handshake {
 		Handshake {
 			nonces: Arc::new(RwLock::new(VecDeque::with_capacity(NONCES_CAP))),
 			genesis,
@@ -91,7 +94,7 @@ impl Handshake {
 			write_msg(conn, hand, Type::Hand)
 				.and_then(|conn| read_msg::<Shake>(conn))
 				.and_then(move |(conn, shake)| {
-					if shake.version != PROTOCOL_VERSION {
+					if shake.version < MIN_PROTOCOL_VERSION {
 						Err(Error::ProtocolMismatch {
 							us: PROTOCOL_VERSION,
 							peer: shake.version,
@@ -102,19 +105,25 @@ impl Handshake {
 							peer: shake.genesis,
 						})
 					} else {
-						let peer_info = PeerInfo {
+						let mut peer_info = PeerInfo {
 							capabilities: shake.capabilities,
 							user_agent: shake.user_agent,
 							addr: peer_addr,
 							version: shake.version,
+							direction: Direction::Outbound,
 							total_difficulty: shake.total_difficulty,
+							height: 0,
+							last_seen: time::now_utc().to_timespec().sec,
+							outdated: false,
+							metrics: PeerMetrics::default(),
 						};
 
 						// If denied then we want to close the connection
 						// (without providing our peer with any details why).
-						if Peer::is_denied(config, peer_info.addr) {
+						if Peer::is_denied(config.read().unwrap().clone(), peer_info.addr) {
 							return Err(Error::ConnectionClose);
 						}
+						check_outdated(&config.read().unwrap(), &mut peer_info)?;
 
 						debug!(
 							LOGGER,
@@ -124,8 +133,8 @@ impl Handshake {
 							peer_info.user_agent,
 							peer_info.capabilities
 						);
-						// when more than one protocol version is supported, choosing should go here
-						Ok((conn, ProtocolV1::new(), peer_info))
+						let checksum = use_checksum(peer_info.version);
+						Ok((conn, ProtocolV1::new(checksum), peer_info))
 					}
 				}),
 		)
@@ -146,7 +155,7 @@ impl Handshake {
 		Box::new(
 			read_msg::<Hand>(conn)
 				.and_then(move |(conn, hand)| {
-					if hand.version != PROTOCOL_VERSION {
+					if hand.version < MIN_PROTOCOL_VERSION {
 						return Err(Error::ProtocolMismatch {
 							us: PROTOCOL_VERSION,
 							peer: hand.version,
@@ -165,21 +174,27 @@ impl Handshake {
 					}
 
 					// all good, keep peer info
-					let peer_info = PeerInfo {
+					let mut peer_info = PeerInfo {
 						capabilities: hand.capabilities,
 						user_agent: hand.user_agent,
 						addr: extract_ip(&hand.sender_addr.0, &conn),
 						version: hand.version,
+						direction: Direction::Inbound,
 						total_difficulty: hand.total_difficulty,
+						height: 0,
+						last_seen: time::now_utc().to_timespec().sec,
+						outdated: false,
+						metrics: PeerMetrics::default(),
 					};
 
 					// At this point we know the published ip and port of the peer
 					// so check if we are configured to explicitly allow or deny it.
 					// If denied then we want to close the connection
 					// (without providing our peer with any details why).
-					if Peer::is_denied(config, peer_info.addr) {
+					if Peer::is_denied(config.read().unwrap().clone(), peer_info.addr) {
 						return Err(Error::ConnectionClose);
 					}
+					check_outdated(&config.read().unwrap(), &mut peer_info)?;
 
 					// send our reply with our info
 					let shake = Shake {
@@ -193,9 +208,9 @@ impl Handshake {
 				})
 				.and_then(|(conn, shake, peer_info)| {
 					debug!(LOGGER, "Success handshake with {}.", peer_info.addr);
+					let checksum = use_checksum(peer_info.version);
 					write_msg(conn, shake, Type::Shake)
-					// when more than one protocol version is supported, choosing should go here
-					.map(|conn| (conn, ProtocolV1::new(), peer_info))
+					.map(move |conn| (conn, ProtocolV1::new(checksum), peer_info))
 				}),
 		)
 	}
@@ -214,6 +229,13 @@ impl Handshake {
 	}
 }
 
+// Whether messages exchanged with a peer at the provided version, once the
+// handshake is done, have a checksum in their header. Both sides come to the
+// same answer as it only depends on the lowest of our versions.
+fn use_checksum(peer_version: u32) -> bool {
+	cmp::min(peer_version, PROTOCOL_VERSION) >= CHECKSUM_VERSION
+}
+
 // Attempts to make a best guess at the correct remote IP by checking if the
 // advertised address is the loopback and our TCP connection. Note that the
 // port reported by the connection is always incorrect for receiving
@@ -239,3 +261,144 @@ fn extract_ip(advertised: &SocketAddr, conn: &TcpStream) -> SocketAddr {
   }
   advertised.clone()
 }
+
+// Applies the outdated peer policy to a peer below the configured minimum
+// version or user agent, refusing it or flagging it as outdated.
+fn check_outdated(config: &P2PConfig, peer_info: &mut PeerInfo) -> Result<(), Error> {
+	if !is_outdated(config, peer_info) {
+		return Ok(());
+	}
+	match config.outdated_peer_policy {
+		OutdatedPeerPolicy::Reject => {
+			info!(
+				LOGGER,
+				"Refusing outdated peer {}: {} with protocol version {}",
+				peer_info.addr,
+				peer_info.user_agent,
+				peer_info.version
+			);
+			Err(Error::ConnectionClose)
+		}
+		OutdatedPeerPolicy::Deprioritize => {
+			info!(
+				LOGGER,
+				"Deprioritizing outdated peer {}: {} with protocol version {}",
+				peer_info.addr,
+				peer_info.user_agent,
+				peer_info.version
+			);
+			peer_info.outdated = true;
+			Ok(())
+		}
+		OutdatedPeerPolicy::LogOnly => {
+			info!(
+				LOGGER,
+				"Accepting outdated peer {}: {} with protocol version {}",
+				peer_info.addr,
+				peer_info.user_agent,
+				peer_info.version
+			);
+			Ok(())
+		}
+	}
+}
+
+// Whether the peer is below the minimum protocol version or user agent
+fn is_outdated(config: &P2PConfig, peer_info: &PeerInfo) -> bool {
+	if let Some(min_version) = config.min_peer_version {
+		if peer_info.version < min_version {
+			return true;
+		}
+	}
+	if let Some(ref min_user_agent) = config.min_user_agent {
+		if let Some((min_name, min_version)) = split_user_agent(min_user_agent) {
+			return match split_user_agent(&peer_info.user_agent) {
+				// other software, nothing to compare with
+				Some((name, _)) if name != min_name => false,
+				Some((_, version)) => version < min_version,
+				None => user_agent_name(&peer_info.user_agent) == min_name,
+			};
+		}
+	}
+	false
+}
+
+// Splits a user agent like "MW/Grin 0.1" into its software name and its
+// version numbers
+fn split_user_agent(user_agent: &str) -> Option<(&str, Vec<u32>)> {
+	let name = user_agent_name(user_agent);
+	if name.len() == user_agent.len() {
+		return None;
+	}
+	let mut version = vec![];
+	for n in user_agent[name.len() + 1..].split('.') {
+		match n.parse::<u32>() {
+			Ok(n) => version.push(n),
+			Err(_) => return None,
+		}
+	}
+	Some((name, version))
+}
+
+fn user_agent_name(user_agent: &str) -> &str {
+	match user_agent.rfind(' ') {
+		Some(i) => &user_agent[..i],
+		None => user_agent,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn peer_info(version: u32, user_agent: &str) -> PeerInfo {
+		PeerInfo {
+			capabilities: Capabilities::UNKNOWN,
+			user_agent: user_agent.to_string(),
+			version: version,
+			addr: "127.0.0.1:13414".parse().unwrap(),
+			direction: Direction::Inbound,
+			total_difficulty: Difficulty::one(),
+			height: 0,
+			last_seen: 0,
+			outdated: false,
+			metrics: PeerMetrics::default(),
+		}
+	}
+
+	#[test]
+	fn outdated_peers() {
+		let mut config = P2PConfig::default();
+		assert!(!is_outdated(&config, &peer_info(1, "MW/Grin 0.1")));
+
+		config.min_peer_version = Some(2);
+		assert!(is_outdated(&config, &peer_info(1, "MW/Grin 0.1")));
+		assert!(!is_outdated(&config, &peer_info(2, "MW/Grin 0.1")));
+
+		config.min_user_agent = Some("MW/Grin 0.2.1".to_string());
+		assert!(is_outdated(&config, &peer_info(2, "MW/Grin 0.1")));
+		assert!(is_outdated(&config, &peer_info(2, "MW/Grin 0.2")));
+		assert!(!is_outdated(&config, &peer_info(2, "MW/Grin 0.2.1")));
+		assert!(!is_outdated(&config, &peer_info(2, "MW/Grin 0.10")));
+		assert!(is_outdated(&config, &peer_info(2, "MW/Grin beta")));
+		assert!(!is_outdated(&config, &peer_info(2, "Other 0.1")));
+	}
+
+	#[test]
+	fn outdated_policy() {
+		let mut config = P2PConfig::default();
+		config.min_peer_version = Some(2);
+
+		let mut info = peer_info(1, "MW/Grin 0.1");
+		assert!(check_outdated(&config, &mut info).is_err());
+
+		config.outdated_peer_policy = OutdatedPeerPolicy::Deprioritize;
+		assert!(check_outdated(&config, &mut info).is_ok());
+		assert!(info.outdated);
+
+		config.outdated_peer_policy = OutdatedPeerPolicy::LogOnly;
+		let mut info = peer_info(1, "MW/Grin 0.1");
+		assert!(check_outdated(&config, &mut info).is_ok());
+		assert!(!info.outdated);
+	}
+}
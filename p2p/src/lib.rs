@@ -48,16 +48,24 @@ mod conn;
 pub mod handshake;
 mod rate_limit;
 pub mod msg;
+mod netsim;
+pub mod peer_list;
 mod peer;
 mod peers;
 mod protocol;
+mod reputation;
 mod server;
+mod stats;
 mod store;
 mod types;
 
 pub use server::{DummyAdapter, Server};
-pub use peers::Peers;
+pub use peers::{Peers, PeersMemory};
 pub use peer::Peer;
-pub use types::{Capabilities, Error, ChainAdapter, P2PConfig, PeerInfo, MAX_BLOCK_HEADERS,
-                  MAX_PEER_ADDRS};
+pub use netsim::{Delivery, LinkConditions, NetworkConditions};
+pub use reputation::Misbehavior;
+pub use stats::{MsgStats, TrafficStats};
+pub use types::{Capabilities, Direction, Error, ChainAdapter, OutdatedPeerPolicy, P2PConfig, PeerInfo,
+                  PeerMetrics,                   MAX_BLOCK_HEADERS, MAX_PEER_ADDRS};
 pub use store::{PeerData, State};
+pub use msg::{protocol_spec, ProtocolSpec};
@@ -23,14 +23,27 @@ use tokio_io::io::{read_exact, write_all};
 
 use core::consensus::MAX_MSG_LEN;
 use core::core::BlockHeader;
-use core::core::hash::Hash;
+use core::core::hash::{Hash, HashWriter};
 use core::core::target::Difficulty;
 use core::ser::{self, Readable, Reader, Writeable, Writer};
 
 use types::*;
 
 /// Current latest version of the protocol
-pub const PROTOCOL_VERSION: u32 = 1;
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Oldest version of the protocol we still talk to. Version 4 changed the
+/// block header and transaction formats (kernel offsets, several kernels
+/// per transaction), peers before it can't read our blocks and transactions.
+pub const MIN_PROTOCOL_VERSION: u32 = 4;
+
+/// First version of the protocol with a checksum in message headers, used
+/// after the handshake when both peers support it
+pub const CHECKSUM_VERSION: u32 = 2;
+
+/// First version of the protocol relaying Dandelion stem transactions, older
+/// peers only get transactions to broadcast
+pub const STEM_VERSION: u32 = 3;
 
 /// Grin's user agent with current version (TODO externalize)
 pub const USER_AGENT: &'static str = "MW/Grin 0.1";
@@ -39,60 +52,202 @@ pub const USER_AGENT: &'static str = "MW/Grin 0.1";
 const MAGIC: [u8; 2] = [0x1e, 0xc5];
 
 /// Size in bytes of a message header
-pub const HEADER_LEN: u64 = 11;
+pub const HEADER_LEN: u64 = 15;
+
+/// Size in bytes of a message header without checksum, as sent during the
+/// handshake and to peers older than CHECKSUM_VERSION
+pub const HEADER_LEN_V1: u64 = 11;
 
 /// Codes for each error that can be produced reading a message.
 #[allow(dead_code)]
 pub enum ErrCodes {
 	UnsupportedVersion = 100,
+	/// The peer is shutting down and about to close the connection
+	ShuttingDown = 101,
 }
 
-/// Types of messages
-enum_from_primitive! {
-	#[derive(Debug, Clone, Copy, PartialEq)]
-	pub enum Type {
-		Error,
-		Hand,
-		Shake,
-		Ping,
-		Pong,
-		GetPeerAddrs,
-		PeerAddrs,
-		GetHeaders,
-		Header,
-		Headers,
-		GetBlock,
-		Block,
-		GetCompactBlock,
-		CompactBlock,
-		Transaction,
+/// Maximum serialized length of a block header, with a comfortable margin
+const MAX_HEADER_LEN: u64 = 1_000;
+
+/// Declares all the protocol messages, generating the message type
+/// enumeration as well as the machine-readable specification of each message
+/// (as sent over the wire after the message header). Message type codes
+/// follow the declaration order, new messages must be added at the end.
+macro_rules! protocol_messages {
+	($($name:ident ($desc:expr), max_len: $max_len:expr, [$($field:expr => $ftype:expr),*];)*) => {
+		enum_from_primitive! {
+			/// Types of messages
+			#[derive(Debug, Clone, Copy, PartialEq)]
+			pub enum Type {
+				$($name,)*
+			}
+		}
+
+		impl Type {
+			/// Maximum length of the body of a message of this type, in bytes
+			pub fn max_len(&self) -> u64 {
+				match *self {
+					$(Type::$name => $max_len,)*
+				}
+			}
+		}
+
+		/// Specifications of all the protocol messages, ordered by type code.
+		pub fn message_specs() -> Vec<MessageSpec> {
+			vec![$(MessageSpec {
+				name: stringify!($name).to_string(),
+				code: Type::$name as u8,
+				description: $desc.to_string(),
+				max_len: $max_len,
+				fields: vec![$(FieldSpec {
+					name: $field.to_string(),
+					field_type: $ftype.to_string(),
+				}),*],
+			}),*]
+		}
+	}
+}
+
+protocol_messages! {
+	Error("Error reported by a peer, usually right before disconnecting"),
+		max_len: 1_000,
+		["code" => "u32", "message" => "bytes"];
+	Hand("First part of the handshake, sent by the connecting peer"),
+		max_len: 1_000,
+		["version" => "u32", "capabilities" => "u32", "nonce" => "u64",
+		 "total_difficulty" => "u64", "sender_addr" => "SockAddr",
+		 "receiver_addr" => "SockAddr", "user_agent" => "bytes", "genesis" => "Hash"];
+	Shake("Second part of the handshake, sent back by the receiving peer"),
+		max_len: 1_000,
+		["version" => "u32", "capabilities" => "u32", "total_difficulty" => "u64",
+		 "user_agent" => "bytes", "genesis" => "Hash"];
+	Ping("Keepalive, advertising our total difficulty and height"),
+		max_len: 16,
+		["total_difficulty" => "u64", "height" => "u64"];
+	Pong("Response to a ping, advertising our total difficulty and height"),
+		max_len: 16,
+		["total_difficulty" => "u64", "height" => "u64"];
+	GetPeerAddrs("Asks for addresses of other peers with the provided capabilities"),
+		max_len: 4,
+		["capabilities" => "u32"];
+	PeerAddrs("Addresses of other peers"),
+		max_len: 4 + MAX_PEER_ADDRS as u64 * 19,
+		["peers" => "u32 count followed by as many SockAddr"];
+	GetHeaders("Asks for the headers following the first locator hash we have"),
+		max_len: 1 + 255 * 32,
+		["hashes" => "u8 count followed by as many Hash"];
+	Header("A single block header, typically a newly mined block"),
+		max_len: MAX_HEADER_LEN,
+		["header" => "BlockHeader"];
+	Headers("Block headers, in response to GetHeaders"),
+		max_len: 2 + MAX_BLOCK_HEADERS as u64 * MAX_HEADER_LEN,
+		["headers" => "u16 count followed by as many BlockHeader"];
+	GetBlock("Asks for a full block"),
+		max_len: 32,
+		["hash" => "Hash"];
+	Block("A full block"),
+		max_len: MAX_MSG_LEN,
+		["block" => "Block"];
+	GetCompactBlock("Asks for a compact block"),
+		max_len: 32,
+		["hash" => "Hash"];
+	CompactBlock("A compact block, only including the kernels ids of its transactions"),
+		max_len: MAX_MSG_LEN,
+		["block" => "CompactBlock"];
+	Transaction("A transaction to add to the pool"),
+		max_len: MAX_MSG_LEN,
+		["transaction" => "Transaction"];
+	GetHeadersRange("Asks for consecutive headers of the current chain from a height, answered with Headers"),
+		max_len: 10,
+		["start_height" => "u64", "count" => "u16"];
+	StemTransaction("A transaction in its Dandelion stem phase, to relay to a single peer or broadcast"),
+		max_len: MAX_MSG_LEN,
+		["transaction" => "Transaction"];
+}
+
+/// Description of a field of a message body
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldSpec {
+	/// Name of the field
+	pub name: String,
+	/// How the field is serialized
+	#[serde(rename = "type")]
+	pub field_type: String,
+}
+
+/// Description of a protocol message
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageSpec {
+	/// Name of the message
+	pub name: String,
+	/// Message type code in the message header
+	pub code: u8,
+	/// What the message is for
+	pub description: String,
+	/// Maximum length of the message body, in bytes
+	pub max_len: u64,
+	/// Fields of the message body, in serialization order
+	pub fields: Vec<FieldSpec>,
+}
+
+/// Machine-readable specification of the p2p protocol
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolSpec {
+	/// Protocol version
+	pub version: u32,
+	/// Magic bytes every message header starts with
+	pub magic: Vec<u8>,
+	/// Fields of the message header preceding every message body
+	pub header: Vec<FieldSpec>,
+	/// All messages of the protocol
+	pub messages: Vec<MessageSpec>,
+}
+
+/// Builds the specification of the protocol, as implemented by this version.
+pub fn protocol_spec() -> ProtocolSpec {
+	let field = |name: &str, field_type: &str| FieldSpec {
+		name: name.to_string(),
+		field_type: field_type.to_string(),
+	};
+	ProtocolSpec {
+		version: PROTOCOL_VERSION,
+		magic: MAGIC.to_vec(),
+		header: vec![
+			field("magic", "[u8; 2]"),
+			field("msg_type", "u8"),
+			field("msg_len", "u64"),
+			// only after the handshake, between peers both at CHECKSUM_VERSION
+			field("checksum", "[u8; 4]"),
+		],
+		messages: message_specs(),
 	}
 }
 
 /// Future combinator to read any message where the body is a Readable. Reads
 /// the  header first, handles its validation and then reads the Readable body,
-/// allocating buffers of the right size.
+/// allocating buffers of the right size. Only used for the handshake, so
+/// expects a header without checksum as we don't know the peer version yet.
 pub fn read_msg<T>(conn: TcpStream) -> Box<Future<Item = (TcpStream, T), Error = Error>>
 where
 	T: Readable + 'static,
 {
-	let read_header = read_exact(conn, vec![0u8; HEADER_LEN as usize])
+	let read_header = read_exact(conn, vec![0u8; HEADER_LEN_V1 as usize])
 		.from_err()
 		.and_then(|(reader, buf)| {
-			let header = try!(ser::deserialize::<MsgHeader>(&mut &buf[..]));
-			if header.msg_len > MAX_MSG_LEN {
-				// TODO add additional restrictions on a per-message-type basis to avoid 20MB
-	// pings
-				return Err(Error::Serialization(ser::Error::TooLargeReadErr));
-			}
+			let header = parse_header(&buf).map_err(Error::Corrupted)?;
 			Ok((reader, header))
 		});
 
 	let read_msg = read_header
 		.and_then(|(reader, header)| {
-			read_exact(reader, vec![0u8; header.msg_len as usize]).from_err()
+			read_exact(reader, vec![0u8; header.msg_len as usize])
+				.map(|(reader, buf)| (reader, header, buf))
+				.from_err()
 		})
-		.and_then(|(reader, buf)| {
+		.and_then(|(reader, header, buf)| {
+			if !header.verify(&buf) {
+				return Err(Error::Corrupted(Corruption::BadChecksum));
+			}
 			let body = try!(ser::deserialize(&mut &buf[..]));
 			Ok((reader, body))
 		});
@@ -101,7 +256,7 @@ where
 
 /// Future combinator to write a full message from a Writeable payload.
 /// Serializes the payload first and then sends the message header and that
-/// payload.
+/// payload. Only used for the handshake, the header is without checksum.
 pub fn write_msg<T>(
 	conn: TcpStream,
 	msg: T,
@@ -115,10 +270,10 @@ where
 		let mut body_buf = vec![];
 		ser::serialize(&mut body_buf, &msg).unwrap();
 
-		// build and serialize the header using the body size
+		// build and serialize the header from the body
 		let mut header_buf = vec![];
-		let blen = body_buf.len() as u64;
-		ser::serialize(&mut header_buf, &MsgHeader::new(msg_type, blen)).unwrap();
+		ser::serialize(&mut header_buf, &MsgHeader::new(msg_type, &body_buf)).unwrap();
+		header_buf.truncate(HEADER_LEN_V1 as usize);
 
 		// send the whole thing
 		write_all(conn, header_buf)
@@ -136,24 +291,98 @@ pub struct MsgHeader {
 	pub msg_type: Type,
 	/// Total length of the message in bytes.
 	pub msg_len: u64,
+	checksum: Option<[u8; 4]>,
 }
 
 impl MsgHeader {
-	/// Creates a new message header.
-	pub fn new(msg_type: Type, len: u64) -> MsgHeader {
+	/// Creates a new message header for the provided serialized body.
+	pub fn new(msg_type: Type, body: &[u8]) -> MsgHeader {
 		MsgHeader {
 			magic: MAGIC,
 			msg_type: msg_type,
-			msg_len: len,
+			msg_len: body.len() as u64,
+			checksum: Some(checksum(body)),
 		}
 	}
 
 	/// Serialized length of the header in bytes
 	pub fn serialized_len(&self) -> u64 {
+		header_len(self.checksum.is_some())
+	}
+
+	/// Whether the provided body is the one this header was built for,
+	/// checking its checksum if it has one.
+	pub fn verify(&self, body: &[u8]) -> bool {
+		body.len() as u64 == self.msg_len && match self.checksum {
+			Some(cs) => checksum(body) == cs,
+			None => true,
+		}
+	}
+}
+
+/// Size in bytes of the message headers exchanged with a peer, depending on
+/// whether they carry a checksum.
+pub fn header_len(checksum: bool) -> u64 {
+	if checksum {
 		HEADER_LEN
+	} else {
+		HEADER_LEN_V1
 	}
 }
 
+/// Turns a full message, as serialized with the header built by
+/// MsgHeader::new, into one for a peer not using checksums.
+pub fn strip_checksum(mut data: Vec<u8>) -> Vec<u8> {
+	if data.len() >= HEADER_LEN as usize {
+		data.drain(HEADER_LEN_V1 as usize..HEADER_LEN as usize);
+	}
+	data
+}
+
+// Checksum of a message body, the first 4 bytes of its hash.
+fn checksum(body: &[u8]) -> [u8; 4] {
+	let mut hasher = HashWriter::default();
+	hasher.write_fixed_bytes(&body).unwrap();
+	let h = hasher.into_hash();
+	[h.0[0], h.0[1], h.0[2], h.0[3]]
+}
+
+/// Parses a message header received from a peer, telling apart the ways it
+/// can be corrupted. A header with a type we don't know about still frames
+/// its message, which can be skipped. Messages over the maximum length of
+/// their type are refused before we allocate anything for their body. The
+/// header has a checksum or not depending on its length.
+pub fn parse_header(buf: &[u8]) -> Result<MsgHeader, Corruption> {
+	if (buf.len() != HEADER_LEN as usize && buf.len() != HEADER_LEN_V1 as usize)
+		|| buf[0..2] != MAGIC
+	{
+		return Err(Corruption::BadMagic);
+	}
+	let msg_len = buf[3..11]
+		.iter()
+		.fold(0u64, |len, b| (len << 8) | *b as u64);
+	if msg_len > MAX_MSG_LEN {
+		return Err(Corruption::TooLong(msg_len));
+	}
+	let msg_type = match Type::from_u8(buf[2]) {
+		Some(t) => t,
+		None => return Err(Corruption::UnknownType(buf[2], msg_len)),
+	};
+	if msg_len > msg_type.max_len() {
+		return Err(Corruption::Oversized(msg_type, msg_len));
+	}
+	Ok(MsgHeader {
+		magic: MAGIC,
+		msg_type: msg_type,
+		msg_len: msg_len,
+		checksum: if buf.len() == HEADER_LEN as usize {
+			Some([buf[11], buf[12], buf[13], buf[14]])
+		} else {
+			None
+		},
+	})
+}
+
 impl Writeable for MsgHeader {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		ser_multiwrite!(
@@ -163,6 +392,9 @@ impl Writeable for MsgHeader {
 			[write_u8, self.msg_type as u8],
 			[write_u64, self.msg_len]
 		);
+		if let Some(ref cs) = self.checksum {
+			try!(writer.write_fixed_bytes(cs));
+		}
 		Ok(())
 	}
 }
@@ -172,11 +404,13 @@ impl Readable for MsgHeader {
 		try!(reader.expect_u8(MAGIC[0]));
 		try!(reader.expect_u8(MAGIC[1]));
 		let (t, len) = ser_multiread!(reader, read_u8, read_u64);
+		let cs = try!(reader.read_fixed_bytes(4));
 		match Type::from_u8(t) {
 			Some(ty) => Ok(MsgHeader {
 				magic: MAGIC,
 				msg_type: ty,
 				msg_len: len,
+				checksum: Some([cs[0], cs[1], cs[2], cs[3]]),
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
@@ -225,7 +459,7 @@ impl Writeable for Hand {
 impl Readable for Hand {
 	fn read(reader: &mut Reader) -> Result<Hand, ser::Error> {
 		let (version, capab, nonce) = ser_multiread!(reader, read_u32, read_u32, read_u64);
-		let capabilities = try!(Capabilities::from_bits(capab).ok_or(ser::Error::CorruptedData,));
+		let capabilities = Capabilities::from_bits_truncate(capab);
 		let total_diff = try!(Difficulty::read(reader));
 		let sender_addr = try!(SockAddr::read(reader));
 		let receiver_addr = try!(SockAddr::read(reader));
@@ -278,7 +512,7 @@ impl Writeable for Shake {
 impl Readable for Shake {
 	fn read(reader: &mut Reader) -> Result<Shake, ser::Error> {
 		let (version, capab) = ser_multiread!(reader, read_u32, read_u32);
-		let capabilities = try!(Capabilities::from_bits(capab).ok_or(ser::Error::CorruptedData,));
+		let capabilities = Capabilities::from_bits_truncate(capab);
 		let total_diff = try!(Difficulty::read(reader));
 		let ua = try!(reader.read_vec());
 		let user_agent = try!(String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData));
@@ -308,7 +542,7 @@ impl Writeable for GetPeerAddrs {
 impl Readable for GetPeerAddrs {
 	fn read(reader: &mut Reader) -> Result<GetPeerAddrs, ser::Error> {
 		let capab = try!(reader.read_u32());
-		let capabilities = try!(Capabilities::from_bits(capab).ok_or(ser::Error::CorruptedData,));
+		let capabilities = Capabilities::from_bits_truncate(capab);
 		Ok(GetPeerAddrs {
 			capabilities: capabilities,
 		})
@@ -455,6 +689,33 @@ impl Readable for Locator {
 	}
 }
 
+/// Range of consecutive block headers of the current chain, by height.
+pub struct HeadersRange {
+	pub start_height: u64,
+	pub count: u16,
+}
+
+impl Writeable for HeadersRange {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(
+			writer,
+			[write_u64, self.start_height],
+			[write_u16, self.count]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for HeadersRange {
+	fn read(reader: &mut Reader) -> Result<HeadersRange, ser::Error> {
+		let (start_height, count) = ser_multiread!(reader, read_u64, read_u16);
+		Ok(HeadersRange {
+			start_height: start_height,
+			count: count,
+		})
+	}
+}
+
 /// Serializable wrapper for a list of block headers.
 pub struct Headers {
 	pub headers: Vec<BlockHeader>,
@@ -542,3 +803,224 @@ impl Readable for Pong {
 		Ok(Pong { total_difficulty, height })
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::core::hash::ZERO_HASH;
+
+	// Serializes the message, reads it back and checks it serializes the same
+	fn round_trip<T: Readable + Writeable>(msg: &T) -> Vec<u8> {
+		let body = ser::ser_vec(msg).unwrap();
+		let read: T = ser::deserialize(&mut &body[..]).unwrap();
+		assert_eq!(ser::ser_vec(&read).unwrap(), body);
+		body
+	}
+
+	// Length of a field at the start of a body, following how the spec says
+	// it's serialized. None for types serialized by the core crate.
+	fn field_len(field_type: &str, body: &[u8]) -> Option<usize> {
+		let be = |n: usize| body[..n].iter().fold(0, |acc, b| (acc << 8) + *b as usize);
+		let len = match field_type {
+			"u8" => 1,
+			"u16" => 2,
+			"u32" => 4,
+			"u64" => 8,
+			"Hash" => 32,
+			"bytes" => 8 + be(8),
+			"SockAddr" => if body[0] == 0 { 7 } else { 19 },
+			_ => {
+				let parts = field_type.split(" count followed by as many ").collect::<Vec<_>>();
+				if parts.len() != 2 {
+					return None;
+				}
+				let mut len = match field_len(parts[0], body) {
+					Some(len) => len,
+					None => return None,
+				};
+				for _ in 0..be(len) {
+					len += match field_len(parts[1], &body[len..]) {
+						Some(item_len) => item_len,
+						None => return None,
+					};
+				}
+				len
+			}
+		};
+		Some(len)
+	}
+
+	#[test]
+	// every message we can build without the core crate types round-trips
+	// and is laid out as the spec says
+	fn messages_match_spec() {
+		let addr = |s: &str| SockAddr(s.parse().unwrap());
+		let samples = vec![
+			(
+				Type::Error,
+				round_trip(&PeerError {
+					code: ErrCodes::UnsupportedVersion as u32,
+					message: "unsupported version".to_string(),
+				}),
+			),
+			(
+				Type::Hand,
+				round_trip(&Hand {
+					version: PROTOCOL_VERSION,
+					capabilities: Capabilities::FULL_HIST,
+					nonce: 7,
+					genesis: ZERO_HASH,
+					total_difficulty: Difficulty::from_num(10),
+					sender_addr: addr("10.0.0.1:13414"),
+					receiver_addr: addr("[::1]:13414"),
+					user_agent: USER_AGENT.to_string(),
+				}),
+			),
+			(
+				Type::Shake,
+				round_trip(&Shake {
+					version: PROTOCOL_VERSION,
+					capabilities: Capabilities::FULL_HIST,
+					genesis: ZERO_HASH,
+					total_difficulty: Difficulty::from_num(10),
+					user_agent: USER_AGENT.to_string(),
+				}),
+			),
+			(
+				Type::Ping,
+				round_trip(&Ping {
+					total_difficulty: Difficulty::from_num(10),
+					height: 3,
+				}),
+			),
+			(
+				Type::Pong,
+				round_trip(&Pong {
+					total_difficulty: Difficulty::from_num(10),
+					height: 3,
+				}),
+			),
+			(
+				Type::GetPeerAddrs,
+				round_trip(&GetPeerAddrs {
+					capabilities: Capabilities::PEER_LIST,
+				}),
+			),
+			(
+				Type::PeerAddrs,
+				round_trip(&PeerAddrs {
+					peers: vec![addr("10.0.0.1:13414"), addr("[::1]:13414")],
+				}),
+			),
+			(
+				Type::GetHeaders,
+				round_trip(&Locator {
+					hashes: vec![ZERO_HASH, ZERO_HASH],
+				}),
+			),
+			(Type::Headers, round_trip(&Headers { headers: vec![] })),
+			(Type::GetBlock, round_trip(&ZERO_HASH)),
+			(Type::GetCompactBlock, round_trip(&ZERO_HASH)),
+			(
+				Type::GetHeadersRange,
+				round_trip(&HeadersRange {
+					start_height: 5,
+					count: 10,
+				}),
+			),
+		];
+
+		let specs = message_specs();
+		for &(msg_type, ref body) in &samples {
+			let spec = specs.iter().find(|s| s.code == msg_type as u8).unwrap();
+			let mut len = 0;
+			for field in &spec.fields {
+				match field_len(&field.field_type, &body[len..]) {
+					Some(field_len) => len += field_len,
+					None => panic!("{:?} field {} not in the spec", msg_type, field.name),
+				}
+			}
+			assert_eq!(len, body.len(), "{:?}", msg_type);
+			assert!(len as u64 <= msg_type.max_len(), "{:?}", msg_type);
+		}
+
+		// the others only carry core types
+		let core_types = ["BlockHeader", "Block", "CompactBlock", "Transaction"];
+		for spec in &specs {
+			if !samples.iter().any(|s| s.0 as u8 == spec.code) {
+				assert!(
+					spec.fields
+						.iter()
+						.all(|f| core_types.iter().any(|t| *t == f.field_type)),
+					"no sample of {}",
+					spec.name
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn header_framing() {
+		let body = vec![1u8, 2, 3, 4, 5];
+		let mut buf = vec![];
+		ser::serialize(&mut buf, &MsgHeader::new(Type::Ping, &body)).unwrap();
+		assert_eq!(buf.len() as u64, HEADER_LEN);
+
+		let header = parse_header(&buf).unwrap();
+		assert_eq!(header.msg_type, Type::Ping);
+		assert_eq!(header.msg_len, 5);
+		assert!(header.verify(&body));
+		assert!(!header.verify(&[1, 2, 3, 4, 6]));
+
+		// a type from the future still frames its message
+		let mut unknown = buf.clone();
+		unknown[2] = 200;
+		assert_eq!(
+			parse_header(&unknown).err(),
+			Some(Corruption::UnknownType(200, 5))
+		);
+		let mut bad_magic = buf.clone();
+		bad_magic[0] = 0;
+		assert_eq!(parse_header(&bad_magic).err(), Some(Corruption::BadMagic));
+		let mut too_long = buf.clone();
+		too_long[3] = 0xff;
+		assert!(match parse_header(&too_long) {
+			Err(Corruption::TooLong(_)) => true,
+			_ => false,
+		});
+
+		// well under the global maximum, still too long for a ping
+		let ping = MsgHeader::new(Type::Ping, &[0u8; 17]);
+		let mut oversized = vec![];
+		ser::serialize(&mut oversized, &ping).unwrap();
+		assert_eq!(
+			parse_header(&oversized).err(),
+			Some(Corruption::Oversized(Type::Ping, 17))
+		);
+	}
+
+	#[test]
+	fn header_framing_without_checksum() {
+		let body = vec![1u8, 2, 3, 4, 5];
+		let mut buf = vec![];
+		ser::serialize(&mut buf, &MsgHeader::new(Type::Ping, &body)).unwrap();
+		buf.extend_from_slice(&body);
+
+		// what a peer older than CHECKSUM_VERSION sends and expects
+		let v1 = strip_checksum(buf.clone());
+		assert_eq!(v1.len() as u64, HEADER_LEN_V1 + 5);
+		assert_eq!(&v1[..HEADER_LEN_V1 as usize], &buf[..HEADER_LEN_V1 as usize]);
+		assert_eq!(&v1[HEADER_LEN_V1 as usize..], &body[..]);
+
+		let header = parse_header(&v1[..HEADER_LEN_V1 as usize]).unwrap();
+		assert_eq!(header.msg_type, Type::Ping);
+		assert_eq!(header.msg_len, 5);
+		assert_eq!(header.serialized_len(), header_len(false));
+		// nothing to check but the length
+		assert!(header.verify(&[1, 2, 3, 4, 6]));
+		assert!(!header.verify(&[1, 2, 3, 4]));
+
+		// any other length isn't a header
+		assert_eq!(parse_header(&buf[..12]).err(), Some(Corruption::BadMagic));
+	}
+}
@@ -0,0 +1,144 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Virtual network conditions between our node and its peers, to simulate
+//! latency, lost messages and network partitions in multi-node tests. The
+//! conditions apply to the messages received from a peer once connected,
+//! handshakes aren't affected. Without any conditions set, which is always
+//! the case outside of simulations, messages are handled as they arrive.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use rand;
+
+/// Conditions of the link with a peer, identified by its listening address.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkConditions {
+	/// Delay before processing each message received from the peer
+	pub latency: Option<Duration>,
+	/// Probability, between 0 and 1, of a message from the peer being lost
+	pub drop_rate: f64,
+	/// Whether the peer is on the other side of a partition, all its
+	/// messages being lost
+	pub partitioned: bool,
+}
+
+/// What happens to a message received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+	/// Processed right away
+	Deliver,
+	/// Processed after the provided delay
+	Delay(Duration),
+	/// Lost, never processed
+	Drop,
+}
+
+/// Conditions of the links with all our peers, shared by all connections and
+/// updatable while they're running.
+pub struct NetworkConditions {
+	links: RwLock<HashMap<SocketAddr, LinkConditions>>,
+}
+
+impl NetworkConditions {
+	/// No conditions, all messages get delivered right away.
+	pub fn new() -> NetworkConditions {
+		NetworkConditions {
+			links: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// Sets the conditions of the link with the provided peer.
+	pub fn set_link(&self, addr: SocketAddr, link: LinkConditions) {
+		let mut links = self.links.write().unwrap();
+		if link == LinkConditions::default() {
+			links.remove(&addr);
+		} else {
+			links.insert(addr, link);
+		}
+	}
+
+	/// Current conditions of the link with the provided peer.
+	pub fn link(&self, addr: &SocketAddr) -> LinkConditions {
+		self.links
+			.read()
+			.unwrap()
+			.get(addr)
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	/// Removes the conditions set on all links.
+	pub fn clear(&self) {
+		self.links.write().unwrap().clear();
+	}
+
+	/// Decides what happens to the next message received from the provided
+	/// peer.
+	pub fn delivery(&self, addr: &SocketAddr) -> Delivery {
+		let link = match self.links.read().unwrap().get(addr) {
+			Some(link) => *link,
+			None => return Delivery::Deliver,
+		};
+		if link.partitioned || (link.drop_rate > 0.0 && rand::random::<f64>() < link.drop_rate) {
+			return Delivery::Drop;
+		}
+		match link.latency {
+			Some(latency) => Delivery::Delay(latency),
+			None => Delivery::Deliver,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn link_delivery() {
+		let conditions = NetworkConditions::new();
+		let addr1 = "127.0.0.1:13414".parse().unwrap();
+		let addr2 = "127.0.0.1:13415".parse().unwrap();
+		assert_eq!(conditions.delivery(&addr1), Delivery::Deliver);
+
+		conditions.set_link(
+			addr1,
+			LinkConditions {
+				latency: Some(Duration::from_millis(200)),
+				..Default::default()
+			},
+		);
+		conditions.set_link(
+			addr2,
+			LinkConditions {
+				partitioned: true,
+				..Default::default()
+			},
+		);
+		assert_eq!(
+			conditions.delivery(&addr1),
+			Delivery::Delay(Duration::from_millis(200))
+		);
+		assert_eq!(conditions.delivery(&addr2), Delivery::Drop);
+
+		// back to normal once cleared
+		conditions.set_link(addr1, LinkConditions::default());
+		assert_eq!(conditions.delivery(&addr1), Delivery::Deliver);
+		conditions.clear();
+		assert_eq!(conditions.delivery(&addr2), Delivery::Deliver);
+	}
+}
@@ -14,15 +14,21 @@
 
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 
 use futures::Future;
 use futures_cpupool::CpuPool;
+use time;
 use tokio_core::net::TcpStream;
 
 use core::core;
 use core::core::hash::{Hash, Hashed};
 use core::core::target::Difficulty;
 use handshake::Handshake;
+use msg::ErrCodes;
+use netsim::NetworkConditions;
+use reputation::Misbehavior;
+use stats::TrafficStats;
 use types::*;
 use util::LOGGER;
 
@@ -48,7 +54,10 @@ unsafe impl Send for Peer {}
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, proto: Box<Protocol>, na: Arc<NetAdapter>) -> Peer {
+	fn new(mut info: PeerInfo, proto: Box<Protocol>, na: Arc<NetAdapter>) -> Peer {
+		let now = time::now_utc().to_timespec().sec;
+		info.metrics.connected_since = now;
+		info.metrics.last_activity = now;
 		Peer {
 			info: info,
 			proto: proto,
@@ -90,12 +99,18 @@ impl Peer {
 
 	/// Main peer loop listening for messages and forwarding to the rest of the
 	/// system.
-	pub fn run(&self, conn: TcpStream, pool: CpuPool) -> Box<Future<Item = (), Error = Error>> {
+	pub fn run(
+		&self,
+		conn: TcpStream,
+		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		conditions: Arc<NetworkConditions>,
+	) -> Box<Future<Item = (), Error = Error>> {
 		let addr = self.info.addr;
 		let state = self.state.clone();
 		let adapter = Arc::new(self.tracking_adapter.clone());
 
-		Box::new(self.proto.handle(conn, adapter, addr, pool).then(move |res| {
+		Box::new(self.proto.handle(conn, adapter.clone(), addr, pool, stats, conditions).then(move |res| {
 			// handle disconnection, standard disconnections aren't considered an error
 			let mut state = state.write().unwrap();
 			match res {
@@ -109,6 +124,20 @@ impl Peer {
 					info!(LOGGER, "Client {} corrupted, ban.", addr);
 					Err(Error::Serialization(e))
 				}
+				Err(Error::Corrupted(c)) => {
+					*state = State::Disconnected;
+					info!(LOGGER, "Client {} sent a corrupted stream: {:?}", addr, c);
+					adapter.peer_misbehaved(addr, Misbehavior::Corrupted);
+					Ok(())
+				}
+				Err(Error::Timeout) => {
+					// the connection only times out on a request the peer
+					// didn't answer
+					*state = State::Disconnected;
+					info!(LOGGER, "Client {} timed out on a request.", addr);
+					adapter.peer_misbehaved(addr, Misbehavior::Timeout);
+					Ok(())
+				}
 				Err(e) => {
 					*state = State::Disconnected;
 					debug!(LOGGER, "Client {} connection lost: {:?}", addr, e);
@@ -158,11 +187,37 @@ impl Peer {
 		*state = State::Banned;
 	}
 
+	/// Information on the peer, with up to date uptime and activity metrics.
+	pub fn current_info(&self) -> PeerInfo {
+		let mut info = self.info.clone();
+		let activity = &self.tracking_adapter.activity;
+		let last_activity = activity.last_activity.load(Ordering::Relaxed) as i64;
+		info.metrics.connected_secs =
+			time::now_utc().to_timespec().sec - info.metrics.connected_since;
+		info.metrics.blocks_received = activity.blocks.load(Ordering::Relaxed) as u64;
+		info.metrics.txs_received = activity.txs.load(Ordering::Relaxed) as u64;
+		if last_activity > info.metrics.last_activity {
+			info.metrics.last_activity = last_activity;
+		}
+		info
+	}
+
 	/// Bytes sent and received by this peer to the remote peer.
 	pub fn transmitted_bytes(&self) -> (u64, u64) {
 		self.proto.transmitted_bytes()
 	}
 
+	/// Bytes waiting to be sent to the remote peer.
+	pub fn queued_bytes(&self) -> u64 {
+		self.proto.queued_bytes()
+	}
+
+	/// Messages from the remote peer we had to skip, being corrupted or of a
+	/// type we don't know about.
+	pub fn skipped_msgs(&self) -> u64 {
+		self.proto.skipped_msgs()
+	}
+
 	pub fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error> {
 		self.proto.send_ping(total_difficulty, height)
 	}
@@ -226,10 +281,31 @@ impl Peer {
 		}
 	}
 
+	/// Sends the provided stem transaction to the remote peer, for it to
+	/// relay further along the stem. Dropped like a regular transaction if
+	/// the remote peer is known to already have it.
+	pub fn send_stem_transaction(&self, tx: &core::Transaction) -> Result<(), Error> {
+		if !self.tracking_adapter.has(tx.hash()) {
+			debug!(LOGGER, "Send stem tx {} to {}", tx.hash(), self.info.addr);
+			self.proto.send_stem_transaction(tx)
+		} else {
+			debug!(LOGGER, "Not sending stem tx {} to {} (already seen)", tx.hash(), self.info.addr);
+			Ok(())
+		}
+	}
+
 	pub fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error> {
 		self.proto.send_header_request(locator)
 	}
 
+	pub fn send_header_range_request(&self, start_height: u64, count: u16) -> Result<(), Error> {
+		debug!(
+			LOGGER,
+			"Requesting {} headers from height {} from {}", count, start_height, self.info.addr
+		);
+		self.proto.send_header_range_request(start_height, count)
+	}
+
 	pub fn send_block_request(&self, h: Hash) -> Result<(), Error> {
 		debug!(LOGGER, "Requesting block {} from {}", h, self.info.addr);
 		self.proto.send_block_request(h)
@@ -245,17 +321,43 @@ impl Peer {
 		self.proto.send_peer_request(capab)
 	}
 
+	/// Tells the remote peer we're shutting down, before closing the
+	/// connection.
+	pub fn send_goodbye(&self) -> Result<(), Error> {
+		debug!(LOGGER, "Saying goodbye to {}", self.info.addr);
+		self.proto.send_error(
+			ErrCodes::ShuttingDown as u32,
+			"shutting down".to_string(),
+		)
+	}
+
 	pub fn stop(&self) {
 		self.proto.close();
 	}
 }
 
+// What the remote peer sent us so far, updated as it gets forwarded
+#[derive(Default)]
+struct Activity {
+	blocks: AtomicUsize,
+	txs: AtomicUsize,
+	last_activity: AtomicIsize,
+}
+
+impl Activity {
+	fn touch(&self) {
+		let now = time::now_utc().to_timespec().sec;
+		self.last_activity.store(now as isize, Ordering::Relaxed);
+	}
+}
+
 /// Adapter implementation that forwards everything to an underlying adapter
 /// but keeps track of the block and transaction hashes that were received.
 #[derive(Clone)]
 struct TrackingAdapter {
 	adapter: Arc<NetAdapter>,
 	known: Arc<RwLock<Vec<Hash>>>,
+	activity: Arc<Activity>,
 }
 
 impl TrackingAdapter {
@@ -263,6 +365,7 @@ impl TrackingAdapter {
 		TrackingAdapter {
 			adapter: adapter,
 			known: Arc::new(RwLock::new(vec![])),
+			activity: Arc::new(Activity::default()),
 		}
 	}
 
@@ -292,26 +395,41 @@ impl ChainAdapter for TrackingAdapter {
 	}
 
 	fn transaction_received(&self, tx: core::Transaction) {
+		self.activity.txs.fetch_add(1, Ordering::Relaxed);
+		self.activity.touch();
 		self.push(tx.hash());
 		self.adapter.transaction_received(tx)
 	}
 
+	fn stem_transaction_received(&self, tx: core::Transaction) {
+		self.activity.txs.fetch_add(1, Ordering::Relaxed);
+		self.activity.touch();
+		self.push(tx.hash());
+		self.adapter.stem_transaction_received(tx)
+	}
+
 	fn block_received(&self, b: core::Block, addr: SocketAddr) -> bool {
+		self.activity.blocks.fetch_add(1, Ordering::Relaxed);
+		self.activity.touch();
 		self.push(b.hash());
 		self.adapter.block_received(b, addr)
 	}
 
 	fn compact_block_received(&self, cb: core::CompactBlock, addr: SocketAddr) -> bool {
+		self.activity.blocks.fetch_add(1, Ordering::Relaxed);
+		self.activity.touch();
 		self.push(cb.hash());
 		self.adapter.compact_block_received(cb, addr)
 	}
 
 	fn header_received(&self, bh: core::BlockHeader, addr: SocketAddr) -> bool {
+		self.activity.touch();
 		self.push(bh.hash());
 		self.adapter.header_received(bh, addr)
 	}
 
 	fn headers_received(&self, bh: Vec<core::BlockHeader>, addr: SocketAddr) {
+		self.activity.touch();
 		self.adapter.headers_received(bh, addr)
 	}
 
@@ -319,6 +437,10 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.locate_headers(locator)
 	}
 
+	fn headers_from_height(&self, height: u64, count: usize) -> Vec<core::BlockHeader> {
+		self.adapter.headers_from_height(height, count)
+	}
+
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		self.adapter.get_block(h)
 	}
@@ -330,10 +452,16 @@ impl NetAdapter for TrackingAdapter {
 	}
 
 	fn peer_addrs_received(&self, addrs: Vec<SocketAddr>) {
+		self.activity.touch();
 		self.adapter.peer_addrs_received(addrs)
 	}
 
 	fn peer_difficulty(&self, addr: SocketAddr, diff: Difficulty, height:u64) {
+		self.activity.touch();
 		self.adapter.peer_difficulty(addr, diff, height)
 	}
+
+	fn peer_misbehaved(&self, addr: SocketAddr, misbehavior: Misbehavior) {
+		self.adapter.peer_misbehaved(addr, misbehavior)
+	}
 }
@@ -0,0 +1,162 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CSV representation of a list of peers, to move the peers a node knows
+//! about to another node (i.e. one without access to the DNS seeds) with the
+//! usual spreadsheet and text tools. One peer per line, after a header line:
+//! address, capabilities (as bits), user agent, state and last banned time.
+
+use types::Capabilities;
+use store::{PeerData, State};
+
+const CSV_HEADER: &'static str = "addr,capabilities,user_agent,state,last_banned";
+
+/// Writes the provided peers as CSV, header line included.
+pub fn to_csv(peers: &[PeerData]) -> String {
+	let mut csv = String::from(CSV_HEADER);
+	csv.push('\n');
+	for p in peers {
+		csv.push_str(&format!(
+			"{},{},{},{:?},{}\n",
+			p.addr,
+			p.capabilities.bits(),
+			quote(&p.user_agent),
+			p.flags,
+			p.last_banned,
+		));
+	}
+	csv
+}
+
+/// Reads peers written as CSV by `to_csv`. The header line is optional and
+/// blank lines are skipped.
+pub fn from_csv(csv: &str) -> Result<Vec<PeerData>, String> {
+	let mut peers = vec![];
+	for (n, line) in csv.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line == CSV_HEADER {
+			continue;
+		}
+		let peer = parse_line(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+		peers.push(peer);
+	}
+	Ok(peers)
+}
+
+fn parse_line(line: &str) -> Result<PeerData, String> {
+	let fields = split_fields(line)?;
+	if fields.len() != 5 {
+		return Err(format!("{} fields instead of 5", fields.len()));
+	}
+	let addr = fields[0]
+		.parse()
+		.map_err(|_| format!("invalid address {}", fields[0]))?;
+	let bits = fields[1]
+		.parse()
+		.map_err(|_| format!("invalid capabilities {}", fields[1]))?;
+	let flags = match fields[3].as_str() {
+		"Healthy" => State::Healthy,
+		"Banned" => State::Banned,
+		"Defunct" => State::Defunct,
+		"Preferred" => State::Preferred,
+		s => return Err(format!("invalid state {}", s)),
+	};
+	let last_banned = fields[4]
+		.parse()
+		.map_err(|_| format!("invalid last banned time {}", fields[4]))?;
+	Ok(PeerData {
+		addr: addr,
+		capabilities: Capabilities::from_bits_truncate(bits),
+		user_agent: fields[2].clone(),
+		flags: flags,
+		last_banned: last_banned,
+	})
+}
+
+// quotes a field when it contains a separator or a quote, doubling quotes
+fn quote(field: &str) -> String {
+	if field.contains(',') || field.contains('"') {
+		format!("\"{}\"", field.replace("\"", "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+fn split_fields(line: &str) -> Result<Vec<String>, String> {
+	let mut fields = vec![];
+	let mut field = String::new();
+	let mut quoted = false;
+	let mut chars = line.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted => {
+				if chars.peek() == Some(&'"') {
+					chars.next();
+					field.push('"');
+				} else {
+					quoted = false;
+				}
+			}
+			'"' if field.is_empty() => quoted = true,
+			',' if !quoted => {
+				fields.push(field);
+				field = String::new();
+			}
+			c => field.push(c),
+		}
+	}
+	if quoted {
+		return Err("unterminated quoted field".to_string());
+	}
+	fields.push(field);
+	Ok(fields)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn csv_roundtrip() {
+		let peers = vec![
+			PeerData {
+				addr: "10.0.0.1:13414".parse().unwrap(),
+				capabilities: Capabilities::FULL_NODE,
+				user_agent: "MW/Grin 0.1, \"test\"".to_string(),
+				flags: State::Preferred,
+				last_banned: 0,
+			},
+			PeerData {
+				addr: "[::1]:13414".parse().unwrap(),
+				capabilities: Capabilities::UNKNOWN,
+				user_agent: "".to_string(),
+				flags: State::Banned,
+				last_banned: 1_500_000_000,
+			},
+		];
+		let csv = to_csv(&peers);
+		let read = from_csv(&csv).unwrap();
+		assert_eq!(read.len(), 2);
+		for (r, p) in read.iter().zip(peers.iter()) {
+			assert_eq!(r.addr, p.addr);
+			assert_eq!(r.capabilities, p.capabilities);
+			assert_eq!(r.user_agent, p.user_agent);
+			assert_eq!(r.flags, p.flags);
+			assert_eq!(r.last_banned, p.last_banned);
+		}
+
+		assert!(from_csv("10.0.0.1:13414,7,ua,Healthy").is_err());
+		assert!(from_csv("10.0.0.1:13414,7,ua,Unknown,0").is_err());
+	}
+}
@@ -14,7 +14,7 @@
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, Weak};
 
 use rand::{thread_rng, Rng};
 
@@ -22,9 +22,13 @@ use core::core;
 use core::core::hash::{Hash, Hashed};
 use core::core::target::Difficulty;
 use util::LOGGER;
+use util::memory::MemoryConsumer;
 use time;
 
+use netsim::NetworkConditions;
 use peer::Peer;
+use reputation::{Misbehavior, Reputation, BAN_SCORE};
+use stats::{MsgStats, TrafficStats};
 use store::{PeerData, PeerStore, State};
 use types::*;
 
@@ -33,37 +37,151 @@ pub struct Peers {
 	pub adapter: Arc<ChainAdapter>,
 	store: Arc<PeerStore>,
 	peers: Arc<RwLock<HashMap<SocketAddr, Arc<RwLock<Peer>>>>>,
-	config: P2PConfig,
+	config: Arc<RwLock<P2PConfig>>,
+	traffic: Arc<TrafficStats>,
+	conditions: Arc<NetworkConditions>,
+	reputations: Arc<RwLock<HashMap<SocketAddr, Reputation>>>,
+	// connections to each peer since we started
+	connections: Arc<RwLock<HashMap<SocketAddr, u32>>>,
 }
 
 unsafe impl Send for Peers {}
 unsafe impl Sync for Peers {}
 
 impl Peers {
-	pub fn new(store: PeerStore, adapter: Arc<ChainAdapter>, config: P2PConfig) -> Peers {
-		Peers {
+	pub fn new(
+		store: PeerStore,
+		adapter: Arc<ChainAdapter>,
+		config: Arc<RwLock<P2PConfig>>,
+	) -> Peers {
+		let peers = Peers {
 			adapter,
 			store: Arc::new(store),
 			peers: Arc::new(RwLock::new(HashMap::new())),
 			config,
+			traffic: Arc::new(TrafficStats::new()),
+			conditions: Arc::new(NetworkConditions::new()),
+			reputations: Arc::new(RwLock::new(HashMap::new())),
+			connections: Arc::new(RwLock::new(HashMap::new())),
+		};
+		peers.flag_preferred();
+		peers
+	}
+
+	/// Current configuration of the p2p layer.
+	pub fn config(&self) -> P2PConfig {
+		self.config.read().unwrap().clone()
+	}
+
+	/// Applies a reloaded configuration, except for the host and port we're
+	/// already listening on. Connected peers the new configuration denies get
+	/// disconnected and the newly preferred ones flagged as such.
+	pub fn update_config(&self, config: P2PConfig) {
+		{
+			let mut current = self.config.write().unwrap();
+			let (host, port) = (current.host, current.port);
+			*current = P2PConfig {
+				host,
+				port,
+				..config
+			};
+		}
+		for p in self.connected_peers() {
+			let p = p.read().unwrap();
+			if self.is_denied(p.info.addr) {
+				debug!(LOGGER, "Disconnecting peer {}, now denied.", p.info.addr);
+				p.stop();
+			}
+		}
+		self.flag_preferred();
+		info!(LOGGER, "Reloaded the p2p configuration.");
+	}
+
+	/// Whether the configuration denies connecting to the provided peer.
+	pub fn is_denied(&self, peer_addr: SocketAddr) -> bool {
+		Peer::is_denied(self.config(), peer_addr)
+	}
+
+	fn broadcast_fanout(&self) -> usize {
+		self.config.read().unwrap().broadcast_fanout as usize
+	}
+
+	// flags the peers of the configured preferred list in our store, adding
+	// the ones we don't know about yet
+	fn flag_preferred(&self) {
+		let preferred = match self.config().peers_preferred {
+			Some(preferred) => preferred,
+			None => return,
+		};
+		for addr in preferred {
+			let addr = match addr.parse::<SocketAddr>() {
+				Ok(addr) => addr,
+				Err(_) => {
+					warn!(LOGGER, "Invalid preferred peer address {}", addr);
+					continue;
+				}
+			};
+			let res = match self.get_peer(addr) {
+				Ok(ref data) if data.flags == State::Preferred => continue,
+				Ok(_) => self.update_state(addr, State::Preferred),
+				Err(_) => self.save_peer(&PeerData {
+					addr: addr,
+					capabilities: Capabilities::UNKNOWN,
+					user_agent: "".to_string(),
+					flags: State::Preferred,
+					last_banned: 0,
+				}),
+			};
+			if let Err(e) = res {
+				error!(LOGGER, "Couldn't flag {} as preferred: {:?}", addr, e);
+			}
 		}
 	}
 
+	/// Messages and bytes exchanged with all our peers so far, by message
+	/// type.
+	pub fn stats(&self) -> Vec<MsgStats> {
+		self.traffic.snapshot()
+	}
+
+	/// Traffic counters to update when running a peer connection.
+	pub fn traffic_stats(&self) -> Arc<TrafficStats> {
+		self.traffic.clone()
+	}
+
+	/// Conditions of the virtual network between us and our peers, only set
+	/// by simulations.
+	pub fn network_conditions(&self) -> Arc<NetworkConditions> {
+		self.conditions.clone()
+	}
+
 	/// Adds the peer to our internal peer mapping. Note that the peer is still
 	/// returned so the server can run it.
-	pub fn add_connected(&self, p: Peer) -> Arc<RwLock<Peer>> {
+	pub fn add_connected(&self, mut p: Peer) -> Arc<RwLock<Peer>> {
 		debug!(LOGGER, "Saving newly connected peer {}.", p.info.addr);
+		let flags = if self.is_preferred(&p.info.addr) {
+			State::Preferred
+		} else {
+			State::Healthy
+		};
 		let peer_data = PeerData {
 			addr: p.info.addr,
 			capabilities: p.info.capabilities,
 			user_agent: p.info.user_agent.clone(),
-			flags: State::Healthy,
+			flags: flags,
 			last_banned: 0,
 		};
 		if let Err(e) = self.save_peer(&peer_data) {
 			error!(LOGGER, "Could not save connected peer: {:?}", e);
 		}
 
+		{
+			let mut connections = self.connections.write().unwrap();
+			let count = connections.entry(p.info.addr).or_insert(0);
+			p.info.metrics.reconnects = *count;
+			*count += 1;
+		}
+
 		let addr = p.info.addr.clone();
 		let apeer = Arc::new(RwLock::new(p));
 		{
@@ -89,6 +207,48 @@ impl Peers {
 		res
 	}
 
+	/// Number of connected peers with all the provided capabilities.
+	pub fn capability_count(&self, capab: Capabilities) -> u32 {
+		self.connected_peers()
+			.iter()
+			.filter(|p| match p.try_read() {
+				Ok(p) => p.info.capabilities.contains(capab),
+				Err(_) => false,
+			})
+			.count() as u32
+	}
+
+	/// Disconnects a peer having none of the provided capabilities, to make
+	/// room for a peer with capabilities we're short of. Preferred peers are
+	/// never disconnected, outdated ones first. Returns the address of the
+	/// disconnected peer, if any could be.
+	pub fn make_room(&self, keep: Capabilities) -> Option<SocketAddr> {
+		let mut candidates = self.connected_peers()
+			.into_iter()
+			.filter(|p| match p.try_read() {
+				Ok(p) => !p.info.capabilities.intersects(keep) && !self.is_preferred(&p.info.addr),
+				Err(_) => false,
+			})
+			.collect::<Vec<_>>();
+		// outdated peers end up last
+		deprioritize_outdated(&mut candidates);
+		let peer = match candidates.pop() {
+			Some(peer) => peer,
+			None => return None,
+		};
+		let addr = {
+			let peer = peer.read().unwrap();
+			debug!(
+				LOGGER,
+				"Disconnecting {} to make room for a peer with {:?}", peer.info.addr, keep
+			);
+			peer.stop();
+			peer.info.addr
+		};
+		self.peers.write().unwrap().remove(&addr);
+		Some(addr)
+	}
+
 	/// Get a peer we're connected to by address.
 	pub fn get_connected_peer(&self, addr: &SocketAddr) -> Option<Arc<RwLock<Peer>>> {
 		self.peers.read().unwrap().get(addr).map(|p| p.clone())
@@ -119,6 +279,7 @@ impl Peers {
 			.collect::<Vec<_>>();
 
 		thread_rng().shuffle(&mut max_peers);
+		deprioritize_outdated(&mut max_peers);
 		max_peers
 	}
 
@@ -157,6 +318,7 @@ impl Peers {
 			.collect::<Vec<_>>();
 
 		thread_rng().shuffle(&mut max_peers);
+		deprioritize_outdated(&mut max_peers);
 		max_peers
 	}
 
@@ -178,21 +340,21 @@ impl Peers {
 		false
 	}
 
+	/// Whether the operator asked for the peer to be preferred
+	pub fn is_preferred(&self, peer_addr: &SocketAddr) -> bool {
+		match self.store.get_peer(*peer_addr) {
+			Ok(peer_data) => peer_data.flags == State::Preferred,
+			Err(_) => false,
+		}
+	}
+
 	/// Bans a peer, disconnecting it if we're currently connected
 	pub fn ban_peer(&self, peer_addr: &SocketAddr) {
-		if let Err(e) = self.update_state(peer_addr.clone(), State::Banned) {
+		let now = time::now_utc().to_timespec().sec;
+		if let Err(e) = self.store.ban_peer(peer_addr.clone(), now) {
 			error!(LOGGER, "Couldn't ban {}: {:?}", peer_addr, e);
 		}
 
-		if let Err(e) =
-			self.update_last_banned(peer_addr.clone(), time::now_utc().to_timespec().sec)
-		{
-			error!(
-				LOGGER,
-				"Couldn't update last_banned time {}: {:?}", peer_addr, e
-			);
-		}
-
 		if let Some(peer) = self.get_connected_peer(peer_addr) {
 			debug!(LOGGER, "Banning peer {}", peer_addr);
 			// setting peer status will get it removed at the next clean_peer
@@ -202,6 +364,35 @@ impl Peers {
 		}
 	}
 
+	/// Penalizes a peer that failed to deliver what we asked for, banning it
+	/// once its penalties add up to the ban score.
+	pub fn penalize_peer(&self, peer_addr: &SocketAddr, misbehavior: Misbehavior) {
+		let now = time::now_utc().to_timespec().sec;
+		let score = {
+			let mut reputations = self.reputations.write().unwrap();
+			let reputation = reputations.entry(*peer_addr).or_insert(Reputation::default());
+			reputation.penalize(misbehavior, now)
+		};
+		info!(
+			LOGGER,
+			"Penalizing peer {} for {:?}, score now {}", peer_addr, misbehavior, score
+		);
+		if score >= BAN_SCORE {
+			self.reputations.write().unwrap().remove(peer_addr);
+			self.ban_peer(peer_addr);
+		}
+	}
+
+	/// Current penalty score of a peer, 0 for a peer that never misbehaved.
+	pub fn peer_score(&self, peer_addr: &SocketAddr) -> u32 {
+		let now = time::now_utc().to_timespec().sec;
+		self.reputations
+			.read()
+			.unwrap()
+			.get(peer_addr)
+			.map_or(0, |r| r.score(now))
+	}
+
 	/// Unbans a peer, checks if it exists and banned then unban
 	pub fn unban_peer(&self, peer_addr: &SocketAddr) {
 		match self.get_peer(peer_addr.clone()) {
@@ -218,14 +409,38 @@ impl Peers {
 		};
 	}
 
-	/// Broadcasts the provided block to PEER_PREFERRED_COUNT of our peers.
-	/// We may be connected to PEER_MAX_COUNT peers so we only
+	/// Requests the block with the provided hash from the peer at addr,
+	/// typically the peer that sent us a block we couldn't connect yet.
+	/// Falls back to our most worked peer if we're not connected to that peer
+	/// anymore. Returns whether the request could be sent at all.
+	pub fn request_block(&self, h: Hash, addr: &SocketAddr) -> bool {
+		let peer = match self.get_connected_peer(addr) {
+			Some(peer) => Some(peer),
+			None => self.most_work_peer(),
+		};
+		if let Some(peer) = peer {
+			if let Ok(peer) = peer.read() {
+				if peer.is_connected() {
+					match peer.send_block_request(h) {
+						Ok(_) => return true,
+						Err(e) => {
+							debug!(LOGGER, "Error requesting block {} from {}: {:?}", h, addr, e)
+						}
+					}
+				}
+			}
+		}
+		false
+	}
+
+	/// Broadcasts the provided block to broadcast_fanout of our peers.
+	/// We may be connected to up to peer_max_count peers so we only
 	/// want to broadcast to a random subset of peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the block.
 	pub fn broadcast_block(&self, b: &core::Block) {
 		let peers = self.connected_peers();
-		let preferred_peers = 8;
+		let preferred_peers = self.broadcast_fanout();
 		let mut count = 0;
 		for p in peers.iter().take(preferred_peers) {
 			let p = p.read().unwrap();
@@ -249,7 +464,7 @@ impl Peers {
 
 	pub fn broadcast_compact_block(&self, b: &core::CompactBlock) {
 		let peers = self.connected_peers();
-		let preferred_peers = 8;
+		let preferred_peers = self.broadcast_fanout();
 		let mut count = 0;
 		for p in peers.iter().take(preferred_peers) {
 			let p = p.read().unwrap();
@@ -271,14 +486,14 @@ impl Peers {
 		);
 	}
 
-	/// Broadcasts the provided block to PEER_PREFERRED_COUNT of our peers.
-	/// We may be connected to PEER_MAX_COUNT peers so we only
+	/// Broadcasts the provided block to broadcast_fanout of our peers.
+	/// We may be connected to up to peer_max_count peers so we only
 	/// want to broadcast to a random subset of peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the block.
 	pub fn broadcast_header(&self, bh: &core::BlockHeader) {
 		let peers = self.connected_peers();
-		let preferred_peers = 8;
+		let preferred_peers = self.broadcast_fanout();
 		let mut count = 0;
 		for p in peers.iter().take(preferred_peers) {
 			let p = p.read().unwrap();
@@ -300,14 +515,14 @@ impl Peers {
 		);
 	}
 
-	/// Broadcasts the provided transaction to PEER_PREFERRED_COUNT of our peers.
-	/// We may be connected to PEER_MAX_COUNT peers so we only
+	/// Broadcasts the provided transaction to broadcast_fanout of our peers.
+	/// We may be connected to up to peer_max_count peers so we only
 	/// want to broadcast to a random subset of peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
 	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
 		let peers = self.connected_peers();
-		for p in peers.iter().take(8) {
+		for p in peers.iter().take(self.broadcast_fanout()) {
 			let p = p.read().unwrap();
 			if p.is_connected() {
 				if let Err(e) = p.send_transaction(tx) {
@@ -349,6 +564,52 @@ impl Peers {
 		self.store.exists_peer(peer_addr).map_err(From::from)
 	}
 
+	/// Saves the provided addresses as healthy peers, skipping the ones we
+	/// already know about. Returns the number of peers added.
+	pub fn add_peer_addrs(&self, peer_addrs: Vec<SocketAddr>) -> usize {
+		let peers = peer_addrs
+			.into_iter()
+			.map(|pa| PeerData {
+				addr: pa,
+				capabilities: Capabilities::UNKNOWN,
+				user_agent: "".to_string(),
+				flags: State::Healthy,
+				last_banned: 0,
+			})
+			.collect();
+		self.import_peers(peers)
+	}
+
+	/// Merges a list of peers exported by another node into the ones we
+	/// know about. Peers we already know, banned ones included, are left
+	/// untouched and the ones banned or defunct on the other node are
+	/// skipped. The others get saved as healthy, with the capabilities and
+	/// user agent they had. Returns the number of peers added.
+	pub fn import_peers(&self, peers: Vec<PeerData>) -> usize {
+		let mut added = 0;
+		for p in peers {
+			if p.flags == State::Banned || p.flags == State::Defunct {
+				continue;
+			}
+			if let Ok(e) = self.exists_peer(p.addr) {
+				if e {
+					continue;
+				}
+			}
+			let peer = PeerData {
+				flags: State::Healthy,
+				last_banned: 0,
+				..p
+			};
+			if let Err(e) = self.save_peer(&peer) {
+				error!(LOGGER, "Could not save peer address: {:?}", e);
+			} else {
+				added += 1;
+			}
+		}
+		added
+	}
+
 	/// Saves updated information about a peer
 	pub fn save_peer(&self, p: &PeerData) -> Result<(), Error> {
 		self.store.save_peer(p).map_err(From::from)
@@ -411,10 +672,17 @@ impl Peers {
 				.iter()
 				.map(|x| {
 					let p = x.read().unwrap();
-					p.info.addr.clone()
+					(p.info.addr.clone(), p.info.outdated)
 				})
 				.collect::<Vec<_>>()
 		};
+		// preferred peers are never dropped, outdated ones are dropped first
+		let mut addrs = addrs
+			.into_iter()
+			.filter(|&(a, _)| !self.is_preferred(&a))
+			.collect::<Vec<_>>();
+		addrs.sort_by_key(|&(_, outdated)| !outdated);
+		let addrs = addrs.into_iter().map(|(a, _)| a).collect::<Vec<_>>();
 
 		// now remove them taking a short-lived write lock each time
 		// maybe better to take write lock once and remove them all?
@@ -424,15 +692,33 @@ impl Peers {
 		}
 	}
 
-	pub fn stop(self) {
+	/// Says goodbye to all connected peers and closes their connections,
+	/// once the goodbyes are sent. Also makes sure the peer store is written
+	/// to disk.
+	pub fn stop(&self) {
 		let peers = self.connected_peers();
 		for peer in peers {
 			let peer = peer.read().unwrap();
+			if let Err(e) = peer.send_goodbye() {
+				debug!(LOGGER, "Could not say goodbye to {}: {:?}", peer.info.addr, e);
+			}
 			peer.stop();
 		}
+		if let Err(e) = self.store.sync() {
+			error!(LOGGER, "Could not sync the peer store: {:?}", e);
+		}
 	}
 }
 
+// Moves the outdated peers to the end of the list, keeping the order of the
+// others, so they're only picked when no other peer fits.
+fn deprioritize_outdated(peers: &mut Vec<Arc<RwLock<Peer>>>) {
+	peers.sort_by_key(|p| match p.try_read() {
+		Ok(p) => p.info.outdated,
+		Err(_) => true,
+	});
+}
+
 impl ChainAdapter for Peers {
 	fn total_difficulty(&self) -> Difficulty {
 		self.adapter.total_difficulty()
@@ -443,6 +729,9 @@ impl ChainAdapter for Peers {
 	fn transaction_received(&self, tx: core::Transaction) {
 		self.adapter.transaction_received(tx)
 	}
+	fn stem_transaction_received(&self, tx: core::Transaction) {
+		self.adapter.stem_transaction_received(tx)
+	}
 	fn block_received(&self, b: core::Block, peer_addr: SocketAddr) -> bool {
 		if !self.adapter.block_received(b, peer_addr) {
 			// if the peer sent us a block that's intrinsically bad
@@ -479,6 +768,9 @@ impl ChainAdapter for Peers {
 	fn locate_headers(&self, hs: Vec<Hash>) -> Vec<core::BlockHeader> {
 		self.adapter.locate_headers(hs)
 	}
+	fn headers_from_height(&self, height: u64, count: usize) -> Vec<core::BlockHeader> {
+		self.adapter.headers_from_height(height, count)
+	}
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		self.adapter.get_block(h)
 	}
@@ -496,23 +788,7 @@ impl NetAdapter for Peers {
 	/// A list of peers has been received from one of our peers.
 	fn peer_addrs_received(&self, peer_addrs: Vec<SocketAddr>) {
 		debug!(LOGGER, "Received {} peer addrs, saving.", peer_addrs.len());
-		for pa in peer_addrs {
-			if let Ok(e) = self.exists_peer(pa) {
-				if e {
-					continue;
-				}
-			}
-			let peer = PeerData {
-				addr: pa,
-				capabilities: Capabilities::UNKNOWN,
-				user_agent: "".to_string(),
-				flags: State::Healthy,
-				last_banned: 0,
-			};
-			if let Err(e) = self.save_peer(&peer) {
-				error!(LOGGER, "Could not save received peer address: {:?}", e);
-			}
-		}
+		self.add_peer_addrs(peer_addrs);
 	}
 
 	fn peer_difficulty(&self, addr: SocketAddr, diff: Difficulty, height: u64) {
@@ -531,7 +807,87 @@ impl NetAdapter for Peers {
 			if let Some(peer) = self.get_connected_peer(&addr) {
 				let mut peer = peer.write().unwrap();
 				peer.info.total_difficulty = diff;
+				peer.info.height = height;
+				peer.info.last_seen = time::now_utc().to_timespec().sec;
 			}
 		}
 	}
+
+	fn peer_misbehaved(&self, addr: SocketAddr, misbehavior: Misbehavior) {
+		if misbehavior == Misbehavior::Corrupted && self.config().ban_corrupted_peers {
+			debug!(LOGGER, "Banning peer {} for a corrupted stream", addr);
+			self.ban_peer(&addr);
+		} else {
+			self.penalize_peer(&addr, misbehavior)
+		}
+	}
+}
+
+/// Accounts for the data queued to be sent to peers. Peers whose connection
+/// can't keep up with what we send them are the ones accumulating data, so
+/// eviction disconnects the peers with the largest send queues.
+/// Accounts for the data queued to be sent to our peers in the global memory
+/// budget, only holding a weak reference to the connected peers.
+pub struct PeersMemory(Weak<RwLock<HashMap<SocketAddr, Arc<RwLock<Peer>>>>>);
+
+impl Peers {
+	/// Memory consumer for the send queues of the connected peers.
+	pub fn memory_consumer(&self) -> PeersMemory {
+		PeersMemory(Arc::downgrade(&self.peers))
+	}
+}
+
+impl PeersMemory {
+	fn connected_peers(&self) -> Vec<Arc<RwLock<Peer>>> {
+		match self.0.upgrade() {
+			Some(peers) => peers.read().unwrap().values().cloned().collect(),
+			None => vec![],
+		}
+	}
+}
+
+impl MemoryConsumer for PeersMemory {
+	fn memory_usage(&self) -> usize {
+		self.connected_peers()
+			.iter()
+			.filter_map(|p| p.try_read().ok().map(|p| p.queued_bytes() as usize))
+			.sum()
+	}
+
+	fn evict(&self, bytes: usize) -> usize {
+		let mut queues = self.connected_peers()
+			.into_iter()
+			.filter_map(|p| {
+				let queued = match p.try_read() {
+					Ok(peer) => peer.queued_bytes() as usize,
+					Err(_) => return None,
+				};
+				Some((queued, p))
+			})
+			.filter(|&(queued, _)| queued > 0)
+			.collect::<Vec<_>>();
+		queues.sort_by(|a, b| b.0.cmp(&a.0));
+
+		let mut freed = 0;
+		for (queued, peer) in queues {
+			if freed >= bytes {
+				break;
+			}
+			if let Ok(peer) = peer.try_read() {
+				debug!(
+					LOGGER,
+					"Disconnecting {} with {} bytes queued, over the memory budget",
+					peer.info.addr,
+					queued
+				);
+				peer.stop();
+				freed += queued;
+			}
+		}
+		freed
+	}
+
+	fn is_active(&self) -> bool {
+		self.0.upgrade().is_some()
+	}
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::sync::Arc;
 use std::net::SocketAddr;
 
@@ -28,6 +29,8 @@ use core::core::target::Difficulty;
 use core::ser;
 use conn::TimeoutConnection;
 use msg::*;
+use netsim::NetworkConditions;
+use stats::TrafficStats;
 use types::*;
 use util::LOGGER;
 use util::OneTime;
@@ -35,12 +38,15 @@ use util::OneTime;
 #[allow(dead_code)]
 pub struct ProtocolV1 {
 	conn: OneTime<TimeoutConnection>,
+	// whether message headers carry a checksum, as negotiated in the handshake
+	checksum: bool,
 }
 
 impl ProtocolV1 {
-	pub fn new() -> ProtocolV1 {
+	pub fn new(checksum: bool) -> ProtocolV1 {
 		ProtocolV1 {
 			conn: OneTime::new(),
+			checksum: checksum,
 		}
 	}
 }
@@ -53,8 +59,10 @@ impl Protocol for ProtocolV1 {
 		adapter: Arc<NetAdapter>,
 		addr: SocketAddr,
 		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		conditions: Arc<NetworkConditions>,
 	) -> Box<Future<Item = (), Error = Error>> {
-		let (conn, listener) = TimeoutConnection::listen(conn, pool, move |sender, header, data| {
+		let (conn, listener) = TimeoutConnection::listen(conn, addr, self.checksum, pool, stats, conditions, move |sender, header, data| {
 			let adapt = adapter.as_ref();
 			handle_payload(adapt, sender, header, data, addr)
 		});
@@ -69,6 +77,15 @@ impl Protocol for ProtocolV1 {
 		self.conn.borrow().transmitted_bytes()
 	}
 
+	/// Bytes waiting to be sent.
+	fn queued_bytes(&self) -> u64 {
+		self.conn.borrow().queued_bytes()
+	}
+
+	fn skipped_msgs(&self) -> u64 {
+		self.conn.borrow().skipped_msgs()
+	}
+
 	/// Sends a ping message to the remote peer. Will panic if handle has never
 	/// been called on this protocol.
 	fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error> {
@@ -99,6 +116,11 @@ impl Protocol for ProtocolV1 {
 		self.send_msg(Type::Transaction, tx)
 	}
 
+	/// Serializes and sends a stem transaction to our remote peer
+	fn send_stem_transaction(&self, tx: &core::Transaction) -> Result<(), Error> {
+		self.send_msg(Type::StemTransaction, tx)
+	}
+
 	fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error> {
 		self.send_request(
 			Type::GetHeaders,
@@ -108,6 +130,18 @@ impl Protocol for ProtocolV1 {
 		)
 	}
 
+	fn send_header_range_request(&self, start_height: u64, count: u16) -> Result<(), Error> {
+		self.send_request(
+			Type::GetHeadersRange,
+			Type::Headers,
+			&HeadersRange {
+				start_height: start_height,
+				count: count,
+			},
+			None,
+		)
+	}
+
 	fn send_block_request(&self, h: Hash) -> Result<(), Error> {
 		self.send_request(Type::GetBlock, Type::Block, &h, Some(h))
 	}
@@ -127,9 +161,24 @@ impl Protocol for ProtocolV1 {
 		)
 	}
 
-	/// Close the connection to the remote peer
+	/// Reports an error to the remote peer, usually right before closing the
+	/// connection.
+	fn send_error(&self, code: u32, message: String) -> Result<(), Error> {
+		self.send_msg(
+			Type::Error,
+			&PeerError {
+				code: code,
+				message: message,
+			},
+		)
+	}
+
+	/// Close the connection to the remote peer, once the messages already
+	/// queued have been sent.
 	fn close(&self) {
-		// TODO some kind of shutdown signal
+		if self.conn.is_initialized() {
+			self.conn.borrow().close();
+		}
 	}
 }
 
@@ -170,7 +219,7 @@ fn handle_payload(
 			let mut data = vec![];
 			try!(ser::serialize(
 				&mut data,
-				&MsgHeader::new(Type::Pong, body_data.len() as u64),
+				&MsgHeader::new(Type::Pong, &body_data),
 			));
 			data.append(&mut body_data);
 
@@ -192,6 +241,13 @@ fn handle_payload(
 			adapter.transaction_received(tx);
 			Ok(None)
 		}
+		Type::StemTransaction => {
+			let tx = ser::deserialize::<core::Transaction>(&mut &buf[..])?;
+			debug!(LOGGER, "handle_payload: StemTransaction: {}", tx.hash());
+
+			adapter.stem_transaction_received(tx);
+			Ok(None)
+		}
 		Type::GetBlock => {
 			let h = ser::deserialize::<Hash>(&mut &buf[..])?;
 			debug!(LOGGER, "handle_payload: GetBlock: {}", h);
@@ -204,7 +260,7 @@ fn handle_payload(
 				let mut data = vec![];
 				try!(ser::serialize(
 					&mut data,
-					&MsgHeader::new(Type::Block, body_data.len() as u64),
+					&MsgHeader::new(Type::Block, &body_data),
 				));
 				data.append(&mut body_data);
 				if let Err(e) = sender.unbounded_send(data) {
@@ -247,13 +303,13 @@ fn handle_payload(
 					try!(ser::serialize(&mut body_data, &b));
 					try!(ser::serialize(
 						&mut data,
-						&MsgHeader::new(Type::Block, body_data.len() as u64),
+						&MsgHeader::new(Type::Block, &body_data),
 					));
 				} else {
 					try!(ser::serialize(&mut body_data, &cb));
 					try!(ser::serialize(
 						&mut data,
-						&MsgHeader::new(Type::CompactBlock, body_data.len() as u64),
+						&MsgHeader::new(Type::CompactBlock, &body_data),
 					));
 				}
 
@@ -278,23 +334,20 @@ fn handle_payload(
 			debug!(LOGGER, "handle_payload: GetHeaders: {:?}", loc);
 
 			let headers = adapter.locate_headers(loc.hashes);
-
-			// serialize and send all the headers over
-			let mut body_data = vec![];
-			try!(ser::serialize(
-				&mut body_data,
-				&Headers { headers: headers },
-			));
-			let mut data = vec![];
-			try!(ser::serialize(
-				&mut data,
-				&MsgHeader::new(Type::Headers, body_data.len() as u64),
-			));
-			data.append(&mut body_data);
-			if let Err(e) = sender.unbounded_send(data) {
-				debug!(LOGGER, "handle_payload: GetHeaders, error sending: {:?}", e);
-			}
-
+			send_headers(sender, headers)?;
+			Ok(None)
+		}
+		// A peer is asking us for some headers by height
+		Type::GetHeadersRange => {
+			let range = ser::deserialize::<HeadersRange>(&mut &buf[..])?;
+			debug!(
+				LOGGER,
+				"handle_payload: GetHeadersRange: {} from {}", range.count, range.start_height
+			);
+
+			let count = cmp::min(range.count as u32, MAX_BLOCK_HEADERS);
+			let headers = adapter.headers_from_height(range.start_height, count as usize);
+			send_headers(sender, headers)?;
 			Ok(None)
 		}
 		// "header first" block propagation - if we have not yet seen this block
@@ -332,7 +385,7 @@ fn handle_payload(
 			let mut data = vec![];
 			try!(ser::serialize(
 				&mut data,
-				&MsgHeader::new(Type::PeerAddrs, body_data.len() as u64),
+				&MsgHeader::new(Type::PeerAddrs, &body_data),
 			));
 			data.append(&mut body_data);
 			if let Err(e) = sender.unbounded_send(data) {
@@ -346,9 +399,43 @@ fn handle_payload(
 			adapter.peer_addrs_received(peer_addrs.peers.iter().map(|pa| pa.0).collect());
 			Ok(None)
 		}
+		Type::Error => {
+			let err = ser::deserialize::<PeerError>(&mut &buf[..])?;
+			if err.code == ErrCodes::ShuttingDown as u32 {
+				debug!(LOGGER, "handle_payload: {} is shutting down", addr);
+			} else {
+				debug!(
+					LOGGER,
+					"handle_payload: {} reported error {}: {}", addr, err.code, err.message
+				);
+			}
+			Ok(None)
+		}
 		_ => {
 			debug!(LOGGER, "unknown message type {:?}", header.msg_type);
 			Ok(None)
 		}
 	}
 }
+
+// Sends the provided headers back to the peer that asked for them.
+fn send_headers(
+	sender: UnboundedSender<Vec<u8>>,
+	headers: Vec<core::BlockHeader>,
+) -> Result<(), ser::Error> {
+	let mut body_data = vec![];
+	try!(ser::serialize(
+		&mut body_data,
+		&Headers { headers: headers },
+	));
+	let mut data = vec![];
+	try!(ser::serialize(
+		&mut data,
+		&MsgHeader::new(Type::Headers, &body_data),
+	));
+	data.append(&mut body_data);
+	if let Err(e) = sender.unbounded_send(data) {
+		debug!(LOGGER, "handle_payload: error sending headers: {:?}", e);
+	}
+	Ok(())
+}
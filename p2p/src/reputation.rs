@@ -0,0 +1,89 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reputation of our peers, built from the misbehaviors we noticed short of
+//! intrinsically invalid data (which gets a peer banned right away). Each
+//! misbehavior adds penalty points to the peer score, which slowly decays
+//! over time, so an occasional slow response is forgiven while a peer that
+//! keeps failing us ends up banned.
+
+use std::cmp;
+
+/// Score at which a peer gets banned.
+pub const BAN_SCORE: u32 = 100;
+
+/// Seconds for a peer score to decay by one point.
+const DECAY_SECS: i64 = 30;
+
+/// Ways a peer can fail us, short of sending intrinsically invalid data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+	/// Didn't answer a request in time
+	Timeout,
+	/// Answered with data we couldn't use, i.e. headers that don't validate
+	BadData,
+	/// Sent a message stream we couldn't follow
+	Corrupted,
+}
+
+impl Misbehavior {
+	/// Points added to the peer score.
+	pub fn penalty(&self) -> u32 {
+		match *self {
+			Misbehavior::Timeout => 20,
+			Misbehavior::BadData | Misbehavior::Corrupted => 50,
+		}
+	}
+}
+
+/// Penalty score of a peer, decaying from the time of its last update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reputation {
+	score: u32,
+	updated: i64,
+}
+
+impl Reputation {
+	/// Current score of the peer at the provided time (in seconds).
+	pub fn score(&self, now: i64) -> u32 {
+		let decay = cmp::max(now - self.updated, 0) / DECAY_SECS;
+		self.score.saturating_sub(cmp::min(decay, u32::max_value() as i64) as u32)
+	}
+
+	/// Adds the penalty of the provided misbehavior to the peer score,
+	/// returning the new score.
+	pub fn penalize(&mut self, misbehavior: Misbehavior, now: i64) -> u32 {
+		self.score = self.score(now).saturating_add(misbehavior.penalty());
+		self.updated = now;
+		self.score
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn penalties_add_up_and_decay() {
+		let mut rep = Reputation::default();
+		assert_eq!(rep.penalize(Misbehavior::Timeout, 1000), 20);
+		assert_eq!(rep.penalize(Misbehavior::BadData, 1000), 70);
+		// 10 points forgiven after 5 minutes
+		assert_eq!(rep.score(1300), 60);
+		assert_eq!(rep.penalize(Misbehavior::BadData, 1300), 110);
+		assert!(rep.score(1300) >= BAN_SCORE);
+		// and everything eventually
+		assert_eq!(rep.score(100_000), 0);
+	}
+}
@@ -16,7 +16,7 @@
 //! other peers in the network.
 
 use std::cell::RefCell;
-use std::net::{SocketAddr, Shutdown};
+use std::net::{self, SocketAddr, Shutdown};
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
@@ -34,6 +34,7 @@ use core::core::target::Difficulty;
 use handshake::Handshake;
 use peer::Peer;
 use peers::Peers;
+use reputation::Misbehavior;
 use store::PeerStore;
 use types::*;
 use util::LOGGER;
@@ -49,6 +50,7 @@ impl ChainAdapter for DummyAdapter {
 		0
 	}
 	fn transaction_received(&self, _tx: core::Transaction) {}
+	fn stem_transaction_received(&self, _tx: core::Transaction) {}
 	fn block_received(&self, _b: core::Block, _addr: SocketAddr) -> bool { true }
 	fn compact_block_received(&self, _cb: core::CompactBlock, _addr: SocketAddr) -> bool { true }
 	fn header_received(&self, _bh: core::BlockHeader, _addr: SocketAddr) -> bool { true }
@@ -56,6 +58,9 @@ impl ChainAdapter for DummyAdapter {
 	fn locate_headers(&self, _loc: Vec<Hash>) -> Vec<core::BlockHeader> {
 		vec![]
 	}
+	fn headers_from_height(&self, _height: u64, _count: usize) -> Vec<core::BlockHeader> {
+		vec![]
+	}
 	fn get_block(&self, _: Hash) -> Option<core::Block> {
 		None
 	}
@@ -67,6 +72,7 @@ impl NetAdapter for DummyAdapter {
 	}
 	fn peer_addrs_received(&self, _: Vec<SocketAddr>) {}
 	fn peer_difficulty(&self, _: SocketAddr, _: Difficulty, _:u64) {}
+	fn peer_misbehaved(&self, _: SocketAddr, _: Misbehavior) {}
 }
 
 /// P2P server implementation, handling bootstrapping to find and connect to
@@ -94,11 +100,13 @@ impl Server {
 		genesis: Hash,
 		pool: CpuPool,
 	) -> Result<Server, Error> {
+		// shared by the handshake and peers, so reloads apply to both
+		let shared_config = Arc::new(RwLock::new(config.clone()));
 		Ok(Server {
-			config: config.clone(),
+			config: config,
 			capabilities: capab,
-			handshake: Arc::new(Handshake::new(genesis, config.clone())),
-			peers: Peers::new(PeerStore::new(db_root)?, adapter, config.clone()),
+			handshake: Arc::new(Handshake::new(genesis, shared_config.clone())),
+			peers: Peers::new(PeerStore::new(db_root)?, adapter, shared_config),
 			pool: pool,
 			stop: RefCell::new(None),
 		})
@@ -107,8 +115,21 @@ impl Server {
 	/// Starts the p2p server. Opens a TCP port to allow incoming
 	/// connections and starts the bootstrapping process to find peers.
 	pub fn start(&self, h: reactor::Handle) -> Box<Future<Item = (), Error = Error>> {
+		self.start_with(h, None)
+	}
+
+	/// Same as start, accepting incoming connections on the provided
+	/// listener if any, already bound to the configured address.
+	pub fn start_with(
+		&self,
+		h: reactor::Handle,
+		listener: Option<net::TcpListener>,
+	) -> Box<Future<Item = (), Error = Error>> {
 		let addr = SocketAddr::new(self.config.host, self.config.port);
-		let socket = TcpListener::bind(&addr, &h.clone()).unwrap();
+		let socket = match listener {
+			Some(l) => TcpListener::from_listener(l, &addr, &h.clone()).unwrap(),
+			None => TcpListener::bind(&addr, &h.clone()).unwrap(),
+		};
 		warn!(LOGGER, "P2P server started on {}", addr);
 
 		let handshake = self.handshake.clone();
@@ -141,6 +162,8 @@ impl Server {
 				Ok(conn)
 			}).and_then(move |conn| {
 				let total_diff = peers2.total_difficulty();
+				let traffic = peers2.traffic_stats();
+				let conditions = peers2.network_conditions();
 
 				// accept the peer and add it to the server map
 				let accept = Peer::accept(
@@ -158,7 +181,7 @@ impl Server {
 				// run the main peer protocol
 				timed_peer.and_then(move |(conn, peer)| {
 					let peer = peer.read().unwrap();
-					peer.run(conn, pool)
+					peer.run(conn, pool, traffic, conditions)
 				})
 			})
 		});
@@ -217,7 +240,7 @@ impl Server {
 		h: reactor::Handle,
 	) -> Box<Future<Item = Option<Arc<RwLock<Peer>>>, Error = Error>> {
 
-		if Peer::is_denied(self.config.clone(), addr) {
+		if self.peers.is_denied(addr) {
 			debug!(LOGGER, "Peer {} denied, not connecting.", addr);
 			return Box::new(future::err(Error::ConnectionClose));
 		}
@@ -235,13 +258,16 @@ impl Server {
 		let handshake = self.handshake.clone();
 		let capab = self.capabilities.clone();
 		let pool = self.pool.clone();
+		let traffic = self.peers.traffic_stats();
+		let conditions = self.peers.network_conditions();
 
 		let self_addr = SocketAddr::new(self.config.host, self.config.port);
+		let dial_timeout = Duration::from_secs(self.peers.config().dial_timeout);
 
 		let timer = Timer::default();
 		let socket_connect = timer.timeout(
 			TcpStream::connect(&addr, &h),
-			Duration::from_secs(5),
+			dial_timeout,
 		).map_err(|e| {
 			debug!(LOGGER, "connect_peer: socket connect error - {:?}", e);
 			Error::Connection(e)
@@ -267,7 +293,7 @@ impl Server {
 			})
 			.and_then(move |(socket, peer)| {
 				let peer_inner = peer.read().unwrap();
-				h2.spawn(peer_inner.run(socket, pool).map_err(|e| {
+				h2.spawn(peer_inner.run(socket, pool, traffic, conditions).map_err(|e| {
 					error!(LOGGER, "Peer error: {:?}", e);
 					()
 				}));
@@ -276,11 +302,14 @@ impl Server {
 		Box::new(request)
 	}
 
-	/// Stops the server. Disconnect from all peers at the same time.
-	pub fn stop(self) {
+	/// Stops the server: stops accepting connections and disconnects from all
+	/// peers at the same time, saying goodbye first.
+	pub fn stop(&self) {
 		info!(LOGGER, "calling stop on server");
+		if let Some(stop) = self.stop.borrow_mut().take() {
+			let _ = stop.send(());
+		}
 		self.peers.stop();
-		self.stop.into_inner().unwrap().send(()).unwrap();
 	}
 }
 
@@ -0,0 +1,136 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Traffic statistics of the p2p layer, counting the messages and bytes sent
+//! to and received from all our peers, by message type. Handshake messages
+//! are exchanged before a connection is set up and aren't counted.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use num::FromPrimitive;
+
+use msg::Type;
+
+/// Messages and bytes exchanged for a given message type, over all peers.
+/// Byte counts include message headers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MsgStats {
+	/// Name of the message type
+	pub msg_type: String,
+	/// Number of messages sent
+	pub sent_msgs: u64,
+	/// Bytes sent
+	pub sent_bytes: u64,
+	/// Number of messages received
+	pub received_msgs: u64,
+	/// Bytes received
+	pub received_bytes: u64,
+}
+
+/// Counters shared by all the peer connections, updated anytime a message
+/// gets written to or read from a connection.
+pub struct TrafficStats {
+	counters: Mutex<HashMap<u8, MsgStats>>,
+}
+
+impl TrafficStats {
+	/// New statistics, with all counters at zero.
+	pub fn new() -> TrafficStats {
+		TrafficStats {
+			counters: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Records a message of the provided type and length sent to a peer.
+	pub fn record_sent(&self, msg_type: Type, bytes: u64) {
+		let mut counters = self.counters.lock().unwrap();
+		let stats = counters
+			.entry(msg_type as u8)
+			.or_insert_with(|| new_msg_stats(msg_type));
+		stats.sent_msgs += 1;
+		stats.sent_bytes += bytes;
+	}
+
+	/// Records a message of the provided type and length received from a
+	/// peer.
+	pub fn record_received(&self, msg_type: Type, bytes: u64) {
+		let mut counters = self.counters.lock().unwrap();
+		let stats = counters
+			.entry(msg_type as u8)
+			.or_insert_with(|| new_msg_stats(msg_type));
+		stats.received_msgs += 1;
+		stats.received_bytes += bytes;
+	}
+
+	/// Current counters of all the message types exchanged so far, ordered
+	/// by type code.
+	pub fn snapshot(&self) -> Vec<MsgStats> {
+		let counters = self.counters.lock().unwrap();
+		let mut codes = counters.keys().cloned().collect::<Vec<_>>();
+		codes.sort();
+		codes.iter().map(|c| counters[c].clone()).collect()
+	}
+}
+
+fn new_msg_stats(msg_type: Type) -> MsgStats {
+	MsgStats {
+		msg_type: format!("{:?}", msg_type),
+		..Default::default()
+	}
+}
+
+/// Message type of a serialized message, read from its header, if any.
+pub fn msg_type_of(data: &[u8]) -> Option<Type> {
+	// the type follows the 2 magic bytes
+	if data.len() < 3 {
+		return None;
+	}
+	Type::from_u8(data[2])
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::ser;
+	use msg::MsgHeader;
+
+	#[test]
+	fn count_by_type() {
+		let stats = TrafficStats::new();
+		stats.record_sent(Type::Ping, 27);
+		stats.record_sent(Type::Ping, 27);
+		stats.record_received(Type::Pong, 27);
+		stats.record_received(Type::Block, 1_000);
+
+		let snapshot = stats.snapshot();
+		assert_eq!(snapshot.len(), 3);
+		assert_eq!(snapshot[0].msg_type, "Ping");
+		assert_eq!(snapshot[0].sent_msgs, 2);
+		assert_eq!(snapshot[0].sent_bytes, 54);
+		assert_eq!(snapshot[0].received_msgs, 0);
+		assert_eq!(snapshot[1].msg_type, "Pong");
+		assert_eq!(snapshot[1].received_msgs, 1);
+		assert_eq!(snapshot[2].msg_type, "Block");
+		assert_eq!(snapshot[2].received_bytes, 1_000);
+	}
+
+	#[test]
+	fn type_from_header() {
+		let mut data = vec![];
+		ser::serialize(&mut data, &MsgHeader::new(Type::Headers, &[0u8; 10])).unwrap();
+		assert_eq!(msg_type_of(&data), Some(Type::Headers));
+		assert_eq!(msg_type_of(&data[..2]), None);
+	}
+}
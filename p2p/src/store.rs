@@ -19,7 +19,7 @@ use num::FromPrimitive;
 use rand::{thread_rng, Rng};
 
 use core::ser::{self, Readable, Reader, Writeable, Writer};
-use grin_store::{self, option_to_not_found, to_key, Error};
+use grin_store::{self, option_to_not_found, to_key, Error, SerIterator};
 use msg::SockAddr;
 use types::Capabilities;
 use util::LOGGER;
@@ -35,6 +35,8 @@ enum_from_primitive! {
 		Healthy,
 		Banned,
 		Defunct,
+		// healthy peer the operator wants us to stay connected to
+		Preferred,
 	}
 }
 
@@ -121,36 +123,71 @@ impl PeerStore {
 	}
 
 	pub fn find_peers(&self, state: State, cap: Capabilities, count: usize) -> Vec<PeerData> {
-		let mut peers = self.db
-			.iter::<PeerData>(&to_key(PEER_PREFIX, &mut "".to_string().into_bytes()))
-			.filter(|p| p.flags == state && p.capabilities.contains(cap))
-			.collect::<Vec<_>>();
+		let mut peers = self.peers_iter()
+			.map(|iter| {
+				iter.filter(|p| p.flags == state && p.capabilities.contains(cap))
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or(vec![]);
 		thread_rng().shuffle(&mut peers[..]);
-		peers.iter().take(count).cloned().collect()
+		peers.truncate(count);
+		peers
 	}
 
 	/// List all known peers
 	/// Used for /v1/peers/all api endpoint
 	pub fn all_peers(&self) -> Vec<PeerData> {
+		self.peers_iter()
+			.map(|iter| iter.collect::<Vec<_>>())
+			.unwrap_or(vec![])
+	}
+
+	// Iterates over the stored peers only, deserializing them lazily
+	fn peers_iter(&self) -> Result<SerIterator<PeerData>, Error> {
 		self.db
-			.iter::<PeerData>(&to_key(PEER_PREFIX, &mut "".to_string().into_bytes()))
-			.collect::<Vec<_>>()
+			.iter::<PeerData>(&to_key(PEER_PREFIX, &mut vec![]))
+			.map_err(|e| {
+				error!(LOGGER, "Could not read the peer store: {:?}", e);
+				e
+			})
+	}
+
+	/// Makes sure everything saved so far is written to disk.
+	pub fn sync(&self) -> Result<(), Error> {
+		self.db.sync()
 	}
 
 	/// Convenience method to load a peer data, update its status and save it
 	/// back.
 	pub fn update_state(&self, peer_addr: SocketAddr, new_state: State) -> Result<(), Error> {
-		let mut peer = self.get_peer(peer_addr)?;
-		peer.flags = new_state;
-		self.save_peer(&peer)
+		self.update_peer(peer_addr, |peer| peer.flags = new_state)
 	}
 
 	/// Convenience method to load a peer data, update its last banned time and
 	/// save it back.
 	pub fn update_last_banned(&self, peer_addr: SocketAddr, last_banned: i64) -> Result<(), Error> {
-		let mut peer = self.get_peer(peer_addr)?;
-		peer.last_banned = last_banned;
-		self.save_peer(&peer)
+		self.update_peer(peer_addr, |peer| peer.last_banned = last_banned)
+	}
+
+	/// Marks a peer as banned since the provided time, updating both its
+	/// status and last banned time at once.
+	pub fn ban_peer(&self, peer_addr: SocketAddr, last_banned: i64) -> Result<(), Error> {
+		self.update_peer(peer_addr, |peer| {
+			peer.flags = State::Banned;
+			peer.last_banned = last_banned;
+		})
+	}
+
+	// Loads a peer data, applies the update and saves it back, all in a single
+	// write transaction so concurrent updates can't interleave.
+	fn update_peer<F>(&self, peer_addr: SocketAddr, update: F) -> Result<(), Error>
+	where
+		F: FnOnce(&mut PeerData),
+	{
+		let batch = self.db.batch()?;
+		let mut peer: PeerData = option_to_not_found(batch.get_ser(&peer_key(peer_addr)[..]))?;
+		update(&mut peer);
+		batch.put_ser(&peer_key(peer_addr)[..], &peer)?.write()
 	}
 }
 
@@ -27,6 +27,10 @@ use core::core::hash::Hash;
 use core::core::target::Difficulty;
 use core::ser;
 use grin_store;
+use msg::Type;
+use netsim::NetworkConditions;
+use reputation::Misbehavior;
+use stats::TrafficStats;
 
 /// Maximum number of block headers a peer should ever send
 pub const MAX_BLOCK_HEADERS: u32 = 512;
@@ -45,6 +49,7 @@ pub enum Error {
 	Banned,
 	ConnectionClose,
 	Timeout,
+	Corrupted(Corruption),
 	Store(grin_store::Error),
 	PeerWithSelf,
 	ProtocolMismatch {
@@ -57,6 +62,24 @@ pub enum Error {
 	},
 }
 
+/// Ways the stream of messages received from a peer can be corrupted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Corruption {
+	/// A message header without the magic number, we lost track of where
+	/// messages start
+	BadMagic,
+	/// A message longer than any message can be
+	TooLong(u64),
+	/// A message longer than the maximum for its type
+	Oversized(Type, u64),
+	/// A message of a type we don't know about, with its length
+	UnknownType(u8, u64),
+	/// A message body not matching the checksum in its header
+	BadChecksum,
+	/// More messages we couldn't use than we tolerate from a peer
+	TooManySkipped(u64),
+}
+
 impl From<ser::Error> for Error {
 	fn from(e: ser::Error) -> Error {
 		Error::Serialization(e)
@@ -78,7 +101,8 @@ impl From<TimerError> for Error {
 	}
 }
 
-/// Configuration for the peer-to-peer server.
+/// Configuration for the peer-to-peer server. Everything but the host and
+/// port can be reloaded while running.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct P2PConfig {
 	pub host: IpAddr,
@@ -87,6 +111,109 @@ pub struct P2PConfig {
 	pub peers_allow: Option<Vec<String>>,
 
 	pub peers_deny: Option<Vec<String>>,
+
+	/// Peers to flag as preferred, which we try to stay connected to
+	#[serde(default)]
+	pub peers_preferred: Option<Vec<String>>,
+
+	/// Maximum number of connected peers, the extra ones getting dropped
+	#[serde(default = "default_peer_max_count")]
+	pub peer_max_count: u32,
+
+	/// Below this number of connected peers, we look for more
+	#[serde(default = "default_peer_min_preferred_count")]
+	pub peer_min_preferred_count: u32,
+
+	/// Number of peers we broadcast blocks, headers and transactions to
+	#[serde(default = "default_broadcast_fanout")]
+	pub broadcast_fanout: u32,
+
+	/// Maximum number of outbound connections attempted at the same time,
+	/// the other addresses waiting for their turn
+	#[serde(default = "default_dial_concurrency")]
+	pub dial_concurrency: u32,
+
+	/// Seconds to wait for an outbound connection to be established before
+	/// giving up on the address
+	#[serde(default = "default_dial_timeout")]
+	pub dial_timeout: u64,
+
+	/// Minimum protocol version of the peers we connect to
+	#[serde(default)]
+	pub min_peer_version: Option<u32>,
+
+	/// Minimum user agent of the peers we connect to, i.e. "MW/Grin 0.2".
+	/// Only applies to peers running the same software, the version after
+	/// the last space being compared.
+	#[serde(default)]
+	pub min_user_agent: Option<String>,
+
+	/// What to do with peers below the minimum version or user agent
+	#[serde(default)]
+	pub outdated_peer_policy: OutdatedPeerPolicy,
+
+	/// Minimum number of connected peers with the full block history, more
+	/// getting dialed (replacing peers without it if needed) when below
+	#[serde(default = "default_min_full_hist_peers")]
+	pub min_full_hist_peers: u32,
+
+	/// Minimum number of connected peers able to provide the txhashset, more
+	/// getting dialed (replacing peers without it if needed) when below
+	#[serde(default = "default_min_utxo_hist_peers")]
+	pub min_utxo_hist_peers: u32,
+
+	/// Whether to ban the peers sending us a corrupted message stream right
+	/// away, instead of only penalizing them
+	#[serde(default)]
+	pub ban_corrupted_peers: bool,
+}
+
+/// What to do with peers advertising a protocol version or user agent below
+/// the configured minimum, on handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OutdatedPeerPolicy {
+	/// Refuse the connection
+	Reject,
+	/// Connect, but never sync from them when other peers are available and
+	/// drop them first when over the maximum peer count
+	Deprioritize,
+	/// Only log them, to check the impact of a new minimum before enforcing
+	/// it
+	LogOnly,
+}
+
+impl Default for OutdatedPeerPolicy {
+	fn default() -> OutdatedPeerPolicy {
+		OutdatedPeerPolicy::Reject
+	}
+}
+
+fn default_peer_max_count() -> u32 {
+	25
+}
+
+fn default_peer_min_preferred_count() -> u32 {
+	8
+}
+
+fn default_broadcast_fanout() -> u32 {
+	8
+}
+
+fn default_dial_concurrency() -> u32 {
+	8
+}
+
+fn default_dial_timeout() -> u64 {
+	5
+}
+
+fn default_min_full_hist_peers() -> u32 {
+	2
+}
+
+fn default_min_utxo_hist_peers() -> u32 {
+	2
 }
 
 /// Default address for peer-to-peer connections.
@@ -98,6 +225,18 @@ impl Default for P2PConfig {
 			port: 13414,
 			peers_allow: None,
 			peers_deny: None,
+			peers_preferred: None,
+			peer_max_count: default_peer_max_count(),
+			peer_min_preferred_count: default_peer_min_preferred_count(),
+			broadcast_fanout: default_broadcast_fanout(),
+			dial_concurrency: default_dial_concurrency(),
+			dial_timeout: default_dial_timeout(),
+			min_peer_version: None,
+			min_user_agent: None,
+			outdated_peer_policy: OutdatedPeerPolicy::default(),
+			min_full_hist_peers: default_min_full_hist_peers(),
+			min_utxo_hist_peers: default_min_utxo_hist_peers(),
+			ban_corrupted_peers: false,
 		}
 	}
 }
@@ -115,11 +254,23 @@ bitflags! {
 	const UTXO_HIST = 0b00000010;
 	/// Can provide a list of healthy peers
 	const PEER_LIST = 0b00000100;
+	/// Can provide block headers by height range, so header sync can
+	/// request them from several peers in parallel
+	const HEADER_RANGES = 0b00001000;
 
 	const FULL_NODE = Capabilities::FULL_HIST.bits | Capabilities::UTXO_HIST.bits | Capabilities::PEER_LIST.bits;
   }
 }
 
+/// Which side initiated the connection with a peer
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+	/// The peer connected to us
+	Inbound,
+	/// We connected to the peer
+	Outbound,
+}
+
 /// General information about a connected peer that's useful to other modules.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeerInfo {
@@ -127,7 +278,39 @@ pub struct PeerInfo {
 	pub user_agent: String,
 	pub version: u32,
 	pub addr: SocketAddr,
+	pub direction: Direction,
 	pub total_difficulty: Difficulty,
+	/// Height last advertised by the peer, 0 until its first ping or pong
+	pub height: u64,
+	/// Last time the peer advertised its total difficulty, in seconds since
+	/// the epoch
+	pub last_seen: i64,
+	/// Whether the peer is below our minimum version or user agent and got
+	/// deprioritized
+	#[serde(default)]
+	pub outdated: bool,
+	/// Uptime and activity of the connection, telling the peers actually
+	/// useful from the ones churning
+	#[serde(default)]
+	pub metrics: PeerMetrics,
+}
+
+/// Uptime and activity of a connected peer.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerMetrics {
+	/// When the connection got established, in seconds since the epoch
+	pub connected_since: i64,
+	/// How long the connection has been up, in seconds
+	pub connected_secs: i64,
+	/// Earlier connections to the peer since the node started
+	pub reconnects: u32,
+	/// Full and compact blocks received from the peer
+	pub blocks_received: u64,
+	/// Transactions received from the peer
+	pub txs_received: u64,
+	/// Last time the peer sent us blocks, headers, transactions, peers or a
+	/// ping, in seconds since the epoch
+	pub last_activity: i64,
 }
 
 /// A given communication protocol agreed upon between 2 peers (usually
@@ -137,9 +320,17 @@ pub trait Protocol {
 	/// Starts handling protocol communication, the connection) is expected to
 	/// be  known already, usually passed during construction. Will typically
 	/// block so needs to be called withing a coroutine. Should also be called
-	/// only once.
-	fn handle(&self, conn: TcpStream, na: Arc<NetAdapter>, addr: SocketAddr, pool: CpuPool)
-		-> Box<Future<Item = (), Error = Error>>;
+	/// only once. Traffic gets counted in the provided stats and received
+	/// messages go through the provided network conditions.
+	fn handle(
+		&self,
+		conn: TcpStream,
+		na: Arc<NetAdapter>,
+		addr: SocketAddr,
+		pool: CpuPool,
+		stats: Arc<TrafficStats>,
+		conditions: Arc<NetworkConditions>,
+	) -> Box<Future<Item = (), Error = Error>>;
 
 	/// Sends a ping message to the remote peer.
 	fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error>;
@@ -155,9 +346,16 @@ pub trait Protocol {
 	/// Relays a transaction to the remote peer.
 	fn send_transaction(&self, tx: &core::Transaction) -> Result<(), Error>;
 
+	/// Relays a transaction in its stem phase to the remote peer.
+	fn send_stem_transaction(&self, tx: &core::Transaction) -> Result<(), Error>;
+
 	/// Sends a request for block headers based on the provided block locator.
 	fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error>;
 
+	/// Sends a request for consecutive block headers starting at the
+	/// provided height, to peers with the HEADER_RANGES capability.
+	fn send_header_range_request(&self, start_height: u64, count: u16) -> Result<(), Error>;
+
 	/// Sends a request for a block from its hash.
 	fn send_block_request(&self, h: Hash) -> Result<(), Error>;
 
@@ -169,6 +367,16 @@ pub trait Protocol {
 	/// How many bytes have been sent/received to/from the remote peer.
 	fn transmitted_bytes(&self) -> (u64, u64);
 
+	/// How many bytes are waiting to be sent to the remote peer.
+	fn queued_bytes(&self) -> u64;
+
+	/// How many messages from the remote peer we had to skip, being corrupted
+	/// or of a type we don't know about.
+	fn skipped_msgs(&self) -> u64;
+
+	/// Reports an error to the remote peer.
+	fn send_error(&self, code: u32, message: String) -> Result<(), Error>;
+
 	/// Close the connection to the remote peer.
 	fn close(&self);
 }
@@ -186,6 +394,10 @@ pub trait ChainAdapter: Sync + Send {
 	/// A valid transaction has been received from one of our peers
 	fn transaction_received(&self, tx: core::Transaction);
 
+	/// A transaction in its stem phase has been received from one of our
+	/// peers, to relay further along the stem
+	fn stem_transaction_received(&self, tx: core::Transaction);
+
 	/// A block has been received from one of our peers. Returns true if the
 	/// block could be handled properly and is not deemed defective by the
 	/// chain. Returning false means the block will never be valid and
@@ -206,6 +418,10 @@ pub trait ChainAdapter: Sync + Send {
 	/// immediately.
 	fn locate_headers(&self, locator: Vec<Hash>) -> Vec<core::BlockHeader>;
 
+	/// Gets at most count consecutive block headers of our current chain,
+	/// starting at the provided height.
+	fn headers_from_height(&self, height: u64, count: usize) -> Vec<core::BlockHeader>;
+
 	/// Gets a full block by its hash.
 	fn get_block(&self, h: Hash) -> Option<core::Block>;
 }
@@ -222,4 +438,7 @@ pub trait NetAdapter: ChainAdapter {
 
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, SocketAddr, Difficulty, u64);
+
+	/// A connected peer failed to deliver what we asked for.
+	fn peer_misbehaved(&self, SocketAddr, Misbehavior);
 }
@@ -20,7 +20,7 @@ extern crate tokio_core;
 
 use std::net::SocketAddr;
 use std::net::TcpListener;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time;
 
 use futures::future::Future;
@@ -51,6 +51,7 @@ fn peer_handshake() {
 		port: open_port(),
 		peers_allow: None,
 		peers_deny: None,
+		..p2p::P2PConfig::default()
 	};
 	let net_adapter = Arc::new(p2p::DummyAdapter {});
 	let pool = CpuPool::new(1);
@@ -63,6 +64,9 @@ fn peer_handshake() {
 		pool.clone(),
 	).unwrap();
 	let run_server = server.start(handle.clone());
+	let traffic = server.peers.traffic_stats();
+	let traffic_check = traffic.clone();
+	let conditions = server.peers.network_conditions();
 	let my_addr = "127.0.0.1:5000".parse().unwrap();
 
 	let phandle = handle.clone();
@@ -87,27 +91,38 @@ fn peer_handshake() {
 							Arc::new(
 								p2p::handshake::Handshake::new(
 									Hash::from_vec(vec![]),
-									p2p_conf.clone(),
+									Arc::new(RwLock::new(p2p_conf.clone())),
 								),
 							),
 							net_adapter.clone(),
 						)
 					})
 					.and_then(move |(socket, peer)| {
-						rhandle.spawn(peer.run(socket, pool).map_err(|e| {
+						rhandle.spawn(peer.run(socket, pool, traffic, conditions).map_err(|e| {
 							panic!("Client run failed: {:?}", e);
 						}));
-						peer.send_ping(Difficulty::one(), 0).unwrap();
+						peer.send_ping(Difficulty::one(), 42).unwrap();
 						timeout_send.from_err().map(|_| peer)
 					})
-					.and_then(|peer| {
+					.and_then(move |peer| {
 						let (sent, recv) = peer.transmitted_bytes();
 						assert!(sent > 0);
 						assert!(recv > 0);
+						// client and server share the same counters here
+						let stats = traffic_check.snapshot();
+						assert!(stats.iter().any(|s| s.msg_type == "Ping" && s.sent_msgs > 0));
+						assert!(stats.iter().any(|s| s.msg_type == "Pong" && s.received_msgs > 0));
+						assert_eq!(peer.info.direction, p2p::Direction::Outbound);
 						Ok(())
 					})
 					.and_then(|_| {
 						assert!(server.peers.peer_count() > 0);
+						// the server saw the client connect and advertise its height
+						let server_peer = server.peers.connected_peers()[0].clone();
+						let info = server_peer.read().unwrap().info.clone();
+						assert_eq!(info.direction, p2p::Direction::Inbound);
+						assert_eq!(info.height, 42);
+						assert!(info.last_seen > 0);
 						server.stop();
 						Ok(())
 					})
@@ -91,6 +91,7 @@ impl DummyUtxoSet {
 pub struct DummyChainImpl {
 	utxo: RwLock<DummyUtxoSet>,
 	block_headers: RwLock<Vec<block::BlockHeader>>,
+	blocks: RwLock<HashMap<hash::Hash, block::Block>>,
 }
 
 #[allow(dead_code)]
@@ -101,6 +102,7 @@ impl DummyChainImpl {
 				outputs: HashMap::new(),
 			}),
 			block_headers: RwLock::new(vec![]),
+			blocks: RwLock::new(HashMap::new()),
 		}
 	}
 }
@@ -138,6 +140,13 @@ impl BlockChain for DummyChainImpl {
 			Err(PoolError::GenericPoolError)
 		}
 	}
+
+	fn get_block(&self, h: &hash::Hash) -> Result<block::Block, PoolError> {
+		match self.blocks.read().unwrap().get(h) {
+			Some(b) => Ok(b.clone()),
+			None => Err(PoolError::GenericPoolError),
+		}
+	}
 }
 
 impl DummyChain for DummyChainImpl {
@@ -147,9 +156,14 @@ impl DummyChain for DummyChainImpl {
 
 	fn apply_block(&self, b: &block::Block) {
 		self.utxo.write().unwrap().with_block(b);
+		self.store_block(b);
 		self.store_head_header(&b.header)
 	}
 
+	fn store_block(&self, b: &block::Block) {
+		self.blocks.write().unwrap().insert(b.hash(), b.clone());
+	}
+
 	fn store_head_header(&self, block_header: &block::BlockHeader) {
 		let mut headers = self.block_headers.write().unwrap();
 		headers.insert(0, block_header.clone());
@@ -159,5 +173,6 @@ impl DummyChain for DummyChainImpl {
 pub trait DummyChain: BlockChain {
 	fn update_utxo_set(&mut self, new_utxo: DummyUtxoSet);
 	fn apply_block(&self, b: &block::Block);
+	fn store_block(&self, b: &block::Block);
 	fn store_head_header(&self, block_header: &block::BlockHeader);
 }
@@ -268,6 +268,11 @@ impl DirectedGraph {
 		self.roots.iter().map(|x| x.transaction_hash).collect()
 	}
 
+	/// Get all the entries in this graph, roots included
+	pub fn get_entries(&self) -> Vec<&PoolEntry> {
+		self.roots.iter().chain(self.vertices.iter()).collect()
+	}
+
 	/// Get list of all vertices in this graph including the roots
 	pub fn get_vertices(&self) -> Vec<core::hash::Hash> {
 		let mut hashes = self.roots
@@ -338,7 +343,8 @@ mod tests {
 				.unwrap(),
 		};
 		let outputs = vec![output];
-		let test_transaction = core::transaction::Transaction::new(inputs, outputs, 5, 0);
+		let kernel = core::transaction::TxKernel::empty().with_fee(5);
+		let test_transaction = core::transaction::Transaction::new(inputs, outputs, vec![kernel]);
 
 		let test_pool_entry = PoolEntry::new(&test_transaction);
 
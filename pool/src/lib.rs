@@ -25,6 +25,7 @@ pub mod graph;
 mod types;
 mod blockchain;
 mod pool;
+mod stempool;
 
 extern crate blake2_rfc as blake2;
 extern crate grin_core as core;
@@ -39,5 +40,6 @@ extern crate serde_derive;
 extern crate slog;
 extern crate time;
 
-pub use pool::TransactionPool;
-pub use types::{BlockChain, PoolAdapter, PoolConfig, PoolError, TxSource};
+pub use pool::{aggregate_dependent, PoolMemory, TransactionPool};
+pub use types::{BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolError, PoolTxInfo,
+                TxCheck, TxSource};
@@ -14,17 +14,57 @@
 
 //! Top-level Pool type, methods, and tests
 
-use std::sync::Arc;
-use std::collections::{HashMap, HashSet};
+use std::cmp;
+use std::sync::{Arc, RwLock, Weak};
+use std::collections::{HashMap, HashSet, VecDeque};
 
+use core::consensus;
 use core::core::transaction;
 use core::core::OutputIdentifier;
 use core::core::{block, hash};
 use util::secp::pedersen::Commitment;
+use util::LOGGER;
+use util::memory::MemoryConsumer;
+use util::secp::constants::MAX_PROOF_SIZE;
 
+use rand::{self, Rng};
+use time;
+
+use stempool::StemPool;
 use types::*;
 pub use graph;
 
+/// Number of recent blocks for which we remember the transactions they
+/// removed from the pool, so they can be restored if a reorg orphans them.
+const REORG_CACHE_SIZE: usize = 50;
+
+/// Maximum number of rebroadcasts the backoff doubles the interval for, the
+/// interval stays constant afterward.
+const MAX_REBROADCAST_BACKOFF: u32 = 6;
+
+// Rebroadcast schedule of a locally submitted transaction
+struct Rebroadcast {
+	next_height: u64,
+	attempts: u32,
+}
+
+// Where the inputs of a transaction checked against the pool come from,
+// along with the pool transactions it replaces
+struct ResolvedTx {
+	tx_hash: hash::Hash,
+	replaced: HashSet<hash::Hash>,
+	blockchain_refs: Vec<graph::Edge>,
+	pool_refs: Vec<graph::Edge>,
+	orphan_refs: Vec<graph::Edge>,
+}
+
+/// Estimated in-memory size of transaction inputs, outputs (excluding their
+/// range proof) and of the rest of the transaction, used for memory
+/// accounting.
+const INPUT_SIZE: usize = 80;
+const OUTPUT_SIZE: usize = 80;
+const TX_BASE_SIZE: usize = 160;
+
 /// The pool itself.
 /// The transactions HashMap holds ownership of all transactions in the pool,
 /// keyed by their transaction hash.
@@ -36,6 +76,15 @@ pub struct TransactionPool<T> {
 	pub pool: Pool,
 	/// Orphans in the pool
 	pub orphans: Orphans,
+	// orphan transactions themselves, with the time they were received
+	orphan_txs: HashMap<hash::Hash, (Box<transaction::Transaction>, i64)>,
+	/// Transactions in their Dandelion stem phase
+	pub stempool: StemPool,
+	// locally submitted transactions, rebroadcast until confirmed
+	local_txs: HashMap<hash::Hash, Rebroadcast>,
+	// transactions removed by the most recent blocks, along with the block
+	// hash and its previous block hash
+	reorg_cache: VecDeque<(hash::Hash, hash::Hash, Vec<Box<transaction::Transaction>>)>,
 
 	// blockchain is a DummyChain, for now, which mimics what the future
 	// chain will offer to the pool
@@ -54,6 +103,10 @@ where
 			transactions: HashMap::new(),
 			pool: Pool::empty(),
 			orphans: Orphans::empty(),
+			orphan_txs: HashMap::new(),
+			stempool: StemPool::empty(),
+			local_txs: HashMap::new(),
+			reorg_cache: VecDeque::new(),
 			blockchain: chain,
 			adapter: adapter,
 		}
@@ -120,6 +173,44 @@ where
 		self.orphans.num_transactions()
 	}
 
+	/// Minimum fee per unit of transaction weight for a transaction to be
+	/// accepted in the pool, and so relayed to our peers
+	pub fn accept_fee_base(&self) -> u64 {
+		self.config.accept_fee_base
+	}
+
+	/// Get the number of transactions in their stem phase
+	pub fn stempool_size(&self) -> usize {
+		self.stempool.len()
+	}
+
+	/// Describes all the transactions in the pool, stem transactions included
+	/// but not orphans.
+	pub fn pool_transactions(&self) -> Vec<PoolTxInfo> {
+		let mut txs = self.pool
+			.get_graph()
+			.get_entries()
+			.into_iter()
+			.filter_map(|e| {
+				self.transactions.get(&e.transaction_hash).map(|tx| PoolTxInfo {
+					tx: tx.clone(),
+					weight: tx_block_weight(tx),
+					received: e.receive_ts.to_timespec().sec,
+					stem: false,
+				})
+			})
+			.collect::<Vec<_>>();
+		for (tx, received) in self.stempool.transactions() {
+			txs.push(PoolTxInfo {
+				tx: Box::new(tx.clone()),
+				weight: tx_block_weight(tx),
+				received: received,
+				stem: true,
+			});
+		}
+		txs
+	}
+
 	/// Get the total size (transactions + orphans) of the pool
 	pub fn total_size(&self) -> usize {
 		self.pool.num_transactions() + self.orphans.num_transactions()
@@ -136,8 +227,145 @@ where
 		_: TxSource,
 		tx: transaction::Transaction,
 	) -> Result<(), PoolError> {
+		let ResolvedTx {
+			tx_hash,
+			replaced,
+			blockchain_refs,
+			pool_refs,
+			orphan_refs,
+		} = self.resolve_transaction(&tx)?;
+		let is_orphan = orphan_refs.len() > 0;
+
+		// Assertion: we have exactly as many resolved spending references as
+		// inputs to the transaction.
+		assert_eq!(
+			tx.inputs.len(),
+			blockchain_refs.len() + pool_refs.len() + orphan_refs.len()
+		);
+
+		// At this point we know if we're spending all known unspents and not
+		// creating any duplicate unspents.
+		let pool_entry = graph::PoolEntry::new(&tx);
+		// an orphan only replaces anything once reconciled into the pool
+		if !is_orphan && !replaced.is_empty() {
+			debug!(
+				LOGGER,
+				"pool: tx {} replaces {} pool txs",
+				tx_hash,
+				replaced.len()
+			);
+			self.sweep_transactions(replaced);
+		}
+		let new_unspents = tx.outputs
+			.iter()
+			.map(|x| {
+				let output = OutputIdentifier::from_output(&x);
+				graph::Edge::new(Some(tx_hash), None, output)
+			})
+			.collect();
+
+		if !is_orphan {
+			// In the non-orphan (pool) case, we've ensured that every input
+			// maps one-to-one with an unspent (available) output, and each
+			// output is unique. No further checks are necessary.
+			self.pool
+				.add_pool_transaction(pool_entry, blockchain_refs, pool_refs, new_unspents);
+
+			self.transactions.insert(tx_hash, Box::new(tx));
+			// fluffed, either by us or someone else along the stem
+			self.stempool.remove(&tx_hash);
+			// only relayed if it survived the eviction it may have triggered,
+			// and before the orphans it may unlock
+			self.enforce_limits();
+			if let Some(tx) = self.transactions.get(&tx_hash) {
+				self.adapter.tx_accepted(tx);
+			}
+			self.reconcile_orphans().unwrap();
+			Ok(())
+		} else {
+			// At this point, we're pretty sure the transaction is an orphan,
+   // but we have to explicitly check for double spends against the
+   // orphans set; we do not check this as part of the connectivity
+   // checking above.
+   // First, any references resolved to the pool need to be compared
+   // against active orphan pool_connections.
+   // Note that pool_connections here also does double duty to
+   // account for blockchain connections.
+			for pool_ref in pool_refs.iter().chain(blockchain_refs.iter()) {
+				match self.orphans
+					.get_external_spent_output(&pool_ref.output_commitment())
+				{
+					// Should the below err be subtyped to orphans somehow?
+					Some(x) => {
+						return Err(PoolError::DoubleSpend {
+							other_tx: x.destination_hash().unwrap(),
+							spent_output: x.output_commitment(),
+						})
+					}
+					None => {}
+				}
+			}
+
+			// Next, we have to consider the possibility of double spends
+   // within the orphans set.
+   // We also have to distinguish now between missing and internal
+   // references.
+			let missing_refs = self.resolve_orphan_refs(tx_hash, &mut orphan_refs)?;
+
+			// We have passed all failure modes.
+			pool_refs.append(&mut blockchain_refs);
+			self.orphans.add_orphan_transaction(
+				pool_entry,
+				pool_refs,
+				orphan_refs,
+				missing_refs,
+				new_unspents,
+			);
+			let now = time::now_utc().to_timespec().sec;
+			self.orphan_txs.insert(tx_hash, (Box::new(tx), now));
+			self.limit_orphans();
+
+			Err(PoolError::OrphanTransaction)
+		}
+	}
+
+	/// Runs the checks a transaction goes through when added to the pool
+	/// (fee policy, signatures and range proofs, lock height, availability
+	/// and maturity of its inputs, duplicate outputs, double spends) without
+	/// adding it, for callers to know whether it would be accepted before
+	/// broadcasting it. Nothing is added, replaced or relayed.
+	pub fn check_transaction(
+		&self,
+		tx: &transaction::Transaction,
+	) -> Result<TxCheck, PoolError> {
+		let resolved = self.resolve_transaction(tx)?;
+		Ok(TxCheck {
+			weight: tx_block_weight(tx),
+			replaces: resolved.replaced.into_iter().collect(),
+			pool_parents: resolved
+				.pool_refs
+				.iter()
+				.filter_map(|e| e.source_hash())
+				.collect(),
+			missing_inputs: resolved
+				.orphan_refs
+				.iter()
+				.map(|e| e.output_commitment())
+				.collect(),
+		})
+	}
+
+	// Checks shared by the transactions added to the pool and the ones only
+	// checked (see check_transaction): pool capacity and fee policy, validity,
+	// lock height, inputs availability and maturity, double spends and
+	// duplicate outputs. Resolves where each input comes from, along with the
+	// pool transactions the transaction replaces.
+	fn resolve_transaction(
+		&self,
+		tx: &transaction::Transaction,
+	) -> Result<ResolvedTx, PoolError> {
 		// Do we have the capacity to accept this transaction?
-		if let Err(e) = self.is_acceptable(&tx) {
+		if let Err(e) = self.is_acceptable(tx) {
 			return Err(e);
 		}
 
@@ -153,18 +381,25 @@ where
   // here may change depending on the exact coverage of the identifier.
   // The current tx.hash() method, for example, does not cover changes
   // to fees or other elements of the signature preimage.
-		let tx_hash = graph::transaction_identifier(&tx);
+		let tx_hash = graph::transaction_identifier(tx);
 		if self.transactions.contains_key(&tx_hash) {
 			return Err(PoolError::AlreadyInPool);
 		}
 
 		let head_header = self.blockchain.head_header()?;
-		if head_header.height < tx.lock_height {
+		if head_header.height < tx.lock_height() {
 			return Err(PoolError::ImmatureTransaction {
-				lock_height: tx.lock_height,
+				lock_height: tx.lock_height(),
 			});
 		}
 
+		// Replace-by-fee, a transaction spending the same outputs as some
+		// transactions already in the pool evicts them (and the transactions
+		// depending on them) if it pays for it. They're only removed once the
+		// transaction passed all the checks below, the outputs they spend
+		// being considered available meanwhile.
+		let replaced = self.find_replaced_transactions(tx)?;
+
 		// The next issue is to identify all unspent outputs that
 		// this transaction will consume and make sure they exist in the set.
 		let mut pool_refs: Vec<graph::Edge> = Vec::new();
@@ -187,6 +422,18 @@ where
 					blockchain_refs.push(base);
 				}
 				Parent::Unknown => orphan_refs.push(base),
+				Parent::AlreadySpent { other_tx: x } if replaced.contains(&x) => {
+					match self.pool
+						.get_internal_spent_output(&input.commitment())
+						.and_then(|e| e.source_hash())
+					{
+						Some(parent) => pool_refs.push(base.with_source(Some(parent))),
+						None => {
+							self.blockchain.is_matured(&input, head_header.height + 1)?;
+							blockchain_refs.push(base);
+						}
+					}
+				}
 				Parent::AlreadySpent { other_tx: x } => {
 					return Err(PoolError::DoubleSpend {
 						other_tx: x,
@@ -205,79 +452,332 @@ where
   // with strict ordering. In the future, if desirable, this could
   // be node policy config or more intelligent.
 		for output in &tx.outputs {
-			self.check_duplicate_outputs(output, is_orphan)?
+			self.check_duplicate_outputs(output, is_orphan, &replaced)?
 		}
 
-		// Assertion: we have exactly as many resolved spending references as
-		// inputs to the transaction.
-		assert_eq!(
-			tx.inputs.len(),
-			blockchain_refs.len() + pool_refs.len() + orphan_refs.len()
-		);
+		Ok(ResolvedTx {
+			tx_hash: tx_hash,
+			replaced: replaced,
+			blockchain_refs: blockchain_refs,
+			pool_refs: pool_refs,
+			orphan_refs: orphan_refs,
+		})
+	}
 
-		// At this point we know if we're spending all known unspents and not
-		// creating any duplicate unspents.
-		let pool_entry = graph::PoolEntry::new(&tx);
-		let new_unspents = tx.outputs
-			.iter()
-			.map(|x| {
-				let output = OutputIdentifier::from_output(&x);
-				graph::Edge::new(Some(tx_hash), None, output)
-			})
-			.collect();
+	/// Adds a transaction in its Dandelion stem phase. The transaction goes
+	/// through the same validation as for the main pool but is kept in the
+	/// stem pool, where it's neither broadcast nor mined, until it gets
+	/// fluffed. Stem transactions can only spend outputs from the chain or the
+	/// main pool, not from orphans or other stem transactions.
+	pub fn add_to_stempool(
+		&mut self,
+		_: TxSource,
+		tx: transaction::Transaction,
+	) -> Result<(), PoolError> {
+		self.add_stem_transaction(tx, true)
+	}
 
-		if !is_orphan {
-			// In the non-orphan (pool) case, we've ensured that every input
-			// maps one-to-one with an unspent (available) output, and each
-			// output is unique. No further checks are necessary.
-			self.pool
-				.add_pool_transaction(pool_entry, blockchain_refs, pool_refs, new_unspents);
+	/// Adds a transaction in its stem phase relayed by a peer, to be relayed
+	/// further along the stem or fluffed like our own stem transactions, but
+	/// without us rebroadcasting it.
+	pub fn add_relayed_to_stempool(
+		&mut self,
+		_: TxSource,
+		tx: transaction::Transaction,
+	) -> Result<(), PoolError> {
+		self.add_stem_transaction(tx, false)
+	}
 
-			self.reconcile_orphans().unwrap();
-			self.adapter.tx_accepted(&tx);
-			self.transactions.insert(tx_hash, Box::new(tx));
-			Ok(())
-		} else {
-			// At this point, we're pretty sure the transaction is an orphan,
-   // but we have to explicitly check for double spends against the
-   // orphans set; we do not check this as part of the connectivity
-   // checking above.
-   // First, any references resolved to the pool need to be compared
-   // against active orphan pool_connections.
-   // Note that pool_connections here also does double duty to
-   // account for blockchain connections.
-			for pool_ref in pool_refs.iter().chain(blockchain_refs.iter()) {
-				match self.orphans
-					.get_external_spent_output(&pool_ref.output_commitment())
-				{
-					// Should the below err be subtyped to orphans somehow?
-					Some(x) => {
-						return Err(PoolError::DoubleSpend {
-							other_tx: x.destination_hash().unwrap(),
-							spent_output: x.output_commitment(),
-						})
-					}
-					None => {}
+	fn add_stem_transaction(
+		&mut self,
+		tx: transaction::Transaction,
+		local: bool,
+	) -> Result<(), PoolError> {
+		self.is_acceptable(&tx)?;
+		tx.validate().map_err(|_e| PoolError::Invalid)?;
+
+		let tx_hash = graph::transaction_identifier(&tx);
+		if self.transactions.contains_key(&tx_hash) || self.stempool.contains(&tx_hash) {
+			return Err(PoolError::AlreadyInPool);
+		}
+
+		let head_header = self.blockchain.head_header()?;
+		if head_header.height < tx.lock_height() {
+			return Err(PoolError::ImmatureTransaction {
+				lock_height: tx.lock_height(),
+			});
+		}
+
+		for input in &tx.inputs {
+			let output = OutputIdentifier::from_input(&input);
+			match self.search_for_best_output(&output) {
+				Parent::PoolTransaction { .. } => {}
+				Parent::BlockTransaction => {
+					self.blockchain.is_matured(&input, head_header.height + 1)?
+				}
+				Parent::Unknown => return Err(PoolError::OutputNotFound),
+				Parent::AlreadySpent { other_tx: x } => {
+					return Err(PoolError::DoubleSpend {
+						other_tx: x,
+						spent_output: input.commitment(),
+					})
 				}
 			}
+			if let Some(x) = self.stempool.spent_by(&input.commitment()) {
+				return Err(PoolError::DoubleSpend {
+					other_tx: x,
+					spent_output: input.commitment(),
+				});
+			}
+		}
+		for output in &tx.outputs {
+			self.check_duplicate_outputs(output, false, &HashSet::new())?
+		}
 
-			// Next, we have to consider the possibility of double spends
-   // within the orphans set.
-   // We also have to distinguish now between missing and internal
-   // references.
-			let missing_refs = self.resolve_orphan_refs(tx_hash, &mut orphan_refs)?;
+		let now = time::now_utc().to_timespec().sec;
+		self.stempool.add(tx, now, &self.config.dandelion_config);
+		if local {
+			self.track_local(tx_hash, head_header.height);
+		}
+		Ok(())
+	}
 
-			// We have passed all failure modes.
-			pool_refs.append(&mut blockchain_refs);
-			self.orphans.add_orphan_transaction(
-				pool_entry,
-				pool_refs,
-				orphan_refs,
-				missing_refs,
-				new_unspents,
+	/// Stops rebroadcasting a locally submitted transaction. Returns whether
+	/// the transaction was being rebroadcast.
+	pub fn cancel_rebroadcast(&mut self, tx_hash: &hash::Hash) -> bool {
+		self.local_txs.remove(tx_hash).is_some()
+	}
+
+	// Starts tracking a locally submitted transaction, so it gets rebroadcast
+	// if it doesn't get mined, for example when the initial broadcast got lost
+	// in a network partition.
+	fn track_local(&mut self, tx_hash: hash::Hash, height: u64) {
+		self.local_txs.insert(
+			tx_hash,
+			Rebroadcast {
+				next_height: height + self.config.rebroadcast_blocks,
+				attempts: 0,
+			},
+		);
+	}
+
+	// Rebroadcasts the locally submitted transactions still unconfirmed after
+	// the configured number of blocks, doubling the interval after each
+	// attempt. The ones no longer in the pool got confirmed (or evicted) and
+	// should already have been cancelled, they aren't tracked anymore either
+	// way.
+	fn rebroadcast_local(&mut self, height: u64) {
+		if self.config.rebroadcast_blocks == 0 {
+			return;
+		}
+		let mut done = vec![];
+		for (tx_hash, r) in self.local_txs.iter_mut() {
+			if self.stempool.contains(tx_hash) {
+				continue;
+			}
+			let tx = match self.transactions.get(tx_hash) {
+				Some(tx) => tx,
+				None => {
+					done.push(*tx_hash);
+					continue;
+				}
+			};
+			if height >= r.next_height {
+				debug!(
+					LOGGER,
+					"pool: rebroadcasting unconfirmed local tx {} (attempt {})",
+					tx_hash,
+					r.attempts + 1
+				);
+				self.adapter.tx_accepted(tx);
+				let backoff = 1 << r.attempts.min(MAX_REBROADCAST_BACKOFF);
+				r.attempts += 1;
+				r.next_height = height + self.config.rebroadcast_blocks * backoff;
+			}
+		}
+		for tx_hash in done {
+			self.local_txs.remove(&tx_hash);
+		}
+	}
+
+	/// Moves stem transactions along. The ones held for the aggregation
+	/// period are aggregated in a single transaction, relayed to the next
+	/// stem peer or, with the remaining probability, fluffed, ending their
+	/// stem phase. The ones which embargo expired without being fluffed by
+	/// another node get fluffed. Expected to be called periodically.
+	pub fn process_stempool(&mut self) {
+		let now = time::now_utc().to_timespec().sec;
+		let config = self.config.dandelion_config.clone();
+
+		for tx in self.stempool.expired(now) {
+			debug!(
+				LOGGER,
+				"pool: stem tx {} embargo expired, fluffing",
+				graph::transaction_identifier(&tx)
 			);
+			self.fluff_transaction(*tx);
+		}
 
-			Err(PoolError::OrphanTransaction)
+		let batch = self.stempool.ready_to_relay(now, &config);
+		if batch.is_empty() {
+			return;
+		}
+		let batch_hashes = batch
+			.iter()
+			.map(|tx| graph::transaction_identifier(tx))
+			.collect::<Vec<_>>();
+		let tx = match transaction::aggregate(batch.into_iter().map(|tx| *tx).collect()) {
+			Ok(tx) => tx,
+			Err(e) => {
+				// left to be fluffed when their embargo expires
+				warn!(LOGGER, "pool: could not aggregate stem txs: {:?}", e);
+				return;
+			}
+		};
+		let tx_hash = graph::transaction_identifier(&tx);
+
+		let stem = rand::thread_rng().gen_range(0, 100) < config.stem_probability;
+		debug!(
+			LOGGER,
+			"pool: {} {} stem txs aggregated as {}",
+			if stem { "relaying" } else { "fluffing" },
+			batch_hashes.len(),
+			tx_hash
+		);
+		if stem {
+			match self.adapter.stem_tx_accepted(&tx) {
+				Ok(()) => return,
+				Err(e) => debug!(
+					LOGGER,
+					"pool: failed to relay stem tx {}, fluffing: {:?}",
+					tx_hash,
+					e
+				),
+			}
+		}
+		for h in &batch_hashes {
+			self.stempool.remove(h);
+		}
+		self.replace_local(&batch_hashes, tx_hash);
+		self.fluff_transaction(tx);
+	}
+
+	// Rebroadcasts an aggregated transaction in place of the local
+	// transactions it includes, if any.
+	fn replace_local(&mut self, tx_hashes: &[hash::Hash], agg_hash: hash::Hash) {
+		let tracked = tx_hashes
+			.iter()
+			.filter_map(|h| self.local_txs.remove(h))
+			.min_by_key(|r| r.next_height);
+		if let Some(r) = tracked {
+			self.local_txs.insert(agg_hash, r);
+		}
+	}
+
+	// Ends the stem phase of a transaction, adding it to the main pool from
+	// where it gets broadcast.
+	fn fluff_transaction(&mut self, tx: transaction::Transaction) {
+		let tx_hash = graph::transaction_identifier(&tx);
+		let source = TxSource {
+			debug_name: "stempool".to_string(),
+			identifier: "?.?.?.?".to_string(),
+		};
+		if let Err(e) = self.add_to_memory_pool(source, tx) {
+			debug!(LOGGER, "pool: fluffed stem tx {} rejected: {:?}", tx_hash, e);
+		}
+	}
+
+	/// Finds the pool transactions the provided transaction would replace,
+	/// along with their descendants. Only transactions all of which inputs
+	/// are spent again by the new transaction can be replaced (i.e. the same
+	/// transaction rebuilt with a higher fee), any other conflict is a plain
+	/// double spend. The replacement is only accepted if the new transaction
+	/// fee covers the fees of all the transactions replaced, plus the minimum
+	/// relay fee for its own weight.
+	fn find_replaced_transactions(
+		&self,
+		tx: &transaction::Transaction,
+	) -> Result<HashSet<hash::Hash>, PoolError> {
+		let spent_by_tx = tx.inputs
+			.iter()
+			.map(|x| x.commitment())
+			.collect::<HashSet<_>>();
+
+		let mut replaced = HashSet::new();
+		for input in &tx.inputs {
+			let spent = self.pool
+				.get_external_spent_output(&input.commitment())
+				.or(self.pool.get_internal_spent_output(&input.commitment()));
+			let other_tx = match spent.and_then(|x| x.destination_hash()) {
+				Some(h) => h,
+				None => continue,
+			};
+			let fully_respent = match self.transactions.get(&other_tx) {
+				Some(other) => other
+					.inputs
+					.iter()
+					.all(|x| spent_by_tx.contains(&x.commitment())),
+				None => false,
+			};
+			if !fully_respent {
+				return Ok(HashSet::new());
+			}
+			self.mark_descendants(other_tx, &mut replaced);
+		}
+		if replaced.is_empty() {
+			return Ok(replaced);
+		}
+
+		let replaced_fees: u64 = replaced
+			.iter()
+			.filter_map(|h| self.transactions.get(h))
+			.map(|tx| tx.fee())
+			.sum();
+		let required = replaced_fees + tx_weight(tx) * self.config.accept_fee_base;
+		if tx.fee() <= replaced_fees || tx.fee() < required {
+			return Err(PoolError::LowFeeReplacement(required));
+		}
+		Ok(replaced)
+	}
+
+	// Collects the transaction and its in-pool ancestors that aren't part of
+	// the excluded set yet, ancestors first.
+	fn collect_ancestors(
+		&self,
+		tx_hash: hash::Hash,
+		excluded: &HashSet<hash::Hash>,
+		acc: &mut Vec<hash::Hash>,
+	) {
+		if excluded.contains(&tx_hash) || acc.contains(&tx_hash) {
+			return;
+		}
+		if let Some(tx) = self.transactions.get(&tx_hash) {
+			for input in &tx.inputs {
+				if let Some(parent) = self.pool
+					.get_internal_spent_output(&input.commitment())
+					.and_then(|e| e.source_hash())
+				{
+					self.collect_ancestors(parent, excluded, acc);
+				}
+			}
+		}
+		acc.push(tx_hash);
+	}
+
+	// Marks the provided pool transaction and all the pool transactions
+	// spending its outputs, recursively.
+	fn mark_descendants(&self, tx_hash: hash::Hash, marked: &mut HashSet<hash::Hash>) {
+		if !marked.insert(tx_hash) {
+			return;
+		}
+		if let Some(tx) = self.transactions.get(&tx_hash) {
+			for output in &tx.outputs {
+				if let Some(x) = self.pool.get_internal_spent_output(&output.commitment()) {
+					if let Some(child) = x.destination_hash() {
+						self.mark_descendants(child, marked);
+					}
+				}
+			}
 		}
 	}
 
@@ -290,6 +790,7 @@ where
 		&self,
 		output: &transaction::Output,
 		is_orphan: bool,
+		replaced: &HashSet<hash::Hash>,
 	) -> Result<(), PoolError> {
 		// Checking against current blockchain unspent outputs
 		// We want outputs even if they're spent by pool txs, so we ignore
@@ -304,16 +805,17 @@ where
 		}
 
 
-		// Check for existence of this output in the pool
+		// Check for existence of this output in the pool, outside of the
+		// transactions being replaced
 		match self.pool.find_output(&output.commitment()) {
-			Some(x) => {
+			Some(x) if !replaced.contains(&x) => {
 				return Err(PoolError::DuplicateOutput {
 					other_tx: Some(x),
 					in_chain: false,
 					output: output.commit,
 				})
 			}
-			None => {}
+			_ => {}
 		};
 
 
@@ -390,16 +892,80 @@ where
 		Ok(missing_refs)
 	}
 
-	/// The primary goal of the reconcile_orphans method is to eliminate any
-	/// orphans who conflict with the recently accepted pool transaction.
-	/// TODO: How do we handle fishing orphans out that look like they could
-	/// be freed? Current thought is to do so under a different lock domain
-	/// so that we don't have the potential for long recursion under the write
-	/// lock.
-	pub fn reconcile_orphans(&self) -> Result<(), PoolError> {
+	/// Re-evaluates the orphans after the pool or the chain changed. Orphans
+	/// held for longer than the configured expiry are dropped, the ones which
+	/// inputs can now all be found in the pool or the chain (their missing
+	/// parent arrived, relayed or in a block) are moved to the pool. The ones
+	/// conflicting with the pool or the chain get rejected at that point.
+	/// Chains of orphans get moved one level at a time, each transaction
+	/// accepted in the pool reconciling the orphans again.
+	pub fn reconcile_orphans(&mut self) -> Result<(), PoolError> {
+		let now = time::now_utc().to_timespec().sec;
+		let expiry = self.config.orphan_expiry_secs as i64;
+		let expired = self.orphan_txs
+			.iter()
+			.filter(|&(_, &(_, received))| received + expiry < now)
+			.map(|(h, _)| *h)
+			.collect::<Vec<_>>();
+		for tx_hash in expired {
+			debug!(LOGGER, "pool: orphan tx {} expired", tx_hash);
+			self.remove_orphan(&tx_hash);
+		}
+
+		let ready = self.orphan_txs
+			.iter()
+			.filter(|&(_, &(ref tx, _))| {
+				tx.inputs.iter().all(|i| {
+					match self.search_for_best_output(&OutputIdentifier::from_input(i)) {
+						Parent::Unknown => false,
+						_ => true,
+					}
+				})
+			})
+			.map(|(h, _)| *h)
+			.collect::<Vec<_>>();
+		for tx_hash in ready {
+			// may have been moved already while reconciling a parent
+			if let Some(tx) = self.remove_orphan(&tx_hash) {
+				let source = TxSource {
+					debug_name: "orphans".to_string(),
+					identifier: "?.?.?.?".to_string(),
+				};
+				match self.add_to_memory_pool(source, *tx) {
+					Ok(()) => debug!(LOGGER, "pool: orphan tx {} now in pool", tx_hash),
+					Err(e) => debug!(LOGGER, "pool: orphan tx {} rejected: {:?}", tx_hash, e),
+				}
+			}
+		}
 		Ok(())
 	}
 
+	// Removes an orphan, returning the transaction.
+	fn remove_orphan(&mut self, tx_hash: &hash::Hash) -> Option<Box<transaction::Transaction>> {
+		let tx = self.orphan_txs.remove(tx_hash).map(|(tx, _)| tx);
+		if let Some(ref tx) = tx {
+			self.orphans.remove_orphan_transaction(tx);
+		}
+		tx
+	}
+
+	// Keeps the orphans within the configured maximum, the oldest ones go
+	// first.
+	fn limit_orphans(&mut self) {
+		while self.orphan_txs.len() > self.config.max_orphans {
+			let oldest = self.orphan_txs
+				.iter()
+				.min_by_key(|&(_, &(_, received))| received)
+				.map(|(h, _)| *h);
+			match oldest {
+				Some(tx_hash) => {
+					self.remove_orphan(&tx_hash);
+				}
+				None => break,
+			}
+		}
+	}
+
 	/// Updates the pool with the details of a new block.
 	///
 	/// Along with add_to_memory_pool, reconcile_block is the other major entry
@@ -481,10 +1047,65 @@ where
 		let freed_txs = self.sweep_transactions(marked_transactions);
 
 		self.reconcile_orphans().unwrap();
+		self.stempool.reconcile_block(block);
+		self.rebroadcast_local(block.header.height);
+
+		self.reorg_cache
+			.push_back((block.hash(), block.header.previous, freed_txs.clone()));
+		if self.reorg_cache.len() > REORG_CACHE_SIZE {
+			self.reorg_cache.pop_front();
+		}
 
 		Ok(freed_txs)
 	}
 
+	/// Reconciles the pool after a reorg. The transactions removed by the
+	/// blocks that just got orphaned, walking back depth blocks from the old
+	/// tip, are added back to the pool. The ones that made it in the new chain
+	/// or conflict with it are simply rejected by the usual validation.
+	/// Blocks we don't remember the removed transactions of (too old, or
+	/// seen before a restart) are loaded from the chain and their content
+	/// added back as a single aggregated transaction.
+	pub fn reconcile_reorg(&mut self, old_tip: hash::Hash, depth: u64) -> usize {
+		let mut blocks_txs = vec![];
+		let mut current = old_tip;
+		for _ in 0..depth {
+			let entry = self.reorg_cache
+				.iter()
+				.position(|&(h, _, _)| h == current)
+				.and_then(|idx| self.reorg_cache.remove(idx));
+			match entry {
+				Some((_, prev, block_txs)) => {
+					blocks_txs.push(block_txs);
+					current = prev;
+				}
+				None => match self.blockchain.get_block(&current) {
+					Ok(b) => {
+						blocks_txs.push(block_transaction(&b).into_iter().map(Box::new).collect());
+						current = b.header.previous;
+					}
+					Err(_) => {
+						debug!(LOGGER, "pool: reorged block {} not found", current);
+						break;
+					}
+				},
+			}
+		}
+
+		// oldest block first, so parents get added before their children
+		let mut restored = 0;
+		for tx in blocks_txs.into_iter().rev().flat_map(|txs| txs) {
+			let source = TxSource {
+				debug_name: "reorg".to_string(),
+				identifier: "?.?.?.?".to_string(),
+			};
+			if self.add_to_memory_pool(source, *tx).is_ok() {
+				restored += 1;
+			}
+		}
+		restored
+	}
+
 	/// The mark portion of our mark-and-sweep pool cleanup.
 	///
 	/// The transaction designated by conflicting_tx is immediately marked.
@@ -533,6 +1154,8 @@ where
 
 		for tx_hash in &marked_transactions {
 			let removed_tx = self.transactions.remove(&tx_hash).unwrap();
+			// mined, evicted or replaced, nothing left to rebroadcast
+			self.cancel_rebroadcast(tx_hash);
 
 			self.pool
 				.remove_pool_transaction(&removed_tx, &marked_transactions);
@@ -549,47 +1172,421 @@ where
 
 	/// Fetch mineable transactions.
 	///
-	/// Select a set of mineable transactions for block building.
+	/// Select a set of mineable transactions for block building, with a total
+	/// block weight (see `consensus::exceeds_weight`) of at most max_weight.
+	/// Transactions are picked by decreasing fee per weight, each one along
+	/// with the pool transactions it depends on (its ancestors). The fee rate
+	/// of the whole package is used so a high fee child can get a low fee
+	/// parent mined ("child pays for parent"). Ancestors always come before
+	/// their descendants in the returned list.
 	///
 	/// TODO - txs have lock_heights, so possible to have "invalid" (immature)
 	/// txs here?
 	///
 	pub fn prepare_mineable_transactions(
 		&self,
-		num_to_fetch: u32,
+		max_weight: usize,
 	) -> Vec<Box<transaction::Transaction>> {
-		self.pool
-			.get_mineable_transactions(num_to_fetch)
+		let mut candidates = self.pool
+			.get_graph()
+			.get_vertices()
+			.into_iter()
+			.filter_map(|h| {
+				let mut ancestors = vec![];
+				self.collect_ancestors(h, &HashSet::new(), &mut ancestors);
+				let (fee, weight) = ancestors
+					.iter()
+					.filter_map(|a| self.transactions.get(a))
+					.fold((0, 0), |(f, w), tx| (f + tx.fee(), w + tx_weight(tx)));
+				if weight == 0 {
+					None
+				} else {
+					Some((h, fee, weight))
+				}
+			})
+			.collect::<Vec<_>>();
+		// highest package fee per weight first, comparing fee1/weight1 with
+		// fee2/weight2 without losing precision
+		candidates.sort_by(|&(_, f1, w1), &(_, f2, w2)| {
+			f2.saturating_mul(w1).cmp(&f1.saturating_mul(w2))
+		});
+
+		let mut selected = HashSet::new();
+		let mut selected_hashes = vec![];
+		let mut total_weight = 0;
+		let mut total_inputs = 0;
+		for (tx_hash, _, _) in candidates {
+			if selected.contains(&tx_hash) {
+				continue;
+			}
+			let mut package = vec![];
+			self.collect_ancestors(tx_hash, &selected, &mut package);
+			let (weight, inputs) = package
+				.iter()
+				.filter_map(|a| self.transactions.get(a))
+				.fold((0, 0), |(w, i), tx| {
+					(w + tx_block_weight(tx), i + tx.inputs.len())
+				});
+			if total_weight + weight > max_weight
+				|| total_inputs + inputs > consensus::MAX_BLOCK_INPUTS
+			{
+				continue;
+			}
+			total_weight += weight;
+			total_inputs += inputs;
+			for h in package {
+				selected.insert(h);
+				selected_hashes.push(h);
+			}
+		}
+		let txs = selected_hashes
 			.iter()
-			.map(|x| self.transactions.get(x).unwrap().clone())
+			.filter_map(|h| self.transactions.get(h).cloned())
+			.collect::<Vec<_>>();
+
+		// transactions with output features unknown at the current block
+		// version are relayed but can't be mined yet, neither can the ones
+		// spending their outputs
+		let version = match self.blockchain.head_header() {
+			Ok(head) => head.version,
+			Err(_) => return txs,
+		};
+		let mut excluded = HashSet::new();
+		let mut excluded_outputs = HashSet::new();
+		loop {
+			let excluded_len = excluded.len();
+			for tx in &txs {
+				if tx.outputs.iter().any(|o| !o.features.is_known(version))
+					|| tx.inputs.iter().any(|i| excluded_outputs.contains(&i.commitment()))
+				{
+					if excluded.insert(graph::transaction_identifier(tx)) {
+						excluded_outputs.extend(tx.outputs.iter().map(|o| o.commitment()));
+					}
+				}
+			}
+			if excluded.len() == excluded_len {
+				break;
+			}
+		}
+		txs.into_iter()
+			.filter(|tx| !excluded.contains(&graph::transaction_identifier(tx)))
 			.collect()
 	}
 
-	/// Whether the transaction is acceptable to the pool, given both how
-	/// full the pool is and the transaction weight.
-	fn is_acceptable(&self, tx: &transaction::Transaction) -> Result<(), PoolError> {
-		if self.total_size() > self.config.max_pool_size {
-			// TODO evict old/large transactions instead
-			return Err(PoolError::OverCapacity);
+	/// Estimates the fee per unit of transaction weight a new transaction
+	/// should pay to get mined within the provided number of blocks, given
+	/// the transactions already waiting in the pool. Never less than the
+	/// minimum fee the pool accepts.
+	pub fn estimate_fee_base(&self, blocks: u64) -> u64 {
+		self.fee_base_within(blocks as usize * consensus::MAX_BLOCK_WEIGHT)
+	}
+
+	// Fee per weight to pay to be part of the transactions mined first, up to
+	// max_weight. The pool transactions that wouldn't make it are the ones a
+	// new transaction has to outbid, the best of them setting the fee.
+	fn fee_base_within(&self, max_weight: usize) -> u64 {
+		let min_fee_base = self.config.accept_fee_base;
+		if self.total_weight() <= max_weight {
+			return min_fee_base;
+		}
+		let selected = self.prepare_mineable_transactions(max_weight)
+			.iter()
+			.map(|tx| graph::transaction_identifier(tx))
+			.collect::<HashSet<_>>();
+		let best_left = self.transactions
+			.iter()
+			.filter(|&(h, _)| !selected.contains(h))
+			.map(|(_, tx)| (tx.fee(), tx_weight(tx)))
+			.max_by(|&(f1, w1), &(f2, w2)| {
+				f1.saturating_mul(w2).cmp(&f2.saturating_mul(w1))
+			});
+		match best_left {
+			Some((fee, weight)) => cmp::max(fee / weight + 1, min_fee_base),
+			None => min_fee_base,
+		}
+	}
+
+	/// Estimated memory held by all the transactions in the pool, orphans
+	/// included.
+	pub fn memory_usage(&self) -> usize {
+		self.transactions.values().map(|tx| tx_size(tx)).sum()
+	}
+
+	/// Evicts pool transactions, lowest fee per weight first and along with
+	/// the transactions depending on them, until at least the provided number
+	/// of bytes are freed or the pool is empty. Returns the estimated number
+	/// of bytes freed.
+	pub fn evict(&mut self, bytes: usize) -> usize {
+		if bytes == 0 {
+			return 0;
+		}
+		let mut freed = 0;
+		let marked = self.mark_evictions(|tx| {
+			freed += tx_size(tx);
+			freed >= bytes
+		});
+		if marked.is_empty() {
+			return 0;
+		}
+
+		let evicted = self.sweep_transactions(marked);
+		debug!(
+			LOGGER,
+			"pool: evicted {} transactions to free {} bytes",
+			evicted.len(),
+			freed
+		);
+		freed
+	}
+
+	/// Get the total block weight of all the transactions in the pool
+	pub fn total_weight(&self) -> usize {
+		self.transactions.values().map(|tx| tx_block_weight(tx)).sum()
+	}
+
+	// Evicts the lowest fee per weight transactions, along with their
+	// descendants, until the pool fits within its configured limits again.
+	fn enforce_limits(&mut self) {
+		let max_size = self.config.max_pool_size;
+		let max_weight = self.config.max_pool_weight;
+		let mut size = self.total_size();
+		let mut weight = self.total_weight();
+		if size <= max_size && weight <= max_weight {
+			return;
+		}
+		let marked = self.mark_evictions(|tx| {
+			size -= 1;
+			weight = weight.saturating_sub(tx_block_weight(tx));
+			size <= max_size && weight <= max_weight
+		});
+		let evicted = self.sweep_transactions(marked);
+		debug!(
+			LOGGER,
+			"pool: full, evicted {} lowest fee transactions",
+			evicted.len()
+		);
+	}
+
+	// Marks pool transactions for eviction, lowest fee per weight first and
+	// along with the transactions depending on them. The provided closure is
+	// called with each marked transaction and returns true once enough have
+	// been marked.
+	fn mark_evictions<F>(&self, mut enough: F) -> HashSet<hash::Hash>
+	where
+		F: FnMut(&transaction::Transaction) -> bool,
+	{
+		let mut candidates = self.pool
+			.get_graph()
+			.get_vertices()
+			.into_iter()
+			.filter_map(|h| self.transactions.get(&h).map(|tx| (h, tx.fee(), tx_weight(tx))))
+			.collect::<Vec<_>>();
+		candidates.sort_by(|&(_, f1, w1), &(_, f2, w2)| {
+			f1.saturating_mul(w2).cmp(&f2.saturating_mul(w1))
+		});
+
+		let mut marked = HashSet::new();
+		for (tx_hash, _, _) in candidates {
+			if marked.contains(&tx_hash) {
+				continue;
+			}
+			let mut descendants = HashSet::new();
+			self.mark_descendants(tx_hash, &mut descendants);
+			let mut done = false;
+			for h in descendants {
+				if marked.insert(h) {
+					if let Some(tx) = self.transactions.get(&h) {
+						done = enough(tx) || done;
+					}
+				}
+			}
+			if done {
+				break;
+			}
+		}
+		marked
+	}
+
+	// Lowest fee per weight in the pool, as a fee and weight pair. That's what
+	// an incoming transaction has to beat when the pool is full.
+	fn eviction_floor(&self) -> Option<(u64, u64)> {
+		self.transactions
+			.values()
+			.map(|tx| (tx.fee(), tx_weight(tx)))
+			.min_by(|&(f1, w1), &(f2, w2)| {
+				f1.saturating_mul(w2).cmp(&f2.saturating_mul(w1))
+			})
+	}
+
+	/// Whether the transaction is acceptable to the pool, given both how
+	/// full the pool is and the transaction weight. When full, a transaction
+	/// gets in only if it pays a better fee per weight than the ones that
+	/// will be evicted to make room for it.
+	fn is_acceptable(&self, tx: &transaction::Transaction) -> Result<(), PoolError> {
+		if self.total_size() >= self.config.max_pool_size
+			|| self.total_weight() + tx_block_weight(tx) > self.config.max_pool_weight
+		{
+			let weight = tx_weight(tx);
+			match self.eviction_floor() {
+				Some((floor_fee, floor_weight)) => {
+					if tx.fee().saturating_mul(floor_weight) <= floor_fee.saturating_mul(weight) {
+						let threshold = floor_fee.saturating_mul(weight) / floor_weight + 1;
+						return Err(PoolError::LowFeeTransaction(threshold));
+					}
+				}
+				// nothing to evict, only orphans
+				None => return Err(PoolError::OverCapacity),
+			}
+		}
+
+		if self.config.accept_fee_base > 0 {
+			let threshold = tx_weight(tx).saturating_mul(self.config.accept_fee_base);
+			if tx.fee() < threshold {
+				return Err(PoolError::LowFeeTransaction(threshold));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Aggregates the transactions spending each other's outputs into a single
+/// cut-through transaction per chain of dependent transactions, keeping the
+/// independent ones as they are. Parents are expected before their children,
+/// as returned by `prepare_mineable_transactions`.
+pub fn aggregate_dependent(
+	txs: Vec<Box<transaction::Transaction>>,
+) -> Vec<Box<transaction::Transaction>> {
+	// groups of dependent transactions and which group produced each output
+	let mut groups: Vec<Vec<transaction::Transaction>> = vec![];
+	let mut producers: HashMap<Commitment, usize> = HashMap::new();
+	for tx in txs {
+		let mut parents = tx.inputs
+			.iter()
+			.filter_map(|i| producers.get(&i.commitment()).cloned())
+			.collect::<Vec<_>>();
+		parents.sort();
+		parents.dedup();
+
+		// a transaction depending on several groups merges them all in the
+		// first one
+		let index = match parents.first() {
+			Some(&first) => {
+				for &other in &parents[1..] {
+					let moved = groups[other].drain(..).collect::<Vec<_>>();
+					for t in &moved {
+						for o in &t.outputs {
+							producers.insert(o.commitment(), first);
+						}
+					}
+					groups[first].extend(moved);
+				}
+				first
+			}
+			None => {
+				groups.push(vec![]);
+				groups.len() - 1
+			}
+		};
+		for o in &tx.outputs {
+			producers.insert(o.commitment(), index);
+		}
+		groups[index].push(*tx);
+	}
+
+	let mut aggregated = vec![];
+	for group in groups {
+		if group.len() < 2 {
+			aggregated.extend(group.into_iter().map(Box::new));
+			continue;
+		}
+		match transaction::aggregate(group.clone()) {
+			Ok(tx) => aggregated.push(Box::new(tx)),
+			Err(e) => {
+				warn!(LOGGER, "pool: could not aggregate transactions: {:?}", e);
+				aggregated.extend(group.into_iter().map(Box::new));
+			}
+		}
+	}
+	aggregated
+}
+
+// Weight of a transaction counted against the maximum block weight
+fn tx_block_weight(tx: &transaction::Transaction) -> usize {
+	tx.inputs.len() * consensus::BLOCK_INPUT_WEIGHT
+		+ tx.outputs.len() * consensus::BLOCK_OUTPUT_WEIGHT
+		+ tx.kernels.len() * consensus::BLOCK_KERNEL_WEIGHT
+}
+
+// Weight of a transaction used for fee calculations, for a basic transaction
+// (1 input, 2 outputs): (-1 * 1) + (4 * 2) + 1 = 8
+fn tx_weight(tx: &transaction::Transaction) -> u64 {
+	let weight = -1 * (tx.inputs.len() as i32) + (4 * tx.outputs.len() as i32)
+		+ tx.kernels.len() as i32;
+	if weight < 1 {
+		1
+	} else {
+		weight as u64
+	}
+}
+
+// Estimated memory size of a transaction
+fn tx_size(tx: &transaction::Transaction) -> usize {
+	TX_BASE_SIZE + tx.inputs.len() * INPUT_SIZE
+		+ tx.outputs.len() * (OUTPUT_SIZE + MAX_PROOF_SIZE)
+}
+
+/// Accounts for the transaction pool memory usage in the global memory
+/// budget, only holding a weak reference to the pool.
+pub struct PoolMemory<T>(pub Weak<RwLock<TransactionPool<T>>>);
+
+impl<T> MemoryConsumer for PoolMemory<T>
+where
+	T: BlockChain + Send + Sync,
+{
+	fn memory_usage(&self) -> usize {
+		match self.0.upgrade() {
+			Some(pool) => pool.read().unwrap().memory_usage(),
+			None => 0,
 		}
+	}
 
-    // for a basic transaction (1 input, 2 outputs) -
-    // (-1 * 1) + (4 * 2) + 1 = 8
-    // 8 * 10 = 80
-		if self.config.accept_fee_base > 0 {
-			let mut tx_weight = -1 * (tx.inputs.len() as i32) + (4 * tx.outputs.len() as i32) + 1;
-			if tx_weight < 1 {
-				tx_weight = 1;
-			}
-			let threshold = (tx_weight as u64) * self.config.accept_fee_base;
-			if tx.fee < threshold {
-				return Err(PoolError::LowFeeTransaction(threshold));
-			}
+	fn evict(&self, bytes: usize) -> usize {
+		// skip this round rather than blocking if the pool is busy
+		match self.0.upgrade() {
+			Some(pool) => match pool.try_write() {
+				Ok(mut pool) => pool.evict(bytes),
+				Err(_) => 0,
+			},
+			None => 0,
 		}
-		Ok(())
+	}
+
+	fn is_active(&self) -> bool {
+		self.0.upgrade().is_some()
 	}
 }
 
+// Aggregated transaction of everything in the block but its coinbase, none
+// if the block has no transaction.
+fn block_transaction(b: &block::Block) -> Option<transaction::Transaction> {
+	let kernels = b.kernels
+		.iter()
+		.filter(|k| !k.features.contains(transaction::COINBASE_KERNEL))
+		.cloned()
+		.collect::<Vec<_>>();
+	if kernels.is_empty() {
+		return None;
+	}
+	let outputs = b.outputs
+		.iter()
+		.filter(|o| !o.features.contains(transaction::COINBASE_OUTPUT))
+		.cloned()
+		.collect();
+	let mut tx = transaction::Transaction::new(b.inputs.clone(), outputs, kernels);
+	tx.offset = b.header.kernel_offset.clone();
+	Some(tx)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -681,6 +1678,52 @@ mod tests {
 		}
 	}
 
+	#[test]
+	/// Checking transactions against the pool leaves it untouched
+	fn test_check_transaction() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(5))
+			.with_output(test_output(6))
+			.with_output(test_output(7));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let mut pool = test_setup(&Arc::new(dummy_chain));
+		let parent = test_transaction(vec![5, 6], vec![9]);
+		let check = pool.check_transaction(&parent).unwrap();
+		assert!(check.pool_parents.is_empty() && check.missing_inputs.is_empty());
+		assert_eq!(pool.total_size(), 0);
+
+		pool.add_to_memory_pool(test_source(), parent).unwrap();
+		let child = test_transaction(vec![9], vec![1]);
+		let check = pool.check_transaction(&child).unwrap();
+		assert_eq!(check.pool_parents.len(), 1);
+
+		let orphan = test_transaction(vec![20], vec![18]);
+		let check = pool.check_transaction(&orphan).unwrap();
+		assert_eq!(check.missing_inputs, vec![test_output(20).commitment()]);
+
+		match pool.check_transaction(&test_transaction(vec![6], vec![2])) {
+			Err(PoolError::DoubleSpend { .. }) => {}
+			x => panic!("Expected a double spend, got {:?}", x),
+		}
+		assert_eq!(pool.total_size(), 1);
+
+		// same pool limits as when adding it
+		let tx = test_transaction(vec![7], vec![6]);
+		pool.config.max_pool_weight = tx_block_weight(&tx);
+		pool.config.max_pool_size = 1;
+		match pool.check_transaction(&tx) {
+			Err(PoolError::LowFeeTransaction(_)) => {}
+			x => panic!("Expected a low fee transaction, got {:?}", x),
+		}
+	}
+
 	#[test]
 	/// Testing various expected error conditions
 	pub fn test_pool_add_error() {
@@ -793,6 +1836,112 @@ mod tests {
 		}
 	}
 
+	#[test]
+	/// Testing the replacement of a pool transaction by a higher fee version
+	/// spending the same inputs
+	fn test_pool_replace_by_fee() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(5))
+			.with_output(test_output(6));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let pool = RwLock::new(test_setup(&Arc::new(dummy_chain)));
+		{
+			let mut write_pool = pool.write().unwrap();
+
+			// fee of 2
+			let original = test_transaction(vec![5, 6], vec![9]);
+			write_pool
+				.add_to_memory_pool(test_source(), original)
+				.unwrap();
+
+			// no fee, not enough to replace the original
+			let low_fee = test_transaction(vec![5, 6], vec![11]);
+			match write_pool.add_to_memory_pool(test_source(), low_fee) {
+				Err(PoolError::LowFeeReplacement(required)) => assert_eq!(required, 2),
+				Err(e) => panic!("expected LowFeeReplacement error here - {:?}", e),
+				Ok(_) => panic!("expected LowFeeReplacement error here"),
+			};
+
+			// fee of 4, replaces the original
+			let bumped = test_transaction(vec![5, 6], vec![7]);
+			write_pool
+				.add_to_memory_pool(test_source(), bumped)
+				.unwrap();
+			assert_eq!(write_pool.total_size(), 1);
+		}
+
+		{
+			let read_pool = pool.read().unwrap();
+			expect_output_parent!(read_pool, Parent::PoolTransaction{tx_ref: _}, 7);
+			expect_output_parent!(read_pool, Parent::Unknown, 9);
+		}
+	}
+
+	#[test]
+	/// Testing a replacement failing validation leaves the pool untouched,
+	/// while one keeping some outputs of the replaced transaction goes through
+	fn test_pool_replace_by_fee_validation() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(5))
+			.with_output(test_output(6));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let pool = RwLock::new(test_setup(&Arc::new(dummy_chain)));
+		{
+			let mut write_pool = pool.write().unwrap();
+
+			// fee of 2
+			let original = test_transaction(vec![5, 6], vec![8, 1]);
+			write_pool
+				.add_to_memory_pool(test_source(), original)
+				.unwrap();
+
+			// enough fee, but creates an output already in the chain
+			let duplicate = test_transaction(vec![5, 6], vec![6]);
+			match write_pool.add_to_memory_pool(test_source(), duplicate) {
+				Err(PoolError::DuplicateOutput { in_chain: true, .. }) => {}
+				Err(e) => panic!("expected DuplicateOutput error here - {:?}", e),
+				Ok(_) => panic!("expected DuplicateOutput error here"),
+			};
+			assert_eq!(write_pool.total_size(), 1);
+		}
+		{
+			let read_pool = pool.read().unwrap();
+			expect_output_parent!(read_pool, Parent::PoolTransaction{tx_ref: _}, 8, 1);
+		}
+
+		{
+			let mut write_pool = pool.write().unwrap();
+
+			// fee of 3, paying the same output
+			let bumped = test_transaction(vec![5, 6], vec![8]);
+			write_pool
+				.add_to_memory_pool(test_source(), bumped)
+				.unwrap();
+			assert_eq!(write_pool.total_size(), 1);
+		}
+		{
+			let read_pool = pool.read().unwrap();
+			expect_output_parent!(read_pool, Parent::PoolTransaction{tx_ref: _}, 8);
+			expect_output_parent!(read_pool, Parent::Unknown, 1);
+		}
+	}
+
 	#[test]
 	fn test_immature_coinbase() {
 		global::set_mining_mode(ChainTypes::AutomatedTesting);
@@ -855,7 +2004,36 @@ mod tests {
 	#[test]
 	/// Testing an expected orphan
 	fn test_add_orphan() {
-		// TODO we need a test here
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty().with_output(test_output(100));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let mut pool = test_setup(&chain_ref);
+
+		// the child arrives before its parent
+		let parent_tx = test_transaction(vec![100], vec![90]);
+		let child_tx = test_transaction(vec![90], vec![80]);
+
+		match pool.add_to_memory_pool(test_source(), child_tx) {
+			Err(PoolError::OrphanTransaction) => {}
+			other => panic!("expected an orphan, got {:?}", other),
+		}
+		assert_eq!(pool.orphans_size(), 1);
+		assert_eq!(pool.pool_size(), 0);
+
+		// the parent gets the orphan moved to the pool
+		pool.add_to_memory_pool(test_source(), parent_tx).unwrap();
+		assert_eq!(pool.orphans_size(), 0);
+		assert_eq!(pool.pool_size(), 2);
+		expect_output_parent!(pool, Parent::AlreadySpent{other_tx: _}, 100, 90);
+		expect_output_parent!(pool, Parent::PoolTransaction{tx_ref: _}, 80);
 	}
 
 	#[test]
@@ -895,7 +2073,8 @@ mod tests {
 		let txs: Vec<transaction::Transaction>;
 		{
 			let read_pool = pool.read().unwrap();
-			let mut mineable_txs = read_pool.prepare_mineable_transactions(3);
+			let mut mineable_txs =
+				read_pool.prepare_mineable_transactions(consensus::MAX_BLOCK_WEIGHT);
 			txs = mineable_txs.drain(..).map(|x| *x).collect();
 
 			// confirm we can preparing both txs for mining here
@@ -1089,6 +2268,336 @@ mod tests {
 		}
 	}
 
+	#[test]
+	/// Test a reorg restores the transactions of the orphaned blocks, loaded
+	/// from the chain when the pool doesn't remember them.
+	fn test_reorg_reconciliation() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+		dummy_chain.update_utxo_set(DummyUtxoSet::empty().with_output(test_output(10)));
+		let chain_ref = Arc::new(dummy_chain);
+
+		// two blocks on the branch being orphaned, the second spending an
+		// output of the first
+		let keychain = Keychain::from_random_seed().unwrap();
+		let key_id = keychain.derive_key_id(1).unwrap();
+		let parent_tx = test_transaction(vec![10], vec![8, 1]);
+		let child_tx = test_transaction(vec![8], vec![7]);
+		let block1 = block::Block::new(
+			&block::BlockHeader::default(),
+			vec![&parent_tx],
+			&keychain,
+			&key_id,
+			Difficulty::one(),
+		).unwrap();
+		let block2 = block::Block::new(
+			&block1.header,
+			vec![&child_tx],
+			&keychain,
+			&key_id,
+			Difficulty::one(),
+		).unwrap();
+		chain_ref.store_block(&block1);
+		chain_ref.store_block(&block2);
+
+		// a freshly started pool, with nothing cached about these blocks
+		let pool = RwLock::new(test_setup(&chain_ref));
+		{
+			let mut write_pool = pool.write().unwrap();
+			assert_eq!(write_pool.reconcile_reorg(block2.hash(), 2), 2);
+			assert_eq!(write_pool.total_size(), 2);
+		}
+		{
+			let read_pool = pool.read().unwrap();
+			expect_output_parent!(read_pool, Parent::PoolTransaction{tx_ref: _}, 7, 1);
+			expect_output_parent!(read_pool, Parent::AlreadySpent{other_tx: _}, 8);
+		}
+
+		// restoring again finds everything already in the pool
+		{
+			let mut write_pool = pool.write().unwrap();
+			assert_eq!(write_pool.reconcile_reorg(block2.hash(), 2), 0);
+			assert_eq!(write_pool.total_size(), 2);
+		}
+	}
+
+	#[test]
+	/// Test a full pool evicts its lowest fee transactions for better ones.
+	fn test_pool_limits() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(200))
+			.with_output(test_output(300))
+			.with_output(test_output(400));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let mut pool = test_setup(&chain_ref);
+		pool.config.max_pool_size = 2;
+
+		let low_tx = test_transaction(vec![100], vec![98]);
+		let mid_tx = test_transaction(vec![200], vec![194]);
+		let high_tx = test_transaction(vec![300], vec![290]);
+		let below_floor_tx = test_transaction(vec![400], vec![396]);
+		let low_hash = low_tx.hash();
+
+		pool.add_to_memory_pool(test_source(), low_tx).unwrap();
+		pool.add_to_memory_pool(test_source(), mid_tx).unwrap();
+		assert_eq!(pool.total_size(), 2);
+
+		// full, the lowest fee transaction makes room for the new one
+		pool.add_to_memory_pool(test_source(), high_tx).unwrap();
+		assert_eq!(pool.total_size(), 2);
+		assert!(!pool.transactions.contains_key(&low_hash));
+
+		// doesn't pay more than the mid fee transaction that would be evicted
+		match pool.add_to_memory_pool(test_source(), below_floor_tx) {
+			Err(PoolError::LowFeeTransaction(7)) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+		assert_eq!(pool.total_size(), 2);
+	}
+
+	#[test]
+	/// Test a transaction evicted right away, along with its low fee parent,
+	/// never gets relayed.
+	fn test_pool_limits_relay() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(200));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let adapter = Arc::new(RecordingAdapter::new());
+		let mut pool = test_setup(&chain_ref);
+		pool.adapter = adapter.clone();
+		pool.config.max_pool_size = 2;
+
+		let low_tx = test_transaction(vec![100], vec![98]);
+		let mid_tx = test_transaction(vec![200], vec![194]);
+		let child_tx = test_transaction(vec![98], vec![88]);
+		let (low_hash, mid_hash, child_hash) = (low_tx.hash(), mid_tx.hash(), child_tx.hash());
+
+		pool.add_to_memory_pool(test_source(), low_tx).unwrap();
+		pool.add_to_memory_pool(test_source(), mid_tx).unwrap();
+		pool.add_to_memory_pool(test_source(), child_tx).unwrap();
+
+		assert_eq!(pool.total_size(), 1);
+		assert!(pool.transactions.contains_key(&mid_hash));
+		let relayed = adapter.relayed.read().unwrap();
+		assert_eq!(*relayed, vec![low_hash, mid_hash]);
+		assert!(!relayed.contains(&child_hash));
+	}
+
+	#[test]
+	/// Test stem transactions relayed by peers go to the stem pool without
+	/// being rebroadcast by us, unlike our own.
+	fn test_relayed_stem_transaction() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(200));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let mut pool = test_setup(&chain_ref);
+
+		let local_tx = test_transaction(vec![100], vec![98]);
+		let relayed_tx = test_transaction(vec![200], vec![194]);
+		let (local_hash, relayed_hash) = (local_tx.hash(), relayed_tx.hash());
+
+		pool.add_to_stempool(test_source(), local_tx).unwrap();
+		pool.add_relayed_to_stempool(test_source(), relayed_tx.clone())
+			.unwrap();
+		assert!(pool.stempool.contains(&local_hash));
+		assert!(pool.stempool.contains(&relayed_hash));
+		assert!(pool.local_txs.contains_key(&local_hash));
+		assert!(!pool.local_txs.contains_key(&relayed_hash));
+
+		match pool.add_relayed_to_stempool(test_source(), relayed_tx) {
+			Err(PoolError::AlreadyInPool) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	/// Test an evicted local transaction doesn't get rebroadcast anymore.
+	fn test_rebroadcast_cancelled() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(200))
+			.with_output(test_output(300));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let mut pool = test_setup(&chain_ref);
+		pool.config.max_pool_size = 1;
+
+		let low_tx = test_transaction(vec![100], vec![98]);
+		let high_tx = test_transaction(vec![200], vec![190]);
+		let other_tx = test_transaction(vec![300], vec![290]);
+		let (low_hash, other_hash) = (low_tx.hash(), other_tx.hash());
+
+		pool.add_to_memory_pool(test_source(), low_tx).unwrap();
+		pool.track_local(low_hash, 1);
+		pool.add_to_memory_pool(test_source(), high_tx).unwrap();
+		assert!(!pool.transactions.contains_key(&low_hash));
+		assert!(!pool.local_txs.contains_key(&low_hash));
+		assert!(!pool.cancel_rebroadcast(&low_hash));
+
+		// explicitly cancelled
+		pool.track_local(other_hash, 1);
+		assert!(pool.cancel_rebroadcast(&other_hash));
+		assert!(pool.local_txs.is_empty());
+	}
+
+	#[test]
+	/// Test a high fee child gets its low fee parent mined first.
+	fn test_mineable_fee_ordering() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(50));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let pool = RwLock::new(test_setup(&chain_ref));
+
+		// fee per weight of 2/4 for the parent alone but 14/8 with its child,
+		// better than the 6/4 of the other transaction
+		let parent_tx = test_transaction(vec![100], vec![98]);
+		let child_tx = test_transaction(vec![98], vec![86]);
+		let other_tx = test_transaction(vec![50], vec![44]);
+		let parent_hash = parent_tx.hash();
+		let child_hash = child_tx.hash();
+
+		{
+			let mut write_pool = pool.write().unwrap();
+			write_pool.add_to_memory_pool(test_source(), parent_tx).unwrap();
+			write_pool.add_to_memory_pool(test_source(), child_tx).unwrap();
+			write_pool.add_to_memory_pool(test_source(), other_tx).unwrap();
+			assert_eq!(write_pool.total_size(), 3);
+		}
+
+		// only room for 2 transactions of 1 input and 1 output
+		let read_pool = pool.read().unwrap();
+		let txs = read_pool.prepare_mineable_transactions(26);
+		assert_eq!(txs.len(), 2);
+		assert_eq!(txs[0].hash(), parent_hash);
+		assert_eq!(txs[1].hash(), child_hash);
+
+		let txs = read_pool.prepare_mineable_transactions(consensus::MAX_BLOCK_WEIGHT);
+		assert_eq!(txs.len(), 3);
+	}
+
+	#[test]
+	/// Test the fee estimate outbids the transactions that won't get mined.
+	fn test_fee_estimate() {
+		let mut dummy_chain = DummyChainImpl::new();
+		let head_header = block::BlockHeader {
+			height: 1,
+			..block::BlockHeader::default()
+		};
+		dummy_chain.store_head_header(&head_header);
+
+		let new_utxo = DummyUtxoSet::empty()
+			.with_output(test_output(100))
+			.with_output(test_output(200))
+			.with_output(test_output(300));
+		dummy_chain.update_utxo_set(new_utxo);
+
+		let chain_ref = Arc::new(dummy_chain);
+		let mut pool = test_setup(&chain_ref);
+		pool.config.accept_fee_base = 1;
+
+		// fees per weight of 2/4, 6/4 and 10/4
+		let low_tx = test_transaction(vec![100], vec![98]);
+		let mid_tx = test_transaction(vec![200], vec![194]);
+		let high_tx = test_transaction(vec![300], vec![290]);
+		pool.add_to_memory_pool(test_source(), low_tx).unwrap();
+		pool.add_to_memory_pool(test_source(), mid_tx).unwrap();
+		pool.add_to_memory_pool(test_source(), high_tx).unwrap();
+
+		// everything fits, the minimum fee is enough
+		assert_eq!(pool.estimate_fee_base(1), 1);
+		assert_eq!(pool.fee_base_within(39), 1);
+		// only room for 2 transactions of 1 input and 1 output, the low fee
+		// one is left out
+		assert_eq!(pool.fee_base_within(26), 1);
+		// only room for 1, has to outbid the mid fee one
+		assert_eq!(pool.fee_base_within(13), 2);
+	}
+
+	#[test]
+	/// Test transactions spending each other's outputs get aggregated.
+	fn test_aggregate_dependent() {
+		let parent_tx = test_transaction(vec![100], vec![90]);
+		let child_tx = test_transaction(vec![90], vec![80]);
+		let grandchild_tx = test_transaction(vec![80], vec![70]);
+		let other_tx = test_transaction(vec![50], vec![46]);
+
+		let txs = aggregate_dependent(vec![
+			Box::new(parent_tx.clone()),
+			Box::new(other_tx.clone()),
+			Box::new(child_tx),
+			Box::new(grandchild_tx.clone()),
+		]);
+		assert_eq!(txs.len(), 2);
+		assert_eq!(txs[0].inputs, parent_tx.inputs);
+		assert_eq!(txs[0].outputs, grandchild_tx.outputs);
+		assert_eq!(txs[0].kernels.len(), 3);
+		assert_eq!(txs[0].fee(), 30);
+		txs[0].validate().unwrap();
+		assert_eq!(txs[1].hash(), other_tx.hash());
+
+		// a transaction spending from 2 chains merges them
+		let tx_a = test_transaction(vec![10], vec![8]);
+		let tx_b = test_transaction(vec![20], vec![16]);
+		let tx_c = test_transaction(vec![8, 16], vec![22]);
+		let txs = aggregate_dependent(vec![Box::new(tx_a), Box::new(tx_b), Box::new(tx_c.clone())]);
+		assert_eq!(txs.len(), 1);
+		assert_eq!(txs[0].inputs.len(), 2);
+		assert_eq!(txs[0].outputs, tx_c.outputs);
+		txs[0].validate().unwrap();
+	}
+
 	#[test]
 	/// Test transaction selection and block building.
 	fn test_block_building() {
@@ -1157,7 +2666,11 @@ mod tests {
 		let mut txs: Vec<Box<transaction::Transaction>>;
 		{
 			let read_pool = pool.read().unwrap();
-			txs = read_pool.prepare_mineable_transactions(3);
+			// root_tx_1 has the best fee rate, followed by child_tx_1 (whose
+			// parent is already in), the child_tx_2 package doesn't fit
+			// anymore and root_tx_2 or root_tx_3, paying the same fee, fills
+			// up the rest
+			txs = read_pool.prepare_mineable_transactions(40);
 			assert_eq!(txs.len(), 3);
 			// TODO: This is ugly, either make block::new take owned
    // txs instead of mut refs, or change
@@ -1190,15 +2703,46 @@ mod tests {
 		}
 	}
 
+	/// Adapter keeping track of the transactions relayed by the pool.
+	struct RecordingAdapter {
+		relayed: RwLock<Vec<Hash>>,
+	}
+
+	impl RecordingAdapter {
+		fn new() -> RecordingAdapter {
+			RecordingAdapter {
+				relayed: RwLock::new(vec![]),
+			}
+		}
+	}
+
+	impl PoolAdapter for RecordingAdapter {
+		fn tx_accepted(&self, tx: &transaction::Transaction) {
+			self.relayed.write().unwrap().push(tx.hash());
+		}
+		fn stem_tx_accepted(&self, _: &transaction::Transaction) -> Result<(), PoolError> {
+			Ok(())
+		}
+	}
+
 	fn test_setup(dummy_chain: &Arc<DummyChainImpl>) -> TransactionPool<DummyChainImpl> {
 		TransactionPool {
 			config: PoolConfig {
 				accept_fee_base: 0,
 				max_pool_size: 10_000,
+				max_pool_weight: 1_000_000,
+				max_orphans: 100,
+				orphan_expiry_secs: 600,
+				rebroadcast_blocks: 0,
+				dandelion_config: DandelionConfig::default(),
 			},
 			transactions: HashMap::new(),
 			pool: Pool::empty(),
 			orphans: Orphans::empty(),
+			orphan_txs: HashMap::new(),
+			stempool: StemPool::empty(),
+			local_txs: HashMap::new(),
+			reorg_cache: VecDeque::new(),
 			blockchain: dummy_chain.clone(),
 			adapter: Arc::new(NoopAdapter {}),
 		}
@@ -0,0 +1,186 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stem pool, holding the transactions in the stem phase of Dandelion. A stem
+//! transaction is only relayed to a single peer instead of being broadcast,
+//! which hides the node it originates from. Stem transactions are kept aside
+//! from the main pool, they're neither broadcast nor mined until they get
+//! fluffed, either by us or by observing them being broadcast by another node
+//! (the main pool accepting them).
+//!
+//! Each stem transaction also gets an embargo timer. If it hasn't been
+//! fluffed when the timer expires, a node along the stem likely dropped it
+//! and we fluff it ourselves.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::{self, Rng};
+
+use core::core::{block, hash, transaction};
+use core::core::hash::Hashed;
+use util::secp::pedersen::Commitment;
+
+use types::DandelionConfig;
+
+struct StemEntry {
+	tx: Box<transaction::Transaction>,
+	received: i64,
+	embargo_until: i64,
+	relayed: bool,
+}
+
+/// Transactions in their stem phase, keyed by transaction hash.
+pub struct StemPool {
+	entries: HashMap<hash::Hash, StemEntry>,
+}
+
+impl StemPool {
+	/// An empty stem pool
+	pub fn empty() -> StemPool {
+		StemPool {
+			entries: HashMap::new(),
+		}
+	}
+
+	/// Number of transactions in the stem pool
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Whether the transaction is in the stem pool
+	pub fn contains(&self, tx_hash: &hash::Hash) -> bool {
+		self.entries.contains_key(tx_hash)
+	}
+
+	/// The stem transaction spending the provided output, if any
+	pub fn spent_by(&self, commit: &Commitment) -> Option<hash::Hash> {
+		self.entries
+			.iter()
+			.find(|&(_, e)| e.tx.inputs.iter().any(|i| i.commitment() == *commit))
+			.map(|(h, _)| *h)
+	}
+
+	/// Adds a transaction in its stem phase, received at the provided time.
+	/// The embargo is randomized (up to an additional half of the configured
+	/// embargo) so the node fluffing an expired transaction first can't be
+	/// predicted.
+	pub fn add(&mut self, tx: transaction::Transaction, now: i64, config: &DandelionConfig) {
+		let jitter = rand::thread_rng().gen_range(0, config.embargo_secs / 2 + 1) as i64;
+		let tx_hash = tx.hash();
+		self.entries.insert(
+			tx_hash,
+			StemEntry {
+				tx: Box::new(tx),
+				received: now,
+				embargo_until: now + config.embargo_secs as i64 + jitter,
+				relayed: false,
+			},
+		);
+	}
+
+	/// All the stem transactions, with the time they were received
+	pub fn transactions(&self) -> Vec<(&transaction::Transaction, i64)> {
+		self.entries
+			.values()
+			.map(|e| (e.tx.as_ref(), e.received))
+			.collect()
+	}
+
+	/// Removes a transaction from the stem pool, returning it.
+	pub fn remove(&mut self, tx_hash: &hash::Hash) -> Option<Box<transaction::Transaction>> {
+		self.entries.remove(tx_hash).map(|e| e.tx)
+	}
+
+	/// Batch of the transactions that haven't been relayed yet and have been
+	/// held for at least the aggregation period. They're marked as relayed,
+	/// staying in the stem pool until fluffed or their embargo expires.
+	pub fn ready_to_relay(
+		&mut self,
+		now: i64,
+		config: &DandelionConfig,
+	) -> Vec<Box<transaction::Transaction>> {
+		let mut batch = vec![];
+		for e in self.entries.values_mut() {
+			if !e.relayed && e.received + config.relay_secs as i64 <= now {
+				e.relayed = true;
+				batch.push(e.tx.clone());
+			}
+		}
+		batch
+	}
+
+	/// Removes and returns the transactions which embargo expired.
+	pub fn expired(&mut self, now: i64) -> Vec<Box<transaction::Transaction>> {
+		let expired = self.entries
+			.iter()
+			.filter(|&(_, e)| e.embargo_until <= now)
+			.map(|(h, _)| *h)
+			.collect::<Vec<_>>();
+		expired
+			.iter()
+			.filter_map(|h| self.entries.remove(h).map(|e| e.tx))
+			.collect()
+	}
+
+	/// Drops the stem transactions made obsolete by a new block, the ones it
+	/// includes or conflicting with it.
+	pub fn reconcile_block(&mut self, block: &block::Block) -> usize {
+		let spent = block
+			.inputs
+			.iter()
+			.map(|i| i.commitment())
+			.collect::<HashSet<_>>();
+		let created = block
+			.outputs
+			.iter()
+			.map(|o| o.commitment())
+			.collect::<HashSet<_>>();
+
+		let before = self.entries.len();
+		self.entries.retain(|_, e| {
+			!e.tx.inputs.iter().any(|i| spent.contains(&i.commitment()))
+				&& !e.tx.outputs.iter().any(|o| created.contains(&o.commitment()))
+		});
+		before - self.entries.len()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn stem_relay_and_embargo() {
+		let config = DandelionConfig {
+			relay_secs: 10,
+			embargo_secs: 100,
+			stem_probability: 90,
+		};
+		let mut stempool = StemPool::empty();
+		let tx = transaction::Transaction::empty();
+		let tx_hash = tx.hash();
+		stempool.add(tx, 0, &config);
+		assert!(stempool.contains(&tx_hash));
+
+		// held during the aggregation period, then relayed once
+		assert!(stempool.ready_to_relay(9, &config).is_empty());
+		assert_eq!(stempool.ready_to_relay(10, &config).len(), 1);
+		assert!(stempool.ready_to_relay(11, &config).is_empty());
+
+		// embargo is between 100 and 150 secs
+		assert!(stempool.expired(99).is_empty());
+		assert_eq!(stempool.expired(150).len(), 1);
+		assert_eq!(stempool.len(), 0);
+	}
+}
@@ -40,6 +40,31 @@ pub struct PoolConfig {
 	/// Maximum capacity of the pool in number of transactions
 	#[serde = "default_max_pool_size"]
 	pub max_pool_size: usize,
+
+	/// Maximum total weight of the transactions in the pool, in block weight
+	/// units (see consensus::MAX_BLOCK_WEIGHT). When either limit is reached,
+	/// the transactions with the lowest fee per weight get evicted.
+	#[serde(default = "default_max_pool_weight")]
+	pub max_pool_weight: usize,
+
+	/// Maximum number of orphans, transactions spending outputs of other
+	/// transactions we haven't seen yet, held until their parents arrive
+	#[serde(default = "default_max_orphans")]
+	pub max_orphans: usize,
+
+	/// Seconds after which an orphan which parents haven't arrived is dropped
+	#[serde(default = "default_orphan_expiry_secs")]
+	pub orphan_expiry_secs: u64,
+
+	/// Number of blocks after which a locally submitted transaction still
+	/// unconfirmed gets broadcast again, the interval doubling after each
+	/// rebroadcast. Disabled when 0.
+	#[serde(default = "default_rebroadcast_blocks")]
+	pub rebroadcast_blocks: u64,
+
+	/// Dandelion stem phase configuration
+	#[serde(default)]
+	pub dandelion_config: DandelionConfig,
 }
 
 impl Default for PoolConfig {
@@ -47,6 +72,11 @@ impl Default for PoolConfig {
 		PoolConfig {
 			accept_fee_base: default_accept_fee_base(),
 			max_pool_size: default_max_pool_size(),
+			max_pool_weight: default_max_pool_weight(),
+			max_orphans: default_max_orphans(),
+			orphan_expiry_secs: default_orphan_expiry_secs(),
+			rebroadcast_blocks: default_rebroadcast_blocks(),
+			dandelion_config: DandelionConfig::default(),
 		}
 	}
 }
@@ -57,6 +87,85 @@ fn default_accept_fee_base() -> u64 {
 fn default_max_pool_size() -> usize {
 	50_000
 }
+fn default_max_pool_weight() -> usize {
+	50 * consensus::MAX_BLOCK_WEIGHT
+}
+fn default_max_orphans() -> usize {
+	1_000
+}
+fn default_orphan_expiry_secs() -> u64 {
+	600
+}
+fn default_rebroadcast_blocks() -> u64 {
+	5
+}
+
+/// Configuration of the Dandelion stem phase, during which transactions are
+/// relayed to a single peer before being broadcast (fluffed).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DandelionConfig {
+	/// Seconds a stem transaction is held before being relayed, so several
+	/// transactions can be aggregated and relayed as a single one
+	#[serde(default = "default_relay_secs")]
+	pub relay_secs: u64,
+	/// Seconds after which a stem transaction that hasn't been observed
+	/// fluffed gets fluffed by us
+	#[serde(default = "default_embargo_secs")]
+	pub embargo_secs: u64,
+	/// Percentage of chance a batch of stem transactions is relayed further
+	/// along the stem instead of being fluffed
+	#[serde(default = "default_stem_probability")]
+	pub stem_probability: u8,
+}
+
+impl Default for DandelionConfig {
+	fn default() -> DandelionConfig {
+		DandelionConfig {
+			relay_secs: default_relay_secs(),
+			embargo_secs: default_embargo_secs(),
+			stem_probability: default_stem_probability(),
+		}
+	}
+}
+
+fn default_relay_secs() -> u64 {
+	10
+}
+fn default_embargo_secs() -> u64 {
+	180
+}
+fn default_stem_probability() -> u8 {
+	90
+}
+
+/// A transaction held by the pool, as reported for inspection
+#[derive(Debug, Clone)]
+pub struct PoolTxInfo {
+	/// The transaction itself
+	pub tx: Box<transaction::Transaction>,
+	/// Weight of the transaction counted against the maximum block weight
+	pub weight: usize,
+	/// When the transaction was received, in seconds since the epoch
+	pub received: i64,
+	/// Whether the transaction is still in its stem phase
+	pub stem: bool,
+}
+
+/// Outcome of the pool checks on a transaction, for a transaction that
+/// passed them without being added to the pool
+#[derive(Debug, Clone)]
+pub struct TxCheck {
+	/// Weight of the transaction counted against the maximum block weight
+	pub weight: usize,
+	/// Pool transactions (and their descendants) the transaction would
+	/// replace, paying a higher fee
+	pub replaces: Vec<hash::Hash>,
+	/// Pool transactions the transaction spends outputs of
+	pub pool_parents: Vec<hash::Hash>,
+	/// Inputs found neither in the chain nor in the pool, the transaction
+	/// would wait in the orphans set until they show up
+	pub missing_inputs: Vec<Commitment>,
+}
 
 /// Placeholder: the data representing where we heard about a tx from.
 ///
@@ -145,6 +254,11 @@ pub enum PoolError {
 	OverCapacity,
 	/// Transaction fee is too low given its weight
 	LowFeeTransaction(u64),
+	/// Transaction double spends pool transactions without paying enough
+	/// fees to replace them, with the minimum fee required
+	LowFeeReplacement(u64),
+	/// The stem transaction couldn't be relayed to the next stem peer
+	StemRelay(String),
 }
 
 /// Interface that the pool requires from a blockchain implementation.
@@ -164,6 +278,10 @@ pub trait BlockChain {
 
 	/// Get the block header at the head
 	fn head_header(&self) -> Result<block::BlockHeader, PoolError>;
+
+	/// Get a block by its hash, whether it's on the current chain or on a
+	/// fork
+	fn get_block(&self, h: &hash::Hash) -> Result<block::Block, PoolError>;
 }
 
 /// Bridge between the transaction pool and the rest of the system. Handles
@@ -173,6 +291,10 @@ pub trait PoolAdapter: Send + Sync {
 	/// The transaction pool has accepted this transactions as valid and added
 	/// it to its internal cache.
 	fn tx_accepted(&self, tx: &transaction::Transaction);
+
+	/// Relays a transaction in its stem phase to the next peer along the
+	/// stem. On failure, the transaction gets fluffed instead.
+	fn stem_tx_accepted(&self, tx: &transaction::Transaction) -> Result<(), PoolError>;
 }
 
 /// Dummy adapter used as a placeholder for real implementations
@@ -181,6 +303,9 @@ pub trait PoolAdapter: Send + Sync {
 pub struct NoopAdapter {}
 impl PoolAdapter for NoopAdapter {
 	fn tx_accepted(&self, _: &transaction::Transaction) {}
+	fn stem_tx_accepted(&self, _: &transaction::Transaction) -> Result<(), PoolError> {
+		Ok(())
+	}
 }
 
 /// Pool contains the elements of the graph that are connected, in full, to
@@ -384,6 +509,38 @@ impl Orphans {
 		self.missing_outputs.get(output)
 	}
 
+	/// Removes an orphan transaction from the orphans set. Outputs of other
+	/// orphans it spent become available again and orphans spending its
+	/// outputs now miss them.
+	pub fn remove_orphan_transaction(&mut self, tx: &transaction::Transaction) {
+		self.graph.remove_vertex(graph::transaction_identifier(tx));
+
+		for input in tx.inputs.iter().map(|x| x.commitment()) {
+			match self.graph.remove_edge_by_commitment(&input) {
+				Some(x) => {
+					self.available_outputs
+						.insert(x.output_commitment(), x.with_destination(None));
+				}
+				None => {
+					self.missing_outputs.remove(&input);
+					self.pool_connections.remove(&input);
+				}
+			}
+		}
+
+		for output in tx.outputs.iter().map(|x| x.commitment()) {
+			match self.graph.remove_edge_by_commitment(&output) {
+				Some(x) => {
+					self.missing_outputs
+						.insert(x.output_commitment(), x.with_source(None));
+				}
+				None => {
+					self.available_outputs.remove(&output);
+				}
+			}
+		}
+	}
+
 	/// Add an orphan transaction to the orphans set.
 	///
 	/// This method adds a given transaction (represented by the PoolEntry at
@@ -47,6 +47,7 @@ extern crate cuckoo_miner;
 mod siphash;
 pub mod plugin;
 pub mod cuckoo;
+pub mod mining;
 pub mod types;
 
 use core::consensus;
@@ -0,0 +1,330 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interface between the node and the mining plugins looking for proofs of
+//! work. The node publishes a job for each block it builds, plugins get the
+//! latest job and submit the solutions they find for it. Plugins are either
+//! the internal cuckoo cycle solver or the (CPU or GPU) solvers cuckoo-miner
+//! loads, as configured.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use time;
+
+use core::consensus;
+use core::core::{BlockHeader, Proof};
+use core::core::hash::{Hash, Hashed};
+use core::global;
+use core::ser::{self, AsFixedBytes};
+use cuckoo;
+use plugin::{AsyncPluginMiner, PluginMiner};
+use types::MinerConfig;
+use util;
+use util::LOGGER;
+use MiningWorker;
+
+const PRE_NONCE_SIZE: usize = 146;
+
+// how often mining plugins log their graph rate, in seconds
+const STATS_INTERVAL: i64 = 2;
+
+/// A block header to find a proof of work for.
+#[derive(Debug, Clone)]
+pub struct MiningJob {
+	/// Job identifier, to submit solutions with
+	pub job_id: u64,
+	/// Header of the block to mine, solvers iterating over its nonce
+	pub header: BlockHeader,
+}
+
+impl MiningJob {
+	/// Hash of the job header with the provided nonce, seeding the cuckoo
+	/// graph.
+	pub fn pow_hash(&self, nonce: u64) -> Hash {
+		let mut header = self.header.clone();
+		header.nonce = nonce;
+		header.hash()
+	}
+
+	/// Hex of the serialized job header before and after its nonce, for
+	/// solvers iterating over nonces themselves.
+	pub fn header_parts(&self) -> (String, String) {
+		let mut header_parts = HeaderPartWriter::default();
+		ser::Writeable::write(&self.header, &mut header_parts).unwrap();
+		header_parts.parts_as_hex_strings()
+	}
+}
+
+/// Where mining plugins get their jobs from and submit their solutions to,
+/// implemented by the node.
+pub trait JobProvider: Send + Sync {
+	/// The latest job to mine, waiting for one if none is available yet.
+	/// None once mining stopped.
+	fn get_job(&self) -> Option<MiningJob>;
+
+	/// Submits a proof of work found for a job with the provided nonce. The
+	/// job gets replaced if the solution makes a block.
+	fn submit_solution(&self, job_id: u64, nonce: u64, proof: Proof);
+
+	/// Reports the number of graphs per second the plugin searches, for the
+	/// mining stats.
+	fn report_graph_rate(&self, _graphs_per_second: f64) {}
+}
+
+/// A cuckoo cycle solver mining the jobs of a provider.
+pub trait MiningPlugin {
+	/// Name of the plugin, for logs
+	fn name(&self) -> String;
+
+	/// Mines the jobs of the provider, switching to the latest job anytime it
+	/// changes. Returns once the provider has no more jobs.
+	fn run(&mut self, jobs: Arc<JobProvider>);
+}
+
+/// Loads the configured mining plugin: the cuckoo-miner solvers, in sync or
+/// async mode, if enabled, the internal solver otherwise.
+pub fn load_plugin(config: &MinerConfig) -> Box<MiningPlugin> {
+	let sizeshift = global::sizeshift() as u32;
+	let proof_size = global::proofsize();
+	if config.use_cuckoo_miner {
+		let mut plugin_miner = PluginMiner::new(consensus::EASINESS, sizeshift, proof_size);
+		plugin_miner.init(config.clone());
+		if config.cuckoo_miner_async_mode == Some(true) {
+			Box::new(AsyncPluginMiner::new(plugin_miner))
+		} else {
+			Box::new(WorkerPlugin::new(
+				format!("cuckoo-miner cuckoo{}", sizeshift),
+				plugin_miner,
+				config.slow_down_in_millis,
+			))
+		}
+	} else {
+		Box::new(WorkerPlugin::new(
+			format!("internal cuckoo{}", sizeshift),
+			cuckoo::Miner::new(consensus::EASINESS, sizeshift, proof_size),
+			config.slow_down_in_millis,
+		))
+	}
+}
+
+/// Runs a mining worker as a plugin, trying consecutive nonces of the latest
+/// job, one at a time.
+pub struct WorkerPlugin<T: MiningWorker> {
+	name: String,
+	worker: T,
+	// testing attribute artificially slowing down each attempt
+	slow_down_in_millis: Option<u64>,
+}
+
+impl<T: MiningWorker> WorkerPlugin<T> {
+	/// Wraps the provided mining worker
+	pub fn new(name: String, worker: T, slow_down_in_millis: Option<u64>) -> WorkerPlugin<T> {
+		WorkerPlugin {
+			name: name,
+			worker: worker,
+			slow_down_in_millis: slow_down_in_millis,
+		}
+	}
+}
+
+impl<T: MiningWorker> MiningPlugin for WorkerPlugin<T> {
+	fn name(&self) -> String {
+		self.name.clone()
+	}
+
+	fn run(&mut self, jobs: Arc<JobProvider>) {
+		let mut job = match jobs.get_job() {
+			Some(job) => job,
+			None => return,
+		};
+		let mut nonce = job.header.nonce;
+		let mut graphs = 0;
+		let mut last_stats = time::get_time().sec;
+		loop {
+			if let Ok(proof) = self.worker.mine(&job.pow_hash(nonce)[..]) {
+				jobs.submit_solution(job.job_id, nonce, proof);
+			}
+			nonce = nonce.wrapping_add(1);
+			graphs += 1;
+
+			let now = time::get_time().sec;
+			if now - last_stats >= STATS_INTERVAL {
+				let graph_rate = graphs as f64 / (now - last_stats) as f64;
+				info!(LOGGER, "Mining at {} graphs per second", graph_rate);
+				jobs.report_graph_rate(graph_rate);
+				graphs = 0;
+				last_stats = now;
+			}
+			if let Some(ms) = self.slow_down_in_millis {
+				if ms > 0 {
+					thread::sleep(Duration::from_millis(ms));
+				}
+			}
+
+			match jobs.get_job() {
+				Some(latest) => if latest.job_id != job.job_id {
+					nonce = latest.header.nonce;
+					job = latest;
+				},
+				None => return,
+			}
+		}
+	}
+}
+
+/// Serializer that outputs pre and post nonce portions of a block header
+/// which can then be sent off to miner to mutate at will
+pub struct HeaderPartWriter {
+	// Header bytes before the nonce, fixed length
+	pub pre_nonce: Vec<u8>,
+	// Post nonce is currently variable length
+	// because of difficulty
+	pub post_nonce: Vec<u8>,
+	// which difficulty field we're on
+	bytes_written: usize,
+	writing_pre: bool,
+}
+
+impl Default for HeaderPartWriter {
+	fn default() -> HeaderPartWriter {
+		HeaderPartWriter {
+			bytes_written: 0,
+			writing_pre: true,
+			pre_nonce: Vec::new(),
+			post_nonce: Vec::new(),
+		}
+	}
+}
+
+impl HeaderPartWriter {
+	/// Hex of the pre and post nonce portions of the header
+	pub fn parts_as_hex_strings(&self) -> (String, String) {
+		(
+			util::to_hex(self.pre_nonce.clone()),
+			util::to_hex(self.post_nonce.clone()),
+		)
+	}
+}
+
+impl ser::Writer for HeaderPartWriter {
+	fn serialization_mode(&self) -> ser::SerializationMode {
+		ser::SerializationMode::Hash
+	}
+
+	fn write_fixed_bytes<T: AsFixedBytes>(&mut self, bytes_in: &T) -> Result<(), ser::Error> {
+		if self.writing_pre {
+			for i in 0..bytes_in.len() {
+				self.pre_nonce.push(bytes_in.as_ref()[i])
+			}
+		} else if self.bytes_written != 0 {
+			for i in 0..bytes_in.len() {
+				self.post_nonce.push(bytes_in.as_ref()[i])
+			}
+		}
+
+		self.bytes_written += bytes_in.len();
+
+		if self.bytes_written == PRE_NONCE_SIZE && self.writing_pre {
+			self.writing_pre = false;
+			self.bytes_written = 0;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::sync::Mutex;
+
+	// finds a proof every few attempts, whatever the header
+	struct EveryFewWorker {
+		attempts: u32,
+	}
+
+	impl MiningWorker for EveryFewWorker {
+		fn new(_ease: u32, _sizeshift: u32, _proof_size: usize) -> EveryFewWorker {
+			EveryFewWorker { attempts: 0 }
+		}
+
+		fn mine(&mut self, _header: &[u8]) -> Result<Proof, cuckoo::Error> {
+			self.attempts += 1;
+			if self.attempts % 3 == 0 {
+				Ok(Proof::new(vec![self.attempts]))
+			} else {
+				Err(cuckoo::Error::NoSolution)
+			}
+		}
+	}
+
+	// serves its jobs in turn, moving to the next one once a solution is
+	// submitted for the current one, and stops after the last one
+	struct QueuedJobs {
+		jobs: Mutex<Vec<MiningJob>>,
+		solutions: Mutex<Vec<(u64, u64)>>,
+	}
+
+	impl JobProvider for QueuedJobs {
+		fn get_job(&self) -> Option<MiningJob> {
+			self.jobs.lock().unwrap().first().cloned()
+		}
+
+		fn submit_solution(&self, job_id: u64, nonce: u64, _proof: Proof) {
+			self.solutions.lock().unwrap().push((job_id, nonce));
+			let mut jobs = self.jobs.lock().unwrap();
+			if jobs.first().map(|j| j.job_id) == Some(job_id) {
+				jobs.remove(0);
+			}
+		}
+	}
+
+	fn job(job_id: u64, nonce: u64) -> MiningJob {
+		let mut header = BlockHeader::default();
+		header.nonce = nonce;
+		MiningJob {
+			job_id: job_id,
+			header: header,
+		}
+	}
+
+	#[test]
+	fn worker_plugin_jobs() {
+		let jobs = Arc::new(QueuedJobs {
+			jobs: Mutex::new(vec![job(1, 10), job(2, 100)]),
+			solutions: Mutex::new(vec![]),
+		});
+		let mut plugin = WorkerPlugin::new("test".to_string(), EveryFewWorker::new(0, 0, 0), None);
+		assert_eq!(plugin.name(), "test");
+
+		// consecutive nonces from the job header one, starting over with the
+		// next job, until there's none left
+		plugin.run(jobs.clone());
+		assert_eq!(*jobs.solutions.lock().unwrap(), vec![(1, 12), (2, 102)]);
+		assert!(jobs.get_job().is_none());
+	}
+
+	#[test]
+	fn header_parts() {
+		let job = job(1, 10);
+		let (pre, post) = job.header_parts();
+		assert_eq!(pre.len(), PRE_NONCE_SIZE * 2);
+		// the nonce isn't part of either
+		let mut other = job.clone();
+		other.header.nonce = 11;
+		assert_eq!(other.header_parts(), (pre, post));
+	}
+}
@@ -18,6 +18,11 @@
 //! with cuckoo-miner at present
 
 use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use time;
 
 use cuckoo;
 use cuckoo::Error;
@@ -25,11 +30,10 @@ use MiningWorker;
 use core::global;
 
 use core::core::Proof;
+use mining::{JobProvider, MiningPlugin};
 use types::MinerConfig;
 use util::LOGGER;
 
-use std::sync::Mutex;
-
 use cuckoo_miner::{CuckooMiner, CuckooMinerConfig, CuckooMinerDeviceStats, CuckooMinerError,
                    CuckooMinerSolution, CuckooPluginManager};
 
@@ -216,3 +220,110 @@ impl MiningWorker for PluginMiner {
 		Err(Error::NoSolution)
 	}
 }
+
+/// Runs the cuckoo-miner plugins in async mode, the plugins iterating over
+/// nonces themselves, possibly on several devices in parallel.
+pub struct AsyncPluginMiner {
+	plugin_miner: PluginMiner,
+}
+
+impl AsyncPluginMiner {
+	/// Runs the provided (initialized) plugin miner in async mode
+	pub fn new(plugin_miner: PluginMiner) -> AsyncPluginMiner {
+		AsyncPluginMiner {
+			plugin_miner: plugin_miner,
+		}
+	}
+}
+
+impl MiningPlugin for AsyncPluginMiner {
+	fn name(&self) -> String {
+		format!("cuckoo-miner cuckoo{} (async)", global::sizeshift())
+	}
+
+	fn run(&mut self, jobs: Arc<JobProvider>) {
+		// how often to output stats
+		let stat_output_interval = 2;
+		let mut next_stat_output = time::get_time().sec + stat_output_interval;
+
+		let mut job = match jobs.get_job() {
+			Some(job) => job,
+			None => return,
+		};
+		loop {
+			// Start the miner working on the job
+			let (pre, post) = job.header_parts();
+			let miner = self.plugin_miner.get_consumable();
+			let job_handle = match miner.notify(1, &pre, &post, 0) {
+				Ok(handle) => handle,
+				Err(e) => {
+					// tried again with the next job
+					error!(LOGGER, "Mining: plugins failed to start job {}: {:?}", job.job_id, e);
+					thread::sleep(Duration::from_secs(1));
+					job = match jobs.get_job() {
+						Some(job) => job,
+						None => return,
+					};
+					continue;
+				}
+			};
+
+			loop {
+				if let Some(s) = job_handle.get_solution() {
+					let proof = Proof::new(s.solution_nonces.to_vec());
+					jobs.submit_solution(job.job_id, s.get_nonce_as_u64(), proof);
+				}
+				if time::get_time().sec > next_stat_output {
+					let mut sps_total = 0.0;
+					for i in 0..self.plugin_miner.loaded_plugin_count() {
+						let stats = job_handle.get_stats(i);
+						if let Ok(stat_vec) = stats {
+							for s in stat_vec {
+								if s.in_use == 0 {continue;}
+								let last_solution_time_secs = s.last_solution_time as f64 / 1000000000.0;
+								let last_hashes_per_sec = 1.0 / last_solution_time_secs;
+								let status = match s.has_errored {
+									0 => "OK",
+									_ => "ERRORED",
+								};
+								debug!(
+									LOGGER,
+									"Mining: Plugin {} - Device {} ({}) Status: {} : Last Graph time: {}s; \
+									 Graphs per second: {:.*} - Total Attempts: {}",
+									i,
+									s.device_id,
+									s.device_name,
+									status,
+									last_solution_time_secs,
+									3,
+									last_hashes_per_sec,
+									s.iterations_completed
+								);
+								if last_hashes_per_sec.is_finite() {
+									sps_total += last_hashes_per_sec;
+								}
+							}
+						}
+					}
+					info!(LOGGER, "Mining at {} graphs per second", sps_total);
+					jobs.report_graph_rate(sps_total);
+					next_stat_output = time::get_time().sec + stat_output_interval;
+				}
+				// avoid busy wait
+				thread::sleep(Duration::from_millis(100));
+
+				match jobs.get_job() {
+					Some(latest) => if latest.job_id != job.job_id {
+						job = latest;
+						break;
+					},
+					None => {
+						job_handle.stop_jobs();
+						return;
+					}
+				}
+			}
+			job_handle.stop_jobs();
+		}
+	}
+}
@@ -59,6 +59,18 @@ pub struct MinerConfig {
 	/// Base address to the HTTP wallet receiver
 	pub wallet_listener_url: String,
 
+	/// File holding the secret of the wallet listener foreign API, if it
+	/// requires one
+	#[serde(default)]
+	pub wallet_listener_secret_path: Option<String>,
+
+	/// File holding the keys of a wallet account (grin wallet coinbase_key)
+	/// to build the coinbase outputs with, instead of contacting the wallet
+	/// receiver. Coinbases pay to the same key until a block paying to it is
+	/// accepted, its index kept in the same file name ending with `.index`.
+	#[serde(default)]
+	pub coinbase_key_path: Option<String>,
+
 	/// Attributes the reward to a random private key instead of contacting the
 	/// wallet receiver. Mostly used for tests.
 	pub burn_reward: bool,
@@ -77,6 +89,8 @@ impl Default for MinerConfig {
 			cuckoo_miner_plugin_dir: None,
 			cuckoo_miner_plugin_config: None,
 			wallet_listener_url: "http://localhost:13415".to_string(),
+			wallet_listener_secret_path: None,
+			coinbase_key_path: None,
 			burn_reward: false,
 			slow_down_in_millis: Some(0),
 			attempt_time_per_block: 2,
@@ -0,0 +1,372 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Multi-node network simulations. Runs several servers in the same process,
+//! each with its own data directory, ports and event loop, all connected to
+//! each other. The links between nodes go through a virtual network where
+//! latency, lost messages and partitions can be set while the simulation
+//! runs. Blocks are mined on demand through the mining API of a node, so
+//! simulations control exactly which node finds which block, and can then
+//! wait for the network to converge.
+
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+#![warn(missing_docs)]
+
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_grin as grin;
+extern crate grin_p2p as p2p;
+extern crate grin_pow as pow;
+extern crate grin_util as util;
+extern crate tokio_core;
+extern crate tokio_timer;
+
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use tokio_core::reactor;
+use tokio_timer::Timer;
+
+use core::consensus;
+use core::core::Block;
+use core::global::{self, ChainTypes};
+use core::ser;
+use pow::MiningWorker;
+use pow::cuckoo;
+
+/// Configuration of a simulated network.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+	/// Name of the simulation, the data of its nodes going under
+	/// target/tmp/simnet/<name>
+	pub name: String,
+	/// Number of nodes
+	pub nodes: usize,
+	/// API port of the first node, the next nodes using the following ports
+	pub base_api_port: u16,
+	/// P2P port of the first node, the next nodes using the following ports
+	pub base_p2p_port: u16,
+}
+
+/// A server running in the simulation.
+pub struct Node {
+	/// Position of the node in the network
+	pub index: usize,
+	/// Address the node p2p server listens on, which its peers know it by
+	pub p2p_addr: SocketAddr,
+	/// Base URL of the node API
+	pub api_url: String,
+	api_secret: String,
+	stats: grin::StatsReader,
+	stop: Arc<AtomicBool>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Node {
+	// Starts a server with the provided configuration on its own thread,
+	// running its event loop until the node is dropped.
+	fn start(index: usize, config: grin::ServerConfig) -> Result<Node, String> {
+		let p2p_addr = SocketAddr::new(
+			"127.0.0.1".parse().unwrap(),
+			config.p2p_config.port,
+		);
+		let api_url = format!("http://{}", config.api_http_addr);
+		let api_secret_path = config.owner_api_secret_path();
+
+		let (tx, rx) = mpsc::channel();
+		let stop = Arc::new(AtomicBool::new(false));
+		let stop_inner = stop.clone();
+		let thread = thread::Builder::new()
+			.name(format!("simnet_node_{}", index))
+			.spawn(move || {
+				let mut evtlp = reactor::Core::new().unwrap();
+				let server = match grin::Server::future(config, &evtlp.handle()) {
+					Ok(server) => server,
+					Err(e) => {
+						let _ = tx.send(Err(format!("{:?}", e)));
+						return;
+					}
+				};
+				let _ = tx.send(Ok(server.stats_reader()));
+
+				let running = Timer::default()
+					.interval(Duration::from_millis(100))
+					.take_while(move |_| Ok(!stop_inner.load(Ordering::Relaxed)))
+					.for_each(|_| Ok(()))
+					.map_err(|_| ());
+				let _ = evtlp.run(running);
+
+				// a little longer for the goodbyes to reach the other nodes
+				server.stop();
+				let flush = Timer::default()
+					.sleep(Duration::from_millis(500))
+					.map_err(|_| ());
+				let _ = evtlp.run(flush);
+			})
+			.map_err(|e| format!("{:?}", e))?;
+
+		let stats = rx.recv()
+			.map_err(|_| format!("node {} failed to start", index))??;
+		// generated by the node when starting
+		let api_secret = api::read_api_secret(&api_secret_path).map_err(|e| format!("{:?}", e))?;
+		Ok(Node {
+			index: index,
+			p2p_addr: p2p_addr,
+			api_url: api_url,
+			api_secret: api_secret,
+			stats: stats,
+			stop: stop,
+			thread: Some(thread),
+		})
+	}
+
+	/// Current stats of the node.
+	pub fn stats(&self) -> grin::ServerStats {
+		self.stats.get_server_stats()
+	}
+
+	/// Chain head of the node.
+	pub fn head(&self) -> chain::Tip {
+		self.stats().head
+	}
+
+	/// Number of messages of the provided type (i.e. "Block") the node
+	/// received from all its peers so far.
+	pub fn received_msgs(&self, msg_type: &str) -> u64 {
+		self.stats()
+			.msg_stats
+			.iter()
+			.find(|s| s.msg_type == msg_type)
+			.map_or(0, |s| s.received_msgs)
+	}
+
+	/// Conditions of the links between the node and its peers.
+	pub fn conditions(&self) -> Arc<p2p::NetworkConditions> {
+		self.stats.network_conditions()
+	}
+
+	/// Mines a block on top of the node chain head: gets a block template
+	/// from the node, finds a proof of work for it and submits it back. The
+	/// node processes and broadcasts the block like any block it mines.
+	pub fn mine_block(&self) -> Result<api::BlockHeaderInfo, String> {
+		// no templates while the node is syncing, which it may do for a bit
+		// after starting or getting back in touch with other nodes
+		let url = format!("{}/v1/mining/template", self.api_url);
+		let mut template = None;
+		wait_until(Duration::from_secs(10), "a block template", || {
+			template =
+				api::client::get_with_secret::<api::BlockTemplate>(&url, Some(self.api_secret.clone()))
+					.ok();
+			template.is_some()
+		})?;
+		let template = template.unwrap();
+
+		let data = util::from_hex(template.block).map_err(|e| format!("{:?}", e))?;
+		let mut b: Block = ser::deserialize(&mut &data[..]).map_err(|e| format!("{:?}", e))?;
+		let sizeshift = global::sizeshift() as u32;
+		let mut miner = cuckoo::Miner::new(consensus::EASINESS, sizeshift, global::proofsize());
+		let difficulty = b.header.difficulty.clone();
+		pow::pow_size(&mut miner, &mut b.header, difficulty, sizeshift)
+			.map_err(|e| format!("{:?}", e))?;
+
+		let submission = api::BlockSubmission {
+			template_id: None,
+			nonce: None,
+			pow: None,
+			block: Some(util::to_hex(ser::ser_vec(&b).unwrap())),
+		};
+		api::client::post_json_with_secret(
+			&format!("{}/v1/mining/submit", self.api_url),
+			&submission,
+			Some(self.api_secret.clone()),
+		).map_err(|e| format!("{:?}", e))
+	}
+}
+
+impl Drop for Node {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Simulated network of nodes all connected to each other. The nodes stop
+/// when the network is dropped.
+pub struct Network {
+	nodes: Vec<Node>,
+}
+
+impl Network {
+	/// Starts the nodes of the network, from a clean state. Each node has the
+	/// ones started before it as seeds, so they all end up connected.
+	pub fn start(config: NetworkConfig) -> Result<Network, String> {
+		global::set_mining_mode(ChainTypes::AutomatedTesting);
+		let dir = format!("target/tmp/simnet/{}", config.name);
+		let _ = fs::remove_dir_all(&dir);
+
+		let mut nodes: Vec<Node> = vec![];
+		for n in 0..config.nodes {
+			let seeds = nodes
+				.iter()
+				.map(|node| node.p2p_addr.to_string())
+				.collect::<Vec<_>>();
+			let server_config = grin::ServerConfig {
+				db_root: format!("{}/node{}", dir, n),
+				api_http_addr: format!("127.0.0.1:{}", config.base_api_port + n as u16),
+				p2p_config: p2p::P2PConfig {
+					port: config.base_p2p_port + n as u16,
+					..p2p::P2PConfig::default()
+				},
+				seeding_type: if seeds.is_empty() {
+					grin::Seeding::None
+				} else {
+					grin::Seeding::List
+				},
+				seeds: Some(seeds),
+				chain_type: ChainTypes::AutomatedTesting,
+				mining_config: Some(pow::types::MinerConfig {
+					enable_mining: false,
+					burn_reward: true,
+					..Default::default()
+				}),
+				skip_sync_wait: Some(true),
+				..Default::default()
+			};
+			nodes.push(Node::start(n, server_config)?);
+		}
+		Ok(Network { nodes: nodes })
+	}
+
+	/// The node at the provided position.
+	pub fn node(&self, n: usize) -> &Node {
+		&self.nodes[n]
+	}
+
+	/// All the nodes of the network.
+	pub fn nodes(&self) -> &[Node] {
+		&self.nodes
+	}
+
+	/// Updates the conditions of the link between the 2 provided nodes, in
+	/// both directions.
+	pub fn update_link<F>(&self, a: usize, b: usize, update: F)
+	where
+		F: Fn(&mut p2p::LinkConditions),
+	{
+		for &(from, to) in [(a, b), (b, a)].iter() {
+			let conditions = self.nodes[from].conditions();
+			let peer_addr = self.nodes[to].p2p_addr;
+			let mut link = conditions.link(&peer_addr);
+			update(&mut link);
+			conditions.set_link(peer_addr, link);
+		}
+	}
+
+	/// Delays the messages exchanged by the 2 provided nodes.
+	pub fn set_latency(&self, a: usize, b: usize, latency: Duration) {
+		self.update_link(a, b, |link| link.latency = Some(latency));
+	}
+
+	/// Loses a share, between 0 and 1, of the messages exchanged by the 2
+	/// provided nodes.
+	pub fn set_drop_rate(&self, a: usize, b: usize, drop_rate: f64) {
+		self.update_link(a, b, |link| link.drop_rate = drop_rate);
+	}
+
+	/// Splits the network in 2, the provided nodes on one side and all the
+	/// others on the other side, no messages going through.
+	pub fn partition(&self, side: &[usize]) {
+		for a in side {
+			for b in (0..self.nodes.len()).filter(|b| !side.contains(b)) {
+				self.update_link(*a, b, |link| link.partitioned = true);
+			}
+		}
+	}
+
+	/// Removes all partitions, latency and losses.
+	pub fn heal(&self) {
+		for node in &self.nodes {
+			node.conditions().clear();
+		}
+	}
+
+	/// Chain heads of all the nodes.
+	pub fn heads(&self) -> Vec<chain::Tip> {
+		self.nodes.iter().map(|node| node.head()).collect()
+	}
+
+	/// Waits for each node to be connected to at least the provided number of
+	/// peers.
+	pub fn wait_for_peers(&self, count: u32, timeout: Duration) -> Result<(), String> {
+		wait_until(timeout, "peer connections", || {
+			self.nodes.iter().all(|node| node.stats().peer_count >= count)
+		})
+	}
+
+	/// Waits for the provided nodes to reach at least the provided height.
+	pub fn wait_for_height(
+		&self,
+		nodes: &[usize],
+		height: u64,
+		timeout: Duration,
+	) -> Result<(), String> {
+		wait_until(timeout, &format!("height {}", height), || {
+			nodes.iter().all(|n| self.nodes[*n].head().height >= height)
+		})
+	}
+
+	/// Waits for all the nodes to agree on the same chain head, returning it.
+	pub fn wait_for_convergence(&self, timeout: Duration) -> Result<chain::Tip, String> {
+		let mut heads = vec![];
+		let res = wait_until(timeout, "convergence", || {
+			heads = self.heads();
+			heads
+				.iter()
+				.all(|h| h.last_block_h == heads[0].last_block_h)
+		});
+		if let Err(e) = res {
+			let heights = heads.iter().map(|h| h.height).collect::<Vec<_>>();
+			return Err(format!("{}, node heights {:?}", e, heights));
+		}
+		Ok(heads[0].clone())
+	}
+}
+
+// Polls the provided condition until it holds or the timeout expires.
+fn wait_until<F>(timeout: Duration, what: &str, mut done: F) -> Result<(), String>
+where
+	F: FnMut() -> bool,
+{
+	let start = Instant::now();
+	while !done() {
+		if start.elapsed() > timeout {
+			return Err(format!("timed out waiting for {}", what));
+		}
+		thread::sleep(Duration::from_millis(100));
+	}
+	Ok(())
+}
@@ -0,0 +1,89 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_simnet as simnet;
+extern crate grin_util as util;
+
+use std::time::Duration;
+
+use simnet::{Network, NetworkConfig};
+
+/// Blocks mined on a node reach all the others, even over slow links and a
+/// lossy one, the third node relaying what gets lost.
+#[test]
+fn converge_over_slow_and_lossy_links() {
+	util::init_test_logger();
+	let net = Network::start(NetworkConfig {
+		name: "slow_and_lossy".to_string(),
+		nodes: 3,
+		base_api_port: 34000,
+		base_p2p_port: 35000,
+	}).unwrap();
+	net.wait_for_peers(2, Duration::from_secs(30)).unwrap();
+
+	net.set_latency(0, 2, Duration::from_millis(300));
+	net.set_latency(1, 2, Duration::from_millis(300));
+	net.set_drop_rate(0, 1, 0.5);
+	for _ in 0..3 {
+		net.node(0).mine_block().unwrap();
+	}
+
+	let head = net.wait_for_convergence(Duration::from_secs(60)).unwrap();
+	assert_eq!(head.height, 3);
+	assert_eq!(head.last_block_h, net.node(0).head().last_block_h);
+	// the other nodes got the new blocks relayed to them
+	for n in 1..3 {
+		let node = net.node(n);
+		let relayed = ["Header", "CompactBlock", "Block"]
+			.iter()
+			.map(|t| node.received_msgs(t))
+			.sum::<u64>();
+		assert!(relayed > 0);
+	}
+}
+
+/// Both sides of a partition keep mining their own chain, the network
+/// settling on the one with the most work once healed.
+#[test]
+fn partition_and_heal() {
+	util::init_test_logger();
+	let net = Network::start(NetworkConfig {
+		name: "partition_and_heal".to_string(),
+		nodes: 4,
+		base_api_port: 34100,
+		base_p2p_port: 35100,
+	}).unwrap();
+	net.wait_for_peers(3, Duration::from_secs(30)).unwrap();
+
+	net.partition(&[0, 1]);
+	net.node(0).mine_block().unwrap();
+	net.node(0).mine_block().unwrap();
+	net.node(2).mine_block().unwrap();
+	net.wait_for_height(&[0, 1], 2, Duration::from_secs(30)).unwrap();
+	net.wait_for_height(&[2, 3], 1, Duration::from_secs(30)).unwrap();
+
+	// nothing crossed the partition
+	let heads = net.heads();
+	assert_eq!(heads[0].last_block_h, heads[1].last_block_h);
+	assert_eq!(heads[2].last_block_h, heads[3].last_block_h);
+	assert!(heads[0].last_block_h != heads[2].last_block_h);
+	assert_eq!(heads[2].height, 1);
+
+	// the longest chain wins once the partition is gone
+	net.heal();
+	net.node(0).mine_block().unwrap();
+	let head = net.wait_for_convergence(Duration::from_secs(90)).unwrap();
+	assert_eq!(head.height, 3);
+	assert_eq!(head.last_block_h, net.node(0).head().last_block_h);
+}
@@ -14,9 +14,13 @@
 
 extern crate term;
 
-use std::net::SocketAddr;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use api;
 use grin::ServerConfig;
+use p2p;
+use serde_json;
 
 pub fn show_status(config: &ServerConfig) {
 	println!();
@@ -35,7 +39,24 @@ pub fn show_status(config: &ServerConfig) {
 			writeln!(e, "Chain height: {}", status.tip.height).unwrap();
 			writeln!(e, "Last block hash: {}", status.tip.last_block_pushed).unwrap();
 			writeln!(e, "Previous block hash: {}", status.tip.prev_block_to_last).unwrap();
-			writeln!(e, "Total difficulty: {}", status.tip.total_difficulty).unwrap()
+			writeln!(e, "Total difficulty: {}", status.tip.total_difficulty).unwrap();
+			if let Some(sync_status) = status.sync_status {
+				writeln!(e, "Sync status: {}", sync_status).unwrap();
+			}
+			if let Some(fee_base) = status.accept_fee_base {
+				writeln!(e, "Minimum relay fee per weight: {}", fee_base).unwrap();
+			}
+			if let Some(reorg) = status.pending_reorg {
+				e.fg(term::color::RED).unwrap();
+				writeln!(
+					e,
+					"WARNING: refused a reorg of depth {} to fork {} at height {}, \
+					 run `grin client accept-reorg` if legitimate",
+					reorg.depth,
+					reorg.tip.last_block_pushed,
+					reorg.tip.height
+				).unwrap();
+			}
 		}
 		Err(_) => writeln!(
 			e,
@@ -49,12 +70,9 @@ pub fn show_status(config: &ServerConfig) {
 pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr) {
 	let params = "";
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/ban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	match api::client::post(url.as_str(), &params).map_err(|e| Error::API(e)) {
+	let url = api_url(config, &format!("peers/{}/ban", peer_addr.to_string()));
+	let res = api::client::post_with_secret(url.as_str(), &params, api_secret(config));
+	match res.map_err(|e| Error::API(e)) {
 		Ok(_) => writeln!(e, "Successfully banned peer {}", peer_addr.to_string()).unwrap(),
 		Err(_) => writeln!(e, "Failed to ban peer {}", peer_addr).unwrap(),
 	};
@@ -64,20 +82,244 @@ pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr) {
 pub fn unban_peer(config: &ServerConfig, peer_addr: &SocketAddr) {
 	let params = "";
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/unban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	match api::client::post(url.as_str(), &params).map_err(|e| Error::API(e)) {
+	let url = api_url(config, &format!("peers/{}/unban", peer_addr.to_string()));
+	let res = api::client::post_with_secret(url.as_str(), &params, api_secret(config));
+	match res.map_err(|e| Error::API(e)) {
 		Ok(_) => writeln!(e, "Successfully unbanned peer {}", peer_addr).unwrap(),
 		Err(_) => writeln!(e, "Failed to unban peer {}", peer_addr).unwrap(),
 	};
 }
 
+pub fn accept_reorg(config: &ServerConfig) {
+	let params = "";
+	let mut e = term::stdout().unwrap();
+	let url = api_url(config, "chain/reorg/accept");
+	let res = api::client::post_with_secret(url.as_str(), &params, api_secret(config));
+	match res.map_err(|e| Error::API(e)) {
+		Ok(_) => writeln!(e, "Reorg accepted").unwrap(),
+		Err(_) => writeln!(e, "Failed to accept reorg, is there one pending?").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn reload_config(config: &ServerConfig) {
+	let params = "";
+	let mut e = term::stdout().unwrap();
+	let url = api_url(config, "config/reload");
+	let res = api::client::post_with_secret(url.as_str(), &params, api_secret(config));
+	match res.map_err(|e| Error::API(e)) {
+		Ok(_) => writeln!(e, "Configuration reloaded").unwrap(),
+		Err(_) => writeln!(e, "Failed to reload the configuration, see the server logs").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+/// Prints the peers the node is connected to, or all the peers it knows.
+pub fn list_peers(config: &ServerConfig, all: bool) {
+	let mut e = term::stdout().unwrap();
+	if all {
+		match get_all_peers(config) {
+			Ok(peers) => {
+				writeln!(e, "{:<24} {:<10} {}", "Address", "State", "User Agent").unwrap();
+				for p in &peers {
+					writeln!(e, "{:<24} {:<10} {}", p.addr, format!("{:?}", p.flags), p.user_agent)
+						.unwrap();
+				}
+				writeln!(e, "{} known peers", peers.len()).unwrap();
+			}
+			Err(_) => writeln!(e, "Failed to get the known peers").unwrap(),
+		}
+	} else {
+		match get_connected_peers(config) {
+			Ok(peers) => {
+				writeln!(
+					e,
+					"{:<24} {:<10} {:<10} {:<18} {}",
+					"Address", "Direction", "Height", "Total Difficulty", "User Agent"
+				).unwrap();
+				for p in &peers {
+					writeln!(
+						e,
+						"{:<24} {:<10} {:<10} {:<18} {}",
+						p.addr,
+						format!("{:?}", p.direction),
+						p.height,
+						p.total_difficulty,
+						p.user_agent
+					).unwrap();
+				}
+				writeln!(e, "{} connected peers", peers.len()).unwrap();
+			}
+			Err(_) => writeln!(e, "Failed to get the connected peers").unwrap(),
+		}
+	}
+	e.reset().unwrap();
+}
+
+/// Prints the details of all the peers the node is connected to.
+pub fn list_connected_peers(config: &ServerConfig) {
+	let mut e = term::stdout().unwrap();
+	match get_connected_peers(config) {
+		Ok(peers) => {
+			for (index, p) in peers.iter().enumerate() {
+				writeln!(e, "Peer {}:", index).unwrap();
+				writeln!(e, "Address: {}", p.addr).unwrap();
+				writeln!(e, "Direction: {:?}", p.direction).unwrap();
+				writeln!(e, "User agent: {}", p.user_agent).unwrap();
+				writeln!(e, "Version: {}", p.version).unwrap();
+				writeln!(e, "Capabilities: {:?}", p.capabilities).unwrap();
+				writeln!(e, "Height: {}", p.height).unwrap();
+				writeln!(e, "Total difficulty: {}", p.total_difficulty).unwrap();
+				writeln!(e, "Connected for: {}s", p.metrics.connected_secs).unwrap();
+				writeln!(e, "Reconnects: {}", p.metrics.reconnects).unwrap();
+				writeln!(
+					e,
+					"Received: {} blocks, {} txs",
+					p.metrics.blocks_received, p.metrics.txs_received
+				).unwrap();
+				writeln!(e, "Last activity: {}", p.metrics.last_activity).unwrap();
+				if p.outdated {
+					writeln!(e, "Outdated: yes").unwrap();
+				}
+				println!();
+			}
+			writeln!(e, "{} connected peers", peers.len()).unwrap();
+		}
+		Err(_) => writeln!(
+			e,
+			"WARNING: Client failed to get data. Is your `grin server` offline or broken?"
+		).unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+/// Writes all the peers known by the node to the provided file, as JSON or
+/// as CSV when the file name ends with ".csv".
+pub fn dump_peers(config: &ServerConfig, path: &str) {
+	let mut e = term::stdout().unwrap();
+	let peers = match get_all_peers(config) {
+		Ok(peers) => peers,
+		Err(_) => {
+			writeln!(e, "Failed to get the known peers").unwrap();
+			return;
+		}
+	};
+	let res = File::create(path).and_then(|mut f| {
+		let content = if is_csv(path) {
+			p2p::peer_list::to_csv(&peers)
+		} else {
+			serde_json::to_string_pretty(&peers).unwrap()
+		};
+		f.write_all(content.as_bytes())
+	});
+	match res {
+		Ok(_) => writeln!(e, "Dumped {} peers to {}", peers.len(), path).unwrap(),
+		Err(err) => writeln!(e, "Failed to write {}: {}", path, err).unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+/// Adds the peers of a file written by dump_peers, JSON or CSV, to the ones
+/// the node knows. The node skips the banned ones and the ones it already
+/// knows.
+pub fn import_peers(config: &ServerConfig, path: &str) {
+	let mut e = term::stdout().unwrap();
+	let mut content = String::new();
+	if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+		writeln!(e, "Failed to read {}: {}", path, err).unwrap();
+		return;
+	}
+	let parsed = if is_csv(path) {
+		p2p::peer_list::from_csv(&content)
+	} else {
+		serde_json::from_str::<Vec<p2p::PeerData>>(&content).map_err(|e| e.to_string())
+	};
+	let peers = match parsed {
+		Ok(peers) => peers,
+		Err(err) => {
+			writeln!(e, "Invalid peer list in {}: {}", path, err).unwrap();
+			return;
+		}
+	};
+
+	let url = api_url(config, "peers/import");
+	let res = api::client::post_json_with_secret::<_, usize>(
+		url.as_str(),
+		&peers,
+		api_secret(config),
+	);
+	match res.map_err(|e| Error::API(e)) {
+		Ok(added) => writeln!(
+			e,
+			"Imported {} new peers out of {} in {}",
+			added,
+			peers.len(),
+			path
+		).unwrap(),
+		Err(_) => writeln!(e, "Failed to import the peers").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+fn is_csv(path: &str) -> bool {
+	path.to_lowercase().ends_with(".csv")
+}
+
+fn get_connected_peers(config: &ServerConfig) -> Result<Vec<p2p::PeerInfo>, Error> {
+	let url = api_url(config, "peers/connected");
+	api::client::get_with_secret::<Vec<p2p::PeerInfo>>(url.as_str(), api_secret(config))
+		.map_err(|e| Error::API(e))
+}
+
+fn get_all_peers(config: &ServerConfig) -> Result<Vec<p2p::PeerData>, Error> {
+	let url = api_url(config, "peers/all");
+	api::client::get_with_secret::<Vec<p2p::PeerData>>(url.as_str(), api_secret(config))
+		.map_err(|e| Error::API(e))
+}
+
 fn get_status_from_node(config: &ServerConfig) -> Result<api::Status, Error> {
-	let url = format!("http://{}/v1/status", config.api_http_addr);
-	api::client::get::<api::Status>(url.as_str()).map_err(|e| Error::API(e))
+	let url = api_url(config, "status");
+	api::client::get_with_secret::<api::Status>(url.as_str(), api_secret(config))
+		.map_err(|e| Error::API(e))
+}
+
+// Secret to authenticate against the API with, the owner one gives access to
+// all endpoints
+fn api_secret(config: &ServerConfig) -> Option<String> {
+	match api::read_api_secret(&config.owner_api_secret_path()) {
+		Ok(secret) => Some(secret),
+		Err(e) => {
+			println!("Could not read the API secret: {}", e);
+			None
+		}
+	}
+}
+
+// Full URL of an API endpoint, over https when the API is served over TLS
+fn api_url(config: &ServerConfig, path: &str) -> String {
+	let scheme = match config.api_tls_config {
+		Some(_) => "https",
+		None => "http",
+	};
+	format!("{}://{}/v1/{}", scheme, api_connect_addr(&config.api_http_addr), path)
+}
+
+// Address to connect to the API at, the loopback one when the API listens on
+// all interfaces, as the unspecified address isn't one we can connect to
+fn api_connect_addr(listen_addr: &str) -> String {
+	match listen_addr.parse::<SocketAddr>() {
+		Ok(mut addr) => {
+			if addr.ip().is_unspecified() {
+				let loopback = match addr.ip() {
+					IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+					IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+				};
+				addr.set_ip(loopback);
+			}
+			addr.to_string()
+		}
+		Err(_) => listen_addr.to_string(),
+	}
 }
 
 /// Error type wrapping underlying module errors.
@@ -86,3 +328,16 @@ enum Error {
 	/// Error originating from HTTP API calls.
 	API(api::Error),
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn connect_addr() {
+		assert_eq!(api_connect_addr("0.0.0.0:13413"), "127.0.0.1:13413");
+		assert_eq!(api_connect_addr("[::]:13413"), "[::1]:13413");
+		assert_eq!(api_connect_addr("192.168.1.2:13413"), "192.168.1.2:13413");
+		assert_eq!(api_connect_addr("localhost:13413"), "localhost:13413");
+	}
+}
@@ -0,0 +1,329 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs the Grin server in the background. On unix the process detaches
+//! itself from the console (classic daemon), on Windows a detached copy of
+//! the binary is spawned in "run" mode. In both cases the pid of the
+//! background process is tracked in a pid file, used to refuse double starts
+//! and to stop the server later on.
+//!
+//! On Windows the server can also run as a service, started and stopped by
+//! the service control manager, which shuts it down gracefully.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use daemonize::Daemonize;
+
+use grin::ServerConfig;
+use util::LOGGER;
+
+/// Name of the pid file, under the db root, when none is provided
+const DEFAULT_PID_FILE: &'static str = "grin.pid";
+
+/// How long to wait for the server process to exit after asking it to stop
+const STOP_TIMEOUT_SECS: u64 = 30;
+
+/// Pid file location for the provided configuration, unless explicitly set.
+pub fn pid_file_path(config: &ServerConfig, pid_file: Option<&str>) -> PathBuf {
+	match pid_file {
+		Some(f) => PathBuf::from(f),
+		None => Path::new(&config.db_root).join(DEFAULT_PID_FILE),
+	}
+}
+
+/// Starts the server in the background, writing its pid to the pid file.
+pub fn start(config: ServerConfig, pid_file: PathBuf) -> Result<(), String> {
+	if let Some(pid) = read_pid(&pid_file) {
+		if is_running(pid) {
+			return Err(format!("Grin server already running with pid {}", pid));
+		}
+		warn!(LOGGER, "Removing stale pid file {:?} (pid {})", pid_file, pid);
+		let _ = fs::remove_file(&pid_file);
+	}
+	if let Some(parent) = pid_file.parent() {
+		fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+	}
+	detach(config, pid_file)
+}
+
+/// Stops the background server identified by the pid file, waiting for it to
+/// exit before cleaning up the pid file.
+pub fn stop(pid_file: PathBuf) -> Result<(), String> {
+	let pid = match read_pid(&pid_file) {
+		Some(pid) => pid,
+		None => {
+			return Err(format!(
+				"No pid file found at {:?}, is the server running?",
+				pid_file
+			))
+		}
+	};
+	if !is_running(pid) {
+		let _ = fs::remove_file(&pid_file);
+		return Err(format!(
+			"Grin server (pid {}) isn't running, removed stale pid file",
+			pid
+		));
+	}
+
+	terminate(pid)?;
+	for _ in 0..STOP_TIMEOUT_SECS {
+		if !is_running(pid) {
+			let _ = fs::remove_file(&pid_file);
+			return Ok(());
+		}
+		thread::sleep(Duration::from_secs(1));
+	}
+	Err(format!(
+		"Grin server (pid {}) still running after {}s",
+		pid,
+		STOP_TIMEOUT_SECS
+	))
+}
+
+fn read_pid(pid_file: &Path) -> Option<u32> {
+	let mut content = String::new();
+	match File::open(pid_file).and_then(|mut f| f.read_to_string(&mut content)) {
+		Ok(_) => content.trim().parse().ok(),
+		Err(_) => None,
+	}
+}
+
+#[cfg(unix)]
+fn detach(config: ServerConfig, pid_file: PathBuf) -> Result<(), String> {
+	let daemonize = Daemonize::new()
+		.pid_file(pid_file)
+		.chown_pid_file(true)
+		.working_directory(env::current_dir().map_err(|e| e.to_string())?)
+		.privileged_action(move || {
+			::start_server(config.clone());
+		});
+	daemonize.start().map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[cfg(windows)]
+fn detach(_config: ServerConfig, pid_file: PathBuf) -> Result<(), String> {
+	use std::io::Write;
+	use std::os::windows::process::CommandExt;
+	const DETACHED_PROCESS: u32 = 0x00000008;
+	const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+
+	// same command line, only running in the foreground of the new process
+	let args = env::args()
+		.skip(1)
+		.map(|a| if a == "start" { "run".to_string() } else { a })
+		.collect::<Vec<_>>();
+	let child = Command::new(env::current_exe().map_err(|e| e.to_string())?)
+		.args(&args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP)
+		.spawn()
+		.map_err(|e| e.to_string())?;
+
+	let mut f = File::create(&pid_file).map_err(|e| e.to_string())?;
+	write!(f, "{}", child.id()).map_err(|e| e.to_string())
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+	Command::new("kill")
+		.args(&["-0", &pid.to_string()])
+		.stderr(Stdio::null())
+		.status()
+		.map(|s| s.success())
+		.unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+	Command::new("tasklist")
+		.args(&["/NH", "/FI", &format!("PID eq {}", pid)])
+		.output()
+		.map(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+		.unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), String> {
+	let status = Command::new("kill")
+		.args(&["-TERM", &pid.to_string()])
+		.status()
+		.map_err(|e| e.to_string())?;
+	if status.success() {
+		Ok(())
+	} else {
+		Err(format!("Failed to signal pid {}", pid))
+	}
+}
+
+// Without a console or a window the detached process can't be asked to
+// close, only killed. Run the server as a service for graceful shutdowns.
+#[cfg(windows)]
+fn terminate(pid: u32) -> Result<(), String> {
+	let status = Command::new("taskkill")
+		.args(&["/F", "/PID", &pid.to_string()])
+		.status()
+		.map_err(|e| e.to_string())?;
+	if status.success() {
+		Ok(())
+	} else {
+		Err(format!("Failed to stop pid {}", pid))
+	}
+}
+
+/// Runs the server as a Windows service, registered with i.e.
+/// `sc create grin binPath= "C:\path\to\grin.exe server service"`.
+/// Returns once the service control manager stopped it.
+#[cfg(windows)]
+pub fn run_service(config: ServerConfig) -> Result<(), String> {
+	service::run(config)
+}
+
+/// Windows services only exist on Windows.
+#[cfg(not(windows))]
+pub fn run_service(_config: ServerConfig) -> Result<(), String> {
+	Err("Running as a service is only supported on Windows".to_string())
+}
+
+#[cfg(windows)]
+mod service {
+	use std::ptr;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+	use std::sync::atomic::{ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+	use std::thread;
+	use std::time::Duration;
+
+	use winapi::shared::minwindef::{DWORD, LPVOID};
+	use winapi::shared::winerror::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR};
+	use winapi::um::winnt::{LPWSTR, SERVICE_WIN32_OWN_PROCESS};
+	use winapi::um::winsvc::*;
+
+	use grin;
+	use grin::ServerConfig;
+	use util::LOGGER;
+
+	// The service entry points are called by the service control manager,
+	// without any context, so what they share goes through statics: the
+	// server configuration (boxed), the status handle and the stop request.
+	static CONFIG: AtomicUsize = ATOMIC_USIZE_INIT;
+	static STATUS_HANDLE: AtomicUsize = ATOMIC_USIZE_INIT;
+	static STOP: AtomicBool = ATOMIC_BOOL_INIT;
+
+	/// Hands the current thread over to the service control manager, which
+	/// calls back service_main on another thread to run the server.
+	pub fn run(config: ServerConfig) -> Result<(), String> {
+		CONFIG.store(Box::into_raw(Box::new(config)) as usize, Ordering::SeqCst);
+		let mut name = wide("grin");
+		let table = [
+			SERVICE_TABLE_ENTRYW {
+				lpServiceName: name.as_mut_ptr(),
+				lpServiceProc: Some(service_main),
+			},
+			SERVICE_TABLE_ENTRYW {
+				lpServiceName: ptr::null_mut(),
+				lpServiceProc: None,
+			},
+		];
+		if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+			return Err(
+				"Could not connect to the service control manager, not started as a service?"
+					.to_string(),
+			);
+		}
+		Ok(())
+	}
+
+	unsafe extern "system" fn service_main(_argc: DWORD, _argv: *mut LPWSTR) {
+		let name = wide("grin");
+		let handle =
+			RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), ptr::null_mut());
+		if handle.is_null() {
+			error!(LOGGER, "Could not register the service control handler");
+			return;
+		}
+		STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+
+		let config = CONFIG.swap(0, Ordering::SeqCst);
+		if config == 0 {
+			set_status(SERVICE_STOPPED, 0);
+			return;
+		}
+		let config = *Box::from_raw(config as *mut ServerConfig);
+
+		set_status(SERVICE_RUNNING, SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN);
+		// the same graceful shutdown as on SIGTERM, once asked to stop
+		let res = grin::Server::start_with_stats(config, |stats_reader| {
+			while !STOP.load(Ordering::SeqCst) {
+				thread::sleep(Duration::from_millis(500));
+			}
+			stats_reader.stop_server();
+		});
+		if let Err(e) = res {
+			error!(LOGGER, "Grin service failed: {:?}", e);
+		}
+		set_status(SERVICE_STOPPED, 0);
+	}
+
+	unsafe extern "system" fn control_handler(
+		control: DWORD,
+		_event_type: DWORD,
+		_event_data: LPVOID,
+		_context: LPVOID,
+	) -> DWORD {
+		match control {
+			SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+				set_status(SERVICE_STOP_PENDING, 0);
+				STOP.store(true, Ordering::SeqCst);
+				NO_ERROR
+			}
+			SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+			_ => ERROR_CALL_NOT_IMPLEMENTED,
+		}
+	}
+
+	fn set_status(state: DWORD, controls_accepted: DWORD) {
+		let mut status = SERVICE_STATUS {
+			dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+			dwCurrentState: state,
+			dwControlsAccepted: controls_accepted,
+			dwWin32ExitCode: NO_ERROR,
+			dwServiceSpecificExitCode: 0,
+			dwCheckPoint: 0,
+			// the server finishes processing its current block before exiting
+			dwWaitHint: if state == SERVICE_STOP_PENDING {
+				super::STOP_TIMEOUT_SECS as DWORD * 1000
+			} else {
+				0
+			},
+		};
+		let handle = STATUS_HANDLE.load(Ordering::SeqCst) as SERVICE_STATUS_HANDLE;
+		unsafe {
+			SetServiceStatus(handle, &mut status);
+		}
+	}
+
+	// Null terminated UTF-16, as the Windows API expects
+	fn wide(s: &str) -> Vec<u16> {
+		s.encode_utf16().chain(Some(0)).collect()
+	}
+}
@@ -16,7 +16,11 @@
 
 extern crate blake2_rfc as blake2;
 extern crate clap;
+extern crate cursive;
+#[cfg(unix)]
 extern crate daemonize;
+#[cfg(windows)]
+extern crate winapi;
 extern crate serde;
 extern crate serde_json;
 #[macro_use]
@@ -27,22 +31,24 @@ extern crate grin_config as config;
 extern crate grin_core as core;
 extern crate grin_grin as grin;
 extern crate grin_keychain as keychain;
+extern crate grin_p2p as p2p;
 extern crate grin_util as util;
 extern crate grin_wallet as wallet;
 
 mod client;
+mod daemon;
+mod setup;
+mod tui;
 
-use std::thread;
-use std::time::Duration;
-use std::env::current_dir;
+use std::fs::File;
+use std::io::{self, Read};
 
 use clap::{App, Arg, ArgMatches, SubCommand};
-use daemonize::Daemonize;
 
 use config::GlobalConfig;
 use core::global;
 use core::core::amount_to_hr_string;
-use util::{init_logger, LoggingConfig, LOGGER};
+use util::{init_logger, tracing, LoggingConfig, LOGGER};
 
 fn start_from_config_file(mut global_config: GlobalConfig) {
 	info!(
@@ -61,10 +67,22 @@ fn start_from_config_file(mut global_config: GlobalConfig) {
 			.chain_type,
 	);
 
-	grin::Server::start(global_config.members.as_mut().unwrap().server.clone()).unwrap();
-	loop {
-		thread::sleep(Duration::from_secs(60));
-	}
+	start_server(global_config.members.as_mut().unwrap().server.clone());
+}
+
+/// Runs the server in the current process, returning once it's been shut
+/// down by a SIGINT or SIGTERM.
+fn start_server(config: grin::ServerConfig) {
+	grin::Server::start(config).unwrap();
+}
+
+// Posts the traces of processed blocks to the OTLP/HTTP collector at the
+// provided endpoint.
+fn start_trace_exporter(endpoint: &str) {
+	let url = tracing::otlp_traces_url(endpoint);
+	tracing::init_exporter(move |span| {
+		api::client::post(&url, &tracing::otlp_json(span)).map_err(|e| e.to_string())
+	});
 }
 
 fn main() {
@@ -83,8 +101,21 @@ fn main() {
 	});
 
 	if global_config.using_config_file {
+		// the dashboard takes over the terminal, nothing else can write to it
+		{
+			let members = global_config.members.as_mut().unwrap();
+			if members.server.run_tui == Some(true) {
+				if let Some(ref mut logging) = members.logging {
+					logging.log_to_stdout = false;
+				}
+			}
+		}
 		// initialise the logger
-		init_logger(global_config.members.as_mut().unwrap().logging.clone());
+		let logging = global_config.members.as_mut().unwrap().logging.clone();
+		if let Some(endpoint) = logging.as_ref().and_then(|l| l.tracing_otlp_endpoint.clone()) {
+			start_trace_exporter(&endpoint);
+		}
+		init_logger(logging);
 		info!(
 			LOGGER,
 			"Using configuration file at: {}",
@@ -113,6 +144,10 @@ fn main() {
 		.author("The Grin Team")
 		.about("Lightweight implementation of the MimbleWimble protocol.")
 
+    // interactive first-run configuration
+    .subcommand(SubCommand::with_name("setup")
+                .about("Interactively create a configuration and a wallet, then start the node"))
+
     // specification of all the server commands and options
     .subcommand(SubCommand::with_name("server")
                 .about("Control the Grin server")
@@ -140,18 +175,40 @@ fn main() {
                      .long("wallet_url")
                      .help("The wallet listener to which mining rewards will be sent")
                 	.takes_value(true))
+                .arg(Arg::with_name("pid_file")
+                     .long("pid_file")
+                     .help("Pid file used when running as a daemon (defaults to grin.pid under the db root)")
+                     .takes_value(true))
                 .subcommand(SubCommand::with_name("start")
                             .about("Start the Grin server as a daemon"))
                 .subcommand(SubCommand::with_name("stop")
                             .about("Stop the Grin server daemon"))
                 .subcommand(SubCommand::with_name("run")
-                            .about("Run the Grin server in this console")))
+                            .about("Run the Grin server in this console"))
+                .subcommand(SubCommand::with_name("service")
+                            .about("Run the Grin server as a Windows service, started by the service control manager"))
+                .subcommand(SubCommand::with_name("validate")
+                            .about("Check the integrity of the chain data of a stopped server"))
+                .subcommand(SubCommand::with_name("export-chain")
+                            .about("Write all the blocks of a stopped server to a dump file")
+                            .arg(Arg::with_name("file")
+                                 .help("File to write the blocks to")
+                                 .index(1)
+                                 .required(true)))
+                .subcommand(SubCommand::with_name("import-chain")
+                            .about("Add the blocks of a dump file to a stopped server, fully validating them")
+                            .arg(Arg::with_name("file")
+                                 .help("File to read the blocks from")
+                                 .index(1)
+                                 .required(true))))
 
     // specification of all the client commands and options
     .subcommand(SubCommand::with_name("client")
                 .about("Communicates with the Grin server")
                 .subcommand(SubCommand::with_name("status")
                             .about("current status of the Grin chain"))
+				.subcommand(SubCommand::with_name("listconnectedpeers")
+							.about("Print the details of the connected peers"))
 				.subcommand(SubCommand::with_name("ban")
 							.about("Ban peer")
 							.arg(Arg::with_name("peer")
@@ -165,7 +222,45 @@ fn main() {
 								.short("p")
 								.long("peer")
 								.help("Peer ip and port (e.g. 10.12.12.13:13414)")
-								.takes_value(true))))
+								.takes_value(true)))
+				.subcommand(SubCommand::with_name("accept-reorg")
+							.about("Switch to a fork refused for being deeper than the maximum reorg depth"))
+				.subcommand(SubCommand::with_name("reload-config")
+							.about("Reload the peer lists and limits and the log levels of the configuration file")))
+
+	// peer management through the API of a running server
+	.subcommand(SubCommand::with_name("peers")
+		.about("Manage the peers of the Grin server")
+		.subcommand(SubCommand::with_name("list")
+			.about("List the connected peers")
+			.arg(Arg::with_name("all")
+				.short("a")
+				.long("all")
+				.help("List all the known peers instead, connected or not")))
+		.subcommand(SubCommand::with_name("ban")
+			.about("Ban peer")
+			.arg(Arg::with_name("peer")
+				.help("Peer ip and port (e.g. 10.12.12.13:13414)")
+				.index(1)
+				.required(true)))
+		.subcommand(SubCommand::with_name("unban")
+			.about("Unban peer")
+			.arg(Arg::with_name("peer")
+				.help("Peer ip and port (e.g. 10.12.12.13:13414)")
+				.index(1)
+				.required(true)))
+		.subcommand(SubCommand::with_name("dump")
+			.about("Write all the known peers to a JSON file, or CSV if its name ends with .csv")
+			.arg(Arg::with_name("file")
+				.help("File to write the peers to")
+				.index(1)
+				.required(true)))
+		.subcommand(SubCommand::with_name("import")
+			.about("Add the peers of a JSON or CSV file written by dump, except the banned ones")
+			.arg(Arg::with_name("file")
+				.help("File to read the peers from")
+				.index(1)
+				.required(true))))
 
 
 	// specification of the wallet commands and options
@@ -206,6 +301,18 @@ fn main() {
 				.long("key_derivations")
 				.default_value("1000")
 				.takes_value(true))
+		.arg(Arg::with_name("account")
+			.help("Wallet account to use, its outputs and balance are separate from the \
+			other accounts")
+			.long("account")
+			.default_value("default")
+			.takes_value(true))
+
+		.subcommand(SubCommand::with_name("account")
+			.about("Lists the wallet accounts, or creates a new one with the provided name.")
+			.arg(Arg::with_name("create")
+				.help("Name of the account to create")
+				.index(1)))
 
 		.subcommand(SubCommand::with_name("listen")
 			.about("Runs the wallet in listening mode waiting for transactions.")
@@ -213,15 +320,58 @@ fn main() {
 				.short("l")
 				.long("port")
 				.help("Port on which to run the wallet listener")
+				.takes_value(true))
+			.arg(Arg::with_name("owner_port")
+				.short("o")
+				.long("owner_port")
+				.help("Port on which to run the wallet owner API, on the local interface only")
 				.takes_value(true)))
 
 		.subcommand(SubCommand::with_name("receive")
-			.about("Processes a JSON transaction file.")
+			.about("Processes a partial transaction file created by a send to a file, \
+				printing the partial transaction to send back to the sender.")
 			.arg(Arg::with_name("input")
 				.help("Partial transaction to process, expects a JSON file.")
-				.short("i")
-				.long("input")
-				.takes_value(true)))
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("adaptor")
+				.help("Locks our partial signature on a new adaptor point, for an \
+					atomic swap: the sender confirms with swap_confirm and we \
+					complete the transaction with swap_complete.")
+				.long("adaptor")))
+
+		.subcommand(SubCommand::with_name("swap_confirm")
+			.about("Confirms the adaptor locked response of the recipient of a \
+				transaction sent to a file, printing the confirmation to send back \
+				to the recipient, who completes and posts the transaction.")
+			.arg(Arg::with_name("input")
+				.help("Partial transaction returned by the recipient, expects a JSON file.")
+				.required(true)
+				.index(1)))
+
+		.subcommand(SubCommand::with_name("swap_complete")
+			.about("Completes an adaptor locked transaction confirmed by the sender \
+				and posts it to the node, printing the final transaction.")
+			.arg(Arg::with_name("input")
+				.help("Confirmation returned by the sender, expects a JSON file.")
+				.required(true)
+				.index(1)))
+
+		.subcommand(SubCommand::with_name("swap_secret")
+			.about("Prints the adaptor secret of an adaptor locked transaction we \
+				sent, once completed by the recipient.")
+			.arg(Arg::with_name("input")
+				.help("Final transaction printed by swap_complete, expects a JSON file.")
+				.required(true)
+				.index(1)))
+
+		.subcommand(SubCommand::with_name("finalize")
+			.about("Finalizes a transaction sent to a file with the partial transaction \
+				returned by the recipient and posts it to the node.")
+			.arg(Arg::with_name("input")
+				.help("Partial transaction returned by the recipient, expects a JSON file.")
+				.required(true)
+				.index(1)))
 
 		.subcommand(SubCommand::with_name("send")
 			.about("Builds a transaction to send coins and sends it to the specified \
@@ -233,21 +383,90 @@ fn main() {
 				.help("Minimum number of confirmations required for an output to be spendable.")
 				.short("c")
 				.long("min_conf")
+				.alias("min-conf")
 				.default_value("1")
 				.takes_value(true))
 			.arg(Arg::with_name("selection_strategy")
-				.help("Coin/Output selection strategy.")
+				.help("Coin/Output selection strategy: 'all' spends as many outputs as possible, \
+				'smallest-first' only the smallest outputs needed, 'minimize-change' the \
+				outputs leaving the least change.")
 				.short("s")
 				.long("selection")
-				.possible_values(&["all", "smallest"])
+				.possible_values(&["all", "smallest", "smallest-first", "minimize-change"])
 				.default_value("all")
 				.takes_value(true))
+			.arg(Arg::with_name("fee_base")
+				.help("Fee per unit of transaction weight, in nanogrins. By default, the fee \
+				the node estimates to get the transaction mined within a few blocks.")
+				.short("f")
+				.long("fee_base")
+				.takes_value(true))
 			.arg(Arg::with_name("dest")
-				.help("Send the transaction to the provided server")
+				.help("Send the transaction to the provided server, or write it to a \
+					file to hand over to the recipient with file:<path>")
 				.short("d")
 				.long("dest")
 				.takes_value(true)))
 
+		.subcommand(SubCommand::with_name("send_batch")
+			.about("Builds a single transaction paying several recipients, listeners or \
+				files, with a single fee.")
+			.arg(Arg::with_name("input")
+				.help("File listing the recipients, one per line as the amount to send \
+					followed by the destination (a listener or file:<path>)")
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("minimum_confirmations")
+				.help("Minimum number of confirmations required for an output to be spendable.")
+				.short("c")
+				.long("min_conf")
+				.alias("min-conf")
+				.default_value("1")
+				.takes_value(true))
+			.arg(Arg::with_name("selection_strategy")
+				.help("Coin/Output selection strategy, as for send.")
+				.short("s")
+				.long("selection")
+				.possible_values(&["all", "smallest", "smallest-first", "minimize-change"])
+				.default_value("all")
+				.takes_value(true))
+			.arg(Arg::with_name("fee_base")
+				.help("Fee per unit of transaction weight, in nanogrins. By default, the fee \
+				the node estimates to get the transaction mined within a few blocks.")
+				.short("f")
+				.long("fee_base")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("repost")
+			.about("Rebuilds a sent transaction that hasn't confirmed yet with a higher \
+				fee and sends it to its recipient again, replacing the original one. The \
+				fee bump comes out of the change, the recipient gets the same amount.")
+			.arg(Arg::with_name("id")
+				.help("Id of the pending transaction to repost, the latest one by default")
+				.index(1))
+			.arg(Arg::with_name("fee_bump")
+				.help("Factor the original transaction fee gets multiplied by")
+				.short("f")
+				.long("fee-bump")
+				.default_value("2")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("resume")
+			.about("Resumes a send which recipient listener couldn't be reached, going \
+				through the exchange with the recipient again.")
+			.arg(Arg::with_name("id")
+				.help("Id of the pending transaction to resume")
+				.required(true)
+				.index(1)))
+
+		.subcommand(SubCommand::with_name("cancel")
+			.about("Cancels a sent transaction that hasn't confirmed, unlocking the outputs \
+				it spends.")
+			.arg(Arg::with_name("id")
+				.help("Id of the pending transaction to cancel")
+				.required(true)
+				.index(1)))
+
 		.subcommand(SubCommand::with_name("burn")
 			.about("** TESTING ONLY ** Burns the provided amount to a known \
 				key. Similar to send but burns an output to allow single-party \
@@ -266,10 +485,76 @@ fn main() {
 			.about("raw wallet info (list of outputs)"))
 
 		.subcommand(SubCommand::with_name("info")
-			.about("basic wallet contents summary"))
+			.about("basic wallet contents summary")
+			.arg(Arg::with_name("minimum_confirmations")
+				.help("Minimum number of confirmations required for an output to be spendable.")
+				.short("c")
+				.long("min_conf")
+				.alias("min-conf")
+				.default_value("1")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("txs")
+			.about("history of the transactions sent and received, with their status"))
+
+		.subcommand(SubCommand::with_name("address")
+			.about("Prints the address payment proofs of the wallet are signed with."))
+
+		.subcommand(SubCommand::with_name("export_proof")
+			.about("Prints the payment proof of a sent transaction, as signed by its recipient.")
+			.arg(Arg::with_name("id")
+				.help("Id of the transaction in the wallet transaction log (see wallet txs)")
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("recipient")
+				.help("Recipient of a batch send, by its position in the batch (from 0)")
+				.short("r")
+				.long("recipient")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("verify_proof")
+			.about("Verifies a payment proof, doesn't require any wallet.")
+			.arg(Arg::with_name("input")
+				.help("Payment proof to verify, expects a JSON file.")
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("address")
+				.help("Address of the expected recipient")
+				.short("a")
+				.long("address")
+				.takes_value(true)))
 
 		.subcommand(SubCommand::with_name("init")
-			.about("Initialize a new wallet seed file."))
+			.about("Initialize a new wallet seed file, printing its recovery phrase.")
+			.arg(Arg::with_name("recover")
+				.help("Recreate the seed file from an existing recovery phrase, read from \
+				the standard input if not provided with --phrase.")
+				.short("r")
+				.long("recover")
+				.takes_value(false))
+			.arg(Arg::with_name("phrase")
+				.help("The 24 words recovery phrase to recover from")
+				.long("phrase")
+				.requires("recover")
+				.takes_value(true))
+			.arg(Arg::with_name("recovery_pass")
+				.help("Optional passphrase protecting the recovery phrase, required again \
+				to recover from it.")
+				.long("recovery_pass")
+				.default_value("")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("coinbase_key")
+			.about("Writes the keys of the selected account (--account, other than the \
+				default one) to a file, for a mining node to pay its coinbase to with \
+				coinbase_key_path instead of a wallet listener. Coinbase outputs are \
+				found back with 'restore' on that account, with key_derivations over \
+				the index the node keeps in the same file name ending with .index, \
+				close to the chain height.")
+			.arg(Arg::with_name("output")
+				.help("File to write the account keys to")
+				.index(1)
+				.required(true)))
 
 		.subcommand(SubCommand::with_name("restore")
 			.about("Attempt to restore wallet contents from the chain using seed and password. \
@@ -278,6 +563,12 @@ fn main() {
 	.get_matches();
 
 	match args.subcommand() {
+		("setup", Some(_)) => {
+			if let Err(e) = setup::run(&global_config) {
+				println!("Setup failed: {}", e);
+			}
+		}
+
 		// server commands and options
 		("server", Some(server_args)) => {
 			server_command(server_args, global_config);
@@ -289,6 +580,10 @@ fn main() {
 		}
 
 		// client commands and options
+		("peers", Some(peers_args)) => {
+			peers_command(peers_args, global_config);
+		}
+
 		("wallet", Some(wallet_args)) => {
 			wallet_command(wallet_args, global_config);
 		}
@@ -318,6 +613,9 @@ fn server_command(server_args: &ArgMatches, global_config: GlobalConfig) {
 
 	// just get defaults from the global config
 	let mut server_config = global_config.members.unwrap().server;
+	server_config.config_file_path = global_config
+		.config_file_path
+		.map(|p| p.to_str().unwrap().to_string());
 
 	if let Some(port) = server_args.value_of("port") {
 		server_config.p2p_config.port = port.parse().unwrap();
@@ -348,25 +646,53 @@ fn server_command(server_args: &ArgMatches, global_config: GlobalConfig) {
 	// start the server in the different run modes (interactive or daemon)
 	match server_args.subcommand() {
 		("run", _) => {
-			grin::Server::start(server_config).unwrap();
+			if server_config.run_tui == Some(true) {
+				// quitting the dashboard shuts the server down
+				grin::Server::start_with_stats(server_config, |stats_reader| {
+					tui::run(stats_reader.clone());
+					stats_reader.stop_server();
+				}).unwrap();
+			} else {
+				grin::Server::start(server_config).unwrap();
+			}
 		}
 		("start", _) => {
-			let daemonize = Daemonize::new()
-				.pid_file("/tmp/grin.pid")
-				.chown_pid_file(true)
-				.working_directory(current_dir().unwrap())
-				.privileged_action(move || {
-					grin::Server::start(server_config.clone()).unwrap();
-					loop {
-						thread::sleep(Duration::from_secs(60));
-					}
-				});
-			match daemonize.start() {
+			let pid_file = daemon::pid_file_path(&server_config, server_args.value_of("pid_file"));
+			match daemon::start(server_config, pid_file) {
 				Ok(_) => info!(LOGGER, "Grin server successfully started."),
 				Err(e) => error!(LOGGER, "Error starting: {}", e),
 			}
 		}
-		("stop", _) => println!("TODO. Just 'kill $pid' for now. Maybe /tmp/grin.pid is $pid"),
+		("service", _) => {
+			if let Err(e) = daemon::run_service(server_config) {
+				error!(LOGGER, "Error running the service: {}", e);
+			}
+		}
+		("stop", _) => {
+			let pid_file = daemon::pid_file_path(&server_config, server_args.value_of("pid_file"));
+			match daemon::stop(pid_file) {
+				Ok(_) => println!("Grin server stopped."),
+				Err(e) => println!("Error stopping: {}", e),
+			}
+		}
+		("validate", _) => match grin::Server::validate_chain(server_config) {
+			Ok(_) => println!("Chain data is valid."),
+			Err(e) => println!("Chain data validation failed: {:?}", e),
+		},
+		("export-chain", Some(file_args)) => {
+			let file = file_args.value_of("file").unwrap();
+			match grin::Server::export_chain(server_config, file) {
+				Ok(count) => println!("Exported {} blocks to {}.", count, file),
+				Err(e) => println!("Chain export failed: {:?}", e),
+			}
+		}
+		("import-chain", Some(file_args)) => {
+			let file = file_args.value_of("file").unwrap();
+			match grin::Server::import_chain(server_config, file) {
+				Ok(count) => println!("Imported {} new blocks from {}.", count, file),
+				Err(e) => println!("Chain import failed: {:?}", e),
+			}
+		}
 		(cmd, _) => {
 			println!(":: {:?}", server_args);
 			panic!(
@@ -385,6 +711,9 @@ fn client_command(client_args: &ArgMatches, global_config: GlobalConfig) {
 		("status", Some(_)) => {
 			client::show_status(&server_config);
 		}
+		("listconnectedpeers", Some(_)) => {
+			client::list_connected_peers(&server_config);
+		}
 		("ban", Some(peer_args)) => {
 			if let Some(peer) = peer_args.value_of("peer") {
 				if let Ok(addr) = peer.parse() {
@@ -403,10 +732,42 @@ fn client_command(client_args: &ArgMatches, global_config: GlobalConfig) {
 				}
 			}
 		}
+		("accept-reorg", Some(_)) => {
+			client::accept_reorg(&server_config);
+		}
+		("reload-config", Some(_)) => {
+			client::reload_config(&server_config);
+		}
 		_ => panic!("Unknown client command, use 'grin help client' for details"),
 	}
 }
 
+fn peers_command(peers_args: &ArgMatches, global_config: GlobalConfig) {
+	// just get defaults from the global config
+	let server_config = global_config.members.unwrap().server;
+
+	match peers_args.subcommand() {
+		("list", Some(list_args)) => {
+			client::list_peers(&server_config, list_args.is_present("all"));
+		}
+		("ban", Some(peer_args)) => match peer_args.value_of("peer").unwrap().parse() {
+			Ok(addr) => client::ban_peer(&server_config, &addr),
+			Err(_) => panic!("Invalid peer address format"),
+		},
+		("unban", Some(peer_args)) => match peer_args.value_of("peer").unwrap().parse() {
+			Ok(addr) => client::unban_peer(&server_config, &addr),
+			Err(_) => panic!("Invalid peer address format"),
+		},
+		("dump", Some(file_args)) => {
+			client::dump_peers(&server_config, file_args.value_of("file").unwrap());
+		}
+		("import", Some(file_args)) => {
+			client::import_peers(&server_config, file_args.value_of("file").unwrap());
+		}
+		_ => panic!("Unknown peers command, use 'grin help peers' for details"),
+	}
+}
+
 fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 	// just get defaults from the global config
 	let mut wallet_config = global_config.members.unwrap().wallet;
@@ -432,48 +793,169 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 
 	// Derive the keychain based on seed from seed file and specified passphrase.
 	// Generate the initial wallet seed if we are running "wallet init".
-	if let ("init", Some(_)) = wallet_args.subcommand() {
-		wallet::WalletSeed::init_file(&wallet_config).expect("Failed to init wallet seed file.");
+	if let ("init", Some(init_args)) = wallet_args.subcommand() {
+		let recovery_pass = init_args.value_of("recovery_pass").unwrap();
+		if init_args.is_present("recover") {
+			let phrase = match init_args.value_of("phrase") {
+				Some(p) => p.to_string(),
+				None => {
+					println!("Enter the recovery phrase:");
+					let mut phrase = String::new();
+					io::stdin()
+						.read_line(&mut phrase)
+						.expect("Failed to read the recovery phrase.");
+					phrase
+				}
+			};
+			match wallet::WalletSeed::recover_file(&wallet_config, &phrase, recovery_pass) {
+				Ok(_) => println!(
+					"Wallet seed recovered. Run 'grin wallet restore' to find its outputs."
+				),
+				Err(wallet::Error::Mnemonic(e)) => println!("Invalid recovery phrase: {}", e),
+				Err(e) => println!("Failed to recover the wallet seed: {:?}", e),
+			}
+		} else {
+			let phrase = wallet::WalletSeed::init_file(&wallet_config, recovery_pass)
+				.expect("Failed to init wallet seed file.");
+			println!("Your recovery phrase is:");
+			println!();
+			println!("{}", phrase);
+			println!();
+			println!("Write it down and keep it safe, it's the only way to recover the wallet.");
+		}
 
 		// we are done here with creating the wallet, so just return
 		return;
 	}
 
+	if let ("account", Some(account_args)) = wallet_args.subcommand() {
+		match account_args.value_of("create") {
+			Some(name) => match wallet::create_account(&wallet_config, name) {
+				Ok(account) => println!(
+					"Account {} created, using derivation index {}.",
+					account.name, account.index
+				),
+				Err(e) => println!("Failed to create the account: {:?}", e),
+			},
+			None => wallet::show_accounts(&wallet_config),
+		}
+		return;
+	}
+
+	if let ("verify_proof", Some(proof_args)) = wallet_args.subcommand() {
+		let input = proof_args.value_of("input").expect("Input file required");
+		let mut file = File::open(input).expect("Unable to open payment proof file.");
+		let mut contents = String::new();
+		file.read_to_string(&mut contents)
+			.expect("Unable to read payment proof file.");
+		let proof: wallet::PaymentProof = serde_json::from_str(&contents)
+			.expect("Could not parse the payment proof file.");
+		match wallet::verify_payment_proof(&proof, proof_args.value_of("address")) {
+			Ok(_) => {
+				println!("Payment proof valid.");
+				println!(" * recipient address: {}", proof.recipient_address);
+				println!(" * amount: {}", core::core::amount_to_hr_string(proof.amount));
+				println!(" * kernel excess: {}", proof.kernel_excess);
+			}
+			Err(e) => println!("Payment proof invalid: {:?}", e),
+		}
+		return;
+	}
+
 	let wallet_seed =
 		wallet::WalletSeed::from_file(&wallet_config).expect("Failed to read wallet seed file.");
 	let passphrase = wallet_args
 		.value_of("pass")
 		.expect("Failed to read passphrase.");
-	let mut keychain = wallet_seed
+	let keychain = wallet_seed
 		.derive_keychain(&passphrase)
 		.expect("Failed to derive keychain from seed file and passphrase.");
+	let account = wallet_args.value_of("account").unwrap();
+	if let ("coinbase_key", Some(key_args)) = wallet_args.subcommand() {
+		let output = key_args.value_of("output").unwrap();
+		match wallet::export_coinbase_key(&wallet_config, &keychain, account, output) {
+			Ok(_) => println!(
+				"Keys of account {} written to {}, keep the file as safe as the wallet seed.",
+				account, output
+			),
+			Err(e) => println!("Failed to export the account keys: {:?}", e),
+		}
+		return;
+	}
+	let mut keychain = wallet::account_keychain(&wallet_config, &keychain, account)
+		.expect("Failed to select the wallet account.");
 
 	match wallet_args.subcommand() {
 		("listen", Some(listen_args)) => {
 			if let Some(port) = listen_args.value_of("port") {
 				wallet_config.api_listen_port = port.to_string();
 			}
+			if let Some(port) = listen_args.value_of("owner_port") {
+				wallet_config.owner_api_listen_port = Some(port.to_string());
+			}
 			wallet::server::start_rest_apis(wallet_config, keychain);
 		}
-		// The following is gone for now, as a result of aggsig transactions
-		// being implemented
-		/*("receive", Some(receive_args)) => {
+		("receive", Some(receive_args)) => {
 			let input = receive_args.value_of("input").expect("Input file required");
 			let mut file = File::open(input).expect("Unable to open transaction file.");
 			let mut contents = String::new();
-			file.read_to_string(&mut contents).expect(
-				"Unable to read transaction file.",
-			);
-			if let Err(e) =
-				wallet::receive_json_tx_str(
-					&wallet_config, &keychain, contents.as_str()) {
-
-				println!("Error receiving transaction, the most likely reasons are:");
-				println!(" * the transaction has already been sent");
-				println!(" * your node isn't running or can't be reached");
-				println!("\nDetailed error: {:?}", e);
+			file.read_to_string(&mut contents)
+				.expect("Unable to read transaction file.");
+			let partial_tx: wallet::PartialTx = serde_json::from_str(&contents)
+				.expect("Could not parse the transaction file.");
+			let res = if receive_args.is_present("adaptor") {
+				wallet::receive_swap_tx(&wallet_config, &keychain, &partial_tx)
+			} else {
+				wallet::receive_file_tx(&wallet_config, &keychain, &partial_tx)
+			};
+			match res {
+				// the response goes to stdout, logs go to stderr
+				Ok(response) => println!("{}", serde_json::to_string_pretty(&response).unwrap()),
+				Err(e) => error!(LOGGER, "Error receiving transaction: {:?}", e),
 			}
-		}*/
+		}
+		("swap_confirm", Some(confirm_args)) => {
+			let input = confirm_args.value_of("input").expect("Input file required");
+			match wallet::confirm_swap_tx(&wallet_config, &keychain, input) {
+				Ok(confirmation) => {
+					println!("{}", serde_json::to_string_pretty(&confirmation).unwrap())
+				}
+				Err(e) => error!(LOGGER, "Tx not confirmed: {:?}", e),
+			}
+		}
+		("swap_complete", Some(complete_args)) => {
+			let input = complete_args.value_of("input").expect("Input file required");
+			let mut contents = String::new();
+			File::open(input)
+				.and_then(|mut f| f.read_to_string(&mut contents))
+				.expect("Unable to read transaction file.");
+			let partial_tx: wallet::PartialTx = serde_json::from_str(&contents)
+				.expect("Could not parse the transaction file.");
+			match wallet::complete_swap_tx(&wallet_config, &keychain, &partial_tx) {
+				Ok(final_tx) => println!("{}", serde_json::to_string_pretty(&final_tx).unwrap()),
+				Err(e) => error!(LOGGER, "Tx not completed: {:?}", e),
+			}
+		}
+		("swap_secret", Some(secret_args)) => {
+			let input = secret_args.value_of("input").expect("Input file required");
+			let mut contents = String::new();
+			File::open(input)
+				.and_then(|mut f| f.read_to_string(&mut contents))
+				.expect("Unable to read transaction file.");
+			let partial_tx: wallet::PartialTx = serde_json::from_str(&contents)
+				.expect("Could not parse the transaction file.");
+			match wallet::swap_secret(&wallet_config, &keychain, &partial_tx) {
+				Ok(secret) => println!("{}", secret),
+				Err(e) => error!(LOGGER, "No adaptor secret: {:?}", e),
+			}
+		}
+		("finalize", Some(finalize_args)) => {
+			let input = finalize_args.value_of("input").expect("Input file required");
+			match wallet::finalize_tx_file(&wallet_config, &keychain, input) {
+				Ok(_) => info!(LOGGER, "Tx finalized and posted to the node"),
+				Err(e) => error!(LOGGER, "Tx not finalized: {:?}", e),
+			}
+		}
 		("send", Some(send_args)) => {
 			let amount = send_args
 				.value_of("amount")
@@ -491,6 +973,10 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 			let dest = send_args
 				.value_of("dest")
 				.expect("Destination wallet address required");
+			let fee_base = send_args.value_of("fee_base").map(|f| {
+				f.parse()
+					.expect("Could not parse fee_base as a whole number.")
+			});
 			let max_outputs = 500;
 			let result = wallet::issue_send_tx(
 				&wallet_config,
@@ -499,7 +985,8 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 				minimum_confirmations,
 				dest.to_string(),
 				max_outputs,
-				selection_strategy == "all",
+				selection_strategy.parse().unwrap(),
+				fee_base,
 			);
 			match result {
 				Ok(_) => info!(
@@ -509,11 +996,21 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 					dest,
 					selection_strategy,
 				),
-				Err(wallet::Error::NotEnoughFunds(available)) => {
+				Err(wallet::Error::NotEnoughFunds {
+					available,
+					awaiting_confirmation,
+					immature,
+					locked,
+				}) => {
 					error!(
 						LOGGER,
-						"Tx not sent: insufficient funds (max: {})",
+						"Tx not sent: insufficient funds (max: {}). Not spendable yet: {} awaiting \
+						 {} confirmation(s), {} of immature coinbase, {} locked by pending transactions.",
 						amount_to_hr_string(available),
+						amount_to_hr_string(awaiting_confirmation),
+						minimum_confirmations,
+						amount_to_hr_string(immature),
+						amount_to_hr_string(locked),
 					);
 				}
 				Err(wallet::Error::FeeExceedsAmount {
@@ -532,6 +1029,89 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 				}
 			};
 		}
+		("send_batch", Some(batch_args)) => {
+			let input = batch_args.value_of("input").expect("Input file required");
+			let recipients = read_batch_recipients(input);
+			let minimum_confirmations: u64 = batch_args
+				.value_of("minimum_confirmations")
+				.unwrap()
+				.parse()
+				.expect("Could not parse minimum_confirmations as a whole number.");
+			let selection_strategy = batch_args
+				.value_of("selection_strategy")
+				.expect("Selection strategy required");
+			let fee_base = batch_args.value_of("fee_base").map(|f| {
+				f.parse()
+					.expect("Could not parse fee_base as a whole number.")
+			});
+			let count = recipients.len();
+			let result = wallet::issue_batch_send_tx(
+				&wallet_config,
+				&keychain,
+				recipients,
+				minimum_confirmations,
+				500,
+				selection_strategy.parse().unwrap(),
+				fee_base,
+			);
+			match result {
+				Ok(_) => info!(LOGGER, "Batch to {} recipients sent", count),
+				Err(wallet::Error::NotEnoughFunds {
+					available,
+					awaiting_confirmation,
+					immature,
+					locked,
+				}) => {
+					error!(
+						LOGGER,
+						"Batch not sent: insufficient funds (max: {}). Not spendable yet: {} awaiting \
+						 {} confirmation(s), {} of immature coinbase, {} locked by pending transactions.",
+						amount_to_hr_string(available),
+						amount_to_hr_string(awaiting_confirmation),
+						minimum_confirmations,
+						amount_to_hr_string(immature),
+						amount_to_hr_string(locked),
+					);
+				}
+				Err(e) => error!(LOGGER, "Batch not sent: {:?}", e),
+			}
+		}
+		("repost", Some(repost_args)) => {
+			let id: Option<u32> = repost_args
+				.value_of("id")
+				.map(|id| id.parse().expect("Could not parse id as a whole number."));
+			let fee_bump: u64 = repost_args
+				.value_of("fee_bump")
+				.unwrap()
+				.parse()
+				.expect("Could not parse fee bump as a whole number.");
+			match wallet::repost_send_tx(&wallet_config, &keychain, id, fee_bump) {
+				Ok(_) => info!(LOGGER, "Tx reposted with a fee {} times higher", fee_bump),
+				Err(e) => error!(LOGGER, "Tx not reposted: {:?}", e),
+			};
+		}
+		("resume", Some(resume_args)) => {
+			let id: u32 = resume_args
+				.value_of("id")
+				.unwrap()
+				.parse()
+				.expect("Could not parse id as a whole number.");
+			match wallet::resume_send_tx(&wallet_config, &keychain, id) {
+				Ok(_) => info!(LOGGER, "Tx {} resumed", id),
+				Err(e) => error!(LOGGER, "Tx not resumed: {:?}", e),
+			};
+		}
+		("cancel", Some(cancel_args)) => {
+			let id: u32 = cancel_args
+				.value_of("id")
+				.unwrap()
+				.parse()
+				.expect("Could not parse id as a whole number.");
+			match wallet::cancel_send_tx(&wallet_config, &keychain, id) {
+				Ok(_) => info!(LOGGER, "Tx {} cancelled", id),
+				Err(e) => error!(LOGGER, "Tx not cancelled: {:?}", e),
+			};
+		}
 		("burn", Some(send_args)) => {
 			let amount = send_args
 				.value_of("amount")
@@ -552,15 +1132,65 @@ fn wallet_command(wallet_args: &ArgMatches, global_config: GlobalConfig) {
 				max_outputs,
 			).unwrap();
 		}
-		("info", Some(_)) => {
-			wallet::show_info(&wallet_config, &keychain);
+		("info", Some(info_args)) => {
+			let minimum_confirmations: u64 = info_args
+				.value_of("minimum_confirmations")
+				.unwrap()
+				.parse()
+				.expect("Could not parse minimum_confirmations as a whole number.");
+			wallet::show_info(&wallet_config, &keychain, minimum_confirmations);
 		}
 		("outputs", Some(_)) => {
 			wallet::show_outputs(&wallet_config, &keychain, show_spent);
 		}
+		("txs", Some(_)) => {
+			wallet::show_txs(&wallet_config, &keychain);
+		}
+		("address", Some(_)) => match wallet::payment_proof_address(&keychain) {
+			Ok(address) => println!("{}", address),
+			Err(e) => error!(LOGGER, "Could not derive the wallet address: {:?}", e),
+		},
+		("export_proof", Some(proof_args)) => {
+			let id: u32 = proof_args
+				.value_of("id")
+				.unwrap()
+				.parse()
+				.expect("Could not parse id as a whole number.");
+			let recipient = proof_args.value_of("recipient").map(|r| {
+				r.parse()
+					.expect("Could not parse recipient as a whole number.")
+			});
+			match wallet::payment_proof(&wallet_config, &keychain, id, recipient) {
+				Ok(proof) => println!("{}", serde_json::to_string_pretty(&proof).unwrap()),
+				Err(e) => error!(LOGGER, "No payment proof: {:?}", e),
+			}
+		}
 		("restore", Some(_)) => {
 			let _ = wallet::restore(&wallet_config, &keychain, key_derivations);
 		}
 		_ => panic!("Unknown wallet command, use 'grin help wallet' for details"),
 	}
 }
+
+// Recipients of a batch send, one per line of the file as the amount to send
+// and its destination. Empty lines and lines starting with # are skipped.
+fn read_batch_recipients(input: &str) -> Vec<(u64, String)> {
+	let mut file = File::open(input).expect("Unable to open batch file.");
+	let mut contents = String::new();
+	file.read_to_string(&mut contents)
+		.expect("Unable to read batch file.");
+	contents
+		.lines()
+		.map(|l| l.trim())
+		.filter(|l| !l.is_empty() && !l.starts_with("#"))
+		.map(|l| {
+			let fields = l.split_whitespace().collect::<Vec<_>>();
+			if fields.len() != 2 {
+				panic!("Expected an amount and a destination, got: {}", l);
+			}
+			let amount = core::core::amount_from_hr_string(fields[0])
+				.expect("Could not parse amount as a number with optional decimal point.");
+			(amount, fields[1].to_string())
+		})
+		.collect()
+}
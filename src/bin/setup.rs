@@ -0,0 +1,243 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interactive first-run setup. Asks the few questions needed to get a node
+//! running (chain, data directory, API exposure, mining, wallet), writes the
+//! resulting grin.toml in the user home directory where it's picked up
+//! automatically, creates the wallet seed and optionally starts the node.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use config::{self, GlobalConfig};
+use core::global::{self, ChainTypes};
+use grin;
+use wallet;
+
+/// Port the REST API listens on
+const API_PORT: u16 = 13413;
+
+/// Answers to the setup questions the configuration depends on
+struct Answers {
+	chain_type: ChainTypes,
+	data_dir: String,
+	expose_api: bool,
+	mine: bool,
+}
+
+/// Runs the setup wizard. The currently loaded configuration, if any, is only
+/// used to warn about it taking precedence over the one being written.
+pub fn run(current_config: &GlobalConfig) -> Result<(), String> {
+	let config_path = match config::config::home_config_path() {
+		Some(p) => p,
+		None => return Err("Could not determine the home directory".to_string()),
+	};
+
+	println!("Grin setup, press enter to accept the default shown in brackets.");
+	println!();
+
+	if config_path.exists()
+		&& !confirm(
+			&format!("A configuration already exists at {:?}, replace it?", config_path),
+			false,
+		)? {
+		return Ok(());
+	}
+
+	let chain_type = ask_chain_type()?;
+
+	let default_dir = config_path.parent().unwrap().to_str().unwrap().to_string();
+	let data_dir = prompt("Data directory", &default_dir)?;
+
+	let expose_api = confirm(
+		"Expose the node API to other machines (only do this behind a firewall)?",
+		false,
+	)?;
+	let mine = confirm("Mine blocks with this node?", false)?;
+	let create_wallet = confirm("Create a wallet?", true)?;
+
+	let answers = Answers {
+		chain_type: chain_type.clone(),
+		data_dir: data_dir,
+		expose_api: expose_api,
+		mine: mine,
+	};
+	let mut global_config = node_config(config_path.clone(), &answers);
+	global_config.write_config().map_err(|e| e.to_string())?;
+	println!();
+	println!("Configuration written to {:?}", config_path);
+
+	let members = global_config.members.unwrap();
+	if create_wallet {
+		let seed_file = PathBuf::from(&members.wallet.data_file_dir).join("wallet.seed");
+		if seed_file.exists() {
+			println!("Keeping the existing wallet seed at {:?}", seed_file);
+		} else {
+			let phrase = wallet::WalletSeed::init_file(&members.wallet, "")
+				.map_err(|e| format!("Failed to create the wallet seed: {:?}", e))?;
+			println!("Wallet seed created at {:?}. Your recovery phrase is:", seed_file);
+			println!();
+			println!("{}", phrase);
+			println!();
+			println!("Write it down and keep it safe, it's the only way to recover the wallet.");
+		}
+	}
+	if mine {
+		println!(
+			"Mining rewards are sent to the wallet listener, run 'grin wallet listen' \
+			 alongside the node."
+		);
+	}
+	if let Some(ref path) = current_config.config_file_path {
+		if current_config.using_config_file && *path != config_path {
+			println!(
+				"Note: the configuration at {:?} takes precedence when running from this \
+				 directory.",
+				path
+			);
+		}
+	}
+	println!();
+
+	if confirm("Start the node now?", true)? {
+		global::set_mining_mode(chain_type);
+		::start_server(members.server);
+	}
+	Ok(())
+}
+
+// Builds the node and wallet configuration out of the setup answers.
+fn node_config(config_path: PathBuf, answers: &Answers) -> GlobalConfig {
+	let mut global_config = GlobalConfig::default();
+	global_config.config_file_path = Some(config_path);
+	{
+		let members = global_config.members.as_mut().unwrap();
+
+		members.server.chain_type = answers.chain_type.clone();
+		members.server.db_root = Path::new(&answers.data_dir)
+			.join("chain_data")
+			.to_str()
+			.unwrap()
+			.to_string();
+		members.server.seeding_type = match answers.chain_type {
+			ChainTypes::Testnet1 | ChainTypes::Testnet2 => grin::Seeding::WebStatic,
+			_ => grin::Seeding::None,
+		};
+		members.server.api_http_addr = if answers.expose_api {
+			format!("0.0.0.0:{}", API_PORT)
+		} else {
+			format!("127.0.0.1:{}", API_PORT)
+		};
+
+		if let Some(ref mut mining_config) = members.mining {
+			mining_config.enable_mining = answers.mine;
+			mining_config.wallet_listener_url = format!(
+				"http://127.0.0.1:{}",
+				members.wallet.api_listen_port
+			);
+		}
+		members.server.mining_config = members.mining.clone();
+
+		members.wallet.data_file_dir = Path::new(&answers.data_dir)
+			.join("wallet")
+			.to_str()
+			.unwrap()
+			.to_string();
+		members.wallet.check_node_api_http_addr = format!("http://127.0.0.1:{}", API_PORT);
+	}
+	global_config
+}
+
+fn ask_chain_type() -> Result<ChainTypes, String> {
+	loop {
+		let chain = prompt("Chain to run on (testnet1, testnet2, usertesting)", "testnet1")?;
+		match chain.to_lowercase().as_str() {
+			"testnet1" => return Ok(ChainTypes::Testnet1),
+			"testnet2" => return Ok(ChainTypes::Testnet2),
+			"usertesting" => return Ok(ChainTypes::UserTesting),
+			_ => println!("Unknown chain '{}'.", chain),
+		}
+	}
+}
+
+// Asks a question, returning the answer or the default if none is given.
+fn prompt(question: &str, default: &str) -> Result<String, String> {
+	print!("{} [{}]: ", question, default);
+	io::stdout().flush().map_err(|e| e.to_string())?;
+
+	let mut answer = String::new();
+	let read = io::stdin()
+		.read_line(&mut answer)
+		.map_err(|e| e.to_string())?;
+	if read == 0 {
+		return Err("Setup aborted".to_string());
+	}
+	let answer = answer.trim();
+	if answer.is_empty() {
+		Ok(default.to_string())
+	} else {
+		Ok(answer.to_string())
+	}
+}
+
+// Asks a yes or no question until getting a proper answer.
+fn confirm(question: &str, default: bool) -> Result<bool, String> {
+	let default_str = if default { "Y/n" } else { "y/N" };
+	loop {
+		let answer = prompt(question, default_str)?;
+		if answer == default_str {
+			return Ok(default);
+		}
+		match answer.to_lowercase().as_str() {
+			"y" | "yes" => return Ok(true),
+			"n" | "no" => return Ok(false),
+			_ => println!("Please answer yes or no."),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::env;
+	use std::fs;
+
+	#[test]
+	fn written_config() {
+		let dir = env::temp_dir().join("grin_setup_test");
+		let _ = fs::remove_dir_all(&dir);
+		let path = dir.join(".grin").join("grin.toml");
+		let answers = Answers {
+			chain_type: ChainTypes::Testnet2,
+			data_dir: dir.to_str().unwrap().to_string(),
+			expose_api: false,
+			mine: true,
+		};
+		node_config(path.clone(), &answers).write_config().unwrap();
+
+		// read back as the node would on startup
+		let config = GlobalConfig::new(path.to_str()).unwrap();
+		let members = config.members.unwrap();
+		assert_eq!(members.server.chain_type, ChainTypes::Testnet2);
+		assert_eq!(
+			PathBuf::from(&members.server.db_root),
+			dir.join("chain_data")
+		);
+		assert_eq!(members.server.api_http_addr, "127.0.0.1:13413");
+		assert!(members.server.mining_config.unwrap().enable_mining);
+		assert_eq!(PathBuf::from(&members.wallet.data_file_dir), dir.join("wallet"));
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
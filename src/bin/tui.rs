@@ -0,0 +1,167 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Terminal dashboard of a server running in the foreground. Shows one panel
+//! at a time (status, peers or mining), switched with the number keys and
+//! refreshed every second.
+
+use std::sync::{Arc, Mutex};
+
+use cursive::Cursive;
+use cursive::traits::*;
+use cursive::views::{Dialog, LinearLayout, TextView};
+
+use grin::{ServerStats, StatsReader};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Panel {
+	Status,
+	Peers,
+	Mining,
+}
+
+impl Panel {
+	fn title(&self) -> &'static str {
+		match *self {
+			Panel::Status => "Status",
+			Panel::Peers => "Peers",
+			Panel::Mining => "Mining",
+		}
+	}
+}
+
+/// Runs the dashboard until the user quits it.
+pub fn run(stats_reader: StatsReader) {
+	let mut siv = Cursive::new();
+	let panel = Arc::new(Mutex::new(Panel::Status));
+
+	let keys = [('1', Panel::Status), ('2', Panel::Peers), ('3', Panel::Mining)];
+	for &(key, p) in keys.iter() {
+		let panel = panel.clone();
+		siv.add_global_callback(key, move |_| *panel.lock().unwrap() = p);
+	}
+	siv.add_global_callback('q', |s| s.quit());
+
+	siv.add_fullscreen_layer(
+		LinearLayout::vertical()
+			.child(
+				Dialog::around(TextView::new("Waiting for the server...").with_id("content"))
+					.title(Panel::Status.title())
+					.with_id("panel")
+					.full_screen(),
+			)
+			.child(TextView::new(
+				"[1] Status   [2] Peers   [3] Mining   [q] Quit",
+			)),
+	);
+
+	// redraws every second even without user input
+	siv.set_fps(1);
+	while siv.is_running() {
+		let current = *panel.lock().unwrap();
+		let stats = stats_reader.get_server_stats();
+		let content = match current {
+			Panel::Status => status_panel(&stats),
+			Panel::Peers => peers_panel(&stats),
+			Panel::Mining => mining_panel(&stats),
+		};
+		siv.call_on_id("panel", |d: &mut Dialog| d.set_title(current.title()));
+		siv.call_on_id("content", |t: &mut TextView| t.set_content(content));
+		siv.step();
+	}
+}
+
+fn status_panel(stats: &ServerStats) -> String {
+	format!(
+		"Sync status:        {}\n\
+		 Connected peers:    {}\n\n\
+		 Chain height:       {}\n\
+		 Last block hash:    {}\n\
+		 Total difficulty:   {}\n\n\
+		 Transaction pool:   {} ({} in stem phase)\n",
+		stats.sync_status,
+		stats.peer_count,
+		stats.head.height,
+		stats.head.last_block_h,
+		stats.head.total_difficulty,
+		stats.pool_size,
+		stats.stem_pool_size,
+	)
+}
+
+fn peers_panel(stats: &ServerStats) -> String {
+	let mut content = format!(
+		"{:<24} {:<10} {:<10} {:<18} {}\n",
+		"Address", "Direction", "Height", "Total Difficulty", "User Agent"
+	);
+	for p in &stats.peer_stats {
+		content.push_str(&format!(
+			"{:<24} {:<10} {:<10} {:<18} {}\n",
+			p.addr, p.direction, p.height, p.total_difficulty, p.user_agent
+		));
+	}
+	if stats.peer_stats.is_empty() {
+		content.push_str("\nNo connected peers.\n");
+	}
+	content
+}
+
+fn mining_panel(stats: &ServerStats) -> String {
+	let mining = &stats.mining_stats;
+	let mut content = String::new();
+	if mining.is_mining {
+		content.push_str(&format!(
+			"Mining with {} at {:.2} graphs per second, {} solutions found\n",
+			mining.plugin.clone().unwrap_or("unknown plugin".to_string()),
+			mining.graphs_per_second,
+			mining.solutions_found,
+		));
+	} else {
+		content.push_str("Local miner not running\n");
+	}
+	if mining.block_height > 0 {
+		content.push_str(&format!(
+			"Mining block {} at network difficulty {}\n",
+			mining.block_height, mining.network_difficulty
+		));
+	}
+
+	if mining.is_stratum_running {
+		content.push_str(&format!(
+			"\nStratum workers ({}):\n{:<20} {:<20} {:<10} {:<10} {:<10} {}\n",
+			mining.workers.len(),
+			"Login",
+			"Agent",
+			"Accepted",
+			"Rejected",
+			"Stale",
+			"Blocks"
+		));
+		for w in &mining.workers {
+			content.push_str(&format!(
+				"{:<20} {:<20} {:<10} {:<10} {:<10} {}\n",
+				w.login, w.agent, w.num_accepted, w.num_rejected, w.num_stale, w.num_blocks_found
+			));
+		}
+	}
+
+	content.push_str("\nRecently found blocks:\n");
+	for b in &mining.recent_blocks {
+		content.push_str(&format!("{:<10} {} by {}\n", b.height, b.hash, b.found_by));
+	}
+	if mining.recent_blocks.is_empty() {
+		content.push_str("None yet.\n");
+	}
+	content
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Storage of core types using RocksDB.
+//! Storage of core types using LMDB.
 
 #![deny(non_upper_case_globals)]
 #![deny(non_camel_case_types)]
@@ -25,79 +25,111 @@ extern crate env_logger;
 extern crate grin_core as core;
 extern crate grin_util as util;
 extern crate libc;
+extern crate lmdb_zero as lmdb;
 extern crate memmap;
 extern crate rocksdb;
 #[macro_use]
 extern crate slog;
+extern crate supercow;
 
 pub mod sumtree;
 
 const SEP: u8 = ':' as u8;
 
+// Size of the memory map of each store, the upper bound of its size on disk.
+// Only reserves address space, 512GB is plenty on 64 bits.
+const MAP_SIZE: usize = 549_755_813_888;
+
+// Number of entries copied per write transaction when migrating a store
+const MIGRATION_BATCH: usize = 10_000;
+
 use std::fmt;
+use std::fs;
 use std::iter::Iterator;
 use std::marker::PhantomData;
-use std::sync::RwLock;
+use std::path::Path;
+use std::sync::Arc;
 
 use byteorder::{BigEndian, WriteBytesExt};
-use rocksdb::{DBCompactionStyle, DBIterator, Direction, IteratorMode, WriteBatch, DB};
+use lmdb::traits::LmdbResultExt;
+use supercow::Supercow;
 
 use core::ser;
+use util::LOGGER;
 
 /// Main error type for this crate.
 #[derive(Debug)]
 pub enum Error {
 	/// Couldn't find what we were looking for
 	NotFoundErr,
-	/// Wraps an error originating from RocksDB (which unfortunately returns
-	/// string errors).
-	RocksDbErr(String),
+	/// Wraps an error originating from LMDB
+	LmdbErr(lmdb::error::Error),
 	/// Wraps a serialization error for Writeable or Readable
 	SerErr(ser::Error),
+	/// The store is in an older format and couldn't be migrated
+	OutdatedStore(String),
+	/// Couldn't create the store directory
+	FileErr(String),
 }
 
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
 			&Error::NotFoundErr => write!(f, "Not Found"),
-			&Error::RocksDbErr(ref s) => write!(f, "RocksDb Error: {}", s),
+			&Error::LmdbErr(ref e) => write!(f, "LMDB Error: {}", e),
 			&Error::SerErr(ref e) => write!(f, "Serialization Error: {}", e.to_string()),
+			&Error::OutdatedStore(ref s) => write!(f, "Outdated Store: {}", s),
+			&Error::FileErr(ref s) => write!(f, "File Error: {}", s),
 		}
 	}
 }
 
-impl From<rocksdb::Error> for Error {
-	fn from(e: rocksdb::Error) -> Error {
-		Error::RocksDbErr(e.to_string())
+impl From<lmdb::error::Error> for Error {
+	fn from(e: lmdb::error::Error) -> Error {
+		Error::LmdbErr(e)
 	}
 }
 
-/// Thread-safe rocksdb wrapper
+/// Key/value store backed by an LMDB environment of its own. LMDB is
+/// thread-safe, readers never block and writes are serialized.
 pub struct Store {
-	rdb: RwLock<DB>,
+	env: Arc<lmdb::Environment>,
+	db: Arc<lmdb::Database<'static>>,
 }
 
-unsafe impl Sync for Store {}
-unsafe impl Send for Store {}
-
 impl Store {
-	/// Opens a new RocksDB at the specified location.
+	/// Opens a new LMDB store at the specified location, creating it if it
+	/// doesn't exist. A store of the older RocksDB format gets migrated
+	/// first.
 	pub fn open(path: &str) -> Result<Store, Error> {
-		let mut opts = rocksdb::Options::default();
-		opts.create_if_missing(true);
-		opts.set_compaction_style(DBCompactionStyle::Universal);
-		opts.set_max_open_files(256);
-		opts.set_use_fsync(false);
-		let db = try!(DB::open(&opts, &path));
-		Ok(Store {
-			rdb: RwLock::new(db),
-		})
+		if Path::new(path).join("CURRENT").exists() {
+			migrate_rocksdb(path)?;
+		}
+		Store::open_lmdb(path)
+	}
+
+	fn open_lmdb(path: &str) -> Result<Store, Error> {
+		fs::create_dir_all(path).map_err(|e| Error::FileErr(e.to_string()))?;
+
+		let env = unsafe {
+			let mut env_builder = lmdb::EnvBuilder::new()?;
+			env_builder.set_mapsize(MAP_SIZE)?;
+			Arc::new(env_builder.open(path, lmdb::open::Flags::empty(), 0o600)?)
+		};
+		let db = Arc::new(lmdb::Database::open(
+			env.clone(),
+			None,
+			&lmdb::DatabaseOptions::defaults(),
+		)?);
+		Ok(Store { env: env, db: db })
 	}
 
 	/// Writes a single key/value pair to the db
 	pub fn put(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
-		let db = self.rdb.write().unwrap();
-		db.put(key, &value[..]).map_err(&From::from)
+		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
+		txn.access()
+			.put(&self.db, key, &value[..], lmdb::put::Flags::empty())?;
+		txn.commit().map_err(From::from)
 	}
 
 	/// Writes a single key and its `Writeable` value to the db. Encapsulates
@@ -112,10 +144,10 @@ impl Store {
 
 	/// Gets a value from the db, provided its key
 	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-		let db = self.rdb.read().unwrap();
-		db.get(key)
-			.map(|r| r.map(|o| o.to_vec()))
-			.map_err(From::from)
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let res = access.get::<[u8], [u8]>(&self.db, key);
+		res.map(|v| v.to_vec()).to_opt().map_err(From::from)
 	}
 
 	/// Gets a `Readable` value from the db, provided its key. Encapsulates
@@ -132,7 +164,9 @@ impl Store {
 		key: &[u8],
 		len: usize,
 	) -> Result<Option<T>, Error> {
-		let data = try!(self.get(key));
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let data = access.get::<[u8], [u8]>(&self.db, key).to_opt()?;
 		match data {
 			Some(val) => {
 				let mut lval = if len > 0 { &val[..len] } else { &val[..] };
@@ -145,55 +179,84 @@ impl Store {
 
 	/// Whether the provided key exists
 	pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
-		let db = self.rdb.read().unwrap();
-		db.get(key).map(|r| r.is_some()).map_err(From::from)
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let res = access.get::<[u8], lmdb::Ignore>(&self.db, key);
+		res.to_opt().map(|r| r.is_some()).map_err(From::from)
 	}
 
 	/// Deletes a key/value pair from the db
 	pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
-		let db = self.rdb.write().unwrap();
-		db.delete(key).map_err(From::from)
+		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
+		txn.access().del_key(&self.db, key).to_opt()?;
+		txn.commit().map_err(From::from)
+	}
+
+	/// Produces an iterator of the `Readable` values whose keys start with
+	/// the provided prefix, in key order. Values are only read and
+	/// deserialized as the iteration goes.
+	pub fn iter<T: ser::Readable>(&self, prefix: &[u8]) -> Result<SerIterator<T>, Error> {
+		self.iter_from(prefix, prefix)
 	}
 
-	/// Produces an iterator of `Readable` types moving forward from the
-	/// provided
-	/// key.
-	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> SerIterator<T> {
-		let db = self.rdb.read().unwrap();
-		SerIterator {
-			iter: db.iterator(IteratorMode::From(from, Direction::Forward)),
+	/// Same as iter but starting at the provided key, which should itself
+	/// start with the prefix. Range scans an index, e.g. from a given height.
+	pub fn iter_from<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		from: &[u8],
+	) -> Result<SerIterator<T>, Error> {
+		// the cursor shares the ownership of its transaction and database,
+		// living as long as the iterator whatever the store does
+		let txn = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
+		let cursor = lmdb::Cursor::construct(
+			Supercow::shared(txn.clone()),
+			Supercow::shared(self.db.clone()),
+		)?;
+		Ok(SerIterator {
+			cursor: cursor,
+			txn: txn,
+			prefix: prefix.to_vec(),
+			from: Some(from.to_vec()),
 			_marker: PhantomData,
-		}
+		})
 	}
 
 	/// Builds a new batch to be used with this store.
-	pub fn batch(&self) -> Batch {
-		Batch {
+	pub fn batch(&self) -> Result<Batch, Error> {
+		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
+		Ok(Batch {
 			store: self,
-			batch: WriteBatch::default(),
-		}
+			txn: txn,
+		})
 	}
 
-	fn write(&self, batch: WriteBatch) -> Result<(), Error> {
-		let db = self.rdb.write().unwrap();
-		db.write(batch).map_err(From::from)
+	/// Makes sure everything written so far made it to disk. Each commit is
+	/// synced already, this is a safety net on shutdown.
+	pub fn sync(&self) -> Result<(), Error> {
+		self.env.sync(true).map_err(From::from)
 	}
 }
 
-/// Batch to write multiple Writeables to RocksDb in an atomic manner.
+/// Batch to write multiple Writeables to LMDB in an atomic manner, in a
+/// single write transaction. Either all of the batch makes it to the store or
+/// none of it does, and reads through the batch see its own pending writes.
+/// Dropping a batch without writing it aborts it.
 pub struct Batch<'a> {
 	store: &'a Store,
-	batch: WriteBatch,
+	txn: lmdb::WriteTransaction<'static>,
 }
 
 impl<'a> Batch<'a> {
 	/// Writes a single key and its `Writeable` value to the batch. The write
 	/// function must be called to "commit" the batch to storage.
-	pub fn put_ser<W: ser::Writeable>(mut self, key: &[u8], value: &W) -> Result<Batch<'a>, Error> {
+	pub fn put_ser<W: ser::Writeable>(self, key: &[u8], value: &W) -> Result<Batch<'a>, Error> {
 		let ser_value = ser::ser_vec(value);
 		match ser_value {
 			Ok(data) => {
-				self.batch.put(key, &data[..])?;
+				self.txn
+					.access()
+					.put(&self.store.db, key, &data[..], lmdb::put::Flags::empty())?;
 				Ok(self)
 			}
 			Err(err) => Err(Error::SerErr(err)),
@@ -202,24 +265,45 @@ impl<'a> Batch<'a> {
 
 	/// Delete a single key from the batch. The write function
 	/// must be called to "commit" the batch to storage.
-	pub fn delete(mut self, key: &[u8]) -> Result<Batch<'a>, Error> {
-		self.batch.delete(key)?;
+	pub fn delete(self, key: &[u8]) -> Result<Batch<'a>, Error> {
+		self.txn.access().del_key(&self.store.db, key).to_opt()?;
 		Ok(self)
 	}
 
-	/// Writes the batch to RocksDb.
+	/// Gets a `Readable` value as the batch sees it, including what was
+	/// written in the batch so far. Reads that a write depends on should go
+	/// through the batch, so nothing changes in between.
+	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
+		let access = self.txn.access();
+		let data = access.get::<[u8], [u8]>(&self.store.db, key).to_opt()?;
+		match data {
+			Some(val) => {
+				let r = try!(ser::deserialize(&mut &val[..]).map_err(Error::SerErr));
+				Ok(Some(r))
+			}
+			None => Ok(None),
+		}
+	}
+
+	/// Writes the batch to LMDB.
 	pub fn write(self) -> Result<(), Error> {
-		self.store.write(self.batch)
+		self.txn.commit().map_err(From::from)
 	}
 }
 
-/// An iterator thad produces Readable instances back. Wraps the lower level
-/// DBIterator and deserializes the returned values.
+/// An iterator that produces Readable instances back, as long as their keys
+/// start with the iterator prefix. Holds its own read transaction, seeing
+/// the store as it was when the iterator got created.
 pub struct SerIterator<T>
 where
 	T: ser::Readable,
 {
-	iter: DBIterator,
+	// dropped before the transaction it reads through
+	cursor: lmdb::Cursor<'static, 'static>,
+	txn: Arc<lmdb::ReadTransaction<'static>>,
+	prefix: Vec<u8>,
+	// key to seek to on the first call to next
+	from: Option<Vec<u8>>,
 	_marker: PhantomData<T>,
 }
 
@@ -230,14 +314,64 @@ where
 	type Item = T;
 
 	fn next(&mut self) -> Option<T> {
-		let next = self.iter.next();
-		next.and_then(|r| {
-			let (_, v) = r;
-			ser::deserialize(&mut &v[..]).ok()
-		})
+		let access = self.txn.access();
+		let kv = match self.from.take() {
+			Some(from) => self.cursor.seek_range_k::<[u8], [u8]>(&access, &from[..]),
+			None => self.cursor.next::<[u8], [u8]>(&access),
+		};
+		match kv {
+			Ok((k, v)) => if k.starts_with(&self.prefix[..]) {
+				ser::deserialize(&mut &v[..]).ok()
+			} else {
+				None
+			},
+			Err(_) => None,
+		}
 	}
 }
 
+// Copies all the entries of the RocksDB store at the provided path to a new
+// LMDB store, keys and values being the same in both formats. The LMDB store
+// then takes its place, the RocksDB one being kept aside as a backup.
+fn migrate_rocksdb(path: &str) -> Result<(), Error> {
+	let migration_err = |e: String| {
+		Error::OutdatedStore(format!("could not migrate the RocksDB store {}: {}", path, e))
+	};
+	let tmp_path = format!("{}.lmdb", path);
+	let backup_path = format!("{}.rocksdb", path);
+	warn!(LOGGER, "Migrating the RocksDB store {} to LMDB, this can take a while", path);
+
+	let _ = fs::remove_dir_all(&tmp_path);
+	let mut count = 0;
+	{
+		let old = rocksdb::DB::open_default(path).map_err(|e| migration_err(e.to_string()))?;
+		let new = Store::open_lmdb(&tmp_path)?;
+		let mut txn = lmdb::WriteTransaction::new(new.env.clone())?;
+		for (key, value) in old.iterator(rocksdb::IteratorMode::Start) {
+			txn.access()
+				.put(&new.db, &key[..], &value[..], lmdb::put::Flags::empty())?;
+			count += 1;
+			if count % MIGRATION_BATCH == 0 {
+				txn.commit()?;
+				txn = lmdb::WriteTransaction::new(new.env.clone())?;
+			}
+		}
+		txn.commit()?;
+		new.sync()?;
+	}
+
+	fs::rename(path, &backup_path).map_err(|e| migration_err(e.to_string()))?;
+	fs::rename(&tmp_path, path).map_err(|e| migration_err(e.to_string()))?;
+	warn!(
+		LOGGER,
+		"Migrated {} entries of {} to LMDB, the RocksDB store is kept in {} and can be removed",
+		count,
+		path,
+		backup_path,
+	);
+	Ok(())
+}
+
 /// Build a db key from a prefix and a byte vector identifier.
 pub fn to_key(prefix: u8, k: &mut Vec<u8>) -> Vec<u8> {
 	let mut res = Vec::with_capacity(k.len() + 2);
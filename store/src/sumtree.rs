@@ -473,10 +473,29 @@ where
 		{
 			return Ok(());
 		}
+		self.compact(::std::u32::MAX).map(|_| ())
+	}
+
+	/// Compacts all the positions in the remove log that were removed at an
+	/// index (typically a block height) strictly lower than cutoff_index,
+	/// rewriting the main hashsum data file without them and adding them to
+	/// the prune list. Positions removed more recently are kept in the remove
+	/// log so the backend can still be rewound past them. Returns the number
+	/// of positions compacted.
+	pub fn compact(&mut self, cutoff_index: u32) -> io::Result<usize> {
+		let to_compact = self.remove_log
+			.removed
+			.iter()
+			.filter(|&&(_, idx)| idx < cutoff_index)
+			.map(|x| *x)
+			.collect::<Vec<_>>();
+		if to_compact.is_empty() {
+			return Ok(0);
+		}
 
 		// 0. validate none of the nodes in the rm log are in the prune list (to
   // avoid accidental double compaction)
-		for pos in &self.remove_log.removed[..] {
+		for pos in &to_compact[..] {
 			if let None = self.pruned_nodes.pruned_pos(pos.0) {
 				// TODO we likely can recover from this by directly jumping to 3
 				error!(
@@ -484,7 +503,7 @@ where
 					"The remove log contains nodes that are already in the pruned \
 					 list, a previous compaction likely failed."
 				);
-				return Ok(());
+				return Ok(0);
 			}
 		}
 
@@ -492,8 +511,7 @@ where
   // remove list
 		let tmp_prune_file = format!("{}/{}.prune", self.data_dir, PMMR_DATA_FILE);
 		let record_len = (32 + T::sum_len()) as u64;
-		let to_rm = self.remove_log
-			.removed
+		let to_rm = to_compact
 			.iter()
 			.map(|&(pos, _)| {
 				let shift = self.pruned_nodes.get_shift(pos);
@@ -504,7 +522,7 @@ where
 			.save_prune(tmp_prune_file.clone(), to_rm, record_len)?;
 
 		// 2. update the prune list and save it in place
-		for &(rm_pos, _) in &self.remove_log.removed[..] {
+		for &(rm_pos, _) in &to_compact[..] {
 			self.pruned_nodes.add(rm_pos);
 		}
 		write_vec(
@@ -520,11 +538,17 @@ where
 		self.hashsum_file = AppendOnlyFile::open(format!("{}/{}", self.data_dir, PMMR_DATA_FILE))?;
 		self.hashsum_file.sync()?;
 
-		// 4. truncate the rm log
-		self.remove_log.truncate(0)?;
+		// 4. cut the compacted positions from the rm log
+		if cutoff_index == ::std::u32::MAX {
+			self.remove_log.truncate(0)?;
+		} else {
+			self.remove_log
+				.removed
+				.retain(|&(_, idx)| idx >= cutoff_index);
+		}
 		self.remove_log.flush()?;
 
-		Ok(())
+		Ok(to_compact.len())
 	}
 }
 
@@ -0,0 +1,80 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_core as core;
+extern crate grin_store as store;
+extern crate rocksdb;
+
+use std::fs;
+use std::path::Path;
+
+use core::ser;
+use store::{to_key, u64_to_key, Store};
+
+fn setup(name: &str) -> (String, Store) {
+	let path = format!("./target/{}", name);
+	let _ = fs::remove_dir_all(&path);
+	let store = Store::open(&path).unwrap();
+	(path, store)
+}
+
+#[test]
+fn prefix_iteration() {
+	let (_, store) = setup("lmdb_prefix");
+	for n in 0..5u64 {
+		store.put_ser(&u64_to_key('a' as u8, n), &n).unwrap();
+		store.put_ser(&u64_to_key('b' as u8, n), &(n * 10)).unwrap();
+	}
+
+	// only the entries of the prefix, in key order
+	let a = store.iter::<u64>(&to_key('a' as u8, &mut vec![])).unwrap();
+	assert_eq!(a.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+	let b = store.iter::<u64>(&to_key('b' as u8, &mut vec![])).unwrap();
+	assert_eq!(b.collect::<Vec<_>>(), vec![0, 10, 20, 30, 40]);
+	let c = store.iter::<u64>(&to_key('c' as u8, &mut vec![])).unwrap();
+	assert_eq!(c.count(), 0);
+
+	// starting from a given key
+	let from = store
+		.iter_from::<u64>(&to_key('a' as u8, &mut vec![]), &u64_to_key('a' as u8, 3))
+		.unwrap();
+	assert_eq!(from.collect::<Vec<_>>(), vec![3, 4]);
+
+	// an iterator sees the store as it was when created, and outlives it
+	let iter = store.iter::<u64>(&to_key('a' as u8, &mut vec![])).unwrap();
+	store.put_ser(&u64_to_key('a' as u8, 5), &5u64).unwrap();
+	drop(store);
+	assert_eq!(iter.count(), 5);
+}
+
+#[test]
+fn migrate_rocksdb() {
+	let path = "./target/lmdb_migrate";
+	let _ = fs::remove_dir_all(path);
+	let _ = fs::remove_dir_all(format!("{}.rocksdb", path));
+	{
+		let old = rocksdb::DB::open_default(path).unwrap();
+		for n in 0..3u64 {
+			let value = ser::ser_vec(&(n + 100)).unwrap();
+			old.put(&u64_to_key('a' as u8, n), &value).unwrap();
+		}
+	}
+
+	// the same entries, the old store kept aside
+	let store = Store::open(path).unwrap();
+	let migrated = store.iter::<u64>(&to_key('a' as u8, &mut vec![])).unwrap();
+	assert_eq!(migrated.collect::<Vec<_>>(), vec![100, 101, 102]);
+	assert!(Path::new(&format!("{}.rocksdb", path)).join("CURRENT").exists());
+	assert!(!Path::new(path).join("CURRENT").exists());
+}
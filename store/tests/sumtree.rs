@@ -111,6 +111,47 @@ fn sumtree_prune_compact() {
 	teardown(data_dir);
 }
 
+#[test]
+fn sumtree_compact_horizon() {
+	let (data_dir, elems) = setup("compact_horizon");
+
+	let mut backend = store::sumtree::PMMRBackend::new(data_dir.to_string()).unwrap();
+	let mmr_size = load(0, &elems[..], &mut backend);
+	backend.sync().unwrap();
+
+	let root: HashSum<TestElem>;
+	{
+		let pmmr = PMMR::at(&mut backend, mmr_size);
+		root = pmmr.root();
+	}
+
+	// pruning nodes at different heights
+	{
+		let mut pmmr = PMMR::at(&mut backend, mmr_size);
+		pmmr.prune(1, 1).unwrap();
+		pmmr.prune(4, 1).unwrap();
+		pmmr.prune(5, 3).unwrap();
+	}
+	backend.sync().unwrap();
+
+	// only what's been pruned before the horizon gets compacted
+	assert_eq!(backend.compact(2).unwrap(), 2);
+	{
+		let pmmr = PMMR::at(&mut backend, mmr_size);
+		assert_eq!(root, pmmr.root());
+	}
+	assert_eq!(backend.compact(2).unwrap(), 0);
+
+	// and the rest once the horizon moves forward (5 and its parent 6)
+	assert_eq!(backend.compact(4).unwrap(), 2);
+	{
+		let pmmr = PMMR::at(&mut backend, mmr_size);
+		assert_eq!(root, pmmr.root());
+	}
+
+	teardown(data_dir);
+}
+
 #[test]
 fn sumtree_reload() {
 	let (data_dir, elems) = setup("reload");
@@ -27,6 +27,7 @@ extern crate slog_async;
 extern crate slog_term;
 extern crate byteorder;
 extern crate rand;
+extern crate zip as zip_rs;
 
 #[macro_use]
 extern crate lazy_static;
@@ -34,6 +35,8 @@ extern crate lazy_static;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 // Re-export so only has to be included once
 pub extern crate secp256k1zkp as secp_;
@@ -41,7 +44,14 @@ pub use secp_ as secp;
 
 // Logging related
 pub mod logger;
-pub use logger::{init_logger, init_test_logger, LOGGER};
+pub use logger::{init_logger, init_test_logger, update_log_levels, LOGGER};
+mod log_rotation;
+
+// Tracing of objects (blocks) across processing stages
+pub mod tracing;
+
+// Memory budget shared by caches and queues
+pub mod memory;
 
 // Static secp instance
 pub mod secp_static;
@@ -50,6 +60,9 @@ pub use secp_static::static_secp_instance;
 pub mod types;
 pub use types::{LoggingConfig, LogLevel};
 
+// Zip archives of directories
+pub mod zip;
+
 // other utils
 use std::cell::{Ref, RefCell};
 #[allow(unused_imports)]
@@ -0,0 +1,144 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Log file rotated once it gets too large or too old. The current file
+//! keeps its configured path, rotated files get a numbered suffix, 1 being
+//! the most recent (grin.log, grin.log.1, grin.log.2...).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Writer to a log file, rotating it when over the size or age limits.
+/// Limits are only checked on flush, which the log formatter does after
+/// every record, so records never get split between files.
+pub struct RotatingFile {
+	path: String,
+	file: File,
+	size: u64,
+	opened: Instant,
+	max_size: Option<u64>,
+	max_age: Option<Duration>,
+	max_files: usize,
+}
+
+impl RotatingFile {
+	/// Opens the log file at the provided path, appending to it or
+	/// truncating it.
+	pub fn open(
+		path: &str,
+		append: bool,
+		max_size: Option<u64>,
+		max_age: Option<Duration>,
+		max_files: usize,
+	) -> io::Result<RotatingFile> {
+		let file = OpenOptions::new()
+			.create(true)
+			.write(true)
+			.append(append)
+			.truncate(!append)
+			.open(path)?;
+		let size = file.metadata()?.len();
+		Ok(RotatingFile {
+			path: path.to_string(),
+			file: file,
+			size: size,
+			opened: Instant::now(),
+			max_size: max_size,
+			max_age: max_age,
+			max_files: max_files,
+		})
+	}
+
+	fn needs_rotation(&self) -> bool {
+		self.max_size.map_or(false, |max| self.size >= max)
+			|| self.max_age.map_or(false, |max| self.opened.elapsed() >= max)
+	}
+
+	fn rotated_path(&self, n: usize) -> String {
+		format!("{}.{}", self.path, n)
+	}
+
+	// shifts the rotated files by one, dropping the oldest, and starts a new
+	// file at the configured path
+	fn rotate(&mut self) -> io::Result<()> {
+		if self.max_files > 0 {
+			let _ = fs::remove_file(self.rotated_path(self.max_files));
+			for n in (1..self.max_files).rev() {
+				let _ = fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+			}
+			fs::rename(&self.path, self.rotated_path(1))?;
+		}
+		self.file = OpenOptions::new()
+			.create(true)
+			.write(true)
+			.truncate(true)
+			.open(&self.path)?;
+		self.size = 0;
+		self.opened = Instant::now();
+		Ok(())
+	}
+}
+
+impl Write for RotatingFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.file.write(buf)?;
+		self.size += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()?;
+		if self.needs_rotation() {
+			self.rotate()?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::env;
+	use std::io::Read;
+	use std::path::Path;
+
+	fn read(path: String) -> String {
+		let mut content = String::new();
+		File::open(path).unwrap().read_to_string(&mut content).unwrap();
+		content
+	}
+
+	#[test]
+	fn rotate_by_size() {
+		let dir = env::temp_dir().join("grin_log_rotation_test");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("grin.log").to_str().unwrap().to_string();
+
+		let mut file = RotatingFile::open(&path, true, Some(10), None, 2).unwrap();
+		for n in 0..4 {
+			file.write_all(format!("record {}..\n", n).as_bytes()).unwrap();
+			file.flush().unwrap();
+		}
+
+		// every record got over the limit, only the last 2 were kept
+		assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+		assert_eq!(read(format!("{}.1", path)), "record 3..\n");
+		assert_eq!(read(format!("{}.2", path)), "record 2..\n");
+		assert!(!Path::new(&format!("{}.3", path)).exists());
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -12,13 +12,14 @@
 // limitations under the License.
 
 //! Logging wrapper to be used throughout all crates in the workspace
-use std::fs::OpenOptions;
-use std::sync::Mutex;
+use std::sync::{Mutex, RwLock};
 use std::ops::Deref;
-use slog::{Discard, Drain, Duplicate, Level, LevelFilter, Logger};
+use std::time::Duration;
+use slog::{Discard, Drain, Duplicate, Level, Logger, OwnedKVList, Record};
 use slog_term;
 use slog_async;
 
+use log_rotation::RotatingFile;
 use types::{LogLevel, LoggingConfig};
 
 fn convert_log_level(in_level: &LogLevel) -> Level {
@@ -32,23 +33,93 @@ fn convert_log_level(in_level: &LogLevel) -> Level {
 	}
 }
 
+/// Levels records get filtered with, which can be updated while running.
+struct LogLevels {
+	stdout: Level,
+	file: Level,
+	// level by crate name, with and without the grin_ prefix
+	modules: Vec<(String, Level)>,
+}
+
+impl LogLevels {
+	fn new(config: &LoggingConfig) -> LogLevels {
+		let mut modules = vec![];
+		for (name, level) in &config.module_log_levels {
+			modules.push((name.clone(), convert_log_level(level)));
+			modules.push((format!("grin_{}", name), convert_log_level(level)));
+		}
+		LogLevels {
+			stdout: convert_log_level(&config.stdout_log_level),
+			file: convert_log_level(&config.file_log_level),
+			modules: modules,
+		}
+	}
+
+	fn level_for(&self, output: Output, module: &str) -> Level {
+		let krate = module.split("::").next().unwrap_or(module);
+		let default = match output {
+			Output::Stdout => self.stdout,
+			Output::File => self.file,
+		};
+		self.modules
+			.iter()
+			.find(|&&(ref name, _)| name == krate)
+			.map(|&(_, level)| level)
+			.unwrap_or(default)
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Output {
+	Stdout,
+	File,
+}
+
+/// Filters records below the current level of their output, like slog's
+/// LevelFilter, except for the modules of the subsystems with their own
+/// configured level.
+struct ModuleLevelFilter<D: Drain> {
+	drain: D,
+	output: Output,
+}
+
+impl<D: Drain> Drain for ModuleLevelFilter<D> {
+	type Ok = Option<D::Ok>;
+	type Err = Option<D::Err>;
+
+	fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+		let level = LOG_LEVELS
+			.read()
+			.unwrap()
+			.level_for(self.output, record.module());
+		if record.level().is_at_least(level) {
+			self.drain.log(record, values).map(Some).map_err(Some)
+		} else {
+			Ok(None)
+		}
+	}
+}
+
 lazy_static! {
 	/// Flag to observe whether logging was explicitly initialised (don't output otherwise)
 	static ref WAS_INIT: Mutex<bool> = Mutex::new(false);
 	/// Static Logging configuration, should only be set once, before first logging call
 	static ref LOGGING_CONFIG: Mutex<LoggingConfig> = Mutex::new(LoggingConfig::default());
+	/// Current log levels, initialized with the logging configuration
+	static ref LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::new(&LoggingConfig::default()));
 
 	/// And a static reference to the logger itself, accessible from all crates
 	pub static ref LOGGER: Logger = {
 		let was_init = WAS_INIT.lock().unwrap().clone();
 		let config = LOGGING_CONFIG.lock().unwrap();
-		let slog_level_stdout = convert_log_level(&config.stdout_log_level);
-		let slog_level_file = convert_log_level(&config.file_log_level);
 
 		//Terminal output drain
 		let terminal_decorator = slog_term::TermDecorator::new().build();
 		let terminal_drain = slog_term::FullFormat::new(terminal_decorator).build().fuse();
-		let terminal_drain = LevelFilter::new(terminal_drain, slog_level_stdout).fuse();
+		let terminal_drain = ModuleLevelFilter {
+			drain: terminal_drain,
+			output: Output::Stdout,
+		}.fuse();
 		let mut terminal_drain = slog_async::Async::new(terminal_drain).build().fuse();
 		if !config.log_to_stdout || !was_init {
 			terminal_drain = slog_async::Async::new(Discard{}).build().fuse();
@@ -57,17 +128,20 @@ lazy_static! {
 		//File drain
 		let mut file_drain_final = slog_async::Async::new(Discard{}).build().fuse();
 		if config.log_to_file && was_init {
-			let file = OpenOptions::new()
-				.create(true)
-				.write(true)
-				.append(config.log_file_append)
-				.truncate(false)
-				.open(&config.log_file_path)
-				.unwrap();
+			let file = RotatingFile::open(
+				&config.log_file_path,
+				config.log_file_append,
+				config.log_max_size_mb.map(|mb| mb * 1_000_000),
+				config.log_max_age_hours.map(|h| Duration::from_secs(h * 3600)),
+				config.log_max_files,
+			).unwrap();
 
 			let file_decorator = slog_term::PlainDecorator::new(file);
 			let file_drain = slog_term::FullFormat::new(file_decorator).build().fuse();
-			let file_drain = LevelFilter::new(file_drain, slog_level_file).fuse();
+			let file_drain = ModuleLevelFilter {
+				drain: file_drain,
+				output: Output::File,
+			}.fuse();
 			file_drain_final = slog_async::Async::new(file_drain).build().fuse();
 		}
 
@@ -82,6 +156,7 @@ lazy_static! {
 /// Initialises the logger with the given configuration
 pub fn init_logger(config: Option<LoggingConfig>) {
 	if let Some(c) = config {
+		update_log_levels(&c);
 		let mut config_ref = LOGGING_CONFIG.lock().unwrap();
 		*config_ref = c.clone();
 		// Logger configuration successfully injected into LOGGING_CONFIG...
@@ -91,6 +166,13 @@ pub fn init_logger(config: Option<LoggingConfig>) {
 	}
 }
 
+/// Updates the stdout, file and subsystem log levels of a running logger
+/// with the ones of the provided configuration. Its other settings (outputs,
+/// file path and rotation) can't be changed once logging started.
+pub fn update_log_levels(config: &LoggingConfig) {
+	*LOG_LEVELS.write().unwrap() = LogLevels::new(config);
+}
+
 /// Initializes the logger for unit and integration tests
 pub fn init_test_logger() {
 	let mut was_init_ref = WAS_INIT.lock().unwrap();
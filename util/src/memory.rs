@@ -0,0 +1,206 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Global memory accounting. Components holding potentially large amounts of
+//! data in memory (orphan pools, caches, the transaction pool, network send
+//! queues) register themselves as consumers. When a budget is set, enforcing
+//! it evicts data from the least critical consumers first until the total
+//! estimated usage fits within the budget again.
+
+use std::sync::{Mutex, RwLock, Weak};
+
+use LOGGER;
+
+/// How critical the data held by a consumer is, consumers with the lowest
+/// priority get evicted from first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+	/// Caches that only save recomputation
+	Cache,
+	/// Orphans, that can be requested again from peers
+	Orphans,
+	/// Data queued for peers, evicting means dropping the slowest peers
+	Network,
+	/// Transactions waiting to be mined
+	Pool,
+}
+
+/// A component holding data in memory that can be evicted when running over
+/// the memory budget.
+pub trait MemoryConsumer: Send + Sync {
+	/// Estimated number of bytes currently held.
+	fn memory_usage(&self) -> usize;
+
+	/// Evicts data to free at least the provided number of bytes if possible.
+	/// Returns the estimated number of bytes actually freed.
+	fn evict(&self, bytes: usize) -> usize;
+
+	/// Whether the data accounted for still exists, consumers that aren't
+	/// active anymore get unregistered.
+	fn is_active(&self) -> bool {
+		true
+	}
+}
+
+/// Consumers are usually registered as a weak reference to the component
+/// holding the data, which stops accounting for it once it's gone.
+impl<T: MemoryConsumer> MemoryConsumer for Weak<T> {
+	fn memory_usage(&self) -> usize {
+		self.upgrade().map(|c| c.memory_usage()).unwrap_or(0)
+	}
+
+	fn evict(&self, bytes: usize) -> usize {
+		self.upgrade().map(|c| c.evict(bytes)).unwrap_or(0)
+	}
+
+	fn is_active(&self) -> bool {
+		self.upgrade().is_some()
+	}
+}
+
+struct Consumer {
+	name: String,
+	priority: Priority,
+	consumer: Box<MemoryConsumer>,
+}
+
+lazy_static! {
+	static ref CONSUMERS: Mutex<Vec<Consumer>> = Mutex::new(vec![]);
+	static ref BUDGET: RwLock<Option<usize>> = RwLock::new(None);
+}
+
+/// Sets the memory budget in bytes all registered consumers must fit in.
+pub fn set_budget(bytes: usize) {
+	let mut budget = BUDGET.write().unwrap();
+	*budget = Some(bytes);
+}
+
+/// Configured memory budget, if any.
+pub fn budget() -> Option<usize> {
+	BUDGET.read().unwrap().clone()
+}
+
+/// Registers a new memory consumer. Consumers are expected to only hold weak
+/// references to the data they account for so they don't keep it alive.
+pub fn register(name: &str, priority: Priority, consumer: Box<MemoryConsumer>) {
+	let mut consumers = CONSUMERS.lock().unwrap();
+	consumers.retain(|c| c.consumer.is_active());
+	consumers.push(Consumer {
+		name: name.to_string(),
+		priority: priority,
+		consumer: consumer,
+	});
+}
+
+/// Estimated memory usage of each registered consumer.
+pub fn usage() -> Vec<(String, usize)> {
+	let consumers = CONSUMERS.lock().unwrap();
+	consumers
+		.iter()
+		.map(|c| (c.name.clone(), c.consumer.memory_usage()))
+		.collect()
+}
+
+/// Checks the total memory usage against the budget and evicts from the
+/// least critical consumers first until it fits. Returns the estimated
+/// number of bytes freed.
+pub fn enforce() -> usize {
+	let budget = match budget() {
+		Some(b) => b,
+		None => return 0,
+	};
+	let mut consumers = CONSUMERS.lock().unwrap();
+	let total: usize = consumers.iter().map(|c| c.consumer.memory_usage()).sum();
+	if total <= budget {
+		return 0;
+	}
+
+	consumers.sort_by_key(|c| c.priority);
+	let mut excess = total - budget;
+	let mut freed = 0;
+	for c in consumers.iter() {
+		if excess == 0 {
+			break;
+		}
+		let n = c.consumer.evict(excess);
+		if n > 0 {
+			debug!(LOGGER, "memory: evicted {} bytes from {}", n, c.name);
+		}
+		freed += n;
+		excess = excess.saturating_sub(n);
+	}
+	warn!(
+		LOGGER,
+		"memory: usage of {} bytes over the budget of {}, evicted {} bytes",
+		total,
+		budget,
+		freed,
+	);
+	freed
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::cmp;
+	use std::sync::Arc;
+
+	struct Held(Mutex<usize>);
+
+	impl Held {
+		fn bytes(&self) -> usize {
+			*self.0.lock().unwrap()
+		}
+	}
+
+	impl MemoryConsumer for Held {
+		fn memory_usage(&self) -> usize {
+			self.bytes()
+		}
+
+		fn evict(&self, bytes: usize) -> usize {
+			let mut held = self.0.lock().unwrap();
+			let freed = cmp::min(bytes, *held);
+			*held -= freed;
+			freed
+		}
+	}
+
+	#[test]
+	fn budget_enforcement() {
+		let pool = Arc::new(Held(Mutex::new(500)));
+		let cache = Arc::new(Held(Mutex::new(300)));
+		register("pool", Priority::Pool, Box::new(Arc::downgrade(&pool)));
+		register("cache", Priority::Cache, Box::new(Arc::downgrade(&cache)));
+
+		// nothing to enforce without a budget
+		assert_eq!(enforce(), 0);
+
+		// the least critical consumers go first
+		set_budget(600);
+		assert_eq!(enforce(), 200);
+		assert_eq!((cache.bytes(), pool.bytes()), (100, 500));
+		set_budget(400);
+		assert_eq!(enforce(), 200);
+		assert_eq!((cache.bytes(), pool.bytes()), (0, 400));
+		assert_eq!(enforce(), 0);
+
+		// registering doesn't keep a consumer alive
+		drop(cache);
+		let other = Arc::new(Held(Mutex::new(0)));
+		register("other", Priority::Cache, Box::new(Arc::downgrade(&other)));
+		let names = usage().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+		assert_eq!(names, vec!["pool".to_string(), "other".to_string()]);
+	}
+}
@@ -0,0 +1,382 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lightweight tracing facade, following an object (typically a block) across
+//! the different stages of its processing by the p2p, chain and pool crates.
+//!
+//! A span is opened by whoever first sees the object (`start`), each crate
+//! then records the stages it's responsible for (`event`) and the last one
+//! closes it (`finish`). Spans are keyed by a string identifier (the block
+//! hash in hex) so no handle needs to be passed around between crates.
+//! Finished spans are logged and, if an exporter has been set up, handed over
+//! to it, typically to post them to an OpenTelemetry collector as OTLP/HTTP
+//! JSON.
+//!
+//! The stages of the blocks we accepted are also aggregated locally, keeping
+//! the most recent timings of each stage to report their percentiles, i.e.
+//! how long relayed blocks take to get validated and rebroadcast.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::{self, Rng};
+use serde_json;
+
+use LOGGER;
+
+/// Maximum number of spans kept open at the same time. Spans that are never
+/// finished (orphans that never get their parent for example) are evicted
+/// once their age goes over MAX_SPAN_AGE_SECS.
+const MAX_OPEN_SPANS: usize = 1_000;
+
+/// Maximum age of an open span before it gets evicted
+const MAX_SPAN_AGE_SECS: u64 = 600;
+
+/// Outcome of the spans whose stages get aggregated in `stage_stats`
+const AGGREGATED_OUTCOME: &'static str = "accepted";
+
+/// Number of most recent timings kept for each stage
+const MAX_STAGE_SAMPLES: usize = 1_000;
+
+/// Percentiles of the time elapsed between the start of a span (first
+/// hearing of a block) and one of its stages, over the recent spans.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageStats {
+	/// Name of the stage
+	pub stage: String,
+	/// Number of timings the percentiles are computed over
+	pub samples: usize,
+	/// Median time to the stage, in milliseconds
+	pub p50_ms: u64,
+	/// 90th percentile time to the stage, in milliseconds
+	pub p90_ms: u64,
+	/// 99th percentile time to the stage, in milliseconds
+	pub p99_ms: u64,
+	/// Longest time to the stage, in milliseconds
+	pub max_ms: u64,
+}
+
+/// A single traced object going through the system
+#[derive(Debug, Clone)]
+pub struct Span {
+	/// Name of the span, as in the kind of object being traced
+	pub name: String,
+	/// Identifier of the traced object
+	pub id: String,
+	/// Where the object originated from (peer address, local miner, etc.)
+	pub origin: String,
+	/// Wall clock time the span was started at
+	pub started: SystemTime,
+	/// Stages recorded so far, with their offset from the start of the span
+	pub events: Vec<(String, Duration)>,
+	/// Final outcome, only set when the span is finished
+	pub outcome: Option<String>,
+	start: Instant,
+}
+
+impl Span {
+	fn new(name: &str, id: &str, origin: &str) -> Span {
+		Span {
+			name: name.to_string(),
+			id: id.to_string(),
+			origin: origin.to_string(),
+			started: SystemTime::now(),
+			events: vec![],
+			outcome: None,
+			start: Instant::now(),
+		}
+	}
+
+	/// Total time elapsed between the start of the span and its last event
+	pub fn duration(&self) -> Duration {
+		self.events
+			.last()
+			.map(|&(_, d)| d)
+			.unwrap_or(Duration::from_secs(0))
+	}
+}
+
+lazy_static! {
+	static ref OPEN_SPANS: Mutex<HashMap<String, Span>> = Mutex::new(HashMap::new());
+	static ref EXPORTER: Mutex<Option<Sender<Span>>> = Mutex::new(None);
+	// recent timings of each stage, in milliseconds, stages in the order
+	// they were first seen
+	static ref STAGE_SAMPLES: Mutex<Vec<(String, VecDeque<u64>)>> = Mutex::new(vec![]);
+}
+
+/// Opens a new span for the object identified by id. Does nothing if a span
+/// is already open for the same object, the first origin wins.
+pub fn start(name: &str, id: &str, origin: &str) {
+	let mut spans = OPEN_SPANS.lock().unwrap();
+	if spans.contains_key(id) {
+		return;
+	}
+	if spans.len() >= MAX_OPEN_SPANS {
+		let max_age = Duration::from_secs(MAX_SPAN_AGE_SECS);
+		spans.retain(|_, s| s.start.elapsed() < max_age);
+		if spans.len() >= MAX_OPEN_SPANS {
+			return;
+		}
+	}
+	spans.insert(id.to_string(), Span::new(name, id, origin));
+}
+
+/// Records a processing stage for the object identified by id. Ignored if
+/// no span has been started for it.
+pub fn event(id: &str, stage: &str) {
+	let mut spans = OPEN_SPANS.lock().unwrap();
+	if let Some(span) = spans.get_mut(id) {
+		let elapsed = span.start.elapsed();
+		span.events.push((stage.to_string(), elapsed));
+	}
+}
+
+/// Closes the span for the object identified by id with the provided
+/// outcome, logging it and handing it over to the exporter if any.
+pub fn finish(id: &str, outcome: &str) {
+	let span = {
+		let mut spans = OPEN_SPANS.lock().unwrap();
+		spans.remove(id)
+	};
+	if let Some(mut span) = span {
+		let elapsed = span.start.elapsed();
+		span.events.push((outcome.to_string(), elapsed));
+		span.outcome = Some(outcome.to_string());
+
+		let stages = span.events
+			.iter()
+			.map(|&(ref stage, d)| format!("{}: {}ms", stage, as_millis(d)))
+			.collect::<Vec<_>>()
+			.join(", ");
+		debug!(
+			LOGGER,
+			"trace: {} {} from {}, {} in {}ms ({})",
+			span.name,
+			span.id,
+			span.origin,
+			outcome,
+			as_millis(span.duration()),
+			stages,
+		);
+
+		if outcome == AGGREGATED_OUTCOME {
+			aggregate(&span);
+		}
+		if let Some(ref tx) = *EXPORTER.lock().unwrap() {
+			let _ = tx.send(span);
+		}
+	}
+}
+
+fn aggregate(span: &Span) {
+	let mut samples = STAGE_SAMPLES.lock().unwrap();
+	for &(ref stage, d) in &span.events {
+		let pos = match samples.iter().position(|&(ref s, _)| s == stage) {
+			Some(pos) => pos,
+			None => {
+				samples.push((stage.clone(), VecDeque::new()));
+				samples.len() - 1
+			}
+		};
+		let stage_samples = &mut samples[pos].1;
+		if stage_samples.len() >= MAX_STAGE_SAMPLES {
+			stage_samples.pop_front();
+		}
+		stage_samples.push_back(as_millis(d));
+	}
+}
+
+/// Timing percentiles of the stages of the most recently accepted blocks.
+pub fn stage_stats() -> Vec<StageStats> {
+	let samples = STAGE_SAMPLES.lock().unwrap();
+	samples
+		.iter()
+		.map(|&(ref stage, ref timings)| {
+			let mut sorted = timings.iter().cloned().collect::<Vec<_>>();
+			sorted.sort();
+			StageStats {
+				stage: stage.clone(),
+				samples: sorted.len(),
+				p50_ms: percentile(&sorted, 50),
+				p90_ms: percentile(&sorted, 90),
+				p99_ms: percentile(&sorted, 99),
+				max_ms: sorted.last().cloned().unwrap_or(0),
+			}
+		})
+		.collect()
+}
+
+// nearest rank percentile of sorted values
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+	if sorted.is_empty() {
+		return 0;
+	}
+	let rank = (sorted.len() * p + 99) / 100;
+	sorted[rank.max(1) - 1]
+}
+
+/// Starts handing finished spans over to the provided export function, called
+/// from a dedicated thread so exporting never holds up block processing.
+pub fn init_exporter<F>(export: F)
+where
+	F: Fn(&Span) -> Result<(), String> + Send + 'static,
+{
+	let (tx, rx) = channel::<Span>();
+	{
+		let mut exporter = EXPORTER.lock().unwrap();
+		*exporter = Some(tx);
+	}
+	let _ = thread::Builder::new()
+		.name("trace_exporter".to_string())
+		.spawn(move || {
+			for span in rx {
+				if let Err(e) = export(&span) {
+					debug!(LOGGER, "trace: failed to export span {}: {}", span.id, e);
+				}
+			}
+		});
+}
+
+/// URL to post OTLP/HTTP traces to for the provided collector endpoint
+/// (i.e. "http://127.0.0.1:4318"), the standard traces path unless the
+/// endpoint already includes one.
+pub fn otlp_traces_url(endpoint: &str) -> String {
+	let endpoint = endpoint.trim_right_matches('/');
+	let host_start = endpoint.find("://").map(|i| i + 3).unwrap_or(0);
+	if endpoint[host_start..].contains('/') {
+		endpoint.to_string()
+	} else {
+		format!("{}/v1/traces", endpoint)
+	}
+}
+
+/// Builds the OTLP/HTTP JSON representation of a span. The trace id is
+/// derived from the traced object identifier so spans for the same block
+/// produced by different nodes end up in the same trace.
+pub fn otlp_json(span: &Span) -> serde_json::Value {
+	let mut trace_id = span.id.clone();
+	trace_id.truncate(32);
+	while trace_id.len() < 32 {
+		trace_id.push('0');
+	}
+	let span_id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+
+	let start_nanos = span.started
+		.duration_since(UNIX_EPOCH)
+		.map(as_nanos)
+		.unwrap_or(0);
+	let events = span.events
+		.iter()
+		.map(|&(ref stage, d)| {
+			json!({
+				"name": stage,
+				"timeUnixNano": (start_nanos + as_nanos(d)).to_string()
+			})
+		})
+		.collect::<Vec<_>>();
+
+	json!({
+		"resourceSpans": [{
+			"resource": {
+				"attributes": [
+					{"key": "service.name", "value": {"stringValue": "grin"}}
+				]
+			},
+			"scopeSpans": [{
+				"scope": {"name": "grin"},
+				"spans": [{
+					"traceId": trace_id,
+					"spanId": span_id,
+					"name": span.name,
+					"kind": 1,
+					"startTimeUnixNano": start_nanos.to_string(),
+					"endTimeUnixNano": (start_nanos + as_nanos(span.duration())).to_string(),
+					"attributes": [
+						{"key": "grin.id", "value": {"stringValue": span.id}},
+						{"key": "grin.origin", "value": {"stringValue": span.origin}},
+						{"key": "grin.outcome", "value": {"stringValue": span.outcome}}
+					],
+					"events": events
+				}]
+			}]
+		}]
+	})
+}
+
+fn as_millis(d: Duration) -> u64 {
+	d.as_secs() * 1_000 + (d.subsec_nanos() / 1_000_000) as u64
+}
+
+fn as_nanos(d: Duration) -> u64 {
+	d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn stage_percentiles() {
+		let timings = (1..101).collect::<Vec<u64>>();
+		assert_eq!(percentile(&timings, 50), 50);
+		assert_eq!(percentile(&timings, 90), 90);
+		assert_eq!(percentile(&timings, 99), 99);
+		assert_eq!(percentile(&[7], 50), 7);
+		assert_eq!(percentile(&[], 50), 0);
+
+		start("block", "stage_percentiles", "test");
+		event("stage_percentiles", "validated");
+		finish("stage_percentiles", "accepted");
+		let stats = stage_stats();
+		assert!(stats.iter().any(|s| s.stage == "validated" && s.samples > 0));
+		assert!(stats.iter().any(|s| s.stage == "accepted"));
+	}
+
+	#[test]
+	fn span_export() {
+		let (tx, rx) = channel();
+		init_exporter(move |span| tx.send(otlp_json(span)).map_err(|e| e.to_string()));
+
+		let id = "00aa11bb22cc33dd44ee55ff66007788aabbccdd";
+		start("block", id, "10.0.0.1:13414");
+		start("block", id, "10.0.0.2:13414");
+		event(id, "validated");
+		finish(id, "rejected");
+		// closed, nothing recorded anymore
+		event(id, "relayed");
+
+		// other tests may be finishing their own spans
+		let span = loop {
+			let json = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+			let span = json["resourceSpans"][0]["scopeSpans"][0]["spans"][0].clone();
+			if span["attributes"][0]["value"]["stringValue"] == id {
+				break span;
+			}
+		};
+		assert_eq!(span["traceId"], "00aa11bb22cc33dd44ee55ff66007788");
+		assert_eq!(span["attributes"][1]["value"]["stringValue"], "10.0.0.1:13414");
+		assert_eq!(span["attributes"][2]["value"]["stringValue"], "rejected");
+		let events = span["events"].as_array().unwrap();
+		assert_eq!(events.len(), 2);
+		assert_eq!(events[0]["name"], "validated");
+		assert_eq!(events[1]["name"], "rejected");
+
+		assert_eq!(otlp_traces_url("http://127.0.0.1:4318"), "http://127.0.0.1:4318/v1/traces");
+		assert_eq!(otlp_traces_url("https://collector/"), "https://collector/v1/traces");
+		assert_eq!(otlp_traces_url("http://collector/otlp/traces"), "http://collector/otlp/traces");
+	}
+}
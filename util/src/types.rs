@@ -14,6 +14,8 @@
 
 //! Logging configuration types
 
+use std::collections::HashMap;
+
 /// Log level types, as slog's don't implement serialize
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LogLevel {
@@ -46,6 +48,23 @@ pub struct LoggingConfig {
 	pub log_file_path: String,
 	/// Whether to append to log or replace
 	pub log_file_append: bool,
+	/// Size in megabytes after which the log file gets rotated, if any
+	#[serde(default)]
+	pub log_max_size_mb: Option<u64>,
+	/// Age in hours after which the log file gets rotated, if any
+	#[serde(default)]
+	pub log_max_age_hours: Option<u64>,
+	/// Number of rotated log files kept, the oldest being deleted
+	#[serde(default = "default_log_max_files")]
+	pub log_max_files: usize,
+	/// Log levels of specific subsystems (p2p, chain, pool, wallet...),
+	/// overriding the stdout and file levels for their messages
+	#[serde(default)]
+	pub module_log_levels: HashMap<String, LogLevel>,
+	/// OTLP/HTTP collector endpoint block processing traces get exported to,
+	/// i.e. "http://127.0.0.1:4318". Traces are only logged when not set.
+	#[serde(default)]
+	pub tracing_otlp_endpoint: Option<String>,
 }
 
 impl Default for LoggingConfig {
@@ -57,6 +76,15 @@ impl Default for LoggingConfig {
 			file_log_level: LogLevel::Trace,
 			log_file_path: String::from("grin.log"),
 			log_file_append: false,
+			log_max_size_mb: None,
+			log_max_age_hours: None,
+			log_max_files: default_log_max_files(),
+			module_log_levels: HashMap::new(),
+			tracing_otlp_endpoint: None,
 		}
 	}
 }
+
+fn default_log_max_files() -> usize {
+	5
+}
@@ -0,0 +1,127 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Zip archives of whole directories, used to export the chain state.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use zip_rs;
+use zip_rs::write::FileOptions;
+
+/// Writes the whole content of the source directory to a new zip archive,
+/// entries being named relative to the source directory.
+pub fn compress(src_dir: &Path, dst_file: &File) -> io::Result<()> {
+	if !src_dir.is_dir() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			"zip source must be a directory",
+		));
+	}
+	let mut zip = zip_rs::ZipWriter::new(dst_file);
+	add_dir(&mut zip, src_dir, "")?;
+	zip.finish().map_err(zip_err)?;
+	dst_file.sync_all()?;
+	Ok(())
+}
+
+/// Extracts all the entries of a zip archive in the destination directory,
+/// created if it doesn't exist.
+pub fn decompress<R>(src_file: R, dst_dir: &Path) -> io::Result<()>
+where
+	R: io::Read + io::Seek,
+{
+	let mut archive = zip_rs::ZipArchive::new(src_file).map_err(zip_err)?;
+	for i in 0..archive.len() {
+		let mut file = archive.by_index(i).map_err(zip_err)?;
+		let name = file.name().to_owned();
+		// never write outside of the destination
+		if name.starts_with("/") || name.split('/').any(|part| part == "..") {
+			return Err(io::Error::new(
+				io::ErrorKind::Other,
+				format!("invalid zip entry {}", name),
+			));
+		}
+		let path = dst_dir.join(&name);
+		if name.ends_with("/") {
+			fs::create_dir_all(&path)?;
+			continue;
+		}
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		let mut out = File::create(&path)?;
+		io::copy(&mut file, &mut out)?;
+	}
+	Ok(())
+}
+
+fn add_dir(zip: &mut zip_rs::ZipWriter<&File>, dir: &Path, prefix: &str) -> io::Result<()> {
+	let options = FileOptions::default().compression_method(zip_rs::CompressionMethod::Deflated);
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+		if entry.file_type()?.is_dir() {
+			add_dir(zip, &entry.path(), &format!("{}/", name))?;
+		} else {
+			zip.start_file(name, options).map_err(zip_err)?;
+			let mut buffer = vec![];
+			File::open(entry.path())?.read_to_end(&mut buffer)?;
+			zip.write_all(&buffer)?;
+		}
+	}
+	Ok(())
+}
+
+fn zip_err(e: zip_rs::result::ZipError) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("zip: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::env;
+
+	#[test]
+	fn zip_unzip() {
+		let root = env::temp_dir().join("grin_zip_test");
+		let _ = fs::remove_dir_all(&root);
+		let src = root.join("src");
+		fs::create_dir_all(src.join("utxo")).unwrap();
+		File::create(src.join("utxo").join("pmmr_dat.bin"))
+			.unwrap()
+			.write_all(b"some mmr data")
+			.unwrap();
+		File::create(src.join("header.bin"))
+			.unwrap()
+			.write_all(b"header")
+			.unwrap();
+
+		let zip_path = root.join("archive.zip");
+		compress(&src, &File::create(&zip_path).unwrap()).unwrap();
+		let dst = root.join("dst");
+		decompress(File::open(&zip_path).unwrap(), &dst).unwrap();
+
+		let mut content = String::new();
+		File::open(dst.join("utxo").join("pmmr_dat.bin"))
+			.unwrap()
+			.read_to_string(&mut content)
+			.unwrap();
+		assert_eq!(content, "some mmr data");
+		assert!(dst.join("header.bin").exists());
+
+		let _ = fs::remove_dir_all(&root);
+	}
+}
@@ -0,0 +1,109 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named wallet accounts. Each account derives its keys on its own branch of
+//! the wallet keychain, so the outputs (and balance) of an account are only
+//! seen when it's selected. The default account uses the wallet root keys.
+
+use std::fs::{self, File};
+use std::io::Write;
+
+use keychain::Keychain;
+use types::{Account, Error, WalletConfig, WalletData, DEFAULT_ACCOUNT};
+use prettytable;
+use util;
+
+/// Keychain of the account with the provided name, which must exist.
+pub fn account_keychain(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	name: &str,
+) -> Result<Keychain, Error> {
+	let index = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.account_index(name)
+	})?;
+	match index {
+		Some(index) => Ok(keychain.derive_account(index)?),
+		None => Err(Error::GenericError(format!(
+			"Unknown account {}, create it with 'grin wallet account {}'",
+			name, name
+		))),
+	}
+}
+
+/// Writes the seed of the keys of an account to the provided file, for a
+/// mining node to build its coinbase outputs with (mining
+/// `coinbase_key_path`) without running a wallet listener. The file only
+/// gives the keys of that account, so it can't be the default one.
+pub fn export_coinbase_key(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	name: &str,
+	path: &str,
+) -> Result<(), Error> {
+	let index = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.account_index(name)
+	})?;
+	let index = match index {
+		Some(0) => {
+			return Err(Error::GenericError(
+				"The default account can't be exported, create a separate one for mining"
+					.to_string(),
+			))
+		}
+		Some(index) => index,
+		None => {
+			return Err(Error::GenericError(format!("Unknown account {}", name)))
+		}
+	};
+	let seed = keychain.account_seed(index)?;
+	let mut file = File::create(path)?;
+	// only the owner should be able to spend the account outputs
+	restrict_permissions(path)?;
+	file.write_all(util::to_hex(seed).as_bytes())?;
+	Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &str) -> Result<(), Error> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &str) -> Result<(), Error> {
+	Ok(())
+}
+
+/// Creates a new named account.
+pub fn create_account(config: &WalletConfig, name: &str) -> Result<Account, Error> {
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.add_account(name)
+	})?
+}
+
+pub fn show_accounts(config: &WalletConfig) {
+	let _ = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		println!("\n____ Wallet Accounts ____\n");
+		let mut table = table!([bFG->"Name", bFG->"Derivation Index"]);
+		table.add_row(row![DEFAULT_ACCOUNT, 0]);
+		for account in &wallet_data.accounts {
+			table.add_row(row![account.name, account.index]);
+		}
+		table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+		table.printstd();
+		println!();
+	});
+}
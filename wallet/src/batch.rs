@@ -0,0 +1,542 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Batch sends, paying several recipients in a single transaction with a
+//! single fee. Our excess gets split in random shares, one per recipient,
+//! and the interactive exchange goes on separately with each of them over
+//! their share: every recipient signs its own kernel and never sees the
+//! outputs of the others. Recipients respond with their output, like they do
+//! for a transaction sent through a file, and we complete the transaction
+//! once all of them did. The first kernel carries the whole fee, which we pay
+//! so recipients get the full amount.
+
+use std::path::{Path, PathBuf};
+
+use rand::thread_rng;
+
+use api;
+use checker;
+use client;
+use core::core::{build, amount_to_hr_string, Output, Transaction, TxKernel};
+use core::ser;
+use keychain::{Identifier, Keychain};
+use receiver::TxWrapper;
+use sender;
+use types::*;
+use util::secp::key::SecretKey;
+use util::LOGGER;
+use util;
+
+/// Sends the provided amounts to their destinations, listeners or files
+/// (file:<path>), in a single transaction. Responses from recipients paid
+/// through a file get added with finalize, the transaction being posted
+/// once all recipients responded.
+pub fn issue_batch_send_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	recipients: Vec<(u64, String)>,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	selection_strategy: SelectionStrategy,
+	fee_base: Option<u64>,
+) -> Result<(), Error> {
+	if recipients.is_empty() {
+		return Err(Error::GenericError(format!("no recipient to send to")));
+	}
+	for &(_, ref dest) in &recipients {
+		if !dest.starts_with("file:") {
+			sender::receive_url(dest)?;
+		}
+	}
+	checker::refresh_outputs(config, keychain)?;
+
+	let chain_tip = checker::get_tip_from_node(config)?;
+	let current_height = chain_tip.height;
+	let lock_height = chain_tip.height;
+	let fee_base = sender::send_fee_base(config, fee_base);
+	let amount: u64 = recipients.iter().map(|r| r.0).sum();
+
+	// the fee only gets lower with more inputs, selecting for the fee of a
+	// single one covers whatever gets selected
+	let key_id = keychain.root_key_id();
+	let max_fee = batch_fee(1, recipients.len(), fee_base);
+	let coins = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.select_coins(
+			key_id.clone(),
+			amount + max_fee,
+			current_height,
+			minimum_confirmations,
+			max_outputs,
+			selection_strategy,
+		)
+	})?;
+	sender::check_funds(
+		config,
+		&key_id,
+		&coins,
+		amount + max_fee,
+		current_height,
+		minimum_confirmations,
+	)?;
+	let fee = batch_fee(coins.len(), recipients.len(), fee_base);
+	let total: u64 = coins.iter().map(|c| c.value).sum();
+	let change = total - amount - fee;
+	let change_key = sender::add_change_output(config, keychain, change)?;
+
+	let res = start_parts(
+		config,
+		keychain,
+		&recipients,
+		&coins,
+		change,
+		&change_key,
+		fee,
+		lock_height,
+	);
+	let (parts, unreachable) = match res {
+		Ok(res) => res,
+		Err(e) => {
+			info!(LOGGER, "cleaning up unused change output from wallet");
+			WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+				wallet_data.delete_output(&change_key);
+			})?;
+			return Err(e);
+		}
+	};
+
+	// lock the coins being spent until the batch confirms, it can't be
+	// reposted but gets cancelled like any other send
+	let complete = parts.iter().all(|p| p.response.is_some());
+	let id = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for coin in &coins {
+			wallet_data.lock_output(coin);
+		}
+		let mut entry = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Sent, fee);
+		entry.amount_debited = total;
+		entry.amount_credited = change;
+		entry.inputs = coins.iter().map(|c| c.key_id.clone()).collect();
+		entry.outputs = vec![change_key.clone()];
+		wallet_data.add_tx_log_entry(entry);
+		wallet_data.add_pending_send(PendingSend {
+			id: 0,
+			amount: amount,
+			fee: fee,
+			lock_height: lock_height,
+			dest: recipients
+				.iter()
+				.map(|r| r.1.clone())
+				.collect::<Vec<_>>()
+				.join(", "),
+			inputs: coins.iter().map(|c| c.key_id.clone()).collect(),
+			change: change_key.clone(),
+			replaced_changes: vec![],
+			file_context: None,
+			kernels: vec![],
+			batch: parts,
+		})
+	})?;
+
+	for (n, partial_tx) in unreachable {
+		let path = slate_path(config, id, n);
+		sender::write_slate(&partial_tx, &path)?;
+		warn!(
+			LOGGER,
+			"Recipient {} unreachable, hand {} over to it and finalize its response",
+			recipients[n].1,
+			path.display()
+		);
+	}
+	if complete {
+		return complete_batch_send(config, keychain, id);
+	}
+	info!(
+		LOGGER,
+		"Batch of {} to {} recipients pending as {} until finalized with the responses \
+		 of its recipients",
+		amount_to_hr_string(amount),
+		recipients.len(),
+		id
+	);
+	Ok(())
+}
+
+/// Adds the response of a recipient of a batch send, as written by wallet
+/// receive, completing and posting the transaction once all recipients
+/// responded.
+pub fn finalize_batch_part(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+	path: &str,
+) -> Result<(), Error> {
+	let (_, _, _, _, tx) = read_partial_tx(keychain, partial_tx)?;
+	let send = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data
+			.pending_sends
+			.iter()
+			.find(|send| {
+				!send.batch.is_empty() && sender::spends_inputs(wallet_data, keychain, &send.inputs, &tx)
+			})
+			.cloned()
+	})?;
+	let send = match send {
+		Some(send) => send,
+		None => {
+			return Err(Error::GenericError(format!(
+				"no pending batch matches the response in {}",
+				path
+			)))
+		}
+	};
+
+	// the part the response is for is the one which share it signs with
+	let n = (0..send.batch.len()).find(|&n| {
+		let part = &send.batch[n];
+		part.response.is_none()
+			&& check_response(keychain, part, part_fee(n, send.fee), send.lock_height, partial_tx)
+				.is_ok()
+	});
+	let n = match n {
+		Some(n) => n,
+		None => {
+			return Err(Error::GenericError(format!(
+				"no recipient of batch {} waits on the response in {}",
+				send.id, path
+			)))
+		}
+	};
+	let complete = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		let mut complete = false;
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == send.id {
+				pending.batch[n].response = Some(partial_tx.clone());
+				complete = pending.batch.iter().all(|p| p.response.is_some());
+			}
+		}
+		complete
+	})?;
+	let _ = ::std::fs::remove_file(slate_path(config, send.id, n));
+	if complete {
+		return complete_batch_send(config, keychain, send.id);
+	}
+	info!(
+		LOGGER,
+		"Response of {} added to batch {}, still waiting on other recipients",
+		send.batch[n].dest,
+		send.id
+	);
+	Ok(())
+}
+
+// Fee of a batch transaction, one more output and kernel per recipient on
+// top of our change.
+fn batch_fee(input_len: usize, recipients: usize, fee_base: u64) -> u64 {
+	tx_fee(input_len, recipients + 1, Some(fee_base)) + (recipients as u64 - 1) * fee_base
+}
+
+// Fee signed by the kernel of a part, the first one carrying the whole fee.
+fn part_fee(n: usize, fee: u64) -> u64 {
+	if n == 0 {
+		fee
+	} else {
+		0
+	}
+}
+
+// Where the transaction for a recipient of a batch which listener couldn't
+// be reached is kept, to hand it over as a file.
+fn slate_path(config: &WalletConfig, id: u32, n: usize) -> PathBuf {
+	Path::new(&config.data_file_dir)
+		.join("slates")
+		.join(format!("send_{}_{}.json", id, n))
+}
+
+// Builds the transaction shared by all recipients, spending our coins to our
+// change, and goes through the first step of the exchange with each
+// recipient over its share of our excess. Listener responses get checked
+// right away, the initiations of unreachable listeners are returned to be
+// handed over as files.
+fn start_parts(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	recipients: &Vec<(u64, String)>,
+	coins: &Vec<OutputData>,
+	change: u64,
+	change_key: &Identifier,
+	fee: u64,
+	lock_height: u64,
+) -> Result<(Vec<BatchPart>, Vec<(usize, PartialTx)>), Error> {
+	let mut elems = sender::input_parts(coins, keychain)?;
+	elems.push(build::with_fee(fee));
+	elems.push(build::output(change, change_key.clone()));
+	elems.push(build::with_lock_height(lock_height));
+	let (tx, blind_sum) = build::transaction(elems, keychain)?;
+
+	// random shares for all recipients but the first, which gets what's left
+	// for the shares to sum to our excess
+	let secp = keychain.secp();
+	let mut shares = vec![];
+	for _ in 1..recipients.len() {
+		shares.push(SecretKey::new(secp, &mut thread_rng()));
+	}
+	let first = secp.blind_sum(vec![blind_sum.secret_key()], shares.clone())?;
+	shares.insert(0, first);
+
+	let mut parts = vec![];
+	let mut unreachable = vec![];
+	for (n, &(amount, ref dest)) in recipients.iter().enumerate() {
+		let mut part_tx = tx.clone();
+		part_tx.kernels[0].fee = part_fee(n, fee);
+
+		keychain.aggsig_create_context(shares[n].clone());
+		let mut partial_tx = build_partial_tx(keychain, amount, None, part_tx);
+		partial_tx.batch = true;
+		let mut part = BatchPart {
+			amount: amount,
+			dest: dest.clone(),
+			context: Some(sender::send_context(keychain)?),
+			response: None,
+		};
+
+		if dest.starts_with("file:") {
+			sender::write_slate(&partial_tx, Path::new(&dest[5..]))?;
+			info!(LOGGER, "Transaction for {} written to {}", amount_to_hr_string(amount), &dest[5..]);
+		} else {
+			let url = sender::receive_url(dest)?;
+			match client::send_partial_tx(config, &url, &partial_tx) {
+				Ok(response) => {
+					check_response(keychain, &part, part_fee(n, fee), lock_height, &response)?;
+					part.response = Some(response);
+				}
+				Err(ref e) if client::is_unreachable(e) => unreachable.push((n, partial_tx)),
+				Err(e) => {
+					sender::log_send_error(&e, "SenderInitiation");
+					return Err(e);
+				}
+			}
+		}
+		parts.push(part);
+	}
+	Ok((parts, unreachable))
+}
+
+// Checks the response of a recipient is for the amount of the part and
+// signed over our share of the excess, restoring the context of the part.
+fn check_response(
+	keychain: &Keychain,
+	part: &BatchPart,
+	fee: u64,
+	lock_height: u64,
+	response: &PartialTx,
+) -> Result<(), Error> {
+	let context = match part.context {
+		Some(ref context) => context,
+		None => return Err(Error::GenericError(format!("batch part already completed"))),
+	};
+	sender::restore_context(keychain, context)?;
+	let (amount, recp_pub_blinding, recp_pub_nonce, sig, _) = read_partial_tx(keychain, response)?;
+	let sig = match sig {
+		Some(sig) => sig,
+		None => {
+			return Err(Error::Signature(String::from(
+				"Response from recipient has no partial signature.",
+			)))
+		}
+	};
+	if amount != part.amount {
+		return Err(Error::GenericError(format!(
+			"response amount {} doesn't match the amount sent {}",
+			amount, part.amount
+		)));
+	}
+	if !keychain.aggsig_verify_partial_sig(&sig, &recp_pub_nonce, &recp_pub_blinding, fee, lock_height) {
+		return Err(Error::Signature(String::from("Partial Sig from recipient invalid.")));
+	}
+	Ok(())
+}
+
+// Completes the kernels of all parts of a batch, once all recipients
+// responded, and posts the transaction with the outputs of all of them.
+fn complete_batch_send(config: &WalletConfig, keychain: &Keychain, id: u32) -> Result<(), Error> {
+	let (send, change) = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		let send = wallet_data.get_pending_send(Some(id)).cloned();
+		let change = send.as_ref().and_then(|s| wallet_data.get_output(&s.change).cloned());
+		(send, change)
+	})?;
+	let (send, change) = match (send, change) {
+		(Some(send), Some(change)) => (send, change),
+		_ => {
+			return Err(Error::WalletData(format!(
+				"pending batch {} or its change output not found",
+				id
+			)))
+		}
+	};
+	let change_commit = keychain.commit(change.value, &send.change)?;
+
+	let mut final_tx: Option<Transaction> = None;
+	let mut outputs: Vec<Output> = vec![];
+	let mut kernels: Vec<TxKernel> = vec![];
+	let mut excesses = vec![];
+	let mut proofs = vec![];
+	for (n, part) in send.batch.iter().enumerate() {
+		let response = match part.response {
+			Some(ref response) => response,
+			None => {
+				return Err(Error::GenericError(format!(
+					"batch {} still waits on {}",
+					id, part.dest
+				)))
+			}
+		};
+		let fee = part_fee(n, send.fee);
+		check_response(keychain, part, fee, send.lock_height, response)?;
+		let (_, recp_pub_blinding, recp_pub_nonce, recp_sig, tx) = read_partial_tx(keychain, response)?;
+		let recp_sig = recp_sig.unwrap();
+
+		let our_sig = keychain.aggsig_calculate_partial_sig(&recp_pub_nonce, fee, send.lock_height)?;
+		let final_sig = keychain.aggsig_calculate_final_sig(&recp_sig, &our_sig, &recp_pub_nonce)?;
+		let final_pubkey = keychain.aggsig_calculate_final_pubkey(&recp_pub_blinding)?;
+		if !keychain.aggsig_verify_final_sig_build_msg(&final_sig, &final_pubkey, fee, send.lock_height) {
+			error!(LOGGER, "Final aggregated signature invalid.");
+			return Err(Error::Signature(String::from("Final aggregated signature invalid.")));
+		}
+		proofs.push(sender::check_payment_proof(keychain, response, part.amount, &final_pubkey)?);
+
+		// the recipient output is the one that isn't our change
+		let received = tx.outputs
+			.iter()
+			.filter(|o| o.commitment() != change_commit)
+			.cloned()
+			.collect::<Vec<_>>();
+		if received.len() != 1 || tx.outputs.len() != 2 {
+			return Err(Error::GenericError(format!(
+				"response from {} doesn't include its output",
+				part.dest
+			)));
+		}
+
+		// the kernel excess is our share plus the recipient output blinding
+		let (share, _) = keychain.aggsig_get_private_keys();
+		let excess = {
+			let secp = keychain.secp();
+			secp.commit_sum(
+				vec![received[0].commitment(), secp.commit(0, share)?],
+				vec![secp.commit_value(part.amount)?],
+			)?
+		};
+		let mut kernel = tx.kernels[0].clone();
+		kernel.fee = fee;
+		kernel.lock_height = send.lock_height;
+		kernel.excess = excess;
+		kernel.excess_sig = final_sig;
+		kernels.push(kernel);
+		outputs.push(received[0].clone());
+		excesses.push(public_excess_hex(keychain, &final_pubkey));
+
+		if final_tx.is_none() {
+			let ours = tx.outputs
+				.iter()
+				.filter(|o| o.commitment() == change_commit)
+				.cloned()
+				.collect::<Vec<_>>();
+			final_tx = Some(Transaction::new(tx.inputs.clone(), ours, vec![]));
+		}
+	}
+
+	let mut final_tx = final_tx.unwrap();
+	for output in outputs {
+		final_tx = final_tx.with_output(output);
+	}
+	for kernel in kernels {
+		final_tx = final_tx.with_kernel(kernel);
+	}
+	final_tx.validate()?;
+	check_inputs(config, keychain, &send, &final_tx)?;
+	let kernel_excesses = final_tx
+		.kernels
+		.iter()
+		.map(|k| util::to_hex(k.excess.0.to_vec()))
+		.collect::<Vec<_>>();
+
+	let tx_hex = util::to_hex(ser::ser_vec(&final_tx).unwrap());
+	let url = format!("{}/v1/pool/push", config.check_node_api_http_addr.as_str());
+	api::client::post_with_secret(
+		url.as_str(),
+		&TxWrapper { tx_hex: tx_hex },
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))?;
+
+	// our shares must never sign again, the batch now just waits for
+	// confirmation
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == id {
+				for part in pending.batch.iter_mut() {
+					part.context = None;
+				}
+				pending.kernels = kernel_excesses.clone();
+			}
+		}
+		if let Some(entry) = wallet_data.sent_tx_log_entry_mut(&send.inputs) {
+			entry.excess = excesses.first().cloned();
+			entry.batch_payment_proofs = proofs;
+		}
+	})?;
+	info!(
+		LOGGER,
+		"Batch {} completed and posted, sending {} to {} recipients",
+		id,
+		amount_to_hr_string(send.amount),
+		send.batch.len()
+	);
+	Ok(())
+}
+
+// Checks the completed transaction spends exactly the inputs of the batch,
+// all recipients having been handed the same ones.
+fn check_inputs(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	send: &PendingSend,
+	tx: &Transaction,
+) -> Result<(), Error> {
+	let spends = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		sender::spends_inputs(wallet_data, keychain, &send.inputs, tx)
+	})?;
+	if !spends || tx.inputs.len() != send.inputs.len() {
+		return Err(Error::GenericError(format!(
+			"responses to batch {} don't spend its inputs",
+			send.id
+		)));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::batch_fee;
+	use types::{tx_fee, DEFAULT_BASE_FEE};
+
+	#[test]
+	fn batch_fee_per_recipient() {
+		// a batch to a single recipient pays as much as a regular send
+		assert_eq!(
+			batch_fee(2, 1, DEFAULT_BASE_FEE),
+			tx_fee(2, 2, Some(DEFAULT_BASE_FEE))
+		);
+		// each more recipient adds an output and a kernel
+		assert_eq!(batch_fee(2, 3, DEFAULT_BASE_FEE), 17 * DEFAULT_BASE_FEE);
+	}
+}
@@ -20,6 +20,7 @@ use std::collections::HashMap;
 
 use api;
 use core::core::hash::Hash;
+use core::global;
 use types::*;
 use keychain::{Identifier, Keychain};
 use util::secp::pedersen;
@@ -111,7 +112,10 @@ fn refresh_missing_block_hashes(config: &WalletConfig, keychain: &Keychain) -> R
 	debug!(LOGGER, "{:?}", url);
 
 	let mut api_blocks: HashMap<pedersen::Commitment, api::BlockHeaderInfo> = HashMap::new();
-	match api::client::get::<Vec<api::BlockOutputs>>(url.as_str()) {
+	match api::client::get_with_secret::<Vec<api::BlockOutputs>>(
+		url.as_str(),
+		config.node_api_secret(),
+	) {
 		Ok(blocks) => {
 			for block in blocks {
 				for out in block.outputs {
@@ -138,9 +142,14 @@ fn refresh_missing_block_hashes(config: &WalletConfig, keychain: &Keychain) -> R
 					let output = output.get_mut();
 					output.block = BlockIdentifier::from_str(&b.hash).unwrap();
 					output.height = b.height;
+					// coinbase maturity counts from the block it was mined in
+					if output.is_coinbase {
+						output.lock_height = b.height + global::coinbase_maturity();
+					}
 				}
 			}
 		}
+		wallet_data.update_tx_log();
 	})
 }
 
@@ -186,7 +195,10 @@ fn refresh_output_state(config: &WalletConfig, keychain: &Keychain) -> Result<()
 		config.check_node_api_http_addr, query_string,
 	);
 
-	match api::client::get::<Vec<api::Utxo>>(url.as_str()) {
+	match api::client::get_with_secret::<Vec<api::Utxo>>(
+		url.as_str(),
+		config.node_api_secret(),
+	) {
 		Ok(outputs) => for out in outputs {
 			api_utxos.insert(out.commit.commit(), out);
 		},
@@ -201,18 +213,51 @@ fn refresh_output_state(config: &WalletConfig, keychain: &Keychain) -> Result<()
 	// the corresponding api output (if it exists)
 	// and refresh it in-place in the wallet.
 	// Note: minimizing the time we spend holding the wallet lock.
-	WalletData::with_wallet(&config.data_file_dir, |wallet_data| for commit in wallet_outputs.keys() {
-		let id = wallet_outputs.get(&commit).unwrap();
-		if let Entry::Occupied(mut output) = wallet_data.outputs.entry(id.to_hex()) {
-			match api_utxos.get(&commit) {
-				Some(_) => mark_unspent_output(&mut output.get_mut()),
-				None => mark_spent_output(&mut output.get_mut()),
-			};
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for commit in wallet_outputs.keys() {
+			let id = wallet_outputs.get(&commit).unwrap();
+			if let Entry::Occupied(mut output) = wallet_data.outputs.entry(id.to_hex()) {
+				match api_utxos.get(&commit) {
+					Some(_) => mark_unspent_output(&mut output.get_mut()),
+					None => mark_spent_output(&mut output.get_mut()),
+				};
+			}
 		}
+		wallet_data.update_tx_log();
 	})
 }
 
+/// Fee per weight the node suggests for a transaction to get mined within
+/// the provided number of blocks
+pub fn get_fee_estimate(config: &WalletConfig, blocks: u64) -> Result<api::FeeEstimate, Error> {
+	let url = format!(
+		"{}/v1/fees/estimate?blocks={}",
+		config.check_node_api_http_addr, blocks
+	);
+	api::client::get_with_secret::<api::FeeEstimate>(
+		url.as_str(),
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))
+}
+
+/// Whether the node has the kernel with the provided excess commitment (hex)
+/// in its chain
+pub fn is_kernel_on_chain(config: &WalletConfig, excess: &str) -> Result<bool, Error> {
+	let url = format!(
+		"{}/v1/chain/kernels/{}",
+		config.check_node_api_http_addr, excess
+	);
+	match api::client::get_with_secret::<api::Kernel>(url.as_str(), config.node_api_secret()) {
+		Ok(_) => Ok(true),
+		Err(api::Error::NotFound) => Ok(false),
+		Err(e) => Err(Error::Node(e)),
+	}
+}
+
 pub fn get_tip_from_node(config: &WalletConfig) -> Result<api::Tip, Error> {
 	let url = format!("{}/v1/chain", config.check_node_api_http_addr);
-	api::client::get::<api::Tip>(url.as_str()).map_err(|e| Error::Node(e))
+	api::client::get_with_secret::<api::Tip>(
+		url.as_str(),
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))
 }
@@ -13,17 +13,22 @@
 // limitations under the License.
 
 use iron::prelude::*;
-use iron::Handler;
+use iron::{BeforeMiddleware, Handler};
 use iron::status;
 use serde_json;
 use bodyparser;
 
+use info::retrieve_info;
+use locks::{lock_outputs, retrieve_locks, unlock_outputs};
 use receiver::receive_coinbase;
+use sender::{cancel_send_tx, issue_send_tx};
+use txs::retrieve_txs;
 use core::ser;
 use api;
 use keychain::Keychain;
 use types::*;
 use util;
+use util::LOGGER;
 
 
 pub struct CoinbaseHandler {
@@ -80,3 +85,179 @@ impl Handler for CoinbaseHandler {
 		}
 	}
 }
+
+/// Lets only the requests coming from the local machine through. Guards the
+/// owner endpoints, which shouldn't be reachable by others even when the
+/// listener is.
+pub struct LocalOnly;
+
+impl BeforeMiddleware for LocalOnly {
+	fn before(&self, req: &mut Request) -> IronResult<()> {
+		if req.remote_addr.ip().is_loopback() {
+			Ok(())
+		} else {
+			warn!(LOGGER, "Rejected owner API request from {}", req.remote_addr);
+			Err(IronError::new(api::Error::Unauthorized, status::Forbidden))
+		}
+	}
+}
+
+/// Balance of the wallet, by output status.
+/// GET /v1/wallet/owner/balance
+pub struct BalanceHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for BalanceHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		let info = retrieve_info(&self.config, &self.keychain, 1).map_err(|e| {
+			api::Error::Internal(format!("Error reading the wallet: {:?}", e))
+		})?;
+		match serde_json::to_string_pretty(&info) {
+			Ok(json) => Ok(Response::with((status::Ok, json))),
+			Err(e) => Err(IronError::new(e, status::InternalServerError)),
+		}
+	}
+}
+
+/// Builds a transaction sending coins to the provided destination, same as
+/// the wallet send command.
+/// POST /v1/wallet/owner/send
+pub struct SendTxHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for SendTxHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let args = match req.get::<bodyparser::Struct<SendTxArgs>>() {
+			Ok(Some(args)) => args,
+			_ => return Ok(Response::with((status::BadRequest, "invalid send parameters"))),
+		};
+		let selection_strategy = match args.selection_strategy.parse() {
+			Ok(strategy) => strategy,
+			Err(e) => return Ok(Response::with((status::BadRequest, format!("{:?}", e)))),
+		};
+		let res = issue_send_tx(
+			&self.config,
+			&self.keychain,
+			args.amount,
+			args.minimum_confirmations,
+			args.dest,
+			args.max_outputs,
+			selection_strategy,
+			args.fee_base,
+		);
+		match res {
+			Ok(_) => Ok(Response::with(status::Ok)),
+			Err(e) => Ok(Response::with((status::BadRequest, format!("{:?}", e)))),
+		}
+	}
+}
+
+/// Cancels a pending sent transaction, unlocking its inputs.
+/// POST /v1/wallet/owner/cancel_tx/3
+pub struct CancelTxHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for CancelTxHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let url = req.url.clone();
+		let id = match url.path().last().and_then(|id| id.parse::<u32>().ok()) {
+			Some(id) => id,
+			None => return Ok(Response::with((status::BadRequest, "invalid transaction id"))),
+		};
+		match cancel_send_tx(&self.config, &self.keychain, id) {
+			Ok(_) => Ok(Response::with(status::Ok)),
+			Err(e) => Ok(Response::with((status::BadRequest, format!("{:?}", e)))),
+		}
+	}
+}
+
+/// Lists the transactions in the wallet log, with their status.
+/// GET /v1/wallet/owner/txs
+pub struct TxsHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for TxsHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		let txs = retrieve_txs(&self.config, &self.keychain).map_err(|e| {
+			api::Error::Internal(format!("Error reading transaction log: {:?}", e))
+		})?;
+		match serde_json::to_string_pretty(&txs) {
+			Ok(json) => Ok(Response::with((status::Ok, json))),
+			Err(e) => Err(IronError::new(e, status::InternalServerError)),
+		}
+	}
+}
+
+/// Locks outputs, leaving them out of coin selection until unlocked or
+/// expired.
+/// POST /v1/wallet/owner/lock_outputs
+pub struct LockOutputsHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for LockOutputsHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let args = match req.get::<bodyparser::Struct<LockOutputsArgs>>() {
+			Ok(Some(args)) => args,
+			_ => return Ok(Response::with((status::BadRequest, "invalid lock parameters"))),
+		};
+		let res = lock_outputs(
+			&self.config,
+			&self.keychain,
+			&args.key_ids,
+			&args.reason,
+			args.expires_in_secs,
+		);
+		match res {
+			Ok(_) => Ok(Response::with(status::Ok)),
+			Err(e) => Ok(Response::with((status::BadRequest, format!("{:?}", e)))),
+		}
+	}
+}
+
+/// Releases locked outputs, responding with how many were locked.
+/// POST /v1/wallet/owner/unlock_outputs
+pub struct UnlockOutputsHandler {
+	pub config: WalletConfig,
+}
+
+impl Handler for UnlockOutputsHandler {
+	fn handle(&self, req: &mut Request) -> IronResult<Response> {
+		let args = match req.get::<bodyparser::Struct<UnlockOutputsArgs>>() {
+			Ok(Some(args)) => args,
+			_ => return Ok(Response::with((status::BadRequest, "invalid unlock parameters"))),
+		};
+		match unlock_outputs(&self.config, &args.key_ids) {
+			Ok(unlocked) => Ok(Response::with((status::Ok, unlocked.to_string()))),
+			Err(e) => Ok(Response::with((status::BadRequest, format!("{:?}", e)))),
+		}
+	}
+}
+
+/// Lists the locked outputs, with the reason and expiration of their lock.
+/// GET /v1/wallet/owner/locked_outputs
+pub struct LockedOutputsHandler {
+	pub config: WalletConfig,
+	pub keychain: Keychain,
+}
+
+impl Handler for LockedOutputsHandler {
+	fn handle(&self, _req: &mut Request) -> IronResult<Response> {
+		let locks = retrieve_locks(&self.config, &self.keychain).map_err(|e| {
+			api::Error::Internal(format!("Error reading the wallet: {:?}", e))
+		})?;
+		match serde_json::to_string_pretty(&locks) {
+			Ok(json) => Ok(Response::with((status::Ok, json))),
+			Err(e) => Err(IronError::new(e, status::InternalServerError)),
+		}
+	}
+}
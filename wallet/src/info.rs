@@ -13,60 +13,131 @@
 // limitations under the License.
 
 use checker;
-use keychain::Keychain;
+use keychain::{Identifier, Keychain};
 use core::core::amount_to_hr_string;
-use types::{WalletConfig, WalletData, OutputStatus};
+use types::{Error, WalletConfig, WalletData, WalletInfo, OutputStatus};
 use prettytable;
+use time;
 
-pub fn show_info(config: &WalletConfig, keychain: &Keychain) {
+/// Balance of the wallet, after checking its outputs against the node if
+/// it's reachable. Outputs with less than the minimum number of
+/// confirmations are still awaiting confirmation.
+pub fn retrieve_info(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	minimum_confirmations: u64,
+) -> Result<WalletInfo, Error> {
 	let result = checker::refresh_outputs(&config, &keychain);
 
-	let _ = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
-		let (current_height, from) = match checker::get_tip_from_node(config) {
-			Ok(tip) => (tip.height, "from server node"),
+	WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		let current_height = match checker::get_tip_from_node(config) {
+			Ok(tip) => tip.height,
 			Err(_) => match wallet_data.outputs.values().map(|out| out.height).max() {
-				Some(height) => (height, "from wallet"),
-				None => (0, "node/wallet unavailable"),
+				Some(height) => height,
+				None => 0,
 			},
 		};
-		let mut unspent_total = 0;
-		let mut unspent_but_locked_total = 0;
-		let mut unconfirmed_total = 0;
-		let mut locked_total = 0;
-		for out in wallet_data
-			.outputs
-			.values()
-			.filter(|out| out.root_key_id == keychain.root_key_id())
-		{
-			if out.status == OutputStatus::Unspent {
-				unspent_total += out.value;
+		WalletInfo {
+			data_confirmed: result.is_ok(),
+			..balance(
+				wallet_data,
+				&keychain.root_key_id(),
+				current_height,
+				minimum_confirmations,
+			)
+		}
+	})
+}
+
+/// Balance of the wallet outputs derived from the provided root key, at the
+/// provided height. Spendable outputs are the ones coin selection picks
+/// from, with at least the minimum number of confirmations and past their
+/// lock height (coinbase maturity). Outputs locked by the owner count as
+/// locked.
+pub fn balance(
+	wallet_data: &WalletData,
+	root_key_id: &Identifier,
+	current_height: u64,
+	minimum_confirmations: u64,
+) -> WalletInfo {
+	let mut total = 0;
+	let mut awaiting_confirmation = 0;
+	let mut confirmed_but_locked = 0;
+	let mut spendable = 0;
+	let mut locked = 0;
+	let now = time::now_utc().to_timespec().sec;
+	for out in wallet_data
+		.outputs
+		.values()
+		.filter(|out| out.root_key_id == *root_key_id)
+	{
+		if out.status != OutputStatus::Spent && wallet_data.is_manually_locked(&out.key_id, now) {
+			locked += out.value;
+			continue;
+		}
+		match out.status {
+			OutputStatus::Unspent => {
+				total += out.value;
 				if out.lock_height > current_height {
-						unspent_but_locked_total += out.value;
+					confirmed_but_locked += out.value;
+				} else if out.eligible_to_spend(current_height, minimum_confirmations) {
+					spendable += out.value;
+				} else {
+					awaiting_confirmation += out.value;
 				}
 			}
-			if out.status == OutputStatus::Unconfirmed && !out.is_coinbase {
-				unconfirmed_total += out.value;
-			}
-			if out.status == OutputStatus::Locked {
-				locked_total += out.value;
+			OutputStatus::Unconfirmed if !out.is_coinbase => {
+				total += out.value;
+				if out.eligible_to_spend(current_height, minimum_confirmations) {
+					spendable += out.value;
+				} else {
+					awaiting_confirmation += out.value;
+				}
 			}
-		};
+			OutputStatus::Locked => locked += out.value,
+			_ => {}
+		}
+	}
 
-		println!("\n____ Wallet Summary Info at {} ({}) ____\n", current_height, from);
-		let mut table = table!(
-			[bFG->"Total", FG->amount_to_hr_string(unspent_total+unconfirmed_total)],
-			[bFY->"Awaiting Confirmation", FY->amount_to_hr_string(unconfirmed_total)],
-			[bFY->"Confirmed but Still Locked", FY->amount_to_hr_string(unspent_but_locked_total)],
-			[bFG->"Currently Spendable", FG->amount_to_hr_string(unspent_total-unspent_but_locked_total)],
-			[Fw->"---------", Fw->"---------"],
-			[Fr->"(Locked by previous transaction)", Fr->amount_to_hr_string(locked_total)]
-		);
-		table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-		table.printstd();
-		println!();
-	});
+	WalletInfo {
+		current_height: current_height,
+		data_confirmed: true,
+		total: total,
+		amount_awaiting_confirmation: awaiting_confirmation,
+		amount_confirmed_but_locked: confirmed_but_locked,
+		amount_currently_spendable: spendable,
+		amount_locked: locked,
+	}
+}
+
+pub fn show_info(config: &WalletConfig, keychain: &Keychain, minimum_confirmations: u64) {
+	let info = match retrieve_info(config, keychain, minimum_confirmations) {
+		Ok(info) => info,
+		Err(e) => {
+			println!("Could not read the wallet: {:?}", e);
+			return;
+		}
+	};
+
+	let from = if info.data_confirmed {
+		"from server node"
+	} else {
+		"from wallet"
+	};
+	println!("\n____ Wallet Summary Info at {} ({}) ____\n", info.current_height, from);
+	let mut table = table!(
+		[bFG->"Total", FG->amount_to_hr_string(info.total)],
+		[bFY->"Awaiting Confirmation", FY->amount_to_hr_string(info.amount_awaiting_confirmation)],
+		[bFY->"Confirmed but Still Locked", FY->amount_to_hr_string(info.amount_confirmed_but_locked)],
+		[bFG->"Currently Spendable", FG->amount_to_hr_string(info.amount_currently_spendable)],
+		[Fw->"---------", Fw->"---------"],
+		[Fr->"(Locked by previous transaction or owner)", Fr->amount_to_hr_string(info.amount_locked)]
+	);
+	table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+	table.printstd();
+	println!();
 
-	if let Err(_) = result {
+	if !info.data_confirmed {
 		println!(
 		"\nWARNING: Failed to verify wallet contents with grin server. \
 		 Above info is maybe not fully updated or invalid! \
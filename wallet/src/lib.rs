@@ -33,6 +33,7 @@ extern crate hyper;
 extern crate iron;
 #[macro_use]
 extern crate router;
+extern crate time;
 extern crate tokio_core;
 extern crate tokio_retry;
 
@@ -41,20 +42,35 @@ extern crate grin_core as core;
 extern crate grin_keychain as keychain;
 extern crate grin_util as util;
 
+mod accounts;
+mod batch;
 mod checker;
 mod handlers;
 mod outputs;
 mod info;
+mod locks;
 mod receiver;
 mod sender;
+mod swap;
 mod types;
 mod restore;
+mod txs;
 pub mod client;
 pub mod server;
 
+pub use accounts::{account_keychain, create_account, export_coinbase_key, show_accounts};
+pub use batch::issue_batch_send_tx;
 pub use outputs::show_outputs;
-pub use info::show_info;
-pub use receiver::{WalletReceiver};
-pub use sender::{issue_burn_tx, issue_send_tx};
-pub use types::{BlockFees, CbData, Error, WalletConfig, WalletReceiveRequest, WalletSeed};
+pub use info::{retrieve_info, show_info};
+pub use locks::{lock_outputs, retrieve_locks, unlock_outputs};
+pub use receiver::{receive_file_tx, WalletReceiver};
+pub use sender::{cancel_send_tx, finalize_tx_file, issue_burn_tx, issue_send_tx,
+	repost_send_tx, resume_send_tx};
+pub use types::{Account, BlockFees, CbData, Error, LockOutputsArgs, LockedOutput, PartialTx,
+	PaymentProof, SelectionStrategy, SendTxArgs, TxLogEntry, TxLogEntryType, TxStatus,
+	UnlockOutputsArgs, WalletConfig, WalletInfo, WalletReceiveRequest, WalletSeed,
+	DEFAULT_ACCOUNT};
 pub use restore::restore;
+pub use swap::{complete_swap_tx, confirm_swap_tx, receive_swap_tx, swap_secret};
+pub use txs::{payment_proof, payment_proof_address, retrieve_txs, show_txs,
+	verify_payment_proof};
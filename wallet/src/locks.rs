@@ -0,0 +1,93 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Outputs locked by the wallet owner, left out of coin selection until
+//! unlocked or expired. Lets integrations building several transactions at
+//! once outside of the wallet (like exchange withdrawals) reserve the inputs
+//! of each, so no other transaction picks them.
+
+use keychain::{Identifier, Keychain};
+use time;
+use types::{Error, LockedOutput, WalletConfig, WalletData};
+use util::LOGGER;
+
+/// Locks the outputs of the current account with the provided key
+/// identifiers (hex), with the reason recorded and an optional expiration.
+/// Either all of them get locked or none.
+pub fn lock_outputs(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	key_ids: &[String],
+	reason: &str,
+	expires_in_secs: Option<u64>,
+) -> Result<(), Error> {
+	let root_key_id = keychain.root_key_id();
+	let now = time::now_utc().to_timespec().sec;
+	let expires_at = expires_in_secs.map(|secs| now + secs as i64);
+	let res = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		let mut ids = vec![];
+		for hex in key_ids {
+			match wallet_data.outputs.get(hex) {
+				Some(out) if out.root_key_id == root_key_id => ids.push(out.key_id.clone()),
+				_ => return Err(Error::GenericError(format!("no output {} in the wallet", hex))),
+			}
+		}
+		wallet_data.lock_outputs_manually(&ids, reason, now, expires_at)
+	})?;
+	res?;
+	info!(LOGGER, "Locked {} outputs: {}", key_ids.len(), reason);
+	Ok(())
+}
+
+/// Releases the owner locks on the outputs with the provided key identifiers
+/// (hex), returning how many were locked.
+pub fn unlock_outputs(config: &WalletConfig, key_ids: &[String]) -> Result<usize, Error> {
+	let now = time::now_utc().to_timespec().sec;
+	let unlocked = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		let ids = wallet_data
+			.output_locks
+			.iter()
+			.filter(|lock| key_ids.contains(&lock.key_id.to_hex()))
+			.map(|lock| lock.key_id.clone())
+			.collect::<Vec<Identifier>>();
+		wallet_data.unlock_outputs_manually(&ids, now)
+	})?;
+	info!(LOGGER, "Unlocked {} outputs", unlocked);
+	Ok(unlocked)
+}
+
+/// Outputs of the current account locked by the owner, the expired locks
+/// left out.
+pub fn retrieve_locks(config: &WalletConfig, keychain: &Keychain) -> Result<Vec<LockedOutput>, Error> {
+	let root_key_id = keychain.root_key_id();
+	let now = time::now_utc().to_timespec().sec;
+	WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data
+			.output_locks
+			.iter()
+			.filter(|lock| lock.is_active(now))
+			.filter_map(|lock| match wallet_data.get_output(&lock.key_id) {
+				Some(out) if out.root_key_id == root_key_id => Some(LockedOutput {
+					key_id: lock.key_id.clone(),
+					value: out.value,
+					status: out.status.clone(),
+					reason: lock.reason.clone(),
+					locked_at: lock.locked_at,
+					expires_at: lock.expires_at,
+				}),
+				_ => None,
+			})
+			.collect()
+	})
+}
@@ -29,6 +29,7 @@ use core::{global, ser};
 use keychain::{Identifier, Keychain};
 use types::*;
 use util::{LOGGER, to_hex, secp};
+use util::secp::key::PublicKey;
 
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
@@ -49,37 +50,14 @@ pub struct TxWrapper {
 fn handle_sender_initiation(
 	config: &WalletConfig,
 	keychain: &Keychain,
-	partial_tx: &PartialTx
+	partial_tx: &PartialTx,
+	adaptor_point: Option<&PublicKey>,
 ) -> Result<PartialTx, Error> {
-	let (amount, _sender_pub_blinding, sender_pub_nonce, _sig, tx) = read_partial_tx(keychain, partial_tx)?;
+	let (amount, sender_pub_blinding, sender_pub_nonce, _sig, tx) = read_partial_tx(keychain, partial_tx)?;
 
 	let root_key_id = keychain.root_key_id();
 
-	// double check the fee amount included in the partial tx
-	// we don't necessarily want to just trust the sender
-	// we could just overwrite the fee here (but we won't) due to the ecdsa sig
-	let fee = tx_fee(tx.inputs.len(), tx.outputs.len() + 1, None);
-	if fee != tx.fee {
-		return Err(Error::FeeDispute {
-			sender_fee: tx.fee,
-			recipient_fee: fee,
-		});
-	}
-
-    if fee > amount {
-		info!(
-			LOGGER,
-			"Rejected the transfer because transaction fee ({}) exceeds received amount ({}).",
-			amount_to_hr_string(fee),
-			amount_to_hr_string(amount)
-		);
-        return Err(Error::FeeExceedsAmount {
-            sender_amount: amount,
-            recipient_fee: fee,
-        });
-    }
-
-	let out_amount = amount - fee;
+	let out_amount = amount - recipient_fee(partial_tx, &tx, amount)?;
 
 	//First step is just to get the excess sum of the outputs we're participating in
 	//Output and key needs to be stored until transaction finalisation time, somehow
@@ -116,15 +94,68 @@ fn handle_sender_initiation(
 	keychain.aggsig_create_context(blind_sum.secret_key());
 	keychain.aggsig_add_output(&key_id);
 
-	let sig_part=keychain.aggsig_calculate_partial_sig(&sender_pub_nonce, fee, tx.lock_height).unwrap();
+	// locked on an adaptor point, the sender can't complete the kernel
+	// signature without the adaptor secret
+	let sig_part = match adaptor_point {
+		Some(point) => keychain.aggsig_calculate_adaptor_partial_sig(
+			&sender_pub_nonce,
+			point,
+			tx.fee(),
+			tx.lock_height(),
+		)?,
+		None => keychain.aggsig_calculate_partial_sig(&sender_pub_nonce, tx.fee(), tx.lock_height()).unwrap(),
+	};
 
 	// Build the response, which should contain sR, blinding excess xR * G, public nonce kR * G
+	// along with our proof of the payment
+	let kernel_excess = keychain.aggsig_calculate_final_pubkey(&sender_pub_blinding)?;
+	let batch = partial_tx.batch;
 	let mut partial_tx = build_partial_tx(keychain, amount, Some(sig_part), tx);
 	partial_tx.phase = PartialTxPhase::ReceiverInitiation;
+	partial_tx.batch = batch;
+	partial_tx.payment_proof = Some(PaymentProof::sign(
+		keychain,
+		amount,
+		&sender_pub_blinding,
+		&kernel_excess,
+	)?);
 
 	Ok(partial_tx)
 }
 
+// The part of the transaction fee we pay, out of the amount we receive. We
+// don't necessarily want to just trust the sender, it has to pay at least
+// the default fee but may pay more to get mined faster. We could just
+// overwrite the fee here (but we won't) due to the ecdsa sig. In a batch
+// send, the sender pays the fee for all its recipients.
+fn recipient_fee(partial_tx: &PartialTx, tx: &Transaction, amount: u64) -> Result<u64, Error> {
+	if partial_tx.batch {
+		return Ok(0);
+	}
+	let min_fee = tx_fee(tx.inputs.len(), tx.outputs.len() + 1, None);
+	if tx.fee() < min_fee {
+		return Err(Error::FeeDispute {
+			sender_fee: tx.fee(),
+			recipient_fee: min_fee,
+		});
+	}
+	let fee = tx.fee();
+
+	if fee > amount {
+		info!(
+			LOGGER,
+			"Rejected the transfer because transaction fee ({}) exceeds received amount ({}).",
+			amount_to_hr_string(fee),
+			amount_to_hr_string(amount)
+		);
+		return Err(Error::FeeExceedsAmount {
+			sender_amount: amount,
+			recipient_fee: fee,
+		});
+	}
+	Ok(fee)
+}
+
 /// Receive Part 3 of interactive transactions from sender, Sender Confirmation
 /// Return Ok/Error
 /// -Receiver receives sS
@@ -144,7 +175,7 @@ fn handle_sender_confirmation(
 ) -> Result<PartialTx, Error> {
 	let (amount, sender_pub_blinding, sender_pub_nonce, sender_sig_part, tx) = read_partial_tx(keychain, partial_tx)?;
 	let sender_sig_part=sender_sig_part.unwrap();
-	let res = keychain.aggsig_verify_partial_sig(&sender_sig_part, &sender_pub_nonce, &sender_pub_blinding, tx.fee, tx.lock_height);
+	let res = keychain.aggsig_verify_partial_sig(&sender_sig_part, &sender_pub_nonce, &sender_pub_blinding, tx.fee(), tx.lock_height());
 
 	if !res {
 		error!(LOGGER, "Partial Sig from sender invalid.");
@@ -152,7 +183,7 @@ fn handle_sender_confirmation(
 	}
 
 	//Just calculate our sig part again instead of storing
-	let our_sig_part=keychain.aggsig_calculate_partial_sig(&sender_pub_nonce, tx.fee, tx.lock_height).unwrap();
+	let our_sig_part=keychain.aggsig_calculate_partial_sig(&sender_pub_nonce, tx.fee(), tx.lock_height()).unwrap();
 
 	// And the final signature
 	let final_sig=keychain.aggsig_calculate_final_sig(&sender_sig_part, &our_sig_part, &sender_pub_nonce).unwrap();
@@ -161,7 +192,7 @@ fn handle_sender_confirmation(
 	let final_pubkey=keychain.aggsig_calculate_final_pubkey(&sender_pub_blinding).unwrap();
 
 	//Check our final sig verifies
-	let res = keychain.aggsig_verify_final_sig_build_msg(&final_sig, &final_pubkey, tx.fee, tx.lock_height);
+	let res = keychain.aggsig_verify_final_sig_build_msg(&final_sig, &final_pubkey, tx.fee(), tx.lock_height());
 
 	if !res {
 		error!(LOGGER, "Final aggregated signature invalid.");
@@ -173,8 +204,13 @@ fn handle_sender_confirmation(
 	let tx_hex = to_hex(ser::ser_vec(&final_tx).unwrap());
 
 	let url = format!("{}/v1/pool/push", config.check_node_api_http_addr.as_str());
-	api::client::post(url.as_str(), &TxWrapper { tx_hex: tx_hex })
-		.map_err(|e| Error::Node(e))?;
+	api::client::post_with_secret(
+		url.as_str(),
+		&TxWrapper { tx_hex: tx_hex },
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))?;
+
+	log_received_tx(config, keychain, amount, tx.fee(), &final_pubkey)?;
 
 	// Return what we've actually posted
 	let mut partial_tx = build_partial_tx(keychain, amount, Some(final_sig), tx);
@@ -182,6 +218,90 @@ fn handle_sender_confirmation(
 	Ok(partial_tx)
 }
 
+/// Responds to a transaction sent through a file (wallet send to a file:
+/// destination). Unlike the exchange with a listening wallet, the response
+/// includes our output, as the sender is the one completing the transaction.
+pub fn receive_file_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+) -> Result<PartialTx, Error> {
+	respond_to_file_tx(config, keychain, partial_tx, None)
+}
+
+/// Responds to a transaction sent through a file, our partial signature
+/// being locked on the adaptor point if one is provided.
+pub fn respond_to_file_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+	adaptor_point: Option<&PublicKey>,
+) -> Result<PartialTx, Error> {
+	match partial_tx.phase {
+		PartialTxPhase::SenderInitiation => (),
+		_ => {
+			return Err(Error::Format(format!(
+				"Expected a sender initiation, got {:?}",
+				partial_tx.phase
+			)))
+		}
+	}
+	let (_, sender_pub_blinding, _, _, _) = read_partial_tx(keychain, partial_tx)?;
+	let response = handle_sender_initiation(config, keychain, partial_tx, adaptor_point)?;
+	let (amount, _, _, sig_part, tx) = read_partial_tx(keychain, &response)?;
+	let fee = recipient_fee(partial_tx, &tx, amount)?;
+
+	let final_pubkey = keychain.aggsig_calculate_final_pubkey(&sender_pub_blinding)?;
+	log_received_tx(config, keychain, amount, fee, &final_pubkey)?;
+
+	let key_id = match keychain.aggsig_get_outputs().first() {
+		Some(key_id) => key_id.clone(),
+		None => return Err(Error::GenericError(format!("no output to receive the amount in"))),
+	};
+	let out_amount = match amount.checked_sub(fee) {
+		Some(out_amount) => out_amount,
+		None => {
+			return Err(Error::FeeExceedsAmount {
+				sender_amount: amount,
+				recipient_fee: fee,
+			})
+		}
+	};
+	let (tx, _) = build::transaction(
+		vec![build::initial_tx(tx), build::output(out_amount, key_id)],
+		keychain,
+	)?;
+
+	let payment_proof = response.payment_proof.clone();
+	let mut response = build_partial_tx(keychain, amount, sig_part, tx);
+	response.phase = PartialTxPhase::ReceiverInitiation;
+	response.payment_proof = payment_proof;
+	response.batch = partial_tx.batch;
+	response.adaptor_point = adaptor_point.map(|point| public_excess_hex(keychain, point));
+	Ok(response)
+}
+
+// Adds the transaction we're receiving to the wallet transaction log, our
+// output being the one tracked by the aggsig context.
+fn log_received_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	amount: u64,
+	fee: u64,
+	final_pubkey: &PublicKey,
+) -> Result<(), Error> {
+	let mut entry = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Received, fee);
+	entry.amount_credited = amount.checked_sub(fee).ok_or(Error::FeeExceedsAmount {
+		sender_amount: amount,
+		recipient_fee: fee,
+	})?;
+	entry.excess = Some(public_excess_hex(keychain, final_pubkey));
+	entry.outputs = keychain.aggsig_get_outputs();
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.add_tx_log_entry(entry);
+	})
+}
+
 /// Component used to receive coins, implements all the receiving end of the
 /// wallet REST API as well as some of the command-line operations.
 #[derive(Clone)]
@@ -196,8 +316,21 @@ impl Handler for WalletReceiver {
 
 		if let Ok(Some(partial_tx)) = struct_body {
 			match partial_tx.phase {
+				// the sender of a batch completes the transaction itself, it
+				// gets our output like through a file
+				PartialTxPhase::SenderInitiation if partial_tx.batch => {
+					let resp_tx=receive_file_tx(&self.config, &self.keychain, &partial_tx)
+					.map_err(|e| {
+						error!(LOGGER, "Phase 1 Batch Sender Initiation -> Problematic partial tx, looks like this: {:?}", partial_tx);
+						api::Error::Internal(
+							format!("Error processing partial transaction: {:?}", e),
+						)})
+					.unwrap();
+					let json = serde_json::to_string(&resp_tx).unwrap();
+					Ok(Response::with((status::Ok, json)))
+				},
 				PartialTxPhase::SenderInitiation => {
-					let resp_tx=handle_sender_initiation(&self.config, &self.keychain, &partial_tx)
+					let resp_tx=handle_sender_initiation(&self.config, &self.keychain, &partial_tx, None)
 					.map_err(|e| {
 						error!(LOGGER, "Phase 1 Sender Initiation -> Problematic partial tx, looks like this: {:?}", partial_tx);
 						api::Error::Internal(
@@ -320,15 +453,17 @@ fn build_final_transaction(
 	let root_key_id = keychain.root_key_id();
 
 	// double check the fee amount included in the partial tx
-	// we don't necessarily want to just trust the sender
+	// we don't necessarily want to just trust the sender, it has to pay at
+	// least the default fee but may pay more to get mined faster
 	// we could just overwrite the fee here (but we won't) due to the ecdsa sig
-	let fee = tx_fee(tx.inputs.len(), tx.outputs.len() + 1, None);
-	if fee != tx.fee {
+	let min_fee = tx_fee(tx.inputs.len(), tx.outputs.len() + 1, None);
+	if tx.fee() < min_fee {
 		return Err(Error::FeeDispute {
-			sender_fee: tx.fee,
-			recipient_fee: fee,
+			sender_fee: tx.fee(),
+			recipient_fee: min_fee,
 		});
 	}
+	let fee = tx.fee();
 
     if fee > amount {
 		info!(
@@ -378,7 +513,9 @@ fn build_final_transaction(
 		keychain,
 	)?;
 
-	final_tx.excess_sig = excess_sig.clone();
+	// the kernel excess is the sum of what we exchanged, not just our part
+	final_tx.kernels[0].excess = final_tx.kernel_excess()?;
+	final_tx.kernels[0].excess_sig = excess_sig.clone();
 
 	// make sure the resulting transaction is valid (could have been lied to on
  // excess).
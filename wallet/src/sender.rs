@@ -12,21 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
 use api;
+use batch;
 use client;
 use checker;
+use info;
 use core::core::{build, Transaction, amount_to_hr_string};
 use core::ser;
 use keychain::{BlindingFactor, Identifier, Keychain};
+use util::secp::Signature;
+use util::secp::key::PublicKey;
 use receiver::TxWrapper;
 use types::*;
 use util::LOGGER;
 use util;
 
+/// Number of blocks a sent transaction should get mined within, when its fee
+/// is estimated by the node
+const FEE_ESTIMATE_BLOCKS: u64 = 3;
+
 /// Issue a new transaction to the provided sender by spending some of our
 /// wallet
 /// UTXOs. The destination can be "stdout" (for command line) (currently disabled) or a URL to the
 /// recipients wallet receiver (to be implemented).
+/// Without a fee per weight provided, the one the node estimates gets paid,
+/// never less than the default one.
 pub fn issue_send_tx(
 	config: &WalletConfig,
 	keychain: &Keychain,
@@ -34,13 +51,16 @@ pub fn issue_send_tx(
 	minimum_confirmations: u64,
 	dest: String,
 	max_outputs: usize,
-	selection_strategy_is_use_all: bool,
+	selection_strategy: SelectionStrategy,
+	fee_base: Option<u64>,
 ) -> Result<(), Error> {
 	checker::refresh_outputs(config, keychain)?;
 
 	let chain_tip = checker::get_tip_from_node(config)?;
 	let current_height = chain_tip.height;
 
+	let fee_base = send_fee_base(config, fee_base);
+
 	// proof of concept - set lock_height on the tx
 	let lock_height = chain_tip.height;
 
@@ -52,8 +72,604 @@ pub fn issue_send_tx(
 		minimum_confirmations,
 		lock_height,
 		max_outputs,
-		selection_strategy_is_use_all,
+		selection_strategy,
+		fee_base,
 	)?;
+	// Closure to acquire wallet lock and lock the coins being spent
+	// so we avoid accidental double spend attempt. The send is tracked
+	// until it confirms so it can be reposted if it gets stuck, and logged.
+	let fee = tx.fee();
+	let update_wallet = |context, excess, kernels, proof| WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for coin in &coins {
+			wallet_data.lock_output(coin);
+		}
+		let mut entry = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Sent, fee);
+		entry.amount_debited = coins.iter().map(|c| c.value).sum();
+		entry.amount_credited = entry.amount_debited - amount;
+		entry.excess = excess;
+		entry.payment_proof = proof;
+		entry.inputs = coins.iter().map(|c| c.key_id.clone()).collect();
+		entry.outputs = vec![change_key.clone()];
+		wallet_data.add_tx_log_entry(entry);
+		wallet_data.add_pending_send(PendingSend {
+			id: 0,
+			amount: amount,
+			fee: fee,
+			lock_height: lock_height,
+			dest: dest.clone(),
+			inputs: coins.iter().map(|c| c.key_id.clone()).collect(),
+			change: change_key.clone(),
+			replaced_changes: vec![],
+			file_context: context,
+			kernels: kernels,
+			batch: vec![],
+		})
+	});
+
+	// Closure to acquire wallet lock and delete the change output in case of tx failure.
+	let rollback_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		info!(LOGGER, "cleaning up unused change output from wallet");
+		wallet_data.delete_output(&change_key);
+	});
+
+	if dest.starts_with("file:") {
+		// the exchange gets resumed by finalize once the recipient responded,
+		// our secret excess and nonce are kept until then
+		let context = match write_partial_tx_file(keychain, amount, tx, blind_sum, &dest[5..]) {
+			Ok(context) => context,
+			Err(e) => {
+				rollback_wallet()?;
+				return Err(e);
+			}
+		};
+		let id = update_wallet(Some(context), None, vec![], None)?;
+		info!(
+			LOGGER,
+			"Transaction written to {}, pending as {} until finalized with the recipient response",
+			&dest[5..],
+			id
+		);
+		return Ok(());
+	}
+
+	keychain.aggsig_create_context(blind_sum.secret_key());
+	let partial_tx = build_partial_tx(keychain, amount, None, tx);
+	let url = match receive_url(&dest) {
+		Ok(url) => url,
+		Err(e) => {
+			rollback_wallet()?;
+			return Err(e);
+		}
+	};
+	let (confirmation, excess, proof) =
+		match initiate_with_recipient(config, keychain, amount, lock_height, &partial_tx, &url) {
+			Ok(res) => res,
+			Err(ref e) if client::is_unreachable(e) => {
+				// keep everything needed to go through the exchange later
+				let id = update_wallet(Some(send_context(keychain)?), None, vec![], None)?;
+				let path = slate_path(config, id);
+				write_slate(&partial_tx, &path)?;
+				warn!(
+					LOGGER,
+					"Recipient unreachable, transaction pending as {}: resume it with wallet \
+					 resume {} or hand {} over to the recipient and finalize its response",
+					id,
+					id,
+					path.display()
+				);
+				return Ok(());
+			}
+			Err(e) => {
+				rollback_wallet()?;
+				return Err(e);
+			}
+		};
+
+	// from now on the recipient may complete the transaction
+	let kernel = kernel_excess_hex(keychain, &confirmation)?;
+	let id = update_wallet(None, Some(excess), vec![kernel], proof)?;
+	confirm_with_recipient(config, &url, &confirmation, id)?;
+	info!(
+		LOGGER,
+		"Transaction sent, pending as {} until confirmed (see wallet repost or cancel)",
+		id
+	);
+	Ok(())
+}
+
+/// Fee per unit of weight to pay, the one provided or the one the node
+/// estimates, never less than the default one. Rounded up to an even value
+/// so that the transaction fee is even whatever its weight.
+pub fn send_fee_base(config: &WalletConfig, fee_base: Option<u64>) -> u64 {
+	let fee_base = match fee_base {
+		Some(fee_base) => fee_base,
+		None => match checker::get_fee_estimate(config, FEE_ESTIMATE_BLOCKS) {
+			Ok(estimate) => cmp::max(estimate.fee_base, DEFAULT_BASE_FEE),
+			Err(e) => {
+				warn!(
+					LOGGER,
+					"Could not get a fee estimate from the node, paying the default fee: {:?}", e
+				);
+				DEFAULT_BASE_FEE
+			}
+		},
+	};
+	fee_base + fee_base % 2
+}
+
+/// Resumes a send which recipient listener couldn't be reached, going
+/// through the exchange with the transaction saved back then.
+pub fn resume_send_tx(config: &WalletConfig, keychain: &Keychain, id: u32) -> Result<(), Error> {
+	let send = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.get_pending_send(Some(id)).cloned()
+	})?;
+	let send = match send {
+		Some(send) => send,
+		None => return Err(Error::GenericError(format!("no pending transaction {}", id))),
+	};
+	let context = match send.file_context {
+		Some(ref context) if !send.dest.starts_with("file:") => context.clone(),
+		_ => {
+			return Err(Error::GenericError(format!(
+				"transaction {} isn't waiting on its recipient listener",
+				id
+			)))
+		}
+	};
+	let path = slate_path(config, id);
+	let mut contents = String::new();
+	File::open(&path)?.read_to_string(&mut contents)?;
+	let partial_tx: PartialTx = serde_json::from_str(&contents)?;
+	restore_context(keychain, &context)?;
+
+	let url = receive_url(&send.dest)?;
+	let (confirmation, excess, proof) =
+		initiate_with_recipient(config, keychain, send.amount, send.lock_height, &partial_tx, &url)?;
+	let kernel = kernel_excess_hex(keychain, &confirmation)?;
+
+	// our secret nonce must never sign twice, the saved context goes before
+	// our partial signature does
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == id {
+				pending.file_context = None;
+				pending.kernels.push(kernel.clone());
+			}
+		}
+		if let Some(entry) = wallet_data.sent_tx_log_entry_mut(&send.inputs) {
+			entry.excess = Some(excess);
+			entry.payment_proof = proof;
+		}
+	})?;
+	let _ = fs::remove_file(&path);
+	confirm_with_recipient(config, &url, &confirmation, id)?;
+	info!(
+		LOGGER,
+		"Transaction {} resumed and sent, pending until confirmed",
+		id
+	);
+	Ok(())
+}
+
+// Starts the exchange by writing the sender initiation to a file, returning
+// the context to save for finalize.
+fn write_partial_tx_file(
+	keychain: &Keychain,
+	amount: u64,
+	tx: Transaction,
+	blind_sum: BlindingFactor,
+	path: &str,
+) -> Result<SendContext, Error> {
+	keychain.aggsig_create_context(blind_sum.secret_key());
+	let partial_tx = build_partial_tx(keychain, amount, None, tx);
+	write_slate(&partial_tx, Path::new(path))?;
+	send_context(keychain)
+}
+
+/// Our secret excess and nonce of the current aggsig context, to save until
+/// the exchange with the recipient completes.
+pub fn send_context(keychain: &Keychain) -> Result<SendContext, Error> {
+	let (sec_key, sec_nonce) = keychain.aggsig_get_private_keys();
+	SendContext::new(keychain, &sec_key, &sec_nonce)
+}
+
+/// Makes a saved context the current aggsig context again.
+pub fn restore_context(keychain: &Keychain, context: &SendContext) -> Result<(), Error> {
+	let (sec_key, sec_nonce) = context.secret_keys(keychain)?;
+	keychain.aggsig_restore_context(sec_key, sec_nonce);
+	Ok(())
+}
+
+pub fn write_slate(partial_tx: &PartialTx, path: &Path) -> Result<(), Error> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let json = serde_json::to_string_pretty(partial_tx)?;
+	let mut file = File::create(path)?;
+	file.write_all(json.as_bytes())?;
+	Ok(())
+}
+
+// Where the transaction of a send waiting on its recipient listener is kept,
+// to resume the send or hand it over to the recipient as a file.
+pub fn slate_path(config: &WalletConfig, id: u32) -> PathBuf {
+	Path::new(&config.data_file_dir)
+		.join("slates")
+		.join(format!("send_{}.json", id))
+}
+
+/// Completes a transaction sent through a file, or which recipient listener
+/// couldn't be reached, with the response of the recipient (as written by
+/// wallet receive). Adds our part of the signature and posts the final
+/// transaction to the node.
+pub fn finalize_tx_file(config: &WalletConfig, keychain: &Keychain, path: &str) -> Result<(), Error> {
+	let mut contents = String::new();
+	File::open(path)?.read_to_string(&mut contents)?;
+	let partial_tx: PartialTx = serde_json::from_str(&contents)?;
+	if partial_tx.batch {
+		return batch::finalize_batch_part(config, keychain, &partial_tx, path);
+	}
+	if partial_tx.adaptor_point.is_some() {
+		return Err(Error::GenericError(format!(
+			"the response in {} is locked on an adaptor point, confirm it with swap_confirm",
+			path
+		)));
+	}
+	let (amount, recp_pub_blinding, recp_pub_nonce, sig, tx) =
+		read_partial_tx(keychain, &partial_tx)?;
+	let recp_sig = match sig {
+		Some(sig) => sig,
+		None => {
+			return Err(Error::Signature(String::from(
+				"Response from recipient has no partial signature.",
+			)))
+		}
+	};
+
+	let send = find_file_send(config, keychain, amount, &tx, path)?;
+	restore_context(keychain, send.file_context.as_ref().unwrap())?;
+	let (final_tx, final_pubkey) =
+		complete_tx(keychain, &recp_sig, &recp_pub_nonce, &recp_pub_blinding, tx)?;
+
+	let tx_hex = util::to_hex(ser::ser_vec(&final_tx).unwrap());
+	let url = format!("{}/v1/pool/push", config.check_node_api_http_addr.as_str());
+	api::client::post_with_secret(
+		url.as_str(),
+		&TxWrapper { tx_hex: tx_hex },
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))?;
+
+	// the exchange is over, the send now just waits for confirmation
+	let proof = check_payment_proof(keychain, &partial_tx, send.amount, &final_pubkey)?;
+	let excess = public_excess_hex(keychain, &final_pubkey);
+	let kernel = util::to_hex(final_tx.kernels[0].excess.0.to_vec());
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == send.id {
+				pending.file_context = None;
+				pending.kernels.push(kernel.clone());
+			}
+		}
+		if let Some(entry) = wallet_data.sent_tx_log_entry_mut(&send.inputs) {
+			entry.excess = Some(excess);
+			entry.payment_proof = proof;
+		}
+	})?;
+	let _ = fs::remove_file(slate_path(config, send.id));
+	info!(
+		LOGGER,
+		"Transaction {} finalized and posted, sending {}",
+		send.id,
+		amount_to_hr_string(send.amount)
+	);
+	Ok(())
+}
+
+/// The send waiting on the response of its recipient in the provided file,
+/// found from the inputs the transaction spends.
+pub fn find_file_send(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	amount: u64,
+	tx: &Transaction,
+	path: &str,
+) -> Result<PendingSend, Error> {
+	let send = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data
+			.pending_sends
+			.iter()
+			.find(|send| {
+				send.file_context.is_some() && spends_inputs(wallet_data, keychain, &send.inputs, tx)
+			})
+			.cloned()
+	})?;
+	let send = match send {
+		Some(send) => send,
+		None => {
+			return Err(Error::GenericError(format!(
+				"no pending transaction matches the response in {}",
+				path
+			)))
+		}
+	};
+	if amount != send.amount {
+		return Err(Error::GenericError(format!(
+			"response amount {} doesn't match the amount sent {}",
+			amount, send.amount
+		)));
+	}
+	Ok(send)
+}
+
+// Completes a transaction with the response of the recipient, the aggsig
+// context of the send being the current one. Returns the final transaction
+// and its kernel public excess.
+fn complete_tx(
+	keychain: &Keychain,
+	recp_sig: &Signature,
+	recp_pub_nonce: &PublicKey,
+	recp_pub_blinding: &PublicKey,
+	tx: Transaction,
+) -> Result<(Transaction, PublicKey), Error> {
+	let res = keychain.aggsig_verify_partial_sig(
+		recp_sig,
+		recp_pub_nonce,
+		recp_pub_blinding,
+		tx.fee(),
+		tx.lock_height(),
+	);
+	if !res {
+		error!(LOGGER, "Partial Sig from recipient invalid.");
+		return Err(Error::Signature(String::from("Partial Sig from recipient invalid.")));
+	}
+	let our_sig = keychain.aggsig_calculate_partial_sig(recp_pub_nonce, tx.fee(), tx.lock_height())?;
+	let final_sig = keychain.aggsig_calculate_final_sig(recp_sig, &our_sig, recp_pub_nonce)?;
+	let final_pubkey = keychain.aggsig_calculate_final_pubkey(recp_pub_blinding)?;
+	if !keychain.aggsig_verify_final_sig_build_msg(
+		&final_sig,
+		&final_pubkey,
+		tx.fee(),
+		tx.lock_height(),
+	) {
+		error!(LOGGER, "Final aggregated signature invalid.");
+		return Err(Error::Signature(String::from("Final aggregated signature invalid.")));
+	}
+
+	let mut final_tx = tx;
+	final_tx.kernels[0].excess = final_tx.kernel_excess()?;
+	final_tx.kernels[0].excess_sig = final_sig;
+	final_tx.validate()?;
+	Ok((final_tx, final_pubkey))
+}
+
+/// Whether the transaction spends all the provided outputs of ours.
+pub fn spends_inputs(
+	wallet_data: &WalletData,
+	keychain: &Keychain,
+	inputs: &Vec<Identifier>,
+	tx: &Transaction,
+) -> bool {
+	let input_commits = tx.inputs.iter().map(|i| i.commitment()).collect::<Vec<_>>();
+	inputs.iter().all(|key_id| match wallet_data.get_output(key_id) {
+		Some(out) => match keychain.commit(out.value, key_id) {
+			Ok(commit) => input_commits.contains(&commit),
+			Err(_) => false,
+		},
+		None => false,
+	})
+}
+
+/// Rebuilds a sent transaction that isn't confirmed yet with its fee
+/// multiplied by fee_bump, spending the same inputs, the fee bump coming out
+/// of our change so the recipient gets the same amount, and goes through the exchange with the recipient again so the new
+/// version gets broadcast. Nodes replace the original transaction in their
+/// pool as the new one pays a higher fee. Reposts the most recent pending
+/// send if no id is provided.
+pub fn repost_send_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	id: Option<u32>,
+	fee_bump: u64,
+) -> Result<(), Error> {
+	if fee_bump < 2 {
+		return Err(Error::GenericError(format!(
+			"fee bump must be at least 2, got {}",
+			fee_bump
+		)));
+	}
+	checker::refresh_outputs(config, keychain)?;
+
+	let send = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.clear_confirmed_sends();
+		wallet_data.get_pending_send(id).cloned()
+	})?;
+	let send = match send {
+		Some(send) => send,
+		None => return Err(Error::GenericError(format!("no pending transaction to repost"))),
+	};
+	if send.dest.starts_with("file:") || !send.batch.is_empty() {
+		return Err(Error::GenericError(format!(
+			"transaction {} was sent through a file or in a batch, cancel it and send \
+			 again instead",
+			send.id
+		)));
+	}
+
+	let (coins, change) = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		let coins = send.inputs
+			.iter()
+			.filter_map(|id| wallet_data.get_output(id).cloned())
+			.collect::<Vec<_>>();
+		(coins, wallet_data.get_output(&send.change).cloned())
+	})?;
+	let change = match change {
+		Some(change) => change,
+		None => {
+			return Err(Error::WalletData(format!(
+				"change output of pending transaction {} not found",
+				send.id
+			)))
+		}
+	};
+	if coins.len() != send.inputs.len() {
+		return Err(Error::WalletData(format!(
+			"inputs of pending transaction {} not found",
+			send.id
+		)));
+	}
+
+	// the fee bump comes out of our change, into a new output as the
+	// original transaction may still get mined
+	let fee = send.fee * fee_bump;
+	let new_change = repost_change(&send, change.value, fee)?;
+	let change_key = add_change_output(config, keychain, new_change)?;
+	let rollback_wallet = || {
+		WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+			wallet_data.delete_output(&change_key);
+		})
+	};
+	let (tx, blind_sum, amount) =
+		match repost_tx(keychain, &send, &coins, new_change, &change_key, fee) {
+			Ok(res) => res,
+			Err(e) => {
+				rollback_wallet()?;
+				return Err(e);
+			}
+		};
+
+	info!(
+		LOGGER,
+		"Reposting transaction {} to {} with fee {} (was {})",
+		send.id,
+		send.dest,
+		amount_to_hr_string(fee),
+		amount_to_hr_string(send.fee),
+	);
+	let res = send_to_recipient(
+		config,
+		keychain,
+		amount,
+		send.lock_height,
+		tx,
+		blind_sum,
+		&send.dest,
+	);
+	let (excess, kernel, proof) = match res {
+		Ok(res) => res,
+		Err(e) => {
+			rollback_wallet()?;
+			return Err(e);
+		}
+	};
+
+	let _ = fs::remove_file(slate_path(config, send.id));
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == send.id {
+				pending.amount = amount;
+				pending.fee = fee;
+				pending.file_context = None;
+				// the original transaction may still get mined instead
+				pending.kernels.push(kernel.clone());
+				pending.replaced_changes.push(send.change.clone());
+				pending.change = change_key.clone();
+			}
+		}
+		// the new transaction replaces the original one
+		if let Some(entry) = wallet_data.sent_tx_log_entry_mut(&send.inputs) {
+			entry.fee = fee;
+			entry.amount_credited = new_change;
+			entry.outputs = vec![change_key.clone()];
+			entry.excess = Some(excess);
+			entry.payment_proof = proof;
+		}
+	})
+}
+
+// Our change once a repost pays the provided fee, the fee bump coming out of
+// it. Fails when the change can't cover the bump.
+fn repost_change(send: &PendingSend, change: u64, fee: u64) -> Result<u64, Error> {
+	let bump = fee - send.fee;
+	match change.checked_sub(bump) {
+		Some(change) => Ok(change),
+		None => Err(Error::GenericError(format!(
+			"change of {} can't cover the fee bump of {} of transaction {}",
+			amount_to_hr_string(change),
+			amount_to_hr_string(bump),
+			send.id
+		))),
+	}
+}
+
+// Builds a repost of the provided send paying the provided fee to the
+// provided change. Returns it with our blinding sum and the amount to send,
+// which the recipient pays the fee out of: the amount grows by the fee bump
+// so the recipient output stays the same.
+fn repost_tx(
+	keychain: &Keychain,
+	send: &PendingSend,
+	coins: &Vec<OutputData>,
+	change: u64,
+	change_key: &Identifier,
+	fee: u64,
+) -> Result<(Transaction, BlindingFactor, u64), Error> {
+	let mut parts = input_parts(coins, keychain)?;
+	parts.push(build::with_fee(fee));
+	parts.push(build::output(change, change_key.clone()));
+	parts.push(build::with_lock_height(send.lock_height));
+	let (tx, blind_sum) = build::transaction(parts, keychain)?;
+	Ok((tx, blind_sum, send.amount + fee - send.fee))
+}
+
+/// Cancels a sent transaction that hasn't confirmed, releasing the outputs it
+/// spends and forgetting about its change output. Refused once its kernel is
+/// on chain, or any of its inputs is spent, meaning it (or a conflicting
+/// transaction) got mined. A transaction that already reached the network
+/// may still confirm, wallet restore recovers its change output then.
+pub fn cancel_send_tx(config: &WalletConfig, keychain: &Keychain, id: u32) -> Result<(), Error> {
+	checker::refresh_outputs(config, keychain)?;
+
+	let kernels = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.get_pending_send(Some(id)).map(|s| s.kernels.clone())
+	})?;
+	for kernel in kernels.unwrap_or(vec![]) {
+		if checker::is_kernel_on_chain(config, &kernel)? {
+			return Err(Error::GenericError(format!(
+				"transaction {} already confirmed, can't be cancelled",
+				id
+			)));
+		}
+	}
+
+	let res = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data.cancel_pending_send(id)
+	})?;
+	let send = res?;
+	let _ = fs::remove_file(slate_path(config, id));
+	info!(
+		LOGGER,
+		"Cancelled transaction {} of {} to {}, {} outputs unlocked",
+		send.id,
+		amount_to_hr_string(send.amount),
+		send.dest,
+		send.inputs.len(),
+	);
+	Ok(())
+}
+
+/// Goes through the interactive aggsig exchange with the recipient at dest,
+/// which finalizes the transaction and posts it to its node. Returns the
+/// public excess of the transaction kernel, its excess commitment (both hex)
+/// and the recipient payment proof.
+fn send_to_recipient(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	amount: u64,
+	lock_height: u64,
+	tx: Transaction,
+	blind_sum: BlindingFactor,
+	dest: &str,
+) -> Result<(String, String, Option<PaymentProof>), Error> {
 	/*
 	 * -Sender picks random blinding factors for all outputs it participates in, computes total blinding excess xS
 	 * -Sender picks random nonce kS
@@ -65,20 +681,6 @@ pub fn issue_send_tx(
 
 	let partial_tx = build_partial_tx(keychain, amount, None, tx);
 
-	// Closure to acquire wallet lock and lock the coins being spent
-	// so we avoid accidental double spend attempt.
-	let update_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
-		for coin in coins {
-			wallet_data.lock_output(&coin);
-		}
-	});
-
-	// Closure to acquire wallet lock and delete the change output in case of tx failure.
-	let rollback_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
-		info!(LOGGER, "cleaning up unused change output from wallet");
-		wallet_data.delete_output(&change_key);
-	});
-
 	// TODO: stdout option removed for now, as it won't work very will with this version of
 	// aggsig exchange
 
@@ -88,27 +690,45 @@ pub fn issue_send_tx(
 		println!("{}", json_tx);
 	} else */
 
-	if &dest[..4] != "http" {
-		panic!("dest formatted as {} but send -d expected stdout or http://IP:port", dest);
+	let url = receive_url(dest)?;
+	let (confirmation, excess, proof) =
+		initiate_with_recipient(config, keychain, amount, lock_height, &partial_tx, &url)?;
+	let kernel = kernel_excess_hex(keychain, &confirmation)?;
+	send_confirmation(config, &url, &confirmation)?;
+	Ok((excess, kernel, proof))
+}
+
+/// The recipient listener endpoint of a send destination.
+pub fn receive_url(dest: &str) -> Result<String, Error> {
+	if !dest.starts_with("http") {
+		return Err(Error::GenericError(format!(
+			"dest formatted as {} but send -d expected http://IP:port or file:<path>",
+			dest
+		)));
 	}
+	Ok(format!("{}/v1/receive/transaction", dest))
+}
 
-	let url = format!("{}/v1/receive/transaction", &dest);
+// Posts our initiation (with the current aggsig context) to the recipient
+// and checks its response. Returns our confirmation, holding our partial
+// signature, along with the public excess of the transaction kernel and the
+// recipient payment proof.
+fn initiate_with_recipient(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	amount: u64,
+	lock_height: u64,
+	partial_tx: &PartialTx,
+	url: &str,
+) -> Result<(PartialTx, String, Option<PaymentProof>), Error> {
 	debug!(LOGGER, "Posting partial transaction to {}", url);
-	let res = client::send_partial_tx(&url, &partial_tx);
-	if let Err(e) = res {
-		match e {
-			Error::FeeExceedsAmount {sender_amount, recipient_fee} =>
-				error!(
-					LOGGER,
-					"Recipient rejected the transfer because transaction fee ({}) exceeded amount ({}).",
-					amount_to_hr_string(recipient_fee),
-					amount_to_hr_string(sender_amount)
-				),
-			_ => error!(LOGGER, "Communication with receiver failed on SenderInitiation send. Aborting transaction"),
+	let response = match client::send_partial_tx(config, url, partial_tx) {
+		Ok(response) => response,
+		Err(e) => {
+			log_send_error(&e, "SenderInitiation");
+			return Err(e);
 		}
-		rollback_wallet()?;
-		return Err(e);
-	}
+	};
 
 	/* -Sender receives xR * G, kR * G, sR
 	 * -Sender computes Schnorr challenge e = H(M | kR * G + kS * G)
@@ -116,40 +736,110 @@ pub fn issue_send_tx(
 	 * -Sender computes their part of signature, sS = kS + e * xS
 	 * -Sender posts sS to receiver
 	*/
-	let (_amount, recp_pub_blinding, recp_pub_nonce, sig, tx) = read_partial_tx(keychain, &res.unwrap())?;
-	let res = keychain.aggsig_verify_partial_sig(&sig.unwrap(), &recp_pub_nonce, &recp_pub_blinding, tx.fee, lock_height);
+	let (_amount, recp_pub_blinding, recp_pub_nonce, sig, tx) = read_partial_tx(keychain, &response)?;
+	let sig = match sig {
+		Some(sig) => sig,
+		None => {
+			return Err(Error::Signature(String::from(
+				"Response from recipient has no partial signature.",
+			)))
+		}
+	};
+	let res = keychain.aggsig_verify_partial_sig(&sig, &recp_pub_nonce, &recp_pub_blinding, tx.fee(), lock_height);
 	if !res {
 		error!(LOGGER, "Partial Sig from recipient invalid.");
 		return Err(Error::Signature(String::from("Partial Sig from recipient invalid.")));
 	}
 
-	let sig_part=keychain.aggsig_calculate_partial_sig(&recp_pub_nonce, tx.fee, tx.lock_height).unwrap();
+	let sig_part = keychain.aggsig_calculate_partial_sig(&recp_pub_nonce, tx.fee(), tx.lock_height())?;
+	let final_pubkey = keychain.aggsig_calculate_final_pubkey(&recp_pub_blinding)?;
+	let proof = check_payment_proof(keychain, &response, amount, &final_pubkey)?;
 
 	// Build the next stage, containing sS (and our pubkeys again, for the recipient's convenience)
-	let mut partial_tx = build_partial_tx(keychain, amount, Some(sig_part), tx);
-	partial_tx.phase = PartialTxPhase::SenderConfirmation;
-
-	// And send again
-	let res = client::send_partial_tx(&url, &partial_tx);
-	if let Err(e) = res {
-		match e {
-			Error::FeeExceedsAmount {sender_amount, recipient_fee} =>
-				error!(
-					LOGGER,
-					"Recipient rejected the transfer because transaction fee ({}) exceeded amount ({}).",
-					amount_to_hr_string(recipient_fee),
-					amount_to_hr_string(sender_amount)
-				),
-			_ => error!(LOGGER, "Communication with receiver failed on SenderConfirmation send. Aborting transaction"),
-		}
-		rollback_wallet()?;
+	let mut confirmation = build_partial_tx(keychain, amount, Some(sig_part), tx);
+	confirmation.phase = PartialTxPhase::SenderConfirmation;
+	Ok((confirmation, public_excess_hex(keychain, &final_pubkey), proof))
+}
+
+// The excess commitment (hex) the kernel of a partial transaction ends up
+// with once finalized, identifying it on chain.
+fn kernel_excess_hex(keychain: &Keychain, partial_tx: &PartialTx) -> Result<String, Error> {
+	let (_, _, _, _, tx) = read_partial_tx(keychain, partial_tx)?;
+	let excess = tx.kernel_excess()?;
+	Ok(util::to_hex(excess.0.to_vec()))
+}
+
+// Posts our confirmation to the recipient, which then finalizes the
+// transaction.
+fn send_confirmation(config: &WalletConfig, url: &str, confirmation: &PartialTx) -> Result<(), Error> {
+	if let Err(e) = client::send_partial_tx(config, url, confirmation) {
+		log_send_error(&e, "SenderConfirmation");
 		return Err(e);
 	}
-	//All good so
-	update_wallet()?;
 	Ok(())
 }
 
+// Sends our confirmation for a send already recorded as pending, which the
+// recipient may have received even if we got no answer.
+fn confirm_with_recipient(
+	config: &WalletConfig,
+	url: &str,
+	confirmation: &PartialTx,
+	id: u32,
+) -> Result<(), Error> {
+	send_confirmation(config, url, confirmation).map_err(|e| {
+		warn!(
+			LOGGER,
+			"Transaction {} stays pending and may still confirm, cancel it to unlock its outputs",
+			id
+		);
+		e
+	})
+}
+
+pub fn log_send_error(e: &Error, phase: &str) {
+	match *e {
+		Error::FeeExceedsAmount {sender_amount, recipient_fee} =>
+			error!(
+				LOGGER,
+				"Recipient rejected the transfer because transaction fee ({}) exceeded amount ({}).",
+				amount_to_hr_string(recipient_fee),
+				amount_to_hr_string(sender_amount)
+			),
+		_ if client::is_unreachable(e) => error!(LOGGER, "Receiver unreachable on {} send.", phase),
+		_ => error!(LOGGER, "Communication with receiver failed on {} send. Aborting transaction", phase),
+	}
+}
+
+/// Checks the payment proof of the recipient response is signed and matches
+/// the transaction we're building. Recipients not providing any are still
+/// accepted.
+pub fn check_payment_proof(
+	keychain: &Keychain,
+	response: &PartialTx,
+	amount: u64,
+	final_pubkey: &PublicKey,
+) -> Result<Option<PaymentProof>, Error> {
+	let proof = match response.payment_proof {
+		Some(ref proof) => proof.clone(),
+		None => {
+			warn!(LOGGER, "Recipient didn't provide any payment proof.");
+			return Ok(None);
+		}
+	};
+	proof.verify()?;
+	let (our_pub_excess, _) = keychain.aggsig_get_public_keys();
+	if proof.amount != amount || proof.sender_excess != public_excess_hex(keychain, &our_pub_excess)
+		|| proof.kernel_excess != public_excess_hex(keychain, final_pubkey)
+	{
+		error!(LOGGER, "Payment proof from recipient doesn't match the transaction.");
+		return Err(Error::Signature(String::from(
+			"Payment proof from recipient doesn't match the transaction.",
+		)));
+	}
+	Ok(Some(proof))
+}
+
 /// Builds a transaction to send to someone from the HD seed associated with the
 /// wallet and the amount to send. Handles reading through the wallet data file,
 /// selecting outputs to spend and building the change.
@@ -161,7 +851,8 @@ fn build_send_tx(
 	minimum_confirmations: u64,
 	lock_height: u64,
 	max_outputs: usize,
-	selection_strategy_is_use_all: bool,
+	selection_strategy: SelectionStrategy,
+	fee_base: u64,
 ) -> Result<(Transaction, BlindingFactor, Vec<OutputData>, Identifier), Error> {
 	let key_id = keychain.clone().root_key_id();
 
@@ -173,12 +864,14 @@ fn build_send_tx(
 			current_height,
 			minimum_confirmations,
 			max_outputs,
-			selection_strategy_is_use_all,
+			selection_strategy,
 		)
 	})?;
+	check_funds(config, &key_id, &coins, amount, current_height, minimum_confirmations)?;
 
 	// build transaction skeleton with inputs and change
-	let (mut parts, change_key) = inputs_and_change(&coins, config, keychain, amount)?;
+	let (mut parts, change_key) =
+		inputs_and_change(&coins, config, keychain, amount, Some(fee_base))?;
 
 	// This is more proof of concept than anything but here we set lock_height
 	// on tx being sent (based on current chain height via api).
@@ -213,13 +906,14 @@ pub fn issue_burn_tx(
 			current_height,
 			minimum_confirmations,
 			max_outputs,
-			false,
+			SelectionStrategy::SmallestFirst,
 		)
 	})?;
 
 	debug!(LOGGER, "selected some coins - {}", coins.len());
+	check_funds(config, &key_id, &coins, amount, current_height, minimum_confirmations)?;
 
-	let (mut parts, _) = inputs_and_change(&coins, config, keychain, amount)?;
+	let (mut parts, _) = inputs_and_change(&coins, config, keychain, amount, None)?;
 
 	// add burn output and fees
 	let fee = tx_fee(coins.len(), 2, None);
@@ -231,29 +925,61 @@ pub fn issue_burn_tx(
 
 	let tx_hex = util::to_hex(ser::ser_vec(&tx_burn).unwrap());
 	let url = format!("{}/v1/pool/push", config.check_node_api_http_addr.as_str());
-	let _: () =
-		api::client::post(url.as_str(), &TxWrapper { tx_hex: tx_hex }).map_err(|e| Error::Node(e))?;
+	let _: () = api::client::post_with_secret(
+		url.as_str(),
+		&TxWrapper { tx_hex: tx_hex },
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))?;
 	Ok(())
 }
 
+/// Checks the selected coins cover the amount, reporting how much of the
+/// balance isn't spendable yet otherwise (not enough confirmations, immature
+/// coinbase or locked by a pending transaction).
+pub fn check_funds(
+	config: &WalletConfig,
+	root_key_id: &Identifier,
+	coins: &Vec<OutputData>,
+	amount: u64,
+	current_height: u64,
+	minimum_confirmations: u64,
+) -> Result<(), Error> {
+	let available: u64 = coins.iter().map(|c| c.value).sum();
+	if available >= amount {
+		return Ok(());
+	}
+	let balance = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		info::balance(
+			wallet_data,
+			root_key_id,
+			current_height,
+			minimum_confirmations,
+		)
+	})?;
+	Err(Error::NotEnoughFunds {
+		available: available,
+		awaiting_confirmation: balance.amount_awaiting_confirmation,
+		immature: balance.amount_confirmed_but_locked,
+		locked: balance.amount_locked,
+	})
+}
+
 fn inputs_and_change(
 	coins: &Vec<OutputData>,
 	config: &WalletConfig,
 	keychain: &Keychain,
 	amount: u64,
+	fee_base: Option<u64>,
 ) -> Result<(Vec<Box<build::Append>>, Identifier), Error> {
 	let mut parts = vec![];
 
 	// calculate the total across all inputs, and how much is left
 	let total: u64 = coins.iter().map(|c| c.value).sum();
-	if total < amount {
-		return Err(Error::NotEnoughFunds(total as u64));
-	}
 
 	// sender is responsible for setting the fee on the partial tx
 	// recipient should double check the fee calculation and not blindly trust the
 	// sender
-	let fee = tx_fee(coins.len(), 2, None);
+	let fee = tx_fee(coins.len(), 2, fee_base);
 	parts.push(build::with_fee(fee));
 
 	// if we are spending 10,000 coins to send 1,000 then our change will be 9,000
@@ -263,17 +989,22 @@ fn inputs_and_change(
 	let change = total - amount;
 
 	// build inputs using the appropriate derived key_ids
-	for coin in coins {
-		let key_id = keychain.derive_key_id(coin.n_child)?;
-		if coin.is_coinbase {
-			parts.push(build::coinbase_input(coin.value, coin.block.hash(), key_id));
-		} else {
-			parts.push(build::input(coin.value, coin.block.hash(), key_id));
-		}
-	}
+	parts.append(&mut input_parts(coins, keychain)?);
+
+	let change_key = add_change_output(config, keychain, change)?;
+	parts.push(build::output(change, change_key.clone()));
+
+	Ok((parts, change_key))
+}
 
-	// track the output representing our change
-	let change_key = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+/// Tracks a new output of the provided value as our change, unconfirmed
+/// until the transaction it's part of gets mined.
+pub fn add_change_output(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	change: u64,
+) -> Result<Identifier, Error> {
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
 		let root_key_id = keychain.root_key_id();
 		let change_derivation = wallet_data.next_child(root_key_id.clone());
 		let change_key = keychain.derive_key_id(change_derivation).unwrap();
@@ -291,20 +1022,53 @@ fn inputs_and_change(
 		});
 
 		change_key
-	})?;
-
-	parts.push(build::output(change, change_key.clone()));
+	})
+}
 
-	Ok((parts, change_key))
+/// Builds the inputs spending the provided coins, using the appropriate
+/// derived key_ids
+pub fn input_parts(
+	coins: &Vec<OutputData>,
+	keychain: &Keychain,
+) -> Result<Vec<Box<build::Append>>, Error> {
+	let mut parts = vec![];
+	for coin in coins {
+		let key_id = keychain.derive_key_id(coin.n_child)?;
+		if coin.is_coinbase {
+			parts.push(build::coinbase_input(coin.value, coin.block.hash(), key_id));
+		} else {
+			parts.push(build::input(coin.value, coin.block.hash(), key_id));
+		}
+	}
+	Ok(parts)
 }
 
 #[cfg(test)]
 mod test {
-	use core::core::build::{input, output, transaction};
+	use std::env;
+	use std::fs;
+
+	use core::core::build::{input, output, transaction, with_fee};
 	use core::core::hash::ZERO_HASH;
 	use keychain::Keychain;
+	use receiver::receive_file_tx;
+	use types::{build_partial_tx, read_partial_tx, tx_fee, BlockIdentifier, OutputData,
+	            OutputStatus, PendingSend, WalletConfig, WalletData};
+	use util;
+	use super::{complete_tx, repost_change, repost_tx, restore_context, send_context,
+	            send_fee_base};
 
 
+	#[test]
+	// an odd fee base could make for an odd fee, refused by the nodes
+	fn fee_base_rounded_to_even() {
+		let config = WalletConfig::default();
+		assert_eq!(send_fee_base(&config, Some(1_000_001)), 1_000_002);
+		assert_eq!(send_fee_base(&config, Some(1_000_000)), 1_000_000);
+		let fee = tx_fee(2, 1, Some(send_fee_base(&config, Some(7))));
+		assert_eq!(fee % 2, 0);
+	}
+
 	#[test]
 	// demonstrate that input.commitment == referenced output.commitment
 	// based on the public key and amount begin spent
@@ -318,4 +1082,167 @@ mod test {
 		assert_eq!(tx1.outputs[0].features, tx2.inputs[0].features);
 		assert_eq!(tx1.outputs[0].commitment(), tx2.inputs[0].commitment());
 	}
+
+	#[test]
+	// a transaction sent through a file, received offline and finalized by a
+	// later invocation of the sender wallet
+	fn file_send_receive_finalize() {
+		let dir = env::temp_dir().join("grin_wallet_file_tx");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let mut recp_config = WalletConfig::default();
+		recp_config.data_file_dir = dir.to_str().unwrap().to_string();
+
+		let seed = [1u8; 32];
+		let sender = Keychain::from_seed(&seed).unwrap();
+		let recipient = Keychain::from_random_seed().unwrap();
+
+		// the recipient pays the fee out of the amount
+		let amount = 6_000_000;
+		let fee = tx_fee(1, 2, None);
+		let (tx, blind_sum) = transaction(
+			vec![
+				input(10_000_000, ZERO_HASH, sender.derive_key_id(1).unwrap()),
+				output(10_000_000 - amount, sender.derive_key_id(2).unwrap()),
+				with_fee(fee),
+			],
+			&sender,
+		).unwrap();
+		sender.aggsig_create_context(blind_sum.secret_key());
+		let context = send_context(&sender).unwrap();
+		let (sec_key, _) = sender.aggsig_get_private_keys();
+		assert!(context.sec_key != util::to_hex(sec_key[..].to_vec()));
+		let partial_tx = build_partial_tx(&sender, amount, None, tx);
+
+		let response = receive_file_tx(&recp_config, &recipient, &partial_tx).unwrap();
+
+		let sender = Keychain::from_seed(&seed).unwrap();
+		restore_context(&sender, &context).unwrap();
+		let (recp_amount, recp_pub_blinding, recp_pub_nonce, recp_sig, tx) =
+			read_partial_tx(&sender, &response).unwrap();
+		assert_eq!(recp_amount, amount);
+		assert_eq!(tx.outputs.len(), 2);
+		let (final_tx, _) = complete_tx(
+			&sender,
+			&recp_sig.unwrap(),
+			&recp_pub_nonce,
+			&recp_pub_blinding,
+			tx,
+		).unwrap();
+		assert_eq!(final_tx.fee(), fee);
+
+		// the saved context doesn't give the keys away to another wallet
+		let other = Keychain::from_seed(&[2u8; 32]).unwrap();
+		match context.secret_keys(&other) {
+			Ok((other_key, _)) => assert!(other_key != sec_key),
+			Err(_) => {}
+		}
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	// an aggregated transaction from the sender is refused, not a crash of
+	// the recipient
+	fn receive_two_kernels_refused() {
+		let dir = env::temp_dir().join("grin_wallet_two_kernels");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let mut recp_config = WalletConfig::default();
+		recp_config.data_file_dir = dir.to_str().unwrap().to_string();
+
+		let sender = Keychain::from_random_seed().unwrap();
+		let recipient = Keychain::from_random_seed().unwrap();
+		let amount = 6_000_000;
+		let (tx, blind_sum) = transaction(
+			vec![
+				input(10_000_000, ZERO_HASH, sender.derive_key_id(1).unwrap()),
+				output(10_000_000 - amount, sender.derive_key_id(2).unwrap()),
+				with_fee(tx_fee(1, 2, None)),
+			],
+			&sender,
+		).unwrap();
+		let kernel = tx.kernels[0].clone();
+		let tx = tx.with_kernel(kernel);
+		sender.aggsig_create_context(blind_sum.secret_key());
+		let partial_tx = build_partial_tx(&sender, amount, None, tx);
+
+		assert!(receive_file_tx(&recp_config, &recipient, &partial_tx).is_err());
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	// the fee bump of a repost comes out of our change, the recipient output
+	// stays the same
+	fn repost_keeps_recipient_amount() {
+		let dir = env::temp_dir().join("grin_wallet_repost");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let mut recp_config = WalletConfig::default();
+		recp_config.data_file_dir = dir.to_str().unwrap().to_string();
+
+		let sender = Keychain::from_random_seed().unwrap();
+		let recipient = Keychain::from_random_seed().unwrap();
+		let amount = 6_000_000;
+		let fee = tx_fee(1, 2, None);
+		let (tx, blind_sum) = transaction(
+			vec![
+				input(10_000_000, ZERO_HASH, sender.derive_key_id(1).unwrap()),
+				output(10_000_000 - amount, sender.derive_key_id(2).unwrap()),
+				with_fee(fee),
+			],
+			&sender,
+		).unwrap();
+		sender.aggsig_create_context(blind_sum.secret_key());
+		let partial_tx = build_partial_tx(&sender, amount, None, tx);
+		receive_file_tx(&recp_config, &recipient, &partial_tx).unwrap();
+
+		let coin = OutputData {
+			root_key_id: sender.root_key_id(),
+			key_id: sender.derive_key_id(1).unwrap(),
+			n_child: 1,
+			value: 10_000_000,
+			status: OutputStatus::Locked,
+			height: 1,
+			lock_height: 0,
+			is_coinbase: false,
+			block: BlockIdentifier::zero(),
+		};
+		let send = PendingSend {
+			id: 1,
+			amount: amount,
+			fee: fee,
+			lock_height: 0,
+			dest: "http://127.0.0.1:13415".to_string(),
+			inputs: vec![coin.key_id.clone()],
+			change: sender.derive_key_id(2).unwrap(),
+			replaced_changes: vec![],
+			file_context: None,
+			kernels: vec![],
+			batch: vec![],
+		};
+
+		// the change can't cover a bump larger than itself
+		assert!(repost_change(&send, 10_000_000 - amount, fee + 10_000_000).is_err());
+
+		let change = repost_change(&send, 10_000_000 - amount, fee * 2).unwrap();
+		assert_eq!(change, 10_000_000 - amount - fee);
+		let change_key = sender.derive_key_id(3).unwrap();
+		let (tx, blind_sum, repost_amount) =
+			repost_tx(&sender, &send, &vec![coin], change, &change_key, fee * 2).unwrap();
+		assert_eq!(tx.fee(), fee * 2);
+		assert_eq!(repost_amount, amount + fee);
+		sender.aggsig_create_context(blind_sum.secret_key());
+		let partial_tx = build_partial_tx(&sender, repost_amount, None, tx);
+		receive_file_tx(&recp_config, &recipient, &partial_tx).unwrap();
+
+		// both versions of the transaction pay the recipient the same
+		let values = WalletData::read_wallet(&recp_config.data_file_dir, |wallet_data| {
+			wallet_data.outputs.values().map(|o| o.value).collect::<Vec<_>>()
+		}).unwrap();
+		assert_eq!(values, vec![amount - fee, amount - fee]);
+
+		let _ = fs::remove_dir_all(&dir);
+	}
 }
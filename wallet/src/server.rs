@@ -13,13 +13,20 @@
 // limitations under the License.
 
 
+use api;
 use api::ApiServer;
+use iron::Chain;
 use keychain::Keychain;
-use handlers::CoinbaseHandler;
+use handlers::{BalanceHandler, CancelTxHandler, CoinbaseHandler, LocalOnly, LockOutputsHandler,
+               LockedOutputsHandler, SendTxHandler, TxsHandler, UnlockOutputsHandler};
 use receiver::WalletReceiver;
 use types::WalletConfig;
 use util::LOGGER;
 
+/// Starts the foreign API of the wallet, receiving transactions and building
+/// coinbase outputs, and its owner API if enabled. The owner API only listens
+/// on the local interface, each API having its own secret and TLS settings.
+/// Blocks as long as the listeners run.
 pub fn start_rest_apis(wallet_config: WalletConfig, keychain: Keychain) {
 	info!(
 		LOGGER,
@@ -27,6 +34,7 @@ pub fn start_rest_apis(wallet_config: WalletConfig, keychain: Keychain) {
 		wallet_config.api_listen_addr()
 	);
 
+	let foreign_secrets = api_secrets(&wallet_config.foreign_api_secret_path, "foreign API");
 	let receive_tx_handler = WalletReceiver {
 		config: wallet_config.clone(),
 		keychain: keychain.clone(),
@@ -35,16 +43,90 @@ pub fn start_rest_apis(wallet_config: WalletConfig, keychain: Keychain) {
 		config: wallet_config.clone(),
 		keychain: keychain.clone(),
 	};
-
-	let router = router!(
-		receive_tx: post "/receive/transaction" => receive_tx_handler,
-		receive_coinbase: post "/receive/coinbase" => coinbase_handler,
+	let foreign_router = router!(
+		receive_tx: post "/receive/transaction" => api::guard(receive_tx_handler, &foreign_secrets),
+		receive_coinbase: post "/receive/coinbase" => api::guard(coinbase_handler, &foreign_secrets),
 	);
 
-	let mut apis = ApiServer::new("/v1".to_string());
-	apis.register_handler(router);
-	match apis.start(wallet_config.api_listen_addr()) {
+	let mut foreign_apis = ApiServer::new("/v1".to_string());
+	foreign_apis.register_handler(foreign_router);
+	let result = match wallet_config.api_tls_config {
+		Some(ref tls_config) => foreign_apis.start_tls(wallet_config.api_listen_addr(), tls_config),
+		None => foreign_apis.start(wallet_config.api_listen_addr()),
+	};
+	match result {
 		Err(e) => error!(LOGGER, "Failed to start Grin wallet listener: {}.", e),
 		Ok(_) => info!(LOGGER, "Wallet listener started"),
 	};
+
+	let owner_addr = match wallet_config.owner_api_listen_addr() {
+		Some(addr) => addr,
+		None => return,
+	};
+	info!(LOGGER, "Starting the Grin wallet owner API at {}...", owner_addr);
+
+	let owner_secrets = api_secrets(&Some(wallet_config.owner_api_secret_path()), "owner API");
+	let balance_handler = BalanceHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let send_tx_handler = SendTxHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let cancel_tx_handler = CancelTxHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let txs_handler = TxsHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let lock_outputs_handler = LockOutputsHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let unlock_outputs_handler = UnlockOutputsHandler {
+		config: wallet_config.clone(),
+	};
+	let locked_outputs_handler = LockedOutputsHandler {
+		config: wallet_config.clone(),
+		keychain: keychain.clone(),
+	};
+	let owner_router = router!(
+		balance: get "/wallet/owner/balance" => api::guard(balance_handler, &owner_secrets),
+		send_tx: post "/wallet/owner/send" => api::guard(send_tx_handler, &owner_secrets),
+		cancel_tx: post "/wallet/owner/cancel_tx/*" => api::guard(cancel_tx_handler, &owner_secrets),
+		txs: get "/wallet/owner/txs" => api::guard(txs_handler, &owner_secrets),
+		lock_outputs: post "/wallet/owner/lock_outputs" => api::guard(lock_outputs_handler, &owner_secrets),
+		unlock_outputs: post "/wallet/owner/unlock_outputs" => api::guard(unlock_outputs_handler, &owner_secrets),
+		locked_outputs: get "/wallet/owner/locked_outputs" => api::guard(locked_outputs_handler, &owner_secrets),
+	);
+	// the listener is local already, double checking doesn't hurt
+	let mut owner_chain = Chain::new(owner_router);
+	owner_chain.link_before(LocalOnly);
+
+	let mut owner_apis = ApiServer::new("/v1".to_string());
+	owner_apis.register_handler(owner_chain);
+	let result = match wallet_config.owner_api_tls_config {
+		Some(ref tls_config) => owner_apis.start_tls(owner_addr, tls_config),
+		None => owner_apis.start(owner_addr),
+	};
+	match result {
+		Err(e) => error!(LOGGER, "Failed to start Grin wallet owner API: {}.", e),
+		Ok(_) => info!(LOGGER, "Wallet owner API started"),
+	};
+}
+
+// Secrets opening an API, read from the provided file or generated on first
+// run, none if no file is configured
+fn api_secrets(path: &Option<String>, name: &str) -> Vec<String> {
+	match *path {
+		Some(ref path) => {
+			let secret = api::init_api_secret(path)
+				.unwrap_or_else(|e| panic!("Failed to read the {} secret: {:?}", name, e));
+			vec![secret]
+		}
+		None => vec![],
+	}
 }
@@ -0,0 +1,420 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Adaptor locked transactions, the wallet side of atomic swaps. The
+//! recipient of a transaction sent through a file locks its partial
+//! signature on an adaptor point T = t*G, of a secret t only it knows. The
+//! sender confirms with its own partial signature, locked on the same point,
+//! but can't complete the kernel signature: the recipient does, with t, and
+//! posts the transaction. The sender then extracts t from the kernel
+//! signature, i.e. to claim the coins locked on T on the other side of the
+//! swap. The exchange goes:
+//!
+//! 1. the sender sends to a file, as for any transaction (`send`),
+//! 2. the recipient responds with its output and its partial signature
+//!    locked on a new adaptor point, keeping t (`receive --adaptor`),
+//! 3. the sender confirms with its partial signature (`swap_confirm`),
+//! 4. the recipient completes and posts the transaction (`swap_complete`),
+//! 5. the sender extracts t from it (`swap_secret`).
+//!
+//! Until the transaction is mined, the sender could still double spend its
+//! inputs while knowing t from the posted transaction. A full swap first
+//! locks the coins in a 2 of 2 output, which the wallet can't build yet (see
+//! `Keychain::multisig_commit`).
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use api;
+use core::core::Transaction;
+use core::ser;
+use keychain::Keychain;
+use receiver::{self, TxWrapper};
+use sender;
+use types::*;
+use util::secp::Signature;
+use util::secp::key::{PublicKey, SecretKey};
+use util::LOGGER;
+use util;
+
+/// Responds to a transaction sent through a file with our output and our
+/// partial signature locked on a new adaptor point. The adaptor secret is
+/// kept, along with our aggsig context, until the sender confirms.
+pub fn receive_swap_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+) -> Result<PartialTx, Error> {
+	let (secret, point) = keychain.adaptor_new_secret()?;
+	let response = receiver::respond_to_file_tx(config, keychain, partial_tx, Some(&point))?;
+	let (sec_key, sec_nonce) = keychain.aggsig_get_private_keys();
+	let context = SwapContext::new(keychain, &sec_key, &sec_nonce, &secret)?;
+	let path = context_path(config, &public_excess_hex(keychain, &point));
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	File::create(&path)?.write_all(serde_json::to_string_pretty(&context)?.as_bytes())?;
+	info!(
+		LOGGER,
+		"Transaction received, locked on adaptor point {}",
+		public_excess_hex(keychain, &point)
+	);
+	Ok(response)
+}
+
+/// Confirms the adaptor locked response of the recipient of a transaction
+/// we sent through a file with our own partial signature, returned to the
+/// recipient. The transaction isn't posted, only the recipient can complete
+/// it.
+pub fn confirm_swap_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	path: &str,
+) -> Result<PartialTx, Error> {
+	let mut contents = String::new();
+	File::open(path)?.read_to_string(&mut contents)?;
+	let response: PartialTx = serde_json::from_str(&contents)?;
+	let (amount, recp_pub_blinding, _, _, tx) = read_partial_tx(keychain, &response)?;
+	let send = sender::find_file_send(config, keychain, amount, &tx, path)?;
+	sender::restore_context(keychain, send.file_context.as_ref().unwrap())?;
+	let (confirmation, lock) = swap_confirmation(keychain, &response)?;
+
+	let final_pubkey = keychain.aggsig_calculate_final_pubkey(&recp_pub_blinding)?;
+	let proof = sender::check_payment_proof(keychain, &response, send.amount, &final_pubkey)?;
+	let excess = public_excess_hex(keychain, &final_pubkey);
+	let kernel = util::to_hex(tx.kernel_excess()?.0.to_vec());
+
+	// our secret nonce must never sign twice, the saved context goes before
+	// our partial signature does
+	WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+		for pending in wallet_data.pending_sends.iter_mut() {
+			if pending.id == send.id {
+				pending.file_context = None;
+				pending.kernels.push(kernel.clone());
+			}
+		}
+		if let Some(entry) = wallet_data.sent_tx_log_entry_mut(&send.inputs) {
+			entry.excess = Some(excess);
+			entry.payment_proof = proof;
+			entry.swap = Some(lock.clone());
+		}
+	})?;
+	let _ = fs::remove_file(sender::slate_path(config, send.id));
+	info!(
+		LOGGER,
+		"Transaction {} confirmed, waiting on the recipient to complete it",
+		send.id
+	);
+	Ok(confirmation)
+}
+
+/// Completes an adaptor locked transaction we received, once confirmed by
+/// the sender, with the adaptor secret and posts it to the node. Returns
+/// the final transaction, the sender extracting the secret from it.
+pub fn complete_swap_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+) -> Result<PartialTx, Error> {
+	match partial_tx.phase {
+		PartialTxPhase::SenderConfirmation => (),
+		_ => {
+			return Err(Error::Format(format!(
+				"Expected a sender confirmation, got {:?}",
+				partial_tx.phase
+			)))
+		}
+	}
+	let point = match partial_tx.adaptor_point {
+		Some(ref point) => point.clone(),
+		None => return Err(Error::Format("No adaptor point in the confirmation.".to_string())),
+	};
+	let path = context_path(config, &point);
+	let mut contents = String::new();
+	File::open(&path)
+		.and_then(|mut f| f.read_to_string(&mut contents))
+		.map_err(|_| {
+			Error::GenericError(format!("no received transaction locked on adaptor point {}", point))
+		})?;
+	let context: SwapContext = serde_json::from_str(&contents)?;
+	sender::restore_context(keychain, &context.context)?;
+	let secret = context.adaptor_secret(keychain)?;
+	let final_tx = complete_swap(keychain, partial_tx, &secret)?;
+
+	let tx_hex = util::to_hex(ser::ser_vec(&final_tx).unwrap());
+	let url = format!("{}/v1/pool/push", config.check_node_api_http_addr.as_str());
+	api::client::post_with_secret(
+		url.as_str(),
+		&TxWrapper { tx_hex: tx_hex },
+		config.node_api_secret(),
+	).map_err(|e| Error::Node(e))?;
+	let _ = fs::remove_file(&path);
+
+	let final_sig = final_tx.kernels[0].excess_sig.clone();
+	let mut response = build_partial_tx(keychain, partial_tx.amount, Some(final_sig), final_tx);
+	response.phase = PartialTxPhase::ReceiverConfirmation;
+	response.adaptor_point = Some(point);
+	Ok(response)
+}
+
+/// Adaptor secret of a transaction we sent, extracted from its kernel once
+/// completed by the recipient, hex encoded.
+pub fn swap_secret(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	partial_tx: &PartialTx,
+) -> Result<String, Error> {
+	let point = match partial_tx.adaptor_point {
+		Some(ref point) => point.clone(),
+		None => return Err(Error::Format("No adaptor point in the transaction.".to_string())),
+	};
+	let lock = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data
+			.tx_log
+			.iter()
+			.filter_map(|entry| entry.swap.clone())
+			.find(|lock| lock.adaptor_point == point)
+	})?;
+	let lock = match lock {
+		Some(lock) => lock,
+		None => {
+			return Err(Error::GenericError(format!(
+				"no sent transaction locked on adaptor point {}",
+				point
+			)))
+		}
+	};
+	let (_, _, _, _, tx) = read_partial_tx(keychain, partial_tx)?;
+	let secret = extract_secret(keychain, &lock, &tx)?;
+	Ok(util::to_hex(secret[..].to_vec()))
+}
+
+// Where the recipient keeps its swap context until the sender confirms.
+fn context_path(config: &WalletConfig, point: &str) -> PathBuf {
+	Path::new(&config.data_file_dir)
+		.join("swaps")
+		.join(format!("{}.json", point))
+}
+
+// Checks the adaptor locked partial signature of the recipient and builds
+// our confirmation, the aggsig context of the send being the current one.
+fn swap_confirmation(
+	keychain: &Keychain,
+	response: &PartialTx,
+) -> Result<(PartialTx, SwapLock), Error> {
+	let point_bin = match response.adaptor_point {
+		Some(ref point) => util::from_hex(point.clone())?,
+		None => return Err(Error::Format("No adaptor point in the response.".to_string())),
+	};
+	let point = PublicKey::from_slice(keychain.secp(), &point_bin[..])?;
+	let (amount, recp_pub_blinding, recp_pub_nonce, sig, tx) =
+		read_partial_tx(keychain, response)?;
+	let recp_sig = match sig {
+		Some(sig) => sig,
+		None => {
+			return Err(Error::Signature(String::from(
+				"Response from recipient has no partial signature.",
+			)))
+		}
+	};
+	if !keychain.aggsig_verify_adaptor_partial_sig(
+		&recp_sig,
+		&recp_pub_nonce,
+		&point,
+		&recp_pub_blinding,
+		tx.fee(),
+		tx.lock_height(),
+	) {
+		error!(LOGGER, "Adaptor partial sig from recipient invalid.");
+		return Err(Error::Signature(String::from(
+			"Adaptor partial sig from recipient invalid.",
+		)));
+	}
+	let our_sig = keychain.aggsig_calculate_adaptor_partial_sig(
+		&recp_pub_nonce,
+		&point,
+		tx.fee(),
+		tx.lock_height(),
+	)?;
+
+	let lock = SwapLock {
+		adaptor_point: public_excess_hex(keychain, &point),
+		their_sig: util::to_hex(recp_sig.serialize_der(keychain.secp())),
+		our_sig: util::to_hex(our_sig.serialize_der(keychain.secp())),
+	};
+	let mut confirmation = build_partial_tx(keychain, amount, Some(our_sig), tx);
+	confirmation.phase = PartialTxPhase::SenderConfirmation;
+	confirmation.adaptor_point = Some(lock.adaptor_point.clone());
+	Ok((confirmation, lock))
+}
+
+// Completes the transaction confirmed by the sender with the adaptor
+// secret, our aggsig context being the current one.
+fn complete_swap(
+	keychain: &Keychain,
+	confirmation: &PartialTx,
+	secret: &SecretKey,
+) -> Result<Transaction, Error> {
+	let point = PublicKey::from_secret_key(keychain.secp(), secret)?;
+	let (_, sender_pub_blinding, sender_pub_nonce, sig, tx) =
+		read_partial_tx(keychain, confirmation)?;
+	let sender_sig = match sig {
+		Some(sig) => sig,
+		None => {
+			return Err(Error::Signature(String::from(
+				"Confirmation from sender has no partial signature.",
+			)))
+		}
+	};
+	if !keychain.aggsig_verify_adaptor_partial_sig(
+		&sender_sig,
+		&sender_pub_nonce,
+		&point,
+		&sender_pub_blinding,
+		tx.fee(),
+		tx.lock_height(),
+	) {
+		error!(LOGGER, "Adaptor partial sig from sender invalid.");
+		return Err(Error::Signature(String::from(
+			"Adaptor partial sig from sender invalid.",
+		)));
+	}
+	let our_sig = keychain.aggsig_calculate_adaptor_partial_sig(
+		&sender_pub_nonce,
+		&point,
+		tx.fee(),
+		tx.lock_height(),
+	)?;
+	let final_pubkey = keychain.aggsig_calculate_final_pubkey(&sender_pub_blinding)?;
+	let final_sig = keychain.aggsig_calculate_adaptor_final_sig(
+		&sender_sig,
+		&our_sig,
+		&sender_pub_nonce,
+		secret,
+		&final_pubkey,
+		tx.fee(),
+		tx.lock_height(),
+	)?;
+
+	let mut final_tx = tx;
+	final_tx.kernels[0].excess = final_tx.kernel_excess()?;
+	final_tx.kernels[0].excess_sig = final_sig;
+	final_tx.validate()?;
+	Ok(final_tx)
+}
+
+// The adaptor secret, as the difference between the kernel signature of the
+// completed transaction and both partial signatures.
+fn extract_secret(
+	keychain: &Keychain,
+	lock: &SwapLock,
+	tx: &Transaction,
+) -> Result<SecretKey, Error> {
+	let secp = keychain.secp();
+	let point = PublicKey::from_slice(secp, &util::from_hex(lock.adaptor_point.clone())?[..])?;
+	let their_sig = Signature::from_der(secp, &util::from_hex(lock.their_sig.clone())?[..])?;
+	let our_sig = Signature::from_der(secp, &util::from_hex(lock.our_sig.clone())?[..])?;
+	Ok(keychain.aggsig_extract_adaptor_secret(
+		&tx.kernels[0].excess_sig,
+		&their_sig,
+		&our_sig,
+		&point,
+	)?)
+}
+
+#[cfg(test)]
+mod test {
+	use std::env;
+	use std::fs::{self, File};
+	use std::io::Read;
+
+	use serde_json;
+
+	use core::core::build::{input, output, transaction, with_fee};
+	use core::core::hash::ZERO_HASH;
+	use keychain::Keychain;
+	use sender::{restore_context, send_context};
+	use types::{build_partial_tx, public_excess_hex, read_partial_tx, tx_fee, PartialTxPhase,
+	            SwapContext, WalletConfig};
+	use util::secp::key::PublicKey;
+	use super::{complete_swap, context_path, extract_secret, receive_swap_tx, swap_confirmation};
+
+	#[test]
+	// the whole exchange between the sender and the recipient, the sender
+	// learning the adaptor secret once the recipient completed the kernel
+	fn swap_round_trip() {
+		let dir = env::temp_dir().join("grin_wallet_swap");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let mut recp_config = WalletConfig::default();
+		recp_config.data_file_dir = dir.to_str().unwrap().to_string();
+
+		let sender = Keychain::from_random_seed().unwrap();
+		let recipient = Keychain::from_random_seed().unwrap();
+		let amount = 6_000_000;
+		let (tx, blind_sum) = transaction(
+			vec![
+				input(10_000_000, ZERO_HASH, sender.derive_key_id(1).unwrap()),
+				output(10_000_000 - amount, sender.derive_key_id(2).unwrap()),
+				with_fee(tx_fee(1, 2, None)),
+			],
+			&sender,
+		).unwrap();
+		sender.aggsig_create_context(blind_sum.secret_key());
+		let send_ctx = send_context(&sender).unwrap();
+		let partial_tx = build_partial_tx(&sender, amount, None, tx);
+
+		// the recipient locks its partial signature, keeping the secret
+		let response = receive_swap_tx(&recp_config, &recipient, &partial_tx).unwrap();
+		assert_eq!(response.phase, PartialTxPhase::ReceiverInitiation);
+		let point = response.adaptor_point.clone().unwrap();
+		let mut contents = String::new();
+		File::open(context_path(&recp_config, &point))
+			.unwrap()
+			.read_to_string(&mut contents)
+			.unwrap();
+		let recp_ctx: SwapContext = serde_json::from_str(&contents).unwrap();
+		let secret = recp_ctx.adaptor_secret(&recipient).unwrap();
+		let secret_point = PublicKey::from_secret_key(recipient.secp(), &secret).unwrap();
+		assert_eq!(public_excess_hex(&recipient, &secret_point), point);
+
+		// not a partial signature the sender could complete the kernel with
+		restore_context(&sender, &send_ctx).unwrap();
+		let (_, recp_pub_blinding, recp_pub_nonce, recp_sig, tx) =
+			read_partial_tx(&sender, &response).unwrap();
+		assert!(!sender.aggsig_verify_partial_sig(
+			&recp_sig.unwrap(),
+			&recp_pub_nonce,
+			&recp_pub_blinding,
+			tx.fee(),
+			tx.lock_height(),
+		));
+
+		let (confirmation, lock) = swap_confirmation(&sender, &response).unwrap();
+		assert_eq!(confirmation.phase, PartialTxPhase::SenderConfirmation);
+
+		// only the adaptor secret completes the transaction
+		restore_context(&recipient, &recp_ctx.context).unwrap();
+		let (other, _) = recipient.adaptor_new_secret().unwrap();
+		assert!(complete_swap(&recipient, &confirmation, &other).is_err());
+		let final_tx = complete_swap(&recipient, &confirmation, &secret).unwrap();
+
+		assert_eq!(extract_secret(&sender, &lock, &final_tx).unwrap(), secret);
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,178 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The wallet transaction log, listing what we sent and received transaction
+//! by transaction rather than output by output, and the payment proofs of
+//! the transactions we sent.
+
+use checker;
+use keychain::Keychain;
+use core::core;
+use types::{public_excess_hex, Error, PaymentProof, TxLogEntry, TxLogEntryType, WalletConfig,
+	WalletData};
+use prettytable;
+use term;
+use time;
+use util::LOGGER;
+use std::io::prelude::*;
+
+/// Logged transactions of the current account, oldest first. Refreshes their
+/// status from the node first, if it can be reached.
+pub fn retrieve_txs(config: &WalletConfig, keychain: &Keychain) -> Result<Vec<TxLogEntry>, Error> {
+	if let Err(e) = checker::refresh_outputs(config, keychain) {
+		warn!(LOGGER, "Could not refresh transaction status: {:?}", e);
+	}
+	let root_key_id = keychain.root_key_id();
+	WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		let mut txs = wallet_data
+			.tx_log
+			.iter()
+			.filter(|t| t.root_key_id == root_key_id)
+			.cloned()
+			.collect::<Vec<_>>();
+		txs.sort_by_key(|t| t.id);
+		txs
+	})
+}
+
+/// Payment proof of a sent transaction, as returned by its recipient. A
+/// batch send has one per recipient, picked by its position in the batch.
+pub fn payment_proof(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	id: u32,
+	recipient: Option<usize>,
+) -> Result<PaymentProof, Error> {
+	let root_key_id = keychain.root_key_id();
+	let entry = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
+		wallet_data
+			.tx_log
+			.iter()
+			.find(|t| t.id == id && t.root_key_id == root_key_id)
+			.cloned()
+	})?;
+	match entry {
+		Some(TxLogEntry {
+			tx_type: TxLogEntryType::Sent,
+			ref batch_payment_proofs,
+			..
+		}) if !batch_payment_proofs.is_empty() => match recipient {
+			Some(n) if n < batch_payment_proofs.len() => match batch_payment_proofs[n] {
+				Some(ref proof) => Ok(proof.clone()),
+				None => Err(Error::GenericError(format!(
+					"recipient {} of transaction {} provided no payment proof",
+					n, id
+				))),
+			},
+			_ => Err(Error::GenericError(format!(
+				"transaction {} is a batch, pick one of its {} recipients",
+				id,
+				batch_payment_proofs.len()
+			))),
+		},
+		Some(TxLogEntry {
+			tx_type: TxLogEntryType::Sent,
+			payment_proof: Some(proof),
+			..
+		}) => Ok(proof),
+		Some(_) => Err(Error::GenericError(format!(
+			"transaction {} has no payment proof",
+			id
+		))),
+		None => Err(Error::GenericError(format!("no transaction {}", id))),
+	}
+}
+
+/// Address recipients sign payment proofs with, for payers to check a proof
+/// is from the expected recipient.
+pub fn payment_proof_address(keychain: &Keychain) -> Result<String, Error> {
+	let address = keychain.payment_proof_pubkey()?;
+	Ok(public_excess_hex(keychain, &address))
+}
+
+/// Checks a payment proof is validly signed, and by the provided recipient
+/// address if any.
+pub fn verify_payment_proof(proof: &PaymentProof, address: Option<&str>) -> Result<(), Error> {
+	if let Some(address) = address {
+		if proof.recipient_address != address {
+			return Err(Error::Signature(format!(
+				"Payment proof is from {}, not {}",
+				proof.recipient_address, address
+			)));
+		}
+	}
+	proof.verify()
+}
+
+pub fn show_txs(config: &WalletConfig, keychain: &Keychain) {
+	let txs = match retrieve_txs(config, keychain) {
+		Ok(txs) => txs,
+		Err(e) => {
+			println!("Could not read the wallet transaction log: {:?}", e);
+			return;
+		}
+	};
+
+	println!();
+	let mut t = term::stdout().unwrap();
+	t.fg(term::color::MAGENTA).unwrap();
+	writeln!(t, "Wallet Transactions").unwrap();
+	t.reset().unwrap();
+
+	let mut table = table!();
+	table.set_titles(row![
+		bMG->"Id",
+		bMG->"Type",
+		bMG->"Created",
+		bMG->"Status",
+		bMG->"Confirmed At",
+		bMG->"Credited",
+		bMG->"Debited",
+		bMG->"Fee",
+		bMG->"Kernel Excess"
+	]);
+
+	for tx in txs {
+		let id = format!("{}", tx.id);
+		let tx_type = format!("{:?}", tx.tx_type);
+		let created = format!(
+			"{}",
+			time::at_utc(time::Timespec::new(tx.creation_ts, 0)).rfc3339()
+		);
+		let status = format!("{:?}", tx.status);
+		let confirmed_at = match tx.confirmation_height {
+			Some(height) => format!("{}", height),
+			None => "-".to_string(),
+		};
+		let credited = core::amount_to_hr_string(tx.amount_credited);
+		let debited = core::amount_to_hr_string(tx.amount_debited);
+		let fee = core::amount_to_hr_string(tx.fee);
+		let excess = tx.excess.unwrap_or("-".to_string());
+		table.add_row(row![
+			bFC->id,
+			bFB->tx_type,
+			bFB->created,
+			bFR->status,
+			bFB->confirmed_at,
+			bFG->credited,
+			bFY->debited,
+			bFB->fee,
+			bFB->excess
+		]);
+	}
+
+	table.set_format(*prettytable::format::consts::FORMAT_NO_COLSEP);
+	table.printstd();
+	println!();
+}
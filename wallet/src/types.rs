@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use blake2;
+use byteorder::{BigEndian, ByteOrder};
 use rand::{thread_rng, Rng};
 use std::{error, fmt, num};
 use std::convert::From;
@@ -22,10 +23,12 @@ use std::path::Path;
 use std::path::MAIN_SEPARATOR;
 use std::collections::HashMap;
 use std::cmp::min;
+use std::str::FromStr;
 
 use hyper;
 use serde;
 use serde_json;
+use time;
 use tokio_core::reactor;
 use tokio_retry::Retry;
 use tokio_retry::strategy::FibonacciBackoff;
@@ -37,17 +40,28 @@ use core::core::{transaction, Transaction};
 use core::core::hash::Hash;
 use core::ser;
 use keychain;
+use keychain::mnemonic;
 use util;
 use util::secp;
 use util::secp::Signature;
-use util::secp::key::PublicKey;
+use util::secp::key::{PublicKey, SecretKey};
 use util::LOGGER;
 
 const DAT_FILE: &'static str = "wallet.dat";
 const LOCK_FILE: &'static str = "wallet.lock";
 const SEED_FILE: &'static str = "wallet.seed";
+const PENDING_FILE: &'static str = "wallet.pending";
+const ACCOUNTS_FILE: &'static str = "wallet.accounts";
+const TX_LOG_FILE: &'static str = "wallet.txs";
+const LOCKS_FILE: &'static str = "wallet.locks";
 
-const DEFAULT_BASE_FEE: u64 = consensus::MILLI_GRIN;
+/// Name of the account used when none is selected, its keys are derived
+/// directly from the wallet root key
+pub const DEFAULT_ACCOUNT: &'static str = "default";
+
+/// Fee per unit of transaction weight used when none is provided, the
+/// minimum a recipient accepts
+pub const DEFAULT_BASE_FEE: u64 = consensus::MILLI_GRIN;
 
 /// Transaction fee calculation
 pub fn tx_fee(input_len: usize, output_len: usize, base_fee: Option<u64>) -> u64 {
@@ -66,10 +80,19 @@ pub fn tx_fee(input_len: usize, output_len: usize, base_fee: Option<u64>) -> u64
 /// Wallet errors, mostly wrappers around underlying crypto or I/O errors.
 #[derive(Debug)]
 pub enum Error {
-	NotEnoughFunds(u64),
+	/// Not enough spendable coins for the amount: the most that could be
+	/// selected, and what isn't spendable yet
+	NotEnoughFunds {
+		available: u64,
+		awaiting_confirmation: u64,
+		immature: u64,
+		locked: u64,
+	},
 	FeeDispute { sender_fee: u64, recipient_fee: u64 },
 	FeeExceedsAmount { sender_amount: u64, recipient_fee: u64 },
 	Keychain(keychain::Error),
+	/// Invalid recovery phrase
+	Mnemonic(mnemonic::Error),
 	Transaction(transaction::Error),
 	Secp(secp::Error),
 	WalletData(String),
@@ -110,6 +133,12 @@ impl From<keychain::Error> for Error {
 	}
 }
 
+impl From<mnemonic::Error> for Error {
+	fn from(e: mnemonic::Error) -> Error {
+		Error::Mnemonic(e)
+	}
+}
+
 impl From<secp::Error> for Error {
 	fn from(e: secp::Error) -> Error {
 		Error::Secp(e)
@@ -172,16 +201,48 @@ pub struct WalletConfig {
 	// This may change in the near-future.
 	// pub enable_wallet: bool,
 
-	// The api interface/ip_address that this api server (i.e. this wallet) will run
-	// by default this is 127.0.0.1 (and will not accept connections from external clients)
+	// The api interface/ip_address the foreign API of this wallet (receiving
+	// transactions and building coinbase outputs) listens on, by default this
+	// is 127.0.0.1 (and will not accept connections from external clients)
 	pub api_listen_interface: String,
-	// The port this wallet will run on
+	// The port the foreign API listens on
 	pub api_listen_port: String,
+	// The port the owner API (balance, send, cancel, transaction log) listens
+	// on, always on 127.0.0.1 whatever the foreign API interface. No owner
+	// API listener unless set.
+	#[serde(default)]
+	pub owner_api_listen_port: Option<String>,
 	// The api address of a running server node against which transaction inputs
 	// will be checked during send
 	pub check_node_api_http_addr: String,
+	// File holding the secret to authenticate against the node API with, if
+	// the node requires it
+	#[serde(default)]
+	pub node_api_secret_path: Option<String>,
+	// Serves the foreign API over TLS only, with the provided certificate
+	#[serde(default)]
+	pub api_tls_config: Option<api::TLSConfig>,
+	// Serves the owner API over TLS only, with the provided certificate
+	#[serde(default)]
+	pub owner_api_tls_config: Option<api::TLSConfig>,
+	// File holding the secret required by the owner API, generated on first
+	// run, .owner_api_secret in the data directory if not set
+	#[serde(default)]
+	pub api_secret_path: Option<String>,
+	// File holding the secret required by the foreign API (receiving
+	// transactions, building coinbase outputs), generated on first run
+	#[serde(default)]
+	pub foreign_api_secret_path: Option<String>,
 	// The directory in which wallet files are stored
 	pub data_file_dir: String,
+	// Seconds to wait for the recipient listener to answer each request of a
+	// send, a slow or stalled listener counting as unreachable
+	#[serde(default = "default_send_timeout_secs")]
+	pub send_timeout_secs: u64,
+	// Number of times a request of a send is retried, with an exponentially
+	// growing delay, while the recipient listener can't be reached
+	#[serde(default = "default_send_retries")]
+	pub send_retries: u32,
 }
 
 impl Default for WalletConfig {
@@ -190,16 +251,62 @@ impl Default for WalletConfig {
 			// enable_wallet: false,
 			api_listen_interface: "127.0.0.1".to_string(),
 			api_listen_port: "13415".to_string(),
+			owner_api_listen_port: None,
 			check_node_api_http_addr: "http://127.0.0.1:13413".to_string(),
+			node_api_secret_path: None,
+			api_tls_config: None,
+			owner_api_tls_config: None,
+			api_secret_path: None,
+			foreign_api_secret_path: None,
 			data_file_dir: ".".to_string(),
+			send_timeout_secs: default_send_timeout_secs(),
+			send_retries: default_send_retries(),
 		}
 	}
 }
 
+fn default_send_timeout_secs() -> u64 {
+	30
+}
+
+fn default_send_retries() -> u32 {
+	4
+}
+
 impl WalletConfig {
 	pub fn api_listen_addr(&self) -> String {
 		format!("{}:{}", self.api_listen_interface, self.api_listen_port)
 	}
+
+	/// Address the owner API listens on, if enabled
+	pub fn owner_api_listen_addr(&self) -> Option<String> {
+		self.owner_api_listen_port
+			.as_ref()
+			.map(|port| format!("127.0.0.1:{}", port))
+	}
+
+	/// File holding the secret of the owner API, which always requires one as
+	/// it can spend the wallet funds
+	pub fn owner_api_secret_path(&self) -> String {
+		match self.api_secret_path {
+			Some(ref path) => path.clone(),
+			None => format!("{}{}{}", self.data_file_dir, MAIN_SEPARATOR, ".owner_api_secret"),
+		}
+	}
+
+	/// Secret to authenticate against the node API with, if configured
+	pub fn node_api_secret(&self) -> Option<String> {
+		match self.node_api_secret_path {
+			Some(ref path) => match api::read_api_secret(path) {
+				Ok(secret) => Some(secret),
+				Err(e) => {
+					error!(LOGGER, "Could not read the node API secret: {}", e);
+					None
+				}
+			},
+			None => None,
+		}
+	}
 }
 
 /// Status of an output that's being tracked by the wallet. Can either be
@@ -310,6 +417,13 @@ impl OutputData {
 		self.status = OutputStatus::Locked;
 	}
 
+	/// Releases a locked output, spendable again
+	fn unlock(&mut self) {
+		if self.status == OutputStatus::Locked {
+			self.status = OutputStatus::Unspent;
+		}
+	}
+
 	/// How many confirmations has this output received?
 	/// If height == 0 then we are either Unconfirmed or the output was
 	/// cut-through
@@ -374,12 +488,42 @@ impl WalletSeed {
 		Ok(result)
 	}
 
-	pub fn init_new() -> WalletSeed {
-		let seed: [u8; 32] = thread_rng().gen();
-		WalletSeed(seed)
+	/// Seed derived from a recovery phrase and its optional passphrase, as
+	/// the first half of the BIP39 seed.
+	pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<WalletSeed, Error> {
+		let seed = mnemonic::to_seed(phrase, passphrase)?;
+		Ok(WalletSeed::from_bytes(&seed))
+	}
+
+	/// Generates a new random recovery phrase of 24 words.
+	pub fn new_mnemonic() -> String {
+		let entropy: [u8; 32] = thread_rng().gen();
+		mnemonic::from_entropy(&entropy).expect("32 bytes of entropy")
+	}
+
+	/// Creates the seed file of a new wallet, returning the recovery phrase
+	/// the seed is derived from. The phrase isn't stored, it's up to the user
+	/// to write it down.
+	pub fn init_file(wallet_config: &WalletConfig, passphrase: &str) -> Result<String, Error> {
+		let phrase = WalletSeed::new_mnemonic();
+		let seed = WalletSeed::from_mnemonic(&phrase, passphrase)?;
+		seed.create_file(wallet_config)?;
+		Ok(phrase)
+	}
+
+	/// Recreates the seed file of an existing wallet from its recovery phrase
+	/// and passphrase, validating the phrase checksum.
+	pub fn recover_file(
+		wallet_config: &WalletConfig,
+		phrase: &str,
+		passphrase: &str,
+	) -> Result<WalletSeed, Error> {
+		let seed = WalletSeed::from_mnemonic(phrase, passphrase)?;
+		seed.create_file(wallet_config)?;
+		Ok(seed)
 	}
 
-	pub fn init_file(wallet_config: &WalletConfig) -> Result<WalletSeed, Error> {
+	fn create_file(&self, wallet_config: &WalletConfig) -> Result<(), Error> {
 		// create directory if it doesn't exist
 		fs::create_dir_all(&wallet_config.data_file_dir)?;
 
@@ -395,10 +539,9 @@ impl WalletSeed {
 		if Path::new(seed_file_path).exists() {
 			panic!("wallet seed file already exists");
 		} else {
-			let seed = WalletSeed::init_new();
 			let mut file = File::create(seed_file_path)?;
-			file.write_all(&seed.to_hex().as_bytes())?;
-			Ok(seed)
+			file.write_all(&self.to_hex().as_bytes())?;
+			Ok(())
 		}
 	}
 
@@ -434,11 +577,383 @@ impl WalletSeed {
 	}
 }
 
+/// A transaction we sent that isn't confirmed yet. Keeps what's needed to
+/// rebuild it with the same inputs and change, for example to bump its fee
+/// when it's stuck.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingSend {
+	/// Local identifier of the send
+	pub id: u32,
+	/// Amount sent, fee included
+	pub amount: u64,
+	/// Fee the transaction currently pays
+	pub fee: u64,
+	/// Lock height of the transaction
+	pub lock_height: u64,
+	/// Where the transaction was sent to
+	pub dest: String,
+	/// Outputs spent by the transaction
+	pub inputs: Vec<keychain::Identifier>,
+	/// Our change output
+	pub change: keychain::Identifier,
+	/// Change outputs of the versions of the transaction replaced by a
+	/// repost, any of which may still get mined instead
+	#[serde(default)]
+	pub replaced_changes: Vec<keychain::Identifier>,
+	/// Set while a send waits for the recipient response, either sent
+	/// through a file or to a listener that couldn't be reached
+	#[serde(default)]
+	pub file_context: Option<SendContext>,
+	/// Excess commitments (hex) of the kernels of the transaction, of every
+	/// version of it that may have reached the network, to tell whether it
+	/// got mined
+	#[serde(default)]
+	pub kernels: Vec<String>,
+	/// Recipients of a batch send, each with its own kernel, empty for a
+	/// send to a single recipient
+	#[serde(default)]
+	pub batch: Vec<BatchPart>,
+}
+
+impl PendingSend {
+	/// Our change outputs, of the current version of the transaction and of
+	/// the ones it replaced.
+	pub fn change_outputs(&self) -> Vec<keychain::Identifier> {
+		let mut outputs = self.replaced_changes.clone();
+		outputs.push(self.change.clone());
+		outputs
+	}
+}
+
+/// The part of a batch send going to one of its recipients. Each part gets
+/// its own kernel, signed with the recipient over a share of our excess.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchPart {
+	/// Amount the recipient gets
+	pub amount: u64,
+	/// Where the part was sent to
+	pub dest: String,
+	/// Our share of the excess and nonce for the part, until the transaction
+	/// gets completed
+	pub context: Option<SendContext>,
+	/// Response of the recipient, including its output, once received
+	pub response: Option<PartialTx>,
+}
+
+/// Our secret excess and nonce for a transaction sent through a file, kept
+/// (hex encoded and encrypted with a key derived from our seed) until it
+/// gets finalized
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SendContext {
+	pub sec_key: String,
+	pub sec_nonce: String,
+	/// Random salt of the encryption key, contexts saved without one aren't
+	/// encrypted
+	#[serde(default)]
+	pub salt: String,
+}
+
+impl SendContext {
+	/// Encrypts our secret excess and nonce to keep them on disk.
+	pub fn new(
+		keychain: &keychain::Keychain,
+		sec_key: &SecretKey,
+		sec_nonce: &SecretKey,
+	) -> Result<SendContext, Error> {
+		let mut salt = [0u8; 16];
+		thread_rng().fill_bytes(&mut salt);
+		Ok(SendContext {
+			sec_key: util::to_hex(xor_pad(keychain, &salt, b"key", &sec_key[..])?),
+			sec_nonce: util::to_hex(xor_pad(keychain, &salt, b"nonce", &sec_nonce[..])?),
+			salt: util::to_hex(salt.to_vec()),
+		})
+	}
+
+	/// Decrypts our secret excess and nonce.
+	pub fn secret_keys(&self, keychain: &keychain::Keychain) -> Result<(SecretKey, SecretKey), Error> {
+		let mut sec_key = util::from_hex(self.sec_key.clone())?;
+		let mut sec_nonce = util::from_hex(self.sec_nonce.clone())?;
+		if !self.salt.is_empty() {
+			let salt = util::from_hex(self.salt.clone())?;
+			sec_key = xor_pad(keychain, &salt, b"key", &sec_key)?;
+			sec_nonce = xor_pad(keychain, &salt, b"nonce", &sec_nonce)?;
+		}
+		Ok((
+			SecretKey::from_slice(keychain.secp(), &sec_key)?,
+			SecretKey::from_slice(keychain.secp(), &sec_nonce)?,
+		))
+	}
+}
+
+/// What the recipient of an adaptor locked transaction keeps until the
+/// sender confirms: its secret excess and nonce, as for a send, and the
+/// adaptor secret, encrypted the same way
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SwapContext {
+	pub context: SendContext,
+	pub secret: String,
+	pub salt: String,
+}
+
+impl SwapContext {
+	/// Encrypts our secret excess, nonce and adaptor secret to keep them on
+	/// disk.
+	pub fn new(
+		keychain: &keychain::Keychain,
+		sec_key: &SecretKey,
+		sec_nonce: &SecretKey,
+		secret: &SecretKey,
+	) -> Result<SwapContext, Error> {
+		let mut salt = [0u8; 16];
+		thread_rng().fill_bytes(&mut salt);
+		Ok(SwapContext {
+			context: SendContext::new(keychain, sec_key, sec_nonce)?,
+			secret: util::to_hex(xor_pad(keychain, &salt, b"adaptor", &secret[..])?),
+			salt: util::to_hex(salt.to_vec()),
+		})
+	}
+
+	/// Decrypts the adaptor secret.
+	pub fn adaptor_secret(&self, keychain: &keychain::Keychain) -> Result<SecretKey, Error> {
+		let salt = util::from_hex(self.salt.clone())?;
+		let secret = xor_pad(keychain, &salt, b"adaptor", &util::from_hex(self.secret.clone())?)?;
+		Ok(SecretKey::from_slice(keychain.secp(), &secret)?)
+	}
+}
+
+// Encrypts or decrypts a 32 bytes secret with the storage key derived from
+// the salt and label.
+fn xor_pad(
+	keychain: &keychain::Keychain,
+	salt: &[u8],
+	label: &[u8],
+	data: &[u8],
+) -> Result<Vec<u8>, Error> {
+	if data.len() != 32 {
+		return Err(Error::Format(format!("expected a 32 bytes secret, got {}", data.len())));
+	}
+	let mut key_salt = salt.to_vec();
+	key_salt.extend_from_slice(label);
+	let pad = keychain.storage_key(&key_salt)?;
+	Ok(data.iter().zip(pad.iter()).map(|(d, p)| d ^ p).collect())
+}
+
+/// Output reserved by the wallet owner, i.e. for a transaction built outside
+/// of the wallet, and left out of coin selection until unlocked or expired
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputLock {
+	/// Output locked
+	pub key_id: keychain::Identifier,
+	/// Why the output is locked, for the owner's records
+	pub reason: String,
+	/// When the output got locked, in seconds since the epoch
+	pub locked_at: i64,
+	/// When the lock expires, in seconds since the epoch, never if not set
+	pub expires_at: Option<i64>,
+}
+
+impl OutputLock {
+	/// Whether the lock still holds at the provided time.
+	pub fn is_active(&self, now: i64) -> bool {
+		match self.expires_at {
+			Some(expires_at) => now < expires_at,
+			None => true,
+		}
+	}
+}
+
+/// How the outputs to spend are picked when building a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionStrategy {
+	/// Spends as many outputs as possible (up to the maximum number of
+	/// outputs), consolidating them into a single change output
+	All,
+	/// Spends the smallest outputs first, only as many as needed
+	SmallestFirst,
+	/// Spends the outputs leaving the smallest change, avoiding the creation
+	/// of many small change outputs
+	MinimizeChange,
+}
+
+impl FromStr for SelectionStrategy {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<SelectionStrategy, Error> {
+		match s {
+			"all" => Ok(SelectionStrategy::All),
+			"smallest" | "smallest-first" => Ok(SelectionStrategy::SmallestFirst),
+			"minimize-change" => Ok(SelectionStrategy::MinimizeChange),
+			_ => Err(Error::Format(format!("Unknown selection strategy: {}", s))),
+		}
+	}
+}
+
+/// Number of combinations tried when looking for the outputs leaving the
+/// least change, the best one found so far being used past that.
+const MAX_SELECTION_TRIES: usize = 10_000;
+
+// Outputs covering the amount while leaving the least change. Searches
+// through combinations depth first, largest outputs first, skipping the ones
+// that can't do better than the best found so far. The first combination
+// found is the largest outputs covering the amount, so that's what's
+// selected at worst.
+fn min_change_selection(amount: u64, outputs: &[OutputData]) -> Vec<OutputData> {
+	let mut values = outputs.iter().enumerate().collect::<Vec<_>>();
+	values.sort_by(|a, b| b.1.value.cmp(&a.1.value));
+	// remaining[i] is the total of the outputs from i onward
+	let mut remaining = vec![0; values.len() + 1];
+	for i in (0..values.len()).rev() {
+		remaining[i] = remaining[i + 1] + values[i].1.value;
+	}
+
+	struct Search {
+		best: Option<(u64, Vec<usize>)>,
+		tries: usize,
+	}
+	fn search(
+		s: &mut Search,
+		values: &[(usize, &OutputData)],
+		remaining: &[u64],
+		amount: u64,
+		i: usize,
+		total: u64,
+		selected: &mut Vec<usize>,
+	) {
+		if let Some((best_total, _)) = s.best {
+			if total >= best_total || best_total == amount {
+				return;
+			}
+		}
+		if total >= amount {
+			s.best = Some((total, selected.clone()));
+			return;
+		}
+		if i == values.len() || total + remaining[i] < amount || s.tries >= MAX_SELECTION_TRIES {
+			return;
+		}
+		s.tries += 1;
+		selected.push(values[i].0);
+		search(s, values, remaining, amount, i + 1, total + values[i].1.value, selected);
+		selected.pop();
+		search(s, values, remaining, amount, i + 1, total, selected);
+	}
+
+	let mut s = Search {
+		best: None,
+		tries: 0,
+	};
+	search(&mut s, &values, &remaining, amount, 0, 0, &mut vec![]);
+	let mut selected = match s.best {
+		Some((_, indexes)) => indexes,
+		None => return vec![],
+	};
+	// keep the outputs sorted by increasing value
+	selected.sort();
+	selected.into_iter().map(|i| outputs[i].clone()).collect()
+}
+
+/// Whether a logged transaction sent or received coins
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TxLogEntryType {
+	Sent,
+	Received,
+}
+
+/// Status of a logged transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TxStatus {
+	/// Not seen on chain yet
+	Pending,
+	/// One of our outputs (or its inputs being spent) shows it's on chain
+	Confirmed,
+	/// Cancelled before confirming, its inputs were unlocked
+	Cancelled,
+}
+
+/// A transaction we sent or received, kept in the wallet transaction log so
+/// the wallet history can be shown transaction by transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxLogEntry {
+	/// Local identifier of the transaction
+	pub id: u32,
+	/// Root key of the account the transaction belongs to
+	pub root_key_id: keychain::Identifier,
+	pub tx_type: TxLogEntryType,
+	/// Sum of our outputs created by the transaction (change included)
+	pub amount_credited: u64,
+	/// Sum of our outputs spent by the transaction
+	pub amount_debited: u64,
+	pub fee: u64,
+	/// Public excess of the transaction kernel (hex), once known
+	pub excess: Option<String>,
+	/// Creation time, in seconds since the epoch
+	pub creation_ts: i64,
+	/// Height of the block the transaction got confirmed in, when known
+	pub confirmation_height: Option<u64>,
+	pub status: TxStatus,
+	/// Our outputs spent by the transaction
+	pub inputs: Vec<keychain::Identifier>,
+	/// Our outputs created by the transaction
+	pub outputs: Vec<keychain::Identifier>,
+	/// Proof of the payment signed by the recipient, for sent transactions
+	#[serde(default)]
+	pub payment_proof: Option<PaymentProof>,
+	/// Proofs of the payments of a batch send, one per recipient in the
+	/// order they were paid (if the recipient provided any)
+	#[serde(default)]
+	pub batch_payment_proofs: Vec<Option<PaymentProof>>,
+	/// Adaptor lock of a sent transaction, to extract the adaptor secret
+	/// once the recipient completed it
+	#[serde(default)]
+	pub swap: Option<SwapLock>,
+}
+
+impl TxLogEntry {
+	/// New pending entry, created now
+	pub fn new(root_key_id: keychain::Identifier, tx_type: TxLogEntryType, fee: u64) -> TxLogEntry {
+		TxLogEntry {
+			id: 0,
+			root_key_id: root_key_id,
+			tx_type: tx_type,
+			amount_credited: 0,
+			amount_debited: 0,
+			fee: fee,
+			excess: None,
+			creation_ts: time::now_utc().to_timespec().sec,
+			confirmation_height: None,
+			status: TxStatus::Pending,
+			inputs: vec![],
+			outputs: vec![],
+			payment_proof: None,
+			batch_payment_proofs: vec![],
+			swap: None,
+		}
+	}
+}
+
+/// A named account of the wallet, deriving its keys on its own branch so its
+/// outputs and balance are kept apart from the other accounts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Account {
+	/// Name of the account
+	pub name: String,
+	/// Index of the account key derivation branch
+	pub index: u32,
+}
+
 /// Wallet information tracking all our outputs. Based on HD derivation and
 /// avoids storing any key data, only storing output amounts and child index.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WalletData {
 	pub outputs: HashMap<String, OutputData>,
+	#[serde(default)]
+	pub pending_sends: Vec<PendingSend>,
+	#[serde(default)]
+	pub accounts: Vec<Account>,
+	#[serde(default)]
+	pub tx_log: Vec<TxLogEntry>,
+	#[serde(default)]
+	pub output_locks: Vec<OutputLock>,
 }
 
 impl WalletData {
@@ -450,7 +965,18 @@ impl WalletData {
 	{
 		// open the wallet readonly and do what needs to be done with it
 		let data_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, DAT_FILE);
-		let wdat = WalletData::read_or_create(data_file_path)?;
+		let pending_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, PENDING_FILE);
+		let accounts_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, ACCOUNTS_FILE);
+		let tx_log_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, TX_LOG_FILE);
+		let locks_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, LOCKS_FILE);
+		let wdat =
+			WalletData::read_or_create(
+				data_file_path,
+				pending_file_path,
+				accounts_file_path,
+				tx_log_file_path,
+				locks_file_path,
+			)?;
 		let res = f(&wdat);
 		Ok(res)
 	}
@@ -471,6 +997,10 @@ impl WalletData {
 		});
 
 		let data_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, DAT_FILE);
+		let pending_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, PENDING_FILE);
+		let accounts_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, ACCOUNTS_FILE);
+		let tx_log_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, TX_LOG_FILE);
+		let locks_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, LOCKS_FILE);
 		let lock_file_path = &format!("{}{}{}", data_file_dir, MAIN_SEPARATOR, LOCK_FILE);
 
 		info!(LOGGER, "Acquiring wallet lock ...");
@@ -501,9 +1031,22 @@ impl WalletData {
 		}
 
 		// We successfully acquired the lock - so do what needs to be done.
-		let mut wdat = WalletData::read_or_create(data_file_path)?;
+		let mut wdat =
+			WalletData::read_or_create(
+				data_file_path,
+				pending_file_path,
+				accounts_file_path,
+				tx_log_file_path,
+				locks_file_path,
+			)?;
 		let res = f(&mut wdat);
-		wdat.write(data_file_path)?;
+		wdat.write(
+			data_file_path,
+			pending_file_path,
+			accounts_file_path,
+			tx_log_file_path,
+			locks_file_path,
+		)?;
 
 		// delete the lock file
 		fs::remove_file(lock_file_path).map_err(|_| {
@@ -518,15 +1061,58 @@ impl WalletData {
 	}
 
 	/// Read the wallet data or created a brand new one if it doesn't exist yet
-	fn read_or_create(data_file_path: &str) -> Result<WalletData, Error> {
-		if Path::new(data_file_path).exists() {
-			WalletData::read(data_file_path)
+	fn read_or_create(
+		data_file_path: &str,
+		pending_file_path: &str,
+		accounts_file_path: &str,
+		tx_log_file_path: &str,
+		locks_file_path: &str,
+	) -> Result<WalletData, Error> {
+		let mut wallet_data = if Path::new(data_file_path).exists() {
+			WalletData::read(data_file_path)?
 		} else {
 			// just create a new instance, it will get written afterward
-			Ok(WalletData {
+			WalletData {
 				outputs: HashMap::new(),
-			})
+				pending_sends: vec![],
+				accounts: vec![],
+				tx_log: vec![],
+				output_locks: vec![],
+			}
+		};
+		if Path::new(accounts_file_path).exists() {
+			let accounts_file = File::open(accounts_file_path).map_err(|e| {
+				Error::WalletData(format!("Could not open {}: {}", accounts_file_path, e))
+			})?;
+			wallet_data.accounts = serde_json::from_reader(accounts_file).map_err(|e| {
+				Error::WalletData(format!("Error reading {}: {}", accounts_file_path, e))
+			})?;
+		}
+		if Path::new(pending_file_path).exists() {
+			let pending_file = File::open(pending_file_path).map_err(|e| {
+				Error::WalletData(format!("Could not open {}: {}", pending_file_path, e))
+			})?;
+			wallet_data.pending_sends = serde_json::from_reader(pending_file).map_err(|e| {
+				Error::WalletData(format!("Error reading {}: {}", pending_file_path, e))
+			})?;
 		}
+		if Path::new(tx_log_file_path).exists() {
+			let tx_log_file = File::open(tx_log_file_path).map_err(|e| {
+				Error::WalletData(format!("Could not open {}: {}", tx_log_file_path, e))
+			})?;
+			wallet_data.tx_log = serde_json::from_reader(tx_log_file).map_err(|e| {
+				Error::WalletData(format!("Error reading {}: {}", tx_log_file_path, e))
+			})?;
+		}
+		if Path::new(locks_file_path).exists() {
+			let locks_file = File::open(locks_file_path).map_err(|e| {
+				Error::WalletData(format!("Could not open {}: {}", locks_file_path, e))
+			})?;
+			wallet_data.output_locks = serde_json::from_reader(locks_file).map_err(|e| {
+				Error::WalletData(format!("Error reading {}: {}", locks_file_path, e))
+			})?;
+		}
+		Ok(wallet_data)
 	}
 
 	/// Read output_data vec from disk.
@@ -544,6 +1130,10 @@ impl WalletData {
 		let outputs = WalletData::read_outputs(data_file_path)?;
 		let mut wallet_data = WalletData {
 			outputs: HashMap::new(),
+			pending_sends: vec![],
+			accounts: vec![],
+			tx_log: vec![],
+			output_locks: vec![],
 		};
 		for out in outputs {
 			wallet_data.add_output(out);
@@ -552,7 +1142,42 @@ impl WalletData {
 	}
 
 	/// Write the wallet data to disk.
-	fn write(&self, data_file_path: &str) -> Result<(), Error> {
+	fn write(
+		&self,
+		data_file_path: &str,
+		pending_file_path: &str,
+		accounts_file_path: &str,
+		tx_log_file_path: &str,
+		locks_file_path: &str,
+	) -> Result<(), Error> {
+		let accounts_file = File::create(accounts_file_path).map_err(|e| {
+			Error::WalletData(format!("Could not create {}: {}", accounts_file_path, e))
+		})?;
+		serde_json::to_writer_pretty(accounts_file, &self.accounts).map_err(|e| {
+			Error::WalletData(format!("Error writing {}: {}", accounts_file_path, e))
+		})?;
+
+		let pending_file = File::create(pending_file_path).map_err(|e| {
+			Error::WalletData(format!("Could not create {}: {}", pending_file_path, e))
+		})?;
+		serde_json::to_writer_pretty(pending_file, &self.pending_sends).map_err(|e| {
+			Error::WalletData(format!("Error writing {}: {}", pending_file_path, e))
+		})?;
+
+		let tx_log_file = File::create(tx_log_file_path).map_err(|e| {
+			Error::WalletData(format!("Could not create {}: {}", tx_log_file_path, e))
+		})?;
+		serde_json::to_writer_pretty(tx_log_file, &self.tx_log).map_err(|e| {
+			Error::WalletData(format!("Error writing {}: {}", tx_log_file_path, e))
+		})?;
+
+		let locks_file = File::create(locks_file_path).map_err(|e| {
+			Error::WalletData(format!("Could not create {}: {}", locks_file_path, e))
+		})?;
+		serde_json::to_writer_pretty(locks_file, &self.output_locks).map_err(|e| {
+			Error::WalletData(format!("Error writing {}: {}", locks_file_path, e))
+		})?;
+
 		let mut data_file = File::create(data_file_path).map_err(|e| {
 			Error::WalletData(format!("Could not create {}: {}", data_file_path, e))
 		})?;
@@ -588,14 +1213,43 @@ impl WalletData {
 		}
 	}
 
+	/// Unlock an output data, making it spendable again.
+	pub fn unlock_output(&mut self, key_id: &keychain::Identifier) {
+		if let Some(out) = self.outputs.get_mut(&key_id.to_hex()) {
+			out.unlock()
+		}
+	}
+
 	pub fn get_output(&self, key_id: &keychain::Identifier) -> Option<&OutputData> {
 		self.outputs.get(&key_id.to_hex())
 	}
 
-	/// Select spendable coins from the wallet.
-	/// Default strategy is to spend the maximum number of outputs (up to max_outputs).
-	/// Alternative strategy is to spend smallest outputs first but only as many as necessary.
-	/// When we introduce additional strategies we should pass something other than a bool in.
+	/// Derivation branch index of the account with the provided name, the
+	/// default account always existing with index 0.
+	pub fn account_index(&self, name: &str) -> Option<u32> {
+		if name == DEFAULT_ACCOUNT {
+			return Some(0);
+		}
+		self.accounts.iter().find(|a| a.name == name).map(|a| a.index)
+	}
+
+	/// Creates a new account on the next unused derivation branch.
+	pub fn add_account(&mut self, name: &str) -> Result<Account, Error> {
+		if self.account_index(name).is_some() {
+			return Err(Error::GenericError(format!("Account {} already exists", name)));
+		}
+		let index = self.accounts.iter().map(|a| a.index).max().unwrap_or(0) + 1;
+		let account = Account {
+			name: name.to_string(),
+			index: index,
+		};
+		self.accounts.push(account.clone());
+		Ok(account)
+	}
+
+	/// Select spendable coins from the wallet, with the provided strategy.
+	/// Only outputs with at least the minimum number of confirmations are
+	/// eligible.
 	pub fn select_coins(
 		&self,
 		root_key_id: keychain::Identifier,
@@ -603,14 +1257,16 @@ impl WalletData {
 		current_height: u64,
 		minimum_confirmations: u64,
 		max_outputs: usize,
-		select_all: bool,
+		strategy: SelectionStrategy,
 	) -> Vec<OutputData> {
 		// first find all eligible outputs based on number of confirmations
+		let now = time::now_utc().to_timespec().sec;
 		let mut eligible = self.outputs
 			.values()
 			.filter(|out| {
 				out.root_key_id == root_key_id
 					&& out.eligible_to_spend(current_height, minimum_confirmations)
+					&& !self.is_manually_locked(&out.key_id, now)
 			})
 			.cloned()
 			.collect::<Vec<OutputData>>();
@@ -629,18 +1285,19 @@ impl WalletData {
 		if eligible.len() > max_outputs {
 			for window in eligible.windows(max_outputs) {
 				let windowed_eligibles = window.iter().cloned().collect::<Vec<_>>();
-				if let Some(outputs) = self.select_from(amount, select_all, windowed_eligibles) {
+				if let Some(outputs) = self.select_from(amount, strategy, windowed_eligibles) {
 					return outputs;
 				}
 			}
 			// Not exist in any window of which total amount >= amount.
 			// Then take coins from the smallest one up to the total amount of selected coins = the amount.
-			if let Some(outputs) = self.select_from(amount, false, eligible.clone()) {
+			let smallest = SelectionStrategy::SmallestFirst;
+			if let Some(outputs) = self.select_from(amount, smallest, eligible.clone()) {
 				debug!(LOGGER, "Extending maximum number of outputs. {} outputs selected.", outputs.len());
 				return outputs;
 			}
 		} else {
-			if let Some(outputs) = self.select_from(amount, select_all, eligible.clone()) {
+			if let Some(outputs) = self.select_from(amount, strategy, eligible.clone()) {
 				return outputs;
 			}
 		}
@@ -651,18 +1308,21 @@ impl WalletData {
 		eligible.iter().take(max_outputs).cloned().collect()
 	}
 
-	// Select the full list of outputs if we are using the select_all strategy.
+	// Select the full list of outputs if we are using the all strategy.
 	// Otherwise select just enough outputs to cover the desired amount.
+	// Outputs are expected sorted by increasing value.
 	fn select_from(
 		&self,
 		amount: u64,
-		select_all: bool,
+		strategy: SelectionStrategy,
 		outputs: Vec<OutputData>,
 	) -> Option<Vec<OutputData>> {
 		let total = outputs.iter().fold(0, |acc, x| acc + x.value);
 		if total >= amount {
-			if select_all {
+			if strategy == SelectionStrategy::All {
 				return Some(outputs.iter().cloned().collect());
+			} else if strategy == SelectionStrategy::MinimizeChange {
+				return Some(min_change_selection(amount, &outputs));
 			} else {
 				let mut selected_amount = 0;
 				return Some(
@@ -681,6 +1341,214 @@ impl WalletData {
 		}
 	}
 
+	/// Records a new pending send, assigning it the next identifier.
+	pub fn add_pending_send(&mut self, mut send: PendingSend) -> u32 {
+		send.id = self.pending_sends.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+		let id = send.id;
+		self.pending_sends.push(send);
+		id
+	}
+
+	/// Cancels a pending send once its kernels are known not to be on chain,
+	/// unlocking its inputs and deleting its change outputs. Refused if any
+	/// of its inputs got spent or any of its change outputs showed up on
+	/// chain.
+	pub fn cancel_pending_send(&mut self, id: u32) -> Result<PendingSend, Error> {
+		let send = match self.get_pending_send(Some(id)).cloned() {
+			Some(send) => send,
+			None => return Err(Error::GenericError(format!("no pending transaction {}", id))),
+		};
+		let spent = send.inputs.iter().any(|key_id| match self.get_output(key_id) {
+			Some(out) => out.status == OutputStatus::Spent,
+			None => false,
+		});
+		let change_confirmed = send.change_outputs().iter().any(|key_id| match self.get_output(key_id) {
+			Some(out) => out.status == OutputStatus::Unspent || out.status == OutputStatus::Spent,
+			None => false,
+		});
+		if spent || change_confirmed {
+			return Err(Error::GenericError(format!(
+				"transaction {} already confirmed, can't be cancelled",
+				id
+			)));
+		}
+
+		for key_id in &send.inputs {
+			self.unlock_output(key_id);
+		}
+		for key_id in send.change_outputs() {
+			self.delete_output(&key_id);
+		}
+		self.pending_sends.retain(|s| s.id != id);
+		if let Some(entry) = self.sent_tx_log_entry_mut(&send.inputs) {
+			entry.status = TxStatus::Cancelled;
+		}
+		Ok(send)
+	}
+
+	/// The pending send with the provided identifier, the most recent one if
+	/// none is provided.
+	pub fn get_pending_send(&self, id: Option<u32>) -> Option<&PendingSend> {
+		match id {
+			Some(id) => self.pending_sends.iter().find(|s| s.id == id),
+			None => self.pending_sends.iter().max_by_key(|s| s.id),
+		}
+	}
+
+	/// Forgets about pending sends which inputs have all been spent, meaning
+	/// the transaction got confirmed, along with the change outputs of the
+	/// versions of it that didn't get mined.
+	pub fn clear_confirmed_sends(&mut self) {
+		let confirmed = {
+			let outputs = &self.outputs;
+			self.pending_sends
+				.iter()
+				.filter(|send| {
+					send.inputs.iter().all(|id| match outputs.get(&id.to_hex()) {
+						Some(out) => out.status == OutputStatus::Spent,
+						None => true,
+					})
+				})
+				.cloned()
+				.collect::<Vec<_>>()
+		};
+		for send in &confirmed {
+			for key_id in send.change_outputs() {
+				let unconfirmed = match self.get_output(&key_id) {
+					Some(out) => out.status == OutputStatus::Unconfirmed,
+					None => false,
+				};
+				if unconfirmed {
+					self.delete_output(&key_id);
+				}
+			}
+		}
+		self.pending_sends
+			.retain(|send| !confirmed.iter().any(|s| s.id == send.id));
+	}
+
+	/// Records a new transaction in the log, assigning it the next
+	/// identifier.
+	pub fn add_tx_log_entry(&mut self, mut entry: TxLogEntry) -> u32 {
+		entry.id = self.tx_log.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+		let id = entry.id;
+		self.tx_log.push(entry);
+		id
+	}
+
+	/// Whether the owner locked the provided output, the lock still holding
+	/// at the provided time.
+	pub fn is_manually_locked(&self, key_id: &keychain::Identifier, now: i64) -> bool {
+		self.output_locks
+			.iter()
+			.any(|lock| lock.key_id == *key_id && lock.is_active(now))
+	}
+
+	/// Locks the provided outputs on behalf of the owner, replacing any
+	/// previous lock on them. Either all of them get locked or, if any isn't
+	/// in the wallet or isn't available anymore, none.
+	pub fn lock_outputs_manually(
+		&mut self,
+		key_ids: &[keychain::Identifier],
+		reason: &str,
+		now: i64,
+		expires_at: Option<i64>,
+	) -> Result<(), Error> {
+		for key_id in key_ids {
+			let status = match self.outputs.get(&key_id.to_hex()) {
+				Some(out) => out.status.clone(),
+				None => {
+					return Err(Error::GenericError(format!(
+						"no output {} in the wallet",
+						key_id.to_hex()
+					)))
+				}
+			};
+			if status != OutputStatus::Unspent && status != OutputStatus::Unconfirmed {
+				return Err(Error::GenericError(format!(
+					"output {} can't be locked, it's {}",
+					key_id.to_hex(),
+					status
+				)));
+			}
+		}
+		self.prune_output_locks(now);
+		self.output_locks.retain(|lock| !key_ids.contains(&lock.key_id));
+		for key_id in key_ids {
+			self.output_locks.push(OutputLock {
+				key_id: key_id.clone(),
+				reason: reason.to_string(),
+				locked_at: now,
+				expires_at: expires_at,
+			});
+		}
+		Ok(())
+	}
+
+	/// Releases the owner locks on the provided outputs, returning how many
+	/// were locked.
+	pub fn unlock_outputs_manually(&mut self, key_ids: &[keychain::Identifier], now: i64) -> usize {
+		self.prune_output_locks(now);
+		let before = self.output_locks.len();
+		self.output_locks.retain(|lock| !key_ids.contains(&lock.key_id));
+		before - self.output_locks.len()
+	}
+
+	// Forgets the expired locks and the ones on outputs spent since.
+	fn prune_output_locks(&mut self, now: i64) {
+		let outputs = &self.outputs;
+		self.output_locks.retain(|lock| {
+			lock.is_active(now) && match outputs.get(&lock.key_id.to_hex()) {
+				Some(out) => out.status != OutputStatus::Spent,
+				None => false,
+			}
+		});
+	}
+
+	/// The pending sent transaction spending the provided inputs.
+	pub fn sent_tx_log_entry_mut(
+		&mut self,
+		inputs: &Vec<keychain::Identifier>,
+	) -> Option<&mut TxLogEntry> {
+		self.tx_log.iter_mut().find(|t| {
+			t.tx_type == TxLogEntryType::Sent && t.status == TxStatus::Pending &&
+				&t.inputs == inputs
+		})
+	}
+
+	/// Confirms the logged transactions that made it on chain, from the
+	/// status of our outputs, and fills their confirmation height once
+	/// known.
+	pub fn update_tx_log(&mut self) {
+		let outputs = &self.outputs;
+		for entry in self.tx_log.iter_mut() {
+			if entry.status == TxStatus::Cancelled || entry.confirmation_height.is_some() {
+				continue;
+			}
+			let confirmed_outputs = entry
+				.outputs
+				.iter()
+				.filter_map(|id| outputs.get(&id.to_hex()))
+				.filter(|out| {
+					out.status == OutputStatus::Unspent || out.status == OutputStatus::Spent
+				})
+				.collect::<Vec<_>>();
+			let inputs_spent = !entry.inputs.is_empty() &&
+				entry.inputs.iter().all(|id| match outputs.get(&id.to_hex()) {
+					Some(out) => out.status == OutputStatus::Spent,
+					None => false,
+				});
+			if confirmed_outputs.is_empty() && !inputs_spent {
+				continue;
+			}
+			entry.status = TxStatus::Confirmed;
+			entry.confirmation_height = confirmed_outputs
+				.iter()
+				.map(|out| out.height)
+				.find(|h| *h > 0);
+		}
+	}
+
 	/// Next child index when we want to create a new output.
 	pub fn next_child(&self, root_key_id: keychain::Identifier) -> u32 {
 		let mut max_n = 0;
@@ -694,7 +1562,7 @@ impl WalletData {
 }
 
 /// Define the stages of a transaction
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum PartialTxPhase {
 	SenderInitiation,
 	ReceiverInitiation,
@@ -704,7 +1572,7 @@ pub enum PartialTxPhase {
 
 /// Helper in serializing the information required during an interactive aggsig
 /// transaction
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PartialTx {
 	pub phase:  PartialTxPhase,
 	pub amount: u64,
@@ -712,6 +1580,96 @@ pub struct PartialTx {
 	pub public_nonce: String,
 	pub part_sig: String,
 	pub tx: String,
+	/// Set by the recipient in its response to the sender initiation
+	#[serde(default)]
+	pub payment_proof: Option<PaymentProof>,
+	/// Part of a batch send, the sender paying the whole fee and completing
+	/// the transaction with the outputs of all recipients
+	#[serde(default)]
+	pub batch: bool,
+	/// Adaptor point (hex) the partial signatures are locked on, set by the
+	/// recipient of an adaptor locked transaction
+	#[serde(default)]
+	pub adaptor_point: Option<String>,
+}
+
+/// Adaptor point a sent transaction is locked on, along with both partial
+/// signatures, DER and hex encoded. The adaptor secret is the difference
+/// between the kernel signature and their sum.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SwapLock {
+	pub adaptor_point: String,
+	pub their_sig: String,
+	pub our_sig: String,
+}
+
+/// Proof, signed by the recipient, that it received a payment of the amount
+/// in the transaction with the provided kernel excess, from the sender with
+/// the provided public excess. As only the sender knows the secret of its
+/// excess, it's the only one able to claim the payment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaymentProof {
+	/// Payment proof address (public key) of the recipient, hex encoded
+	pub recipient_address: String,
+	/// Amount the sender sent the recipient, as in the partial transaction.
+	/// The recipient pays the transaction fee out of it (except for a batch
+	/// part), so its output holds this amount minus the fee.
+	pub amount: u64,
+	/// Public excess of the sender, hex encoded
+	pub sender_excess: String,
+	/// Public excess of the transaction kernel, hex encoded
+	pub kernel_excess: String,
+	/// Signature of the recipient over all the above, DER and hex encoded
+	pub signature: String,
+}
+
+impl PaymentProof {
+	/// Signs the proof of a payment we're receiving with our payment proof
+	/// key.
+	pub fn sign(
+		keychain: &keychain::Keychain,
+		amount: u64,
+		sender_excess: &PublicKey,
+		kernel_excess: &PublicKey,
+	) -> Result<PaymentProof, Error> {
+		let address = keychain.payment_proof_pubkey()?;
+		let mut proof = PaymentProof {
+			recipient_address: public_excess_hex(keychain, &address),
+			amount: amount,
+			sender_excess: public_excess_hex(keychain, sender_excess),
+			kernel_excess: public_excess_hex(keychain, kernel_excess),
+			signature: String::new(),
+		};
+		let sig = keychain.payment_proof_sign(&proof.message()?)?;
+		proof.signature = util::to_hex(sig.serialize_der(keychain.secp()));
+		Ok(proof)
+	}
+
+	// Message signed by the recipient, committing to the amount and both
+	// excesses.
+	fn message(&self) -> Result<secp::Message, Error> {
+		let mut amount_bytes = [0; 8];
+		BigEndian::write_u64(&mut amount_bytes, self.amount);
+		let mut data = b"grin_payment_proof".to_vec();
+		data.extend_from_slice(&amount_bytes);
+		data.extend(util::from_hex(self.sender_excess.clone())?);
+		data.extend(util::from_hex(self.kernel_excess.clone())?);
+		let hash = blake2::blake2b::blake2b(32, &[], &data);
+		Ok(secp::Message::from_slice(hash.as_bytes())?)
+	}
+
+	/// Checks the recipient signature, without needing any wallet.
+	pub fn verify(&self) -> Result<(), Error> {
+		let secp = util::static_secp_instance();
+		let secp = secp.lock().unwrap();
+		let address_bin = util::from_hex(self.recipient_address.clone())?;
+		let address = PublicKey::from_slice(&secp, &address_bin[..])?;
+		let sig_bin = util::from_hex(self.signature.clone())?;
+		let sig = Signature::from_der(&secp, &sig_bin[..])?;
+		secp.verify(&self.message()?, &sig, &address).map_err(|_| {
+			Error::Signature(String::from("Payment proof signature invalid."))
+		})
+	}
 }
 
 /// Builds a PartialTx
@@ -743,9 +1701,18 @@ pub fn build_partial_tx(
 			Some(p) => util::to_hex(p.serialize_der(&keychain.secp())),
 		},
 		tx: util::to_hex(ser::ser_vec(&tx).unwrap()),
+		payment_proof: None,
+		batch: false,
+		adaptor_point: None,
 	}
 }
 
+/// Hex of a public excess (i.e. the public excess of the final kernel), as
+/// stored in the transaction log.
+pub fn public_excess_hex(keychain: &keychain::Keychain, excess: &PublicKey) -> String {
+	util::to_hex(excess.serialize_vec(keychain.secp(), true).to_vec())
+}
+
 /// Reads a partial transaction into the amount, sum of blinding
 /// factors and the transaction itself.
 pub fn read_partial_tx(
@@ -762,9 +1729,16 @@ pub fn read_partial_tx(
 		_ => Some(Signature::from_der(keychain.secp(), &sig_bin[..])?),
 	};
 	let tx_bin = util::from_hex(partial_tx.tx.clone())?;
-	let tx = ser::deserialize(&mut &tx_bin[..]).map_err(|_| {
+	let tx: Transaction = ser::deserialize(&mut &tx_bin[..]).map_err(|_| {
 		Error::Format("Could not deserialize transaction, invalid format.".to_string())
 	})?;
+	// built on by both parties, which only works with a single kernel
+	if tx.kernels.len() != 1 {
+		return Err(Error::Format(format!(
+			"Partial transaction must have a single kernel, got {}.",
+			tx.kernels.len()
+		)));
+	}
 	Ok((partial_tx.amount, blinding, nonce, sig, tx))
 }
 
@@ -797,3 +1771,334 @@ pub struct CbData {
 	pub kernel: String,
 	pub key_id: String,
 }
+
+/// Balance of the wallet, by output status
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletInfo {
+	/// Height the amounts are computed at
+	pub current_height: u64,
+	/// Whether the outputs could be checked against the node, the amounts
+	/// may be outdated otherwise
+	pub data_confirmed: bool,
+	/// Spendable, locked and awaiting confirmation
+	pub total: u64,
+	/// Received but not confirmed yet
+	pub amount_awaiting_confirmation: u64,
+	/// Confirmed but not mature yet (coinbase) or lock height not reached
+	pub amount_confirmed_but_locked: u64,
+	/// Ready to be spent
+	pub amount_currently_spendable: u64,
+	/// Spent by a transaction that hasn't confirmed yet, or locked by the
+	/// owner
+	pub amount_locked: u64,
+}
+
+/// Parameters of a send through the owner API, same as the wallet send
+/// command.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendTxArgs {
+	pub amount: u64,
+	pub minimum_confirmations: u64,
+	pub dest: String,
+	pub max_outputs: usize,
+	/// One of "all", "smallest-first" or "minimize-change"
+	pub selection_strategy: String,
+	/// Fee per unit of transaction weight, estimated by the node if not
+	/// provided
+	#[serde(default)]
+	pub fee_base: Option<u64>,
+}
+
+/// Parameters of an output lock through the owner API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockOutputsArgs {
+	/// Key identifiers (hex) of the outputs to lock
+	pub key_ids: Vec<String>,
+	/// Why the outputs are locked, for the owner's records
+	#[serde(default)]
+	pub reason: String,
+	/// Seconds after which the lock expires, never if not provided
+	#[serde(default)]
+	pub expires_in_secs: Option<u64>,
+}
+
+/// Parameters of an output unlock through the owner API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnlockOutputsArgs {
+	/// Key identifiers (hex) of the outputs to unlock
+	pub key_ids: Vec<String>,
+}
+
+/// An output locked by the owner, as listed by the owner API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedOutput {
+	pub key_id: keychain::Identifier,
+	pub value: u64,
+	pub status: OutputStatus,
+	pub reason: String,
+	pub locked_at: i64,
+	pub expires_at: Option<i64>,
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn wallet_with_values(keychain: &keychain::Keychain, values: &[u64]) -> WalletData {
+		let mut wallet_data = WalletData {
+			outputs: HashMap::new(),
+			pending_sends: vec![],
+			accounts: vec![],
+			tx_log: vec![],
+			output_locks: vec![],
+		};
+		for (i, value) in values.iter().enumerate() {
+			let n_child = i as u32 + 1;
+			wallet_data.add_output(OutputData {
+				root_key_id: keychain.root_key_id(),
+				key_id: keychain.derive_key_id(n_child).unwrap(),
+				n_child: n_child,
+				value: *value,
+				status: OutputStatus::Unspent,
+				height: 1,
+				lock_height: 0,
+				is_coinbase: false,
+				block: BlockIdentifier::zero(),
+			});
+		}
+		wallet_data
+	}
+
+	fn selected_values(
+		wallet_data: &WalletData,
+		keychain: &keychain::Keychain,
+		amount: u64,
+		strategy: &str,
+	) -> Vec<u64> {
+		wallet_data
+			.select_coins(keychain.root_key_id(), amount, 10, 1, 500, strategy.parse().unwrap())
+			.iter()
+			.map(|out| out.value)
+			.collect()
+	}
+
+	#[test]
+	fn coin_selection_strategies() {
+		let keychain = keychain::Keychain::from_random_seed().unwrap();
+		let wallet_data = wallet_with_values(&keychain, &[1, 5, 6, 9, 20]);
+
+		assert_eq!(selected_values(&wallet_data, &keychain, 10, "all"), vec![1, 5, 6, 9, 20]);
+		assert_eq!(selected_values(&wallet_data, &keychain, 10, "smallest-first"), vec![1, 5, 6]);
+		// 9 + 1 covers the amount exactly
+		assert_eq!(selected_values(&wallet_data, &keychain, 10, "minimize-change"), vec![1, 9]);
+		assert_eq!(selected_values(&wallet_data, &keychain, 15, "minimize-change"), vec![6, 9]);
+		// ties go to the largest outputs
+		assert_eq!(selected_values(&wallet_data, &keychain, 19, "minimize-change"), vec![20]);
+
+		assert!("largest".parse::<SelectionStrategy>().is_err());
+	}
+
+	#[test]
+	fn immature_and_unconfirmed_excluded() {
+		let keychain = keychain::Keychain::from_random_seed().unwrap();
+		let mut wallet_data = wallet_with_values(&keychain, &[1, 5, 6]);
+		// coinbase mined at height 8, mature at 11, and an output mined at 10
+		for out in wallet_data.outputs.values_mut() {
+			if out.value == 5 {
+				out.is_coinbase = true;
+				out.height = 8;
+				out.lock_height = 11;
+			} else if out.value == 6 {
+				out.height = 10;
+			}
+		}
+
+		assert_eq!(selected_values(&wallet_data, &keychain, 20, "all"), vec![6, 1]);
+		assert_eq!(
+			wallet_data
+				.select_coins(keychain.root_key_id(), 20, 10, 2, 500, SelectionStrategy::All)
+				.iter()
+				.map(|out| out.value)
+				.collect::<Vec<_>>(),
+			vec![1]
+		);
+
+		let balance = ::info::balance(&wallet_data, &keychain.root_key_id(), 10, 2);
+		assert_eq!(balance.total, 12);
+		assert_eq!(balance.amount_currently_spendable, 1);
+		assert_eq!(balance.amount_awaiting_confirmation, 6);
+		assert_eq!(balance.amount_confirmed_but_locked, 5);
+	}
+
+	#[test]
+	fn manually_locked_excluded() {
+		let keychain = keychain::Keychain::from_random_seed().unwrap();
+		let mut wallet_data = wallet_with_values(&keychain, &[1, 5, 6]);
+		let key_id = |wallet_data: &WalletData, value: u64| {
+			wallet_data
+				.outputs
+				.values()
+				.find(|out| out.value == value)
+				.unwrap()
+				.key_id
+				.clone()
+		};
+		let (five, six) = (key_id(&wallet_data, 5), key_id(&wallet_data, 6));
+		let now = time::now_utc().to_timespec().sec;
+		wallet_data
+			.lock_outputs_manually(&[five.clone()], "withdrawal 1", now, None)
+			.unwrap();
+		// already expired
+		wallet_data
+			.lock_outputs_manually(&[six.clone()], "withdrawal 2", now - 20, Some(now - 10))
+			.unwrap();
+
+		let mut selected = selected_values(&wallet_data, &keychain, 20, "all");
+		selected.sort();
+		assert_eq!(selected, vec![1, 6]);
+		let balance = ::info::balance(&wallet_data, &keychain.root_key_id(), 10, 1);
+		assert_eq!(balance.amount_currently_spendable, 7);
+		assert_eq!(balance.amount_locked, 5);
+
+		// spent outputs can't be locked, and unlocking makes them spendable
+		wallet_data.outputs.get_mut(&six.to_hex()).unwrap().status = OutputStatus::Spent;
+		assert!(
+			wallet_data
+				.lock_outputs_manually(&[five.clone(), six.clone()], "", now, None)
+				.is_err()
+		);
+		assert_eq!(wallet_data.unlock_outputs_manually(&[five.clone()], now), 1);
+		assert!(!wallet_data.is_manually_locked(&five, now));
+		assert_eq!(selected_values(&wallet_data, &keychain, 20, "all").len(), 2);
+	}
+
+	#[test]
+	fn payment_proof() {
+		let recipient = keychain::Keychain::from_random_seed().unwrap();
+		let sender_excess = keychain::Keychain::from_random_seed()
+			.unwrap()
+			.payment_proof_pubkey()
+			.unwrap();
+		let kernel_excess = keychain::Keychain::from_random_seed()
+			.unwrap()
+			.payment_proof_pubkey()
+			.unwrap();
+
+		let proof = PaymentProof::sign(&recipient, 1000, &sender_excess, &kernel_excess).unwrap();
+		assert!(proof.verify().is_ok());
+
+		let mut tampered = proof.clone();
+		tampered.amount = 2000;
+		assert!(tampered.verify().is_err());
+
+		let mut tampered = proof.clone();
+		tampered.sender_excess = proof.kernel_excess.clone();
+		assert!(tampered.verify().is_err());
+	}
+
+	#[test]
+	fn cancel_pending_send() {
+		let keychain = keychain::Keychain::from_random_seed().unwrap();
+		let mut wallet_data = wallet_with_values(&keychain, &[5, 6, 7]);
+		let key_id = |n| keychain.derive_key_id(n).unwrap();
+
+		// the first two outputs sent, with the third as change
+		wallet_data.outputs.get_mut(&key_id(3).to_hex()).unwrap().status =
+			OutputStatus::Unconfirmed;
+		for n in 1..3 {
+			wallet_data.outputs.get_mut(&key_id(n).to_hex()).unwrap().status =
+				OutputStatus::Locked;
+		}
+		let mut entry = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Sent, 1);
+		entry.inputs = vec![key_id(1), key_id(2)];
+		wallet_data.add_tx_log_entry(entry);
+		let send = PendingSend {
+			id: 0,
+			amount: 8,
+			fee: 1,
+			lock_height: 0,
+			dest: "http://127.0.0.1:13415".to_string(),
+			inputs: vec![key_id(1), key_id(2)],
+			change: key_id(3),
+			replaced_changes: vec![],
+			file_context: None,
+			kernels: vec![],
+			batch: vec![],
+		};
+		let id = wallet_data.add_pending_send(send.clone());
+
+		// not once the transaction spent one of its inputs
+		let mut spent = wallet_with_values(&keychain, &[5, 6, 7]);
+		spent.add_pending_send(send.clone());
+		spent.outputs.get_mut(&key_id(1).to_hex()).unwrap().status = OutputStatus::Spent;
+		assert!(spent.cancel_pending_send(id).is_err());
+		assert!(spent.get_pending_send(Some(id)).is_some());
+
+		assert_eq!(wallet_data.cancel_pending_send(id).unwrap().inputs, send.inputs);
+		assert_eq!(wallet_data.get_output(&key_id(1)).unwrap().status, OutputStatus::Unspent);
+		assert_eq!(wallet_data.get_output(&key_id(2)).unwrap().status, OutputStatus::Unspent);
+		assert!(wallet_data.get_output(&key_id(3)).is_none());
+		assert!(wallet_data.get_pending_send(Some(id)).is_none());
+		assert_eq!(wallet_data.tx_log[0].status, TxStatus::Cancelled);
+		assert!(wallet_data.cancel_pending_send(id).is_err());
+	}
+
+	#[test]
+	fn tx_log() {
+		let keychain = keychain::Keychain::from_random_seed().unwrap();
+		let mut wallet_data = wallet_with_values(&keychain, &[5, 6, 7]);
+		let key_id = |n| keychain.derive_key_id(n).unwrap();
+
+		// sent, spending the first output with the change in the third one
+		let mut sent = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Sent, 1);
+		sent.inputs = vec![key_id(1)];
+		sent.outputs = vec![key_id(3)];
+		// received in the second output
+		let mut received = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Received, 1);
+		received.outputs = vec![key_id(2)];
+		let mut cancelled = TxLogEntry::new(keychain.root_key_id(), TxLogEntryType::Sent, 1);
+		cancelled.inputs = vec![key_id(1)];
+		cancelled.status = TxStatus::Cancelled;
+
+		assert_eq!(wallet_data.add_tx_log_entry(sent), 1);
+		assert_eq!(wallet_data.add_tx_log_entry(received), 2);
+		assert_eq!(wallet_data.add_tx_log_entry(cancelled), 3);
+		assert_eq!(wallet_data.sent_tx_log_entry_mut(&vec![key_id(1)]).unwrap().id, 1);
+
+		// nothing on chain yet
+		for n in 1..4 {
+			wallet_data.outputs.get_mut(&key_id(n).to_hex()).unwrap().status =
+				OutputStatus::Unconfirmed;
+		}
+		wallet_data.update_tx_log();
+		assert!(wallet_data.tx_log.iter().all(|t| t.confirmation_height.is_none()));
+		assert_eq!(wallet_data.tx_log[0].status, TxStatus::Pending);
+
+		// the received output made it in block 12
+		{
+			let out = wallet_data.outputs.get_mut(&key_id(2).to_hex()).unwrap();
+			out.status = OutputStatus::Unspent;
+			out.height = 12;
+		}
+		// the input of the sent transaction is spent, its change not seen yet
+		wallet_data.outputs.get_mut(&key_id(1).to_hex()).unwrap().status = OutputStatus::Spent;
+		wallet_data.update_tx_log();
+
+		assert_eq!(wallet_data.tx_log[0].status, TxStatus::Confirmed);
+		assert_eq!(wallet_data.tx_log[0].confirmation_height, None);
+		assert_eq!(wallet_data.tx_log[1].status, TxStatus::Confirmed);
+		assert_eq!(wallet_data.tx_log[1].confirmation_height, Some(12));
+		assert_eq!(wallet_data.tx_log[2].status, TxStatus::Cancelled);
+		assert!(wallet_data.sent_tx_log_entry_mut(&vec![key_id(1)]).is_none());
+
+		// the height shows up once the change does
+		{
+			let out = wallet_data.outputs.get_mut(&key_id(3).to_hex()).unwrap();
+			out.status = OutputStatus::Unspent;
+			out.height = 12;
+		}
+		wallet_data.update_tx_log();
+		assert_eq!(wallet_data.tx_log[0].confirmation_height, Some(12));
+		assert_eq!(wallet_data.tx_log[2].confirmation_height, None);
+	}
+}